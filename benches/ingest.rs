@@ -0,0 +1,164 @@
+//! Benchmarks of the ingest and query paths: parsing a 3000 transaction block, finalizing blocks
+//! into RocksDB and serving a block range query.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use solana_agg::config::ParseMode;
+use solana_agg::db_handler::RocksDb;
+use solana_agg::parser::Parser;
+use solana_agg::util::{Block, BlockHeader, Channel, ProtocolMessage, Response, UnprocessedBlock};
+use solana_sdk::hash::Hash;
+use solana_sdk::signature::{Keypair, Signer};
+use solana_sdk::system_transaction;
+use solana_sdk::transaction::VersionedTransaction;
+use solana_transaction_status::{
+    EncodedTransactionWithStatusMeta, TransactionStatusMeta, UiTransactionEncoding,
+    VersionedTransactionWithStatusMeta,
+};
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc::UnboundedSender;
+
+const CHUNK_SIZE: usize = 10;
+const LAMPORTS_PER_ACCOUNT: u64 = 10_000_000_000;
+
+/// Builds `tx_count` base64 encoded SOL transfers with metadata, as returned by `getBlock`
+fn transfer_transactions(tx_count: usize) -> Vec<EncodedTransactionWithStatusMeta> {
+    (0..tx_count)
+        .map(|index| {
+            let from = Keypair::new();
+            let to = Keypair::new();
+            let lamports = 1_000 + index as u64;
+            let transaction = VersionedTransaction::from(system_transaction::transfer(
+                &from,
+                &to.pubkey(),
+                lamports,
+                Hash::default(),
+            ));
+            let meta = TransactionStatusMeta {
+                fee: 5000,
+                pre_balances: vec![LAMPORTS_PER_ACCOUNT, 0, 1],
+                post_balances: vec![LAMPORTS_PER_ACCOUNT - lamports - 5000, lamports, 1],
+                ..TransactionStatusMeta::default()
+            };
+            VersionedTransactionWithStatusMeta { transaction, meta }
+                .encode(UiTransactionEncoding::Base64, Some(0), false)
+                .unwrap()
+        })
+        .collect()
+}
+
+/// Parses the transactions chunk by chunk and assembles the block, like the block fetcher and
+/// handler do
+async fn parse_block(block_no: u64, txs: &[EncodedTransactionWithStatusMeta]) -> Block {
+    let header = BlockHeader {
+        slot: block_no,
+        blockhash: Hash::default().to_string(),
+        block_time: Some(1_722_000_000),
+        ..Default::default()
+    };
+    let chunks: Vec<_> = txs.chunks(CHUNK_SIZE).collect();
+    let total_chunks = chunks.len() as u64;
+    let mut channel = Channel::<ProtocolMessage>::new();
+    for (chunk_no, chunk) in chunks.into_iter().enumerate() {
+        Parser::invoke(
+            ProtocolMessage::new_chuck(
+                block_no,
+                header.clone(),
+                chunk_no as u64,
+                total_chunks,
+                chunk.to_vec(),
+                channel.sender(),
+            ),
+            ParseMode::Permissive,
+        )
+        .await
+        .unwrap();
+    }
+    let mut unprocessed = UnprocessedBlock::new(total_chunks);
+    while !unprocessed.is_complete() {
+        if let Some(ProtocolMessage::ParsedBlock(_, _, chunk_no, partial)) =
+            channel.receiver.recv().await
+        {
+            unprocessed.insert_chunk(chunk_no, partial).unwrap();
+        }
+    }
+    unprocessed.complete_the_block().unwrap()
+}
+
+/// Starts the db actor on a fresh database in `dir`
+fn start_db(runtime: &Runtime, dir: &tempfile::TempDir) -> UnboundedSender<ProtocolMessage> {
+    let channel = Channel::<ProtocolMessage>::new();
+    let sender = channel.sender();
+    let path = dir.path().to_str().unwrap().to_string();
+    let mut db = RocksDb::initialize(path, channel.receiver, None).unwrap();
+    runtime.spawn(async move { db.run().await });
+    sender
+}
+
+/// Finalizes a block and waits until the db actor has written it
+async fn finalize(db_sender: &UnboundedSender<ProtocolMessage>, block_no: u64, block: Block) {
+    db_sender
+        .send(ProtocolMessage::FinalizeBlock(block_no, block))
+        .unwrap();
+    // The actor handles messages in order, so the reply means the block has been written
+    ProtocolMessage::ask(db_sender, ProtocolMessage::FetchLatestBlock)
+        .await
+        .unwrap();
+}
+
+fn parse(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let txs = transfer_transactions(3000);
+    c.bench_function("parse_3000_tx_block", |b| {
+        b.iter(|| runtime.block_on(parse_block(1, &txs)))
+    });
+}
+
+fn finalize_block(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let db_sender = start_db(&runtime, &dir);
+    let block = runtime.block_on(parse_block(1, &transfer_transactions(3000)));
+    let mut block_no = 0;
+    c.bench_function("finalize_3000_tx_block", |b| {
+        b.iter_batched(
+            || block.clone(),
+            |block| {
+                block_no += 1;
+                runtime.block_on(finalize(&db_sender, block_no, block))
+            },
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+fn block_range(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let db_sender = start_db(&runtime, &dir);
+    let txs = transfer_transactions(300);
+    for block_no in 1..=100 {
+        let block = runtime.block_on(parse_block(block_no, &txs));
+        runtime.block_on(finalize(&db_sender, block_no, block));
+    }
+    c.bench_function("block_range_20_blocks", |b| {
+        b.iter(|| {
+            runtime.block_on(async {
+                let response = ProtocolMessage::ask(&db_sender, |reply| {
+                    ProtocolMessage::FetchBlockRange(40, 60, reply)
+                })
+                .await;
+                match response {
+                    Ok(Response::BlockRangeDetails(blocks)) => blocks,
+                    _ => panic!("block range query failed"),
+                }
+            })
+        })
+    });
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = parse, finalize_block, block_range
+}
+criterion_main!(benches);
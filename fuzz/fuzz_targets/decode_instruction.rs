@@ -0,0 +1,46 @@
+#![no_main]
+
+//! Feeds arbitrary compiled instructions to the instruction decoding. The program id can be
+//! forced to the System Program so the transfer decoding is reached with arbitrary data and
+//! account indexes.
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use solana_agg::parser::Parser;
+use solana_program::instruction::CompiledInstruction;
+use solana_program::message::{legacy, VersionedMessage};
+use solana_program::pubkey::Pubkey;
+use solana_program::system_program;
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    account_keys: Vec<[u8; 32]>,
+    system_program: bool,
+    program_id_index: u8,
+    accounts: Vec<u8>,
+    data: Vec<u8>,
+}
+
+fuzz_target!(|input: Input| {
+    let mut account_keys: Vec<Pubkey> = input
+        .account_keys
+        .into_iter()
+        .map(Pubkey::new_from_array)
+        .collect();
+    let mut program_id_index = input.program_id_index;
+    if input.system_program {
+        program_id_index = account_keys.len().min(u8::MAX as usize) as u8;
+        account_keys.insert(program_id_index as usize, system_program::id());
+    }
+    let instruction = CompiledInstruction {
+        program_id_index,
+        accounts: input.accounts,
+        data: input.data,
+    };
+    let message = VersionedMessage::Legacy(legacy::Message {
+        account_keys,
+        instructions: vec![instruction.clone()],
+        ..legacy::Message::default()
+    });
+    let _ = Parser::decode_instruction(&message, &instruction);
+});
@@ -0,0 +1,92 @@
+#![no_main]
+
+//! Runs arbitrary transactions, with or without status metadata, through the parser the same way
+//! the block fetcher hands a chunk to it.
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use solana_agg::config::ParseMode;
+use solana_agg::parser::Parser;
+use solana_agg::util::{BlockHeader, Channel, ProtocolMessage};
+use solana_program::hash::Hash;
+use solana_program::instruction::CompiledInstruction;
+use solana_program::message::{legacy, MessageHeader, VersionedMessage};
+use solana_program::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::VersionedTransaction;
+use solana_transaction_status::{
+    EncodedTransaction, EncodedTransactionWithStatusMeta, TransactionStatusMeta,
+};
+
+#[derive(Arbitrary, Debug)]
+struct FuzzInstruction {
+    program_id_index: u8,
+    accounts: Vec<u8>,
+    data: Vec<u8>,
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    num_required_signatures: u8,
+    num_readonly_signed_accounts: u8,
+    num_readonly_unsigned_accounts: u8,
+    account_keys: Vec<[u8; 32]>,
+    instructions: Vec<FuzzInstruction>,
+    post_balances: Option<Vec<u64>>,
+}
+
+fuzz_target!(|input: Input| {
+    let message = legacy::Message {
+        header: MessageHeader {
+            num_required_signatures: input.num_required_signatures,
+            num_readonly_signed_accounts: input.num_readonly_signed_accounts,
+            num_readonly_unsigned_accounts: input.num_readonly_unsigned_accounts,
+        },
+        account_keys: input
+            .account_keys
+            .into_iter()
+            .map(Pubkey::new_from_array)
+            .collect(),
+        recent_blockhash: Hash::default(),
+        instructions: input
+            .instructions
+            .into_iter()
+            .map(|instruction| CompiledInstruction {
+                program_id_index: instruction.program_id_index,
+                accounts: instruction.accounts,
+                data: instruction.data,
+            })
+            .collect(),
+    };
+    let transaction = VersionedTransaction {
+        signatures: vec![Signature::default(); input.num_required_signatures as usize],
+        message: VersionedMessage::Legacy(message),
+    };
+    let Ok(bytes) = bincode::serialize(&transaction) else {
+        return;
+    };
+    let tx = EncodedTransactionWithStatusMeta {
+        transaction: EncodedTransaction::LegacyBinary(bs58::encode(bytes).into_string()),
+        meta: input.post_balances.map(|post_balances| {
+            TransactionStatusMeta {
+                post_balances,
+                ..TransactionStatusMeta::default()
+            }
+            .into()
+        }),
+        version: None,
+    };
+    let header = BlockHeader {
+        slot: 1,
+        blockhash: Hash::default().to_string(),
+        ..Default::default()
+    };
+    let channel = Channel::<ProtocolMessage>::new();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let _ = runtime.block_on(Parser::invoke(
+        ProtocolMessage::new_chuck(1, header, 0, 1, vec![tx], channel.sender()),
+        ParseMode::Permissive,
+    ));
+});
@@ -0,0 +1,76 @@
+use crate::config::{AggregationRule, RuleBucket, RuleKind};
+use crate::util::Block;
+use solana_program::clock::DEFAULT_SLOTS_PER_EPOCH;
+use std::collections::HashSet;
+
+/// Evaluates the configured aggregation rules against finalized blocks
+#[derive(Default)]
+pub struct RuleEngine {
+    rules: Vec<(AggregationRule, HashSet<String>)>,
+}
+
+impl RuleEngine {
+    pub fn new(rules: Vec<AggregationRule>) -> Self {
+        let rules = rules
+            .into_iter()
+            .map(|rule| {
+                let addresses = match &rule.kind {
+                    RuleKind::SumTransfersTo { addresses } => addresses.iter().cloned().collect(),
+                    RuleKind::CountProgramCalls { .. } => HashSet::new(),
+                };
+                (rule, addresses)
+            })
+            .collect();
+        RuleEngine { rules }
+    }
+
+    pub fn has_rule(&self, name: &str) -> bool {
+        self.rules.iter().any(|(rule, _)| rule.name == name)
+    }
+
+    /// This function computes the contribution of a block to every rule
+    ///
+    /// # Arguments
+    ///
+    /// * `block` - A Block that holds the finalized block
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<(&str, String, f64)>` - The rule name, bucket and value to add for each rule
+    ///   the block contributes to
+    pub fn evaluate(&self, block: &Block) -> Vec<(&str, String, f64)> {
+        let mut contributions = vec![];
+        for (rule, addresses) in self.rules.iter() {
+            let value = match &rule.kind {
+                RuleKind::SumTransfersTo { .. } => block
+                    .transfers()
+                    .filter(|(_, to, _)| addresses.contains(*to))
+                    .map(|(_, _, amount)| amount)
+                    .sum(),
+                RuleKind::CountProgramCalls { program } => block
+                    .get_program_calls()
+                    .get(program)
+                    .copied()
+                    .unwrap_or_default()
+                    as f64,
+            };
+            if value == 0.0 {
+                continue;
+            }
+            if let Some(bucket) = Self::bucket(rule.bucket, block) {
+                contributions.push((rule.name.as_str(), bucket, value));
+            }
+        }
+        contributions
+    }
+
+    fn bucket(bucket: RuleBucket, block: &Block) -> Option<String> {
+        match bucket {
+            RuleBucket::Day => block.day(),
+            RuleBucket::Epoch => block
+                .slot()
+                .map(|slot| (slot / DEFAULT_SLOTS_PER_EPOCH).to_string()),
+            RuleBucket::Total => Some("total".to_string()),
+        }
+    }
+}
@@ -0,0 +1,560 @@
+use crate::chain;
+use crate::config::{AlertSinkConfig, AlertingConfig};
+use crate::endpoints;
+use crate::envelope::{SlotContext, SlotTracker};
+use crate::error::{AggError, ErrorContextExt};
+use crate::metrics;
+use crate::shutdown::{Shutdown, Worker};
+use futures_util::future::BoxFuture;
+use log::{error, info, warn};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Time an alerting sink has to accept an alert
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Endpoint of the PagerDuty Events API v2
+const PAGERDUTY_EVENTS_URL: &str = "https://events.pagerduty.com/v2/enqueue";
+/// Source the alerts are reported from when the chain id is not known yet
+const SOURCE: &str = "solana-agg";
+
+/// Condition an alert reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    /// No new block was indexed for `stall_secs`
+    Stall,
+    /// The latest indexed slot trails the finalized slot by more than `max_lag_slots`
+    Lag,
+    /// A supervised worker panicked or stopped and was restarted
+    Crash,
+    /// At least `dead_letter_growth` dead letters were added within one check interval
+    DeadLetters,
+}
+
+impl AlertKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertKind::Stall => "stall",
+            AlertKind::Lag => "lag",
+            AlertKind::Crash => "crash",
+            AlertKind::DeadLetters => "dead_letters",
+        }
+    }
+}
+
+/// Whether the condition of an alert started or is over. Crashes are only ever firing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertState {
+    Firing,
+    Resolved,
+}
+
+impl AlertState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertState::Firing => "firing",
+            AlertState::Resolved => "resolved",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Alert {
+    pub kind: AlertKind,
+    pub state: AlertState,
+    pub summary: String,
+    /// Chain id of the instance, None until it is known
+    pub chain_id: Option<String>,
+}
+
+impl Alert {
+    fn new(kind: AlertKind, state: AlertState, summary: String) -> Self {
+        Alert {
+            kind,
+            state,
+            summary,
+            chain_id: None,
+        }
+    }
+
+    /// This function returns the source the alert is reported from, the chain id of the instance
+    /// when known
+    pub fn source(&self) -> &str {
+        self.chain_id.as_deref().unwrap_or(SOURCE)
+    }
+
+    /// This function returns the one line message of the alert
+    pub fn message(&self) -> String {
+        format!(
+            "[{}] {} on {}: {}",
+            self.state.as_str().to_uppercase(),
+            self.kind.as_str(),
+            self.source(),
+            self.summary
+        )
+    }
+}
+
+/// What the alert monitor observes of the process at one check
+#[derive(Debug, Clone, Default)]
+pub struct Observation {
+    pub slots: Option<SlotContext>,
+    /// Restarts of every supervised worker since the process started
+    pub worker_restarts: BTreeMap<String, u64>,
+    /// Dead letters stored since the process started
+    pub dead_letters: u64,
+}
+
+impl Observation {
+    /// This function observes the slots of the tracker and the restarts and dead letters
+    /// counted in the metrics
+    ///
+    /// # Arguments
+    ///
+    /// * `slot_tracker` - A SlotTracker that holds the latest indexed and finalized slots
+    pub fn current(slot_tracker: &SlotTracker) -> Self {
+        Observation {
+            slots: Some(slot_tracker.context()),
+            worker_restarts: metrics::counter_totals(&metrics::WORKER_RESTARTS, "worker"),
+            dead_letters: metrics::counter_totals(&metrics::DEAD_LETTERS, "stage")
+                .values()
+                .sum(),
+        }
+    }
+}
+
+/// Turns the observations of the process into alerts, raising an alert once when its condition
+/// starts and resolving it once when it is over
+pub struct AlertRules {
+    stall_after: Duration,
+    max_lag_slots: u64,
+    dead_letter_growth: u64,
+    /// Latest indexed slot and when it was first observed
+    indexed_since: Option<(Option<u64>, Instant)>,
+    worker_restarts: BTreeMap<String, u64>,
+    dead_letters: u64,
+    stalled: bool,
+    lagging: bool,
+    dead_letters_growing: bool,
+}
+
+impl AlertRules {
+    /// This function initializes the rules
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - An AlertingConfig that holds the thresholds
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The rules, with nothing observed yet
+    pub fn new(config: &AlertingConfig) -> Self {
+        AlertRules {
+            stall_after: config.stall_after(),
+            max_lag_slots: config.max_lag_slots,
+            dead_letter_growth: config.dead_letter_growth,
+            indexed_since: None,
+            worker_restarts: BTreeMap::new(),
+            dead_letters: 0,
+            stalled: false,
+            lagging: false,
+            dead_letters_growing: false,
+        }
+    }
+
+    /// This function compares an observation with the previous ones
+    ///
+    /// # Arguments
+    ///
+    /// * `observation` - An Observation that holds the current state of the process
+    /// * `now` - An Instant that holds the time of the observation
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<Alert>` - The alerts whose condition started or is over
+    pub fn evaluate(&mut self, observation: &Observation, now: Instant) -> Vec<Alert> {
+        let mut alerts = vec![];
+        let latest_indexed = observation.slots.and_then(|slots| slots.latest_indexed);
+        let since = match self.indexed_since {
+            Some((slot, since)) if slot == latest_indexed => since,
+            _ => {
+                self.indexed_since = Some((latest_indexed, now));
+                if self.stalled {
+                    self.stalled = false;
+                    alerts.push(Alert::new(
+                        AlertKind::Stall,
+                        AlertState::Resolved,
+                        format!("indexing resumed at slot {}", latest_indexed.unwrap_or(0)),
+                    ));
+                }
+                now
+            }
+        };
+        if !self.stalled && now.duration_since(since) >= self.stall_after {
+            self.stalled = true;
+            alerts.push(Alert::new(
+                AlertKind::Stall,
+                AlertState::Firing,
+                match latest_indexed {
+                    Some(slot) => format!(
+                        "no block indexed for {}s, the latest indexed slot is {}",
+                        self.stall_after.as_secs(),
+                        slot
+                    ),
+                    None => format!(
+                        "no block indexed {}s after startup",
+                        self.stall_after.as_secs()
+                    ),
+                },
+            ));
+        }
+
+        if let Some(SlotContext {
+            latest_indexed: Some(indexed),
+            finalized: Some(finalized),
+        }) = observation.slots
+        {
+            let lag = finalized.saturating_sub(indexed);
+            if lag > self.max_lag_slots && !self.lagging {
+                self.lagging = true;
+                alerts.push(Alert::new(
+                    AlertKind::Lag,
+                    AlertState::Firing,
+                    format!(
+                        "the latest indexed slot {} trails the finalized slot {} by {} slots",
+                        indexed, finalized, lag
+                    ),
+                ));
+            } else if lag <= self.max_lag_slots && self.lagging {
+                self.lagging = false;
+                alerts.push(Alert::new(
+                    AlertKind::Lag,
+                    AlertState::Resolved,
+                    format!(
+                        "the latest indexed slot {} trails by {} slots",
+                        indexed, lag
+                    ),
+                ));
+            }
+        }
+
+        for (worker, restarts) in &observation.worker_restarts {
+            let previous = self.worker_restarts.get(worker).copied().unwrap_or(0);
+            if *restarts > previous {
+                alerts.push(Alert::new(
+                    AlertKind::Crash,
+                    AlertState::Firing,
+                    format!(
+                        "worker {} was restarted {} time(s), {} since startup",
+                        worker,
+                        restarts - previous,
+                        restarts
+                    ),
+                ));
+            }
+        }
+        self.worker_restarts = observation.worker_restarts.clone();
+
+        let growth = observation.dead_letters.saturating_sub(self.dead_letters);
+        self.dead_letters = observation.dead_letters;
+        if growth >= self.dead_letter_growth && !self.dead_letters_growing {
+            self.dead_letters_growing = true;
+            alerts.push(Alert::new(
+                AlertKind::DeadLetters,
+                AlertState::Firing,
+                format!(
+                    "{} dead letters were added since the last check, {} since startup",
+                    growth, observation.dead_letters
+                ),
+            ));
+        } else if growth < self.dead_letter_growth && self.dead_letters_growing {
+            self.dead_letters_growing = false;
+            alerts.push(Alert::new(
+                AlertKind::DeadLetters,
+                AlertState::Resolved,
+                format!("{} dead letters were added since the last check", growth),
+            ));
+        }
+        alerts
+    }
+}
+
+/// Destination of the alerts. Embedders add their own sinks to the monitor besides the ones of
+/// the config.
+pub trait Notifier: Send + Sync + 'static {
+    /// This function returns the name the sink is logged and counted under
+    fn name(&self) -> &str;
+
+    /// This function sends an alert
+    ///
+    /// # Arguments
+    ///
+    /// * `alert` - An Alert that holds the alert
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error if the sink did not
+    ///   accept the alert
+    fn notify<'a>(&'a self, alert: &'a Alert) -> BoxFuture<'a, Result<(), AggError>>;
+}
+
+/// This function posts a JSON body, failing unless the endpoint answers with a success status
+///
+/// # Arguments
+///
+/// * `client` - A reqwest Client that holds the HTTP client
+/// * `url` - A string slice that holds the url
+/// * `body` - A Value that holds the body
+///
+/// # Returns
+///
+/// * `Result<(), AggError>` - A Result that holds the result or an error naming only the host of
+///   the url, as the url of a sink may be its secret
+async fn post(client: &reqwest::Client, url: &str, body: &Value) -> Result<(), AggError> {
+    client
+        .post(url)
+        .timeout(NOTIFY_TIMEOUT)
+        .json(body)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(reqwest::Error::without_url)
+        .with_endpoint(endpoints::redact(url))?;
+    Ok(())
+}
+
+/// Slack incoming webhook, posted the message of every alert
+pub struct SlackNotifier {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl SlackNotifier {
+    pub fn new(url: String) -> Self {
+        Self {
+            url,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+impl Notifier for SlackNotifier {
+    fn name(&self) -> &str {
+        "slack"
+    }
+
+    fn notify<'a>(&'a self, alert: &'a Alert) -> BoxFuture<'a, Result<(), AggError>> {
+        let body = json!({"text": alert.message()});
+        Box::pin(async move { post(&self.client, &self.url, &body).await })
+    }
+}
+
+/// Any HTTP endpoint, posted every alert as JSON
+pub struct WebhookNotifier {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl WebhookNotifier {
+    pub fn new(url: String) -> Self {
+        Self {
+            url,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> &str {
+        "webhook"
+    }
+
+    fn notify<'a>(&'a self, alert: &'a Alert) -> BoxFuture<'a, Result<(), AggError>> {
+        Box::pin(async move {
+            let body = serde_json::to_value(alert)?;
+            post(&self.client, &self.url, &body).await
+        })
+    }
+}
+
+/// PagerDuty Events API v2, triggering an incident per kind of alert and resolving it with the
+/// alert resolving the condition
+pub struct PagerDutyNotifier {
+    routing_key: String,
+    client: reqwest::Client,
+}
+
+impl PagerDutyNotifier {
+    pub fn new(routing_key: String) -> Self {
+        Self {
+            routing_key,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// This function builds the event of an alert, deduplicated by the source and kind of the
+    /// alert
+    ///
+    /// # Arguments
+    ///
+    /// * `alert` - An Alert that holds the alert
+    ///
+    /// # Returns
+    ///
+    /// * `Value` - The body posted to the Events API
+    pub fn event(&self, alert: &Alert) -> Value {
+        let severity = match alert.kind {
+            AlertKind::Stall | AlertKind::Crash => "critical",
+            AlertKind::Lag | AlertKind::DeadLetters => "warning",
+        };
+        json!({
+            "routing_key": self.routing_key,
+            "event_action": match alert.state {
+                AlertState::Firing => "trigger",
+                AlertState::Resolved => "resolve",
+            },
+            "dedup_key": format!("{}/{}", alert.source(), alert.kind.as_str()),
+            "payload": {
+                "summary": alert.message(),
+                "source": alert.source(),
+                "severity": severity,
+                "component": SOURCE,
+                "class": alert.kind.as_str(),
+            },
+        })
+    }
+}
+
+impl Notifier for PagerDutyNotifier {
+    fn name(&self) -> &str {
+        "pagerduty"
+    }
+
+    fn notify<'a>(&'a self, alert: &'a Alert) -> BoxFuture<'a, Result<(), AggError>> {
+        let body = self.event(alert);
+        Box::pin(async move { post(&self.client, PAGERDUTY_EVENTS_URL, &body).await })
+    }
+}
+
+/// This function builds the sink of an alerting sink config
+///
+/// # Arguments
+///
+/// * `config` - An AlertSinkConfig that holds the sink
+///
+/// # Returns
+///
+/// * `Box<dyn Notifier>` - The sink
+pub fn notifier(config: &AlertSinkConfig) -> Box<dyn Notifier> {
+    match config {
+        AlertSinkConfig::Slack { url } => Box::new(SlackNotifier::new(url.clone())),
+        AlertSinkConfig::Webhook { url } => Box::new(WebhookNotifier::new(url.clone())),
+        AlertSinkConfig::PagerDuty { routing_key } => {
+            Box::new(PagerDutyNotifier::new(routing_key.clone()))
+        }
+    }
+}
+
+/// Worker checking the process every `check_interval_secs` and sending the alerts raised and
+/// resolved to every sink
+pub struct AlertMonitor {
+    rules: AlertRules,
+    check_interval: Duration,
+    slot_tracker: Arc<SlotTracker>,
+    notifiers: Vec<Box<dyn Notifier>>,
+    shutdown: Option<Shutdown>,
+}
+
+impl AlertMonitor {
+    /// This function initializes the monitor with the sinks of the config
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - An AlertingConfig that holds the thresholds and the sinks
+    /// * `slot_tracker` - An Arc<SlotTracker> shared with the db and the subscriber
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The monitor
+    pub fn new(config: &AlertingConfig, slot_tracker: Arc<SlotTracker>) -> Self {
+        Self {
+            rules: AlertRules::new(config),
+            check_interval: config.check_interval(),
+            slot_tracker,
+            notifiers: config.sinks.iter().map(notifier).collect(),
+            shutdown: None,
+        }
+    }
+
+    /// This function adds a sink the alerts are sent to
+    ///
+    /// # Arguments
+    ///
+    /// * `notifier` - A Notifier that holds the sink
+    pub fn add_notifier(&mut self, notifier: impl Notifier) {
+        self.notifiers.push(Box::new(notifier));
+    }
+
+    /// This function tells whether the monitor has any sink to send alerts to
+    pub fn has_notifiers(&self) -> bool {
+        !self.notifiers.is_empty()
+    }
+
+    /// This function checks the process at every interval until it is asked to stop
+    pub async fn run(&mut self) {
+        let mut interval = tokio::time::interval(self.check_interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = Shutdown::wait(&mut self.shutdown) => return,
+            }
+            let observation = Observation::current(&self.slot_tracker);
+            for mut alert in self.rules.evaluate(&observation, Instant::now()) {
+                alert.chain_id = chain::installed().map(str::to_string);
+                self.send(&alert).await;
+            }
+        }
+    }
+
+    /// This function logs an alert and sends it to every sink, a sink failing is logged and
+    /// counted without holding back the others
+    ///
+    /// # Arguments
+    ///
+    /// * `alert` - An Alert that holds the alert
+    async fn send(&self, alert: &Alert) {
+        metrics::ALERTS
+            .with_label_values(&[alert.kind.as_str(), alert.state.as_str()])
+            .inc();
+        match alert.state {
+            AlertState::Firing => warn!(target: "alerting", "{}", alert.message()),
+            AlertState::Resolved => info!(target: "alerting", "{}", alert.message()),
+        }
+        for notifier in &self.notifiers {
+            if let Err(err) = notifier.notify(alert).await {
+                metrics::ALERT_NOTIFICATION_FAILURES
+                    .with_label_values(&[notifier.name()])
+                    .inc();
+                error!(
+                    target: "alerting",
+                    "Failed to send the {} alert to {} {}", alert.kind.as_str(), notifier.name(), err
+                );
+            }
+        }
+    }
+}
+
+impl Worker for AlertMonitor {
+    fn set_shutdown(&mut self, shutdown: Shutdown) {
+        self.shutdown = Some(shutdown);
+    }
+
+    fn work(&mut self) -> BoxFuture<'_, ()> {
+        Box::pin(self.run())
+    }
+}
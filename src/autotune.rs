@@ -0,0 +1,175 @@
+use crate::config::AutotuneConfig;
+use crate::metrics::{AUTOTUNE_SETTINGS, BLOCK_WRITE_LATENCY};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Weight of a new write in the moving average, one eighth
+const LATENCY_WEIGHT_SHIFT: u32 = 3;
+
+/// Moving average of the time the db takes to store a block, written by the db and read by the
+/// subscriber. Zero stands for no block stored yet.
+#[derive(Default)]
+pub struct WriteLatency {
+    micros: AtomicU64,
+}
+
+impl WriteLatency {
+    /// This function folds the time a block took to store into the average
+    ///
+    /// # Arguments
+    ///
+    /// * `elapsed` - A Duration that holds the time the block took to store
+    pub fn record(&self, elapsed: Duration) {
+        let sample = (elapsed.as_micros() as u64).max(1);
+        let average = match self.micros.load(Ordering::Relaxed) {
+            0 => sample,
+            average => {
+                average - (average >> LATENCY_WEIGHT_SHIFT) + (sample >> LATENCY_WEIGHT_SHIFT)
+            }
+        };
+        self.micros.store(average.max(1), Ordering::Relaxed);
+        BLOCK_WRITE_LATENCY.set(average as f64 / 1_000_000.0);
+    }
+
+    pub fn get(&self) -> Option<Duration> {
+        match self.micros.load(Ordering::Relaxed) {
+            0 => None,
+            micros => Some(Duration::from_micros(micros)),
+        }
+    }
+}
+
+/// Slots whose blocks start being fetched at once and transactions parsed per task
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FetchSettings {
+    pub slots_per_round: u64,
+    pub chunk_size: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Adjustment {
+    /// The db stores blocks slower than the target, fewer slots are fetched at once in larger
+    /// chunks
+    BackOff,
+    /// The db keeps up but the indexed slot falls behind, more slots are fetched at once in
+    /// smaller chunks
+    SpeedUp,
+}
+
+/// Feedback controller of the fetch settings. Fetching backs off multiplicatively as soon as
+/// writes are slower than the target and speeds up by an eighth while they stay under three
+/// quarters of it and the indexed slot lags, holding in between so it settles instead of
+/// oscillating between idle and overload.
+pub struct Autotuner {
+    config: AutotuneConfig,
+    settings: FetchSettings,
+    last_adjusted: Option<Instant>,
+}
+
+impl Autotuner {
+    /// This function initializes the controller
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - An AutotuneConfig that holds the target and the bounds of the settings
+    /// * `initial` - A FetchSettings that holds the configured settings, clamped to the bounds
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The controller
+    pub fn new(config: AutotuneConfig, initial: FetchSettings) -> Self {
+        let settings = FetchSettings {
+            slots_per_round: initial
+                .slots_per_round
+                .clamp(config.min_slots_per_round, config.max_slots_per_round),
+            chunk_size: initial
+                .chunk_size
+                .clamp(config.min_chunk_size, config.max_chunk_size),
+        };
+        Self::publish(settings);
+        Autotuner {
+            config,
+            settings,
+            last_adjusted: None,
+        }
+    }
+
+    pub fn settings(&self) -> FetchSettings {
+        self.settings
+    }
+
+    /// This function adjusts the settings to the latest observations, at most once per interval
+    ///
+    /// # Arguments
+    ///
+    /// * `write_latency` - An Option<Duration> that holds the average write latency, None before
+    ///   the first block is stored
+    /// * `slot_lag` - A u64 that holds the slots the latest indexed slot trails the fetched ones
+    /// * `now` - An Instant that holds the time of the observations
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Adjustment>` - The adjustment made, None if the settings are unchanged
+    pub fn adjust(
+        &mut self,
+        write_latency: Option<Duration>,
+        slot_lag: u64,
+        now: Instant,
+    ) -> Option<Adjustment> {
+        if let Some(last_adjusted) = self.last_adjusted {
+            if now.duration_since(last_adjusted) < self.config.interval() {
+                return None;
+            }
+        }
+        let target = self.config.target_write_latency();
+        let current = self.settings;
+        let (adjustment, settings) = match write_latency {
+            Some(latency) if latency > target => (
+                Adjustment::BackOff,
+                FetchSettings {
+                    slots_per_round: (current.slots_per_round / 2)
+                        .max(self.config.min_slots_per_round),
+                    chunk_size: current
+                        .chunk_size
+                        .saturating_mul(2)
+                        .min(self.config.max_chunk_size),
+                },
+            ),
+            latency
+                if latency.is_none_or(|latency| latency * 4 <= target * 3)
+                    && slot_lag > self.config.lag_slots =>
+            {
+                (
+                    Adjustment::SpeedUp,
+                    FetchSettings {
+                        slots_per_round: current
+                            .slots_per_round
+                            .saturating_add((current.slots_per_round / 8).max(1))
+                            .min(self.config.max_slots_per_round),
+                        chunk_size: current
+                            .chunk_size
+                            .saturating_sub((current.chunk_size / 8).max(1))
+                            .max(self.config.min_chunk_size),
+                    },
+                )
+            }
+            _ => return None,
+        };
+        if settings == current {
+            return None;
+        }
+        self.settings = settings;
+        self.last_adjusted = Some(now);
+        Self::publish(settings);
+        Some(adjustment)
+    }
+
+    fn publish(settings: FetchSettings) {
+        AUTOTUNE_SETTINGS
+            .with_label_values(&["slots_per_round"])
+            .set(settings.slots_per_round as i64);
+        AUTOTUNE_SETTINGS
+            .with_label_values(&["chunk_size"])
+            .set(settings.chunk_size as i64);
+    }
+}
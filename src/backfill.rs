@@ -0,0 +1,443 @@
+use crate::block_importer::{self, BlockFetcher};
+use crate::config::{BackfillConfig, Commitment, NodeConfig, ParseMode};
+use crate::endpoints::RpcEndpoints;
+use crate::error::{AggError, ErrorContextExt};
+use crate::fetch_cache::FetchCache;
+use crate::gaps::FetchOutcome;
+use crate::shutdown::Shutdown;
+use crate::util::{
+    BackfillProgress, DeadLetter, FailureStage, IndexedSlots, JobState, JobTask, ProtocolMessage,
+    Response,
+};
+use log::{error, info, warn};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+
+const MAX_BACKFILL_BATCH_SIZE: u64 = 256;
+/// Fetches of the slots of a batch that are still missing before the batch gives up on them
+const MAX_FETCH_ATTEMPTS: u32 = 3;
+/// Time the fetched blocks of a batch have to be parsed and stored before the slots still
+/// missing are fetched again
+const BATCH_SETTLE_TIMEOUT: Duration = Duration::from_secs(30);
+const SETTLE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Fetches the blocks of past slots in concurrent batches. A backfill is a job of the db, its
+/// progress is checkpointed in the db after every batch so a restart resumes it at the batch it
+/// stopped in, and slots the db already stores are never fetched again.
+pub struct Backfiller {
+    endpoints: Arc<RpcEndpoints>,
+    batch_size: u64,
+    /// Permits of the fetches running at the same time
+    fetch_permits: Arc<Semaphore>,
+    archive_raw_blocks: bool,
+    parse_mode: ParseMode,
+    chunk_size: usize,
+    /// Finalized blocks fetched before, shared with the subscriber
+    fetch_cache: Option<Arc<FetchCache>>,
+    handler_sender: UnboundedSender<ProtocolMessage>,
+    shutdown: Option<Shutdown>,
+    /// Tasks of the backfill jobs started, waited for on shutdown
+    jobs: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Backfiller {
+    /// This function initializes the backfiller
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoints` - An Arc<RpcEndpoints> that holds the RPC endpoints, shared with the
+    ///   subscriber
+    /// * `config` - A BackfillConfig that holds the batch size and the concurrent fetches
+    /// * `handler_sender` - A UnboundedSender<ProtocolMessage> that holds the handler sender
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The backfiller
+    pub fn initialize(
+        endpoints: Arc<RpcEndpoints>,
+        config: &BackfillConfig,
+        handler_sender: UnboundedSender<ProtocolMessage>,
+    ) -> Self {
+        let batch_size = config.batch_size.clamp(1, MAX_BACKFILL_BATCH_SIZE);
+        Backfiller {
+            endpoints,
+            batch_size,
+            fetch_permits: Arc::new(Semaphore::new(
+                config.concurrency.clamp(1, batch_size as usize),
+            )),
+            archive_raw_blocks: false,
+            parse_mode: ParseMode::default(),
+            chunk_size: NodeConfig::default().chunk_size,
+            fetch_cache: None,
+            handler_sender,
+            shutdown: None,
+            jobs: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// This function makes the backfiller archive every fetched block before it is parsed
+    ///
+    /// # Arguments
+    ///
+    /// * `archive` - A bool that holds whether raw blocks are archived
+    pub fn archive_raw_blocks(&mut self, archive: bool) {
+        self.archive_raw_blocks = archive;
+    }
+
+    /// This function sets what the parser does with transactions failing to parse
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - A ParseMode that holds the parse mode
+    pub fn set_parse_mode(&mut self, mode: ParseMode) {
+        self.parse_mode = mode;
+    }
+
+    /// This function sets the transactions of a block parsed by one task
+    ///
+    /// # Arguments
+    ///
+    /// * `chunk_size` - A usize that holds the transactions per chunk
+    pub fn set_chunk_size(&mut self, chunk_size: usize) {
+        self.chunk_size = chunk_size;
+    }
+
+    /// This function makes the backfills read the blocks fetched before from the cache and cache
+    /// the ones they fetch
+    ///
+    /// # Arguments
+    ///
+    /// * `fetch_cache` - An Arc<FetchCache> that holds the cache, shared with the subscriber
+    pub fn set_fetch_cache(&mut self, fetch_cache: Arc<FetchCache>) {
+        self.fetch_cache = Some(fetch_cache);
+    }
+
+    /// This function makes the backfills stop after their current batch once the process is
+    /// asked to stop, they resume from their last checkpoint after the restart
+    ///
+    /// # Arguments
+    ///
+    /// * `shutdown` - A Shutdown that holds the signal the backfills stop on
+    pub fn set_shutdown(&mut self, shutdown: Shutdown) {
+        self.shutdown = Some(shutdown);
+    }
+
+    /// This function resumes the backfills that were running when the process stopped and starts
+    /// the requested one. A running backfill from the same slot is resumed instead, extended up
+    /// to the requested end slot.
+    ///
+    /// # Arguments
+    ///
+    /// * `requested` - An Option<(u64, u64)> that holds the first and last slot to backfill
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<u64>, AggError>` - A Result that holds the ids of the running backfill jobs
+    ///   or an error
+    pub async fn start(
+        self: &Arc<Self>,
+        requested: Option<(u64, u64)>,
+    ) -> Result<Vec<u64>, AggError> {
+        let mut running: Vec<(u64, BackfillProgress)> = self
+            .fetch_jobs()
+            .await?
+            .into_iter()
+            .filter(|(_, state, _)| *state == JobState::Running)
+            .filter_map(|(id, _, task)| match task {
+                JobTask::Backfill(progress) => Some((id, progress)),
+                _ => None,
+            })
+            .collect();
+        if let Some((start_slot, end_slot)) = requested {
+            if start_slot > end_slot {
+                return Err(AggError::BackfillError(format!(
+                    "Backfill start slot {} is after the first slot of the subscriber {}",
+                    start_slot, end_slot
+                )));
+            }
+            match running
+                .iter_mut()
+                .find(|(_, progress)| progress.start_slot == start_slot)
+            {
+                Some((id, progress)) => {
+                    // The subscriber restarted at a later slot, the slots in between are left
+                    // to the backfill
+                    progress.end_slot = progress.end_slot.max(end_slot);
+                    self.report(*id, progress, JobState::Running, None)?;
+                }
+                None => {
+                    let progress = BackfillProgress::new(start_slot, end_slot);
+                    let id = self.register(&progress).await?;
+                    running.push((id, progress));
+                }
+            }
+        }
+        let mut ids = Vec::new();
+        for (id, progress) in running {
+            info!(
+                target: "backfill",
+                "Backfilling slots {}..={} of job {} from slot {}",
+                progress.start_slot, progress.end_slot, id, progress.next_slot
+            );
+            ids.push(id);
+            let backfiller = self.clone();
+            let job = tokio::spawn(async move {
+                backfiller.run_job(id, progress).await;
+            });
+            self.jobs.lock().expect("backfill jobs lock").push(job);
+        }
+        Ok(ids)
+    }
+
+    /// This function waits for the started backfill jobs to finish, or to stop after their
+    /// current batch once the process is asked to stop
+    pub async fn wait(&self) {
+        let jobs = std::mem::take(&mut *self.jobs.lock().expect("backfill jobs lock"));
+        for job in jobs {
+            if let Err(err) = job.await {
+                error!(target: "backfill", "Backfill task failed {}", err);
+            }
+        }
+    }
+
+    async fn run_job(&self, id: u64, mut progress: BackfillProgress) {
+        let (state, error) = match self.backfill(id, &mut progress).await {
+            Ok(true) => {
+                info!(target: "backfill", "Backfill {} finished {:?}", id, progress);
+                (JobState::Finished, None)
+            }
+            // Cancelled, the db already holds the final state of the job, or stopping, the job
+            // resumes from its last checkpoint
+            Ok(false) => return,
+            Err(err) => {
+                error!(target: "backfill", "Backfill {} failed {}", id, err);
+                (JobState::Failed, Some(err.to_string()))
+            }
+        };
+        if let Err(err) = self.report(id, &progress, state, error) {
+            error!(target: "backfill", "Failed to checkpoint backfill {} {}", id, err);
+        }
+    }
+
+    /// This function backfills the remaining batches of a job, checkpointing after every batch
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - A u64 that holds the job id
+    /// * `progress` - A BackfillProgress that holds the checkpoint the job resumes from
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, AggError>` - A Result that holds whether the job finished, false if it was
+    ///   cancelled or the process is stopping, or an error
+    async fn backfill(&self, id: u64, progress: &mut BackfillProgress) -> Result<bool, AggError> {
+        while progress.next_slot <= progress.end_slot {
+            if Shutdown::is_set(&self.shutdown) {
+                info!(target: "backfill", "Backfill {} stopped at slot {}", id, progress.next_slot);
+                return Ok(false);
+            }
+            if !self.is_running(id).await? {
+                info!(target: "backfill", "Backfill {} was cancelled at slot {}", id, progress.next_slot);
+                return Ok(false);
+            }
+            let start_slot = progress.next_slot;
+            let end_slot = start_slot
+                .saturating_add(self.batch_size - 1)
+                .min(progress.end_slot);
+            let slots = self
+                .backfill_batch(start_slot, end_slot)
+                .await
+                .with_slot(start_slot)?;
+            if Shutdown::is_set(&self.shutdown) {
+                // The blocks fetched are still stored, the batch is checked again on resume
+                info!(target: "backfill", "Backfill {} stopped at slot {}", id, start_slot);
+                return Ok(false);
+            }
+            let span = end_slot - start_slot + 1;
+            let indexed = slots.indexed.len() as u64;
+            let skipped = slots.skipped.len() as u64;
+            progress.indexed_blocks += indexed;
+            progress.skipped_slots += skipped;
+            progress.missing_slots += span.saturating_sub(indexed + skipped);
+            progress.next_slot = end_slot.saturating_add(1);
+            self.report(id, progress, JobState::Running, None)?;
+            if end_slot == u64::MAX {
+                break;
+            }
+        }
+        Ok(true)
+    }
+
+    /// This function fetches the slots of a batch the db does not store yet, up to the configured
+    /// concurrency at a time, and waits for their blocks to be stored, fetching the slots still
+    /// missing again
+    ///
+    /// # Arguments
+    ///
+    /// * `start_slot` - A u64 that holds the first slot of the batch
+    /// * `end_slot` - A u64 that holds the last slot of the batch
+    ///
+    /// # Returns
+    ///
+    /// * `Result<IndexedSlots, AggError>` - A Result that holds the stored and skipped slots of
+    ///   the batch or an error
+    async fn backfill_batch(
+        &self,
+        start_slot: u64,
+        end_slot: u64,
+    ) -> Result<IndexedSlots, AggError> {
+        let mut attempts = 0;
+        loop {
+            let slots = self.indexed_slots(start_slot, end_slot).await?;
+            let missing = Self::missing_slots(start_slot, end_slot, &slots);
+            if missing.is_empty() {
+                return Ok(slots);
+            }
+            if attempts == MAX_FETCH_ATTEMPTS {
+                warn!(
+                    target: "backfill",
+                    "Giving up on {} slots of batch {}..={}: {:?}",
+                    missing.len(), start_slot, end_slot, missing
+                );
+                return Ok(slots);
+            }
+            attempts += 1;
+            let fetches: Vec<_> = missing
+                .into_iter()
+                .map(|slot| {
+                    let message = ProtocolMessage::FetchBlock(
+                        self.endpoints.clone(),
+                        // Past slots are finalized whatever the commitment the subscriber follows
+                        block_importer::block_config(Commitment::Finalized),
+                        slot,
+                        self.archive_raw_blocks,
+                        self.parse_mode,
+                        self.chunk_size,
+                        self.fetch_cache.clone(),
+                        self.handler_sender.clone(),
+                    );
+                    let permits = self.fetch_permits.clone();
+                    let shutdown = self.shutdown.clone();
+                    let fetch = tokio::spawn(async move {
+                        // The semaphore is never closed
+                        let _permit = permits.acquire_owned().await;
+                        // Fetches started finish on shutdown, the others are left to the resume
+                        if Shutdown::is_set(&shutdown) {
+                            return None;
+                        }
+                        Some(BlockFetcher::invoke(message).await)
+                    });
+                    (slot, fetch)
+                })
+                .collect();
+            for (slot, fetch) in fetches {
+                match fetch.await {
+                    Ok(Some(FetchOutcome::Failed(failure))) => BlockFetcher::dead_letter(
+                        &self.handler_sender,
+                        DeadLetter::new(FailureStage::Fetch, Some(slot), None, &failure),
+                    ),
+                    Ok(_) => {}
+                    Err(err) => error!(target: "backfill", "Block fetch panicked {}", err),
+                }
+            }
+            // Fetched blocks are parsed and stored asynchronously
+            let deadline = Instant::now() + BATCH_SETTLE_TIMEOUT;
+            while Instant::now() < deadline && !Shutdown::is_set(&self.shutdown) {
+                tokio::time::sleep(SETTLE_POLL_INTERVAL).await;
+                let slots = self.indexed_slots(start_slot, end_slot).await?;
+                if Self::missing_slots(start_slot, end_slot, &slots).is_empty() {
+                    break;
+                }
+            }
+        }
+    }
+
+    fn missing_slots(start_slot: u64, end_slot: u64, slots: &IndexedSlots) -> Vec<u64> {
+        (start_slot..=end_slot)
+            .filter(|slot| !slots.indexed.contains(slot) && !slots.skipped.contains(slot))
+            .collect()
+    }
+
+    async fn indexed_slots(
+        &self,
+        start_slot: u64,
+        end_slot: u64,
+    ) -> Result<IndexedSlots, AggError> {
+        let limit = end_slot - start_slot + 1;
+        let response = ProtocolMessage::ask(&self.handler_sender, |reply| {
+            ProtocolMessage::FetchIndexedSlots(start_slot, end_slot, limit, reply)
+        })
+        .await?;
+        match response {
+            Response::IndexedSlots(slots) => Ok(slots),
+            _ => Err(Self::unexpected()),
+        }
+    }
+
+    async fn is_running(&self, id: u64) -> Result<bool, AggError> {
+        let response = ProtocolMessage::ask(&self.handler_sender, |reply| {
+            ProtocolMessage::FetchJob(id, reply)
+        })
+        .await?;
+        match response {
+            Response::Job(job) => Ok(job.is_some_and(|job| job.state == JobState::Running)),
+            _ => Err(Self::unexpected()),
+        }
+    }
+
+    async fn fetch_jobs(&self) -> Result<Vec<(u64, JobState, JobTask)>, AggError> {
+        let response =
+            ProtocolMessage::ask(&self.handler_sender, ProtocolMessage::FetchJobs).await?;
+        match response {
+            Response::Jobs(jobs) => Ok(jobs
+                .into_iter()
+                .map(|job| (job.id, job.state, job.task))
+                .collect()),
+            _ => Err(Self::unexpected()),
+        }
+    }
+
+    /// This function registers a backfill as a job of the db
+    ///
+    /// # Arguments
+    ///
+    /// * `progress` - A BackfillProgress that holds the slot range of the backfill
+    ///
+    /// # Returns
+    ///
+    /// * `Result<u64, AggError>` - A Result that holds the id of the job or an error
+    async fn register(&self, progress: &BackfillProgress) -> Result<u64, AggError> {
+        let response = ProtocolMessage::ask(&self.handler_sender, |reply| {
+            ProtocolMessage::StartJob(JobTask::Backfill(progress.clone()), reply)
+        })
+        .await?;
+        match response {
+            Response::Job(Some(job)) => Ok(job.id),
+            _ => Err(Self::unexpected()),
+        }
+    }
+
+    /// This function checkpoints the progress of a backfill in the db
+    fn report(
+        &self,
+        id: u64,
+        progress: &BackfillProgress,
+        state: JobState,
+        error: Option<String>,
+    ) -> Result<(), AggError> {
+        self.handler_sender.send(ProtocolMessage::UpdateJob(
+            id,
+            JobTask::Backfill(progress.clone()),
+            state,
+            error,
+        ))?;
+        Ok(())
+    }
+
+    fn unexpected() -> AggError {
+        AggError::UnexpectedReply("the db did not answer the backfill".to_string())
+    }
+}
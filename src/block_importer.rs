@@ -1,19 +1,71 @@
+use crate::autotune::{Adjustment, Autotuner, FetchSettings, WriteLatency};
+use crate::config::{
+    AutotuneConfig, Commitment, NodeConfig, ParseMode, RefetchConfig, SubscriberConfig,
+};
+use crate::endpoints::{self, RpcEndpoints};
+use crate::envelope::SlotTracker;
 use crate::error::AggError;
+use crate::fetch_cache::FetchCache;
+use crate::fetch_pool::FetchPool;
+use crate::gaps::{FetchOutcome, GapTracker, GapUpdate};
 use crate::parser::Parser;
-use crate::util::ProtocolMessage;
-use log::{error, warn};
-use solana_client::rpc_client::RpcClient;
+use crate::retry::{Failure, FETCH_RETRY, PARSE_RETRY};
+use crate::shutdown::{Shutdown, Worker};
+use crate::spill;
+use crate::util::{BlockHeader, DeadLetter, FailureStage, ProtocolMessage, RawBlock};
+use futures_util::future::BoxFuture;
+use futures_util::StreamExt;
+use log::{debug, error, info, warn};
+use solana_client::client_error::ClientErrorKind;
+use solana_client::nonblocking::pubsub_client::PubsubClient;
 use solana_client::rpc_config::RpcBlockConfig;
-use solana_sdk::commitment_config::CommitmentConfig;
-use solana_transaction_status::UiTransactionEncoding;
-use tokio::sync::mpsc::UnboundedSender;
+use solana_client::rpc_custom_error::{
+    JSON_RPC_SERVER_ERROR_LONG_TERM_STORAGE_SLOT_SKIPPED, JSON_RPC_SERVER_ERROR_SLOT_SKIPPED,
+};
+use solana_client::rpc_request::RpcError;
+use solana_transaction_status::{UiConfirmedBlock, UiTransactionEncoding};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+/// Number of slots the fetched block trails the latest finalized slot
+const SLOT_LAG: u64 = 500;
+/// Interval between polls of the finalized slot once the subscriber caught up
+const POLL_INTERVAL: Duration = Duration::from_millis(400);
+/// Time spent polling after the slot subscription failed before reconnecting
+const WS_RECONNECT_INTERVAL: Duration = Duration::from_secs(10);
 
 pub struct Subscriber {
     latest_slot: u64,
-    chain_url: String,
-    rpc_client: RpcClient,
+    genesis_hash: String,
+    ws_url: Option<String>,
+    ws_timeout: Duration,
+    /// RPC endpoints the slots and blocks are fetched from, shared with the block fetches
+    endpoints: Arc<RpcEndpoints>,
     rpc_block_config: RpcBlockConfig,
+    commitment: Commitment,
+    chunk_size: usize,
+    max_slots_per_round: u64,
+    /// Blocks fetched at once by the workers of the fetch pool
+    fetch_workers: usize,
+    /// Queue of the fetches, started with the first fetch
+    fetch_pool: Option<FetchPool>,
+    archive_raw_blocks: bool,
+    parse_mode: ParseMode,
+    /// Finalized blocks fetched before, shared with the backfills
+    fetch_cache: Option<Arc<FetchCache>>,
+    slot_tracker: Arc<SlotTracker>,
     unbounded_sender: UnboundedSender<ProtocolMessage>,
+    gaps: GapTracker,
+    /// Outcomes of the fetches of the pool, read back before every round
+    outcome_sender: UnboundedSender<(u64, FetchOutcome)>,
+    outcome_receiver: UnboundedReceiver<(u64, FetchOutcome)>,
+    /// Slots of canonical blocks the db found missing after a reorg
+    refetch_receiver: Option<UnboundedReceiver<u64>>,
+    /// Adjusts the slots fetched at once and the chunk size when auto-tuning is enabled
+    autotuner: Option<Autotuner>,
+    write_latency: Arc<WriteLatency>,
+    shutdown: Option<Shutdown>,
 }
 
 impl Subscriber {
@@ -22,7 +74,8 @@ impl Subscriber {
     ///
     /// # Arguments
     ///
-    /// * `chain_url` - A string slice that holds the chain url
+    /// * `chain_url` - A String that holds the chain url, or several separated by commas to
+    ///   fail over between
     /// * `message_sender` - A UnboundedSender<ProtocolMessage> that holds the message sender
     ///
     /// # Returns
@@ -32,62 +85,527 @@ impl Subscriber {
         chain_url: String,
         message_sender: UnboundedSender<ProtocolMessage>,
     ) -> Result<Self, AggError> {
-        let rpc_client = RpcClient::new(&chain_url);
-        let rpc_block_config = RpcBlockConfig {
-            encoding: Some(UiTransactionEncoding::Base64),
-            transaction_details: None,
-            rewards: None,
-            commitment: Some(CommitmentConfig::finalized()),
-            max_supported_transaction_version: Some(0),
-        };
-        let latest_slot = rpc_client.get_slot_with_commitment(CommitmentConfig::finalized())?;
+        let endpoints = Arc::new(RpcEndpoints::new(endpoints::chain_urls(&chain_url))?);
+        let node = NodeConfig::default();
+        let rpc_block_config = block_config(node.commitment);
+        // Every attempt goes to the next endpoint, so one unavailable node does not fail startup
+        let latest_slot = FETCH_RETRY
+            .run_blocking(FailureStage::Fetch, || {
+                endpoints
+                    .call(|client| Ok(client.get_slot_with_commitment(node.commitment.config())?))
+            })
+            .map_err(|failure| failure.error)?;
+        let genesis_hash = FETCH_RETRY
+            .run_blocking(FailureStage::Fetch, || {
+                endpoints.call(|client| Ok(client.get_genesis_hash()?))
+            })
+            .map_err(|failure| failure.error)?
+            .to_string();
+        let (outcome_sender, outcome_receiver) = unbounded_channel();
         Ok(Self {
             latest_slot,
-            chain_url,
-            rpc_client,
+            genesis_hash,
+            ws_url: None,
+            ws_timeout: SubscriberConfig::default().ws_timeout(),
+            endpoints,
             rpc_block_config,
+            commitment: node.commitment,
+            chunk_size: node.chunk_size,
+            max_slots_per_round: node.max_slots_per_round,
+            fetch_workers: node.fetch_workers,
+            fetch_pool: None,
+            archive_raw_blocks: false,
+            parse_mode: ParseMode::default(),
+            fetch_cache: None,
+            slot_tracker: Arc::new(SlotTracker::default()),
             unbounded_sender: message_sender,
+            gaps: GapTracker::new(RefetchConfig::default()),
+            outcome_sender,
+            outcome_receiver,
+            refetch_receiver: None,
+            autotuner: None,
+            write_latency: Arc::new(WriteLatency::default()),
+            shutdown: None,
         })
     }
 
+    /// This function makes the subscriber continue ingestion right after the given slot
+    ///
+    /// # Arguments
+    ///
+    /// * `slot` - A u64 that holds the last slot already stored
+    pub fn resume_from_slot(&mut self, slot: u64) {
+        self.latest_slot = slot.saturating_add(SLOT_LAG);
+    }
+
+    /// This function makes the subscriber archive every fetched block before it is parsed
+    ///
+    /// # Arguments
+    ///
+    /// * `archive` - A bool that holds whether raw blocks are archived
+    pub fn archive_raw_blocks(&mut self, archive: bool) {
+        self.archive_raw_blocks = archive;
+    }
+
+    /// This function sets what the parser does with transactions failing to parse
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - A ParseMode that holds the parse mode
+    pub fn set_parse_mode(&mut self, mode: ParseMode) {
+        self.parse_mode = mode;
+    }
+
+    /// This function makes the subscriber read finalized blocks fetched before from the cache
+    /// and cache the ones it fetches
+    ///
+    /// # Arguments
+    ///
+    /// * `fetch_cache` - An Arc<FetchCache> that holds the cache, shared with the backfills
+    pub fn set_fetch_cache(&mut self, fetch_cache: Arc<FetchCache>) {
+        self.fetch_cache = Some(fetch_cache);
+    }
+
+    /// This function sets the commitment of the slots followed and the blocks fetched, the
+    /// transactions parsed per task, the slots fetched at once and the workers fetching them
+    ///
+    /// # Arguments
+    ///
+    /// * `node` - A NodeConfig that holds the fetch settings
+    pub fn set_fetch_settings(&mut self, node: &NodeConfig) {
+        self.commitment = node.commitment;
+        self.rpc_block_config = block_config(node.commitment);
+        self.chunk_size = node.chunk_size;
+        self.max_slots_per_round = node.max_slots_per_round;
+        self.fetch_workers = node.fetch_workers;
+    }
+
+    /// This function sets the tracker responses read the finalized slot from
+    ///
+    /// # Arguments
+    ///
+    /// * `slot_tracker` - An Arc<SlotTracker> shared with the server
+    pub fn set_slot_tracker(&mut self, slot_tracker: Arc<SlotTracker>) {
+        self.slot_tracker = slot_tracker;
+    }
+
+    /// This function sets how slots whose block failed to be fetched are fetched again
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - A RefetchConfig that holds the delays and the re-fetches allowed
+    pub fn set_refetch_config(&mut self, config: RefetchConfig) {
+        self.gaps = GapTracker::new(config);
+    }
+
+    /// This function sets where the db sends the slots of canonical blocks to fetch again after
+    /// a reorg
+    ///
+    /// # Arguments
+    ///
+    /// * `receiver` - An UnboundedReceiver<u64> that holds the slots to fetch again
+    pub fn set_refetch_receiver(&mut self, receiver: UnboundedReceiver<u64>) {
+        self.refetch_receiver = Some(receiver);
+    }
+
+    /// This function makes the subscriber adjust the slots fetched at once and the chunk size to
+    /// the write latency of the db and the slot lag, starting from the fetch settings set
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - An AutotuneConfig that holds the target and the bounds of the settings
+    /// * `write_latency` - An Arc<WriteLatency> shared with the db
+    pub fn set_autotune(&mut self, config: AutotuneConfig, write_latency: Arc<WriteLatency>) {
+        if !config.enabled {
+            self.autotuner = None;
+            return;
+        }
+        let autotuner = Autotuner::new(
+            config,
+            FetchSettings {
+                slots_per_round: self.max_slots_per_round,
+                chunk_size: self.chunk_size,
+            },
+        );
+        let settings = autotuner.settings();
+        self.max_slots_per_round = settings.slots_per_round;
+        self.chunk_size = settings.chunk_size;
+        self.autotuner = Some(autotuner);
+        self.write_latency = write_latency;
+    }
+
+    /// This function returns the genesis hash of the cluster the subscriber ingests from
+    pub fn genesis_hash(&self) -> &str {
+        &self.genesis_hash
+    }
+
+    /// This function returns the RPC endpoints of the subscriber, for backfills to share their
+    /// health
+    pub fn endpoints(&self) -> Arc<RpcEndpoints> {
+        self.endpoints.clone()
+    }
+
+    /// This function makes the subscriber fetch through endpoints shared with other users of the
+    /// node, so they share their cooldowns
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoints` - An Arc<RpcEndpoints> that holds the endpoints of the node
+    pub fn set_endpoints(&mut self, endpoints: Arc<RpcEndpoints>) {
+        self.endpoints = endpoints;
+    }
+
+    /// This function returns the first slot the subscriber fetches once it runs, older slots
+    /// are left to a backfill
+    pub fn first_slot(&self) -> u64 {
+        self.latest_slot.saturating_add(1).saturating_sub(SLOT_LAG)
+    }
+
+    /// This function makes the subscriber follow slot notifications of a PubSub endpoint
+    /// instead of polling
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_url` - An Option<String> that holds the websocket url, None to poll
+    pub fn set_ws_url(&mut self, ws_url: Option<String>) {
+        self.ws_url = ws_url;
+    }
+
+    /// This function sets the time without a slot notification after which the subscription is
+    /// given up on, so a websocket that stalls without closing falls back to polling
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_timeout` - A Duration that holds the timeout, also bounding the connection
+    pub fn set_ws_timeout(&mut self, ws_timeout: Duration) {
+        self.ws_timeout = ws_timeout;
+    }
+
     fn fetch_latest_slot(&self) -> Result<u64, AggError> {
-        let slot = self
-            .rpc_client
-            .get_slot_with_commitment(CommitmentConfig::finalized())?;
-        Ok(slot)
+        let commitment = self.commitment.config();
+        self.endpoints
+            .call(|client| Ok(client.get_slot_with_commitment(commitment)?))
     }
 
-    /// This function runs the subscriber client
+    /// This function runs the subscriber client until it is asked to stop. Blocks whose fetch
+    /// already started are still handed to the handler.
     pub async fn run(&mut self) {
+        let mut shutdown = self.shutdown.clone();
+        tokio::select! {
+            _ = self.follow_chain() => {}
+            _ = Shutdown::wait(&mut shutdown) => {}
+        }
+        info!(
+            target: "subscriber",
+            "Subscriber stopped after slot {}",
+            self.latest_slot.saturating_sub(SLOT_LAG)
+        );
+    }
+
+    /// This function fetches the blocks of the finalized slots as they come
+    async fn follow_chain(&mut self) {
         loop {
-            match self.fetch_latest_slot() {
-                Ok(fetched_slot) => {
-                    if self.latest_slot < fetched_slot {
-                        self.latest_slot = self.latest_slot.saturating_add(1);
-                        let sender_clone = self.unbounded_sender.clone();
-                        let chain_url = self.chain_url.clone();
-                        let rpc_block_config = self.rpc_block_config.clone();
-                        let latest_slot = self.latest_slot;
-                        tokio::spawn(async move {
-                            BlockFetcher::invoke(ProtocolMessage::fetch_block(
-                                chain_url,
-                                rpc_block_config,
-                                latest_slot,
-                                sender_clone,
-                            ))
-                            .await;
-                        });
+            match self.ws_url.clone() {
+                Some(ws_url) => {
+                    if let Err(err) = self.follow_slot_notifications(&ws_url).await {
+                        warn!(
+                            target: "subscriber",
+                            "Slot subscription to {} failed, polling for {:?} before reconnecting: {}",
+                            ws_url, WS_RECONNECT_INTERVAL, err
+                        );
+                    }
+                    let reconnect_at = Instant::now() + WS_RECONNECT_INTERVAL;
+                    while Instant::now() < reconnect_at {
+                        self.poll().await;
                     }
                 }
-                Err(err) => {
-                    error!(target: "subscriber", "Failed to fetch latest slot {:?}", err);
+                None => self.poll().await,
+            }
+        }
+    }
+
+    /// This function polls the latest finalized slot once, waiting POLL_INTERVAL when the
+    /// subscriber is caught up or the node is unavailable
+    async fn poll(&mut self) {
+        match self.fetch_latest_slot() {
+            Ok(fetched_slot) => {
+                if !self.advance_to(fetched_slot) {
+                    return;
+                }
+            }
+            Err(err) => {
+                error!(target: "subscriber", "Failed to fetch latest slot {:?}", err);
+            }
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+
+    /// This function follows the `slotSubscribe` notifications of the node until the
+    /// subscription fails, closes or stays silent for longer than the websocket timeout
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_url` - A string slice that holds the websocket url
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - An error once the subscription is over
+    async fn follow_slot_notifications(&mut self, ws_url: &str) -> Result<(), AggError> {
+        let ws_timeout = self.ws_timeout;
+        let timed_out = |step: &str| {
+            AggError::PubsubError(format!("{} timed out after {:?}", step, ws_timeout))
+        };
+        let client = tokio::time::timeout(ws_timeout, PubsubClient::new(ws_url))
+            .await
+            .map_err(|_| timed_out("connecting"))??;
+        let (mut notifications, unsubscribe) =
+            tokio::time::timeout(ws_timeout, client.slot_subscribe())
+                .await
+                .map_err(|_| timed_out("subscribing"))??;
+        info!(target: "subscriber", "Following slot notifications of {}", ws_url);
+        let err = loop {
+            match tokio::time::timeout(ws_timeout, notifications.next()).await {
+                // The root is the latest slot the node finalized
+                Ok(Some(slot_info)) => {
+                    self.advance_to(slot_info.root);
+                }
+                Ok(None) => {
+                    break AggError::PubsubError("the slot subscription was closed".to_string())
+                }
+                Err(_) => break timed_out("waiting for a slot notification"),
+            }
+        };
+        // A stalled node may not answer the unsubscription either
+        let _ = tokio::time::timeout(ws_timeout, unsubscribe()).await;
+        Err(err)
+    }
+
+    /// This function queues the fetches of the blocks up to the latest finalized slot, keeping
+    /// at most `max_slots_per_round` fetches pending, along with the missing slots whose re-fetch
+    /// is due and the slots the db asked for after a reorg
+    ///
+    /// # Arguments
+    ///
+    /// * `finalized_slot` - A u64 that holds the latest finalized slot
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Whether the subscriber caught up with the finalized slot or has to wait for
+    ///   pending fetches to complete
+    fn advance_to(&mut self, finalized_slot: u64) -> bool {
+        self.slot_tracker.set_finalized(finalized_slot);
+        self.track_gaps();
+        for slot in self.gaps.due(Instant::now()) {
+            info!(target: "subscriber", "Fetching missing slot {} again", slot);
+            self.spawn_fetch(slot);
+        }
+        let mut reorged = vec![];
+        if let Some(receiver) = self.refetch_receiver.as_mut() {
+            while let Ok(slot) = receiver.try_recv() {
+                reorged.push(slot);
+            }
+        }
+        for slot in reorged {
+            info!(target: "subscriber", "Fetching slot {} again after a reorg", slot);
+            self.spawn_fetch(slot);
+        }
+        self.autotune();
+        // Slots are only queued while the pool keeps up, so a lagging node or db does not grow
+        // the queue without bound
+        let pending = self.fetch_pool.as_ref().map_or(0, FetchPool::pending) as u64;
+        let room = self.max_slots_per_round.saturating_sub(pending);
+        let target = finalized_slot.min(self.latest_slot.saturating_add(room));
+        while self.latest_slot < target {
+            self.latest_slot = self.latest_slot.saturating_add(1);
+            self.spawn_fetch(self.latest_slot.saturating_sub(SLOT_LAG));
+        }
+        self.latest_slot >= finalized_slot || room == 0
+    }
+
+    /// This function adjusts the fetch settings to the write latency of the db and the slots the
+    /// latest indexed slot trails the slots being fetched
+    fn autotune(&mut self) {
+        let Some(autotuner) = self.autotuner.as_mut() else {
+            return;
+        };
+        // Nothing is lagging before the first block is indexed
+        let slot_lag = self
+            .slot_tracker
+            .context()
+            .latest_indexed
+            .map_or(0, |indexed| {
+                self.latest_slot
+                    .saturating_sub(SLOT_LAG)
+                    .saturating_sub(indexed)
+            });
+        let write_latency = self.write_latency.get();
+        let Some(adjustment) = autotuner.adjust(write_latency, slot_lag, Instant::now()) else {
+            return;
+        };
+        let settings = autotuner.settings();
+        let reason = match adjustment {
+            Adjustment::BackOff => "the db stores blocks slower than the target",
+            Adjustment::SpeedUp => "the indexed slot falls behind",
+        };
+        info!(
+            target: "subscriber",
+            "Fetching {} slots at once in chunks of {} transactions, {} (write latency {:?}, slot lag {})",
+            settings.slots_per_round, settings.chunk_size, reason, write_latency, slot_lag
+        );
+        self.max_slots_per_round = settings.slots_per_round;
+        self.chunk_size = settings.chunk_size;
+    }
+
+    /// This function records the outcomes of the fetches that completed since the last round,
+    /// dead-lettering the slots given up on
+    fn track_gaps(&mut self) {
+        while let Ok((slot, outcome)) = self.outcome_receiver.try_recv() {
+            match self.gaps.record(slot, &outcome, Instant::now()) {
+                Some(GapUpdate::Scheduled(delay)) => {
+                    warn!(
+                        target: "subscriber",
+                        "Block of slot {} is missing, fetching it again in {:?}",
+                        slot, delay
+                    );
+                }
+                Some(GapUpdate::GivenUp) => {
+                    if let FetchOutcome::Failed(failure) = outcome {
+                        error!(
+                            target: "subscriber",
+                            "Giving up on the block of slot {} {:?}",
+                            slot, failure.error
+                        );
+                        BlockFetcher::dead_letter(
+                            &self.unbounded_sender,
+                            DeadLetter::new(FailureStage::Fetch, Some(slot), None, &failure),
+                        );
+                    }
                 }
+                None => {}
             }
         }
     }
+
+    /// This function queues the fetch of the block of a slot in the fetch pool, its outcome is
+    /// read back by the next round
+    ///
+    /// # Arguments
+    ///
+    /// * `slot` - A u64 that holds the slot
+    fn spawn_fetch(&mut self, slot: u64) {
+        let fetch = BlockFetcher::invoke(ProtocolMessage::FetchBlock(
+            self.endpoints.clone(),
+            self.rpc_block_config,
+            slot,
+            self.archive_raw_blocks,
+            self.parse_mode,
+            self.chunk_size,
+            self.fetch_cache.clone(),
+            self.unbounded_sender.clone(),
+        ));
+        // Started with the first fetch, once the shutdown signal is set
+        self.fetch_pool
+            .get_or_insert_with(|| {
+                FetchPool::start(
+                    self.fetch_workers,
+                    self.outcome_sender.clone(),
+                    self.shutdown.clone(),
+                )
+            })
+            .submit(slot, Box::pin(fetch));
+    }
+}
+
+/// This function returns how blocks are requested from the node
+///
+/// # Arguments
+///
+/// * `commitment` - A Commitment that holds the commitment of the blocks requested
+pub(crate) fn block_config(commitment: Commitment) -> RpcBlockConfig {
+    RpcBlockConfig {
+        encoding: Some(UiTransactionEncoding::Base64),
+        transaction_details: None,
+        rewards: None,
+        commitment: Some(commitment.config()),
+        max_supported_transaction_version: Some(0),
+    }
+}
+
+/// This function returns the header of a block fetched from the node
+///
+/// # Arguments
+///
+/// * `slot` - A u64 that holds the slot of the block
+/// * `block` - A UiConfirmedBlock that holds the block
+fn block_header(slot: u64, block: &UiConfirmedBlock) -> BlockHeader {
+    BlockHeader {
+        slot,
+        blockhash: block.blockhash.clone(),
+        block_time: block.block_time,
+        previous_blockhash: Some(block.previous_blockhash.clone()),
+        parent_slot: Some(block.parent_slot),
+        transaction_count: block.transactions.as_ref().map(|txs| txs.len() as u64),
+    }
+}
+
+/// This function fetches the block of a slot from the node again, outside the subscriber, so a
+/// stored block can be corrected. Every attempt goes to the next endpoint, an endpoint failing
+/// transiently cools down as it does for the subscriber.
+///
+/// # Arguments
+///
+/// * `endpoints` - An Arc<RpcEndpoints> that holds the endpoints of the node, shared with the
+///   subscriber
+/// * `commitment` - A Commitment that holds the commitment of the block fetched
+/// * `slot` - A u64 that holds the slot
+///
+/// # Returns
+///
+/// * `Result<(u64, RawBlock), AggError>` - The block number and the block as fetched, or an error
+///   if the slot was skipped or no endpoint served it
+pub async fn fetch_raw_block(
+    endpoints: &Arc<RpcEndpoints>,
+    commitment: Commitment,
+    slot: u64,
+) -> Result<(u64, RawBlock), AggError> {
+    let mut attempts = endpoints.urls().len();
+    loop {
+        attempts -= 1;
+        let fetched = endpoints
+            .call_blocking(move |client| {
+                Ok(client.get_block_with_config(slot, block_config(commitment))?)
+            })
+            .await;
+        let error = match fetched {
+            Ok(block) => {
+                let block_no = block
+                    .block_height
+                    .ok_or_else(|| AggError::NotFound(format!("block height of slot {}", slot)))?;
+                let header = block_header(slot, &block);
+                let transactions = block.transactions.unwrap_or_default();
+                return Ok((
+                    block_no,
+                    RawBlock {
+                        header,
+                        transactions,
+                    },
+                ));
+            }
+            Err(error) => error,
+        };
+        if BlockFetcher::is_slot_skipped(&error) {
+            return Err(AggError::NotFound(format!(
+                "block of skipped slot {}",
+                slot
+            )));
+        }
+        if attempts == 0 || !error.is_transient() {
+            return Err(error);
+        }
+    }
 }
 
-struct BlockFetcher;
+pub(crate) struct BlockFetcher;
 
 impl BlockFetcher {
 
@@ -96,48 +614,171 @@ impl BlockFetcher {
     /// # Arguments
     ///
     /// * `message` - A ProtocolMessage that holds the message
-    async fn invoke(message: ProtocolMessage) {
+    ///
+    /// # Returns
+    ///
+    /// * `FetchOutcome` - Whether the block was fetched, the slot skipped or the fetch failed.
+    ///   Failed fetches are left to the caller to retry or dead-letter
+    pub(crate) async fn invoke(message: ProtocolMessage) -> FetchOutcome {
         match message {
-            ProtocolMessage::FetchBlock(chain_url, rpc_block_config, latest_slot, sender) => {
-                let client =
-                    RpcClient::new_with_timeout(chain_url, std::time::Duration::from_secs(30));
-                match client
-                    .get_block_with_config(latest_slot.saturating_sub(500), rpc_block_config)
-                {
+            ProtocolMessage::FetchBlock(
+                endpoints,
+                rpc_block_config,
+                slot,
+                archive_raw_block,
+                parse_mode,
+                chunk_size,
+                fetch_cache,
+                sender,
+            ) => {
+                // Only finalized blocks are cached, a confirmed block may still be orphaned
+                let cache = fetch_cache.filter(|_| {
+                    rpc_block_config
+                        .commitment
+                        .is_some_and(|commitment| commitment.is_finalized())
+                });
+                let cached = match &cache {
+                    Some(cache) => cache.get_blocking(slot, rpc_block_config).await,
+                    None => None,
+                };
+                let fetched = match cached {
+                    Some(block) => Ok(block),
+                    None => {
+                        // Every attempt goes to the next endpoint not cooling down
+                        let fetched = FETCH_RETRY
+                            .run(FailureStage::Fetch, || {
+                                // The RpcClient blocks, so the request runs on the blocking pool
+                                // rather than holding a runtime thread for the whole round trip
+                                endpoints.call_blocking(move |client| {
+                                    Ok(client.get_block_with_config(slot, rpc_block_config)?)
+                                })
+                            })
+                            .await;
+                        match (cache, fetched) {
+                            (Some(cache), Ok(block)) => cache
+                                .insert_blocking(slot, rpc_block_config, block)
+                                .await
+                                .map_err(|error| Failure { error, attempts: 1 }),
+                            (_, fetched) => fetched,
+                        }
+                    }
+                };
+                match fetched {
                     Ok(block) => {
                         if let Some(block_no) = block.block_height {
-                            if let Some(txs) = block.transactions {
-                                let chunks = txs.chunks(10);
-                                let len_of_chunks = chunks.len() as u64;
-                                for (index, chunk) in chunks.enumerate() {
-                                    let sender_clone = sender.clone();
-                                    let chunk_clone = chunk.to_vec();
-                                    tokio::spawn(async move {
-                                        if let Err(error) =
-                                            Parser::invoke(ProtocolMessage::new_chuck(
-                                                block_no,
-                                                index as u64,
-                                                len_of_chunks,
-                                                chunk_clone,
-                                                sender_clone,
-                                            ))
-                                            .await
-                                        {
-                                            error!(target: "subscriber", "Error from Parser {}", error);
-                                        }
-                                    });
+                            let header = block_header(slot, &block);
+                            let txs = block.transactions.unwrap_or_default();
+                            if archive_raw_block {
+                                let raw_block = RawBlock {
+                                    header: header.clone(),
+                                    transactions: txs.clone(),
+                                };
+                                if let Err(error) = sender.send(ProtocolMessage::ArchiveRawBlock(
+                                    block_no,
+                                    Box::new(raw_block),
+                                )) {
+                                    error!(target: "subscriber", "Error from sender {}", error);
+                                }
+                            }
+                            // A block without transactions is still sent as one empty chunk, so
+                            // it is finalized and does not hold back the blocks after it. The
+                            // transactions are moved into the chunks, spilled past the budget
+                            let mut chunks = vec![];
+                            let mut txs = txs.into_iter();
+                            loop {
+                                let chunk: Vec<_> = txs.by_ref().take(chunk_size).collect();
+                                if chunk.is_empty() && !chunks.is_empty() {
+                                    break;
                                 }
+                                let chunk_no = chunks.len() as u64;
+                                chunks.push(spill::payload(slot, chunk_no, chunk));
+                            }
+                            let len_of_chunks = chunks.len() as u64;
+                            for (index, chunk_clone) in chunks.into_iter().enumerate() {
+                                let sender_clone = sender.clone();
+                                let header_clone = header.clone();
+                                tokio::spawn(async move {
+                                    let parsed = PARSE_RETRY
+                                        .run(FailureStage::Parse, || {
+                                            Parser::invoke(
+                                                ProtocolMessage::new_chuck(
+                                                    block_no,
+                                                    header_clone.clone(),
+                                                    index as u64,
+                                                    len_of_chunks,
+                                                    chunk_clone.clone(),
+                                                    sender_clone.clone(),
+                                                ),
+                                                parse_mode,
+                                            )
+                                        })
+                                        .await;
+                                    if let Err(failure) = parsed {
+                                        error!(target: "subscriber", "Error from Parser {}", failure.error);
+                                        Self::dead_letter(
+                                            &sender_clone,
+                                            DeadLetter::new(
+                                                FailureStage::Parse,
+                                                Some(slot),
+                                                Some(block_no),
+                                                &failure,
+                                            ),
+                                        );
+                                    }
+                                });
                             }
                         } else {
                             warn!(target: "subscriber", "Block Number not available");
                         }
+                        FetchOutcome::Fetched
                     }
-                    Err(err) => {
-                        error!(target: "subscriber", "Failed to fetch block {:?}", err);
+                    Err(failure) if Self::is_slot_skipped(&failure.error) => {
+                        debug!(target: "subscriber", "Slot {} was skipped", slot);
+                        if let Err(error) = sender.send(ProtocolMessage::SkippedSlot(slot)) {
+                            error!(target: "subscriber", "Error from sender {}", error);
+                        }
+                        FetchOutcome::Skipped
+                    }
+                    Err(failure) => {
+                        error!(
+                            target: "subscriber",
+                            "Failed to fetch block of slot {} after {} attempts {:?}",
+                            slot, failure.attempts, failure.error
+                        );
+                        FetchOutcome::Failed(failure)
                     }
                 }
             }
-            _ => {}
+            _ => FetchOutcome::Fetched,
         }
     }
+
+    fn is_slot_skipped(err: &AggError) -> bool {
+        matches!(
+            err.root(),
+            AggError::ClientError(err) if matches!(
+                err.kind(),
+                ClientErrorKind::RpcError(RpcError::RpcResponseError { code, .. })
+                    if *code == JSON_RPC_SERVER_ERROR_SLOT_SKIPPED
+                        || *code == JSON_RPC_SERVER_ERROR_LONG_TERM_STORAGE_SLOT_SKIPPED
+            )
+        )
+    }
+
+    /// This function hands a block the pipeline gave up on to the dead-letter queue of the db
+    pub(crate) fn dead_letter(sender: &UnboundedSender<ProtocolMessage>, letter: DeadLetter) {
+        if let Err(error) = sender.send(ProtocolMessage::DeadLetter(Box::new(letter))) {
+            error!(target: "subscriber", "Error from sender {}", error);
+        }
+    }
+}
+
+impl Worker for Subscriber {
+    fn set_shutdown(&mut self, shutdown: Shutdown) {
+        self.shutdown = Some(shutdown);
+    }
+
+    fn work(&mut self) -> BoxFuture<'_, ()> {
+        Box::pin(self.run())
+    }
 }
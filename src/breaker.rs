@@ -0,0 +1,126 @@
+use crate::config::BreakerConfig;
+use crate::error::AggError;
+use crate::metrics;
+use log::{info, warn};
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    /// Calls failed in a row
+    consecutive_failures: u32,
+    /// Until when calls fail without reaching the node
+    open_until: Option<Instant>,
+}
+
+/// Circuit breaker of the calls made to the node while answering requests. Once
+/// `failure_threshold` calls failed in a row, with a transient error or by timing out, calls fail
+/// at once for `open_ms` instead of holding their request. After that a single call probes the
+/// node while the others keep failing, closing the breaker if it succeeds and opening it again if
+/// it fails.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    config: BreakerConfig,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: BreakerConfig) -> Self {
+        CircuitBreaker {
+            config,
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    /// This function tells whether a call may go to the node. Once the breaker was open for
+    /// `open_ms` the call is let through as the probe, the breaker stays open for the others
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - An Instant that holds the current time
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - An error while the breaker is open
+    pub fn check(&self, now: Instant) -> Result<(), AggError> {
+        let Ok(mut state) = self.state.lock() else {
+            return Ok(());
+        };
+        match state.open_until {
+            Some(until) if until > now => Err(AggError::UpstreamUnavailable(format!(
+                "the node failed {} call(s) in a row, retry in {:?}",
+                state.consecutive_failures,
+                until - now
+            ))),
+            Some(_) => {
+                // Moved forward rather than cleared, so a probe dropped before its outcome is
+                // recorded is followed by another one instead of holding the breaker open
+                state.open_until = Some(now + Duration::from_millis(self.config.open_ms));
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// This function records the outcome of a call, opening the breaker once too many calls failed
+    /// in a row
+    ///
+    /// # Arguments
+    ///
+    /// * `failed` - A bool that holds whether the call failed
+    /// * `now` - An Instant that holds the current time
+    pub fn record(&self, failed: bool, now: Instant) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        if !failed {
+            if state.open_until.take().is_some() {
+                info!(target: "rpc", "Node responds again, closing the circuit breaker");
+            }
+            state.consecutive_failures = 0;
+            metrics::UPSTREAM_BREAKER_OPEN.set(0);
+            return;
+        }
+        state.consecutive_failures = state.consecutive_failures.saturating_add(1);
+        if state.consecutive_failures >= self.config.failure_threshold {
+            let open = Duration::from_millis(self.config.open_ms);
+            state.open_until = Some(now + open);
+            metrics::UPSTREAM_BREAKER_OPEN.set(1);
+            warn!(
+                target: "rpc",
+                "Node failed {} call(s) in a row, failing calls at once for {:?}",
+                state.consecutive_failures, open
+            );
+        }
+    }
+
+    /// This function makes a call to the node unless the breaker is open, bounded by a timeout
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - A Duration that holds the time the call may take
+    /// * `call` - A Future that makes the call
+    ///
+    /// # Returns
+    ///
+    /// * `Result<T, AggError>` - The result of the call, an error if the breaker is open or the
+    ///   call timed out
+    pub async fn call<T, F>(&self, timeout: Duration, call: F) -> Result<T, AggError>
+    where
+        F: Future<Output = Result<T, AggError>>,
+    {
+        self.check(Instant::now())?;
+        let result = match tokio::time::timeout(timeout, call).await {
+            Ok(result) => result,
+            Err(_) => Err(AggError::QueryTimedOut),
+        };
+        // An answer such as a skipped slot is not a failure of the node
+        let failed = matches!(
+            &result,
+            Err(err) if err.is_transient() || matches!(err.root(), AggError::QueryTimedOut)
+        );
+        self.record(failed, Instant::now());
+        result
+    }
+}
@@ -195,7 +195,18 @@ impl Builder<SourceChain, NoDbPath, NoDbSender, NoDbReceiver, HandlerSender, NoH
 
 impl Builder<NoSourceChain, DbPath, NoDbSender, DbReceiver, NoHandlerSender, NoHandlerReceiver> {
     pub fn build(self) -> Result<RocksDb, AggError> {
-        RocksDb::initialize(self.db_path.0, self.db_receiver.0)
+        RocksDb::initialize(self.db_path.0, self.db_receiver.0, None)
+    }
+
+    /// This function builds a db client that opens the db as a read only secondary, so it can
+    /// serve queries against the data directory of a running instance without taking its lock
+    /// and keeps up with the blocks the instance writes
+    ///
+    /// # Arguments
+    ///
+    /// * `secondary_path` - A String that holds the directory the secondary keeps its own files in
+    pub fn build_read_only(self, secondary_path: String) -> Result<RocksDb, AggError> {
+        RocksDb::initialize(self.db_path.0, self.db_receiver.0, Some(secondary_path))
     }
 }
 
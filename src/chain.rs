@@ -0,0 +1,91 @@
+//! Chain id of the cluster the process indexes. A process runs one pipeline indexing one
+//! cluster, so the chain id, the metrics it labels and the sync state in `/status` are process
+//! wide. Clusters are indexed by one instance each, told apart on one dashboard by their chain id.
+
+use crate::envelope::SlotContext;
+use crate::error::AggError;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+
+/// Genesis hashes of the public clusters and the chain ids they are labeled with
+const KNOWN_CLUSTERS: [(&str, &str); 3] = [
+    (
+        "5eykt4UsFv8P8NJdTREpY1vzqKqZKvdpKuc147dw2N9d",
+        "mainnet-beta",
+    ),
+    ("EtWTRABZaYq6iMfeYKouRu166VU2xqa1wcaWoxPkrZBG", "devnet"),
+    ("4uhcVJyU9pJkvQyS88uRDiswHXSCkY3zQawwpjk2NsNY", "testnet"),
+];
+
+static CHAIN_ID: OnceCell<String> = OnceCell::new();
+
+/// This function returns the chain id of a cluster, the name of a public cluster or the first
+/// eight characters of the genesis hash of any other
+///
+/// # Arguments
+///
+/// * `genesis_hash` - A string slice that holds the genesis hash of the cluster
+pub fn chain_id_of(genesis_hash: &str) -> String {
+    KNOWN_CLUSTERS
+        .iter()
+        .find(|(hash, _)| *hash == genesis_hash)
+        .map(|(_, name)| name.to_string())
+        .unwrap_or_else(|| genesis_hash.chars().take(8).collect())
+}
+
+/// This function sets the chain id the metrics, the logs and the status of this instance are
+/// labeled with. Installing the chain id already set again does nothing.
+///
+/// # Arguments
+///
+/// * `chain_id` - A String that holds the chain id
+///
+/// # Returns
+///
+/// * `Result<(), AggError>` - An error when the process already indexes another chain
+pub fn install(chain_id: String) -> Result<(), AggError> {
+    let installed = CHAIN_ID.get_or_init(|| chain_id.clone());
+    if *installed != chain_id {
+        return Err(AggError::ConfigError(format!(
+            "the process indexes {}, it can not index {} as well, run one instance per cluster",
+            installed, chain_id
+        )));
+    }
+    Ok(())
+}
+
+/// This function returns the chain id of this instance, None unless it was installed
+pub fn installed() -> Option<&'static str> {
+    CHAIN_ID.get().map(String::as_str)
+}
+
+/// How far the indexed blocks of the chain of the process trail the chain
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SyncState {
+    pub chain_id: String,
+    /// Latest finalized slot of the chain, None on instances not ingesting from a node
+    pub finalized_slot: Option<u64>,
+    /// Slot of the latest block whose state is applied, None before the first block
+    pub latest_indexed_slot: Option<u64>,
+    /// Slots the latest indexed slot trails the finalized slot
+    pub slot_lag: Option<u64>,
+}
+
+impl SyncState {
+    /// This function builds the sync state of a chain from the slots responses are stamped with
+    ///
+    /// # Arguments
+    ///
+    /// * `chain_id` - A string slice that holds the chain id
+    /// * `context` - A SlotContext that holds the finalized and latest indexed slots
+    pub fn new(chain_id: &str, context: SlotContext) -> Self {
+        SyncState {
+            chain_id: chain_id.to_string(),
+            finalized_slot: context.finalized,
+            latest_indexed_slot: context.latest_indexed,
+            slot_lag: context
+                .finalized
+                .map(|finalized| finalized.saturating_sub(context.latest_indexed.unwrap_or(0))),
+        }
+    }
+}
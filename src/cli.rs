@@ -1,21 +1,110 @@
+use std::path::PathBuf;
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
 pub struct Cli {
-    #[structopt(
-        short = "s",
-        long = "chain-url",
-        default_value = "https://devnet.helius-rpc.com/?api-key=5f5e0b82-0a0e-4638-b095-8077e2d8c9b8"
-    )]
-    pub chain_url: String,
-
-    #[structopt(
-        short = "d",
-        long = "db-url",
-        default_value = "/Users/krishnasingh/Workspace/Official/solana-agg/db"
-    )]
-    pub db_path: String,
-
-    #[structopt(short = "", long = "port-no", default_value = "9944")]
-    pub port_no: String,
+    /// RPC url of the node, or the urls of several nodes separated by commas to fail over
+    /// between, overrides `node.chain_url` of the config
+    #[structopt(short = "s", long = "chain-url")]
+    pub chain_url: Option<String>,
+
+    /// Overrides `node.db_path` of the config
+    #[structopt(short = "d", long = "db-url")]
+    pub db_path: Option<String>,
+
+    /// Port of the HTTP server, overrides `node.port` of the config
+    #[structopt(long = "port-no")]
+    pub port_no: Option<u16>,
+
+    /// Blocks fetched from the node at once, overrides `node.fetch_workers` of the config
+    #[structopt(long = "fetch-workers")]
+    pub fetch_workers: Option<usize>,
+
+    /// Port of the gRPC server, which is not started when unset
+    #[structopt(long = "grpc-port")]
+    pub grpc_port: Option<u16>,
+
+    /// TOML config file, its settings are overridden by the `AGG_*` environment variables and
+    /// the flags
+    #[structopt(short = "c", long = "config", parse(from_os_str))]
+    pub config: Option<PathBuf>,
+
+    /// Block stream url of a primary to follow as a hot standby,
+    /// e.g. ws://primary:9944/block_stream
+    #[structopt(long = "standby-of")]
+    pub standby_of: Option<String>,
+
+    #[structopt(long = "failover-timeout-secs", default_value = "30")]
+    pub failover_timeout_secs: u64,
+
+    /// Follows the db of a running instance as a read only secondary and only serves queries,
+    /// without ingesting blocks
+    #[structopt(long = "read-only", conflicts_with = "standby-of")]
+    pub read_only: bool,
+
+    /// Directory a read only instance keeps its own files in, `<db path>-secondary` when unset
+    #[structopt(long = "secondary-path", requires = "read-only")]
+    pub secondary_path: Option<String>,
+
+    /// Fetches the blocks from this slot up to the first slot of the subscriber, resuming the
+    /// backfill started from the same slot before a restart
+    #[structopt(long = "backfill-from", conflicts_with_all = &["standby-of", "read-only"])]
+    pub backfill_from: Option<u64>,
+
+    /// Starts even when a startup check fails, logging the failure as a warning
+    #[structopt(long = "force")]
+    pub force: bool,
+
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    /// Compares the block digests of two aggregator instances over a block range
+    Compare {
+        /// Base url of the first instance, e.g. http://127.0.0.1:9944
+        #[structopt(long = "left")]
+        left: String,
+
+        /// Base url of the second instance
+        #[structopt(long = "right")]
+        right: String,
+
+        #[structopt(long = "start")]
+        start: u64,
+
+        #[structopt(long = "end")]
+        end: u64,
+    },
+    /// Creates or restores a consistent snapshot of the db at `node.db_path`
+    Snapshot(SnapshotCommand),
+    /// Submits synthetic transfers to the test validator or devnet at `node.chain_url` and
+    /// checks a running aggregator indexes them, as a smoke test after a deployment
+    TestHarness {
+        /// Base url of the aggregator ingesting from the node
+        #[structopt(long = "api-url", default_value = "http://127.0.0.1:9944")]
+        api_url: String,
+
+        #[structopt(long = "transfers", default_value = "3")]
+        transfers: u64,
+
+        /// Slots the chain may advance past a transfer before it has to be indexed
+        #[structopt(long = "within-slots", default_value = "150")]
+        within_slots: u64,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub enum SnapshotCommand {
+    /// Writes a RocksDB checkpoint of the db to a new directory, with the node stopped
+    Create {
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+    },
+    /// Bootstraps a missing or empty db from a snapshot
+    Restore {
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+    },
 }
@@ -0,0 +1,460 @@
+use crate::envelope::ResponseEnvelope;
+use crate::error::{AggError, ErrorBody, ErrorContextExt};
+use crate::inclusion::TxVerification;
+use crate::replication::ReplicationMessage;
+use crate::tenant::API_KEY_HEADER;
+use crate::util::{
+    AccountBalance, AccountEvent, AccountSummary, AccountTransactions, ActiveAccountsStats, Block,
+    BlockDigest, BlockFilter, BlockSummary, Status, TxInclusion, TxLogs, TxRecord,
+};
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{SinkExt, StreamExt};
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+pub const TX_DETAILS: &str = "/tx_details/{tx_id}";
+pub const TX_LOGS: &str = "/tx_logs/{tx_id}";
+pub const TX_INCLUSION: &str = "/tx_inclusion/{tx_id}";
+pub const VERIFY_TX: &str = "/verify_tx/{tx_id}";
+pub const BLOCK_DETAILS: &str = "/block_details/{id}";
+pub const LATEST_BLOCK: &str = "/latest_block";
+pub const BLOCK_RANGE: &str = "/block_range/{start}/{end}";
+pub const LATEST_BLOCKS: &str = "/latest_blocks";
+pub const ACCOUNT_BALANCE: &str = "/account_balance/{account_id}";
+pub const ACCOUNT_TXS: &str = "/account_txs/{account_id}";
+pub const ACCOUNT_SUMMARY: &str = "/account_summary/{account_id}";
+pub const STATUS: &str = "/status";
+pub const ACTIVE_ACCOUNTS: &str = "/active_accounts";
+pub const BLOCK_DIGEST: &str = "/block_digest/{block_no}";
+pub const BLOCK_STREAM: &str = "/block_stream";
+pub const ACCOUNT_STREAM: &str = "/account_stream/{account_id}";
+
+/// Routes of the server the client calls, each one served by a handler of the same path
+pub const ROUTES: [&str; 16] = [
+    TX_DETAILS,
+    TX_LOGS,
+    TX_INCLUSION,
+    VERIFY_TX,
+    BLOCK_DETAILS,
+    LATEST_BLOCK,
+    BLOCK_RANGE,
+    LATEST_BLOCKS,
+    ACCOUNT_BALANCE,
+    ACCOUNT_TXS,
+    ACCOUNT_SUMMARY,
+    STATUS,
+    ACTIVE_ACCOUNTS,
+    BLOCK_DIGEST,
+    BLOCK_STREAM,
+    ACCOUNT_STREAM,
+];
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Async client of the HTTP and WebSocket API of an aggregator. Responses are requested in the
+/// default format, amounts in lamports and fields in snake case, and unwrapped from their
+/// envelope.
+#[derive(Clone)]
+pub struct AggClient {
+    base_url: String,
+    api_key: Option<String>,
+    http: reqwest::Client,
+}
+
+impl AggClient {
+    /// This function creates a client of an aggregator
+    ///
+    /// # Arguments
+    ///
+    /// * `base_url` - A string slice that holds the base url, e.g. http://127.0.0.1:9944
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The client
+    pub fn new(base_url: &str) -> Self {
+        AggClient {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: None,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// This function makes the client send an api key with every request, required by
+    /// instances serving tenants
+    pub fn with_api_key(mut self, api_key: &str) -> Self {
+        self.api_key = Some(api_key.to_string());
+        self
+    }
+
+    pub async fn tx_details(&self, tx_id: &str) -> Result<TxRecord, AggError> {
+        self.get(&route(TX_DETAILS, &[tx_id]), &[]).await
+    }
+
+    /// This function fetches the outcome, compute units and program logs of a transaction
+    pub async fn tx_logs(&self, tx_id: &str) -> Result<TxLogs, AggError> {
+        self.get(&route(TX_LOGS, &[tx_id]), &[]).await
+    }
+
+    /// This function fetches the block context and position of a transaction
+    pub async fn tx_inclusion(&self, tx_id: &str) -> Result<TxInclusion, AggError> {
+        self.get(&route(TX_INCLUSION, &[tx_id]), &[]).await
+    }
+
+    /// This function has the server check a stored transaction against the block of the node
+    pub async fn verify_tx(&self, tx_id: &str) -> Result<TxVerification, AggError> {
+        self.get(&route(VERIFY_TX, &[tx_id]), &[]).await
+    }
+
+    /// This function fetches a block with the full records of its transactions
+    pub async fn block_details(&self, block_no: u64) -> Result<Block, AggError> {
+        self.get(
+            &route(BLOCK_DETAILS, &[&block_no.to_string()]),
+            &[("expand", "full".to_string())],
+        )
+        .await
+    }
+
+    /// This function fetches the block of a slot with the full records of its transactions
+    pub async fn block_details_at_slot(&self, slot: u64) -> Result<Block, AggError> {
+        self.get(
+            &route(BLOCK_DETAILS, &[&slot.to_string()]),
+            &[("expand", "full".to_string()), ("by", "slot".to_string())],
+        )
+        .await
+    }
+
+    /// This function fetches the latest finalized block along with its block number
+    pub async fn latest_block(&self) -> Result<(u64, Block), AggError> {
+        self.get(LATEST_BLOCK, &[("expand", "full".to_string())])
+            .await
+    }
+
+    /// This function fetches the blocks of a range by block number, at most the span the
+    /// instance allows
+    pub async fn block_range(
+        &self,
+        start: u64,
+        end: u64,
+    ) -> Result<BTreeMap<u64, Block>, AggError> {
+        self.get(
+            &route(BLOCK_RANGE, &[&start.to_string(), &end.to_string()]),
+            &[("expand", "full".to_string())],
+        )
+        .await
+    }
+
+    pub async fn latest_blocks(&self, limit: u64) -> Result<Vec<BlockSummary>, AggError> {
+        self.get(LATEST_BLOCKS, &[("limit", limit.to_string())])
+            .await
+    }
+
+    /// This function fetches the balance of an account, at the latest block or at a block.
+    /// Accounts never observed are returned with `known` set to false.
+    pub async fn account_balance(
+        &self,
+        account: &str,
+        block_no: Option<u64>,
+    ) -> Result<AccountBalance, AggError> {
+        let query: Vec<_> = block_no
+            .map(|block_no| ("block_no", block_no.to_string()))
+            .into_iter()
+            .collect();
+        // Accounts never observed are answered with a 404 still holding their balance
+        self.fetch(&route(ACCOUNT_BALANCE, &[account]), &query, true)
+            .await
+    }
+
+    /// This function fetches a page of the transactions involving an account, newest first
+    ///
+    /// # Arguments
+    ///
+    /// * `account` - A string slice that holds the account
+    /// * `cursor` - An Option<&str> that holds the `next_cursor` of the previous page
+    /// * `limit` - A u64 that holds the transactions of the page
+    ///
+    /// # Returns
+    ///
+    /// * `Result<AccountTransactions, AggError>` - The page or an error
+    pub async fn account_transactions(
+        &self,
+        account: &str,
+        cursor: Option<&str>,
+        limit: u64,
+    ) -> Result<AccountTransactions, AggError> {
+        let mut query = vec![("limit", limit.to_string())];
+        if let Some(cursor) = cursor {
+            query.push(("cursor", cursor.to_string()));
+        }
+        self.get(&route(ACCOUNT_TXS, &[account]), &query).await
+    }
+
+    pub async fn account_summary(&self, account: &str) -> Result<AccountSummary, AggError> {
+        self.get(&route(ACCOUNT_SUMMARY, &[account]), &[]).await
+    }
+
+    pub async fn status(&self) -> Result<Status, AggError> {
+        self.get(STATUS, &[]).await
+    }
+
+    pub async fn active_accounts(&self, days: u64) -> Result<Vec<ActiveAccountsStats>, AggError> {
+        self.get(ACTIVE_ACCOUNTS, &[("days", days.to_string())])
+            .await
+    }
+
+    pub async fn block_digest(&self, block_no: u64) -> Result<BlockDigest, AggError> {
+        self.get(&route(BLOCK_DIGEST, &[&block_no.to_string()]), &[])
+            .await
+    }
+
+    /// This function subscribes to the finalized blocks
+    ///
+    /// # Arguments
+    ///
+    /// * `from_block_no` - An Option<u64> that holds the first stored block to replay
+    /// * `filter` - A BlockFilter that holds the transactions wanted, the empty filter streams
+    ///   blocks whole
+    ///
+    /// # Returns
+    ///
+    /// * `Result<BlockSubscription, AggError>` - The subscription or an error
+    pub async fn subscribe_blocks(
+        &self,
+        from_block_no: Option<u64>,
+        filter: &BlockFilter,
+    ) -> Result<BlockSubscription, AggError> {
+        let mut query = filter_query(filter);
+        if let Some(block_no) = from_block_no {
+            query.push(("block_no", block_no.to_string()));
+        }
+        let socket = self.connect(BLOCK_STREAM, &query).await?;
+        Ok(BlockSubscription(Subscription::new(socket)))
+    }
+
+    /// This function subscribes to the transactions involving an account
+    ///
+    /// # Arguments
+    ///
+    /// * `account` - A string slice that holds the account
+    /// * `from_slot` - An Option<u64> that holds the slot to replay the transactions from
+    /// * `resume_token` - An Option<&str> that holds the token the position of the stream is
+    ///   kept under
+    ///
+    /// # Returns
+    ///
+    /// * `Result<AccountSubscription, AggError>` - The subscription or an error
+    pub async fn subscribe_account(
+        &self,
+        account: &str,
+        from_slot: Option<u64>,
+        resume_token: Option<&str>,
+    ) -> Result<AccountSubscription, AggError> {
+        let mut query = Vec::new();
+        if let Some(slot) = from_slot {
+            query.push(("from_slot", slot.to_string()));
+        }
+        if let Some(token) = resume_token {
+            query.push(("resume_token", token.to_string()));
+        }
+        let socket = self
+            .connect(&route(ACCOUNT_STREAM, &[account]), &query)
+            .await?;
+        Ok(AccountSubscription(Subscription::new(socket)))
+    }
+
+    async fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> Result<T, AggError> {
+        self.fetch(path, query, false).await
+    }
+
+    /// This function sends a GET request and unwraps the data of its response
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - A string slice that holds the path of the route
+    /// * `query` - A slice of the query parameters
+    /// * `data_when_not_found` - A bool that holds whether a 404 response holds data
+    ///
+    /// # Returns
+    ///
+    /// * `Result<T, AggError>` - The data of the response, or the error the server answered with
+    async fn fetch<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+        data_when_not_found: bool,
+    ) -> Result<T, AggError> {
+        let url = format!("{}{}", self.base_url, path);
+        let mut request = self.http.get(&url).query(query);
+        if let Some(api_key) = &self.api_key {
+            request = request.header(API_KEY_HEADER, api_key);
+        }
+        let response = request.send().await.with_endpoint(&url)?;
+        let status = response.status();
+        let body = response.bytes().await.with_endpoint(&url)?;
+        if status.is_success() || (data_when_not_found && status == StatusCode::NOT_FOUND) {
+            let envelope: ResponseEnvelope<T> = serde_json::from_slice(&body)?;
+            return Ok(envelope.data);
+        }
+        // Instances of older versions answer errors with an enveloped message
+        let message = serde_json::from_slice::<ErrorBody>(&body)
+            .map(|body| body.error.message)
+            .or_else(|_| {
+                serde_json::from_slice::<ResponseEnvelope<String>>(&body)
+                    .map(|envelope| envelope.data)
+            })
+            .unwrap_or_else(|_| String::from_utf8_lossy(&body).into_owned());
+        Err(AggError::ApiError(status.as_u16(), message)).with_endpoint(&url)
+    }
+
+    async fn connect(&self, path: &str, query: &[(&str, String)]) -> Result<Socket, AggError> {
+        let mut url = reqwest::Url::parse(&format!("{}{}", self.base_url, path))
+            .map_err(|err| AggError::ConfigError(err.to_string()))?;
+        url.query_pairs_mut()
+            .extend_pairs(query.iter().map(|(key, value)| (*key, value.as_str())));
+        let scheme = if url.scheme() == "https" { "wss" } else { "ws" };
+        url.set_scheme(scheme)
+            .map_err(|_| AggError::ConfigError(format!("{} has no websocket url", url)))?;
+        let mut request =
+            tokio_tungstenite::tungstenite::client::IntoClientRequest::into_client_request(
+                url.as_str(),
+            )
+            .map_err(|err| AggError::PubsubError(err.to_string()))?;
+        if let Some(api_key) = &self.api_key {
+            let value = api_key.parse().map_err(|_| {
+                AggError::ConfigError("the api key is not a valid header".to_string())
+            })?;
+            request.headers_mut().insert(API_KEY_HEADER, value);
+        }
+        let (socket, _) = tokio_tungstenite::connect_async(request)
+            .await
+            .map_err(|err| AggError::PubsubError(err.to_string()))
+            .with_endpoint(url.as_str())?;
+        Ok(socket)
+    }
+}
+
+/// This function fills the parameters of a route template
+///
+/// # Arguments
+///
+/// * `template` - A string slice that holds the route, its parameters in braces
+/// * `params` - A slice of the values of the parameters, in order
+///
+/// # Returns
+///
+/// * `String` - The path
+pub fn route(template: &str, params: &[&str]) -> String {
+    let mut params = params.iter();
+    template
+        .split('/')
+        .map(
+            |segment| match (segment.starts_with('{'), segment.ends_with('}')) {
+                (true, true) => params.next().copied().unwrap_or(segment),
+                _ => segment,
+            },
+        )
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// This function returns the query parameters of a block stream filter
+pub fn filter_query(filter: &BlockFilter) -> Vec<(&'static str, String)> {
+    let mut query = Vec::new();
+    if !filter.programs.is_empty() {
+        query.push(("programs", filter.programs.join(",")));
+    }
+    if !filter.accounts.is_empty() {
+        query.push(("accounts", filter.accounts.join(",")));
+    }
+    if let Some(min_amount) = filter.min_amount {
+        query.push(("min_amount", min_amount.to_string()));
+    }
+    if filter.failed_only {
+        query.push(("failed_only", "true".to_string()));
+    }
+    query
+}
+
+/// A WebSocket stream of the server, answering its pings
+struct Subscription {
+    sink: SplitSink<Socket, Message>,
+    stream: SplitStream<Socket>,
+}
+
+impl Subscription {
+    fn new(socket: Socket) -> Self {
+        let (sink, stream) = socket.split();
+        Subscription { sink, stream }
+    }
+
+    /// This function waits for the next text message of the stream
+    async fn next_text(&mut self) -> Option<Result<String, AggError>> {
+        while let Some(message) = self.stream.next().await {
+            match message {
+                Ok(Message::Text(text)) => return Some(Ok(text)),
+                Ok(Message::Ping(bytes)) => {
+                    if let Err(err) = self.sink.send(Message::Pong(bytes)).await {
+                        return Some(Err(AggError::PubsubError(err.to_string())));
+                    }
+                }
+                Ok(Message::Close(_)) => return None,
+                Ok(_) => {}
+                Err(err) => return Some(Err(AggError::PubsubError(err.to_string()))),
+            }
+        }
+        None
+    }
+}
+
+/// Blocks streamed by the server, reduced to the matching transactions when filtered
+pub struct BlockSubscription(Subscription);
+
+impl BlockSubscription {
+    /// This function waits for the next block
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Result<(u64, Block), AggError>>` - The block number and block, None once the
+    ///   server closed the stream
+    pub async fn next(&mut self) -> Option<Result<(u64, Block), AggError>> {
+        loop {
+            let text = match self.0.next_text().await? {
+                Ok(text) => text,
+                Err(err) => return Some(Err(err)),
+            };
+            match serde_json::from_str(&text) {
+                Ok(ReplicationMessage::Block(block_no, block)) => {
+                    return Some(Ok((block_no, *block)))
+                }
+                Ok(ReplicationMessage::Heartbeat) => {}
+                Err(err) => return Some(Err(err.into())),
+            }
+        }
+    }
+}
+
+/// Transactions involving an account streamed by the server
+pub struct AccountSubscription(Subscription);
+
+impl AccountSubscription {
+    /// This function waits for the next transaction involving the account
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Result<AccountEvent, AggError>>` - The event, None once the server closed the
+    ///   stream
+    pub async fn next(&mut self) -> Option<Result<AccountEvent, AggError>> {
+        let text = match self.0.next_text().await? {
+            Ok(text) => text,
+            Err(err) => return Some(Err(err)),
+        };
+        Some(serde_json::from_str(&text).map_err(AggError::from))
+    }
+}
@@ -0,0 +1,98 @@
+use crate::error::AggError;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Format version byte leading the records encoded in JSON
+const JSON_FORMAT: u8 = 0x01;
+/// Format version byte leading the records encoded in bincode
+const BINCODE_FORMAT: u8 = 0x02;
+
+/// A record stored behind the codec. Stored types omit their empty fields when serialized, which
+/// bincode cannot decode, so bincode encodes the wire form of the record, whose fields are always
+/// serialized.
+pub trait Record: Serialize + DeserializeOwned {
+    type Wire: Serialize + DeserializeOwned;
+
+    /// This function returns the wire form of the record
+    fn to_wire(&self) -> Result<Self::Wire, AggError>;
+
+    /// This function builds the record back from its wire form
+    ///
+    /// # Arguments
+    ///
+    /// * `wire` - The wire form of the record
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, AggError>` - The record or an error
+    fn from_wire(wire: Self::Wire) -> Result<Self, AggError>;
+}
+
+/// How blocks and raw blocks are encoded when stored. JSON keeps the db readable by hand, bincode
+/// takes less space and decodes faster. Every record is led by the format version byte of its
+/// codec, records stored before the codec existed are JSON without one. Either codec reads the
+/// records of the other, so it can be switched at any time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Codec {
+    #[default]
+    Json,
+    Bincode,
+}
+
+impl Codec {
+    /// This function encodes a record, leading it with the format version byte of the codec
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - A reference to the record
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<u8>, AggError>` - The encoded record or an error
+    pub fn encode<T: Record>(self, value: &T) -> Result<Vec<u8>, AggError> {
+        match self {
+            Codec::Json => {
+                let mut bytes = vec![JSON_FORMAT];
+                serde_json::to_writer(&mut bytes, value)?;
+                Ok(bytes)
+            }
+            Codec::Bincode => {
+                let mut bytes = vec![BINCODE_FORMAT];
+                bincode::serialize_into(&mut bytes, &value.to_wire()?)
+                    .map_err(|err| AggError::CodecError(err.to_string()))?;
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Codec::Json => write!(f, "json"),
+            Codec::Bincode => write!(f, "bincode"),
+        }
+    }
+}
+
+/// This function decodes a record in the format its version byte tells, JSON without one
+///
+/// # Arguments
+///
+/// * `bytes` - A byte slice that holds the encoded record
+///
+/// # Returns
+///
+/// * `Result<T, AggError>` - The record or an error
+pub fn decode<T: Record>(bytes: &[u8]) -> Result<T, AggError> {
+    match bytes.split_first() {
+        Some((&JSON_FORMAT, record)) => Ok(serde_json::from_slice(record)?),
+        Some((&BINCODE_FORMAT, record)) => T::from_wire(
+            bincode::deserialize(record).map_err(|err| AggError::CodecError(err.to_string()))?,
+        ),
+        // A JSON text never starts with a format version byte
+        _ => Ok(serde_json::from_slice(bytes)?),
+    }
+}
@@ -0,0 +1,58 @@
+use crate::envelope::ResponseEnvelope;
+use crate::error::{AggError, ErrorContextExt};
+use crate::util::BlockDigest;
+
+/// This function compares the block digests of two aggregator instances
+///
+/// # Arguments
+///
+/// * `left` - A string slice that holds the base url of the first instance
+/// * `right` - A string slice that holds the base url of the second instance
+/// * `start` - A u64 that holds the first block number
+/// * `end` - A u64 that holds the last block number
+///
+/// # Returns
+///
+/// * `Result<bool, AggError>` - A Result that holds whether both instances indexed identical data
+pub async fn run(left: &str, right: &str, start: u64, end: u64) -> Result<bool, AggError> {
+    let client = reqwest::Client::new();
+    let mut identical = true;
+    for block_no in start..=end {
+        let left_digest = fetch_digest(&client, left, block_no).await?;
+        let right_digest = fetch_digest(&client, right, block_no).await?;
+        if left_digest != right_digest {
+            identical = false;
+            println!(
+                "Block {} differs: left {} right {}",
+                block_no,
+                left_digest.as_deref().unwrap_or("missing"),
+                right_digest.as_deref().unwrap_or("missing")
+            );
+        }
+    }
+    Ok(identical)
+}
+
+async fn fetch_digest(
+    client: &reqwest::Client,
+    base_url: &str,
+    block_no: u64,
+) -> Result<Option<String>, AggError> {
+    let url = format!(
+        "{}/block_digest/{}",
+        base_url.trim_end_matches('/'),
+        block_no
+    );
+    let response = client.get(&url).send().await.with_endpoint(&url)?;
+    if !response.status().is_success() {
+        return Ok(None);
+    }
+    Ok(Some(
+        response
+            .json::<ResponseEnvelope<BlockDigest>>()
+            .await
+            .with_endpoint(&url)?
+            .data
+            .digest,
+    ))
+}
@@ -0,0 +1,1374 @@
+use crate::cli::Cli;
+use crate::codec::Codec;
+use crate::endpoints;
+use crate::error::AggError;
+use serde::{Deserialize, Serialize};
+use solana_sdk::commitment_config::CommitmentConfig;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Default, Debug, Clone, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub node: NodeConfig,
+    #[serde(default)]
+    pub admin_api_key: Option<String>,
+    #[serde(default)]
+    pub tenants: Vec<TenantConfig>,
+    #[serde(default)]
+    pub aggregation_rules: Vec<AggregationRule>,
+    #[serde(default)]
+    pub program_metrics: Vec<ProgramMetricConfig>,
+    #[serde(default)]
+    pub durability: DurabilityConfig,
+    #[serde(default)]
+    pub compaction: CompactionConfig,
+    #[serde(default)]
+    pub recovery: RecoveryConfig,
+    #[serde(default)]
+    pub query: QueryConfig,
+    #[serde(default)]
+    pub archive: ArchiveConfig,
+    #[serde(default)]
+    pub parser: ParserConfig,
+    #[serde(default)]
+    pub subscriber: SubscriberConfig,
+    #[serde(default)]
+    pub subscriptions: SubscriptionConfig,
+    #[serde(default)]
+    pub public: PublicConfig,
+    #[serde(default)]
+    pub export: ExportConfig,
+    #[serde(default)]
+    pub backfill: BackfillConfig,
+    #[serde(default)]
+    pub fan_out: FanOutConfig,
+    #[serde(default)]
+    pub plugins: PluginConfig,
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+    #[serde(default)]
+    pub startup: StartupConfig,
+    #[serde(default)]
+    pub reorg: ReorgConfig,
+    #[serde(default)]
+    pub autotune: AutotuneConfig,
+    #[serde(default)]
+    pub fetch_cache: FetchCacheConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub spill: SpillConfig,
+    #[serde(default)]
+    pub alerting: AlertingConfig,
+    #[serde(default)]
+    pub warmup: WarmupConfig,
+    #[serde(default)]
+    pub breaker: BreakerConfig,
+}
+
+/// Where the aggregator ingests from, stores to and serves on, and how it fetches blocks. Set in
+/// the `[node]` section, overridden by the `AGG_*` environment variables, themselves overridden
+/// by the command line flags.
+#[derive(Debug, Clone, Deserialize)]
+pub struct NodeConfig {
+    /// RPC url of the node blocks are fetched from, or the urls of several nodes separated by
+    /// commas, which requests rotate between and fail over to
+    #[serde(default = "default_chain_url")]
+    pub chain_url: String,
+    #[serde(default = "default_db_path")]
+    pub db_path: String,
+    /// Port of the HTTP server
+    #[serde(default = "default_port")]
+    pub port: u16,
+    /// Commitment of the slots followed and the blocks fetched by the subscriber
+    #[serde(default)]
+    pub commitment: Commitment,
+    /// Transactions of a block parsed by one task
+    #[serde(default = "default_chunk_size")]
+    pub chunk_size: usize,
+    /// Slots whose blocks start being fetched at once, so catching up after a restart does not
+    /// spawn thousands of fetches
+    #[serde(default = "default_max_slots_per_round")]
+    pub max_slots_per_round: u64,
+    /// Workers of the fetch pool, bounding the blocks requested from the node at once to stay
+    /// within its rate limits
+    #[serde(default = "default_fetch_workers")]
+    pub fetch_workers: usize,
+    /// Threads of the async runtime, one per core when unset
+    #[serde(default)]
+    pub worker_threads: Option<usize>,
+    /// Chain id the metrics, logs and status are labeled with, named after the cluster of the
+    /// genesis hash of the node when unset
+    #[serde(default)]
+    pub chain_id: Option<String>,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        NodeConfig {
+            chain_url: default_chain_url(),
+            db_path: default_db_path(),
+            port: default_port(),
+            commitment: Commitment::default(),
+            chunk_size: default_chunk_size(),
+            max_slots_per_round: default_max_slots_per_round(),
+            fetch_workers: default_fetch_workers(),
+            worker_threads: None,
+            chain_id: None,
+        }
+    }
+}
+
+fn default_chain_url() -> String {
+    "https://api.devnet.solana.com".to_string()
+}
+
+fn default_db_path() -> String {
+    "db".to_string()
+}
+
+fn default_port() -> u16 {
+    9944
+}
+
+fn default_chunk_size() -> usize {
+    10
+}
+
+fn default_max_slots_per_round() -> u64 {
+    64
+}
+
+fn default_fetch_workers() -> usize {
+    16
+}
+
+impl NodeConfig {
+    /// This function returns the RPC urls of `chain_url`, in the order given
+    pub fn chain_urls(&self) -> Vec<String> {
+        endpoints::chain_urls(&self.chain_url)
+    }
+
+    fn validate(&self) -> Result<(), AggError> {
+        let invalid = |setting: &str| {
+            Err(AggError::ConfigError(format!(
+                "node.{} must be greater than 0",
+                setting
+            )))
+        };
+        if self.chain_urls().is_empty() {
+            return Err(AggError::ConfigError(
+                "node.chain_url must hold at least one url".to_string(),
+            ));
+        }
+        if self.chunk_size == 0 {
+            return invalid("chunk_size");
+        }
+        if self.max_slots_per_round == 0 {
+            return invalid("max_slots_per_round");
+        }
+        if self.fetch_workers == 0 {
+            return invalid("fetch_workers");
+        }
+        if self.worker_threads == Some(0) {
+            return invalid("worker_threads");
+        }
+        Ok(())
+    }
+}
+
+/// Commitment level of the blocks ingested. Confirmed blocks are ingested sooner but may still
+/// be rolled back, the node does not serve blocks of a lower commitment.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Commitment {
+    Confirmed,
+    #[default]
+    Finalized,
+}
+
+impl Commitment {
+    pub fn config(self) -> CommitmentConfig {
+        match self {
+            Commitment::Confirmed => CommitmentConfig::confirmed(),
+            Commitment::Finalized => CommitmentConfig::finalized(),
+        }
+    }
+}
+
+impl fmt::Display for Commitment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Commitment::Confirmed => write!(f, "confirmed"),
+            Commitment::Finalized => write!(f, "finalized"),
+        }
+    }
+}
+
+impl FromStr for Commitment {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "confirmed" => Ok(Commitment::Confirmed),
+            "finalized" => Ok(Commitment::Finalized),
+            _ => Err(format!(
+                "unknown commitment {}, expected confirmed or finalized",
+                value
+            )),
+        }
+    }
+}
+
+/// How long the stages of the pipeline have to stop once the process receives SIGINT or SIGTERM
+#[derive(Debug, Clone, Deserialize)]
+pub struct ShutdownConfig {
+    /// Time the tasks of a stage have to drain and stop before they are aborted
+    #[serde(default = "default_stage_timeout_secs")]
+    pub stage_timeout_secs: u64,
+    /// Time the chunks of the blocks still being parsed have to arrive once the handler is asked
+    /// to stop, the blocks still incomplete after it are dropped
+    #[serde(default = "default_chunk_drain_timeout_secs")]
+    pub chunk_drain_timeout_secs: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        ShutdownConfig {
+            stage_timeout_secs: default_stage_timeout_secs(),
+            chunk_drain_timeout_secs: default_chunk_drain_timeout_secs(),
+        }
+    }
+}
+
+impl ShutdownConfig {
+    pub fn stage_timeout(&self) -> Duration {
+        Duration::from_secs(self.stage_timeout_secs)
+    }
+
+    pub fn chunk_drain_timeout(&self) -> Duration {
+        Duration::from_secs(self.chunk_drain_timeout_secs)
+    }
+
+    fn validate(&self) -> Result<(), AggError> {
+        if self.chunk_drain_timeout_secs > self.stage_timeout_secs {
+            return Err(AggError::ConfigError(
+                "shutdown.chunk_drain_timeout_secs must be at most shutdown.stage_timeout_secs"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn default_stage_timeout_secs() -> u64 {
+    30
+}
+
+fn default_chunk_drain_timeout_secs() -> u64 {
+    10
+}
+
+/// How the db follows reorgs of the chain when ingesting `confirmed` blocks, which may still be
+/// replaced by the blocks of another fork. Finalized blocks never change, a block that does not
+/// chain onto its stored parent is quarantined instead.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReorgConfig {
+    /// Stored blocks a reorg may roll back, a deeper reorg quarantines the new block instead
+    #[serde(default = "default_max_reorg_depth")]
+    pub max_depth: u64,
+}
+
+impl Default for ReorgConfig {
+    fn default() -> Self {
+        ReorgConfig {
+            max_depth: default_max_reorg_depth(),
+        }
+    }
+}
+
+fn default_max_reorg_depth() -> u64 {
+    32
+}
+
+/// How the subscriber adjusts the slots fetched at once and the transactions parsed per task to
+/// the write latency of the db and the slot lag, starting from `node.max_slots_per_round` and
+/// `node.chunk_size`
+#[derive(Debug, Clone, Deserialize)]
+pub struct AutotuneConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Average time the db takes to store a block above which fetching is backed off
+    #[serde(default = "default_target_write_latency_ms")]
+    pub target_write_latency_ms: u64,
+    /// Slots the latest indexed slot may trail the slots being fetched before fetching speeds up
+    #[serde(default = "default_autotune_lag_slots")]
+    pub lag_slots: u64,
+    #[serde(default = "default_min_slots_per_round")]
+    pub min_slots_per_round: u64,
+    #[serde(default = "default_autotune_max_slots_per_round")]
+    pub max_slots_per_round: u64,
+    #[serde(default = "default_min_chunk_size")]
+    pub min_chunk_size: usize,
+    #[serde(default = "default_max_chunk_size")]
+    pub max_chunk_size: usize,
+    /// Time between adjustments, so the effect of one is observed before the next
+    #[serde(default = "default_autotune_interval_secs")]
+    pub interval_secs: u64,
+}
+
+impl Default for AutotuneConfig {
+    fn default() -> Self {
+        AutotuneConfig {
+            enabled: false,
+            target_write_latency_ms: default_target_write_latency_ms(),
+            lag_slots: default_autotune_lag_slots(),
+            min_slots_per_round: default_min_slots_per_round(),
+            max_slots_per_round: default_autotune_max_slots_per_round(),
+            min_chunk_size: default_min_chunk_size(),
+            max_chunk_size: default_max_chunk_size(),
+            interval_secs: default_autotune_interval_secs(),
+        }
+    }
+}
+
+impl AutotuneConfig {
+    pub fn target_write_latency(&self) -> Duration {
+        Duration::from_millis(self.target_write_latency_ms)
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs)
+    }
+
+    fn validate(&self) -> Result<(), AggError> {
+        if self.min_slots_per_round == 0 || self.min_slots_per_round > self.max_slots_per_round {
+            return Err(AggError::ConfigError(
+                "autotune.min_slots_per_round must be greater than 0 and at most autotune.max_slots_per_round".to_string(),
+            ));
+        }
+        if self.min_chunk_size == 0 || self.min_chunk_size > self.max_chunk_size {
+            return Err(AggError::ConfigError(
+                "autotune.min_chunk_size must be greater than 0 and at most autotune.max_chunk_size".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn default_target_write_latency_ms() -> u64 {
+    250
+}
+
+fn default_autotune_lag_slots() -> u64 {
+    32
+}
+
+fn default_min_slots_per_round() -> u64 {
+    1
+}
+
+fn default_autotune_max_slots_per_round() -> u64 {
+    256
+}
+
+fn default_min_chunk_size() -> usize {
+    5
+}
+
+fn default_max_chunk_size() -> usize {
+    200
+}
+
+fn default_autotune_interval_secs() -> u64 {
+    5
+}
+
+/// Sanity checks run before the pipeline starts, failing startup unless `--force` is passed
+#[derive(Debug, Clone, Deserialize)]
+pub struct StartupConfig {
+    /// Checks not run, out of `schema_version`, `genesis_hash`, `rpc`, `commitment` and
+    /// `disk_space`
+    #[serde(default)]
+    pub disabled: Vec<String>,
+    /// Free space the volume of the db needs at startup
+    #[serde(default = "default_min_free_disk_mb")]
+    pub min_free_disk_mb: u64,
+    /// Time the RPC node has to answer each check
+    #[serde(default = "default_rpc_timeout_secs")]
+    pub rpc_timeout_secs: u64,
+}
+
+impl Default for StartupConfig {
+    fn default() -> Self {
+        StartupConfig {
+            disabled: Vec::new(),
+            min_free_disk_mb: default_min_free_disk_mb(),
+            rpc_timeout_secs: default_rpc_timeout_secs(),
+        }
+    }
+}
+
+impl StartupConfig {
+    pub fn rpc_timeout(&self) -> Duration {
+        Duration::from_secs(self.rpc_timeout_secs)
+    }
+}
+
+fn default_min_free_disk_mb() -> u64 {
+    1024
+}
+
+fn default_rpc_timeout_secs() -> u64 {
+    10
+}
+
+/// Queues between the fan-out of finalized blocks and each of its sinks
+#[derive(Debug, Clone, Deserialize)]
+pub struct FanOutConfig {
+    /// Blocks a sink may have queued before the fan-out waits for it or drops blocks for it
+    #[serde(default = "default_fan_out_queue_capacity")]
+    pub queue_capacity: usize,
+}
+
+impl Default for FanOutConfig {
+    fn default() -> Self {
+        FanOutConfig {
+            queue_capacity: default_fan_out_queue_capacity(),
+        }
+    }
+}
+
+fn default_fan_out_queue_capacity() -> usize {
+    256
+}
+
+/// Which of the plugins registered by the embedder run on the finalized blocks
+#[derive(Debug, Clone, Deserialize)]
+pub struct PluginConfig {
+    /// Names of the plugins not to run
+    #[serde(default)]
+    pub disabled: Vec<String>,
+    /// Whether blocks a plugin rejects are kept from storage, or only logged, e.g. to try a new
+    /// validation plugin against live traffic
+    #[serde(default = "default_enforce")]
+    pub enforce: bool,
+}
+
+impl Default for PluginConfig {
+    fn default() -> Self {
+        PluginConfig {
+            disabled: Vec::new(),
+            enforce: default_enforce(),
+        }
+    }
+}
+
+fn default_enforce() -> bool {
+    true
+}
+
+/// Where finalized blocks fetched from the node are kept on disk, so overlapping backfills and
+/// retries after parser failures do not download them again
+#[derive(Debug, Clone, Deserialize)]
+pub struct FetchCacheConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_fetch_cache_dir")]
+    pub dir: PathBuf,
+    /// Size of the cached blocks above which the least recently used ones are evicted
+    #[serde(default = "default_fetch_cache_max_size_mb")]
+    pub max_size_mb: u64,
+}
+
+impl Default for FetchCacheConfig {
+    fn default() -> Self {
+        FetchCacheConfig {
+            enabled: false,
+            dir: default_fetch_cache_dir(),
+            max_size_mb: default_fetch_cache_max_size_mb(),
+        }
+    }
+}
+
+impl FetchCacheConfig {
+    pub fn max_size_bytes(&self) -> u64 {
+        self.max_size_mb.saturating_mul(1024 * 1024)
+    }
+
+    fn validate(&self) -> Result<(), AggError> {
+        if self.enabled && self.max_size_mb == 0 {
+            return Err(AggError::ConfigError(
+                "fetch_cache.max_size_mb must be greater than 0".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn default_fetch_cache_dir() -> PathBuf {
+    PathBuf::from("fetch_cache")
+}
+
+fn default_fetch_cache_max_size_mb() -> u64 {
+    1024
+}
+
+/// Where the chunks of transactions waiting to be parsed are written once the chunks in memory
+/// take their budget
+#[derive(Debug, Clone, Deserialize)]
+pub struct SpillConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_spill_dir")]
+    pub dir: PathBuf,
+    /// Estimated size of the chunks in memory above which further chunks are spilled
+    #[serde(default = "default_spill_max_in_memory_mb")]
+    pub max_in_memory_mb: u64,
+}
+
+impl Default for SpillConfig {
+    fn default() -> Self {
+        SpillConfig {
+            enabled: false,
+            dir: default_spill_dir(),
+            max_in_memory_mb: default_spill_max_in_memory_mb(),
+        }
+    }
+}
+
+impl SpillConfig {
+    pub fn max_in_memory_bytes(&self) -> u64 {
+        self.max_in_memory_mb.saturating_mul(1024 * 1024)
+    }
+
+    fn validate(&self) -> Result<(), AggError> {
+        if self.enabled && self.max_in_memory_mb == 0 {
+            return Err(AggError::ConfigError(
+                "spill.max_in_memory_mb must be greater than 0".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn default_spill_dir() -> PathBuf {
+    PathBuf::from("spill")
+}
+
+fn default_spill_max_in_memory_mb() -> u64 {
+    512
+}
+
+/// Operational alerts sent straight to the configured sinks, so a small deployment is paged
+/// without running Prometheus and Alertmanager. Nothing is checked without a sink.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AlertingConfig {
+    #[serde(default)]
+    pub sinks: Vec<AlertSinkConfig>,
+    /// Interval between two evaluations of the alerts
+    #[serde(default = "default_alert_check_interval_secs")]
+    pub check_interval_secs: u64,
+    /// Time without a new indexed block after which ingestion is reported stalled
+    #[serde(default = "default_alert_stall_secs")]
+    pub stall_secs: u64,
+    /// Slots the latest indexed slot may trail the finalized slot before the lag is reported
+    #[serde(default = "default_alert_max_lag_slots")]
+    pub max_lag_slots: u64,
+    /// Dead letters added within one check interval that are reported as growing
+    #[serde(default = "default_alert_dead_letter_growth")]
+    pub dead_letter_growth: u64,
+}
+
+impl Default for AlertingConfig {
+    fn default() -> Self {
+        AlertingConfig {
+            sinks: vec![],
+            check_interval_secs: default_alert_check_interval_secs(),
+            stall_secs: default_alert_stall_secs(),
+            max_lag_slots: default_alert_max_lag_slots(),
+            dead_letter_growth: default_alert_dead_letter_growth(),
+        }
+    }
+}
+
+impl AlertingConfig {
+    pub fn check_interval(&self) -> Duration {
+        Duration::from_secs(self.check_interval_secs)
+    }
+
+    pub fn stall_after(&self) -> Duration {
+        Duration::from_secs(self.stall_secs)
+    }
+
+    fn validate(&self) -> Result<(), AggError> {
+        let invalid = |setting: &str| {
+            Err(AggError::ConfigError(format!(
+                "alerting.{} must be greater than 0",
+                setting
+            )))
+        };
+        if self.check_interval_secs == 0 {
+            return invalid("check_interval_secs");
+        }
+        if self.stall_secs == 0 {
+            return invalid("stall_secs");
+        }
+        if self.dead_letter_growth == 0 {
+            return invalid("dead_letter_growth");
+        }
+        Ok(())
+    }
+}
+
+fn default_alert_check_interval_secs() -> u64 {
+    30
+}
+
+fn default_alert_stall_secs() -> u64 {
+    300
+}
+
+fn default_alert_max_lag_slots() -> u64 {
+    1_000
+}
+
+fn default_alert_dead_letter_growth() -> u64 {
+    10
+}
+
+/// Where the alerts are sent
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AlertSinkConfig {
+    /// Slack incoming webhook, posted a message per alert
+    Slack { url: String },
+    /// Any HTTP endpoint, posted every alert as JSON
+    Webhook { url: String },
+    /// PagerDuty Events API v2, triggering and resolving an incident per alert
+    #[serde(rename = "pagerduty")]
+    PagerDuty { routing_key: String },
+}
+
+/// What the db reads into the caches on startup before `/ready` reports the instance ready, so
+/// the first queries after a restart are not served from a cold disk
+#[derive(Debug, Clone, Deserialize)]
+pub struct WarmupConfig {
+    /// Warms the caches up on startup, the instance is ready as soon as the db is opened without
+    #[serde(default)]
+    pub enabled: bool,
+    /// Latest blocks read
+    #[serde(default = "default_warmup_blocks")]
+    pub blocks: u64,
+    /// Accounts involved in the most transactions of those blocks whose summary is read
+    #[serde(default = "default_warmup_accounts")]
+    pub accounts: usize,
+}
+
+impl Default for WarmupConfig {
+    fn default() -> Self {
+        WarmupConfig {
+            enabled: false,
+            blocks: default_warmup_blocks(),
+            accounts: default_warmup_accounts(),
+        }
+    }
+}
+
+impl WarmupConfig {
+    fn validate(&self) -> Result<(), AggError> {
+        if self.enabled && self.blocks == 0 {
+            return Err(AggError::ConfigError(
+                "warmup.blocks must be greater than 0".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn default_warmup_blocks() -> u64 {
+    1_000
+}
+
+fn default_warmup_accounts() -> usize {
+    1_000
+}
+
+/// When the calls made to the node while answering requests, verifying a transaction or
+/// refetching a block, stop reaching the node after it kept failing
+#[derive(Debug, Clone, Deserialize)]
+pub struct BreakerConfig {
+    /// Calls failing in a row, with a transient error or by timing out, that open the breaker
+    #[serde(default = "default_breaker_failure_threshold")]
+    pub failure_threshold: u32,
+    /// Time calls fail at once while the breaker is open, before a single call probes the node
+    #[serde(default = "default_breaker_open_ms")]
+    pub open_ms: u64,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        BreakerConfig {
+            failure_threshold: default_breaker_failure_threshold(),
+            open_ms: default_breaker_open_ms(),
+        }
+    }
+}
+
+impl BreakerConfig {
+    fn validate(&self) -> Result<(), AggError> {
+        if self.failure_threshold == 0 {
+            return Err(AggError::ConfigError(
+                "breaker.failure_threshold must be greater than 0".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn default_breaker_failure_threshold() -> u32 {
+    5
+}
+
+fn default_breaker_open_ms() -> u64 {
+    30_000
+}
+
+/// Where slot range exports are written and how many workers share a range by default
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExportConfig {
+    #[serde(default = "default_export_dir")]
+    pub dir: PathBuf,
+    #[serde(default = "default_export_shards")]
+    pub shards: u64,
+}
+
+impl Default for ExportConfig {
+    fn default() -> Self {
+        ExportConfig {
+            dir: default_export_dir(),
+            shards: default_export_shards(),
+        }
+    }
+}
+
+fn default_export_dir() -> PathBuf {
+    PathBuf::from("exports")
+}
+
+fn default_export_shards() -> u64 {
+    4
+}
+
+/// How many past slots a backfill fetches at once
+#[derive(Debug, Clone, Deserialize)]
+pub struct BackfillConfig {
+    /// Slots of a batch, fetched concurrently and checkpointed once the batch is stored
+    #[serde(default = "default_backfill_batch_size")]
+    pub batch_size: u64,
+    /// Blocks of a batch fetched at the same time
+    #[serde(default = "default_backfill_concurrency")]
+    pub concurrency: usize,
+}
+
+impl Default for BackfillConfig {
+    fn default() -> Self {
+        BackfillConfig {
+            batch_size: default_backfill_batch_size(),
+            concurrency: default_backfill_concurrency(),
+        }
+    }
+}
+
+fn default_backfill_batch_size() -> u64 {
+    32
+}
+
+fn default_backfill_concurrency() -> usize {
+    8
+}
+
+/// Open access for community developers, every client spending a per minute budget on the cost
+/// of the endpoints it calls
+#[derive(Debug, Clone, Deserialize)]
+pub struct PublicConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_budget_per_minute")]
+    pub budget_per_minute: u64,
+    /// Cost of an endpoint without a weight of its own
+    #[serde(default = "default_endpoint_cost")]
+    pub default_cost: u64,
+    /// Cost of the endpoints by the first segment of their path, overriding the built in weights
+    #[serde(default)]
+    pub costs: BTreeMap<String, u64>,
+}
+
+impl Default for PublicConfig {
+    fn default() -> Self {
+        PublicConfig {
+            enabled: false,
+            budget_per_minute: default_budget_per_minute(),
+            default_cost: default_endpoint_cost(),
+            costs: BTreeMap::new(),
+        }
+    }
+}
+
+fn default_budget_per_minute() -> u64 {
+    600
+}
+
+fn default_endpoint_cost() -> u64 {
+    1
+}
+
+/// Limit on the requests of every client address, whatever api key it presents, so the server can
+/// be exposed without a gateway in front of it
+#[derive(Debug, Clone, Deserialize)]
+pub struct RateLimitConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_requests_per_ip_per_minute")]
+    pub requests_per_minute: u64,
+    /// Addresses not limited, such as the load balancer health checks
+    #[serde(default)]
+    pub exempt_ips: Vec<IpAddr>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            enabled: false,
+            requests_per_minute: default_requests_per_ip_per_minute(),
+            exempt_ips: vec![],
+        }
+    }
+}
+
+impl RateLimitConfig {
+    fn validate(&self) -> Result<(), AggError> {
+        if self.enabled && self.requests_per_minute == 0 {
+            return Err(AggError::ConfigError(
+                "rate_limit.requests_per_minute must be greater than 0".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn default_requests_per_ip_per_minute() -> u64 {
+    1_200
+}
+
+/// Expiry of the persisted webhook subscriptions and account stream resume cursors
+#[derive(Debug, Clone, Deserialize)]
+pub struct SubscriptionConfig {
+    /// Lifetime of a webhook created without its own ttl, kept until deleted when unset
+    #[serde(default)]
+    pub webhook_ttl_secs: Option<u64>,
+    /// Time a resume cursor is kept after the last event sent on its stream
+    #[serde(default = "default_resume_cursor_ttl_secs")]
+    pub resume_cursor_ttl_secs: u64,
+}
+
+impl Default for SubscriptionConfig {
+    fn default() -> Self {
+        SubscriptionConfig {
+            webhook_ttl_secs: None,
+            resume_cursor_ttl_secs: default_resume_cursor_ttl_secs(),
+        }
+    }
+}
+
+fn default_resume_cursor_ttl_secs() -> u64 {
+    86_400
+}
+
+/// What is kept besides the parsed blocks
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ArchiveConfig {
+    /// Store every block as fetched from the node, so it can be re-parsed after parser upgrades
+    #[serde(default)]
+    pub raw_blocks: bool,
+}
+
+/// How the subscriber learns about new finalized slots
+#[derive(Debug, Clone, Deserialize)]
+pub struct SubscriberConfig {
+    #[serde(default)]
+    pub mode: SlotSource,
+    /// PubSub endpoint of the node, derived from the chain url when unset
+    #[serde(default)]
+    pub ws_url: Option<String>,
+    /// Time without a slot notification, or to connect, after which the websocket is given up
+    /// on and the subscriber polls
+    #[serde(default = "default_ws_timeout_secs")]
+    pub ws_timeout_secs: u64,
+    #[serde(default)]
+    pub refetch: RefetchConfig,
+}
+
+impl Default for SubscriberConfig {
+    fn default() -> Self {
+        SubscriberConfig {
+            mode: SlotSource::default(),
+            ws_url: None,
+            ws_timeout_secs: default_ws_timeout_secs(),
+            refetch: RefetchConfig::default(),
+        }
+    }
+}
+
+fn default_ws_timeout_secs() -> u64 {
+    30
+}
+
+/// How slots whose block failed to be fetched are fetched again
+#[derive(Debug, Clone, Deserialize)]
+pub struct RefetchConfig {
+    /// Wait before the first re-fetch, doubled after every re-fetch that fails
+    #[serde(default = "default_refetch_initial_delay_secs")]
+    pub initial_delay_secs: u64,
+    #[serde(default = "default_refetch_max_delay_secs")]
+    pub max_delay_secs: u64,
+    /// Re-fetches of a slot before it is given up on and dead-lettered
+    #[serde(default = "default_max_refetches")]
+    pub max_refetches: u32,
+}
+
+impl Default for RefetchConfig {
+    fn default() -> Self {
+        RefetchConfig {
+            initial_delay_secs: default_refetch_initial_delay_secs(),
+            max_delay_secs: default_refetch_max_delay_secs(),
+            max_refetches: default_max_refetches(),
+        }
+    }
+}
+
+fn default_refetch_initial_delay_secs() -> u64 {
+    5
+}
+
+fn default_refetch_max_delay_secs() -> u64 {
+    600
+}
+
+fn default_max_refetches() -> u32 {
+    10
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SlotSource {
+    /// Polls the finalized slot over RPC
+    #[default]
+    Poll,
+    /// Follows `slotSubscribe` notifications, polling while the websocket is unavailable
+    Websocket,
+}
+
+impl SubscriberConfig {
+    /// This function returns the PubSub endpoint to follow slots on
+    ///
+    /// # Arguments
+    ///
+    /// * `chain_url` - A string slice that holds the RPC url of the node, or several separated by
+    ///   commas
+    ///
+    /// # Returns
+    ///
+    /// * `Option<String>` - The websocket url, None in poll mode. Derived from the first RPC url
+    ///   with the `ws` scheme and, for the default RPC port 8899, the default PubSub port 8900
+    pub fn ws_url(&self, chain_url: &str) -> Option<String> {
+        if self.mode == SlotSource::Poll {
+            return None;
+        }
+        if let Some(ws_url) = &self.ws_url {
+            return Some(ws_url.clone());
+        }
+        let chain_url = endpoints::chain_urls(chain_url).into_iter().next()?;
+        let ws_url = if let Some(rest) = chain_url.strip_prefix("https://") {
+            format!("wss://{}", rest)
+        } else if let Some(rest) = chain_url.strip_prefix("http://") {
+            format!("ws://{}", rest)
+        } else {
+            chain_url.to_string()
+        };
+        Some(match ws_url.split_once(":8899") {
+            Some((host, path)) if path.is_empty() || path.starts_with('/') => {
+                format!("{}:8900{}", host, path)
+            }
+            _ => ws_url,
+        })
+    }
+
+    pub fn ws_timeout(&self) -> Duration {
+        Duration::from_secs(self.ws_timeout_secs)
+    }
+
+    fn validate(&self) -> Result<(), AggError> {
+        if self.ws_timeout_secs == 0 {
+            return Err(AggError::ConfigError(
+                "subscriber.ws_timeout_secs must be greater than 0".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// How the parser handles transactions it fails to decode
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ParserConfig {
+    #[serde(default)]
+    pub mode: ParseMode,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParseMode {
+    /// A transaction failing to decode is stored as a partial record with its `parse_error`
+    #[default]
+    Permissive,
+    /// A transaction failing to decode is quarantined in its block with its raw encoding
+    Strict,
+}
+
+/// Limits applied by the server before a query reaches the db
+#[derive(Debug, Clone, Deserialize)]
+pub struct QueryConfig {
+    /// Maximum number of blocks a single block range request may span
+    #[serde(default = "default_max_block_range_span")]
+    pub max_block_range_span: u64,
+    /// Time a request waits for the db, queries still queued after it are dropped by the db
+    #[serde(default = "default_request_timeout_ms")]
+    pub request_timeout_ms: u64,
+    /// Maximum number of blocks, slots or per block index entries a single query may read
+    #[serde(default = "default_max_blocks_scanned")]
+    pub max_blocks_scanned: u64,
+    /// Maximum size of the data a single query may return
+    #[serde(default = "default_max_response_bytes")]
+    pub max_response_bytes: u64,
+}
+
+impl Default for QueryConfig {
+    fn default() -> Self {
+        QueryConfig {
+            max_block_range_span: default_max_block_range_span(),
+            request_timeout_ms: default_request_timeout_ms(),
+            max_blocks_scanned: default_max_blocks_scanned(),
+            max_response_bytes: default_max_response_bytes(),
+        }
+    }
+}
+
+impl QueryConfig {
+    pub fn request_timeout(&self) -> Duration {
+        Duration::from_millis(self.request_timeout_ms)
+    }
+}
+
+fn default_max_block_range_span() -> u64 {
+    100
+}
+
+fn default_request_timeout_ms() -> u64 {
+    10_000
+}
+
+fn default_max_blocks_scanned() -> u64 {
+    100_000
+}
+
+fn default_max_response_bytes() -> u64 {
+    64 * 1024 * 1024
+}
+
+#[derive(Default, Debug, Clone, Deserialize)]
+pub struct RecoveryConfig {
+    /// Directory holding one db checkpoint per sub directory, restored from when the db is
+    /// corrupted beyond repair
+    #[serde(default)]
+    pub checkpoint_dir: Option<PathBuf>,
+}
+
+/// Schedule of the background compaction of cold blocks
+#[derive(Debug, Clone, Deserialize)]
+pub struct CompactionConfig {
+    /// Interval between compaction runs, disabled when unset
+    #[serde(default)]
+    pub interval_minutes: Option<u64>,
+    /// UTC hours during which scheduled runs are allowed, any hour when empty
+    #[serde(default)]
+    pub hours_utc: Vec<u8>,
+    /// Number of blocks behind the latest block after which a block is considered cold
+    #[serde(default = "default_cold_block_lag")]
+    pub cold_block_lag: u64,
+}
+
+impl Default for CompactionConfig {
+    fn default() -> Self {
+        CompactionConfig {
+            interval_minutes: None,
+            hours_utc: Vec::new(),
+            cold_block_lag: default_cold_block_lag(),
+        }
+    }
+}
+
+impl CompactionConfig {
+    fn validate(&self) -> Result<(), AggError> {
+        if self.interval_minutes == Some(0) {
+            return Err(AggError::ConfigError(
+                "compaction.interval_minutes must be greater than 0".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn default_cold_block_lag() -> u64 {
+    10_000
+}
+
+/// How the db encodes what it stores
+#[derive(Default, Debug, Clone, Deserialize)]
+pub struct StorageConfig {
+    /// Codec of the blocks and raw blocks written from now on, the stored ones are read whatever
+    /// their codec
+    #[serde(default)]
+    pub codec: Codec,
+}
+
+/// Trade-off between ingest throughput and durability of the db writes
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct DurabilityConfig {
+    /// Fsyncs the write ahead log on every write
+    #[serde(default)]
+    pub sync_writes: bool,
+    /// Skips the write ahead log, unflushed writes are lost on a crash
+    #[serde(default)]
+    pub disable_wal: bool,
+    /// Interval at which the write ahead log is flushed and synced to disk
+    #[serde(default)]
+    pub wal_flush_interval_ms: Option<u64>,
+}
+
+impl DurabilityConfig {
+    fn validate(&self) -> Result<(), AggError> {
+        if self.wal_flush_interval_ms == Some(0) {
+            return Err(AggError::ConfigError(
+                "durability.wal_flush_interval_ms must be greater than 0".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TenantConfig {
+    pub name: String,
+    pub api_keys: Vec<String>,
+    #[serde(default)]
+    pub requests_per_minute: Option<u64>,
+}
+
+/// An operator defined aggregation evaluated on every finalized block
+#[derive(Debug, Clone, Deserialize)]
+pub struct AggregationRule {
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: RuleKind,
+    #[serde(default)]
+    pub bucket: RuleBucket,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RuleKind {
+    /// Sums the SOL transferred to any of the addresses
+    SumTransfersTo { addresses: Vec<String> },
+    /// Counts the top level instructions invoking the program
+    CountProgramCalls { program: String },
+}
+
+/// An operator defined counter evaluated on the instructions of every parsed transaction and
+/// exported as `agg_program_metric_total{metric="<name>"}`
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProgramMetricConfig {
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: ProgramMetricKind,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ProgramMetricKind {
+    /// Counts the instructions invoking the program whose data starts with `data_prefix`, e.g.
+    /// the discriminator of a swap instruction
+    CountInstructions {
+        program: String,
+        #[serde(default)]
+        data_prefix: Vec<u8>,
+    },
+    /// Sums the lamports transferred to the address by system program transfers
+    SumTransfersTo { address: String },
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleBucket {
+    Day,
+    Epoch,
+    #[default]
+    Total,
+}
+
+impl Config {
+    /// This function loads the config from a TOML file
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - A Path that holds the config file path
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, AggError>` - A Result that holds the config or an error
+    pub fn load(path: &Path) -> Result<Self, AggError> {
+        let raw = std::fs::read_to_string(path)
+            .map_err(|err| AggError::ConfigError(format!("{}: {}", path.display(), err)))?;
+        toml::from_str(&raw).map_err(|err| AggError::ConfigError(err.to_string()))
+    }
+
+    /// This function resolves the config of a run from the config file, the environment and the
+    /// command line, each overriding the one before
+    ///
+    /// # Arguments
+    ///
+    /// * `cli` - A Cli that holds the command line
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, AggError>` - A Result that holds the config or an error
+    pub fn resolve(cli: &Cli) -> Result<Self, AggError> {
+        let mut config = cli
+            .config
+            .as_deref()
+            .map(Config::load)
+            .transpose()?
+            .unwrap_or_default();
+        config.apply_env(|name| std::env::var(name).ok())?;
+        config.apply_cli(cli);
+        config.node.validate()?;
+        config.autotune.validate()?;
+        config.fetch_cache.validate()?;
+        config.spill.validate()?;
+        config.rate_limit.validate()?;
+        config.alerting.validate()?;
+        config.warmup.validate()?;
+        config.breaker.validate()?;
+        config.shutdown.validate()?;
+        config.subscriber.validate()?;
+        config.durability.validate()?;
+        config.compaction.validate()?;
+        Ok(config)
+    }
+
+    /// This function overrides the node settings with the `AGG_*` environment variables set
+    ///
+    /// # Arguments
+    ///
+    /// * `var` - A closure that returns the value of an environment variable, if set
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - An error when a variable holds an invalid value
+    pub fn apply_env<F>(&mut self, var: F) -> Result<(), AggError>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let node = &mut self.node;
+        if let Some(chain_url) = var("AGG_CHAIN_URL") {
+            node.chain_url = chain_url;
+        }
+        if let Some(db_path) = var("AGG_DB_PATH") {
+            node.db_path = db_path;
+        }
+        if let Some(port) = parse_env(&var, "AGG_PORT")? {
+            node.port = port;
+        }
+        if let Some(commitment) = parse_env(&var, "AGG_COMMITMENT")? {
+            node.commitment = commitment;
+        }
+        if let Some(chunk_size) = parse_env(&var, "AGG_CHUNK_SIZE")? {
+            node.chunk_size = chunk_size;
+        }
+        if let Some(max_slots_per_round) = parse_env(&var, "AGG_MAX_SLOTS_PER_ROUND")? {
+            node.max_slots_per_round = max_slots_per_round;
+        }
+        if let Some(fetch_workers) = parse_env(&var, "AGG_FETCH_WORKERS")? {
+            node.fetch_workers = fetch_workers;
+        }
+        if let Some(worker_threads) = parse_env(&var, "AGG_WORKER_THREADS")? {
+            node.worker_threads = Some(worker_threads);
+        }
+        if let Some(chain_id) = var("AGG_CHAIN_ID") {
+            node.chain_id = Some(chain_id);
+        }
+        Ok(())
+    }
+
+    /// This function overrides the node settings with the flags passed on the command line
+    ///
+    /// # Arguments
+    ///
+    /// * `cli` - A Cli that holds the command line
+    pub fn apply_cli(&mut self, cli: &Cli) {
+        if let Some(chain_url) = &cli.chain_url {
+            self.node.chain_url = chain_url.clone();
+        }
+        if let Some(db_path) = &cli.db_path {
+            self.node.db_path = db_path.clone();
+        }
+        if let Some(port) = cli.port_no {
+            self.node.port = port;
+        }
+        if let Some(fetch_workers) = cli.fetch_workers {
+            self.node.fetch_workers = fetch_workers;
+        }
+    }
+}
+
+/// This function parses an environment variable
+///
+/// # Arguments
+///
+/// * `var` - A closure that returns the value of an environment variable, if set
+/// * `name` - A string slice that holds the name of the variable
+///
+/// # Returns
+///
+/// * `Result<Option<T>, AggError>` - The parsed value, None when unset, or an error
+fn parse_env<T, F>(var: &F, name: &str) -> Result<Option<T>, AggError>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+    F: Fn(&str) -> Option<String>,
+{
+    var(name)
+        .map(|value| {
+            value
+                .trim()
+                .parse()
+                .map_err(|err| AggError::ConfigError(format!("{}: {}", name, err)))
+        })
+        .transpose()
+}
@@ -1,332 +1,5583 @@
-use crate::error::AggError;
-use crate::util::{Block, ProtocolMessage};
-use log::{debug, error};
+use crate::aggregation::RuleEngine;
+use crate::autotune::WriteLatency;
+use crate::chain::{self, SyncState};
+use crate::codec::{self, Codec};
+use crate::config::{
+    Commitment, CompactionConfig, DurabilityConfig, ParseMode, PluginConfig, QueryConfig,
+    ReorgConfig, SubscriptionConfig, WarmupConfig,
+};
+use crate::envelope::SlotTracker;
+use crate::error::{AggError, ErrorContextExt};
+use crate::fanout;
+use crate::metrics;
+use crate::parser::Parser;
+use crate::plugin;
+use crate::reorg::{self, ForkCheck};
+use crate::retry::WRITE_RETRY;
+use crate::shutdown::{Shutdown, Worker};
+use crate::state_applier::{ReadyBlock, StateApplier};
+use crate::timestamp::{self, TimeAnchor};
+use crate::util::{
+    AccountBalance, AccountDelta, AccountEvent, AccountSummary, AccountTransaction,
+    AccountTransactions, AccountTxEntry, ActiveAccountsDay, Annotation, AuthorityEntry,
+    AuthorityEvent, BalanceChange, BalancesAt, Block, BlockFilter, BlockSummary, ChainBreak,
+    ChainLink, ChainStatus, CompactionStats, DeadLetter, DeletedSlots, DeliveryReceipt,
+    FailureStage, FirstSeen, IndexedSlots, Job, JobState, JobTask, LayoutMigration, MintBalance,
+    PendingRefetch, ProtocolMessage, PruneProgress, RawBlock, RefetchedBlock, ReparseProgress,
+    Reply, Response, ResumeCursor, Status, Subscriptions, TimeRange, TokenAccountBalance,
+    TokenBalance, TokenHolder, TxConflict, TxCursor, TxIndexMigration, TxLocation, WebhookDelivery,
+    WebhookSubscription,
+};
+use crate::warmup::{self, Readiness, WarmupReport};
+use crate::webhook;
+use futures_util::future::BoxFuture;
+use log::{debug, error, info, warn};
+use rocksdb::{
+    ColumnFamily, Direction, IteratorMode, Options, Snapshot, WriteBatch, WriteOptions, DB,
+};
+use serde::de::DeserializeOwned;
 use serde_json::{from_slice, to_vec};
-use std::collections::{BTreeMap, BTreeSet};
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use solana_program::pubkey::Pubkey;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::{Receiver, Sender, UnboundedReceiver, UnboundedSender};
+use tokio::time::Interval;
 
-const LATEST_BLOCK_NO_KEY: &str = "lst_blk_no";
+pub(crate) const LATEST_BLOCK_NO_KEY: &str = "lst_blk_no";
+const TOKEN_BALANCE_PREFIX: &str = "TokenBalance/";
+const CUSTOM_STAT_PREFIX: &str = "CustomStat/";
+/// Marks a block whose contribution is added to the custom stats, so storing it again does not
+/// add it twice
+const CUSTOM_STAT_BLOCK_PREFIX: &str = "CustomStatBlock/";
+/// Prefix of the active accounts of each day in the default column family of an older db
+const LEGACY_ACTIVE_ACCOUNTS_PREFIX: &str = "ActiveAccounts/";
+/// Last block merged into the active accounts of its day, the blocks after it may still be
+/// rolled back by a reorg
+const ACTIVE_ACCOUNTS_SETTLED_KEY: &str = "ActiveAccountsSettled";
+const PENDING_STATE_PREFIX: &str = "PendingState";
+const REFETCH_PREFIX: &str = "Refetch/";
+const SLOT_TIME_PREFIX: &str = "SlotTime/";
+const CHAIN_LINK_PREFIX: &str = "ChainLink";
+const CHAIN_STATUS_KEY: &str = "ChainStatus";
+const QUARANTINE_PREFIX: &str = "Quarantine/";
+/// Number of quarantined blocks listed in the status
+const MAX_REPORTED_QUARANTINED: usize = 100;
+const ANNOTATION_PREFIX: &str = "Annotation/";
+/// Annotations in the order they were created, for the status to list the latest ones
+const ANNOTATION_LOG_PREFIX: &str = "AnnotationLog/";
+/// Number of annotations listed in the status
+const MAX_REPORTED_ANNOTATIONS: usize = 100;
+/// Key of the re-parse job from before re-parsing ran as a job, migrated on startup
+const LEGACY_REPARSE_JOB_KEY: &str = "ReparseJob";
+const JOB_PREFIX: &str = "Job/";
+pub(crate) const GENESIS_HASH_KEY: &str = "GenesisHash";
+/// Version of the db layout this build writes, raised whenever older builds can no longer read
+/// the db. Older layouts are migrated when the db is opened writable.
+pub const SCHEMA_VERSION: u64 = 1;
+pub(crate) const SCHEMA_VERSION_KEY: &str = "SchemaVersion";
+const WEBHOOK_PREFIX: &str = "Webhook/";
+const RESUME_CURSOR_PREFIX: &str = "ResumeCursor/";
+const WEBHOOK_DELIVERY_PREFIX: &str = "WebhookDelivery/";
+const LAST_DELIVERY_ID_KEY: &str = "LastWebhookDeliveryId";
+const DEAD_LETTER_PREFIX: &str = "DeadLetter/";
+const TX_CONFLICT_PREFIX: &str = "TxConflict/";
+/// First block the balance index covers, balances at earlier blocks are read from the cumulative
+/// account maps that blocks were stored with before the index existed
+const BALANCE_INDEX_FROM_KEY: &str = "BalanceIndexFrom";
+/// Interval at which expired webhooks and resume cursors are removed
+const SUBSCRIPTION_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+/// Interval at which a read only instance catches up with the writes of the primary
+const CATCH_UP_INTERVAL: Duration = Duration::from_secs(1);
+/// Blocks re-parsed on every tick of the re-parse job, so queries keep being served in between
+const REPARSE_BATCH_SIZE: u64 = 50;
+/// Slots a prune job deletes per tick
+const PRUNE_BATCH_SIZE: u64 = 1_000;
+/// Legacy transaction index entries a migration job moves per tick
+const TX_INDEX_MIGRATION_BATCH_SIZE: usize = 1_000;
+/// Legacy transaction index keys are JSON strings, the only keys starting with a quote
+const LEGACY_TX_KEY_PREFIX: &[u8] = b"\"";
+/// Legacy blocks and first sightings a layout migration job moves per tick
+const LAYOUT_MIGRATION_BATCH_SIZE: usize = 500;
+/// Transactions an account stream replays at a time, the db handles other messages in between
+const ACCOUNT_REPLAY_BATCH_SIZE: usize = 256;
+/// Blocks a block stream replays at a time, the db handles other messages in between
+const BLOCK_REPLAY_BATCH_SIZE: u64 = 64;
+/// Name the live blocks dropped for a full block stream are counted under
+const BLOCK_STREAM: &str = "block_stream";
+/// Name the live events dropped for a full account stream are counted under
+const ACCOUNT_STREAM: &str = "account_stream";
+/// Prefix of the blocks stored in the default column family by older dbs, followed by the
+/// decimal block number
+const LEGACY_BLOCK_PREFIX: &str = "BlockNo";
+/// Prefix of the first sightings stored in the default column family by older dbs, followed by
+/// the base58 pubkey
+const LEGACY_FIRST_SEEN_PREFIX: &str = "FirstSeen";
+const JOB_TICK: Duration = Duration::from_millis(100);
+/// Stored blocks scanned on each side of a new block for a neighbour to sanitize its time with
+const TIME_ANCHOR_SCAN: usize = 16;
+/// Length of a transaction signature, message hashes are shorter
+const SIGNATURE_BYTES: usize = 64;
+/// Owner, delegate and authority changes listed by the summary of an account
+const AUTHORITY_SUMMARY_LIMIT: usize = 20;
+/// Per block summaries keyed by the big endian block number
+const BLOCK_SUMMARY_CF: &str = "block_summary";
+/// Post balances of the accounts touched by each block keyed by `pubkey || slot_be`, so the
+/// history of an account is a single prefix iteration
+const ACCOUNTS_DELTA_CF: &str = "accounts_delta";
+/// Transactions involving each account keyed by `pubkey || slot_be || tx_id`, valued by the block
+/// number. Program ids are account keys of the transactions invoking them, so this also lists the
+/// transactions of a program
+const ACCOUNT_TXS_CF: &str = "account_txs";
+/// Blocks as fetched from the node keyed by the big endian block number, kept when raw block
+/// archiving is enabled so the blocks can be re-parsed after parser changes
+const RAW_BLOCKS_CF: &str = "raw_blocks";
+/// Post balances of the accounts touched by each applied block keyed by `pubkey || block_no_be`,
+/// the balance of an account at a block is the last entry at or before it
+const ACCOUNT_BALANCES_CF: &str = "account_balances";
+/// Location of every transaction keyed by the raw bytes of its id, see TxLocation
+const TX_INDEX_CF: &str = "tx_index";
+/// Parsed blocks keyed by the big endian block number
+const BLOCKS_CF: &str = "blocks";
+/// First sighting of every account keyed by the 32 bytes of its pubkey
+const ACCOUNTS_CF: &str = "accounts";
+/// Values describing the db as a whole, such as the latest block number, keyed by name
+const META_CF: &str = "meta";
+/// Blocks replaced by a re-fetch as they were stored, keyed by `block_no_be || replaced_at_be`
+/// with the unix time of the replacement in milliseconds
+const REPLACED_BLOCKS_CF: &str = "replaced_blocks";
+/// Owner, delegate and authority changes of each account keyed like `account_txs` by
+/// `pubkey || slot_be || tx_id`, valued by an AuthorityEntry
+const AUTHORITY_HISTORY_CF: &str = "authority_history";
+/// Latest balance of every token account holding tokens keyed by `owner || mint || account`,
+/// valued by a TokenAccountBalance, so the balances of an owner are a single prefix iteration
+const OWNER_TOKEN_BALANCES_CF: &str = "owner_token_balances";
+/// Owner and mint of every token account in `owner_token_balances` keyed by the account, valued
+/// by `owner || mint`, so a token account changing owner leaves its previous owner. A token account
+/// emptied or closed is valued by the `block_no_be` of that block instead, so a late older block
+/// does not index its balance again.
+const TOKEN_ACCOUNT_OWNERS_CF: &str = "token_account_owners";
+/// Length of the value of a token account emptied or closed in `token_account_owners`
+const EMPTIED_TOKEN_ACCOUNT_BYTES: usize = 8;
+/// Message hash of every transaction keyed by the raw bytes of each of its signatures, so a
+/// transaction is also found by signature
+const TX_SIGNATURES_CF: &str = "tx_signatures";
+/// Sketches of the fee payers and accounts active over each UTC day keyed by the day as
+/// `YYYY-MM-DD`, valued by an ActiveAccountsDay
+const ACTIVE_ACCOUNTS_CF: &str = "active_accounts";
+const COLUMN_FAMILIES: [&str; 15] = [
+    BLOCK_SUMMARY_CF,
+    ACCOUNTS_DELTA_CF,
+    ACCOUNT_TXS_CF,
+    RAW_BLOCKS_CF,
+    ACCOUNT_BALANCES_CF,
+    TX_INDEX_CF,
+    BLOCKS_CF,
+    ACCOUNTS_CF,
+    META_CF,
+    REPLACED_BLOCKS_CF,
+    AUTHORITY_HISTORY_CF,
+    OWNER_TOKEN_BALANCES_CF,
+    TOKEN_ACCOUNT_OWNERS_CF,
+    TX_SIGNATURES_CF,
+    ACTIVE_ACCOUNTS_CF,
+];
+/// Keys of the meta column family, moved there from the default column family when an older db
+/// is opened writable
+const META_KEYS: [&str; 6] = [
+    LATEST_BLOCK_NO_KEY,
+    CHAIN_STATUS_KEY,
+    GENESIS_HASH_KEY,
+    BALANCE_INDEX_FROM_KEY,
+    LAST_DELIVERY_ID_KEY,
+    ACTIVE_ACCOUNTS_SETTLED_KEY,
+];
+
+/// This function lists the transactions of a block with the raw bytes of their id and their
+/// position in the block, leaving out ids that are not base58
+///
+/// # Arguments
+///
+/// * `block` - A reference to the Block
+///
+/// # Returns
+///
+/// * `Vec<(String, Vec<u8>, u32)>` - The transaction ids, raw ids and positions
+fn raw_tx_offsets(block: &Block) -> Vec<(String, Vec<u8>, u32)> {
+    block
+        .tx_offsets()
+        .into_iter()
+        .filter_map(|(tx_id, offset)| {
+            let raw_tx_id = bs58::decode(&tx_id).into_vec().ok()?;
+            Some((tx_id, raw_tx_id, offset))
+        })
+        .collect()
+}
+
+/// This function builds the accounts delta key of an account at a slot
+///
+/// # Arguments
+///
+/// * `pubkey` - A Pubkey that holds the account
+/// * `slot` - A u64 that holds the slot
+///
+/// # Returns
+///
+/// * `Vec<u8>` - The account bytes followed by the big endian slot
+fn account_delta_key(pubkey: &Pubkey, slot: u64) -> Vec<u8> {
+    let mut key = pubkey.to_bytes().to_vec();
+    key.extend_from_slice(&slot.to_be_bytes());
+    key
+}
+
+/// This function builds the balance index key of an account at a block
+///
+/// # Arguments
+///
+/// * `pubkey` - A Pubkey that holds the account
+/// * `block_no` - A u64 that holds the block number
+///
+/// # Returns
+///
+/// * `Vec<u8>` - The account bytes followed by the big endian block number
+fn account_balance_key(pubkey: &Pubkey, block_no: u64) -> Vec<u8> {
+    let mut key = pubkey.to_bytes().to_vec();
+    key.extend_from_slice(&block_no.to_be_bytes());
+    key
+}
+
+/// This function builds the account transactions key of a transaction involving an account
+///
+/// # Arguments
+///
+/// * `pubkey` - A Pubkey that holds the account
+/// * `slot` - A u64 that holds the slot of the transaction
+/// * `tx_id` - A byte slice that holds the raw transaction id
+///
+/// # Returns
+///
+/// * `Vec<u8>` - The account bytes followed by the big endian slot and the transaction id
+fn account_tx_key(pubkey: &Pubkey, slot: u64, tx_id: &[u8]) -> Vec<u8> {
+    let mut key = account_delta_key(pubkey, slot);
+    key.extend_from_slice(tx_id);
+    key
+}
+
+/// This function builds the prefix of the token balances of an owner in a mint
+///
+/// # Arguments
+///
+/// * `owner` - A Pubkey that holds the owner
+/// * `mint` - A Pubkey that holds the mint
+///
+/// # Returns
+///
+/// * `Vec<u8>` - The owner bytes followed by the mint bytes
+fn owner_token_key(owner: &Pubkey, mint: &Pubkey) -> Vec<u8> {
+    let mut key = owner.to_bytes().to_vec();
+    key.extend_from_slice(mint.as_ref());
+    key
+}
+
+/// This function builds the key of a replaced block in the replaced blocks column family
+///
+/// # Arguments
+///
+/// * `block_no` - A u64 that holds the block number the block was stored at
+/// * `replaced_at` - A u64 that holds the unix time of the replacement in milliseconds
+///
+/// # Returns
+///
+/// * `Vec<u8>` - The big endian block number followed by the big endian time
+fn replaced_block_key(block_no: u64, replaced_at: u64) -> Vec<u8> {
+    let mut key = block_no.to_be_bytes().to_vec();
+    key.extend_from_slice(&replaced_at.to_be_bytes());
+    key
+}
+
+/// Blocks read and bytes returned by a query, checked against the query limits as it runs so a
+/// pathological query is aborted before it holds the db for long
+struct QueryBudget {
+    max_blocks_scanned: u64,
+    max_response_bytes: u64,
+    blocks_scanned: u64,
+    response_bytes: u64,
+}
+
+impl QueryBudget {
+    fn new(limits: &QueryConfig) -> Self {
+        Self {
+            max_blocks_scanned: limits.max_blocks_scanned,
+            max_response_bytes: limits.max_response_bytes,
+            blocks_scanned: 0,
+            response_bytes: 0,
+        }
+    }
+
+    /// This function accounts for a block, slot or index entry read by the query
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds an error once the limit is exceeded
+    fn scan(&mut self) -> Result<(), AggError> {
+        self.blocks_scanned += 1;
+        if self.blocks_scanned > self.max_blocks_scanned {
+            return Err(AggError::ScanLimitExceeded(self.max_blocks_scanned));
+        }
+        Ok(())
+    }
+
+    /// This function accounts for data returned by the query
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - A usize that holds the stored size of the data
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds an error once the limit is exceeded
+    fn produce(&mut self, bytes: usize) -> Result<(), AggError> {
+        self.response_bytes += bytes as u64;
+        if self.response_bytes > self.max_response_bytes {
+            return Err(AggError::ResponseTooLarge(self.max_response_bytes));
+        }
+        Ok(())
+    }
+}
+
+/// This function returns the current unix timestamp in seconds
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// This function opens the db with all of its column families, creating any that are missing.
+/// Opened read only, the db is a frozen view of the data directory, which suits one-off reads.
+///
+/// # Arguments
+///
+/// * `path` - A string slice that holds the path to the database
+/// * `read_only` - A bool that is true if the database must be opened in read only mode
+///
+/// # Returns
+///
+/// * `Result<DB, rocksdb::Error>` - A Result that holds the db or an error
+pub(crate) fn open_db(path: &str, read_only: bool) -> Result<DB, rocksdb::Error> {
+    let mut options = Options::default();
+    options.create_if_missing(true);
+    options.create_missing_column_families(true);
+    if read_only {
+        DB::open_cf_for_read_only(&options, path, COLUMN_FAMILIES, false)
+    } else {
+        DB::open_cf(&options, path, COLUMN_FAMILIES)
+    }
+}
+
+/// This function opens the db as a secondary instance of the primary writing to it. The secondary
+/// keeps its own files in a directory of its own and sees the writes of the primary each time
+/// it catches up with it.
+///
+/// # Arguments
+///
+/// * `path` - A string slice that holds the path to the database of the primary
+/// * `secondary_path` - A string slice that holds the directory of the secondary
+///
+/// # Returns
+///
+/// * `Result<DB, rocksdb::Error>` - A Result that holds the db or an error
+pub(crate) fn open_secondary_db(path: &str, secondary_path: &str) -> Result<DB, rocksdb::Error> {
+    let mut options = Options::default();
+    // The primary deletes the table files it compacts, the secondary keeps the ones it reads open
+    options.set_max_open_files(-1);
+    DB::open_cf_as_secondary(&options, path, secondary_path, COLUMN_FAMILIES)
+}
+
+/// This function reads a value of the meta column family, falling back to the default column
+/// family of dbs opened read only before their meta keys were moved
+///
+/// # Arguments
+///
+/// * `db` - A DB that holds the opened db
+/// * `key` - A string slice that holds the meta key
+///
+/// # Returns
+///
+/// * `Result<Option<Vec<u8>>, rocksdb::Error>` - A Result that holds the value or an error
+pub(crate) fn get_meta(db: &DB, key: &str) -> Result<Option<Vec<u8>>, rocksdb::Error> {
+    if let Some(cf) = db.cf_handle(META_CF) {
+        if let Some(value) = db.get_cf(cf, key)? {
+            return Ok(Some(value));
+        }
+    }
+    db.get(key)
+}
+
+/// This function reads a stored block, falling back to the default column family for blocks the
+/// layout migration has not moved yet
+///
+/// # Arguments
+///
+/// * `db` - A DB that holds the opened db
+/// * `block_no` - A u64 that holds the block number
+///
+/// # Returns
+///
+/// * `Result<Option<Vec<u8>>, AggError>` - A Result that holds the encoded block or an error
+fn get_block_bytes(db: &DB, block_no: u64) -> Result<Option<Vec<u8>>, AggError> {
+    let cf = db
+        .cf_handle(BLOCKS_CF)
+        .ok_or(AggError::MissingColumnFamily(BLOCKS_CF))?;
+    if let Some(block) = db.get_cf(cf, block_no.to_be_bytes())? {
+        return Ok(Some(block));
+    }
+    Ok(db.get(format!("{}{}", LEGACY_BLOCK_PREFIX, block_no))?)
+}
+
+/// This function builds the key a legacy entry of the default column family is moved to
+///
+/// # Arguments
+///
+/// * `prefix` - A string slice that holds the legacy key prefix
+/// * `suffix` - A byte slice that holds the rest of the legacy key
+///
+/// # Returns
+///
+/// * `Option<Vec<u8>>` - The big endian block number or the pubkey bytes, None if the legacy key
+///   does not parse
+fn migrated_key(prefix: &str, suffix: &[u8]) -> Option<Vec<u8>> {
+    let suffix = std::str::from_utf8(suffix).ok()?;
+    match prefix {
+        LEGACY_BLOCK_PREFIX => Some(suffix.parse::<u64>().ok()?.to_be_bytes().to_vec()),
+        _ => Some(Pubkey::from_str(suffix).ok()?.to_bytes().to_vec()),
+    }
+}
+
+/// Account stream replaying the transactions of its account before it is sent newly applied
+/// blocks
+struct AccountReplay {
+    pubkey: String,
+    account: Pubkey,
+    /// Key of the next transaction of the account to replay
+    from: Vec<u8>,
+    subscriber: Sender<ProtocolMessage>,
+}
+
+/// Block stream replaying the stored blocks before it is sent newly applied blocks
+struct BlockReplay {
+    filter: BlockFilter,
+    /// Number of the next block to replay
+    next_block_no: u64,
+    subscriber: Sender<ProtocolMessage>,
+}
 
 pub struct RocksDb {
     db: rocksdb::DB,
     receiver: UnboundedReceiver<ProtocolMessage>,
-    temp_db: BTreeSet<u64>,
+    /// Finalized blocks from the fan-out, bounded so a db falling behind holds the fan-out back
+    block_receiver: Option<Receiver<ProtocolMessage>>,
+    state_applier: StateApplier,
+    block_subscribers: Vec<(BlockFilter, Sender<ProtocolMessage>)>,
+    account_subscribers: Vec<(String, Sender<ProtocolMessage>)>,
+    block_replays: VecDeque<BlockReplay>,
+    account_replays: VecDeque<AccountReplay>,
+    subscriptions: SubscriptionConfig,
+    webhooks: BTreeMap<u64, WebhookSubscription>,
+    resume_cursors: BTreeMap<String, ResumeCursor>,
+    webhook_sender: Option<UnboundedSender<ProtocolMessage>>,
+    last_delivery_id: u64,
+    subscription_sweep_interval: Option<Interval>,
+    rule_engine: RuleEngine,
+    read_only: bool,
+    /// Interval at which a read only instance catches up with the primary
+    catch_up_interval: Option<Interval>,
+    durability: DurabilityConfig,
+    /// Codec of the blocks and raw blocks written
+    codec: Codec,
+    write_options: WriteOptions,
+    wal_flush_interval: Option<Interval>,
+    compaction: CompactionConfig,
+    compaction_interval: Option<Interval>,
+    compaction_stats: CompactionStats,
+    query_limits: QueryConfig,
+    slot_tracker: Arc<SlotTracker>,
+    chain_status: ChainStatus,
+    jobs: BTreeMap<u64, Job>,
+    job_interval: Option<Interval>,
+    genesis_hash: Option<String>,
+    balance_index_from: u64,
+    /// Last block merged into the active accounts of its day
+    active_accounts_settled: u64,
+    /// Commitment of the ingested blocks, reorgs are only followed for confirmed blocks
+    commitment: Commitment,
+    reorg: ReorgConfig,
+    /// Where the slots of canonical blocks found missing by a reorg are fetched again
+    refetch_sender: Option<UnboundedSender<u64>>,
+    /// How blocks fetched again through the admin api are parsed, and the plugins they go through
+    parse_mode: ParseMode,
+    plugin_config: PluginConfig,
+    /// Time blocks take to store, read by the auto-tuner of the subscriber
+    write_latency: Arc<WriteLatency>,
+    /// What is read into the caches before the instance is marked ready
+    warmup: WarmupConfig,
+    readiness: Arc<Readiness>,
+    shutdown: Option<Shutdown>,
 }
 
-impl RocksDb {
+impl RocksDb {
+
+    /// This function initializes the RocksDb client
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - A string slice that holds the path to the database
+    /// * `receiver` - A UnboundedReceiver<ProtocolMessage> that holds the receiver
+    /// * `secondary_path` - An Option<String> that holds the directory of a read only instance
+    ///   following the primary writing to the database, None to open the database as the primary
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, AggError>` - A Result that holds the RocksDb client or an error
+    pub fn initialize(
+        path: String,
+        receiver: UnboundedReceiver<ProtocolMessage>,
+        secondary_path: Option<String>,
+    ) -> Result<Self, AggError> {
+        let read_only = secondary_path.is_some();
+        let db = match &secondary_path {
+            Some(secondary_path) => open_secondary_db(&path, secondary_path)?,
+            None => open_db(&path, false)?,
+        };
+        if !read_only {
+            Self::migrate_meta(&db)?;
+            Self::migrate_active_accounts(&db)?;
+            Self::stamp_schema_version(&db)?;
+        }
+        let state_applier = StateApplier::new(Self::pending_state(&db)?);
+        let mut jobs: BTreeMap<u64, Job> = Self::load_prefixed::<Job>(&db, JOB_PREFIX)?
+            .into_iter()
+            .map(|job| (job.id, job))
+            .collect();
+        if let Some(progress) = db.get(LEGACY_REPARSE_JOB_KEY)? {
+            let progress = from_slice::<ReparseProgress>(&progress)?;
+            let id = jobs.keys().next_back().map_or(1, |id| id + 1);
+            let state = if progress.finished {
+                JobState::Finished
+            } else {
+                JobState::Running
+            };
+            let job = Self::new_job(id, JobTask::Reindex(progress), state);
+            if !read_only {
+                db.put(format!("{}{:020}", JOB_PREFIX, id), to_vec(&job)?)?;
+                db.delete(LEGACY_REPARSE_JOB_KEY)?;
+            }
+            jobs.insert(id, job);
+        }
+        if !read_only {
+            if let Some(job) = Self::tx_index_migration(&db, &jobs)? {
+                db.put(format!("{}{:020}", JOB_PREFIX, job.id), to_vec(&job)?)?;
+                jobs.insert(job.id, job);
+            }
+            if let Some(job) = Self::layout_migration(&db, &jobs)? {
+                db.put(format!("{}{:020}", JOB_PREFIX, job.id), to_vec(&job)?)?;
+                jobs.insert(job.id, job);
+            }
+        }
+        // Unfinished jobs pick up where they stopped before the restart
+        let job_interval = (!read_only && jobs.values().any(Self::is_run_by_db))
+            .then(|| tokio::time::interval(JOB_TICK));
+        let webhooks = Self::load_prefixed::<WebhookSubscription>(&db, WEBHOOK_PREFIX)?
+            .into_iter()
+            .map(|webhook| (webhook.id, webhook))
+            .collect();
+        let resume_cursors = Self::load_prefixed::<ResumeCursor>(&db, RESUME_CURSOR_PREFIX)?
+            .into_iter()
+            .map(|cursor| (cursor.token.clone(), cursor))
+            .collect();
+        let last_delivery_id = match get_meta(&db, LAST_DELIVERY_ID_KEY)? {
+            Some(id) => from_slice::<u64>(&id)?,
+            None => 0,
+        };
+        let genesis_hash = match get_meta(&db, GENESIS_HASH_KEY)? {
+            Some(genesis_hash) => Some(from_slice::<String>(&genesis_hash)?),
+            None => None,
+        };
+        let chain_status = match get_meta(&db, CHAIN_STATUS_KEY)? {
+            Some(status) => from_slice::<ChainStatus>(&status)?,
+            None => ChainStatus::default(),
+        };
+        let balance_index_from = match get_meta(&db, BALANCE_INDEX_FROM_KEY)? {
+            Some(block_no) => from_slice::<u64>(&block_no)?,
+            None => Self::seed_balance_index(&db, read_only)?,
+        };
+        let active_accounts_settled = match get_meta(&db, ACTIVE_ACCOUNTS_SETTLED_KEY)? {
+            Some(block_no) => from_slice::<u64>(&block_no)?,
+            // Blocks stored before settling was added were merged as they were stored
+            None => {
+                let latest_block_no = match get_meta(&db, LATEST_BLOCK_NO_KEY)? {
+                    Some(block_no) => from_slice::<u64>(&block_no)?,
+                    None => 0,
+                };
+                Self::last_stored_block_no(&db)?
+                    .unwrap_or_default()
+                    .max(latest_block_no)
+            }
+        };
+        Ok(Self {
+            db,
+            receiver,
+            block_receiver: None,
+            state_applier,
+            block_subscribers: Vec::new(),
+            account_subscribers: Vec::new(),
+            block_replays: VecDeque::new(),
+            account_replays: VecDeque::new(),
+            subscriptions: SubscriptionConfig::default(),
+            webhooks,
+            resume_cursors,
+            webhook_sender: None,
+            last_delivery_id,
+            subscription_sweep_interval: None,
+            rule_engine: RuleEngine::default(),
+            read_only,
+            catch_up_interval: read_only.then(|| tokio::time::interval(CATCH_UP_INTERVAL)),
+            durability: DurabilityConfig::default(),
+            codec: Codec::default(),
+            write_options: WriteOptions::default(),
+            wal_flush_interval: None,
+            compaction: CompactionConfig::default(),
+            compaction_interval: None,
+            compaction_stats: CompactionStats::default(),
+            query_limits: QueryConfig::default(),
+            slot_tracker: Arc::new(SlotTracker::default()),
+            chain_status,
+            jobs,
+            job_interval,
+            genesis_hash,
+            balance_index_from,
+            active_accounts_settled,
+            commitment: Commitment::default(),
+            reorg: ReorgConfig::default(),
+            refetch_sender: None,
+            parse_mode: ParseMode::default(),
+            plugin_config: PluginConfig::default(),
+            write_latency: Arc::new(WriteLatency::default()),
+            warmup: WarmupConfig::default(),
+            readiness: Arc::new(Readiness::default()),
+            shutdown: None,
+        })
+    }
+
+    /// This function creates the job migrating the legacy transaction index entries of the db,
+    /// unless there are none or a migration job exists already
+    ///
+    /// # Arguments
+    ///
+    /// * `db` - A DB that holds the opened db
+    /// * `jobs` - A BTreeMap<u64, Job> that holds the stored jobs
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<Job>, AggError>` - A Result that holds the new job or an error
+    fn tx_index_migration(db: &DB, jobs: &BTreeMap<u64, Job>) -> Result<Option<Job>, AggError> {
+        if jobs.values().any(|job| {
+            job.state == JobState::Running && matches!(job.task, JobTask::MigrateTxIndex(_))
+        }) {
+            return Ok(None);
+        }
+        let legacy = match db
+            .iterator(IteratorMode::From(LEGACY_TX_KEY_PREFIX, Direction::Forward))
+            .next()
+        {
+            Some(entry) => entry?.0.starts_with(LEGACY_TX_KEY_PREFIX),
+            None => false,
+        };
+        if !legacy {
+            return Ok(None);
+        }
+        // Counting the legacy entries would read the whole default column family on the start,
+        // the estimate of its keys is read from the table properties instead
+        let total = db
+            .property_int_value("rocksdb.estimate-num-keys")?
+            .unwrap_or_default();
+        info!(target: "db", "Migrating about {} transaction index entries to raw keys", total);
+        let id = jobs.keys().next_back().map_or(1, |id| id + 1);
+        let migration = TxIndexMigration {
+            total,
+            ..TxIndexMigration::default()
+        };
+        Ok(Some(Self::new_job(
+            id,
+            JobTask::MigrateTxIndex(migration),
+            JobState::Running,
+        )))
+    }
+
+    /// This function moves the meta keys of an older db from the default column family to the
+    /// meta column family
+    ///
+    /// # Arguments
+    ///
+    /// * `db` - A DB that holds the opened db
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn migrate_meta(db: &DB) -> Result<(), AggError> {
+        let cf = db
+            .cf_handle(META_CF)
+            .ok_or(AggError::MissingColumnFamily(META_CF))?;
+        let mut batch = WriteBatch::default();
+        let mut moved = 0;
+        for key in META_KEYS {
+            if let Some(value) = db.get(key)? {
+                if db.get_cf(cf, key)?.is_none() {
+                    batch.put_cf(cf, key, value);
+                }
+                batch.delete(key);
+                moved += 1;
+            }
+        }
+        if moved > 0 {
+            info!(target: "db", "Moving {} keys to the {} column family", moved, META_CF);
+            db.write_opt(batch, &WriteOptions::default())?;
+        }
+        Ok(())
+    }
+
+    /// This function moves the active accounts of every day of an older db from the default
+    /// column family to their column family, a single value per day
+    ///
+    /// # Arguments
+    ///
+    /// * `db` - A DB that holds the opened db
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn migrate_active_accounts(db: &DB) -> Result<(), AggError> {
+        let cf = db
+            .cf_handle(ACTIVE_ACCOUNTS_CF)
+            .ok_or(AggError::MissingColumnFamily(ACTIVE_ACCOUNTS_CF))?;
+        let mut batch = WriteBatch::default();
+        let mut moved = 0;
+        for entry in db.iterator(IteratorMode::From(
+            LEGACY_ACTIVE_ACCOUNTS_PREFIX.as_bytes(),
+            Direction::Forward,
+        )) {
+            let (key, value) = entry?;
+            let Some(day) = key.strip_prefix(LEGACY_ACTIVE_ACCOUNTS_PREFIX.as_bytes()) else {
+                break;
+            };
+            batch.put_cf(cf, day, value);
+            batch.delete(&key);
+            moved += 1;
+        }
+        if moved > 0 {
+            info!(target: "db", "Moving {} days to the {} column family", moved, ACTIVE_ACCOUNTS_CF);
+            db.write_opt(batch, &WriteOptions::default())?;
+        }
+        Ok(())
+    }
+
+    /// This function records the schema version of this build, unless the db was written by a
+    /// newer one, which the startup checks refuse without `--force`
+    ///
+    /// # Arguments
+    ///
+    /// * `db` - A DB that holds the opened db
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn stamp_schema_version(db: &DB) -> Result<(), AggError> {
+        let stored = match get_meta(db, SCHEMA_VERSION_KEY)? {
+            Some(version) => from_slice::<u64>(&version)?,
+            None => 0,
+        };
+        if stored < SCHEMA_VERSION {
+            let cf = db
+                .cf_handle(META_CF)
+                .ok_or(AggError::MissingColumnFamily(META_CF))?;
+            db.put_cf_opt(
+                cf,
+                SCHEMA_VERSION_KEY,
+                to_vec(&SCHEMA_VERSION)?,
+                &WriteOptions::default(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// This function creates the job moving the blocks and first sightings of an older db to
+    /// their column families, unless there are none or a migration job exists already
+    ///
+    /// # Arguments
+    ///
+    /// * `db` - A DB that holds the opened db
+    /// * `jobs` - A BTreeMap<u64, Job> that holds the stored jobs
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<Job>, AggError>` - A Result that holds the new job or an error
+    fn layout_migration(db: &DB, jobs: &BTreeMap<u64, Job>) -> Result<Option<Job>, AggError> {
+        if jobs.values().any(|job| {
+            job.state == JobState::Running && matches!(job.task, JobTask::MigrateLayout(_))
+        }) {
+            return Ok(None);
+        }
+        let mut legacy = false;
+        for prefix in [LEGACY_BLOCK_PREFIX, LEGACY_FIRST_SEEN_PREFIX] {
+            if let Some(entry) = db
+                .iterator(IteratorMode::From(prefix.as_bytes(), Direction::Forward))
+                .next()
+            {
+                let (key, _) = entry?;
+                legacy |= key.starts_with(prefix.as_bytes());
+            }
+        }
+        if !legacy {
+            return Ok(None);
+        }
+        // Counting the legacy entries would read the whole default column family on the start,
+        // the estimate of its keys is read from the table properties instead
+        let total = db
+            .property_int_value("rocksdb.estimate-num-keys")?
+            .unwrap_or_default();
+        info!(target: "db", "Migrating about {} blocks and first sightings to their column families", total);
+        let id = jobs.keys().next_back().map_or(1, |id| id + 1);
+        let migration = LayoutMigration {
+            total,
+            ..LayoutMigration::default()
+        };
+        Ok(Some(Self::new_job(
+            id,
+            JobTask::MigrateLayout(migration),
+            JobState::Running,
+        )))
+    }
+
+    /// This function returns the highest block number stored
+    ///
+    /// # Arguments
+    ///
+    /// * `db` - A reference to the DB
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<u64>, AggError>` - A Result that holds the block number, None for a db
+    ///   without blocks, or an error
+    fn last_stored_block_no(db: &DB) -> Result<Option<u64>, AggError> {
+        let cf = db
+            .cf_handle(BLOCKS_CF)
+            .ok_or(AggError::MissingColumnFamily(BLOCKS_CF))?;
+        match db.iterator_cf(cf, IteratorMode::End).next() {
+            Some(entry) => {
+                let (key, _) = entry?;
+                Ok(Some(u64::from_be_bytes(key.as_ref().try_into()?)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// This function starts the balance index of a db without one. The cumulative account map
+    /// of the latest block is indexed at the latest block, blocks before it keep answering from
+    /// their own cumulative map.
+    ///
+    /// # Arguments
+    ///
+    /// * `db` - A DB that holds the opened db
+    /// * `read_only` - A bool that is true if the database is opened in read only mode
+    ///
+    /// # Returns
+    ///
+    /// * `Result<u64, AggError>` - A Result that holds the first block the index covers or an
+    ///   error
+    fn seed_balance_index(db: &DB, read_only: bool) -> Result<u64, AggError> {
+        let latest_block = match get_meta(db, LATEST_BLOCK_NO_KEY)? {
+            Some(block_no) => Some(from_slice::<u64>(&block_no)?),
+            None => None,
+        };
+        let (from, block) = match latest_block {
+            Some(block_no) => match get_block_bytes(db, block_no)? {
+                Some(block) => (block_no, Some(codec::decode::<Block>(&block)?)),
+                None => (block_no, None),
+            },
+            None => (0, None),
+        };
+        // Nothing can be written, every balance is read from the blocks
+        if read_only {
+            return Ok(if latest_block.is_some() { u64::MAX } else { 0 });
+        }
+        let cf = db
+            .cf_handle(ACCOUNT_BALANCES_CF)
+            .ok_or(AggError::MissingColumnFamily(ACCOUNT_BALANCES_CF))?;
+        let mut batch = WriteBatch::default();
+        if let Some(account_map) = block.and_then(|block| block.get_account_map()) {
+            info!(target: "db", "Indexing the balances of {} accounts at block {}", account_map.len(), from);
+            for (account, balance) in account_map {
+                let pubkey = Pubkey::from_str(&account)?;
+                batch.put_cf(cf, account_balance_key(&pubkey, from), to_vec(&balance)?);
+            }
+        }
+        let meta = db
+            .cf_handle(META_CF)
+            .ok_or(AggError::MissingColumnFamily(META_CF))?;
+        batch.put_cf(meta, BALANCE_INDEX_FROM_KEY, to_vec(&from)?);
+        db.write_opt(batch, &WriteOptions::default())?;
+        Ok(from)
+    }
+
+    /// This function sets the aggregation rules evaluated on every finalised block
+    ///
+    /// # Arguments
+    ///
+    /// * `rule_engine` - A RuleEngine that holds the configured rules
+    pub fn set_rule_engine(&mut self, rule_engine: RuleEngine) {
+        self.rule_engine = rule_engine;
+    }
+
+    /// This function sets the queue the db receives the finalized blocks from, as a sink of the
+    /// fan-out
+    ///
+    /// # Arguments
+    ///
+    /// * `block_receiver` - A Receiver<ProtocolMessage> that receives the finalized blocks
+    pub fn set_block_receiver(&mut self, block_receiver: Receiver<ProtocolMessage>) {
+        self.block_receiver = Some(block_receiver);
+    }
+
+    /// This function checks the db holds data of the cluster the blocks are ingested from,
+    /// recording the cluster's genesis hash if the db has none yet
+    ///
+    /// # Arguments
+    ///
+    /// * `genesis_hash` - A string slice that holds the genesis hash of the chain
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error if the db was
+    ///   created for another cluster
+    pub fn verify_genesis_hash(&mut self, genesis_hash: &str) -> Result<(), AggError> {
+        match self.genesis_hash.as_deref() {
+            Some(stored) if stored == genesis_hash => Ok(()),
+            Some(stored) => Err(AggError::GenesisHashMismatch(
+                stored.to_string(),
+                genesis_hash.to_string(),
+            )),
+            None if self.read_only => Ok(()),
+            None => {
+                self.put_cf(META_CF, GENESIS_HASH_KEY, to_vec(genesis_hash)?)?;
+                info!(target: "db", "Recorded genesis hash {}", genesis_hash);
+                self.genesis_hash = Some(genesis_hash.to_string());
+                Ok(())
+            }
+        }
+    }
+
+    /// This function sets the codec of the blocks and raw blocks written from now on
+    ///
+    /// # Arguments
+    ///
+    /// * `codec` - A Codec that holds the codec
+    pub fn set_codec(&mut self, codec: Codec) {
+        self.codec = codec;
+    }
+
+    /// This function sets the durability policy of the db writes
+    ///
+    /// # Arguments
+    ///
+    /// * `durability` - A DurabilityConfig that holds the wal and fsync settings
+    pub fn set_durability(&mut self, durability: DurabilityConfig) {
+        let mut write_options = WriteOptions::default();
+        write_options.set_sync(durability.sync_writes);
+        write_options.disable_wal(durability.disable_wal);
+        self.write_options = write_options;
+        self.wal_flush_interval = durability
+            .wal_flush_interval_ms
+            .map(|interval| tokio::time::interval(Duration::from_millis(interval)));
+        self.durability = durability;
+    }
+
+    /// This function sets the schedule of the background compaction
+    ///
+    /// # Arguments
+    ///
+    /// * `compaction` - A CompactionConfig that holds the compaction schedule
+    pub fn set_compaction(&mut self, compaction: CompactionConfig) {
+        self.compaction_interval = compaction
+            .interval_minutes
+            .filter(|_| !self.read_only)
+            .map(|interval| tokio::time::interval(Duration::from_secs(interval * 60)));
+        self.compaction = compaction;
+    }
+
+    /// This function sets the limits queries are aborted at
+    ///
+    /// # Arguments
+    ///
+    /// * `query_limits` - A QueryConfig that holds the maximum blocks scanned and response bytes
+    pub fn set_query_limits(&mut self, query_limits: QueryConfig) {
+        self.query_limits = query_limits;
+    }
+
+    /// This function sets the commitment of the ingested blocks and how reorgs are followed
+    ///
+    /// # Arguments
+    ///
+    /// * `commitment` - A Commitment that holds the commitment of the ingested blocks
+    /// * `reorg` - A ReorgConfig that holds the depth a reorg may roll back
+    /// * `refetch_sender` - An Option<UnboundedSender<u64>> that holds where the slots of missing
+    ///   canonical blocks are sent, None on instances not ingesting from a node
+    pub fn set_reorg_handling(
+        &mut self,
+        commitment: Commitment,
+        reorg: ReorgConfig,
+        refetch_sender: Option<UnboundedSender<u64>>,
+    ) {
+        self.commitment = commitment;
+        self.reorg = reorg;
+        self.refetch_sender = refetch_sender;
+    }
+
+    /// This function sets how blocks fetched again through the admin api are parsed and which
+    /// plugins they go through, as the pipeline does for ingested blocks
+    ///
+    /// # Arguments
+    ///
+    /// * `parse_mode` - A ParseMode that holds what to do with transactions failing to parse
+    /// * `plugin_config` - A PluginConfig that holds the disabled plugins
+    pub fn set_parsing(&mut self, parse_mode: ParseMode, plugin_config: PluginConfig) {
+        self.parse_mode = parse_mode;
+        self.plugin_config = plugin_config;
+    }
+
+    /// This function sets the tracker responses read the latest indexed slot from
+    ///
+    /// # Arguments
+    ///
+    /// * `slot_tracker` - An Arc<SlotTracker> shared with the server
+    pub fn set_slot_tracker(&mut self, slot_tracker: Arc<SlotTracker>) {
+        if let Some(slot) = self
+            .get_latest_block()
+            .and_then(|block_no| self.get_block(block_no))
+            .and_then(|block| block.slot())
+        {
+            slot_tracker.set_latest_indexed(slot);
+        }
+        self.slot_tracker = slot_tracker;
+    }
+
+    /// This function sets where the time blocks take to store is recorded
+    ///
+    /// # Arguments
+    ///
+    /// * `write_latency` - An Arc<WriteLatency> shared with the subscriber
+    pub fn set_write_latency(&mut self, write_latency: Arc<WriteLatency>) {
+        self.write_latency = write_latency;
+    }
+
+    /// This function sets what is read into the caches on startup and where the instance is
+    /// marked ready once it was
+    ///
+    /// # Arguments
+    ///
+    /// * `warmup` - A WarmupConfig that holds the blocks and accounts read
+    /// * `readiness` - An Arc<Readiness> shared with the server
+    pub fn set_warmup(&mut self, warmup: WarmupConfig, readiness: Arc<Readiness>) {
+        self.warmup = warmup;
+        self.readiness = readiness;
+    }
+
+    /// This function sets the expiry of the subscriptions and where webhook events are delivered
+    ///
+    /// # Arguments
+    ///
+    /// * `subscriptions` - A SubscriptionConfig that holds the expiry policies
+    /// * `webhook_sender` - A UnboundedSender<ProtocolMessage> that holds the webhook dispatcher
+    pub fn set_subscriptions(
+        &mut self,
+        subscriptions: SubscriptionConfig,
+        webhook_sender: UnboundedSender<ProtocolMessage>,
+    ) {
+        self.subscription_sweep_interval =
+            (!self.read_only).then(|| tokio::time::interval(SUBSCRIPTION_SWEEP_INTERVAL));
+        self.subscriptions = subscriptions;
+        self.webhook_sender = Some(webhook_sender);
+    }
+
+    /// This function waits for the next message, running the wal flush and the scheduled
+    /// compaction whenever their interval elapses in between. Once the db is asked to stop it
+    /// only takes the messages already queued, the finalized blocks first.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<ProtocolMessage>` - The next message or None if the channel is closed or the
+    ///   db is drained
+    async fn next_message(&mut self) -> Option<ProtocolMessage> {
+        loop {
+            if Shutdown::is_set(&self.shutdown) {
+                if let Some(Ok(message)) = self.block_receiver.as_mut().map(Receiver::try_recv) {
+                    return Some(message);
+                }
+                return self.receiver.try_recv().ok();
+            }
+            tokio::select! {
+                message = self.receiver.recv() => return message,
+                message = Self::recv_block(&mut self.block_receiver) => match message {
+                    Some(message) => return Some(message),
+                    None => self.block_receiver = None,
+                },
+                _ = Self::tick(&mut self.wal_flush_interval) => {
+                    if let Err(err) = self.db.flush_wal(true) {
+                        error!(target: "db", "Error flushing wal {}", err);
+                    }
+                }
+                _ = Self::tick(&mut self.compaction_interval) => self.run_scheduled_compaction(),
+                _ = Self::tick(&mut self.job_interval) => self.run_job_batches(),
+                _ = Self::tick(&mut self.catch_up_interval) => {
+                    if let Err(err) = self.db.try_catch_up_with_primary() {
+                        error!(target: "db", "Error catching up with the primary {}", err);
+                    }
+                }
+                _ = Self::tick(&mut self.subscription_sweep_interval) => {
+                    if let Err(err) = self.remove_expired_subscriptions() {
+                        error!(target: "db", "Error removing expired subscriptions {}", err);
+                    }
+                }
+                index = Self::replay_room(&self.block_replays, |replay| &replay.subscriber),
+                    if !self.block_replays.is_empty() => self.replay_block_batch(index),
+                index = Self::replay_room(&self.account_replays, |replay| &replay.subscriber),
+                    if !self.account_replays.is_empty() => self.replay_account_batch(index),
+                _ = Shutdown::wait(&mut self.shutdown) => {}
+            }
+        }
+    }
+
+    /// This function flushes the memtables and the write ahead log once the db is drained, so the
+    /// next start neither replays the log nor loses unsynced writes
+    fn flush_on_shutdown(&self) {
+        if self.read_only {
+            return;
+        }
+        if let Err(err) = self.db.flush_wal(true) {
+            error!(target: "db", "Error flushing wal {}", err);
+        }
+        if let Err(err) = self.db.flush() {
+            error!(target: "db", "Error flushing memtables {}", err);
+        }
+        // Flushing the default column family leaves the memtables of the others to the log
+        for name in COLUMN_FAMILIES {
+            if let Err(err) = self.cf(name).and_then(|cf| Ok(self.db.flush_cf(cf)?)) {
+                error!(target: "db", "Error flushing memtables of {} {}", name, err);
+            }
+        }
+        info!(target: "db", "Db drained and flushed");
+    }
+
+    /// This function receives the next finalized block, or never completes if there is no block
+    /// queue
+    async fn recv_block(
+        receiver: &mut Option<Receiver<ProtocolMessage>>,
+    ) -> Option<ProtocolMessage> {
+        match receiver {
+            Some(receiver) => receiver.recv().await,
+            None => std::future::pending().await,
+        }
+    }
+
+    /// This function completes on the next tick of the interval, or never if it is not set
+    async fn tick(interval: &mut Option<Interval>) {
+        match interval {
+            Some(interval) => {
+                interval.tick().await;
+            }
+            None => std::future::pending().await,
+        }
+    }
+
+    /// This function compacts the blocks that turned cold since the last scheduled run, if the
+    /// current hour is inside the configured low traffic window
+    fn run_scheduled_compaction(&mut self) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let hour = ((now / 3600) % 24) as u8;
+        if !self.compaction.hours_utc.is_empty() && !self.compaction.hours_utc.contains(&hour) {
+            return;
+        }
+        let Some(cold_end) = self
+            .get_latest_block()
+            .and_then(|latest| latest.checked_sub(self.compaction.cold_block_lag))
+        else {
+            return;
+        };
+        let start = self
+            .compaction_stats
+            .compacted_through
+            .map_or(0, |block_no| block_no + 1);
+        if start > cold_end {
+            return;
+        }
+        self.compact(Some((start, cold_end)));
+        self.compaction_stats.compacted_through = Some(cold_end);
+    }
+
+    /// This function compacts a range of blocks, or the whole db
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - An Option<(u64, u64)> that holds the inclusive block range to compact
+    fn compact(&mut self, range: Option<(u64, u64)>) {
+        let started = Instant::now();
+        self.compaction_stats.last_run_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|now| now.as_secs());
+        match range {
+            Some((start, end)) => match self.cf(BLOCKS_CF) {
+                Ok(cf) => {
+                    self.db
+                        .compact_range_cf(cf, Some(start.to_be_bytes()), Some(end.to_be_bytes()))
+                }
+                Err(err) => error!(target: "db", "Error compacting {:?} {}", range, err),
+            },
+            None => self.db.compact_range(None::<&[u8]>, None::<&[u8]>),
+        }
+        self.compaction_stats.runs += 1;
+        self.compaction_stats.last_range = range;
+        self.compaction_stats.last_duration_ms = Some(started.elapsed().as_millis() as u64);
+        info!(target: "db", "Compacted {:?} in {:?}", range, started.elapsed());
+    }
+
+    /// This function returns the compaction stats
+    fn compaction_stats(&self) -> CompactionStats {
+        let mut stats = self.compaction_stats.clone();
+        stats.pending_compaction_bytes = self
+            .db
+            .property_int_value("rocksdb.estimate-pending-compaction-bytes")
+            .ok()
+            .flatten();
+        stats
+    }
+
+    /// This function returns the handle of a column family
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - A string slice that holds the column family name
+    ///
+    /// # Returns
+    ///
+    /// * `Result<&ColumnFamily, AggError>` - A Result that holds the handle or an error
+    fn cf(&self, name: &'static str) -> Result<&ColumnFamily, AggError> {
+        self.db
+            .cf_handle(name)
+            .ok_or(AggError::MissingColumnFamily(name))
+    }
+
+    /// This function writes a key with the configured durability policy
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key to write
+    /// * `value` - The value to write
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn put<K: AsRef<[u8]>, V: AsRef<[u8]>>(&self, key: K, value: V) -> Result<(), AggError> {
+        self.db
+            .put_opt(&key, &value, &self.write_options)
+            .map_err(AggError::from)
+            .with_key(String::from_utf8_lossy(key.as_ref()))
+    }
+
+    /// This function writes a key of a column family with the configured durability policy
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - A string slice that holds the column family name
+    /// * `key` - The key to write
+    /// * `value` - The value to write
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn put_cf<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        &self,
+        name: &'static str,
+        key: K,
+        value: V,
+    ) -> Result<(), AggError> {
+        let cf = self.cf(name)?;
+        self.db
+            .put_cf_opt(cf, &key, &value, &self.write_options)
+            .map_err(AggError::from)
+            .with_key(format!(
+                "{}/{}",
+                name,
+                String::from_utf8_lossy(key.as_ref())
+            ))
+    }
+
+    /// This function runs the RocksDb client until it is asked to stop and drained
+    pub async fn run(&mut self) {
+        if !self.read_only {
+            self.resume_refetches();
+        }
+        if self.warmup.enabled && !self.readiness.is_ready() {
+            let started = Instant::now();
+            let report = self.warm_up();
+            info!(
+                target: "db",
+                "Warmed up {} blocks and {} account summaries in {:?}",
+                report.blocks,
+                report.accounts,
+                started.elapsed()
+            );
+            metrics::WARMUP_SECONDS.set(started.elapsed().as_secs_f64());
+        }
+        self.readiness.mark_ready();
+        while let Some(message) = self.next_message().await {
+            let message = match message {
+                ProtocolMessage::Deadline(deadline, message) => {
+                    if Instant::now() >= deadline {
+                        // Stuck behind a backfill, the server already gave up on it
+                        metrics::EXPIRED_QUERIES.inc();
+                        debug!(target: "db", "Dropping query past its deadline {:?}", message);
+                        continue;
+                    }
+                    *message
+                }
+                message => message,
+            };
+            match message {
+                ProtocolMessage::FinalizeBlock(block_no, block) => {
+                    debug!(
+                        target: "db",
+                        "Finalizing block {} with {} transactions",
+                        block_no,
+                        block.get_tx_hash().len()
+                    );
+                    let slot = block.slot();
+                    let started = Instant::now();
+                    // Writes failing transiently are retried without blocking the runtime, every
+                    // attempt stores the block again and the custom stats count it once
+                    let handled = WRITE_RETRY
+                        .run(FailureStage::Write, || {
+                            let handled = self
+                                .handle_block(block_no, block.clone())
+                                .with_block(block_no);
+                            async move { handled }
+                        })
+                        .await;
+                    self.write_latency.record(started.elapsed());
+                    if let Err(failure) = handled {
+                        error!(target: "db", "Error from handle_block {}", failure.error);
+                        self.store_dead_letter(DeadLetter::new(
+                            FailureStage::Write,
+                            slot,
+                            Some(block_no),
+                            &failure,
+                        ));
+                    }
+                }
+                ProtocolMessage::FetchTransactionDetails(tx_id, reply) => {
+                    debug!(target: "db", "Fetching tx details {:?}", tx_id);
+                    Self::reply(reply, self.handle_tx_request(tx_id));
+                }
+                ProtocolMessage::FetchTxInclusion(tx_id, reply) => {
+                    Self::reply(reply, self.handle_tx_inclusion_request(tx_id));
+                }
+                ProtocolMessage::FetchBlockDetails(block_no, reply) => {
+                    debug!(target: "db", "Fetching block details {:?}", block_no);
+                    Self::reply(reply, self.handle_block_request(block_no));
+                }
+                ProtocolMessage::FetchBlockAtSlot(slot, reply) => {
+                    Self::reply(reply, self.handle_block_at_slot_request(slot));
+                }
+                ProtocolMessage::FetchLatestBlock(reply) => {
+                    debug!(target: "db", "Fetching latest block");
+                    Self::reply(reply, self.handle_latest_block_request());
+                }
+                ProtocolMessage::FetchBlockRange(start, end, reply) => {
+                    debug!(target: "db", "Fetching block range {}..={}", start, end);
+                    Self::reply(reply, self.handle_block_range_request(start, end));
+                }
+                ProtocolMessage::FetchBlocksBySlot(start, end, limit, reply) => {
+                    Self::reply(reply, self.handle_blocks_by_slot_request(start, end, limit));
+                }
+                ProtocolMessage::FetchAccountBalance(pubkey, block_no, reply) => {
+                    debug!(target: "db", "Fetching account balance {}", pubkey);
+                    Self::reply(reply, self.handle_account_balance_request(pubkey, block_no));
+                }
+                ProtocolMessage::FetchAccountBalanceAtSlot(pubkey, slot, reply) => {
+                    Self::reply(
+                        reply,
+                        self.handle_account_balance_at_slot_request(pubkey, slot),
+                    );
+                }
+                ProtocolMessage::FetchBalancesAt(pubkeys, block_no, reply) => {
+                    Self::reply(reply, self.handle_balances_at_request(pubkeys, block_no));
+                }
+                ProtocolMessage::FetchBalanceHistory(
+                    pubkey,
+                    start_slot,
+                    end_slot,
+                    limit,
+                    reply,
+                ) => {
+                    Self::reply(
+                        reply,
+                        self.handle_balance_history_request(pubkey, start_slot, end_slot, limit),
+                    );
+                }
+                ProtocolMessage::FetchTimeRange(start_time, end_time, reply) => {
+                    Self::reply(reply, self.handle_time_range_request(start_time, end_time));
+                }
+                ProtocolMessage::SkippedSlot(slot) => {
+                    if let Err(err) = self.put(format!("SkippedSlot{}", slot), []) {
+                        error!(target: "db", "Error marking skipped slot {}", err);
+                    }
+                }
+                ProtocolMessage::FetchIndexedSlots(start, end, limit, reply) => {
+                    Self::reply(reply, self.handle_indexed_slots_request(start, end, limit));
+                }
+                ProtocolMessage::FetchTokenHolders(mint, block_no, reply) => {
+                    Self::reply(reply, self.handle_token_holders_request(mint, block_no));
+                }
+                ProtocolMessage::FetchTokenBalance(owner, mint, reply) => {
+                    Self::reply(reply, self.handle_token_balance_request(owner, mint));
+                }
+                ProtocolMessage::FetchTokenBalances(owner, reply) => {
+                    Self::reply(reply, self.handle_token_balances_request(owner));
+                }
+                ProtocolMessage::FetchAccountSummary(pubkey, reply) => {
+                    Self::reply(reply, self.handle_account_summary_request(pubkey));
+                }
+                ProtocolMessage::FetchAccountTransactions(pubkey, cursor, limit, reply) => {
+                    Self::reply(
+                        reply,
+                        self.handle_account_transactions_request(pubkey, cursor, limit),
+                    );
+                }
+                ProtocolMessage::FetchCustomStats(rule, reply) => {
+                    Self::reply(reply, self.handle_custom_stats_request(rule));
+                }
+                ProtocolMessage::FetchActiveAccounts(days, reply) => {
+                    Self::reply(reply, self.handle_active_accounts_request(days));
+                }
+                ProtocolMessage::Compact(range, reply) => {
+                    if self.read_only {
+                        Self::reply(reply, Err(AggError::ReadOnly));
+                    } else {
+                        self.compact(range);
+                        Self::reply(
+                            reply,
+                            Ok(Response::CompactionStats(self.compaction_stats())),
+                        );
+                    }
+                }
+                ProtocolMessage::DeleteSlotRange(start, end, reply) => {
+                    Self::reply(reply, self.handle_delete_slot_range(start, end));
+                }
+                ProtocolMessage::RefetchBlock(block_no, raw_block, reply) => {
+                    Self::reply(reply, self.handle_refetch_request(block_no, *raw_block));
+                }
+                ProtocolMessage::FetchLatestBlockSummaries(limit, reply) => {
+                    Self::reply(reply, self.handle_latest_block_summaries_request(limit));
+                }
+                ProtocolMessage::FetchStatus(reply) => {
+                    Self::reply(reply, self.handle_status_request());
+                }
+                ProtocolMessage::Annotate(slot, note, reply) => {
+                    Self::reply(reply, self.handle_annotate_request(slot, note));
+                }
+                ProtocolMessage::FetchBlockDigest(block_no, reply) => {
+                    Self::reply(reply, self.handle_block_digest_request(block_no));
+                }
+                ProtocolMessage::SubscribeBlocks(from_block_no, filter, subscriber) => {
+                    self.handle_block_subscription(from_block_no, filter, subscriber);
+                }
+                ProtocolMessage::SubscribeAccount(pubkey, from_slot, token, subscriber) => {
+                    if let Err(error) = self.handle_account_subscription(
+                        pubkey,
+                        from_slot,
+                        token,
+                        subscriber.clone(),
+                    ) {
+                        Self::handle_error(&subscriber, error);
+                    }
+                }
+                ProtocolMessage::AckResumeCursor(token, slot) => {
+                    if let Err(err) = self.advance_resume_cursor(&token, slot) {
+                        error!(target: "db", "Error advancing resume cursor {}", err);
+                    }
+                }
+                ProtocolMessage::CreateWebhook(pubkey, url, ttl_secs, reply) => {
+                    Self::reply(
+                        reply,
+                        self.handle_create_webhook_request(pubkey, url, ttl_secs),
+                    );
+                }
+                ProtocolMessage::DeleteWebhook(id, reply) => {
+                    Self::reply(reply, self.handle_delete_webhook_request(id));
+                }
+                ProtocolMessage::DeliveryReport(receipt) => {
+                    if let Err(err) = self.add_delivery_receipt(&receipt) {
+                        error!(target: "db", "Error storing delivery receipt {}", err);
+                    }
+                }
+                ProtocolMessage::DeadLetter(letter) => {
+                    let dropped = letter.block_no.filter(|_| letter.stage.drops_block());
+                    self.store_dead_letter(*letter);
+                    if let Some(block_no) = dropped {
+                        if let Err(err) = self.skip_block(block_no) {
+                            error!(target: "db", "Error skipping block {} {}", block_no, err);
+                        }
+                    }
+                }
+                ProtocolMessage::FetchDeadLetters(limit, reply) => {
+                    Self::reply(reply, self.handle_dead_letters_request(limit));
+                }
+                ProtocolMessage::FetchWebhookDeliveries(id, limit, reply) => {
+                    Self::reply(reply, self.handle_webhook_deliveries_request(id, limit));
+                }
+                ProtocolMessage::FetchSubscriptions(reply) => {
+                    let subscriptions = Subscriptions {
+                        webhooks: self
+                            .webhooks
+                            .values()
+                            .map(|webhook| WebhookSubscription {
+                                secret: String::new(),
+                                ..webhook.clone()
+                            })
+                            .collect(),
+                        resume_cursors: self.resume_cursors.values().cloned().collect(),
+                    };
+                    Self::reply(reply, Ok(Response::Subscriptions(subscriptions)));
+                }
+                ProtocolMessage::ArchiveRawBlock(block_no, raw_block) => {
+                    if let Err(err) = self.add_raw_block(block_no, &raw_block) {
+                        error!(target: "db", "Error archiving raw block {}", err);
+                    }
+                }
+                ProtocolMessage::VerifyGenesisHash(genesis_hash, reply) => {
+                    let verified = self
+                        .verify_genesis_hash(&genesis_hash)
+                        .map(|()| Response::GenesisHash(genesis_hash));
+                    Self::reply(reply, verified);
+                }
+                ProtocolMessage::StartJob(task, reply) => {
+                    Self::reply(reply, self.handle_start_job_request(task));
+                }
+                ProtocolMessage::FetchJobs(reply) => {
+                    Self::reply(
+                        reply,
+                        Ok(Response::Jobs(self.jobs.values().cloned().collect())),
+                    );
+                }
+                ProtocolMessage::FetchJob(id, reply) => {
+                    Self::reply(reply, Ok(Response::Job(self.jobs.get(&id).cloned())));
+                }
+                ProtocolMessage::CancelJob(id, reply) => {
+                    Self::reply(reply, self.handle_cancel_job_request(id));
+                }
+                ProtocolMessage::UpdateJob(id, task, state, job_error) => {
+                    if let Err(err) = self.update_job(id, task, state, job_error) {
+                        error!(target: "db", "Error updating job {} {}", id, err);
+                    }
+                }
+                _ => {}
+            }
+        }
+        if Shutdown::is_set(&self.shutdown) {
+            self.flush_on_shutdown();
+        }
+    }
+
+    /// This function lists the slots of a range that are indexed or marked skipped
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - A u64 that holds the first slot
+    /// * `end` - A u64 that holds the last slot
+    /// * `limit` - A u64 that holds the maximum number of slots returned
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the response or an error
+    fn handle_indexed_slots_request(
+        &self,
+        start: u64,
+        end: u64,
+        limit: u64,
+    ) -> Result<Response, AggError> {
+        let snapshot = self.db.snapshot();
+        let mut budget = QueryBudget::new(&self.query_limits);
+        let mut slots = IndexedSlots::default();
+        for slot in start..=end {
+            if (slots.indexed.len() + slots.skipped.len()) as u64 >= limit {
+                slots.next_slot = Some(slot);
+                break;
+            }
+            budget.scan()?;
+            if snapshot.get(format!("Slot{}", slot))?.is_some() {
+                slots.indexed.push(slot);
+            } else if snapshot.get(format!("SkippedSlot{}", slot))?.is_some() {
+                slots.skipped.push(slot);
+            }
+        }
+        Ok(Response::IndexedSlots(slots))
+    }
+
+    /// This function reads the stored blocks of a slot range in slot order, a batch at a time
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - A u64 that holds the first slot
+    /// * `end` - A u64 that holds the last slot
+    /// * `limit` - A u64 that holds the maximum number of blocks returned
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the response or an error
+    fn handle_blocks_by_slot_request(
+        &self,
+        start: u64,
+        end: u64,
+        limit: u64,
+    ) -> Result<Response, AggError> {
+        let snapshot = self.db.snapshot();
+        let mut blocks = Vec::new();
+        let mut next_slot = None;
+        // Skipped and unindexed slots are scanned too, bound them so a sparse range can not
+        // hold the db for long
+        let scan_end = end.min(start.saturating_add(limit.saturating_mul(100)));
+        for slot in start..=scan_end {
+            if blocks.len() as u64 >= limit {
+                next_slot = Some(slot);
+                break;
+            }
+            let Some(block_no) = snapshot.get(format!("Slot{}", slot))? else {
+                continue;
+            };
+            let block_no = from_slice::<u64>(&block_no)?;
+            if let Some(block) = self.snapshot_block(&snapshot, block_no)? {
+                blocks.push((block_no, self.annotate(&snapshot, block)?));
+            }
+        }
+        if next_slot.is_none() && scan_end < end {
+            next_slot = Some(scan_end + 1);
+        }
+        Ok(Response::BlocksBySlot(blocks, next_slot))
+    }
+
+    /// This function deletes everything indexed for the blocks of a slot range, so the range
+    /// can be backfilled cleanly, in a single atomic batch
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - A u64 that holds the first slot
+    /// * `end` - A u64 that holds the last slot
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the response or an error
+    fn handle_delete_slot_range(&mut self, start: u64, end: u64) -> Result<Response, AggError> {
+        if self.read_only {
+            return Err(AggError::ReadOnly);
+        }
+        let deleted = self.delete_slot_range(start, end)?;
+        Ok(Response::DeletedSlots(deleted))
+    }
+
+    /// This function replaces the stored block of a slot with the block fetched from the node
+    /// again. The fetched block is parsed in the configured mode and run through the plugins
+    /// first, so a block failing either leaves the stored block in place. The replaced block is
+    /// then archived as it was stored and deleted with everything indexed for it, in one batch
+    /// with a record of the fetched block, which is stored and has its account state applied
+    /// again even if it is behind the latest block.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number of the fetched block
+    /// * `raw_block` - A RawBlock that holds the block as fetched from the node
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the refetched block or an error
+    fn handle_refetch_request(
+        &mut self,
+        block_no: u64,
+        raw_block: RawBlock,
+    ) -> Result<Response, AggError> {
+        if self.read_only {
+            return Err(AggError::ReadOnly);
+        }
+        let slot = raw_block.header.slot;
+        let mut block = Parser::parse_block(
+            raw_block.header.clone(),
+            &raw_block.transactions,
+            self.parse_mode,
+        )?;
+        if let Err(rejection) = plugin::run(&self.plugin_config, block_no, &mut block) {
+            return Err(AggError::PluginRejected(rejection.plugin, rejection.reason));
+        }
+        let transactions = block.get_tx_hash().len() as u64;
+        let replaced = match self.db.get(format!("Slot{}", slot))? {
+            Some(replaced) => Some(from_slice::<u64>(&replaced)?),
+            None => None,
+        };
+        let mut batch = WriteBatch::default();
+        let mut deleted = DeletedSlots::default();
+        // The raw block is deleted with the replaced block, it is kept if it was archived
+        let mut archive_raw_block = false;
+        if let Some(replaced) = replaced {
+            archive_raw_block = self
+                .db
+                .get_cf(self.cf(RAW_BLOCKS_CF)?, replaced.to_be_bytes())?
+                .is_some();
+            if let Some(block) = get_block_bytes(&self.db, replaced)? {
+                let replaced_at = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis() as u64;
+                batch.put_cf(
+                    self.cf(REPLACED_BLOCKS_CF)?,
+                    replaced_block_key(replaced, replaced_at),
+                    block,
+                );
+            }
+            deleted = self.stage_slot_range_deletion(slot, slot, &mut batch)?;
+        }
+        let refetch = PendingRefetch {
+            block,
+            raw_block: archive_raw_block.then_some(raw_block),
+        };
+        batch.put(Self::refetch_key(block_no), self.codec.encode(&refetch)?);
+        self.db.write_opt(batch, &self.write_options)?;
+        for block_no in deleted.block_nos.iter() {
+            self.state_applier.remove(*block_no);
+        }
+        self.store_refetched_block(block_no, refetch)?;
+        info!(
+            target: "db",
+            "Replaced block {:?} of slot {} with block {} fetched again",
+            replaced, slot, block_no
+        );
+        Ok(Response::RefetchedBlock(RefetchedBlock {
+            slot,
+            block_no,
+            transactions,
+            replaced,
+        }))
+    }
+
+    /// This function stores a block fetched again and applies its account state. A block behind
+    /// the latest block would only have its balances indexed by the state applier, so it is
+    /// checked against its parent and has its state applied here without becoming the latest
+    /// block.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number
+    /// * `refetch` - A PendingRefetch that holds the block and the raw block to archive
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn store_refetched_block(
+        &mut self,
+        block_no: u64,
+        refetch: PendingRefetch,
+    ) -> Result<(), AggError> {
+        if let Some(raw_block) = &refetch.raw_block {
+            self.add_raw_block(block_no, raw_block)?;
+        }
+        if self
+            .get_latest_block()
+            .is_some_and(|latest| block_no <= latest)
+        {
+            self.store_block(block_no, refetch.block)?;
+            if self.verify_parent(block_no)? != ChainLink::Broken {
+                let block = self.get_block(block_no).ok_or(AggError::BlockNotFound)?;
+                self.index_account_balances(block_no, &block)?;
+            }
+        } else {
+            self.handle_block(block_no, refetch.block)?;
+        }
+        self.db
+            .delete_opt(Self::refetch_key(block_no), &self.write_options)?;
+        Ok(())
+    }
+
+    /// This function stores the blocks fetched again by a previous run that stopped after their
+    /// replaced blocks were deleted
+    fn resume_refetches(&mut self) {
+        let mut pending = vec![];
+        for entry in self.db.prefix_iterator(REFETCH_PREFIX) {
+            let Ok((key, value)) = entry else {
+                break;
+            };
+            let Some(block_no) = key.strip_prefix(REFETCH_PREFIX.as_bytes()) else {
+                break;
+            };
+            if let Ok(block_no) = String::from_utf8_lossy(block_no).parse::<u64>() {
+                pending.push((block_no, value));
+            }
+        }
+        for (block_no, refetch) in pending {
+            let stored = codec::decode::<PendingRefetch>(&refetch)
+                .and_then(|refetch| self.store_refetched_block(block_no, refetch));
+            match stored {
+                Ok(()) => info!(target: "db", "Stored block {} fetched before a restart", block_no),
+                Err(err) => {
+                    error!(target: "db", "Error storing refetched block {} {}", block_no, err)
+                }
+            }
+        }
+    }
+
+    /// This function builds the key a block fetched again is recorded under until it is stored
+    fn refetch_key(block_no: u64) -> String {
+        format!("{}{:020}", REFETCH_PREFIX, block_no)
+    }
+
+    /// This function deletes everything indexed for the blocks of a slot range in a single
+    /// atomic batch
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - A u64 that holds the first slot
+    /// * `end` - A u64 that holds the last slot
+    ///
+    /// # Returns
+    ///
+    /// * `Result<DeletedSlots, AggError>` - A Result that holds the deleted blocks or an error
+    fn delete_slot_range(&mut self, start: u64, end: u64) -> Result<DeletedSlots, AggError> {
+        let mut batch = WriteBatch::default();
+        let deleted = self.stage_slot_range_deletion(start, end, &mut batch)?;
+        self.db.write_opt(batch, &self.write_options)?;
+        for block_no in deleted.block_nos.iter() {
+            self.state_applier.remove(*block_no);
+        }
+        info!(target: "db", "Deleted slots {}..={} {:?}", start, end, deleted);
+        Ok(deleted)
+    }
+
+    /// This function adds the deletion of everything indexed for the blocks of a slot range to a
+    /// batch, for the caller to write along with writes of its own. The deleted blocks are to be
+    /// dropped from the state applier once the batch is written.
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - A u64 that holds the first slot
+    /// * `end` - A u64 that holds the last slot
+    /// * `batch` - A mutable reference to the WriteBatch that receives the deletions
+    ///
+    /// # Returns
+    ///
+    /// * `Result<DeletedSlots, AggError>` - A Result that holds the deleted blocks or an error
+    fn stage_slot_range_deletion(
+        &mut self,
+        start: u64,
+        end: u64,
+        batch: &mut WriteBatch,
+    ) -> Result<DeletedSlots, AggError> {
+        let mut deleted = DeletedSlots::default();
+        let mut custom_stats: BTreeMap<String, f64> = BTreeMap::new();
+        // Token accounts whose latest balance is restored from the blocks left, with their mint
+        let mut token_accounts: BTreeMap<String, String> = BTreeMap::new();
+        for slot in start..=end {
+            batch.delete(format!("SkippedSlot{}", slot));
+            let Some(block_no) = self.db.get(format!("Slot{}", slot))? else {
+                continue;
+            };
+            batch.delete(format!("Slot{}", slot));
+            let block_no = from_slice::<u64>(&block_no)?;
+            deleted.block_nos.push(block_no);
+            batch.delete(format!("BlockDigest{}", block_no));
+            batch.delete(format!("{}{}", CHAIN_LINK_PREFIX, block_no));
+            batch.delete(Self::quarantine_key(block_no));
+            batch.delete(format!("{}{}", PENDING_STATE_PREFIX, block_no));
+            let Some(block) = self.get_block(block_no) else {
+                continue;
+            };
+            batch.delete(format!("{}{}", LEGACY_BLOCK_PREFIX, block_no));
+            if let Some(key) = Self::slot_time_key(&block) {
+                batch.delete(key);
+            }
+            if let (Some(slot), Some(account_map)) = (block.slot(), block.get_account_map()) {
+                for account in account_map.keys() {
+                    if let Ok(pubkey) = Pubkey::from_str(account) {
+                        batch.delete_cf(
+                            self.cf(ACCOUNTS_DELTA_CF)?,
+                            account_delta_key(&pubkey, slot),
+                        );
+                        batch.delete_cf(
+                            self.cf(ACCOUNT_BALANCES_CF)?,
+                            account_balance_key(&pubkey, block_no),
+                        );
+                    }
+                }
+            }
+            for tx in block.get_tx_hash() {
+                batch.delete(to_vec(&tx)?);
+                if let Ok(raw_tx_id) = bs58::decode(&tx).into_vec() {
+                    // A transaction also in a block outside the range stays indexed there
+                    let fallback = match self.db.get(Self::tx_conflict_key(&tx))? {
+                        Some(conflict) => {
+                            batch.delete(Self::tx_conflict_key(&tx));
+                            let conflict = from_slice::<TxConflict>(&conflict)?;
+                            [conflict.kept, conflict.other]
+                                .into_iter()
+                                .find(|location| {
+                                    location.block_no != block_no
+                                        && !(start..=end).contains(&location.slot)
+                                })
+                        }
+                        None => None,
+                    };
+                    match fallback {
+                        Some(location) => {
+                            batch.put_cf(self.cf(TX_INDEX_CF)?, raw_tx_id, location.to_bytes())
+                        }
+                        None => batch.delete_cf(self.cf(TX_INDEX_CF)?, raw_tx_id),
+                    }
+                }
+                deleted.transactions += 1;
+            }
+            for (_, tx) in block.transactions() {
+                for signature in tx.signatures() {
+                    if let Ok(raw_signature) = bs58::decode(signature).into_vec() {
+                        batch.delete_cf(self.cf(TX_SIGNATURES_CF)?, raw_signature);
+                    }
+                }
+            }
+            if let Some(slot) = block.slot() {
+                for (tx_id, account, _) in block.authority_changes() {
+                    let (Ok(tx_id), Ok(pubkey)) =
+                        (bs58::decode(tx_id).into_vec(), Pubkey::from_str(account))
+                    else {
+                        continue;
+                    };
+                    batch.delete_cf(
+                        self.cf(AUTHORITY_HISTORY_CF)?,
+                        account_tx_key(&pubkey, slot, &tx_id),
+                    );
+                }
+                for (tx_id, accounts) in block.tx_accounts() {
+                    let Ok(tx_id) = bs58::decode(tx_id).into_vec() else {
+                        continue;
+                    };
+                    for account in accounts {
+                        if let Ok(pubkey) = Pubkey::from_str(account) {
+                            batch.delete_cf(
+                                self.cf(ACCOUNT_TXS_CF)?,
+                                account_tx_key(&pubkey, slot, &tx_id),
+                            );
+                        }
+                    }
+                }
+            }
+            for (token_account, balance) in block.get_token_balances() {
+                batch.delete(format!(
+                    "{}{}/{}/{:020}",
+                    TOKEN_BALANCE_PREFIX, balance.mint, token_account, block_no
+                ));
+                token_accounts.insert(token_account.clone(), balance.mint.clone());
+            }
+            for account in block.get_first_seen().keys() {
+                let Ok(pubkey) = Pubkey::from_str(account) else {
+                    continue;
+                };
+                if let Some(first_seen) = self.get_first_seen(&pubkey)? {
+                    if first_seen.block_no == block_no {
+                        batch.delete_cf(self.cf(ACCOUNTS_CF)?, pubkey.to_bytes());
+                        batch.delete(format!("{}{}", LEGACY_FIRST_SEEN_PREFIX, account));
+                    }
+                }
+            }
+            // Only a block counted in the custom stats has its contribution taken back
+            let counted_key = Self::custom_stat_block_key(block_no);
+            if self.db.get_pinned(&counted_key)?.is_some() {
+                batch.delete(counted_key);
+                for (rule, bucket, value) in self.rule_engine.evaluate(&block) {
+                    *custom_stats
+                        .entry(format!("{}{}/{}", CUSTOM_STAT_PREFIX, rule, bucket))
+                        .or_default() += value;
+                }
+            }
+        }
+        // The blocks of a slot range are a range of block numbers, which key the bodies, summaries
+        // and raw blocks in big endian, so they are deleted with one range tombstone each. The
+        // other indexes are keyed by something else and deleted key by key above.
+        if let (Some(first), Some(last)) = (
+            deleted.block_nos.iter().min(),
+            deleted.block_nos.iter().max(),
+        ) {
+            let from = first.to_be_bytes();
+            let to = last.saturating_add(1).to_be_bytes();
+            for name in [BLOCKS_CF, BLOCK_SUMMARY_CF, RAW_BLOCKS_CF] {
+                batch.delete_range_cf(self.cf(name)?, from, to);
+            }
+        }
+        for (token_account, mint) in token_accounts {
+            let Ok(account) = Pubkey::from_str(&token_account) else {
+                continue;
+            };
+            let latest = self.latest_token_balance(&mint, &token_account, &deleted.block_nos)?;
+            self.index_token_account(
+                batch,
+                &account,
+                latest
+                    .as_ref()
+                    .map(|(block_no, balance)| (*block_no, balance)),
+            )?;
+        }
+        for (key, value) in custom_stats {
+            if let Some(current) = self.db.get(&key)? {
+                batch.put(&key, to_vec(&(from_slice::<f64>(&current)? - value))?);
+            }
+        }
+        // Move the latest pointer back below the range if its block was deleted
+        let latest_block = self.get_latest_block();
+        if let (Some(latest), Some(first)) = (latest_block, deleted.block_nos.iter().min()) {
+            if deleted.block_nos.contains(&latest) {
+                match self.block_before(*first)? {
+                    Some(block_no) => {
+                        batch.put_cf(self.cf(META_CF)?, LATEST_BLOCK_NO_KEY, to_vec(&block_no)?)
+                    }
+                    None => batch.delete_cf(self.cf(META_CF)?, LATEST_BLOCK_NO_KEY),
+                }
+            }
+        }
+        self.chain_status
+            .quarantined_blocks
+            .retain(|block_no| !deleted.block_nos.contains(block_no));
+        batch.put_cf(
+            self.cf(META_CF)?,
+            CHAIN_STATUS_KEY,
+            to_vec(&self.chain_status)?,
+        );
+        Ok(deleted)
+    }
+
+    /// This function finds the latest stored block before a block with a single seek of the block
+    /// summaries
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<u64>, AggError>` - A Result that holds the block number, None if no block
+    ///   is stored before it, or an error
+    fn block_before(&self, block_no: u64) -> Result<Option<u64>, AggError> {
+        let Some(before) = block_no.checked_sub(1) else {
+            return Ok(None);
+        };
+        let from = before.to_be_bytes();
+        let mut iterator = self.db.iterator_cf(
+            self.cf(BLOCK_SUMMARY_CF)?,
+            IteratorMode::From(&from, Direction::Reverse),
+        );
+        match iterator.next().transpose()? {
+            Some((key, _)) => Ok(Some(u64::from_be_bytes(key.as_ref().try_into()?))),
+            None => Ok(None),
+        }
+    }
+
+    /// This function handles the account balance request at a slot
+    ///
+    /// # Arguments
+    ///
+    /// * `pubkey` - A String that holds the public key
+    /// * `slot` - A u64 that holds the slot
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the response or an error
+    fn handle_account_balance_at_slot_request(
+        &self,
+        pubkey: String,
+        slot: u64,
+    ) -> Result<Response, AggError> {
+        let pubkey = Pubkey::from_str(&pubkey)?;
+        let snapshot = self.db.snapshot();
+        let balance = match Self::snapshot_account_delta(
+            &snapshot,
+            self.cf(ACCOUNTS_DELTA_CF)?,
+            &pubkey,
+            slot,
+        )? {
+            Some((observed_slot, delta)) => AccountBalance::new(Some(delta.balance), Some(slot))
+                .observed(observed_slot, delta.block_no),
+            None => AccountBalance::new(None, Some(slot)),
+        };
+        Ok(Response::AccountBalance(balance))
+    }
+
+    /// This function handles the balance history request
+    ///
+    /// # Arguments
+    ///
+    /// * `pubkey` - A String that holds the public key
+    /// * `start_slot` - A u64 that holds the first slot
+    /// * `end_slot` - A u64 that holds the last slot
+    /// * `limit` - A u64 that holds the maximum number of entries returned
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the response or an error
+    fn handle_balance_history_request(
+        &self,
+        pubkey: String,
+        start_slot: u64,
+        end_slot: u64,
+        limit: u64,
+    ) -> Result<Response, AggError> {
+        let pubkey = Pubkey::from_str(&pubkey)?;
+        let from = account_delta_key(&pubkey, start_slot);
+        let iterator = self.db.iterator_cf(
+            self.cf(ACCOUNTS_DELTA_CF)?,
+            IteratorMode::From(&from, Direction::Forward),
+        );
+        let mut history: Vec<BalanceChange> = vec![];
+        for entry in iterator {
+            let (key, delta) = entry?;
+            let Some(slot) = key.strip_prefix(pubkey.as_ref()) else {
+                break;
+            };
+            let slot = u64::from_be_bytes(slot.try_into()?);
+            if slot > end_slot || history.len() as u64 >= limit {
+                break;
+            }
+            let delta = from_slice::<AccountDelta>(&delta)?;
+            history.push(BalanceChange {
+                slot,
+                block_no: delta.block_no,
+                balance: delta.balance,
+                change: history
+                    .last()
+                    .map(|previous| delta.balance as i128 - previous.balance as i128),
+            });
+        }
+        Ok(Response::BalanceHistory(history))
+    }
+
+    /// This function returns the key indexing the slot of a block by its block time
+    ///
+    /// # Arguments
+    ///
+    /// * `block` - A Block that holds the block
+    ///
+    /// # Returns
+    ///
+    /// * `Option<String>` - The key, None when the block has no slot or block time
+    fn slot_time_key(block: &Block) -> Option<String> {
+        let slot = block.slot()?;
+        let block_time = u64::try_from(block.block_time()?).ok()?;
+        Some(format!(
+            "{}{:020}/{:020}",
+            SLOT_TIME_PREFIX, block_time, slot
+        ))
+    }
+
+    /// This function reads the block time and slot back from a slot time key
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A byte slice that holds the key
+    ///
+    /// # Returns
+    ///
+    /// * `Option<(u64, u64)>` - The block time and slot, None for any other key
+    fn parse_slot_time_key(key: &[u8]) -> Option<(u64, u64)> {
+        let position = std::str::from_utf8(key.strip_prefix(SLOT_TIME_PREFIX.as_bytes())?).ok()?;
+        let (block_time, slot) = position.split_once('/')?;
+        Some((block_time.parse().ok()?, slot.parse().ok()?))
+    }
+
+    /// This function handles the time range request, translating a time window to the slots and
+    /// blocks of the first and last stored block inside it
+    ///
+    /// # Arguments
+    ///
+    /// * `start_time` - A u64 that holds the start of the window in unix seconds
+    /// * `end_time` - A u64 that holds the end of the window in unix seconds
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the response or an error
+    fn handle_time_range_request(
+        &self,
+        start_time: u64,
+        end_time: u64,
+    ) -> Result<Response, AggError> {
+        let snapshot = self.db.snapshot();
+        let from = format!("{}{:020}/", SLOT_TIME_PREFIX, start_time);
+        let first = match snapshot
+            .iterator(IteratorMode::From(from.as_bytes(), Direction::Forward))
+            .next()
+        {
+            Some(entry) => {
+                let (key, block_no) = entry?;
+                match Self::parse_slot_time_key(&key) {
+                    Some((block_time, slot)) if block_time <= end_time => {
+                        Some((slot, from_slice::<u64>(&block_no)?))
+                    }
+                    _ => None,
+                }
+            }
+            None => None,
+        };
+        let to = format!("{}{:020}/{:020}", SLOT_TIME_PREFIX, end_time, u64::MAX);
+        let last = match snapshot
+            .iterator(IteratorMode::From(to.as_bytes(), Direction::Reverse))
+            .next()
+        {
+            Some(entry) => {
+                let (key, block_no) = entry?;
+                match Self::parse_slot_time_key(&key) {
+                    Some((block_time, slot)) if block_time >= start_time => {
+                        Some((slot, from_slice::<u64>(&block_no)?))
+                    }
+                    _ => None,
+                }
+            }
+            None => None,
+        };
+        // Block times are not strictly increasing with the slot, so the earliest block of the
+        // window is not always the one with the lowest slot
+        let range = match (first, last) {
+            (Some((first_slot, first_block_no)), Some((last_slot, last_block_no))) => {
+                Some(TimeRange {
+                    start_slot: first_slot.min(last_slot),
+                    end_slot: first_slot.max(last_slot),
+                    start_block_no: first_block_no.min(last_block_no),
+                    end_block_no: first_block_no.max(last_block_no),
+                })
+            }
+            _ => None,
+        };
+        Ok(Response::TimeRange(range))
+    }
+
+    /// This function records the post balances of the accounts touched by a block
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number
+    /// * `block` - A Block that holds the block
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn add_account_deltas(&self, block_no: u64, block: &Block) -> Result<(), AggError> {
+        let (Some(slot), Some(account_map)) = (block.slot(), block.get_account_map()) else {
+            return Ok(());
+        };
+        let cf = self.cf(ACCOUNTS_DELTA_CF)?;
+        let mut batch = WriteBatch::default();
+        for (account, balance) in account_map {
+            let pubkey = Pubkey::from_str(&account)?;
+            batch.put_cf(
+                cf,
+                account_delta_key(&pubkey, slot),
+                to_vec(&AccountDelta { block_no, balance })?,
+            );
+        }
+        self.db.write_opt(batch, &self.write_options)?;
+        Ok(())
+    }
+
+    /// This function indexes the transactions of a block under every account they involve
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number
+    /// * `block` - A Block that holds the block
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn add_account_transactions(&self, block_no: u64, block: &Block) -> Result<(), AggError> {
+        let Some(slot) = block.slot() else {
+            return Ok(());
+        };
+        let cf = self.cf(ACCOUNT_TXS_CF)?;
+        let mut batch = WriteBatch::default();
+        for (tx_id, tx) in block.transactions() {
+            let Ok(tx_id) = bs58::decode(tx_id).into_vec() else {
+                continue;
+            };
+            for account in tx.accounts() {
+                let pubkey = Pubkey::from_str(account)?;
+                let entry = AccountTxEntry::Indexed {
+                    block_no,
+                    direction: tx.direction(account),
+                };
+                batch.put_cf(cf, account_tx_key(&pubkey, slot, &tx_id), to_vec(&entry)?);
+            }
+        }
+        self.db.write_opt(batch, &self.write_options)?;
+        Ok(())
+    }
+
+    /// This function records the owner, delegate and authority changes of a block in the history
+    /// of the accounts they changed
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number
+    /// * `block` - A Block that holds the block
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn add_authority_changes(&self, block_no: u64, block: &Block) -> Result<(), AggError> {
+        let Some(slot) = block.slot() else {
+            return Ok(());
+        };
+        let mut entries: BTreeMap<Vec<u8>, AuthorityEntry> = BTreeMap::new();
+        for (tx_id, account, change) in block.authority_changes() {
+            let Ok(tx_id) = bs58::decode(tx_id).into_vec() else {
+                continue;
+            };
+            let pubkey = Pubkey::from_str(account)?;
+            entries
+                .entry(account_tx_key(&pubkey, slot, &tx_id))
+                .or_insert_with(|| AuthorityEntry {
+                    block_no,
+                    changes: vec![],
+                })
+                .changes
+                .push(change.clone());
+        }
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let cf = self.cf(AUTHORITY_HISTORY_CF)?;
+        let mut batch = WriteBatch::default();
+        for (key, entry) in entries {
+            batch.put_cf(cf, key, to_vec(&entry)?);
+        }
+        self.db.write_opt(batch, &self.write_options)?;
+        Ok(())
+    }
+
+    /// This function reads the latest owner, delegate and authority changes of an account from a
+    /// snapshot, newest first
+    ///
+    /// # Arguments
+    ///
+    /// * `snapshot` - A Snapshot that holds the consistent view of the db
+    /// * `pubkey` - A Pubkey that holds the account
+    /// * `limit` - A usize that holds the maximum number of changes returned
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<AuthorityEvent>, AggError>` - A Result that holds the changes or an error
+    fn snapshot_authority_history(
+        &self,
+        snapshot: &Snapshot,
+        pubkey: &Pubkey,
+        limit: usize,
+    ) -> Result<Vec<AuthorityEvent>, AggError> {
+        let from = account_tx_key(pubkey, u64::MAX, &[u8::MAX; 64]);
+        let iterator = snapshot.iterator_cf(
+            self.cf(AUTHORITY_HISTORY_CF)?,
+            IteratorMode::From(&from, Direction::Reverse),
+        );
+        let mut history = vec![];
+        for entry in iterator {
+            let (key, value) = entry?;
+            let Some(position) = key.strip_prefix(pubkey.as_ref()) else {
+                break;
+            };
+            let (slot, tx_id) = position.split_at(8);
+            let slot = u64::from_be_bytes(slot.try_into()?);
+            let tx_id = bs58::encode(tx_id).into_string();
+            let entry = from_slice::<AuthorityEntry>(&value)?;
+            // Changes of a transaction are listed last to first like the transactions
+            for change in entry.changes.into_iter().rev() {
+                if history.len() >= limit {
+                    return Ok(history);
+                }
+                history.push(AuthorityEvent {
+                    slot,
+                    block_no: entry.block_no,
+                    tx_id: tx_id.clone(),
+                    change,
+                });
+            }
+        }
+        Ok(history)
+    }
+
+    /// This function archives a block as fetched from the node
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number
+    /// * `raw_block` - A RawBlock that holds the header and encoded transactions
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn add_raw_block(&self, block_no: u64, raw_block: &RawBlock) -> Result<(), AggError> {
+        if self.read_only {
+            return Err(AggError::ReadOnly);
+        }
+        self.db.put_cf_opt(
+            self.cf(RAW_BLOCKS_CF)?,
+            block_no.to_be_bytes(),
+            self.codec.encode(raw_block)?,
+            &self.write_options,
+        )?;
+        Ok(())
+    }
+
+    /// This function creates a job in the running state
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - A u64 that holds the job id
+    /// * `task` - A JobTask that holds the work of the job
+    /// * `state` - A JobState that holds the state of the job
+    ///
+    /// # Returns
+    ///
+    /// * `Job` - The job
+    fn new_job(id: u64, task: JobTask, state: JobState) -> Job {
+        let now = now_secs();
+        Job {
+            id,
+            state,
+            progress: task.progress(),
+            task,
+            error: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    /// This function returns whether a job is running and advanced by the db on every tick
+    fn is_run_by_db(job: &Job) -> bool {
+        job.state == JobState::Running
+            && matches!(
+                job.task,
+                JobTask::Reindex(_)
+                    | JobTask::Prune(_)
+                    | JobTask::MigrateTxIndex(_)
+                    | JobTask::MigrateLayout(_)
+            )
+    }
+
+    fn put_job(&self, job: &Job) -> Result<(), AggError> {
+        self.put(format!("{}{:020}", JOB_PREFIX, job.id), to_vec(job)?)
+    }
+
+    /// This function starts a background job. A re-index job cancels any re-index job that is
+    /// still running, as they would upgrade the same blocks.
+    ///
+    /// # Arguments
+    ///
+    /// * `task` - A JobTask that holds the work of the job
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the response or an error
+    fn handle_start_job_request(&mut self, task: JobTask) -> Result<Response, AggError> {
+        if self.read_only {
+            return Err(AggError::ReadOnly);
+        }
+        if let JobTask::Reindex(_) = task {
+            let replaced: Vec<u64> = self
+                .jobs
+                .values()
+                .filter(|job| {
+                    job.state == JobState::Running && matches!(job.task, JobTask::Reindex(_))
+                })
+                .map(|job| job.id)
+                .collect();
+            for id in replaced {
+                warn!(target: "db", "Replacing unfinished re-index job {}", id);
+                self.set_job_state(id, JobState::Cancelled)?;
+            }
+        }
+        let id = self.jobs.keys().next_back().map_or(1, |id| id + 1);
+        let job = Self::new_job(id, task, JobState::Running);
+        self.put_job(&job)?;
+        if Self::is_run_by_db(&job) && self.job_interval.is_none() {
+            self.job_interval = Some(tokio::time::interval(JOB_TICK));
+        }
+        info!(target: "db", "Started job {} {:?}", id, job.task);
+        self.jobs.insert(id, job.clone());
+        Ok(Response::Job(Some(job)))
+    }
+
+    /// This function cancels a running job, keeping the progress it made. The job is returned
+    /// unchanged if it is not running anymore.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - A u64 that holds the job id
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the response or an error
+    fn handle_cancel_job_request(&mut self, id: u64) -> Result<Response, AggError> {
+        if self.read_only {
+            return Err(AggError::ReadOnly);
+        }
+        if self
+            .jobs
+            .get(&id)
+            .is_some_and(|job| job.state == JobState::Running)
+        {
+            self.set_job_state(id, JobState::Cancelled)?;
+            info!(target: "db", "Cancelled job {}", id);
+        }
+        Ok(Response::Job(self.jobs.get(&id).cloned()))
+    }
+
+    fn set_job_state(&mut self, id: u64, state: JobState) -> Result<(), AggError> {
+        let Some(job) = self.jobs.get_mut(&id) else {
+            return Ok(());
+        };
+        job.state = state;
+        job.updated_at = now_secs();
+        let job = job.clone();
+        self.put_job(&job)
+    }
+
+    /// This function records the progress of a job run outside the db
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - A u64 that holds the job id
+    /// * `task` - A JobTask that holds the progress
+    /// * `state` - A JobState that holds the state of the job
+    /// * `error` - An Option<String> that holds why the job failed
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn update_job(
+        &mut self,
+        id: u64,
+        task: JobTask,
+        state: JobState,
+        error: Option<String>,
+    ) -> Result<(), AggError> {
+        if self.read_only {
+            return Err(AggError::ReadOnly);
+        }
+        let Some(job) = self.jobs.get_mut(&id) else {
+            return Ok(());
+        };
+        if matches!(job.state, JobState::Finished | JobState::Cancelled) {
+            return Ok(());
+        }
+        job.progress = task.progress();
+        job.task = task;
+        job.state = state;
+        job.error = error;
+        job.updated_at = now_secs();
+        let job = job.clone();
+        self.put_job(&job)
+    }
+
+    /// This function advances every running job of the db by one batch and persists their
+    /// progress, stopping the ticks once no job is left running
+    fn run_job_batches(&mut self) {
+        let running: Vec<u64> = self
+            .jobs
+            .values()
+            .filter(|job| Self::is_run_by_db(job))
+            .map(|job| job.id)
+            .collect();
+        if running.is_empty() {
+            self.job_interval = None;
+            return;
+        }
+        for id in running {
+            let Some(mut job) = self.jobs.get(&id).cloned() else {
+                continue;
+            };
+            let result = match &mut job.task {
+                JobTask::Reindex(progress) => {
+                    self.run_reparse_batch(progress);
+                    Ok(progress.finished)
+                }
+                JobTask::Prune(progress) => self.run_prune_batch(progress),
+                JobTask::MigrateTxIndex(progress) => self.run_tx_index_migration_batch(progress),
+                JobTask::MigrateLayout(progress) => self.run_layout_migration_batch(progress),
+                JobTask::Export(_) | JobTask::Backfill(_) => continue,
+            };
+            match result {
+                Ok(true) => {
+                    info!(target: "db", "Finished job {} {:?}", id, job.task);
+                    job.state = JobState::Finished;
+                }
+                Ok(false) => {}
+                Err(err) => {
+                    error!(target: "db", "Job {} failed {}", id, err);
+                    job.state = JobState::Failed;
+                    job.error = Some(err.to_string());
+                }
+            }
+            job.progress = job.task.progress();
+            job.updated_at = now_secs();
+            if let Err(err) = self.put_job(&job) {
+                error!(target: "db", "Error persisting job {} {}", id, err);
+            }
+            self.jobs.insert(id, job);
+        }
+    }
+
+    /// This function re-parses the next batch of blocks of a re-index job
+    ///
+    /// # Arguments
+    ///
+    /// * `progress` - A ReparseProgress that holds the progress of the job
+    fn run_reparse_batch(&self, progress: &mut ReparseProgress) {
+        let batch_end = progress.end.min(
+            progress
+                .next_block_no
+                .saturating_add(REPARSE_BATCH_SIZE - 1),
+        );
+        for block_no in progress.next_block_no..=batch_end {
+            match self.reparse_block(block_no) {
+                Ok(Some(upgraded)) => {
+                    progress.upgraded_blocks += 1;
+                    progress.upgraded_transactions += upgraded;
+                }
+                Ok(None) => progress.missing_blocks += 1,
+                Err(err) => {
+                    progress.failed_blocks += 1;
+                    error!(target: "db", "Error re-parsing block {} {}", block_no, err);
+                }
+            }
+        }
+        progress.next_block_no = batch_end.saturating_add(1);
+        progress.finished = batch_end >= progress.end;
+    }
+
+    /// This function moves the next batch of legacy transaction index entries to the raw key
+    /// index. Moved entries are deleted, so every batch starts at the first legacy entry left.
+    ///
+    /// # Arguments
+    ///
+    /// * `progress` - A TxIndexMigration that holds the progress of the job
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, AggError>` - A Result that holds whether the job is done or an error
+    fn run_tx_index_migration_batch(
+        &self,
+        progress: &mut TxIndexMigration,
+    ) -> Result<bool, AggError> {
+        let cf = self.cf(TX_INDEX_CF)?;
+        let mut batch = WriteBatch::default();
+        // Locations of the transactions of every block the batch touches, empty for missing blocks
+        let mut blocks: BTreeMap<u64, BTreeMap<String, TxLocation>> = BTreeMap::new();
+        let mut scanned = 0;
+        for entry in self
+            .db
+            .iterator(IteratorMode::From(LEGACY_TX_KEY_PREFIX, Direction::Forward))
+            .take(TX_INDEX_MIGRATION_BATCH_SIZE)
+        {
+            let (key, block_no) = entry?;
+            if !key.starts_with(LEGACY_TX_KEY_PREFIX) {
+                break;
+            }
+            scanned += 1;
+            batch.delete(&key);
+            let tx_id = from_slice::<String>(&key)?;
+            let block_no = from_slice::<u64>(&block_no)?;
+            let locations = blocks.entry(block_no).or_insert_with(|| {
+                let Some(block) = self.get_block(block_no) else {
+                    return BTreeMap::new();
+                };
+                let slot = block.slot().unwrap_or(block_no);
+                block
+                    .tx_offsets()
+                    .into_iter()
+                    .map(|(tx_id, offset)| {
+                        let location = TxLocation {
+                            slot,
+                            block_no,
+                            offset,
+                        };
+                        (tx_id, location)
+                    })
+                    .collect()
+            });
+            let location = locations.get(&tx_id);
+            match (location, bs58::decode(&tx_id).into_vec()) {
+                (Some(location), Ok(raw_tx_id)) => {
+                    batch.put_cf(cf, raw_tx_id, location.to_bytes());
+                    progress.migrated += 1;
+                }
+                _ => progress.orphaned += 1,
+            }
+        }
+        self.db.write_opt(batch, &self.write_options)?;
+        progress.finished = scanned < TX_INDEX_MIGRATION_BATCH_SIZE;
+        Ok(progress.finished)
+    }
+
+    /// This function moves the next batch of legacy blocks, then of legacy first sightings, to
+    /// their column families. Moved entries are deleted, so every batch starts at the first legacy
+    /// entry left.
+    ///
+    /// # Arguments
+    ///
+    /// * `progress` - A LayoutMigration that holds the progress of the job
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, AggError>` - A Result that holds whether the job is done or an error
+    fn run_layout_migration_batch(&self, progress: &mut LayoutMigration) -> Result<bool, AggError> {
+        let mut batch = WriteBatch::default();
+        let mut scanned = 0;
+        for (prefix, name) in [
+            (LEGACY_BLOCK_PREFIX, BLOCKS_CF),
+            (LEGACY_FIRST_SEEN_PREFIX, ACCOUNTS_CF),
+        ] {
+            let cf = self.cf(name)?;
+            for entry in self
+                .db
+                .iterator(IteratorMode::From(prefix.as_bytes(), Direction::Forward))
+                .take(LAYOUT_MIGRATION_BATCH_SIZE - scanned)
+            {
+                let (key, value) = entry?;
+                if !key.starts_with(prefix.as_bytes()) {
+                    break;
+                }
+                scanned += 1;
+                batch.delete(&key);
+                let Some(migrated_key) = migrated_key(prefix, &key[prefix.len()..]) else {
+                    progress.skipped += 1;
+                    continue;
+                };
+                // Entries written since the migration started are newer than their legacy copy
+                if self.db.get_cf(cf, &migrated_key)?.is_none() {
+                    batch.put_cf(cf, migrated_key, value);
+                }
+                progress.migrated += 1;
+            }
+        }
+        self.db.write_opt(batch, &self.write_options)?;
+        progress.finished = scanned < LAYOUT_MIGRATION_BATCH_SIZE;
+        Ok(progress.finished)
+    }
+
+    /// This function deletes the next batch of slots of a prune job
+    ///
+    /// # Arguments
+    ///
+    /// * `progress` - A PruneProgress that holds the progress of the job
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, AggError>` - A Result that holds whether the whole range is deleted or
+    ///   an error
+    fn run_prune_batch(&mut self, progress: &mut PruneProgress) -> Result<bool, AggError> {
+        let batch_end = progress
+            .end_slot
+            .min(progress.next_slot.saturating_add(PRUNE_BATCH_SIZE - 1));
+        let deleted = self.delete_slot_range(progress.next_slot, batch_end)?;
+        progress.deleted_blocks += deleted.block_nos.len() as u64;
+        progress.deleted_transactions += deleted.transactions;
+        progress.next_slot = batch_end.saturating_add(1);
+        Ok(batch_end >= progress.end_slot)
+    }
+
+    /// This function re-parses a block from its archived raw block and upgrades the stored
+    /// transaction records in place
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<u64>, AggError>` - A Result that holds the number of upgraded
+    ///   transactions, None if the block or its raw block is not stored, or an error
+    fn reparse_block(&self, block_no: u64) -> Result<Option<u64>, AggError> {
+        let Some(raw_block) = self
+            .db
+            .get_cf(self.cf(RAW_BLOCKS_CF)?, block_no.to_be_bytes())?
+        else {
+            return Ok(None);
+        };
+        let Some(mut block) = self.get_block(block_no) else {
+            return Ok(None);
+        };
+        let raw_block = codec::decode::<RawBlock>(&raw_block)?;
+        let reparsed = Parser::parse_chunk(raw_block.header, &raw_block.transactions)?;
+        let offsets = block.tx_offsets();
+        let upgraded = block.upgrade_transactions(reparsed);
+        self.add_block(block_no, &block)?;
+        self.add_account_transactions(block_no, &block)?;
+        // A block stored before its order was kept is put in the order of the node
+        if block.tx_offsets() != offsets {
+            self.reindex_tx_offsets(block_no, &block)?;
+        }
+        Ok(Some(upgraded))
+    }
+
+    /// This function points the transaction index entries and the conflicts of a block at the
+    /// positions of its transactions after they were put in another order
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number
+    /// * `block` - A reference to the Block in its new order
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn reindex_tx_offsets(&self, block_no: u64, block: &Block) -> Result<(), AggError> {
+        let cf = self.cf(TX_INDEX_CF)?;
+        let offsets = raw_tx_offsets(block);
+        let indexed = self.db.batched_multi_get_cf(
+            cf,
+            offsets.iter().map(|(_, raw_tx_id, _)| raw_tx_id),
+            false,
+        );
+        let conflicts = self.db.multi_get(
+            offsets
+                .iter()
+                .map(|(tx_id, _, _)| Self::tx_conflict_key(tx_id)),
+        );
+        let mut batch = WriteBatch::default();
+        for (((tx_id, raw_tx_id, offset), indexed), conflict) in
+            offsets.into_iter().zip(indexed).zip(conflicts)
+        {
+            // The index of a transaction in two blocks may point at the other block
+            if let Some(mut location) =
+                indexed?.and_then(|bytes| TxLocation::from_bytes(&bytes).ok())
+            {
+                if location.block_no == block_no && location.offset != offset {
+                    location.offset = offset;
+                    batch.put_cf(cf, &raw_tx_id, location.to_bytes());
+                }
+            }
+            if let Some(conflict) = conflict? {
+                let mut conflict = from_slice::<TxConflict>(&conflict)?;
+                for location in [&mut conflict.kept, &mut conflict.other] {
+                    if location.block_no == block_no {
+                        location.offset = offset;
+                    }
+                }
+                batch.put(Self::tx_conflict_key(&tx_id), to_vec(&conflict)?);
+            }
+        }
+        self.db.write_opt(batch, &self.write_options)?;
+        Ok(())
+    }
+
+    /// This function handles the account transactions request, listing the transactions
+    /// involving an account from the newest to the oldest
+    ///
+    /// # Arguments
+    ///
+    /// * `pubkey` - A String that holds the public key
+    /// * `cursor` - An Option<TxCursor> that holds the position after which the page starts
+    /// * `limit` - A u64 that holds the maximum number of transactions returned
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the response or an error
+    fn handle_account_transactions_request(
+        &self,
+        pubkey: String,
+        cursor: Option<TxCursor>,
+        limit: u64,
+    ) -> Result<Response, AggError> {
+        let pubkey = Pubkey::from_str(&pubkey)?;
+        let from = match &cursor {
+            Some(cursor) => account_tx_key(&pubkey, cursor.slot, &cursor.tx_id),
+            None => account_tx_key(&pubkey, u64::MAX, &[u8::MAX; 64]),
+        };
+        let iterator = self.db.iterator_cf(
+            self.cf(ACCOUNT_TXS_CF)?,
+            IteratorMode::From(&from, Direction::Reverse),
+        );
+        let mut page = AccountTransactions::default();
+        let mut last_cursor: Option<TxCursor> = None;
+        let mut block: Option<(u64, Block)> = None;
+        for entry in iterator {
+            let (key, value) = entry?;
+            if cursor.is_some() && *key == *from {
+                continue;
+            }
+            let Some(position) = key.strip_prefix(pubkey.as_ref()) else {
+                break;
+            };
+            let (slot, tx_id) = position.split_at(8);
+            let cursor = TxCursor {
+                slot: u64::from_be_bytes(slot.try_into()?),
+                tx_id: tx_id.to_vec(),
+            };
+            if page.transactions.len() as u64 >= limit {
+                // There is at least one more transaction, the next page starts after this one
+                page.next_cursor = last_cursor.as_ref().map(TxCursor::encode);
+                break;
+            }
+            let tx_id = bs58::encode(&cursor.tx_id).into_string();
+            let (block_no, direction) = match from_slice::<AccountTxEntry>(&value)? {
+                AccountTxEntry::Indexed {
+                    block_no,
+                    direction,
+                } => (block_no, direction),
+                // Entries indexed before directions were take it from their block
+                AccountTxEntry::Legacy(block_no) => {
+                    if block.as_ref().map(|(cached, _)| *cached) != Some(block_no) {
+                        block = self.get_block(block_no).map(|block| (block_no, block));
+                    }
+                    let direction = block
+                        .as_ref()
+                        .and_then(|(_, block)| block.get_tx_details(&tx_id))
+                        .map(|tx| tx.direction(&pubkey.to_string()))
+                        .unwrap_or_default();
+                    (block_no, direction)
+                }
+            };
+            page.transactions.push(AccountTransaction {
+                slot: cursor.slot,
+                block_no,
+                tx_id,
+                direction,
+            });
+            last_cursor = Some(cursor);
+        }
+        Ok(Response::AccountTransactions(page))
+    }
+
+    /// This function handles the latest block summaries request
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - A u64 that holds the number of summaries to return
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the response or an error
+    fn handle_latest_block_summaries_request(&self, limit: u64) -> Result<Response, AggError> {
+        let snapshot = self.db.snapshot();
+        let latest_block_no = self
+            .snapshot_latest_block(&snapshot)?
+            .ok_or(AggError::NoBlockFinalised)?;
+        let from = latest_block_no.to_be_bytes();
+        let mut summaries = vec![];
+        for entry in snapshot
+            .iterator_cf(
+                self.cf(BLOCK_SUMMARY_CF)?,
+                IteratorMode::From(&from, Direction::Reverse),
+            )
+            .take(limit as usize)
+        {
+            let (_, summary) = entry?;
+            summaries.push(from_slice::<BlockSummary>(&summary)?);
+        }
+        Ok(Response::BlockSummaries(summaries))
+    }
+
+    /// This function handles the custom stats request
+    ///
+    /// # Arguments
+    ///
+    /// * `rule` - A String that holds the rule name
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the response or an error
+    fn handle_custom_stats_request(&self, rule: String) -> Result<Response, AggError> {
+        if !self.rule_engine.has_rule(&rule) {
+            return Err(AggError::RuleNotFound);
+        }
+        let prefix = format!("{}{}/", CUSTOM_STAT_PREFIX, rule);
+        let mut stats = BTreeMap::new();
+        let iterator = self
+            .db
+            .iterator(IteratorMode::From(prefix.as_bytes(), Direction::Forward));
+        for entry in iterator {
+            let (key, value) = entry?;
+            let Some(bucket) = key.strip_prefix(prefix.as_bytes()) else {
+                break;
+            };
+            stats.insert(
+                String::from_utf8_lossy(bucket).to_string(),
+                from_slice::<f64>(&value)?,
+            );
+        }
+        Ok(Response::CustomStats(stats))
+    }
+
+    /// This function adds the contribution of a block to the custom stats, unless it was added
+    /// before. The stats are written in one batch with the mark of the block, so a block stored
+    /// again by a retried write, a resumed refetch or a redelivery is counted once.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number
+    /// * `block` - A Block that holds the block
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn add_custom_stats(&self, block_no: u64, block: &Block) -> Result<(), AggError> {
+        let counted_key = Self::custom_stat_block_key(block_no);
+        if self.db.get_pinned(&counted_key)?.is_some() {
+            return Ok(());
+        }
+        let mut custom_stats: BTreeMap<String, f64> = BTreeMap::new();
+        for (rule, bucket, value) in self.rule_engine.evaluate(block) {
+            *custom_stats
+                .entry(format!("{}{}/{}", CUSTOM_STAT_PREFIX, rule, bucket))
+                .or_default() += value;
+        }
+        let mut batch = WriteBatch::default();
+        for (key, value) in custom_stats {
+            let current = match self.db.get(&key)? {
+                Some(current) => from_slice::<f64>(&current)?,
+                None => 0.0,
+            };
+            batch.put(key, to_vec(&(current + value))?);
+        }
+        batch.put(counted_key, []);
+        self.db.write_opt(batch, &self.write_options)?;
+        Ok(())
+    }
+
+    /// This function builds the key marking a block as counted in the custom stats
+    fn custom_stat_block_key(block_no: u64) -> String {
+        format!("{}{}", CUSTOM_STAT_BLOCK_PREFIX, block_no)
+    }
+
+    /// This function handles the active accounts request
+    ///
+    /// # Arguments
+    ///
+    /// * `days` - A u64 that holds the number of most recent days to return
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the response or an error
+    fn handle_active_accounts_request(&self, days: u64) -> Result<Response, AggError> {
+        // Days sort as `YYYY-MM-DD`, the last key is the latest day
+        let mut active_days = self.unsettled_active_accounts()?;
+        for entry in self
+            .db
+            .iterator_cf(self.cf(ACTIVE_ACCOUNTS_CF)?, IteratorMode::End)
+            .take(days as usize)
+        {
+            let (day, value) = entry?;
+            let day = String::from_utf8_lossy(&day).to_string();
+            active_days
+                .entry(day)
+                .or_default()
+                .merge(&from_slice::<ActiveAccountsDay>(&value)?);
+        }
+        let skip = active_days.len().saturating_sub(days as usize);
+        let stats = active_days
+            .into_iter()
+            .skip(skip)
+            .map(|(day, active)| active.stats(day))
+            .collect();
+        Ok(Response::ActiveAccounts(stats))
+    }
+
+    /// This function reads the active accounts of the blocks not settled yet, by day
+    ///
+    /// # Returns
+    ///
+    /// * `Result<BTreeMap<String, ActiveAccountsDay>, AggError>` - A Result that holds the active
+    ///   accounts by day or an error
+    fn unsettled_active_accounts(&self) -> Result<BTreeMap<String, ActiveAccountsDay>, AggError> {
+        let mut active_days: BTreeMap<String, ActiveAccountsDay> = BTreeMap::new();
+        let from = self.active_accounts_settled.saturating_add(1).to_be_bytes();
+        for entry in self.db.iterator_cf(
+            self.cf(BLOCKS_CF)?,
+            IteratorMode::From(&from, Direction::Forward),
+        ) {
+            let (_, value) = entry?;
+            let block = codec::decode::<Block>(&value)?;
+            if let Some(day) = block.day() {
+                active_days.entry(day).or_default().add_block(&block);
+            }
+        }
+        Ok(active_days)
+    }
+
+    /// This function adds the fee payers and accounts of a block to the active accounts of its
+    /// day
+    ///
+    /// # Arguments
+    ///
+    /// * `block` - A Block that holds the block
+    /// * `stored_before` - A bool that holds whether the block was stored and counted before, its
+    ///   accounts are added again without counting it twice
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn add_active_accounts(&self, block: &Block, stored_before: bool) -> Result<(), AggError> {
+        let Some(day) = block.day() else {
+            return Ok(());
+        };
+        let cf = self.cf(ACTIVE_ACCOUNTS_CF)?;
+        let mut active = match self.db.get_cf(cf, &day)? {
+            Some(active) => from_slice::<ActiveAccountsDay>(&active)?,
+            None => ActiveAccountsDay::default(),
+        };
+        if stored_before {
+            active.add_accounts(block);
+        } else {
+            active.add_block(block);
+        }
+        self.db
+            .put_cf_opt(cf, day, to_vec(&active)?, &self.write_options)?;
+        Ok(())
+    }
+
+    /// This function merges the blocks that can no longer be rolled back by a reorg into the
+    /// active accounts of their day. With confirmed blocks the last `reorg.max_depth` blocks are
+    /// left out, so rolling them back leaves nothing to revert.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number of the block just stored
+    /// * `block` - A reference to the Block just stored
+    /// * `stored_before` - A bool that holds whether a block was stored at the block number before
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn settle_active_accounts(
+        &mut self,
+        block_no: u64,
+        block: &Block,
+        stored_before: bool,
+    ) -> Result<(), AggError> {
+        // The block arrived, or was delivered again, after the blocks around it were settled
+        if block_no <= self.active_accounts_settled {
+            return self.add_active_accounts(block, stored_before);
+        }
+        let settled = block_no.saturating_sub(self.reorg_depth());
+        if settled <= self.active_accounts_settled {
+            return Ok(());
+        }
+        if settled == block_no && self.active_accounts_settled + 1 == block_no {
+            // Usually only the block just stored is settled, it is not read back
+            self.add_active_accounts(block, false)?;
+        } else {
+            let from = self.active_accounts_settled.saturating_add(1).to_be_bytes();
+            let mut blocks = vec![];
+            for entry in self.db.iterator_cf(
+                self.cf(BLOCKS_CF)?,
+                IteratorMode::From(&from, Direction::Forward),
+            ) {
+                let (key, value) = entry?;
+                if u64::from_be_bytes(key.as_ref().try_into()?) > settled {
+                    break;
+                }
+                blocks.push(codec::decode::<Block>(&value)?);
+            }
+            for block in blocks.iter() {
+                self.add_active_accounts(block, false)?;
+            }
+        }
+        self.active_accounts_settled = settled;
+        self.put_cf(META_CF, ACTIVE_ACCOUNTS_SETTLED_KEY, to_vec(&settled)?)
+    }
+
+    /// This function handles the account summary request
+    ///
+    /// # Arguments
+    ///
+    /// * `pubkey` - A String that holds the public key
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the response or an error
+    fn handle_account_summary_request(&self, pubkey: String) -> Result<Response, AggError> {
+        let snapshot = self.db.snapshot();
+        let balance = match self.snapshot_latest_block(&snapshot)? {
+            Some(block_no) => self.snapshot_account_balance(&snapshot, &pubkey, block_no)?,
+            None => None,
+        };
+        let first_seen =
+            match snapshot.get_cf(self.cf(ACCOUNTS_CF)?, Pubkey::from_str(&pubkey)?.to_bytes())? {
+                Some(first_seen) => Some(from_slice::<FirstSeen>(&first_seen)?),
+                None => match snapshot.get(format!("{}{}", LEGACY_FIRST_SEEN_PREFIX, pubkey))? {
+                    Some(first_seen) => Some(from_slice::<FirstSeen>(&first_seen)?),
+                    None => None,
+                },
+            };
+        let authority_history = self.snapshot_authority_history(
+            &snapshot,
+            &Pubkey::from_str(&pubkey)?,
+            AUTHORITY_SUMMARY_LIMIT,
+        )?;
+        Ok(Response::AccountSummary(AccountSummary {
+            account: pubkey,
+            balance,
+            first_seen,
+            authority_history,
+        }))
+    }
+
+    /// This function reads the latest blocks and the summaries of the accounts most involved in
+    /// their transactions, pulling what the first queries after a restart read into the block
+    /// cache and the page cache. What cannot be read is skipped, a cold cache only slows the first
+    /// queries down.
+    ///
+    /// # Returns
+    ///
+    /// * `WarmupReport` - The blocks and summaries read
+    pub fn warm_up(&self) -> WarmupReport {
+        let mut report = WarmupReport::default();
+        let Some(latest) = self.get_latest_block() else {
+            return report;
+        };
+        let mut counts: HashMap<String, u64> = HashMap::new();
+        let first = latest.saturating_sub(self.warmup.blocks - 1);
+        for block_no in (first..=latest).rev() {
+            let Some(block) = self.get_block(block_no) else {
+                continue;
+            };
+            report.blocks += 1;
+            for (_, tx) in block.transactions() {
+                for party in tx.parties().collect::<BTreeSet<_>>() {
+                    *counts.entry(party.to_string()).or_default() += 1;
+                }
+            }
+        }
+        for account in warmup::hottest_accounts(counts, self.warmup.accounts) {
+            // Parties that are not valid keys have no summary to read
+            if self.handle_account_summary_request(account).is_ok() {
+                report.accounts += 1;
+            }
+        }
+        report
+    }
+
+    /// This function records the first block and transaction each account of a block was seen in
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number
+    /// * `block` - A Block that holds the block
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn add_first_seen(&self, block_no: u64, block: &Block) -> Result<(), AggError> {
+        for (account, tx_hash) in block.get_first_seen() {
+            let pubkey = Pubkey::from_str(account)?;
+            // Blocks can be finalised out of order, so an earlier block may still replace the record
+            if let Some(existing) = self.get_first_seen(&pubkey)? {
+                if existing.block_no <= block_no {
+                    continue;
+                }
+            }
+            let first_seen = FirstSeen {
+                block_no,
+                slot: block.slot(),
+                tx_hash: tx_hash.clone(),
+            };
+            self.put_cf(ACCOUNTS_CF, pubkey.to_bytes(), to_vec(&first_seen)?)?;
+        }
+        Ok(())
+    }
+
+    /// This function reads the first sighting of an account, falling back to the default column
+    /// family for records the layout migration has not moved yet
+    ///
+    /// # Arguments
+    ///
+    /// * `pubkey` - A Pubkey that holds the account
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<FirstSeen>, AggError>` - A Result that holds the record or an error
+    fn get_first_seen(&self, pubkey: &Pubkey) -> Result<Option<FirstSeen>, AggError> {
+        let first_seen = match self.db.get_cf(self.cf(ACCOUNTS_CF)?, pubkey.to_bytes())? {
+            Some(first_seen) => Some(first_seen),
+            None => self
+                .db
+                .get(format!("{}{}", LEGACY_FIRST_SEEN_PREFIX, pubkey))?,
+        };
+        match first_seen {
+            Some(first_seen) => Ok(Some(from_slice::<FirstSeen>(&first_seen)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// This function handles the token holders request
+    ///
+    /// # Arguments
+    ///
+    /// * `mint` - A String that holds the mint address
+    /// * `block_no` - An Option<u64> that holds the block number, the latest block if None
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the response or an error
+    fn handle_token_holders_request(
+        &self,
+        mint: String,
+        block_no: Option<u64>,
+    ) -> Result<Response, AggError> {
+        let snapshot = self.db.snapshot();
+        let block_no = match block_no {
+            Some(block_no) => block_no,
+            None => self
+                .snapshot_latest_block(&snapshot)?
+                .ok_or(AggError::NoBlockFinalised)?,
+        };
+        let prefix = format!("{}{}/", TOKEN_BALANCE_PREFIX, mint);
+        let mut budget = QueryBudget::new(&self.query_limits);
+        let mut balances: BTreeMap<String, TokenBalance> = BTreeMap::new();
+        let iterator = snapshot.iterator(IteratorMode::From(prefix.as_bytes(), Direction::Forward));
+        for entry in iterator {
+            let (key, value) = entry?;
+            let Some(suffix) = key.strip_prefix(prefix.as_bytes()) else {
+                break;
+            };
+            budget.scan()?;
+            let suffix = String::from_utf8_lossy(suffix);
+            let Some((account, entry_block_no)) = suffix.rsplit_once('/') else {
+                continue;
+            };
+            // Entries of an account are ordered by block number, so the last one
+            // at or before the requested block is its balance as of that block
+            if entry_block_no
+                .parse::<u64>()
+                .is_ok_and(|entry| entry <= block_no)
+            {
+                if !balances.contains_key(account) {
+                    budget.produce(value.len())?;
+                }
+                balances.insert(account.to_string(), from_slice(&value)?);
+            }
+        }
+        let mut holders: Vec<TokenHolder> = balances
+            .into_iter()
+            .filter(|(_, balance)| balance.amount > 0)
+            .map(|(account, balance)| TokenHolder {
+                account,
+                owner: balance.owner,
+                amount: balance.amount,
+                decimals: balance.decimals,
+            })
+            .collect();
+        holders.sort_by_key(|holder| std::cmp::Reverse(holder.amount));
+        Ok(Response::TokenHolders(holders))
+    }
+
+    /// This function adds the token balance history entries of a block
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number
+    /// * `block` - A Block that holds the block
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn add_token_balances(&self, block_no: u64, block: &Block) -> Result<(), AggError> {
+        for (token_account, balance) in block.get_token_balances() {
+            self.put(
+                format!(
+                    "{}{}/{}/{:020}",
+                    TOKEN_BALANCE_PREFIX, balance.mint, token_account, block_no
+                ),
+                to_vec(balance)?,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// This function indexes the token balances of a block under the owners of the token
+    /// accounts. A block stored after a later block that changed the same token account leaves
+    /// the later balance in place.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number
+    /// * `block` - A Block that holds the block
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn add_owner_token_balances(&self, block_no: u64, block: &Block) -> Result<(), AggError> {
+        let mut batch = WriteBatch::default();
+        for (token_account, balance) in block.get_token_balances() {
+            let Ok(account) = Pubkey::from_str(token_account) else {
+                continue;
+            };
+            if self
+                .token_account_block_no(&account)?
+                .is_some_and(|latest| latest > block_no)
+            {
+                continue;
+            }
+            self.index_token_account(&mut batch, &account, Some((block_no, balance)))?;
+        }
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.db.write_opt(batch, &self.write_options)?;
+        Ok(())
+    }
+
+    /// This function reads the block the latest balance of a token account was indexed from,
+    /// including the block that emptied or closed it
+    ///
+    /// # Arguments
+    ///
+    /// * `account` - A Pubkey that holds the token account
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<u64>, AggError>` - A Result that holds the block number, None when no
+    ///   balance of the account was indexed, or an error
+    fn token_account_block_no(&self, account: &Pubkey) -> Result<Option<u64>, AggError> {
+        let Some(owner) = self
+            .db
+            .get_cf(self.cf(TOKEN_ACCOUNT_OWNERS_CF)?, account.as_ref())?
+        else {
+            return Ok(None);
+        };
+        if let Ok(block_no) = <[u8; EMPTIED_TOKEN_ACCOUNT_BYTES]>::try_from(owner.as_slice()) {
+            return Ok(Some(u64::from_be_bytes(block_no)));
+        }
+        Ok(self
+            .token_account_entry(account)?
+            .map(|(_, entry)| entry.block_no))
+    }
+
+    /// This function reads the latest balance of a token account indexed under its owner
+    ///
+    /// # Arguments
+    ///
+    /// * `account` - A Pubkey that holds the token account
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<(Vec<u8>, TokenAccountBalance)>, AggError>` - A Result that holds the key
+    ///   and the balance, None when the account holds no tokens, or an error
+    fn token_account_entry(
+        &self,
+        account: &Pubkey,
+    ) -> Result<Option<(Vec<u8>, TokenAccountBalance)>, AggError> {
+        let Some(mut key) = self
+            .db
+            .get_cf(self.cf(TOKEN_ACCOUNT_OWNERS_CF)?, account.as_ref())?
+        else {
+            return Ok(None);
+        };
+        if key.len() == EMPTIED_TOKEN_ACCOUNT_BYTES {
+            return Ok(None);
+        }
+        key.extend_from_slice(account.as_ref());
+        match self.db.get_cf(self.cf(OWNER_TOKEN_BALANCES_CF)?, &key)? {
+            Some(value) => Ok(Some((key, from_slice(&value)?))),
+            None => Ok(None),
+        }
+    }
+
+    /// This function replaces the balance of a token account indexed under its owner. An
+    /// account left empty, closed or without a known owner is removed from the index, leaving the
+    /// block that emptied it so the balances of older blocks stored later are not indexed.
+    ///
+    /// # Arguments
+    ///
+    /// * `batch` - A WriteBatch that receives the changes
+    /// * `account` - A Pubkey that holds the token account
+    /// * `balance` - An Option<(u64, &TokenBalance)> that holds the block number and the latest
+    ///   balance of the account, None when no stored block holds one
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn index_token_account(
+        &self,
+        batch: &mut WriteBatch,
+        account: &Pubkey,
+        balance: Option<(u64, &TokenBalance)>,
+    ) -> Result<(), AggError> {
+        if let Some((key, _)) = self.token_account_entry(account)? {
+            batch.delete_cf(self.cf(OWNER_TOKEN_BALANCES_CF)?, key);
+        }
+        let held = balance.and_then(|(block_no, balance)| {
+            let owner = Pubkey::from_str(balance.owner.as_deref()?).ok()?;
+            let mint = Pubkey::from_str(&balance.mint).ok()?;
+            (balance.amount > 0).then_some((owner_token_key(&owner, &mint), block_no, balance))
+        });
+        let Some((owner_key, block_no, balance)) = held else {
+            match balance {
+                Some((block_no, _)) => batch.put_cf(
+                    self.cf(TOKEN_ACCOUNT_OWNERS_CF)?,
+                    account.as_ref(),
+                    block_no.to_be_bytes(),
+                ),
+                None => batch.delete_cf(self.cf(TOKEN_ACCOUNT_OWNERS_CF)?, account.as_ref()),
+            }
+            return Ok(());
+        };
+        let mut key = owner_key.clone();
+        key.extend_from_slice(account.as_ref());
+        batch.put_cf(
+            self.cf(OWNER_TOKEN_BALANCES_CF)?,
+            key,
+            to_vec(&TokenAccountBalance {
+                account: account.to_string(),
+                amount: balance.amount,
+                decimals: balance.decimals,
+                block_no,
+            })?,
+        );
+        batch.put_cf(
+            self.cf(TOKEN_ACCOUNT_OWNERS_CF)?,
+            account.as_ref(),
+            owner_key,
+        );
+        Ok(())
+    }
+
+    /// This function reads the latest balance history entry of a token account outside of the
+    /// given blocks
+    ///
+    /// # Arguments
+    ///
+    /// * `mint` - A string slice that holds the mint of the account
+    /// * `token_account` - A string slice that holds the token account
+    /// * `excluded` - A slice of u64 that holds the block numbers being deleted
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<(u64, TokenBalance)>, AggError>` - A Result that holds the block number
+    ///   and the balance, None without any entry, or an error
+    fn latest_token_balance(
+        &self,
+        mint: &str,
+        token_account: &str,
+        excluded: &[u64],
+    ) -> Result<Option<(u64, TokenBalance)>, AggError> {
+        let prefix = format!("{}{}/{}/", TOKEN_BALANCE_PREFIX, mint, token_account);
+        let from = format!("{}{:020}", prefix, u64::MAX);
+        let iterator = self
+            .db
+            .iterator(IteratorMode::From(from.as_bytes(), Direction::Reverse));
+        for entry in iterator {
+            let (key, value) = entry?;
+            let Some(block_no) = key.strip_prefix(prefix.as_bytes()) else {
+                break;
+            };
+            let Ok(block_no) = String::from_utf8_lossy(block_no).parse::<u64>() else {
+                continue;
+            };
+            if !excluded.contains(&block_no) {
+                return Ok(Some((block_no, from_slice(&value)?)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// This function reads the token accounts indexed under a prefix of the owner token
+    /// balances from a snapshot
+    ///
+    /// # Arguments
+    ///
+    /// * `snapshot` - A Snapshot that holds the consistent view of the db
+    /// * `prefix` - A byte slice that holds the owner, optionally followed by the mint
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<(Pubkey, TokenAccountBalance)>, AggError>` - A Result that holds the mint
+    ///   and the balance of every token account, or an error
+    fn snapshot_owner_token_accounts(
+        &self,
+        snapshot: &Snapshot,
+        prefix: &[u8],
+    ) -> Result<Vec<(Pubkey, TokenAccountBalance)>, AggError> {
+        let mut budget = QueryBudget::new(&self.query_limits);
+        let iterator = snapshot.iterator_cf(
+            self.cf(OWNER_TOKEN_BALANCES_CF)?,
+            IteratorMode::From(prefix, Direction::Forward),
+        );
+        let mut accounts = vec![];
+        for entry in iterator {
+            let (key, value) = entry?;
+            if !key.starts_with(prefix) {
+                break;
+            }
+            budget.scan()?;
+            budget.produce(value.len())?;
+            let mint = Pubkey::try_from(&key[32..64])?;
+            accounts.push((mint, from_slice(&value)?));
+        }
+        Ok(accounts)
+    }
+
+    /// This function handles the token balance request of an owner in a mint
+    ///
+    /// # Arguments
+    ///
+    /// * `owner` - A String that holds the owner address
+    /// * `mint` - A String that holds the mint address
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the response or an error
+    fn handle_token_balance_request(
+        &self,
+        owner: String,
+        mint: String,
+    ) -> Result<Response, AggError> {
+        let owner = Pubkey::from_str(&owner)?;
+        let mint = Pubkey::from_str(&mint)?;
+        let snapshot = self.db.snapshot();
+        let accounts = self
+            .snapshot_owner_token_accounts(&snapshot, &owner_token_key(&owner, &mint))?
+            .into_iter()
+            .map(|(_, account)| account)
+            .collect();
+        Ok(Response::TokenBalance(MintBalance::new(
+            mint.to_string(),
+            accounts,
+        )))
+    }
+
+    /// This function handles the token balances request of an owner
+    ///
+    /// # Arguments
+    ///
+    /// * `owner` - A String that holds the owner address
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the response or an error
+    fn handle_token_balances_request(&self, owner: String) -> Result<Response, AggError> {
+        let owner = Pubkey::from_str(&owner)?;
+        let snapshot = self.db.snapshot();
+        let mut mints: BTreeMap<String, Vec<TokenAccountBalance>> = BTreeMap::new();
+        for (mint, account) in self.snapshot_owner_token_accounts(&snapshot, owner.as_ref())? {
+            mints.entry(mint.to_string()).or_default().push(account);
+        }
+        Ok(Response::TokenBalances(
+            mints
+                .into_iter()
+                .map(|(mint, accounts)| MintBalance::new(mint, accounts))
+                .collect(),
+        ))
+    }
+
+    /// This function handles the block digest request
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the response or an error
+    fn handle_block_digest_request(&self, block_no: u64) -> Result<Response, AggError> {
+        let digest = self
+            .db
+            .get(format!("BlockDigest{}", block_no))?
+            .ok_or(AggError::BlockNotFound)?;
+        Ok(Response::BlockDigest(
+            block_no,
+            bs58::encode(digest).into_string(),
+        ))
+    }
+
+    /// This function registers a block subscriber, replaying stored blocks first. Blocks are
+    /// filtered before they are sent, the ones without a matching transaction are not sent.
+    ///
+    /// The replay runs in batches between the other messages of the db and waits for room in
+    /// the queue of the stream, so no replayed block is dropped. Newly applied blocks are sent
+    /// once the replay caught up with the latest block, a stream whose queue is full then is
+    /// ended.
+    ///
+    /// # Arguments
+    ///
+    /// * `from_block_no` - An Option<u64> that holds the first block number to replay
+    /// * `filter` - A BlockFilter that holds the transactions the subscriber wants
+    /// * `subscriber` - A Sender<ProtocolMessage> that receives the blocks
+    fn handle_block_subscription(
+        &mut self,
+        from_block_no: Option<u64>,
+        filter: BlockFilter,
+        subscriber: Sender<ProtocolMessage>,
+    ) {
+        match from_block_no {
+            Some(next_block_no) => self.block_replays.push_back(BlockReplay {
+                filter,
+                next_block_no,
+                subscriber,
+            }),
+            None => self.block_subscribers.push((filter, subscriber)),
+        }
+    }
+
+    /// This function waits until one of the streams waiting for their replay has room in its
+    /// queue or is closed, so a client that stopped reading does not hold back the others
+    ///
+    /// # Arguments
+    ///
+    /// * `replays` - A reference to the VecDeque of the replays
+    /// * `subscriber` - A function returning the Sender<ProtocolMessage> of a replay
+    ///
+    /// # Returns
+    ///
+    /// * `usize` - The position of the stream among the replays
+    async fn replay_room<R>(
+        replays: &VecDeque<R>,
+        subscriber: impl Fn(&R) -> &Sender<ProtocolMessage>,
+    ) -> usize {
+        let rooms = replays
+            .iter()
+            .map(|replay| Box::pin(subscriber(replay).reserve()));
+        let (_, index, _) = futures_util::future::select_all(rooms).await;
+        index
+    }
+
+    /// This function replays the next batch of stored blocks of a block stream. A stream whose
+    /// replay caught up with the latest block is sent the newly applied blocks from then on, the
+    /// others wait for their next batch.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - A usize that holds the position of the stream among the replays
+    fn replay_block_batch(&mut self, index: usize) {
+        let Some(mut replay) = self.block_replays.remove(index) else {
+            return;
+        };
+        match self.replay_blocks(&mut replay) {
+            Ok(true) => self
+                .block_subscribers
+                .push((replay.filter, replay.subscriber)),
+            Ok(false) => self.block_replays.push_back(replay),
+            Err(error) => Self::handle_error(&replay.subscriber, error),
+        }
+    }
+
+    /// This function sends a batch of the stored blocks of a block stream replay, up to the
+    /// latest block or until the queue of the stream is full
+    ///
+    /// # Arguments
+    ///
+    /// * `replay` - A mutable reference to the BlockReplay, moved past the sent blocks
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, AggError>` - A Result that holds whether the replay caught up with the
+    ///   latest block, or an error
+    fn replay_blocks(&self, replay: &mut BlockReplay) -> Result<bool, AggError> {
+        let snapshot = self.db.snapshot();
+        let Some(latest_block_no) = self.snapshot_latest_block(&snapshot)? else {
+            return Ok(true);
+        };
+        for _ in 0..BLOCK_REPLAY_BATCH_SIZE {
+            if replay.next_block_no > latest_block_no {
+                return Ok(true);
+            }
+            if replay.subscriber.is_closed() {
+                return Err(AggError::OneshotChannelError);
+            }
+            if replay.subscriber.capacity() == 0 {
+                return Ok(false);
+            }
+            let block_no = replay.next_block_no;
+            if let Some(block) = self
+                .snapshot_block(&snapshot, block_no)?
+                .and_then(|block| replay.filter.apply(&block))
+            {
+                replay
+                    .subscriber
+                    .try_send(ProtocolMessage::NewBlock(block_no, block))
+                    .map_err(|_| AggError::OneshotChannelError)?;
+            }
+            replay.next_block_no += 1;
+        }
+        Ok(replay.next_block_no > latest_block_no)
+    }
+
+    /// This function handles the account subscription request, replaying the transactions of
+    /// the account since `from_slot` before streaming the ones of newly applied blocks
+    ///
+    /// The replay runs in batches between the other messages of the db, so a long replay does
+    /// not hold back queries and writes. Only blocks whose state is applied are replayed, the
+    /// stream follows newly applied blocks once the replay caught up with the latest applied
+    /// block, so no transaction is sent twice or skipped at the switch. A stream opened with the
+    /// resume token of an earlier stream of the account replays from the slot of the last event
+    /// sent on it instead.
+    ///
+    /// # Arguments
+    ///
+    /// * `pubkey` - A String that holds the public key
+    /// * `from_slot` - An Option<u64> that holds the first slot to replay
+    /// * `resume_token` - An Option<String> that holds the token the stream position is kept under
+    /// * `subscriber` - A Sender<ProtocolMessage> that receives the events
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn handle_account_subscription(
+        &mut self,
+        pubkey: String,
+        from_slot: Option<u64>,
+        resume_token: Option<String>,
+        subscriber: Sender<ProtocolMessage>,
+    ) -> Result<(), AggError> {
+        let account = Pubkey::from_str(&pubkey)?;
+        let mut from_slot = from_slot;
+        if let Some(token) = resume_token {
+            if let Some(cursor) = self
+                .resume_cursors
+                .get(&token)
+                .filter(|cursor| cursor.account == pubkey)
+            {
+                from_slot = cursor.last_slot.or(from_slot);
+            }
+            if !self.read_only {
+                let cursor = ResumeCursor {
+                    token,
+                    account: pubkey.clone(),
+                    last_slot: from_slot,
+                    expires_at: now_secs() + self.subscriptions.resume_cursor_ttl_secs,
+                };
+                self.put_resume_cursor(cursor)?;
+            }
+        }
+        match from_slot {
+            Some(from_slot) => self.account_replays.push_back(AccountReplay {
+                pubkey,
+                account,
+                from: account_delta_key(&account, from_slot),
+                subscriber,
+            }),
+            None => self.account_subscribers.push((pubkey, subscriber)),
+        }
+        Ok(())
+    }
+
+    /// This function replays the next batch of transactions of an account stream. A stream
+    /// whose replay caught up with the latest applied block is sent the newly applied blocks from
+    /// then on, the others wait for their next batch.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - A usize that holds the position of the stream among the replays
+    fn replay_account_batch(&mut self, index: usize) {
+        let Some(mut replay) = self.account_replays.remove(index) else {
+            return;
+        };
+        match self.replay_account_transactions(&mut replay) {
+            Ok(true) => self
+                .account_subscribers
+                .push((replay.pubkey, replay.subscriber)),
+            Ok(false) => self.account_replays.push_back(replay),
+            Err(error) => Self::handle_error(&replay.subscriber, error),
+        }
+    }
+
+    /// This function sends a batch of the transactions of an account stream replay, up to the
+    /// latest applied block or until the queue of the stream is full
+    ///
+    /// # Arguments
+    ///
+    /// * `replay` - A mutable reference to the AccountReplay, moved past the sent transactions
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, AggError>` - A Result that holds whether the replay caught up with the
+    ///   latest applied block, or an error
+    fn replay_account_transactions(&self, replay: &mut AccountReplay) -> Result<bool, AggError> {
+        let Some(applied_slot) = self
+            .get_latest_block()
+            .and_then(|block_no| self.get_block(block_no))
+            .and_then(|block| block.slot())
+        else {
+            return Ok(true);
+        };
+        let snapshot = self.db.snapshot();
+        let iterator = snapshot.iterator_cf(
+            self.cf(ACCOUNT_TXS_CF)?,
+            IteratorMode::From(&replay.from, Direction::Forward),
+        );
+        let mut block: Option<(u64, Block)> = None;
+        for (replayed, entry) in iterator.enumerate() {
+            let (key, value) = entry?;
+            let Some(position) = key.strip_prefix(replay.account.as_ref()) else {
+                return Ok(true);
+            };
+            if replayed == ACCOUNT_REPLAY_BATCH_SIZE || replay.subscriber.capacity() == 0 {
+                replay.from = key.to_vec();
+                return Ok(false);
+            }
+            let (slot, tx_id) = position.split_at(8);
+            let slot = u64::from_be_bytes(slot.try_into()?);
+            if slot > applied_slot {
+                return Ok(true);
+            }
+            let block_no = from_slice::<AccountTxEntry>(&value)?.block_no();
+            if block.as_ref().map(|(cached, _)| *cached) != Some(block_no) {
+                block = self
+                    .snapshot_block(&snapshot, block_no)?
+                    .map(|block| (block_no, block));
+            }
+            let Some((_, block)) = &block else {
+                continue;
+            };
+            let tx_id = bs58::encode(tx_id).into_string();
+            if let Some(tx) = block.get_tx_details(&tx_id) {
+                let event = AccountEvent {
+                    slot,
+                    block_no,
+                    tx_id,
+                    transaction: tx.clone(),
+                    balance: self.snapshot_account_balance(&snapshot, &replay.pubkey, block_no)?,
+                    replayed: true,
+                };
+                replay
+                    .subscriber
+                    .try_send(ProtocolMessage::AccountEvent(Box::new(event)))
+                    .map_err(|_| AggError::OneshotChannelError)?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// This function moves the resume cursor of a stream to the slot of the last event sent
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - A string slice that holds the resume token of the stream
+    /// * `slot` - A u64 that holds the slot of the event
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn advance_resume_cursor(&mut self, token: &str, slot: u64) -> Result<(), AggError> {
+        let Some(mut cursor) = self.resume_cursors.get(token).cloned() else {
+            return Ok(());
+        };
+        cursor.last_slot = cursor.last_slot.max(Some(slot));
+        cursor.expires_at = now_secs() + self.subscriptions.resume_cursor_ttl_secs;
+        self.put_resume_cursor(cursor)
+    }
+
+    /// This function persists a resume cursor
+    ///
+    /// # Arguments
+    ///
+    /// * `cursor` - A ResumeCursor that holds the stream position
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn put_resume_cursor(&mut self, cursor: ResumeCursor) -> Result<(), AggError> {
+        self.put(
+            format!("{}{}", RESUME_CURSOR_PREFIX, cursor.token),
+            to_vec(&cursor)?,
+        )?;
+        self.resume_cursors.insert(cursor.token.clone(), cursor);
+        Ok(())
+    }
+
+    /// This function handles the create webhook request
+    ///
+    /// # Arguments
+    ///
+    /// * `pubkey` - A String that holds the account whose transactions are delivered
+    /// * `url` - A String that holds the url the transactions are posted to
+    /// * `ttl_secs` - An Option<u64> that holds the lifetime, the configured default when None
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the response or an error
+    fn handle_create_webhook_request(
+        &mut self,
+        pubkey: String,
+        url: String,
+        ttl_secs: Option<u64>,
+    ) -> Result<Response, AggError> {
+        if self.read_only {
+            return Err(AggError::ReadOnly);
+        }
+        let created_at = now_secs();
+        let webhook = WebhookSubscription {
+            id: self.webhooks.keys().next_back().map_or(1, |id| id + 1),
+            account: pubkey,
+            url,
+            secret: webhook::generate_secret(),
+            created_at,
+            expires_at: ttl_secs
+                .or(self.subscriptions.webhook_ttl_secs)
+                .map(|ttl| created_at + ttl),
+            last_slot: None,
+        };
+        self.put_webhook(&webhook)?;
+        self.webhooks.insert(webhook.id, webhook.clone());
+        Ok(Response::Webhook(Some(webhook)))
+    }
+
+    /// This function handles the delete webhook request
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - A u64 that holds the webhook id
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the response or an error
+    fn handle_delete_webhook_request(&mut self, id: u64) -> Result<Response, AggError> {
+        if self.read_only {
+            return Err(AggError::ReadOnly);
+        }
+        let webhook = self.webhooks.remove(&id);
+        if webhook.is_some() {
+            let mut batch = WriteBatch::default();
+            Self::delete_webhook_keys(&mut batch, id);
+            self.db.write_opt(batch, &self.write_options)?;
+        }
+        // The secret is only returned when the webhook is created
+        Ok(Response::Webhook(webhook.map(|webhook| {
+            WebhookSubscription {
+                secret: String::new(),
+                ..webhook
+            }
+        })))
+    }
+
+    /// This function persists a webhook subscription
+    ///
+    /// # Arguments
+    ///
+    /// * `webhook` - A WebhookSubscription that holds the subscription
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn put_webhook(&self, webhook: &WebhookSubscription) -> Result<(), AggError> {
+        self.put(
+            format!("{}{:020}", WEBHOOK_PREFIX, webhook.id),
+            to_vec(webhook)?,
+        )
+    }
+
+    /// This function deletes a webhook subscription and its delivery history
+    ///
+    /// # Arguments
+    ///
+    /// * `batch` - A WriteBatch the deletes are added to
+    /// * `id` - A u64 that holds the webhook id
+    fn delete_webhook_keys(batch: &mut WriteBatch, id: u64) {
+        batch.delete(format!("{}{:020}", WEBHOOK_PREFIX, id));
+        // Delivery keys of the webhook are contiguous, padded ids sort numerically
+        batch.delete_range(
+            format!("{}{:020}/", WEBHOOK_DELIVERY_PREFIX, id),
+            format!("{}{:020}0", WEBHOOK_DELIVERY_PREFIX, id),
+        );
+    }
+
+    /// This function builds the key of a delivery receipt
+    ///
+    /// # Arguments
+    ///
+    /// * `webhook_id` - A u64 that holds the webhook id
+    /// * `delivery_id` - A u64 that holds the delivery id
+    ///
+    /// # Returns
+    ///
+    /// * `String` - The key, ordered by delivery id within the webhook
+    fn delivery_key(webhook_id: u64, delivery_id: u64) -> String {
+        format!(
+            "{}{:020}/{:020}",
+            WEBHOOK_DELIVERY_PREFIX, webhook_id, delivery_id
+        )
+    }
+
+    /// This function stores the outcome of a delivery reported by the dispatcher
+    ///
+    /// # Arguments
+    ///
+    /// * `receipt` - A DeliveryReceipt that holds the status and attempts
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn add_delivery_receipt(&self, receipt: &DeliveryReceipt) -> Result<(), AggError> {
+        // The history of a deleted webhook is gone, reports still in flight are dropped
+        if !self.webhooks.contains_key(&receipt.webhook_id) {
+            return Ok(());
+        }
+        self.put(
+            Self::delivery_key(receipt.webhook_id, receipt.delivery_id),
+            to_vec(receipt)?,
+        )
+    }
+
+    /// This function handles the webhook deliveries request, listing the newest deliveries first
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - A u64 that holds the webhook id
+    /// * `limit` - A u64 that holds the maximum number of deliveries returned
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the response or an error
+    fn handle_webhook_deliveries_request(&self, id: u64, limit: u64) -> Result<Response, AggError> {
+        let prefix = format!("{}{:020}/", WEBHOOK_DELIVERY_PREFIX, id);
+        let from = Self::delivery_key(id, u64::MAX);
+        let mut deliveries = Vec::new();
+        for entry in self
+            .db
+            .iterator(IteratorMode::From(from.as_bytes(), Direction::Reverse))
+        {
+            let (key, value) = entry?;
+            if !key.starts_with(prefix.as_bytes()) || deliveries.len() as u64 >= limit {
+                break;
+            }
+            deliveries.push(from_slice::<DeliveryReceipt>(&value)?);
+        }
+        Ok(Response::WebhookDeliveries(deliveries))
+    }
+
+    /// This function stores a block the pipeline gave up on in the dead-letter queue, numbered
+    /// after the last stored letter
+    ///
+    /// # Arguments
+    ///
+    /// * `letter` - A DeadLetter that holds the failure
+    fn store_dead_letter(&mut self, mut letter: DeadLetter) {
+        metrics::DEAD_LETTERS
+            .with_label_values(&[letter.stage.as_str(), &letter.code])
+            .inc();
+        if self.read_only {
+            return;
+        }
+        let stored = self.last_dead_letter_id().and_then(|last_id| {
+            letter.id = last_id + 1;
+            self.put(
+                format!("{}{:020}", DEAD_LETTER_PREFIX, letter.id),
+                to_vec(&letter)?,
+            )
+        });
+        if let Err(err) = stored {
+            error!(target: "db", "Failed to store dead letter {:?} {}", letter, err);
+        }
+    }
+
+    fn last_dead_letter_id(&self) -> Result<u64, AggError> {
+        let from = format!("{}{:020}", DEAD_LETTER_PREFIX, u64::MAX);
+        match self
+            .db
+            .iterator(IteratorMode::From(from.as_bytes(), Direction::Reverse))
+            .next()
+        {
+            Some(entry) => {
+                let (key, value) = entry?;
+                if !key.starts_with(DEAD_LETTER_PREFIX.as_bytes()) {
+                    return Ok(0);
+                }
+                Ok(from_slice::<DeadLetter>(&value)?.id)
+            }
+            None => Ok(0),
+        }
+    }
+
+    /// This function handles the dead letters request, the latest letters first
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - A u64 that holds the maximum number of letters returned
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the response or an error
+    fn handle_dead_letters_request(&self, limit: u64) -> Result<Response, AggError> {
+        let from = format!("{}{:020}", DEAD_LETTER_PREFIX, u64::MAX);
+        let mut letters = Vec::new();
+        for entry in self
+            .db
+            .iterator(IteratorMode::From(from.as_bytes(), Direction::Reverse))
+        {
+            let (key, value) = entry?;
+            if !key.starts_with(DEAD_LETTER_PREFIX.as_bytes()) || letters.len() as u64 >= limit {
+                break;
+            }
+            letters.push(from_slice::<DeadLetter>(&value)?);
+        }
+        Ok(Response::DeadLetters(letters))
+    }
+
+    /// This function removes the webhooks and resume cursors that expired
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn remove_expired_subscriptions(&mut self) -> Result<(), AggError> {
+        let now = now_secs();
+        let mut batch = WriteBatch::default();
+        self.webhooks.retain(|id, webhook| {
+            let expired = webhook
+                .expires_at
+                .is_some_and(|expires_at| expires_at <= now);
+            if expired {
+                info!(target: "db", "Webhook {} expired", id);
+                Self::delete_webhook_keys(&mut batch, *id);
+            }
+            !expired
+        });
+        self.resume_cursors.retain(|token, cursor| {
+            let expired = cursor.expires_at <= now;
+            if expired {
+                batch.delete(format!("{}{}", RESUME_CURSOR_PREFIX, token));
+            }
+            !expired
+        });
+        self.db.write_opt(batch, &self.write_options)?;
+        Ok(())
+    }
+
+    /// This function hands the transactions of a block to the webhooks of the accounts involved
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number
+    /// * `block` - A Block that holds the block
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn dispatch_webhooks(&mut self, block_no: u64, block: &Block) -> Result<(), AggError> {
+        let (Some(webhook_sender), Some(slot)) = (&self.webhook_sender, block.slot()) else {
+            return Ok(());
+        };
+        let now = now_secs();
+        let balances = self.account_balances(
+            self.webhooks.values().map(|webhook| &webhook.account),
+            block_no,
+            block,
+        )?;
+        let mut batch = WriteBatch::default();
+        let mut deliveries = Vec::new();
+        for webhook in self.webhooks.values_mut() {
+            if webhook
+                .expires_at
+                .is_some_and(|expires_at| expires_at <= now)
+            {
+                continue;
+            }
+            let balance = balances.get(&webhook.account).copied().flatten();
+            let events =
+                Self::account_events(block_no, slot, block, &webhook.account, balance, false);
+            if events.is_empty() {
+                continue;
+            }
+            webhook.last_slot = Some(slot);
+            for event in events {
+                self.last_delivery_id += 1;
+                let delivery = WebhookDelivery {
+                    delivery_id: self.last_delivery_id,
+                    webhook_id: webhook.id,
+                    event,
+                };
+                batch.put(
+                    Self::delivery_key(webhook.id, delivery.delivery_id),
+                    to_vec(&DeliveryReceipt::pending(&delivery))?,
+                );
+                deliveries.push((webhook.clone(), delivery));
+            }
+            batch.put(
+                format!("{}{:020}", WEBHOOK_PREFIX, webhook.id),
+                to_vec(&*webhook)?,
+            );
+        }
+        if deliveries.is_empty() {
+            return Ok(());
+        }
+        batch.put_cf(
+            self.cf(META_CF)?,
+            LAST_DELIVERY_ID_KEY,
+            to_vec(&self.last_delivery_id)?,
+        );
+        // Pending receipts are stored before the dispatcher can report on them
+        self.db.write_opt(batch, &self.write_options)?;
+        for (webhook, delivery) in deliveries {
+            webhook_sender
+                .send(ProtocolMessage::WebhookEvent(webhook, Box::new(delivery)))
+                .map_err(|_| AggError::OneshotChannelError)?;
+        }
+        Ok(())
+    }
+
+    /// This function builds the events of the transactions of a block involving an account,
+    /// ordered like the account transactions index
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number
+    /// * `slot` - A u64 that holds the slot of the block
+    /// * `block` - A Block that holds the block
+    /// * `account` - A string slice that holds the account
+    /// * `balance` - An Option<u64> that holds the balance of the account at the block
+    /// * `replayed` - A bool that is true if the events are replayed from the index
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<AccountEvent>` - The events of the account
+    fn account_events(
+        block_no: u64,
+        slot: u64,
+        block: &Block,
+        account: &str,
+        balance: Option<u64>,
+        replayed: bool,
+    ) -> Vec<AccountEvent> {
+        let mut transactions: Vec<_> = block.account_transactions(account).collect();
+        transactions.sort_by_key(|(tx_id, _)| bs58::decode(tx_id).into_vec().ok());
+        transactions
+            .into_iter()
+            .map(|(tx_id, tx)| AccountEvent {
+                slot,
+                block_no,
+                tx_id: tx_id.to_string(),
+                transaction: tx.clone(),
+                balance,
+                replayed,
+            })
+            .collect()
+    }
+
+    /// This function reads the balances at a block of the accounts involved in its transactions
+    ///
+    /// # Arguments
+    ///
+    /// * `accounts` - An iterator of the accounts
+    /// * `block_no` - A u64 that holds the block number
+    /// * `block` - A Block that holds the block
+    ///
+    /// # Returns
+    ///
+    /// * `Result<BTreeMap<String, Option<u64>>, AggError>` - A Result that holds the balance of
+    ///   every involved account or an error
+    fn account_balances<'a>(
+        &self,
+        accounts: impl Iterator<Item = &'a String>,
+        block_no: u64,
+        block: &Block,
+    ) -> Result<BTreeMap<String, Option<u64>>, AggError> {
+        let snapshot = self.db.snapshot();
+        let mut balances = BTreeMap::new();
+        for account in accounts {
+            if balances.contains_key(account)
+                || block.account_transactions(account).next().is_none()
+            {
+                continue;
+            }
+            let balance = self.snapshot_account_balance(&snapshot, account, block_no)?;
+            balances.insert(account.clone(), balance);
+        }
+        Ok(balances)
+    }
+
+    /// This function sends a newly finalised block to every block subscriber and its
+    /// transactions to the subscribers of the accounts involved. A subscriber whose queue is full
+    /// is removed, counted in `agg_fan_out_dropped_blocks_total`.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number
+    fn publish_block(&mut self, block_no: u64) {
+        if self.block_subscribers.is_empty()
+            && self.account_subscribers.is_empty()
+            && self.webhooks.is_empty()
+        {
+            return;
+        }
+        if let Some(block) = self.get_block(block_no) {
+            self.block_subscribers
+                .retain(|(filter, subscriber)| match filter.apply(&block) {
+                    Some(block) => fanout::deliver(
+                        BLOCK_STREAM,
+                        subscriber,
+                        ProtocolMessage::NewBlock(block_no, block),
+                    ),
+                    None => !subscriber.is_closed(),
+                });
+            let Some(slot) = block.slot() else {
+                return;
+            };
+            let balances = match self.account_balances(
+                self.account_subscribers.iter().map(|(account, _)| account),
+                block_no,
+                &block,
+            ) {
+                Ok(balances) => balances,
+                Err(err) => {
+                    error!(target: "db", "Error reading the balances of block {} {}", block_no, err);
+                    BTreeMap::new()
+                }
+            };
+            self.account_subscribers.retain(|(account, subscriber)| {
+                let balance = balances.get(account).copied().flatten();
+                !subscriber.is_closed()
+                    && Self::account_events(block_no, slot, &block, account, balance, false)
+                        .into_iter()
+                        .all(|event| {
+                            fanout::deliver(
+                                ACCOUNT_STREAM,
+                                subscriber,
+                                ProtocolMessage::AccountEvent(Box::new(event)),
+                            )
+                        })
+            });
+            if let Err(err) = self.dispatch_webhooks(block_no, &block) {
+                error!(target: "db", "Error dispatching webhooks {}", err);
+            }
+        }
+    }
+
+    /// This function handles the account balance request
+    ///
+    /// # Arguments
+    ///
+    /// * `pubkey` - A string slice that holds the public key
+    /// * `block_no` - An Option<u64> that holds the block number
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the response or an error
+    fn handle_account_balance_request(
+        &self,
+        pubkey: String,
+        block_no: Option<u64>,
+    ) -> Result<Response, AggError> {
+        let snapshot = self.db.snapshot();
+        let block_no = match block_no {
+            Some(block_no) => block_no,
+            None => self
+                .snapshot_latest_block(&snapshot)?
+                .ok_or(AggError::NoBlockFinalised)?,
+        };
+        let block = self
+            .snapshot_block(&snapshot, block_no)?
+            .ok_or(AggError::BlockNotFound)?;
+        Ok(Response::AccountBalance(self.snapshot_balance_as_of(
+            &snapshot,
+            &pubkey,
+            block_no,
+            block.slot(),
+        )?))
+    }
+
+    /// This function handles the request of the balances of several accounts as of one block,
+    /// all read from the same snapshot
+    ///
+    /// # Arguments
+    ///
+    /// * `pubkeys` - A Vec<String> that holds the public keys
+    /// * `block_no` - A u64 that holds the block number
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the response or an error
+    fn handle_balances_at_request(
+        &self,
+        pubkeys: Vec<String>,
+        block_no: u64,
+    ) -> Result<Response, AggError> {
+        let snapshot = self.db.snapshot();
+        let mut budget = QueryBudget::new(&self.query_limits);
+        let slot = self
+            .snapshot_block(&snapshot, block_no)?
+            .ok_or(AggError::BlockNotFound)?
+            .slot();
+        let mut balances = BTreeMap::new();
+        for pubkey in pubkeys {
+            budget.scan()?;
+            let balance = self.snapshot_balance_as_of(&snapshot, &pubkey, block_no, slot)?;
+            balances.insert(pubkey, balance);
+        }
+        Ok(Response::BalancesAt(BalancesAt {
+            block_no,
+            slot,
+            balances,
+        }))
+    }
+
+    /// This function reads the balance of an account as of a block and the block it was last
+    /// observed in
+    ///
+    /// # Arguments
+    ///
+    /// * `snapshot` - A Snapshot that holds the snapshot of the db
+    /// * `pubkey` - A string slice that holds the public key
+    /// * `block_no` - A u64 that holds the block number
+    /// * `slot` - An Option<u64> that holds the slot of the block
+    ///
+    /// # Returns
+    ///
+    /// * `Result<AccountBalance, AggError>` - A Result that holds the balance or an error
+    fn snapshot_balance_as_of(
+        &self,
+        snapshot: &Snapshot,
+        pubkey: &str,
+        block_no: u64,
+        slot: Option<u64>,
+    ) -> Result<AccountBalance, AggError> {
+        let mut balance = AccountBalance::new(
+            self.snapshot_account_balance(snapshot, pubkey, block_no)?,
+            slot,
+        );
+        if let Some(slot) = slot {
+            let delta = Self::snapshot_account_delta(
+                snapshot,
+                self.cf(ACCOUNTS_DELTA_CF)?,
+                &Pubkey::from_str(pubkey)?,
+                slot,
+            )?;
+            if let Some((observed_slot, delta)) = delta {
+                balance = balance.observed(observed_slot, delta.block_no);
+            }
+        }
+        Ok(balance)
+    }
+
+    /// This function handles the block range request
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - A u64 that holds the start block number
+    /// * `end` - A u64 that holds the end block number
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the response or an error
+    fn handle_block_range_request(&self, start: u64, end: u64) -> Result<Response, AggError> {
+        let snapshot = self.db.snapshot();
+        let mut budget = QueryBudget::new(&self.query_limits);
+        let mut blocks = BTreeMap::new();
+        for block_no in start..=end {
+            budget.scan()?;
+            if let Some(block) = self.snapshot_block_bytes(&snapshot, block_no)? {
+                budget.produce(block.len())?;
+                blocks.insert(
+                    block_no,
+                    self.annotate(&snapshot, codec::decode::<Block>(&block)?)?,
+                );
+            }
+        }
+        Ok(Response::BlockRangeDetails(blocks))
+    }
+
+    /// This function handles the status request
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the response or an error
+    fn handle_status_request(&self) -> Result<Response, AggError> {
+        Ok(Response::Status(Status {
+            latest_block_no: self.get_latest_block(),
+            read_only: self.read_only,
+            durability: self.durability.clone(),
+            compaction: self.compaction_stats(),
+            chain: self.chain_status.clone(),
+            genesis_hash: self.genesis_hash.clone(),
+            chains: chain::installed()
+                .map(|chain_id| vec![SyncState::new(chain_id, self.slot_tracker.context())])
+                .unwrap_or_default(),
+            annotations: self.latest_annotations()?,
+        }))
+    }
+
+    /// This function attaches an operator note to a slot, after the notes attached to it before
+    ///
+    /// # Arguments
+    ///
+    /// * `slot` - A u64 that holds the slot
+    /// * `note` - A String that holds the note
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the response or an error
+    fn handle_annotate_request(&mut self, slot: u64, note: String) -> Result<Response, AggError> {
+        if self.read_only {
+            return Err(AggError::ReadOnly);
+        }
+        let mut annotations = self.snapshot_annotations(&self.db.snapshot(), slot)?;
+        let annotation = Annotation {
+            slot,
+            note,
+            created_at: now_secs(),
+        };
+        let mut batch = WriteBatch::default();
+        batch.put_cf(
+            self.cf(META_CF)?,
+            Self::annotation_log_key(self.last_annotation_no()? + 1),
+            to_vec(&annotation)?,
+        );
+        annotations.push(annotation);
+        batch.put_cf(
+            self.cf(META_CF)?,
+            Self::annotation_key(slot),
+            to_vec(&annotations)?,
+        );
+        self.db.write_opt(batch, &self.write_options)?;
+        info!(target: "db", "Annotated slot {}", slot);
+        Ok(Response::Annotations(annotations))
+    }
+
+    fn annotation_key(slot: u64) -> String {
+        format!("{}{:020}", ANNOTATION_PREFIX, slot)
+    }
+
+    fn annotation_log_key(annotation_no: u64) -> Vec<u8> {
+        [
+            ANNOTATION_LOG_PREFIX.as_bytes(),
+            &annotation_no.to_be_bytes(),
+        ]
+        .concat()
+    }
+
+    fn last_annotation_no(&self) -> Result<u64, AggError> {
+        let from = Self::annotation_log_key(u64::MAX);
+        match self
+            .db
+            .iterator_cf(
+                self.cf(META_CF)?,
+                IteratorMode::From(&from, Direction::Reverse),
+            )
+            .next()
+        {
+            Some(entry) => {
+                let (key, _) = entry?;
+                match key.strip_prefix(ANNOTATION_LOG_PREFIX.as_bytes()) {
+                    Some(annotation_no) => Ok(u64::from_be_bytes(annotation_no.try_into()?)),
+                    None => Ok(0),
+                }
+            }
+            None => Ok(0),
+        }
+    }
+
+    /// This function reads the notes attached to a slot from a snapshot
+    ///
+    /// # Arguments
+    ///
+    /// * `snapshot` - A Snapshot that holds the consistent view of the db
+    /// * `slot` - A u64 that holds the slot
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<Annotation>, AggError>` - A Result that holds the notes, oldest first, or an
+    ///   error
+    fn snapshot_annotations(
+        &self,
+        snapshot: &Snapshot,
+        slot: u64,
+    ) -> Result<Vec<Annotation>, AggError> {
+        match snapshot.get_cf(self.cf(META_CF)?, Self::annotation_key(slot))? {
+            Some(annotations) => Ok(from_slice::<Vec<Annotation>>(&annotations)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// This function attaches the notes on the slot of a block to the block before it is served
+    ///
+    /// # Arguments
+    ///
+    /// * `snapshot` - A Snapshot that holds the consistent view of the db
+    /// * `block` - A Block that holds the block
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Block, AggError>` - A Result that holds the annotated block or an error
+    fn annotate(&self, snapshot: &Snapshot, mut block: Block) -> Result<Block, AggError> {
+        if let Some(slot) = block.slot() {
+            block.set_annotations(self.snapshot_annotations(snapshot, slot)?);
+        }
+        Ok(block)
+    }
+
+    /// This function lists the latest notes in the order they were attached, whatever their slot
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<Annotation>, AggError>` - A Result that holds at most
+    ///   MAX_REPORTED_ANNOTATIONS notes, oldest first, or an error
+    fn latest_annotations(&self) -> Result<Vec<Annotation>, AggError> {
+        let from = Self::annotation_log_key(u64::MAX);
+        let mut latest = Vec::new();
+        for entry in self.db.iterator_cf(
+            self.cf(META_CF)?,
+            IteratorMode::From(&from, Direction::Reverse),
+        ) {
+            let (key, annotation) = entry?;
+            if !key.starts_with(ANNOTATION_LOG_PREFIX.as_bytes())
+                || latest.len() >= MAX_REPORTED_ANNOTATIONS
+            {
+                break;
+            }
+            latest.push(from_slice::<Annotation>(&annotation)?);
+        }
+        latest.reverse();
+        Ok(latest)
+    }
+
+    /// This function handles the latest block request
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the response or an error
+    fn handle_latest_block_request(&self) -> Result<Response, AggError> {
+        let snapshot = self.db.snapshot();
+        let block_no = self
+            .snapshot_latest_block(&snapshot)?
+            .ok_or(AggError::NoBlockFinalised)?;
+        let block = self
+            .snapshot_block(&snapshot, block_no)?
+            .ok_or(AggError::BlockNotFound)?;
+        Ok(Response::LatestBlockDetails(
+            block_no,
+            self.annotate(&snapshot, block)?,
+        ))
+    }
+
+    /// This function handles the block request
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the response or an error
+    fn handle_block_request(&self, block_no: u64) -> Result<Response, AggError> {
+        let snapshot = self.db.snapshot();
+        let mut block = self
+            .snapshot_block(&snapshot, block_no)?
+            .ok_or(AggError::BlockNotFound)?;
+        block.set_block_height(block_no);
+        Ok(Response::BlockDetails(self.annotate(&snapshot, block)?))
+    }
+
+    /// This function handles the block request of a slot, resolving the block height of the
+    /// slot through the slot index
+    ///
+    /// # Arguments
+    ///
+    /// * `slot` - A u64 that holds the slot
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the response or an error
+    fn handle_block_at_slot_request(&self, slot: u64) -> Result<Response, AggError> {
+        let snapshot = self.db.snapshot();
+        let block_no = snapshot
+            .get(format!("Slot{}", slot))?
+            .ok_or(AggError::BlockNotFound)?;
+        let block_no = from_slice::<u64>(&block_no)?;
+        let mut block = self
+            .snapshot_block(&snapshot, block_no)?
+            .ok_or(AggError::BlockNotFound)?;
+        block.set_block_height(block_no);
+        Ok(Response::BlockDetails(self.annotate(&snapshot, block)?))
+    }
+
+    /// This function handles the transaction request
+    ///
+    /// # Arguments
+    ///
+    /// * `tx_id` - A string slice that holds the transaction id
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the response or an error
+    fn handle_tx_request(&self, tx_id: String) -> Result<Response, AggError> {
+        let snapshot = self.db.snapshot();
+        let tx_id = self.snapshot_resolve_tx_id(&snapshot, tx_id)?;
+        let block_no = self
+            .snapshot_tx_block_no(&snapshot, &tx_id)?
+            .ok_or(AggError::TxNotFound)?;
+        let block = self
+            .snapshot_block(&snapshot, block_no)?
+            .ok_or(AggError::BlockNotFound)?;
+        let tx = block.get_tx_details(&tx_id).ok_or(AggError::TxNotFound)?;
+        Ok(Response::TxDetails(tx.clone()))
+    }
+
+    /// This function finds where a transaction was included, along with its record
+    ///
+    /// # Arguments
+    ///
+    /// * `tx_id` - A String that holds the transaction id
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - The inclusion and record of the transaction or an error
+    fn handle_tx_inclusion_request(&self, tx_id: String) -> Result<Response, AggError> {
+        let snapshot = self.db.snapshot();
+        let tx_id = self.snapshot_resolve_tx_id(&snapshot, tx_id)?;
+        let block_no = self
+            .snapshot_tx_block_no(&snapshot, &tx_id)?
+            .ok_or(AggError::TxNotFound)?;
+        let block = self
+            .snapshot_block(&snapshot, block_no)?
+            .ok_or(AggError::BlockNotFound)?;
+        let inclusion = block
+            .tx_inclusion(block_no, &tx_id)
+            .ok_or(AggError::TxNotFound)?;
+        let tx = block.get_tx_details(&tx_id).ok_or(AggError::TxNotFound)?;
+        Ok(Response::TxInclusion(inclusion, tx.clone()))
+    }
+
+    /// This function handles the block
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number
+    /// * `block` - A Block that holds the block
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn handle_block(&mut self, block_no: u64, block: Block) -> Result<(), AggError> {
+        self.store_block(block_no, block)?;
+        // The body is stored in arrival order, the account state is applied in block order
+        self.put(format!("{}{}", PENDING_STATE_PREFIX, block_no), [])?;
+        self.state_applier.park(block_no);
+        self.apply_pending_state()
+    }
+
+    /// This function stores a block with everything indexed for it, leaving the account state
+    /// to be applied by the caller
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number
+    /// * `block` - A Block that holds the block
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn store_block(&mut self, block_no: u64, mut block: Block) -> Result<(), AggError> {
+        block.set_block_height(block_no);
+        if self.commitment == Commitment::Confirmed {
+            self.follow_reorg(block_no, &block)?;
+        }
+        self.sanitize_block_time(block_no, &mut block)?;
+        self.put(format!("BlockDigest{}", block_no), block.digest().as_ref())?;
+        self.db.put_cf_opt(
+            self.cf(BLOCK_SUMMARY_CF)?,
+            block_no.to_be_bytes(),
+            to_vec(&block.summary(block_no))?,
+            &self.write_options,
+        )?;
+        if let Some(slot) = block.slot() {
+            self.put(format!("Slot{}", slot), to_vec(&block_no)?)?;
+            if let Some(key) = Self::slot_time_key(&block) {
+                self.put(key, to_vec(&block_no)?)?;
+            }
+        }
+        self.add_token_balances(block_no, &block)?;
+        self.add_owner_token_balances(block_no, &block)?;
+        self.add_account_deltas(block_no, &block)?;
+        self.add_account_transactions(block_no, &block)?;
+        self.add_authority_changes(block_no, &block)?;
+        self.add_first_seen(block_no, &block)?;
+        self.add_custom_stats(block_no, &block)?;
+        let stored_before = self
+            .db
+            .get_pinned_cf(self.cf(BLOCKS_CF)?, block_no.to_be_bytes())?
+            .is_some();
+        self.add_block(block_no, &block)?;
+        self.settle_active_accounts(block_no, &block, stored_before)?;
+        self.add_transactions(block, block_no)
+    }
+
+    /// This function rolls back the stored blocks of a fork the chain abandoned, found by a new
+    /// confirmed block replacing the stored block at its height or not chaining onto the stored
+    /// parent. The canonical parent of the new block is fetched again, the state of the new block
+    /// is applied once the parent is stored.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number
+    /// * `block` - A Block that holds the new block
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn follow_reorg(&mut self, block_no: u64, block: &Block) -> Result<(), AggError> {
+        let reorg = match reorg::detect(block_no, block, self.reorg.max_depth, |block_no| {
+            self.get_block(block_no)
+        }) {
+            ForkCheck::Extends => return Ok(()),
+            ForkCheck::Reorg(reorg) => reorg,
+            ForkCheck::TooDeep(depth) => {
+                error!(
+                    target: "db",
+                    "Block {} abandons at least {} stored blocks, more than reorg.max_depth, not rolling them back",
+                    block_no, depth
+                );
+                return Ok(());
+            }
+        };
+        warn!(
+            target: "db",
+            "Block {} of slot {:?} reorganizes the chain, rolling back {:?}",
+            block_no, block.slot(), reorg.orphaned
+        );
+        // The orphaned blocks are the stored blocks from the first one on, so their slots hold
+        // no other stored block. They are past the settled active accounts, which leave them out.
+        if let (Some(first), Some(last)) = (reorg.orphaned.first(), reorg.orphaned.last()) {
+            self.delete_slot_range(first.slot, last.slot)?;
+        }
+        if let Some(slot) = reorg.canonical_parent_slot {
+            // The slot was marked skipped if it was fetched before its block was confirmed
+            self.db
+                .delete_opt(format!("SkippedSlot{}", slot), &self.write_options)?;
+            match &self.refetch_sender {
+                Some(sender) if sender.send(slot).is_ok() => {
+                    info!(target: "db", "Fetching the canonical block of slot {} again", slot)
+                }
+                _ => warn!(
+                    target: "db",
+                    "Unable to fetch the canonical block of slot {} again, the state of block {} waits for it",
+                    slot, block_no
+                ),
+            }
+        }
+        let rolled_back = reorg.orphaned.len() as u64;
+        self.chain_status.reorgs += 1;
+        self.chain_status.rolled_back_blocks += rolled_back;
+        self.put_cf(META_CF, CHAIN_STATUS_KEY, to_vec(&self.chain_status)?)?;
+        metrics::REORGS.inc();
+        metrics::ROLLED_BACK_BLOCKS.inc_by(rolled_back);
+        Ok(())
+    }
+
+    /// This function sanitizes the block time of a block against the nearest stored blocks
+    /// around it, keeping the time reported by the node next to the sanitized one
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number
+    /// * `block` - A Block that holds the block
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn sanitize_block_time(&self, block_no: u64, block: &mut Block) -> Result<(), AggError> {
+        let previous = self.time_anchor(block_no, Direction::Reverse)?;
+        let next = self.time_anchor(block_no, Direction::Forward)?;
+        let slot = block.slot().unwrap_or(block_no);
+        let sanitized = timestamp::sanitize(slot, block.raw_block_time(), previous, next);
+        if let Some(flag) = sanitized.flag {
+            debug!(
+                target: "db",
+                "Block {} time {:?} sanitized to {:?} ({:?})",
+                block_no, block.raw_block_time(), sanitized.block_time, flag
+            );
+            metrics::SANITIZED_BLOCK_TIMES
+                .with_label_values(&[&format!("{:?}", flag).to_lowercase()])
+                .inc();
+        }
+        block.set_sanitized_block_time(sanitized);
+        Ok(())
+    }
 
-    /// This function initializes the RocksDb client
+    /// This function finds the nearest stored block with a block time before or after a block
     ///
     /// # Arguments
     ///
-    /// * `path` - A string slice that holds the path to the database
-    /// * `receiver` - A UnboundedReceiver<ProtocolMessage> that holds the receiver
+    /// * `block_no` - A u64 that holds the block number
+    /// * `direction` - A Direction that holds whether to look before or after the block
     ///
     /// # Returns
     ///
-    /// * `Result<Self, AggError>` - A Result that holds the RocksDb client or an error
-    pub fn initialize(
-        path: String,
-        receiver: UnboundedReceiver<ProtocolMessage>,
-    ) -> Result<Self, AggError> {
-        let db = rocksdb::DB::open_default(&path)?;
-        Ok(Self {
-            db,
-            receiver,
-            temp_db: Default::default(),
-        })
+    /// * `Result<Option<TimeAnchor>, AggError>` - A Result that holds the slot and sanitized
+    ///   time of the block, None if none is stored within TIME_ANCHOR_SCAN blocks, or an error
+    fn time_anchor(
+        &self,
+        block_no: u64,
+        direction: Direction,
+    ) -> Result<Option<TimeAnchor>, AggError> {
+        let from = block_no.to_be_bytes();
+        for entry in self
+            .db
+            .iterator_cf(
+                self.cf(BLOCK_SUMMARY_CF)?,
+                IteratorMode::From(&from, direction),
+            )
+            .take(TIME_ANCHOR_SCAN)
+        {
+            let (key, summary) = entry?;
+            if key.as_ref() == from {
+                continue;
+            }
+            let summary = from_slice::<BlockSummary>(&summary)?;
+            if let Some(block_time) = summary.block_time {
+                return Ok(Some(TimeAnchor {
+                    slot: summary.slot.unwrap_or(summary.block_no),
+                    block_time,
+                }));
+            }
+        }
+        Ok(None)
     }
 
-    /// This function runs the RocksDb client
-    pub(crate) async fn run(&mut self) {
-        loop {
-            if let Some(message) = self.receiver.recv().await {
-                match message {
-                    ProtocolMessage::FinalizeBlock(block_no, block) => {
-                        println!(
-                            "here block no {:?} {:?}",
-                            block_no,
-                            block.get_tx_hash().len()
-                        );
-                        if let Err(err) = self.handle_block(block_no, block) {
-                            error!(target: "db", "Error from handle_block {}", err);
-                        }
-                    }
-                    ProtocolMessage::FetchTransactionDetails(tx_id, server_sender) => {
-                        println!("Fetching tx details {:?}", tx_id);
-                        if let Err(error) = self.handle_tx_request(tx_id, server_sender.clone()) {
-                            Self::handle_error(server_sender, error);
-                        }
-                    }
-                    ProtocolMessage::FetchBlockDetails(block_no, server_sender) => {
-                        println!("Fetching block details {:?}", block_no);
-                        if let Err(error) =
-                            self.handle_block_request(block_no, server_sender.clone())
-                        {
-                            Self::handle_error(server_sender, error);
-                        }
-                    }
-                    ProtocolMessage::FetchLatestBlock(server_sender) => {
-                        println!("Fetching latest block");
-                        if let Err(error) = self.handle_latest_block_request(server_sender.clone())
-                        {
-                            Self::handle_error(server_sender, error);
-                        }
-                    }
-                    ProtocolMessage::FetchBlockRange(start, end, server_sender) => {
-                        println!("Fetching block range");
-                        if let Err(error) =
-                            self.handle_block_range_request(start, end, server_sender.clone())
-                        {
-                            Self::handle_error(server_sender, error);
-                        }
+    /// This function passes over a block given up on before it was stored, so the account state
+    /// of the blocks after it is not held back waiting for it
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn skip_block(&mut self, block_no: u64) -> Result<(), AggError> {
+        self.state_applier.skip(block_no);
+        self.apply_pending_state()
+    }
+
+    /// This function applies the account state of every parked block that is next in order
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn apply_pending_state(&mut self) -> Result<(), AggError> {
+        // A quarantined block is handled without becoming the latest block, the next block is
+        // applied on top of the block before it
+        while let Some(ready) = self.state_applier.next_ready(
+            self.get_latest_block()
+                .max(self.chain_status.quarantined_blocks.last().copied()),
+        ) {
+            let block_no = match ready {
+                ReadyBlock::Apply(block_no) => {
+                    if self.verify_parent(block_no)? != ChainLink::Broken {
+                        debug!("Applying state of block {:?}", block_no);
+                        self.apply_block_state(block_no)?;
+                        self.publish_block(block_no);
                     }
-                    ProtocolMessage::FetchAccountBalance(pubkey, block_no, server_sender) => {
-                        println!("Fetching account balance");
-                        if let Err(error) = self.handle_account_balance_request(
-                            pubkey,
-                            block_no,
-                            server_sender.clone(),
-                        ) {
-                            Self::handle_error(server_sender, error);
-                        }
+                    block_no
+                }
+                ReadyBlock::Stale(block_no) => {
+                    // Balances are indexed by block, so those of an older block do not shadow
+                    // the balances of the later blocks
+                    warn!(target: "db", "Block {} arrived after a later block was applied, only its balances are indexed", block_no);
+                    if let Some(block) = self.get_block(block_no) {
+                        self.index_account_balances(block_no, &block)?;
                     }
-                    _ => {}
+                    block_no
                 }
-            }
+            };
+            self.db.delete_opt(
+                format!("{}{}", PENDING_STATE_PREFIX, block_no),
+                &self.write_options,
+            )?;
         }
+        Ok(())
     }
 
-    /// This function handles the account balance request
+    /// This function checks that a block chains onto its parent and records the outcome, a block
+    /// breaking the chain is quarantined so its state is not applied nor published
     ///
     /// # Arguments
     ///
-    /// * `pubkey` - A string slice that holds the public key
-    /// * `block_no` - An Option<u64> that holds the block number
-    /// * `server_sender` - A UnboundedSender<ProtocolMessage> that holds the server sender
+    /// * `block_no` - A u64 that holds the block number
     ///
     /// # Returns
     ///
-    /// * `Result<(), AggError>` - A Result that holds the result or an error
-    fn handle_account_balance_request(
-        &self,
-        pubkey: String,
-        block_no: Option<u64>,
-        server_sender: UnboundedSender<ProtocolMessage>,
-    ) -> Result<(), AggError> {
-        if let Some(block_no) = block_no {
-            if let Some(block) = self.db.get(format!("BlockNo{}", block_no))? {
-                let block = from_slice::<Block>(&block)?;
-                let balance = block.get_account_balance(&pubkey);
-                server_sender
-                    .send(ProtocolMessage::AccountBalance(balance.unwrap_or_default()))
-                    .map_err(|_| AggError::OneshotChannelError)?;
+    /// * `Result<ChainLink, AggError>` - A Result that holds the outcome or an error
+    fn verify_parent(&mut self, block_no: u64) -> Result<ChainLink, AggError> {
+        let block = self.get_block(block_no).ok_or(AggError::BlockNotFound)?;
+        let parent = match block_no.checked_sub(1) {
+            Some(parent_no) if self.db.get(Self::quarantine_key(parent_no))?.is_none() => {
+                self.get_block(parent_no)
             }
-        } else {
-            if let Some(block_no) = self.get_latest_block() {
-                if let Some(block) = self.db.get(format!("BlockNo{}", block_no))? {
-                    let block = from_slice::<Block>(&block)?;
-                    let balance = block.get_account_balance(&pubkey);
-                    server_sender
-                        .send(ProtocolMessage::AccountBalance(balance.unwrap_or_default()))
-                        .map_err(|_| AggError::OneshotChannelError)?;
+            _ => None,
+        };
+        let parent_blockhash = parent.as_ref().and_then(|parent| parent.blockhash());
+        let link = match (block.previous_blockhash(), parent_blockhash) {
+            (Some(previous), Some(parent)) if previous == parent => ChainLink::Verified,
+            (Some(_), Some(_)) => ChainLink::Broken,
+            _ => ChainLink::Unverified,
+        };
+        let mut batch = WriteBatch::default();
+        batch.put(format!("{}{}", CHAIN_LINK_PREFIX, block_no), to_vec(&link)?);
+        match link {
+            ChainLink::Verified => {
+                self.chain_status.verified_blocks += 1;
+                self.chain_status.last_verified_block_no = Some(block_no);
+            }
+            ChainLink::Unverified => self.chain_status.unverified_blocks += 1,
+            ChainLink::Broken => {
+                warn!(
+                    target: "db",
+                    "Block {} does not chain onto block {}, quarantining it", block_no, block_no - 1
+                );
+                let chain_break = ChainBreak {
+                    block_no,
+                    slot: block.slot(),
+                    previous_blockhash: block.previous_blockhash().map(str::to_string),
+                    parent_blockhash: parent_blockhash.map(str::to_string),
+                };
+                batch.put(Self::quarantine_key(block_no), to_vec(&chain_break)?);
+                self.fall_back_conflicts(block_no, &block, &mut batch)?;
+                self.chain_status.broken_links += 1;
+                self.chain_status.quarantined_blocks.push(block_no);
+                if self.chain_status.quarantined_blocks.len() > MAX_REPORTED_QUARANTINED {
+                    self.chain_status.quarantined_blocks.remove(0);
                 }
             }
         }
-        Ok(())
+        batch.put_cf(
+            self.cf(META_CF)?,
+            CHAIN_STATUS_KEY,
+            to_vec(&self.chain_status)?,
+        );
+        self.db.write_opt(batch, &self.write_options)?;
+        Ok(link)
     }
 
-    /// This function handles the block range request
+    fn quarantine_key(block_no: u64) -> String {
+        format!("{}{:020}", QUARANTINE_PREFIX, block_no)
+    }
+
+    /// This function loads the blocks stored by a previous run whose state was not applied
     ///
     /// # Arguments
     ///
-    /// * `start` - A u64 that holds the start block number
-    /// * `end` - A u64 that holds the end block number
-    /// * `server_sender` - A UnboundedSender<ProtocolMessage> that holds the server sender
+    /// * `db` - A DB that holds the opened database
+    ///
+    /// # Returns
+    ///
+    /// * `Result<BTreeSet<u64>, AggError>` - A Result that holds the pending blocks or an error
+    fn pending_state(db: &DB) -> Result<BTreeSet<u64>, AggError> {
+        let mut pending = BTreeSet::new();
+        for entry in db.prefix_iterator(PENDING_STATE_PREFIX) {
+            let (key, _) = entry?;
+            let Some(block_no) = key.strip_prefix(PENDING_STATE_PREFIX.as_bytes()) else {
+                break;
+            };
+            if let Ok(block_no) = String::from_utf8_lossy(block_no).parse::<u64>() {
+                pending.insert(block_no);
+            }
+        }
+        Ok(pending)
+    }
+
+    /// This function loads the values stored under a key prefix
+    ///
+    /// # Arguments
+    ///
+    /// * `db` - A DB that holds the opened database
+    /// * `prefix` - A string slice that holds the key prefix
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<T>, AggError>` - A Result that holds the values in key order or an error
+    fn load_prefixed<T: DeserializeOwned>(db: &DB, prefix: &str) -> Result<Vec<T>, AggError> {
+        let mut values = Vec::new();
+        for entry in db.prefix_iterator(prefix) {
+            let (key, value) = entry?;
+            if !key.starts_with(prefix.as_bytes()) {
+                break;
+            }
+            values.push(from_slice::<T>(&value)?);
+        }
+        Ok(values)
+    }
+
+    /// This function adds the transactions
+    ///
+    /// # Arguments
+    ///
+    /// * `block` - A Block that holds the block
+    /// * `block_no` - A u64 that holds the block number
     ///
     /// # Returns
     ///
     /// * `Result<(), AggError>` - A Result that holds the result or an error
-    fn handle_block_range_request(
-        &self,
-        start: u64,
-        end: u64,
-        server_sender: UnboundedSender<ProtocolMessage>,
-    ) -> Result<(), AggError> {
-        let mut blocks = BTreeMap::new();
-        for block_no in start..=end {
-            if let Some(block) = self.db.get(format!("BlockNo{}", block_no))? {
-                let block = from_slice::<Block>(&block)?;
-                blocks.insert(block_no, block);
+    fn add_transactions(&mut self, block: Block, block_no: u64) -> Result<(), AggError> {
+        let cf = self.cf(TX_INDEX_CF)?;
+        let slot = block.slot().unwrap_or(block_no);
+        let mut batch = WriteBatch::default();
+        let mut conflicts = 0;
+        for (tx_id, tx) in block.transactions() {
+            let Ok(raw_tx_id) = bs58::decode(tx_id).into_vec() else {
+                continue;
+            };
+            for signature in tx.signatures() {
+                if let Ok(raw_signature) = bs58::decode(signature).into_vec() {
+                    batch.put_cf(self.cf(TX_SIGNATURES_CF)?, raw_signature, &raw_tx_id);
+                }
+            }
+        }
+        let offsets = raw_tx_offsets(&block);
+        // The transactions indexed before are found in one batched read of the block
+        let indexed = self.db.batched_multi_get_cf(
+            cf,
+            offsets.iter().map(|(_, raw_tx_id, _)| raw_tx_id),
+            false,
+        );
+        for ((tx_id, raw_tx_id, offset), indexed) in offsets.into_iter().zip(indexed) {
+            let location = TxLocation {
+                slot,
+                block_no,
+                offset,
+            };
+            let indexed = indexed?.and_then(|bytes| TxLocation::from_bytes(&bytes).ok());
+            if let Some(indexed) = indexed.filter(|indexed| indexed.slot != slot) {
+                // The node returned the transaction in another slot before. The index only keeps
+                // that block while it is finalized and the new one is not, the other block is
+                // remembered to fall back to
+                let keep_indexed = !self.is_finalized(&location, block_no)?
+                    && self.is_finalized(&indexed, block_no)?;
+                let (kept, other, resolution) = if keep_indexed {
+                    (indexed, location, "kept_indexed")
+                } else {
+                    (location, indexed, "replaced")
+                };
+                // A block fetched again does not count its conflicts twice
+                let recorded = match self.db.get(Self::tx_conflict_key(&tx_id))? {
+                    Some(conflict) => {
+                        let conflict = from_slice::<TxConflict>(&conflict)?;
+                        [conflict.kept.slot, conflict.other.slot].contains(&slot)
+                    }
+                    None => false,
+                };
+                if !recorded {
+                    warn!(
+                        target: "db",
+                        "Transaction {} is in slot {} and slot {}, indexing slot {}",
+                        tx_id, indexed.slot, slot, kept.slot
+                    );
+                    metrics::TX_CONFLICTS.with_label_values(&[resolution]).inc();
+                    conflicts += 1;
+                }
+                let conflict = TxConflict {
+                    tx_id: tx_id.clone(),
+                    kept,
+                    other,
+                    at: now_secs(),
+                };
+                batch.put(Self::tx_conflict_key(&tx_id), to_vec(&conflict)?);
+                if keep_indexed {
+                    continue;
+                }
             }
+            batch.put_cf(cf, raw_tx_id, location.to_bytes());
         }
-        server_sender
-            .send(ProtocolMessage::BlockRangeDetails(blocks))
-            .map_err(|_| AggError::OneshotChannelError)?;
+        if conflicts > 0 {
+            self.chain_status.tx_conflicts += conflicts;
+            batch.put_cf(
+                self.cf(META_CF)?,
+                CHAIN_STATUS_KEY,
+                to_vec(&self.chain_status)?,
+            );
+        }
+        self.db.write_opt(batch, &self.write_options)?;
         Ok(())
     }
 
-    /// This function handles the latest block request
+    fn tx_conflict_key(tx_id: &str) -> String {
+        format!("{}{}", TX_CONFLICT_PREFIX, tx_id)
+    }
+
+    /// This function returns the number of blocks behind the latest block that a reorg may still
+    /// roll back, none unless confirmed blocks are ingested
+    ///
+    /// # Returns
+    ///
+    /// * `u64` - The depth a reorg may reach
+    fn reorg_depth(&self) -> u64 {
+        match self.commitment {
+            Commitment::Confirmed => self.reorg.max_depth,
+            _ => 0,
+        }
+    }
+
+    /// This function tells whether a transaction location is in a canonical block that can no
+    /// longer be rolled back by a reorg
+    ///
+    /// # Arguments
+    ///
+    /// * `location` - A reference to the TxLocation
+    /// * `latest_block_no` - A u64 that holds the number of the block being stored
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, AggError>` - A Result that holds whether the block is finalized or an error
+    fn is_finalized(&self, location: &TxLocation, latest_block_no: u64) -> Result<bool, AggError> {
+        if location.block_no.saturating_add(self.reorg_depth()) > latest_block_no {
+            return Ok(false);
+        }
+        self.is_canonical(location)
+    }
+
+    /// This function tells whether a transaction location is in a block still stored under its
+    /// slot and not quarantined
+    ///
+    /// # Arguments
+    ///
+    /// * `location` - A reference to the TxLocation
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, AggError>` - A Result that holds whether the block is canonical or an error
+    fn is_canonical(&self, location: &TxLocation) -> Result<bool, AggError> {
+        let stored = match self.db.get(format!("Slot{}", location.slot))? {
+            Some(block_no) => from_slice::<u64>(&block_no)? == location.block_no,
+            None => false,
+        };
+        Ok(stored
+            && self
+                .db
+                .get(Self::quarantine_key(location.block_no))?
+                .is_none())
+    }
+
+    /// This function points the transaction index at the other block of the conflicts whose kept
+    /// block is quarantined
     ///
     /// # Arguments
     ///
-    /// * `server_sender` - A UnboundedSender<ProtocolMessage> that holds the server sender
+    /// * `block_no` - A u64 that holds the number of the quarantined block
+    /// * `block` - A reference to the Block
+    /// * `batch` - A mutable reference to the WriteBatch the changes are added to
     ///
     /// # Returns
     ///
     /// * `Result<(), AggError>` - A Result that holds the result or an error
-    fn handle_latest_block_request(
+    fn fall_back_conflicts(
         &self,
-        server_sender: UnboundedSender<ProtocolMessage>,
+        block_no: u64,
+        block: &Block,
+        batch: &mut WriteBatch,
     ) -> Result<(), AggError> {
-        if let Some(block_no) = self.get_latest_block() {
-            if let Some(block) = self.db.get(format!("BlockNo{}", block_no))? {
-                let block = from_slice::<Block>(&block)?;
-                server_sender
-                    .send(ProtocolMessage::LatestBlockDetails(block_no, block.clone()))
-                    .map_err(|_| AggError::OneshotChannelError)?;
-            } else {
-                return Err(AggError::BlockNotFound);
+        for tx_id in block.get_tx_hash() {
+            let Some(conflict) = self.db.get(Self::tx_conflict_key(&tx_id))? else {
+                continue;
+            };
+            let conflict = from_slice::<TxConflict>(&conflict)?;
+            if conflict.kept.block_no != block_no || !self.is_canonical(&conflict.other)? {
+                continue;
             }
-        } else {
-            return Err(AggError::NoBlockFinalised);
+            let Ok(raw_tx_id) = bs58::decode(&tx_id).into_vec() else {
+                continue;
+            };
+            info!(
+                target: "db",
+                "Transaction {} falls back to slot {}", tx_id, conflict.other.slot
+            );
+            batch.put_cf(self.cf(TX_INDEX_CF)?, raw_tx_id, conflict.other.to_bytes());
+            let swapped = TxConflict {
+                kept: conflict.other,
+                other: conflict.kept,
+                ..conflict
+            };
+            batch.put(Self::tx_conflict_key(&tx_id), to_vec(&swapped)?);
         }
         Ok(())
     }
 
-    /// This function handles the block request
+    /// This function resolves the signature of a transaction to the message hash it is indexed
+    /// by, any other id is returned as is
     ///
     /// # Arguments
     ///
-    /// * `block_no` - A string slice that holds the block number
-    /// * `server_sender` - A UnboundedSender<ProtocolMessage> that holds the server sender
+    /// * `snapshot` - A Snapshot that holds the consistent view of the db
+    /// * `tx_id` - A String that holds the message hash or a signature of the transaction
     ///
     /// # Returns
     ///
-    /// * `Result<(), AggError>` - A Result that holds the result or an error
-    fn handle_block_request(
+    /// * `Result<String, AggError>` - A Result that holds the message hash or an error
+    fn snapshot_resolve_tx_id(
         &self,
-        block_no: String,
-        server_sender: UnboundedSender<ProtocolMessage>,
-    ) -> Result<(), AggError> {
-        if let Some(block) = self.db.get(format!("BlockNo{}", block_no))? {
-            let block = from_slice::<Block>(&block)?;
-            server_sender
-                .send(ProtocolMessage::BlockDetails(block.clone()))
-                .map_err(|_| AggError::OneshotChannelError)?;
-        } else {
-            return Err(AggError::BlockNotFound);
+        snapshot: &Snapshot,
+        tx_id: String,
+    ) -> Result<String, AggError> {
+        let Ok(raw_signature) = bs58::decode(&tx_id).into_vec() else {
+            return Ok(tx_id);
+        };
+        if raw_signature.len() != SIGNATURE_BYTES {
+            return Ok(tx_id);
+        }
+        match snapshot.get_cf(self.cf(TX_SIGNATURES_CF)?, raw_signature)? {
+            Some(raw_tx_id) => Ok(bs58::encode(raw_tx_id).into_string()),
+            None => Ok(tx_id),
         }
-        Ok(())
     }
 
-    /// This function handles the transaction request
+    /// This function finds the block of a transaction from a snapshot, in the transaction index
+    /// or among the legacy entries a migration job has not moved yet
     ///
     /// # Arguments
     ///
+    /// * `snapshot` - A Snapshot that holds the consistent view of the db
     /// * `tx_id` - A string slice that holds the transaction id
-    /// * `server_sender` - A UnboundedSender<ProtocolMessage> that holds the server sender
     ///
     /// # Returns
     ///
-    /// * `Result<(), AggError>` - A Result that holds the result or an error
-    fn handle_tx_request(
+    /// * `Result<Option<u64>, AggError>` - A Result that holds the block number, None if the
+    ///   transaction is not stored, or an error
+    fn snapshot_tx_block_no(
         &self,
-        tx_id: String,
-        server_sender: UnboundedSender<ProtocolMessage>,
-    ) -> Result<(), AggError> {
-        if let Some(block_no) = self.db.get(to_vec(&tx_id).unwrap())? {
-            let block_no = from_slice::<u64>(&block_no)?;
-            if let Some(block) = self.db.get(format!("BlockNo{}", block_no))? {
-                let block = from_slice::<Block>(&block)?;
-                let tx = block.get_tx_details(&tx_id).ok_or(AggError::TxNotFound)?;
-                server_sender
-                    .send(ProtocolMessage::TxDetails(tx.clone()))
-                    .map_err(|_| AggError::OneshotChannelError)?;
-            } else {
-                return Err(AggError::BlockNotFound);
+        snapshot: &Snapshot,
+        tx_id: &str,
+    ) -> Result<Option<u64>, AggError> {
+        if let Ok(raw_tx_id) = bs58::decode(tx_id).into_vec() {
+            if let Some(location) = snapshot.get_cf(self.cf(TX_INDEX_CF)?, raw_tx_id)? {
+                return Ok(Some(TxLocation::from_bytes(&location)?.block_no));
             }
-        } else {
-            return Err(AggError::TxNotFound);
         }
-        Ok(())
+        match snapshot.get(to_vec(tx_id)?)? {
+            Some(block_no) => Ok(Some(from_slice::<u64>(&block_no)?)),
+            None => Ok(None),
+        }
     }
 
-    /// This function handles the block
+    /// This function gets the block
     ///
     /// # Arguments
     ///
     /// * `block_no` - A u64 that holds the block number
-    /// * `block` - A Block that holds the block
     ///
     /// # Returns
     ///
-    /// * `Result<(), AggError>` - A Result that holds the result or an error
-    fn handle_block(&mut self, block_no: u64, block: Block) -> Result<(), AggError> {
-        if let Some(latest_block) = self.get_latest_block() {
-            debug!("Latest block no {:?}", latest_block);
-            if block_no == latest_block.saturating_add(1) {
-                debug!("Added to db {:?}", block_no);
-                self.add_block(block_no, &block)?;
-                self.update_latest_block_no_and_account_map(block_no)?;
-            } else {
-                self.temp_db.insert(block_no);
-                self.add_block(block_no, &block)?;
-            }
-        } else {
-            debug!("Updated latest block no first time{:?}", block_no);
-            self.add_block(block_no, &block)?;
-            self.update_latest_block_no_and_account_map(block_no)?;
-        }
-        self.add_transactions(block, block_no)?;
-        if !self.temp_db.is_empty() {
-            let mut block_to_removed = vec![];
-            for block_no in self.temp_db.iter() {
-                if block_no.saturating_sub(1)
-                    == self.get_latest_block().ok_or(AggError::NoBlockFinalised)?
-                {
-                    self.update_latest_block_no_and_account_map(*block_no)?;
-                    block_to_removed.push(*block_no);
-                }
-            }
-            for block_no in block_to_removed {
-                self.temp_db.remove(&block_no);
+    /// * `Option<Block>` - An Option that holds the block, None when it is missing or corrupt
+    fn get_block(&self, block_no: u64) -> Option<Block> {
+        let block = get_block_bytes(&self.db, block_no).ok()??;
+        // A corrupt block is skipped like a missing one rather than taking the db actor down
+        match codec::decode::<Block>(&block) {
+            Ok(block) => Some(block),
+            Err(error) => {
+                error!(target: "db", "Failed to decode block {} {}", block_no, error);
+                None
             }
         }
-        Ok(())
     }
 
-    /// This function adds the transactions
+    /// This function reads a block from a snapshot
     ///
     /// # Arguments
     ///
-    /// * `block` - A Block that holds the block
+    /// * `snapshot` - A Snapshot that holds the consistent view of the db
     /// * `block_no` - A u64 that holds the block number
     ///
     /// # Returns
     ///
-    /// * `Result<(), AggError>` - A Result that holds the result or an error
-    fn add_transactions(&mut self, block: Block, block_no: u64) -> Result<(), AggError> {
-        for tx in block.get_tx_hash() {
-            self.db.put(to_vec(&tx)?, to_vec(&block_no).unwrap())?;
+    /// * `Result<Option<Block>, AggError>` - A Result that holds the block or an error
+    fn snapshot_block(
+        &self,
+        snapshot: &Snapshot,
+        block_no: u64,
+    ) -> Result<Option<Block>, AggError> {
+        match self.snapshot_block_bytes(snapshot, block_no)? {
+            Some(block) => Ok(Some(codec::decode::<Block>(&block)?)),
+            None => Ok(None),
         }
-        Ok(())
     }
 
-    /// This function gets the block
+    /// This function reads an encoded block from a snapshot, falling back to the default column
+    /// family for blocks the layout migration has not moved yet
     ///
     /// # Arguments
     ///
+    /// * `snapshot` - A Snapshot that holds the consistent view of the db
     /// * `block_no` - A u64 that holds the block number
     ///
     /// # Returns
     ///
-    /// * `Option<Block>` - An Option that holds the block
-    fn get_block(&self, block_no: u64) -> Option<Block> {
-        if let Ok(Some(block)) = self.db.get(format!("BlockNo{}", block_no)) {
-            Some(from_slice::<Block>(&block).unwrap())
-        } else {
-            None
+    /// * `Result<Option<Vec<u8>>, AggError>` - A Result that holds the encoded block or an error
+    fn snapshot_block_bytes(
+        &self,
+        snapshot: &Snapshot,
+        block_no: u64,
+    ) -> Result<Option<Vec<u8>>, AggError> {
+        if let Some(block) = snapshot.get_cf(self.cf(BLOCKS_CF)?, block_no.to_be_bytes())? {
+            return Ok(Some(block));
+        }
+        Ok(snapshot.get(format!("{}{}", LEGACY_BLOCK_PREFIX, block_no))?)
+    }
+
+    /// This function reads the last recorded balance of an account at or before a slot from a
+    /// snapshot
+    ///
+    /// # Arguments
+    ///
+    /// * `snapshot` - A Snapshot that holds the consistent view of the db
+    /// * `cf` - A ColumnFamily that holds the accounts delta column family
+    /// * `pubkey` - A Pubkey that holds the account
+    /// * `slot` - A u64 that holds the slot
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<(u64, AccountDelta)>, AggError>` - The slot the balance was recorded at and
+    ///   the recorded balance, or an error
+    fn snapshot_account_delta(
+        snapshot: &Snapshot,
+        cf: &ColumnFamily,
+        pubkey: &Pubkey,
+        slot: u64,
+    ) -> Result<Option<(u64, AccountDelta)>, AggError> {
+        let from = account_delta_key(pubkey, slot);
+        let mut iterator = snapshot.iterator_cf(cf, IteratorMode::From(&from, Direction::Reverse));
+        let Some((key, delta)) = iterator.next().transpose()? else {
+            return Ok(None);
+        };
+        let Some(observed_slot) = key.strip_prefix(pubkey.as_ref()) else {
+            return Ok(None);
+        };
+        Ok(Some((
+            u64::from_be_bytes(observed_slot.try_into()?),
+            from_slice::<AccountDelta>(&delta)?,
+        )))
+    }
+
+    /// This function reads the latest block number from a snapshot
+    ///
+    /// # Arguments
+    ///
+    /// * `snapshot` - A Snapshot that holds the consistent view of the db
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<u64>, AggError>` - A Result that holds the block number or an error
+    fn snapshot_latest_block(&self, snapshot: &Snapshot) -> Result<Option<u64>, AggError> {
+        let block_no = match snapshot.get_cf(self.cf(META_CF)?, LATEST_BLOCK_NO_KEY)? {
+            Some(block_no) => Some(block_no),
+            None => snapshot.get(LATEST_BLOCK_NO_KEY)?,
+        };
+        match block_no {
+            Some(block_no) => Ok(Some(from_slice::<u64>(&block_no)?)),
+            None => Ok(None),
         }
     }
 
@@ -336,7 +5587,7 @@ impl RocksDb {
     ///
     /// * `Option<u64>` - An Option that holds the block number
     fn get_latest_block(&self) -> Option<u64> {
-        if let Ok(Some(block_no)) = self.db.get(LATEST_BLOCK_NO_KEY) {
+        if let Ok(Some(block_no)) = get_meta(&self.db, LATEST_BLOCK_NO_KEY) {
             Some(from_slice::<u64>(&block_no).unwrap())
         } else {
             None
@@ -354,12 +5605,12 @@ impl RocksDb {
     ///
     /// * `Result<(), AggError>` - A Result that holds the result or an error
     fn add_block(&self, block_no: u64, block: &Block) -> Result<(), AggError> {
-        self.db
-            .put(format!("BlockNo{}", block_no), to_vec(block).unwrap())?;
+        self.put_cf(BLOCKS_CF, block_no.to_be_bytes(), self.codec.encode(block)?)?;
         Ok(())
     }
 
-    /// This function updates the latest block number and account map
+    /// This function makes a block the latest block and indexes the balances of the accounts
+    /// it touched
     ///
     /// # Arguments
     ///
@@ -368,41 +5619,117 @@ impl RocksDb {
     /// # Returns
     ///
     /// * `Result<(), AggError>` - A Result that holds the result or an error
-    fn update_latest_block_no_and_account_map(&self, block_no: u64) -> Result<(), AggError> {
-        if let Some(mut latest_block) = self.get_block(block_no) {
-            let mut account_map = BTreeMap::new();
-            if let Some(last_block_no) = self.get_latest_block() {
-                if let Some(last_block) = self.get_block(last_block_no) {
-                    if let Some(last_account_map) = last_block.get_account_map() {
-                        println!("Size of AccountMap {:?}", last_account_map.len());
-                        account_map = last_account_map;
-                    }
-                }
-            }
-            if let Some(block_account_map) = latest_block.get_account_map() {
-                for (account, balance) in block_account_map.iter() {
-                    account_map.insert(account.to_string(), *balance);
-                }
-            }
-            latest_block.set_account_map(account_map);
-            self.add_block(block_no, &latest_block)?;
-            self.db
-                .put(LATEST_BLOCK_NO_KEY, to_vec(&block_no).unwrap())?;
-        } else {
-            return Err(AggError::BlockNotFound);
+    fn apply_block_state(&self, block_no: u64) -> Result<(), AggError> {
+        let block = self.get_block(block_no).ok_or(AggError::BlockNotFound)?;
+        self.index_account_balances(block_no, &block)?;
+        self.put_cf(META_CF, LATEST_BLOCK_NO_KEY, to_vec(&block_no)?)?;
+        if let Some(slot) = block.slot() {
+            self.slot_tracker.set_latest_indexed(slot);
+        }
+        Ok(())
+    }
+
+    /// This function indexes the post balances of the accounts touched by a block at the block
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number
+    /// * `block` - A Block that holds the block
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn index_account_balances(&self, block_no: u64, block: &Block) -> Result<(), AggError> {
+        let Some(account_map) = block.get_account_map() else {
+            return Ok(());
+        };
+        let cf = self.cf(ACCOUNT_BALANCES_CF)?;
+        let mut batch = WriteBatch::default();
+        for (account, balance) in account_map {
+            let pubkey = Pubkey::from_str(&account)?;
+            batch.put_cf(
+                cf,
+                account_balance_key(&pubkey, block_no),
+                to_vec(&balance)?,
+            );
         }
+        self.db.write_opt(batch, &self.write_options)?;
         Ok(())
     }
 
-    /// This function handles the error
+    /// This function reads the balance of an account at a block from a snapshot. Blocks the
+    /// balance index does not cover are answered from the cumulative account map they were
+    /// stored with.
+    ///
+    /// # Arguments
+    ///
+    /// * `snapshot` - A Snapshot that holds the consistent view of the db
+    /// * `pubkey` - A string slice that holds the account
+    /// * `block_no` - A u64 that holds the block number
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<u64>, AggError>` - A Result that holds the balance, None if the account
+    ///   was not touched up to the block, or an error
+    fn snapshot_account_balance(
+        &self,
+        snapshot: &Snapshot,
+        pubkey: &str,
+        block_no: u64,
+    ) -> Result<Option<u64>, AggError> {
+        if block_no < self.balance_index_from {
+            return Ok(self
+                .snapshot_block(snapshot, block_no)?
+                .and_then(|block| block.get_account_balance(pubkey)));
+        }
+        let pubkey = Pubkey::from_str(pubkey)?;
+        let from = account_balance_key(&pubkey, block_no);
+        let mut iterator = snapshot.iterator_cf(
+            self.cf(ACCOUNT_BALANCES_CF)?,
+            IteratorMode::From(&from, Direction::Reverse),
+        );
+        match iterator.next().transpose()? {
+            Some((key, balance)) if key.starts_with(pubkey.as_ref()) => {
+                Ok(Some(from_slice::<u64>(&balance)?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// This function answers a query, the server may have timed out and dropped the receiver
+    ///
+    /// # Arguments
+    ///
+    /// * `reply` - A Reply that holds the sender of the query
+    /// * `response` - A Result that holds the response or an error
+    fn reply(reply: Reply, response: Result<Response, AggError>) {
+        if reply
+            .send(response.unwrap_or_else(Response::Error))
+            .is_err()
+        {
+            debug!(target: "db", "Query answered after the server stopped waiting");
+        }
+    }
+
+    /// This function handles the error ending a block or account stream
     ///
     /// # Arguments
     ///
-    /// * `server_sender` - A UnboundedSender<ProtocolMessage> that holds the server sender
+    /// * `subscriber` - A reference to the Sender<ProtocolMessage> of the stream
     /// * `error` - An AggError that holds the error
-    fn handle_error(server_sender: UnboundedSender<ProtocolMessage>, error: AggError) {
-        if let Err(error) = server_sender.send(ProtocolMessage::Error(error.to_string())) {
+    fn handle_error(subscriber: &Sender<ProtocolMessage>, error: AggError) {
+        if let Err(error) = subscriber.try_send(ProtocolMessage::Error(error)) {
             error!(target: "db", "Failed to send error message {:?}", error);
         }
     }
 }
+
+impl Worker for RocksDb {
+    fn set_shutdown(&mut self, shutdown: Shutdown) {
+        self.shutdown = Some(shutdown);
+    }
+
+    fn work(&mut self) -> BoxFuture<'_, ()> {
+        Box::pin(self.run())
+    }
+}
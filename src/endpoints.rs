@@ -0,0 +1,331 @@
+use crate::error::{AggError, ErrorContextExt};
+use crate::metrics::{RPC_ENDPOINT_AVAILABLE, RPC_ENDPOINT_FAILURES, RPC_ENDPOINT_LATENCY};
+use log::{info, warn};
+use solana_client::client_error::ClientErrorKind;
+use solana_client::rpc_client::RpcClient;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Time an endpoint is skipped after failing, doubled for every further failure in a row
+const BASE_COOLDOWN: Duration = Duration::from_secs(1);
+const MAX_COOLDOWN: Duration = Duration::from_secs(60);
+/// Timeout of a request to an endpoint
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// Weight of a new response in the moving average of the latency, one eighth
+const LATENCY_WEIGHT_DIVISOR: u32 = 8;
+/// Times the latency of the fastest endpoint above which an endpoint is left out of the rotation
+const SLOW_LATENCY_FACTOR: u32 = 4;
+/// Requests after which the next one goes to the next endpoint in turn however slow it is, so
+/// the latency of the slow endpoints is still measured
+const SLOW_ENDPOINT_PROBE_INTERVAL: usize = 16;
+
+/// This function splits the comma separated RPC urls of `node.chain_url`
+///
+/// # Arguments
+///
+/// * `chain_url` - A string slice that holds one url or several separated by commas
+///
+/// # Returns
+///
+/// * `Vec<String>` - The urls in the order given, without blanks
+pub fn chain_urls(chain_url: &str) -> Vec<String> {
+    chain_url
+        .split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// This function strips a url down to its scheme, host and port, leaving out the path, query
+/// and credentials that often carry an api key or are the secret themselves, e.g. a Slack
+/// incoming webhook
+///
+/// # Arguments
+///
+/// * `url` - A string slice that holds the url
+///
+/// # Returns
+///
+/// * `String` - The redacted url, `<invalid url>` when it does not parse
+pub fn redact(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(url) => match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}://{}:{}", url.scheme(), host, port),
+            (Some(host), None) => format!("{}://{}", url.scheme(), host),
+            (None, _) => format!("{}:", url.scheme()),
+        },
+        Err(_) => "<invalid url>".to_string(),
+    }
+}
+
+/// Health of an endpoint as seen from the responses to the requests sent to it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EndpointHealth {
+    /// Requests failed in a row with an error another node may not return, e.g. a timeout or
+    /// throttling
+    pub consecutive_failures: u32,
+    /// Moving average of the time the endpoint takes to respond
+    pub latency: Option<Duration>,
+    /// Until when the endpoint is only used if every other endpoint is cooling down as well
+    pub cooling_down_until: Option<Instant>,
+}
+
+impl EndpointHealth {
+    fn is_available(&self, now: Instant) -> bool {
+        !matches!(self.cooling_down_until, Some(until) if until > now)
+    }
+}
+
+struct Endpoint {
+    url: String,
+    /// The url redacted down to its host, the metrics and errors of the endpoint are labeled with
+    label: String,
+    client: RpcClient,
+    health: Mutex<EndpointHealth>,
+}
+
+impl Endpoint {
+    fn health(&self) -> EndpointHealth {
+        self.health
+            .lock()
+            .map(|health| health.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// The RPC endpoints blocks and slots are fetched from. Requests rotate between the endpoints,
+/// an endpoint failing with a timeout, an unavailable node or throttling is skipped for a
+/// cooldown growing with its failures in a row, so requests fail over to the other endpoints
+/// until it responds again.
+pub struct RpcEndpoints {
+    endpoints: Vec<Endpoint>,
+    next: AtomicUsize,
+}
+
+impl RpcEndpoints {
+    /// This function initializes the endpoints
+    ///
+    /// # Arguments
+    ///
+    /// * `urls` - A Vec<String> that holds the RPC urls
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, AggError>` - The endpoints or an error if no url is given
+    pub fn new(urls: Vec<String>) -> Result<Self, AggError> {
+        if urls.is_empty() {
+            return Err(AggError::ConfigError(
+                "node.chain_url must hold at least one url".to_string(),
+            ));
+        }
+        let mut endpoints: Vec<Endpoint> = Vec::with_capacity(urls.len());
+        for url in urls {
+            let mut label = redact(&url);
+            // Urls of the same provider often only differ by the api key in their path
+            if endpoints.iter().any(|endpoint| endpoint.label == label) {
+                label = format!("{}#{}", label, endpoints.len());
+            }
+            endpoints.push(Endpoint {
+                client: RpcClient::new_with_timeout(url.clone(), REQUEST_TIMEOUT),
+                url,
+                label,
+                health: Mutex::new(EndpointHealth::default()),
+            });
+        }
+        Ok(Self {
+            endpoints,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// This function returns the urls of the endpoints
+    pub fn urls(&self) -> Vec<&str> {
+        self.endpoints
+            .iter()
+            .map(|endpoint| endpoint.url.as_str())
+            .collect()
+    }
+
+    /// This function returns the health of the endpoint of the url
+    pub fn health(&self, url: &str) -> Option<EndpointHealth> {
+        self.endpoints
+            .iter()
+            .find(|endpoint| endpoint.url == url)
+            .map(Endpoint::health)
+    }
+
+    /// This function returns the labels of the endpoints, their urls redacted down to the host
+    pub fn labels(&self) -> Vec<&str> {
+        self.endpoints
+            .iter()
+            .map(|endpoint| endpoint.label.as_str())
+            .collect()
+    }
+
+    /// This function picks the endpoint of the next request, the next one in turn that is not
+    /// cooling down nor more than SLOW_LATENCY_FACTOR times slower than the fastest one or, when
+    /// all of them are cooling down, the one whose cooldown ends first. Every
+    /// SLOW_ENDPOINT_PROBE_INTERVAL requests the slow endpoints take their turn too.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - An Instant that holds the current time
+    ///
+    /// # Returns
+    ///
+    /// * `usize` - The index of the endpoint
+    pub fn pick(&self, now: Instant) -> usize {
+        let len = self.endpoints.len();
+        let request = self.next.fetch_add(1, Ordering::Relaxed);
+        let start = request % len;
+        let healths: Vec<EndpointHealth> = self.endpoints.iter().map(Endpoint::health).collect();
+        let fastest = healths
+            .iter()
+            .filter(|health| health.is_available(now))
+            .filter_map(|health| health.latency)
+            .min();
+        let probe = request.is_multiple_of(SLOW_ENDPOINT_PROBE_INTERVAL);
+        // Endpoints not measured yet take their turn until their first response
+        let is_fast = |health: &EndpointHealth| match (health.latency, fastest) {
+            (Some(latency), Some(fastest)) => {
+                probe || latency <= fastest.saturating_mul(SLOW_LATENCY_FACTOR)
+            }
+            _ => true,
+        };
+        (0..len)
+            .map(|offset| (start + offset) % len)
+            .find(|index| healths[*index].is_available(now) && is_fast(&healths[*index]))
+            .unwrap_or_else(|| {
+                (0..len)
+                    .min_by_key(|index| healths[*index].cooling_down_until)
+                    .unwrap_or(start)
+            })
+    }
+
+    /// This function records the response of an endpoint to a request. Only errors another node
+    /// may not return count as failures, a skipped slot is an answer like any other.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - A usize that holds the index of the endpoint
+    /// * `elapsed` - A Duration that holds the time the endpoint took to respond
+    /// * `error` - An Option<&AggError> that holds the error of the request, if it failed
+    /// * `now` - An Instant that holds the current time
+    pub fn record(&self, index: usize, elapsed: Duration, error: Option<&AggError>, now: Instant) {
+        let Some(endpoint) = self.endpoints.get(index) else {
+            return;
+        };
+        let Ok(mut health) = endpoint.health.lock() else {
+            return;
+        };
+        let label = endpoint.label.as_str();
+        match error {
+            Some(error) if error.is_transient() => {
+                health.consecutive_failures = health.consecutive_failures.saturating_add(1);
+                let cooldown = BASE_COOLDOWN
+                    .saturating_mul(2u32.saturating_pow(health.consecutive_failures - 1))
+                    .min(MAX_COOLDOWN);
+                health.cooling_down_until = Some(now + cooldown);
+                RPC_ENDPOINT_FAILURES.with_label_values(&[label]).inc();
+                RPC_ENDPOINT_AVAILABLE.with_label_values(&[label]).set(0);
+                if self.endpoints.len() > 1 {
+                    warn!(
+                        target: "rpc",
+                        "RPC endpoint {} failed {} time(s) in a row, failing over for {:?}: {}",
+                        label, health.consecutive_failures, cooldown, error
+                    );
+                }
+            }
+            _ => {
+                if health.consecutive_failures > 0 && self.endpoints.len() > 1 {
+                    info!(target: "rpc", "RPC endpoint {} responds again", label);
+                }
+                health.consecutive_failures = 0;
+                health.cooling_down_until = None;
+                let latency = match health.latency {
+                    Some(average) => {
+                        average - average / LATENCY_WEIGHT_DIVISOR
+                            + elapsed / LATENCY_WEIGHT_DIVISOR
+                    }
+                    None => elapsed,
+                };
+                health.latency = Some(latency);
+                RPC_ENDPOINT_LATENCY
+                    .with_label_values(&[label])
+                    .set(latency.as_secs_f64());
+                RPC_ENDPOINT_AVAILABLE.with_label_values(&[label]).set(1);
+            }
+        }
+    }
+
+    /// This function sends a request to the endpoint picked and records its response
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - A closure that sends the request with the client of the endpoint
+    ///
+    /// # Returns
+    ///
+    /// * `Result<T, AggError>` - The response or the error of the endpoint
+    pub fn call<T>(
+        &self,
+        request: impl Fn(&RpcClient) -> Result<T, AggError>,
+    ) -> Result<T, AggError> {
+        let index = self.pick(Instant::now());
+        let endpoint = &self.endpoints[index];
+        let started = Instant::now();
+        let response = request(&endpoint.client).map_err(without_url);
+        self.record(
+            index,
+            started.elapsed(),
+            response.as_ref().err(),
+            Instant::now(),
+        );
+        response.with_endpoint(&endpoint.label)
+    }
+
+    /// This function sends a request like `call` on the blocking thread pool, so the runtime
+    /// threads are not held while the endpoint responds
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - A closure that sends the request with the client of the endpoint
+    ///
+    /// # Returns
+    ///
+    /// * `Result<T, AggError>` - The response or the error of the endpoint
+    pub async fn call_blocking<T, F>(self: &Arc<Self>, request: F) -> Result<T, AggError>
+    where
+        T: Send + 'static,
+        F: Fn(&RpcClient) -> Result<T, AggError> + Send + 'static,
+    {
+        let endpoints = self.clone();
+        tokio::task::spawn_blocking(move || endpoints.call(request))
+            .await
+            .map_err(|err| AggError::TaskFailed(format!("RPC request task failed {}", err)))?
+    }
+}
+
+/// This function leaves the url out of the error of a request, the errors of reqwest name the url
+/// the request was sent to, which may carry the api key of the endpoint
+fn without_url(err: AggError) -> AggError {
+    match err {
+        AggError::ClientError(mut err) => {
+            err.kind = match err.kind {
+                ClientErrorKind::Reqwest(err) => ClientErrorKind::Reqwest(err.without_url()),
+                kind => kind,
+            };
+            AggError::ClientError(err)
+        }
+        err => err,
+    }
+}
+
+impl fmt::Debug for RpcEndpoints {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.labels()).finish()
+    }
+}
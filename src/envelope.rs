@@ -0,0 +1,405 @@
+use crate::config::Commitment;
+use crate::error::{AggError, ErrorBody};
+use crate::metrics::SLOTS;
+use crate::server::error_response;
+use crate::tenant::is_admin_request;
+use crate::util::Block;
+use actix_web::body::{self, BoxBody, MessageBody};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::error::ErrorInternalServerError;
+use actix_web::http::header::{HeaderValue, CONTENT_TYPE};
+use actix_web::{web, Error};
+use futures_util::future::{ready, LocalBoxFuture, Ready};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use solana_program::native_token::LAMPORTS_PER_SOL;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Slots every response is stamped with, updated by the db and the subscriber as they advance.
+/// Zero stands for a slot not known yet.
+#[derive(Default)]
+pub struct SlotTracker {
+    latest_indexed: AtomicU64,
+    finalized: AtomicU64,
+}
+
+impl SlotTracker {
+    /// This function records the slot of the latest block whose state is applied
+    ///
+    /// # Arguments
+    ///
+    /// * `slot` - A u64 that holds the slot
+    pub fn set_latest_indexed(&self, slot: u64) {
+        self.latest_indexed.store(slot, Ordering::Relaxed);
+        SLOTS
+            .with_label_values(&["latest_indexed"])
+            .set(slot as i64);
+    }
+
+    /// This function records the latest finalized slot of the chain
+    ///
+    /// # Arguments
+    ///
+    /// * `slot` - A u64 that holds the slot
+    pub fn set_finalized(&self, slot: u64) {
+        self.finalized.store(slot, Ordering::Relaxed);
+        SLOTS.with_label_values(&["finalized"]).set(slot as i64);
+    }
+
+    /// This function tells whether a slot is finalized, false while the finalized slot is not
+    /// known
+    ///
+    /// # Arguments
+    ///
+    /// * `slot` - A u64 that holds the slot
+    pub fn is_finalized(&self, slot: u64) -> bool {
+        let finalized = self.finalized.load(Ordering::Relaxed);
+        finalized != 0 && slot <= finalized
+    }
+
+    pub fn context(&self) -> SlotContext {
+        let known = |slot: u64| (slot != 0).then_some(slot);
+        SlotContext {
+            latest_indexed: known(self.latest_indexed.load(Ordering::Relaxed)),
+            finalized: known(self.finalized.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct SlotContext {
+    /// Slot of the latest block whose state is applied, None before the first block
+    pub latest_indexed: Option<u64>,
+    /// Latest finalized slot of the chain, None on instances not ingesting from a node
+    pub finalized: Option<u64>,
+}
+
+/// Which stored blocks a query asking for `?commitment=finalized` may see. Blocks ingested at
+/// `confirmed` are only finalized once the chain finalized their slot, blocks ingested at
+/// `finalized` always are.
+pub struct Finality {
+    /// Commitment of the ingested blocks
+    ingested: Commitment,
+    slot_tracker: Arc<SlotTracker>,
+}
+
+impl Finality {
+    pub fn new(ingested: Commitment, slot_tracker: Arc<SlotTracker>) -> Self {
+        Finality {
+            ingested,
+            slot_tracker,
+        }
+    }
+
+    /// This function tells whether the stored blocks must be checked against the finalized slot
+    /// to answer a query at the requested commitment
+    ///
+    /// # Arguments
+    ///
+    /// * `requested` - An Option<Commitment> that holds the commitment the query asked for
+    pub fn filters(&self, requested: Option<Commitment>) -> bool {
+        requested == Some(Commitment::Finalized) && self.ingested == Commitment::Confirmed
+    }
+
+    /// This function tells whether a block reached the commitment a query asked for. A block
+    /// stored before its slot was kept is never taken for finalized.
+    ///
+    /// # Arguments
+    ///
+    /// * `requested` - An Option<Commitment> that holds the commitment the query asked for
+    /// * `slot` - An Option<u64> that holds the slot of the block
+    pub fn admits(&self, requested: Option<Commitment>, slot: Option<u64>) -> bool {
+        !self.filters(requested) || slot.is_some_and(|slot| self.slot_tracker.is_finalized(slot))
+    }
+
+    /// This function fails a lookup of a block that did not reach the commitment a query asked for
+    ///
+    /// # Arguments
+    ///
+    /// * `requested` - An Option<Commitment> that holds the commitment the query asked for
+    /// * `slot` - An Option<u64> that holds the slot of the block
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - An error if the block is not finalized yet
+    pub fn check(&self, requested: Option<Commitment>, slot: Option<u64>) -> Result<(), AggError> {
+        if self.admits(requested, slot) {
+            return Ok(());
+        }
+        Err(AggError::NotFinalized(match slot {
+            Some(slot) => format!("slot {} is confirmed but not finalized yet", slot),
+            None => "the block was stored without its slot".to_string(),
+        }))
+    }
+}
+
+/// Body of every JSON response
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResponseEnvelope<T> {
+    pub data: T,
+    pub slot_context: SlotContext,
+    pub took_ms: u64,
+}
+
+/// Fields holding an amount of lamports
+const LAMPORT_FIELDS: [&str; 4] = ["balance", "change", "fee", "total_fees"];
+/// Fields holding a map from accounts to an amount of lamports
+const LAMPORT_MAP_FIELDS: [&str; 1] = ["account_map"];
+/// Fields holding a map keyed by data, such as accounts or transaction ids, whose keys are kept
+const DATA_KEYED_FIELDS: [&str; 6] = [
+    "tx_map",
+    "account_map",
+    "token_balances",
+    "first_seen",
+    "program_calls",
+    "token_transferred",
+];
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Units {
+    #[default]
+    Lamports,
+    Sol,
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldCase {
+    #[default]
+    Snake,
+    Camel,
+}
+
+/// How much of the transactions of a block a block response holds
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Expand {
+    /// A TxSummary per transaction, in the order of the block
+    #[default]
+    Summary,
+    /// The full records in `tx_map`, metadata included
+    Full,
+}
+
+/// Shape of a JSON response, chosen per request with `?units=lamports|sol`, `?case=snake|camel`
+/// and, for block responses, `?expand=summary|full`
+#[derive(Deserialize, Default, Clone, Copy)]
+pub struct ResponseFormat {
+    #[serde(default)]
+    pub units: Units,
+    #[serde(default)]
+    pub case: FieldCase,
+    #[serde(default)]
+    pub expand: Expand,
+}
+
+impl ResponseFormat {
+    /// This function reshapes a JSON value to the format
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - A Value that holds the response
+    /// * `field` - An Option<&str> that holds the name of the field holding the value
+    ///
+    /// # Returns
+    ///
+    /// * `Value` - The reshaped value
+    pub fn apply(&self, value: Value, field: Option<&str>) -> Value {
+        match value {
+            Value::Number(_) if field.is_some_and(|field| LAMPORT_FIELDS.contains(&field)) => {
+                self.lamports(value)
+            }
+            Value::Object(object) => {
+                let keyed_by_data = field.is_some_and(|field| DATA_KEYED_FIELDS.contains(&field));
+                let lamport_map = field.is_some_and(|field| LAMPORT_MAP_FIELDS.contains(&field));
+                object
+                    .into_iter()
+                    .map(|(key, value)| match (keyed_by_data, lamport_map) {
+                        (true, true) => (key, self.lamports(value)),
+                        (true, false) => (key, self.apply(value, None)),
+                        _ => {
+                            let value = self.apply(value, Some(&key));
+                            (self.rename(key), value)
+                        }
+                    })
+                    .collect()
+            }
+            Value::Array(values) => values
+                .into_iter()
+                .map(|value| self.apply(value, field))
+                .collect(),
+            value => value,
+        }
+    }
+
+    /// This function projects a block to the transactions asked for, summaries by default
+    ///
+    /// # Arguments
+    ///
+    /// * `block` - A Block that holds the block
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Value, serde_json::Error>` - The block as served, or an error when it does not
+    ///   serialize
+    pub fn project_block(&self, block: Block) -> Result<Value, serde_json::Error> {
+        if self.expand == Expand::Full {
+            return serde_json::to_value(block);
+        }
+        let (block, transactions) = block.into_tx_summaries();
+        let mut value = serde_json::to_value(block)?;
+        if let Value::Object(object) = &mut value {
+            object.remove("tx_map");
+            object.insert(
+                "transactions".to_string(),
+                serde_json::to_value(transactions)?,
+            );
+        }
+        Ok(value)
+    }
+
+    fn lamports(&self, value: Value) -> Value {
+        match (self.units, value.as_f64()) {
+            (Units::Sol, Some(lamports)) => Value::from(lamports / LAMPORTS_PER_SOL as f64),
+            _ => value,
+        }
+    }
+
+    fn rename(&self, key: String) -> String {
+        if self.case == FieldCase::Snake || !key.contains('_') {
+            return key;
+        }
+        let mut renamed = String::with_capacity(key.len());
+        let mut upper = false;
+        for c in key.chars() {
+            if c == '_' {
+                upper = true;
+            } else if upper {
+                renamed.extend(c.to_uppercase());
+                upper = false;
+            } else {
+                renamed.push(c);
+            }
+        }
+        renamed
+    }
+}
+
+/// Middleware wrapping the body of every JSON response in a ResponseEnvelope shaped to the
+/// ResponseFormat of the request, so handlers keep responding with their data only. Error bodies
+/// and other responses, metrics and websocket upgrades among them, pass through untouched, and
+/// admin responses keep the default format.
+pub struct Envelope(pub Arc<SlotTracker>);
+
+impl<S, B> Transform<S, ServiceRequest> for Envelope
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = EnvelopeMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(EnvelopeMiddleware {
+            service,
+            slot_tracker: self.0.clone(),
+        }))
+    }
+}
+
+pub struct EnvelopeMiddleware<S> {
+    service: S,
+    slot_tracker: Arc<SlotTracker>,
+}
+
+impl<S, B> Service<ServiceRequest> for EnvelopeMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let started = Instant::now();
+        let slot_tracker = self.slot_tracker.clone();
+        let format = if is_admin_request(&req) {
+            Ok(ResponseFormat::default())
+        } else {
+            web::Query::<ResponseFormat>::from_query(req.query_string()).map(web::Query::into_inner)
+        };
+        match format {
+            Ok(format) => {
+                let fut = self.service.call(req);
+                Box::pin(async move { wrap(fut.await?, format, &slot_tracker, started).await })
+            }
+            Err(err) => {
+                let response =
+                    req.into_response(error_response(&AggError::BadRequest(err.to_string())));
+                Box::pin(async move {
+                    wrap(response, ResponseFormat::default(), &slot_tracker, started).await
+                })
+            }
+        }
+    }
+}
+
+/// This function wraps the body of a JSON response in a ResponseEnvelope
+///
+/// # Arguments
+///
+/// * `response` - A ServiceResponse that holds the response of the handler
+/// * `format` - A ResponseFormat that holds the format requested
+/// * `slot_tracker` - A SlotTracker that holds the slots the response is stamped with
+/// * `started` - An Instant that holds when the request was received
+///
+/// # Returns
+///
+/// * `Result<ServiceResponse<BoxBody>, Error>` - The wrapped response or an error
+async fn wrap<B: MessageBody + 'static>(
+    response: ServiceResponse<B>,
+    format: ResponseFormat,
+    slot_tracker: &SlotTracker,
+    started: Instant,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let is_json =
+        response.headers().get(CONTENT_TYPE) == Some(&HeaderValue::from_static("application/json"));
+    if !is_json {
+        return Ok(response.map_into_boxed_body());
+    }
+    let (request, response) = response.into_parts();
+    let (response, body) = response.into_parts();
+    let body = body::to_bytes(body)
+        .await
+        .map_err(|err| ErrorInternalServerError(err.into().to_string()))?;
+    // Error bodies are answered as they are, so every failed request has the same body
+    let is_error_body =
+        !response.status().is_success() && serde_json::from_slice::<ErrorBody>(&body).is_ok();
+    let body = match serde_json::from_slice::<Value>(&body) {
+        Ok(data) if !is_error_body => {
+            let envelope = serde_json::to_value(ResponseEnvelope {
+                data,
+                slot_context: slot_tracker.context(),
+                took_ms: started.elapsed().as_millis() as u64,
+            })
+            .map_err(ErrorInternalServerError)?;
+            serde_json::to_vec(&format.apply(envelope, None))
+                .map_err(ErrorInternalServerError)?
+                .into()
+        }
+        _ => body,
+    };
+    Ok(ServiceResponse::new(
+        request,
+        response.set_body(BoxBody::new(body)),
+    ))
+}
@@ -1,102 +1,421 @@
 use crate::util::ProtocolMessage;
-use solana_client::client_error::ClientError;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use serde::{Deserialize, Serialize};
+use solana_client::client_error::{ClientError, ClientErrorKind};
+use solana_client::pubsub_client::PubsubClientError;
+use solana_client::rpc_custom_error::{
+    JSON_RPC_SERVER_ERROR_BLOCK_NOT_AVAILABLE,
+    JSON_RPC_SERVER_ERROR_BLOCK_STATUS_NOT_AVAILABLE_YET,
+    JSON_RPC_SERVER_ERROR_MIN_CONTEXT_SLOT_NOT_REACHED, JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY,
+};
+use solana_client::rpc_request::RpcError;
 use solana_program::pubkey::ParsePubkeyError;
 use std::array::TryFromSliceError;
-use std::fmt::{Debug, Display, Formatter};
-use std::io::Error;
+use std::fmt::{Display, Formatter};
+use thiserror::Error;
 use tokio::sync::mpsc::error::SendError;
+
+#[derive(Error, Debug)]
 pub enum AggError {
-    ClientError(ClientError),
-    UnableToParsePublicKey(ParsePubkeyError),
-    ConversionError(TryFromSliceError),
-    MpscChannelError(SendError<ProtocolMessage>),
+    #[error("Client Error: {0}")]
+    ClientError(Box<ClientError>),
+    #[error("Unable to parse public key: {0}")]
+    UnableToParsePublicKey(#[from] ParsePubkeyError),
+    #[error("Conversion Error: {0}")]
+    ConversionError(#[from] TryFromSliceError),
+    /// The unsent message is boxed, as messages carry errors themselves
+    #[error("Mpsc Channel Error: {0}")]
+    MpscChannelError(Box<SendError<ProtocolMessage>>),
+    #[error("Oneshot Channel Error")]
     OneshotChannelError,
-    DbError(rocksdb::Error),
-    JsonError(serde_json::Error),
-    ServerError(std::io::Error),
+    #[error("Db Error: {0}")]
+    DbError(#[from] rocksdb::Error),
+    #[error("Json Error: {0}")]
+    JsonError(#[from] serde_json::Error),
+    #[error("Server Error {0}")]
+    ServerError(#[from] std::io::Error),
+    #[error("Block Not Found")]
     BlockNotFound,
+    #[error("No Block Finalised")]
     NoBlockFinalised,
+    #[error("Transaction Not Found")]
     TxNotFound,
+    #[error("Aggregation Rule Not Found")]
+    RuleNotFound,
+    #[error("Database Opened In Read Only Mode")]
+    ReadOnly,
+    #[error("Missing Column Family: {0}")]
+    MissingColumnFamily(&'static str),
+    #[error("Config Error: {0}")]
+    ConfigError(String),
+    #[error("Replication Error: {0}")]
+    ReplicationError(String),
+    #[error("Recovery Error: {0}")]
+    RecoveryError(String),
+    #[error("Snapshot Error: {0}")]
+    SnapshotError(String),
+    #[error("Codec Error: {0}")]
+    CodecError(String),
+    #[error("Invalid Chunk: {0} of {1}")]
+    InvalidChunk(u64, u64),
+    #[error("Incomplete Block: {0} of {1} chunks")]
+    IncompleteBlock(u64, u64),
+    #[error("Http Client Error: {0}")]
+    HttpClientError(#[from] reqwest::Error),
+    #[error("gRPC Error: {0}")]
+    GrpcError(#[from] tonic::transport::Error),
+    #[error("Query would read more than {0} blocks, narrow the range or lower the limit")]
+    ScanLimitExceeded(u64),
+    #[error("Response would exceed {0} bytes, request a smaller range")]
+    ResponseTooLarge(u64),
+    #[error("Export Error: {0}")]
+    ExportError(String),
+    #[error("Backfill Error: {0}")]
+    BackfillError(String),
+    /// A job or export is not in a state allowing the requested change
+    #[error("Job Conflict: {0}")]
+    JobConflict(String),
+    /// Genesis hash the db was created for and the genesis hash of the chain
+    #[error(
+        "Genesis Hash Mismatch: the db holds data of the cluster with genesis hash {0} but the chain has {1}"
+    )]
+    GenesisHashMismatch(String, String),
+    #[error("Undecodable Transaction")]
+    UndecodableTransaction,
+    /// An instruction lacks the data or accounts its program expects
+    #[error("Malformed Instruction: {0}")]
+    MalformedInstruction(String),
+    /// The metadata of a transaction does not match its message
+    #[error("Malformed Transaction: {0}")]
+    MalformedTransaction(String),
+    #[error("PubSub Error: {0}")]
+    PubsubError(String),
+    /// A component answered a request with a message it does not answer it with
+    #[error("Unexpected Reply: {0}")]
+    UnexpectedReply(String),
+    /// Status and message of an error response of another aggregator instance
+    #[error("Api Error: {0} {1}")]
+    ApiError(u16, String),
+    /// Name of the plugin and the reason it gave
+    #[error("Rejected By Plugin {0}: {1}")]
+    PluginRejected(String, String),
+    /// Number of startup checks that failed, each logged with what to fix
+    #[error("Startup Check Failed: {0} check(s) failed, fix them or start with --force")]
+    StartupCheckFailed(usize),
+    #[error("Profiling Error: {0}")]
+    ProfilingError(String),
+    #[error("Profile In Progress: another CPU profile is being captured")]
+    ProfileInProgress,
+    /// The request carries no api key or one of no tenant
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+    /// The client spent its budget or quota for the current window
+    #[error("Rate Limited: {0}")]
+    RateLimited(String),
+    /// What an assembled block violates, it is dead-lettered instead of stored
+    #[error("Invariant Violation: {0}")]
+    InvariantViolation(String),
+    /// The block asked for did not reach the commitment the query asked for
+    #[error("Not Finalized: {0}")]
+    NotFinalized(String),
+    /// Malformed or out of range parameters of a request
+    #[error("Bad Request: {0}")]
+    BadRequest(String),
+    /// What the request asked for, a job, an export or a webhook among others
+    #[error("Not Found: {0}")]
+    NotFound(String),
+    #[error("Query Timed Out")]
+    QueryTimedOut,
+    /// The circuit breaker of the calls made to the node while answering requests is open
+    #[error("Upstream Unavailable: {0}")]
+    UpstreamUnavailable(String),
+    /// A spawned task panicked or was cancelled before finishing
+    #[error("Task Failed: {0}")]
+    TaskFailed(String),
+    #[error("{source} ({context})")]
+    WithContext {
+        context: ErrorContext,
+        source: Box<AggError>,
+    },
 }
 
-impl Display for AggError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let err_mgs = match self {
-            AggError::ClientError(err) => format!("Client Error: {}", err),
-            AggError::UnableToParsePublicKey(err) => format!("Unable to parse public key: {}", err),
-            AggError::ConversionError(err) => format!("Conversion Error: {}", err),
-            AggError::MpscChannelError(err) => format!("Mpsc Channel Error: {}", err),
-            AggError::OneshotChannelError => "Oneshot Channel Error".to_string(),
-            AggError::DbError(err) => format!("Db Error: {}", err),
-            AggError::JsonError(err) => format!("Json Error: {}", err),
-            AggError::BlockNotFound => "Block Not Found".to_string(),
-            AggError::NoBlockFinalised => "No Block Finalised".to_string(),
-            AggError::TxNotFound => "Transaction Not Found".to_string(),
-            AggError::ServerError(err) => format!("Server Error {}", err),
-        };
-        write!(f, "{}", err_mgs)
-    }
+/// Body every failed request of the API is answered with, `{"error": {"code", "message"}}`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ErrorBody {
+    pub error: ErrorDetails,
 }
 
-impl Debug for AggError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let err_mgs = match self {
-            AggError::ClientError(err) => format!("Client Error: {:?}", err),
-            AggError::UnableToParsePublicKey(err) => {
-                format!("Unable to parse public key: {:?}", err)
-            }
-            AggError::ConversionError(err) => format!("Conversion Error: {:?}", err),
-            AggError::MpscChannelError(err) => format!("Mpsc Channel Error: {:?}", err),
-            AggError::OneshotChannelError => "Oneshot Channel Error".to_string(),
-            AggError::DbError(err) => format!("Db Error: {:?}", err),
-            AggError::JsonError(err) => format!("Json Error: {:?}", err),
-            AggError::BlockNotFound => "Block Not Found".to_string(),
-            AggError::NoBlockFinalised => "No Block Finalised".to_string(),
-            AggError::TxNotFound => "Transaction Not Found".to_string(),
-            AggError::ServerError(err) => format!("Server Error {:?}", err),
-        };
-        write!(f, "{}", err_mgs)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ErrorDetails {
+    /// Stable machine-readable code of the error, e.g. `tx_not_found`
+    pub code: String,
+    pub message: String,
+}
+
+impl From<&AggError> for ErrorBody {
+    fn from(err: &AggError) -> Self {
+        ErrorBody {
+            error: ErrorDetails {
+                code: err.code().to_string(),
+                message: err.to_string(),
+            },
+        }
     }
 }
 
-impl From<ClientError> for AggError {
-    fn from(err: ClientError) -> Self {
-        Self::ClientError(err)
+/// Requests failing with an error are answered with the status of the error and its ErrorBody
+impl ResponseError for AggError {
+    fn status_code(&self) -> StatusCode {
+        self.status()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status()).json(ErrorBody::from(self))
     }
 }
 
-impl From<ParsePubkeyError> for AggError {
-    fn from(err: ParsePubkeyError) -> Self {
-        Self::UnableToParsePublicKey(err)
+/// What an error happened on, attached with the `with_*` functions of ErrorContextExt
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorContext {
+    Slot(u64),
+    Block(u64),
+    /// Key of the db
+    Key(String),
+    /// Url of a node or another instance
+    Endpoint(String),
+}
+
+impl Display for ErrorContext {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorContext::Slot(slot) => write!(f, "slot {}", slot),
+            ErrorContext::Block(block_no) => write!(f, "block {}", block_no),
+            ErrorContext::Key(key) => write!(f, "key {}", key),
+            ErrorContext::Endpoint(url) => write!(f, "endpoint {}", url),
+        }
     }
 }
 
-impl From<TryFromSliceError> for AggError {
-    fn from(err: TryFromSliceError) -> Self {
-        Self::ConversionError(err)
+impl AggError {
+    /// This function returns the stable code and HTTP status of an error. It is the one table
+    /// mapping errors to responses, codes never change once released.
+    ///
+    /// # Returns
+    ///
+    /// * `(&'static str, StatusCode)` - The machine-readable code and the status
+    fn spec(&self) -> (&'static str, StatusCode) {
+        match self.root() {
+            AggError::ClientError(_) => ("node_client", StatusCode::BAD_GATEWAY),
+            AggError::UnableToParsePublicKey(_) => ("invalid_public_key", StatusCode::BAD_REQUEST),
+            AggError::ConversionError(_) => ("conversion", StatusCode::INTERNAL_SERVER_ERROR),
+            AggError::MpscChannelError(_) => ("channel_closed", StatusCode::SERVICE_UNAVAILABLE),
+            AggError::OneshotChannelError => ("reply_dropped", StatusCode::INTERNAL_SERVER_ERROR),
+            AggError::DbError(_) => ("db", StatusCode::INTERNAL_SERVER_ERROR),
+            AggError::JsonError(_) => ("json", StatusCode::INTERNAL_SERVER_ERROR),
+            AggError::ServerError(_) => ("io", StatusCode::INTERNAL_SERVER_ERROR),
+            AggError::BlockNotFound => ("block_not_found", StatusCode::NOT_FOUND),
+            AggError::NoBlockFinalised => ("no_block_finalised", StatusCode::SERVICE_UNAVAILABLE),
+            AggError::TxNotFound => ("tx_not_found", StatusCode::NOT_FOUND),
+            AggError::RuleNotFound => ("rule_not_found", StatusCode::NOT_FOUND),
+            AggError::ReadOnly => ("read_only", StatusCode::CONFLICT),
+            AggError::MissingColumnFamily(_) => {
+                ("missing_column_family", StatusCode::INTERNAL_SERVER_ERROR)
+            }
+            AggError::ConfigError(_) => ("config", StatusCode::BAD_REQUEST),
+            AggError::ReplicationError(_) => ("replication", StatusCode::BAD_GATEWAY),
+            AggError::RecoveryError(_) => ("recovery", StatusCode::INTERNAL_SERVER_ERROR),
+            AggError::SnapshotError(_) => ("snapshot", StatusCode::INTERNAL_SERVER_ERROR),
+            AggError::CodecError(_) => ("codec", StatusCode::INTERNAL_SERVER_ERROR),
+            AggError::InvalidChunk(..) => ("invalid_chunk", StatusCode::INTERNAL_SERVER_ERROR),
+            AggError::IncompleteBlock(..) => {
+                ("incomplete_block", StatusCode::INTERNAL_SERVER_ERROR)
+            }
+            AggError::HttpClientError(_) => ("http_client", StatusCode::BAD_GATEWAY),
+            AggError::GrpcError(_) => ("grpc", StatusCode::INTERNAL_SERVER_ERROR),
+            AggError::ScanLimitExceeded(_) => {
+                ("scan_limit_exceeded", StatusCode::UNPROCESSABLE_ENTITY)
+            }
+            AggError::ResponseTooLarge(_) => ("response_too_large", StatusCode::PAYLOAD_TOO_LARGE),
+            AggError::ExportError(_) => ("export", StatusCode::BAD_REQUEST),
+            AggError::BackfillError(_) => ("backfill", StatusCode::BAD_REQUEST),
+            AggError::JobConflict(_) => ("job_conflict", StatusCode::CONFLICT),
+            AggError::GenesisHashMismatch(..) => ("genesis_hash_mismatch", StatusCode::CONFLICT),
+            AggError::UndecodableTransaction => {
+                ("undecodable_transaction", StatusCode::UNPROCESSABLE_ENTITY)
+            }
+            AggError::MalformedInstruction(_) => {
+                ("malformed_instruction", StatusCode::UNPROCESSABLE_ENTITY)
+            }
+            AggError::MalformedTransaction(_) => {
+                ("malformed_transaction", StatusCode::UNPROCESSABLE_ENTITY)
+            }
+            AggError::PubsubError(_) => ("pubsub", StatusCode::BAD_GATEWAY),
+            AggError::UnexpectedReply(_) => ("unexpected_reply", StatusCode::INTERNAL_SERVER_ERROR),
+            AggError::ApiError(..) => ("api", StatusCode::BAD_GATEWAY),
+            AggError::PluginRejected(..) => ("plugin_rejected", StatusCode::UNPROCESSABLE_ENTITY),
+            AggError::StartupCheckFailed(_) => {
+                ("startup_check_failed", StatusCode::INTERNAL_SERVER_ERROR)
+            }
+            AggError::ProfilingError(_) => ("profiling", StatusCode::INTERNAL_SERVER_ERROR),
+            AggError::ProfileInProgress => ("profile_in_progress", StatusCode::CONFLICT),
+            AggError::Unauthorized(_) => ("unauthorized", StatusCode::UNAUTHORIZED),
+            AggError::RateLimited(_) => ("rate_limited", StatusCode::TOO_MANY_REQUESTS),
+            AggError::NotFinalized(_) => ("not_finalized", StatusCode::NOT_FOUND),
+            AggError::InvariantViolation(_) => {
+                ("invariant_violation", StatusCode::INTERNAL_SERVER_ERROR)
+            }
+            AggError::BadRequest(_) => ("bad_request", StatusCode::BAD_REQUEST),
+            AggError::NotFound(_) => ("not_found", StatusCode::NOT_FOUND),
+            AggError::QueryTimedOut => ("query_timed_out", StatusCode::GATEWAY_TIMEOUT),
+            AggError::UpstreamUnavailable(_) => {
+                ("upstream_unavailable", StatusCode::SERVICE_UNAVAILABLE)
+            }
+            AggError::TaskFailed(_) => ("task_failed", StatusCode::INTERNAL_SERVER_ERROR),
+            AggError::WithContext { .. } => ("internal", StatusCode::INTERNAL_SERVER_ERROR),
+        }
+    }
+
+    /// This function returns the stable machine-readable code of the error, e.g. `tx_not_found`
+    pub fn code(&self) -> &'static str {
+        self.spec().0
+    }
+
+    /// This function returns the HTTP status a request failing with the error is answered with
+    pub fn status(&self) -> StatusCode {
+        self.spec().1
+    }
+
+    /// This function tells whether an operation failing with the error may succeed when retried,
+    /// e.g. an RPC timeout or a node still catching up, while a permanent error such as a skipped
+    /// slot or an undecodable transaction fails the same way on every attempt
+    pub fn is_transient(&self) -> bool {
+        match self.root() {
+            AggError::ClientError(err) => match err.kind() {
+                ClientErrorKind::Io(_) | ClientErrorKind::Middleware(_) => true,
+                ClientErrorKind::Reqwest(err) => Self::is_transient_http(err),
+                ClientErrorKind::RpcError(RpcError::RpcRequestError(_)) => true,
+                ClientErrorKind::RpcError(RpcError::RpcResponseError { code, .. }) => matches!(
+                    *code,
+                    JSON_RPC_SERVER_ERROR_BLOCK_NOT_AVAILABLE
+                        | JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY
+                        | JSON_RPC_SERVER_ERROR_BLOCK_STATUS_NOT_AVAILABLE_YET
+                        | JSON_RPC_SERVER_ERROR_MIN_CONTEXT_SLOT_NOT_REACHED
+                ),
+                _ => false,
+            },
+            AggError::HttpClientError(err) => Self::is_transient_http(err),
+            AggError::DbError(err) => matches!(
+                err.kind(),
+                rocksdb::ErrorKind::Busy
+                    | rocksdb::ErrorKind::TryAgain
+                    | rocksdb::ErrorKind::TimedOut
+                    | rocksdb::ErrorKind::Incomplete
+            ),
+            AggError::ServerError(err) => matches!(
+                err.kind(),
+                std::io::ErrorKind::Interrupted
+                    | std::io::ErrorKind::TimedOut
+                    | std::io::ErrorKind::WouldBlock
+                    | std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionRefused
+            ),
+            AggError::PubsubError(_) | AggError::ReplicationError(_) => true,
+            AggError::ApiError(status, _) => {
+                *status >= 500 || *status == StatusCode::TOO_MANY_REQUESTS.as_u16()
+            }
+            _ => false,
+        }
+    }
+
+    /// This function tells whether a failed HTTP request may succeed when retried, which is the
+    /// case of timeouts, connection failures, throttling and server errors
+    fn is_transient_http(err: &reqwest::Error) -> bool {
+        err.is_timeout()
+            || err.is_connect()
+            || err.status().is_some_and(|status| {
+                status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            })
+    }
+
+    /// This function returns the error without the context attached to it
+    pub fn root(&self) -> &AggError {
+        match self {
+            AggError::WithContext { source, .. } => source.root(),
+            error => error,
+        }
+    }
+
+    /// This function returns the contexts attached to the error, the innermost first
+    pub fn contexts(&self) -> Vec<&ErrorContext> {
+        match self {
+            AggError::WithContext { context, source } => {
+                let mut contexts = source.contexts();
+                contexts.push(context);
+                contexts
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// This function attaches a context to the error
+    ///
+    /// # Arguments
+    ///
+    /// * `context` - An ErrorContext that holds what the error happened on
+    ///
+    /// # Returns
+    ///
+    /// * `AggError` - The error with its context
+    pub fn context(self, context: ErrorContext) -> AggError {
+        AggError::WithContext {
+            context,
+            source: Box::new(self),
+        }
     }
 }
 
-impl From<SendError<ProtocolMessage>> for AggError {
-    fn from(err: SendError<ProtocolMessage>) -> Self {
-        Self::MpscChannelError(err)
+/// Attaches a context to the error of a Result
+pub trait ErrorContextExt<T> {
+    fn with_slot(self, slot: u64) -> Result<T, AggError>;
+    fn with_block(self, block_no: u64) -> Result<T, AggError>;
+    fn with_key(self, key: impl Display) -> Result<T, AggError>;
+    fn with_endpoint(self, url: impl Display) -> Result<T, AggError>;
+}
+
+impl<T, E: Into<AggError>> ErrorContextExt<T> for Result<T, E> {
+    fn with_slot(self, slot: u64) -> Result<T, AggError> {
+        self.map_err(|err| err.into().context(ErrorContext::Slot(slot)))
+    }
+
+    fn with_block(self, block_no: u64) -> Result<T, AggError> {
+        self.map_err(|err| err.into().context(ErrorContext::Block(block_no)))
+    }
+
+    fn with_key(self, key: impl Display) -> Result<T, AggError> {
+        self.map_err(|err| err.into().context(ErrorContext::Key(key.to_string())))
+    }
+
+    fn with_endpoint(self, url: impl Display) -> Result<T, AggError> {
+        self.map_err(|err| err.into().context(ErrorContext::Endpoint(url.to_string())))
     }
 }
 
-impl From<rocksdb::Error> for AggError {
-    fn from(err: rocksdb::Error) -> Self {
-        Self::DbError(err)
+impl From<ClientError> for AggError {
+    fn from(err: ClientError) -> Self {
+        Self::ClientError(Box::new(err))
     }
 }
 
-impl From<serde_json::Error> for AggError {
-    fn from(err: serde_json::Error) -> Self {
-        Self::JsonError(err)
+impl From<PubsubClientError> for AggError {
+    fn from(err: PubsubClientError) -> Self {
+        Self::PubsubError(err.to_string())
     }
 }
 
-impl From<std::io::Error> for AggError {
-    fn from(value: Error) -> Self {
-        Self::ServerError(value)
+impl From<SendError<ProtocolMessage>> for AggError {
+    fn from(err: SendError<ProtocolMessage>) -> Self {
+        Self::MpscChannelError(Box::new(err))
     }
 }
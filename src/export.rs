@@ -0,0 +1,512 @@
+use crate::config::ExportConfig;
+use crate::error::AggError;
+use crate::util::{
+    Block, ExportParams, ExportProgress, Instruction, JobState, JobTask, ProtocolMessage, Response,
+};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::SeekFrom;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Blocks read from the db per page of a shard, progress is checkpointed after every page
+const EXPORT_PAGE_SIZE: u64 = 100;
+const MAX_EXPORT_SHARDS: u64 = 64;
+/// Checkpoint of a job, next to its shard files
+const JOB_FILE: &str = "job.json";
+const CSV_HEADER: &str =
+    "slot,block_no,block_time,tx_id,fee,succeeded,accounts,transfers,transferred_sol\n";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    /// One row per transaction
+    Csv,
+    /// One stored block per line
+    Jsonl,
+}
+
+impl ExportFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Jsonl => "jsonl",
+        }
+    }
+
+    /// This function appends a block to a shard file in the format
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer` - A Vec<u8> the block is written to
+    /// * `block_no` - A u64 that holds the block number
+    /// * `block` - A Block that holds the block
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn write_block(
+        &self,
+        buffer: &mut Vec<u8>,
+        block_no: u64,
+        block: &Block,
+    ) -> Result<(), AggError> {
+        match self {
+            ExportFormat::Jsonl => {
+                serde_json::to_writer(&mut *buffer, &ExportedBlock { block_no, block })?;
+                buffer.push(b'\n');
+            }
+            ExportFormat::Csv => {
+                for (tx_id, tx) in block.transactions() {
+                    let transfers = tx
+                        .instructions()
+                        .iter()
+                        .filter(|instruction| !matches!(instruction, Instruction::Authority { .. }))
+                        .count();
+                    buffer.extend(
+                        format!(
+                            "{},{},{},{},{},{},{},{},{}\n",
+                            block.slot().map_or(String::new(), |slot| slot.to_string()),
+                            block_no,
+                            block
+                                .block_time()
+                                .map_or(String::new(), |time| time.to_string()),
+                            tx_id,
+                            tx.fee().map_or(String::new(), |fee| fee.to_string()),
+                            tx.succeeded()
+                                .map_or(String::new(), |succeeded| succeeded.to_string()),
+                            tx.accounts().len(),
+                            transfers,
+                            tx.sol_transferred()
+                        )
+                        .as_bytes(),
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct ExportedBlock<'a> {
+    block_no: u64,
+    block: &'a Block,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExportShard {
+    pub start_slot: u64,
+    pub end_slot: u64,
+    /// First slot not exported yet, None once the shard is done
+    pub next_slot: Option<u64>,
+    pub blocks: u64,
+    /// Length of the shard file at the last checkpoint, anything written after it is discarded
+    /// when the shard resumes
+    pub bytes_written: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExportJob {
+    pub id: u64,
+    pub format: ExportFormat,
+    pub start_slot: u64,
+    pub end_slot: u64,
+    pub state: JobState,
+    pub error: Option<String>,
+    pub created_at: u64,
+    pub shards: Vec<ExportShard>,
+    /// Shards with a worker running in this process
+    #[serde(skip)]
+    active_shards: usize,
+}
+
+impl ExportJob {
+    pub fn progress(&self) -> ExportProgress {
+        ExportProgress {
+            format: self.format,
+            start_slot: self.start_slot,
+            end_slot: self.end_slot,
+            shards: self.shards.len() as u64,
+            finished_shards: self
+                .shards
+                .iter()
+                .filter(|shard| shard.next_slot.is_none())
+                .count() as u64,
+            exported_slots: self
+                .shards
+                .iter()
+                .map(|shard| {
+                    shard
+                        .next_slot
+                        .unwrap_or(shard.end_slot + 1)
+                        .saturating_sub(shard.start_slot)
+                })
+                .sum(),
+            exported_blocks: self.shards.iter().map(|shard| shard.blocks).sum(),
+        }
+    }
+}
+
+/// Exports slot ranges to files, sharding every range across worker tasks that checkpoint
+/// their progress so an interrupted export resumes where it stopped
+pub struct Exporter {
+    dir: PathBuf,
+    default_shards: u64,
+    handler_sender: UnboundedSender<ProtocolMessage>,
+    jobs: Mutex<BTreeMap<u64, ExportJob>>,
+}
+
+impl Exporter {
+    /// This function initializes the exporter with the jobs checkpointed in the export directory
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - An ExportConfig that holds the export directory and default shard count
+    /// * `handler_sender` - A UnboundedSender<ProtocolMessage> that holds the handler sender
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Arc<Self>, AggError>` - A Result that holds the exporter or an error
+    pub fn initialize(
+        config: &ExportConfig,
+        handler_sender: UnboundedSender<ProtocolMessage>,
+    ) -> Result<Arc<Self>, AggError> {
+        std::fs::create_dir_all(&config.dir)?;
+        let mut jobs = BTreeMap::new();
+        for entry in std::fs::read_dir(&config.dir)? {
+            let path = entry?.path().join(JOB_FILE);
+            if !path.is_file() {
+                continue;
+            }
+            let job = serde_json::from_slice::<ExportJob>(&std::fs::read(&path)?)?;
+            jobs.insert(job.id, job);
+        }
+        Ok(Arc::new(Exporter {
+            dir: config.dir.clone(),
+            default_shards: config.shards,
+            handler_sender,
+            jobs: Mutex::new(jobs),
+        }))
+    }
+
+    /// This function restarts the workers of the jobs that were running when the process stopped
+    pub fn resume_running(self: &Arc<Self>) {
+        let running: Vec<u64> = self
+            .lock()
+            .values()
+            .filter(|job| job.state == JobState::Running)
+            .map(|job| job.id)
+            .collect();
+        for id in running {
+            info!(target: "export", "Resuming export {}", id);
+            self.spawn_unfinished_shards(id);
+        }
+    }
+
+    /// This function starts an export job, registered as a job of the db so it is listed and
+    /// cancelled along with the other jobs
+    ///
+    /// # Arguments
+    ///
+    /// * `params` - An ExportParams that holds the slot range, format and shard count
+    ///
+    /// # Returns
+    ///
+    /// * `Result<ExportJob, AggError>` - A Result that holds the started job or an error
+    pub async fn start(self: &Arc<Self>, params: ExportParams) -> Result<ExportJob, AggError> {
+        if params.start_slot > params.end_slot {
+            return Err(AggError::ExportError(
+                "start_slot must be <= end_slot".to_string(),
+            ));
+        }
+        let span = params.end_slot - params.start_slot + 1;
+        let shard_count = params
+            .shards
+            .unwrap_or(self.default_shards)
+            .clamp(1, MAX_EXPORT_SHARDS)
+            .min(span);
+        let shard_span = span.div_ceil(shard_count);
+        let shards = (0..shard_count)
+            .map(|shard_no| {
+                let start_slot = params.start_slot + shard_no * shard_span;
+                ExportShard {
+                    start_slot,
+                    end_slot: (start_slot + shard_span - 1).min(params.end_slot),
+                    next_slot: Some(start_slot),
+                    blocks: 0,
+                    bytes_written: 0,
+                }
+            })
+            .collect();
+        let mut job = ExportJob {
+            id: 0,
+            format: params.format,
+            start_slot: params.start_slot,
+            end_slot: params.end_slot,
+            state: JobState::Running,
+            error: None,
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            shards,
+            active_shards: 0,
+        };
+        job.id = self.register(&job).await?;
+        std::fs::create_dir_all(self.job_dir(job.id))?;
+        self.checkpoint(&job)?;
+        self.lock().insert(job.id, job.clone());
+        self.spawn_unfinished_shards(job.id);
+        Ok(job)
+    }
+
+    /// This function registers an export as a job of the db
+    ///
+    /// # Arguments
+    ///
+    /// * `job` - An ExportJob that holds the export
+    ///
+    /// # Returns
+    ///
+    /// * `Result<u64, AggError>` - A Result that holds the id of the job or an error
+    async fn register(&self, job: &ExportJob) -> Result<u64, AggError> {
+        let response = ProtocolMessage::ask(&self.handler_sender, |reply| {
+            ProtocolMessage::StartJob(JobTask::Export(job.progress()), reply)
+        })
+        .await?;
+        match response {
+            Response::Job(Some(registered)) => Ok(registered.id),
+            _ => Err(AggError::UnexpectedReply(
+                "the db did not answer the export".to_string(),
+            )),
+        }
+    }
+
+    /// This function cancels a running export, its shards stop after their current page
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - A u64 that holds the job id
+    ///
+    /// # Returns
+    ///
+    /// * `Option<ExportJob>` - The export, None if it does not exist
+    pub fn cancel(&self, id: u64) -> Option<ExportJob> {
+        let mut jobs = self.lock();
+        let job = jobs.get_mut(&id)?;
+        if job.state == JobState::Running {
+            job.state = JobState::Cancelled;
+            if let Err(err) = self.checkpoint(job) {
+                error!(target: "export", "Failed to checkpoint export {} {}", id, err);
+            }
+        }
+        Some(job.clone())
+    }
+
+    /// This function resumes a failed job from its last checkpoint
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - A u64 that holds the job id
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<ExportJob>, AggError>` - A Result that holds the resumed job, None if it
+    ///   does not exist, or an error if it is not failed or still has running workers
+    pub fn resume(self: &Arc<Self>, id: u64) -> Result<Option<ExportJob>, AggError> {
+        let job = {
+            let mut jobs = self.lock();
+            let Some(job) = jobs.get_mut(&id) else {
+                return Ok(None);
+            };
+            if job.state != JobState::Failed || job.active_shards > 0 {
+                return Err(AggError::JobConflict(format!(
+                    "Export {} is not failed or is still stopping",
+                    id
+                )));
+            }
+            job.state = JobState::Running;
+            job.error = None;
+            self.checkpoint(job)?;
+            job.clone()
+        };
+        self.spawn_unfinished_shards(id);
+        Ok(Some(job))
+    }
+
+    pub fn jobs(&self) -> Vec<ExportJob> {
+        self.lock().values().cloned().collect()
+    }
+
+    pub fn job(&self, id: u64) -> Option<ExportJob> {
+        self.lock().get(&id).cloned()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<u64, ExportJob>> {
+        match self.jobs.lock() {
+            Ok(jobs) => jobs,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    fn job_dir(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("{:020}", id))
+    }
+
+    /// This function writes the checkpoint of a job, replacing the previous one atomically, and
+    /// reports its progress to the db
+    fn checkpoint(&self, job: &ExportJob) -> Result<(), AggError> {
+        let path = self.job_dir(job.id).join(JOB_FILE);
+        let staged = path.with_extension("json.tmp");
+        std::fs::write(&staged, serde_json::to_vec_pretty(job)?)?;
+        std::fs::rename(staged, path)?;
+        self.handler_sender.send(ProtocolMessage::UpdateJob(
+            job.id,
+            JobTask::Export(job.progress()),
+            job.state,
+            job.error.clone(),
+        ))?;
+        Ok(())
+    }
+
+    fn spawn_unfinished_shards(self: &Arc<Self>, id: u64) {
+        let unfinished: Vec<usize> = {
+            let mut jobs = self.lock();
+            let Some(job) = jobs.get_mut(&id) else {
+                return;
+            };
+            let unfinished: Vec<usize> = (0..job.shards.len())
+                .filter(|shard_no| job.shards[*shard_no].next_slot.is_some())
+                .collect();
+            job.active_shards += unfinished.len();
+            unfinished
+        };
+        if unfinished.is_empty() {
+            self.update(id, |_| {});
+        }
+        for shard_no in unfinished {
+            let exporter = self.clone();
+            tokio::spawn(async move {
+                let result = exporter.run_shard(id, shard_no).await;
+                exporter.update(id, |job| {
+                    job.active_shards -= 1;
+                    if let Err(err) = result {
+                        error!(target: "export", "Shard {} of export {} failed {}", shard_no, id, err);
+                        if job.state == JobState::Running {
+                            job.state = JobState::Failed;
+                            job.error = Some(err.to_string());
+                        }
+                    }
+                });
+            });
+        }
+    }
+
+    /// This function applies a change to a job and checkpoints it, finishing the job once every
+    /// shard is done
+    fn update(&self, id: u64, change: impl FnOnce(&mut ExportJob)) {
+        let mut jobs = self.lock();
+        let Some(job) = jobs.get_mut(&id) else {
+            return;
+        };
+        change(job);
+        if job.state == JobState::Running
+            && job.shards.iter().all(|shard| shard.next_slot.is_none())
+        {
+            info!(target: "export", "Export {} finished", id);
+            job.state = JobState::Finished;
+        }
+        if let Err(err) = self.checkpoint(job) {
+            error!(target: "export", "Failed to checkpoint export {} {}", id, err);
+        }
+    }
+
+    /// This function exports the slots of a shard from its last checkpoint on
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - A u64 that holds the job id
+    /// * `shard_no` - A usize that holds the shard
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    async fn run_shard(&self, id: u64, shard_no: usize) -> Result<(), AggError> {
+        let Some((format, mut shard)) = self
+            .job(id)
+            .map(|job| (job.format, job.shards[shard_no].clone()))
+        else {
+            return Ok(());
+        };
+        let path = self
+            .job_dir(id)
+            .join(format!("shard-{:04}.{}", shard_no, format.extension()));
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .await?;
+        file.set_len(shard.bytes_written).await?;
+        file.seek(SeekFrom::End(0)).await?;
+        let mut buffer = Vec::new();
+        if shard.bytes_written == 0 && format == ExportFormat::Csv {
+            buffer.extend(CSV_HEADER.as_bytes());
+        }
+        while let Some(from_slot) = shard.next_slot {
+            if self
+                .job(id)
+                .is_some_and(|job| job.state != JobState::Running)
+            {
+                // The export was cancelled or another shard failed, this one stops at its last
+                // checkpoint
+                return Ok(());
+            }
+            let (blocks, next_slot) = self.fetch_page(from_slot, shard.end_slot).await?;
+            for (block_no, block) in blocks.iter() {
+                format.write_block(&mut buffer, *block_no, block)?;
+            }
+            file.write_all(&buffer).await?;
+            file.sync_data().await?;
+            shard.bytes_written += buffer.len() as u64;
+            shard.blocks += blocks.len() as u64;
+            shard.next_slot = next_slot;
+            buffer.clear();
+            self.update(id, |job| job.shards[shard_no] = shard.clone());
+        }
+        Ok(())
+    }
+
+    /// This function reads the next page of stored blocks of a slot range from the db
+    ///
+    /// # Arguments
+    ///
+    /// * `start_slot` - A u64 that holds the first slot
+    /// * `end_slot` - A u64 that holds the last slot
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(Vec<(u64, Block)>, Option<u64>), AggError>` - The blocks and the next slot to
+    ///   read, None once the range is done
+    async fn fetch_page(
+        &self,
+        start_slot: u64,
+        end_slot: u64,
+    ) -> Result<(Vec<(u64, Block)>, Option<u64>), AggError> {
+        let response = ProtocolMessage::ask(&self.handler_sender, |reply| {
+            ProtocolMessage::FetchBlocksBySlot(start_slot, end_slot, EXPORT_PAGE_SIZE, reply)
+        })
+        .await?;
+        match response {
+            Response::BlocksBySlot(blocks, next_slot) => Ok((blocks, next_slot)),
+            _ => Err(AggError::UnexpectedReply(
+                "the db did not answer the export".to_string(),
+            )),
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use crate::metrics;
+use crate::shutdown::{Shutdown, Worker};
+use crate::util::{Block, ProtocolMessage};
+use futures_util::future::BoxFuture;
+use log::{error, warn};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{self, Receiver, Sender, UnboundedReceiver};
+
+/// What the fan-out does with a block for a sink whose queue is full
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overflow {
+    /// Wait for room, holding back the next blocks of every sink. For sinks that must see every
+    /// block, like the db.
+    Wait,
+    /// Drop the block for this sink only, counted in `agg_fan_out_dropped_blocks_total`
+    Drop,
+}
+
+/// Messages a block or account stream may have queued before it is ended for falling behind
+pub const SUBSCRIBER_CAPACITY: usize = 1024;
+
+struct Sink {
+    name: String,
+    overflow: Overflow,
+    sender: Sender<ProtocolMessage>,
+}
+
+/// Stage delivering every finalized block to each of its sinks through a bounded queue of the
+/// sink's own, so a slow sink only holds back the others when it is one they wait for
+pub struct FanOut {
+    receiver: UnboundedReceiver<ProtocolMessage>,
+    sinks: Vec<Sink>,
+    shutdown: Option<Shutdown>,
+}
+
+impl FanOut {
+    /// This function initializes the fan-out
+    ///
+    /// # Arguments
+    ///
+    /// * `receiver` - A UnboundedReceiver<ProtocolMessage> that receives the finalized blocks
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The fan-out
+    pub fn initialize(receiver: UnboundedReceiver<ProtocolMessage>) -> Self {
+        Self {
+            receiver,
+            sinks: Vec::new(),
+            shutdown: None,
+        }
+    }
+
+    /// This function adds a sink receiving every finalized block as a FinalizeBlock message
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - A string slice that holds the name the sink is reported under
+    /// * `capacity` - A usize that holds the number of blocks the sink may have queued
+    /// * `overflow` - An Overflow that holds what happens to blocks once the queue is full
+    ///
+    /// # Returns
+    ///
+    /// * `Receiver<ProtocolMessage>` - The receiver the sink reads the blocks from
+    pub fn add_sink(
+        &mut self,
+        name: &str,
+        capacity: usize,
+        overflow: Overflow,
+    ) -> Receiver<ProtocolMessage> {
+        let (sender, receiver) = mpsc::channel(capacity.max(1));
+        self.sinks.push(Sink {
+            name: name.to_string(),
+            overflow,
+            sender,
+        });
+        receiver
+    }
+
+    /// This function waits for the next message, once the fan-out is asked to stop only the
+    /// messages already queued are taken
+    ///
+    /// # Returns
+    ///
+    /// * `Option<ProtocolMessage>` - The next message or None once the fan-out is drained
+    async fn next_message(&mut self) -> Option<ProtocolMessage> {
+        if !Shutdown::is_set(&self.shutdown) {
+            tokio::select! {
+                message = self.receiver.recv() => return message,
+                _ = Shutdown::wait(&mut self.shutdown) => {}
+            }
+        }
+        self.receiver.try_recv().ok()
+    }
+
+    /// This function runs the fan-out
+    pub async fn run(&mut self) {
+        while let Some(message) = self.next_message().await {
+            match message {
+                ProtocolMessage::FinalizeBlock(block_no, block) => {
+                    self.publish(block_no, block).await;
+                }
+                message => {
+                    warn!(target: "fan_out", "Ignoring unexpected message {:?}", message);
+                }
+            }
+        }
+    }
+
+    /// This function delivers a block to every sink. Sinks dropping blocks get it first, so they
+    /// are not held back by a full sink the fan-out waits for.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number
+    /// * `block` - A Block that holds the finalized block
+    async fn publish(&mut self, block_no: u64, block: Block) {
+        let mut closed = Vec::new();
+        for sink in self
+            .sinks
+            .iter()
+            .filter(|sink| sink.overflow == Overflow::Drop)
+        {
+            match sink
+                .sender
+                .try_send(ProtocolMessage::FinalizeBlock(block_no, block.clone()))
+            {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    metrics::FAN_OUT_DROPPED
+                        .with_label_values(&[&sink.name])
+                        .inc();
+                }
+                Err(TrySendError::Closed(_)) => closed.push(sink.name.clone()),
+            }
+        }
+        for sink in self
+            .sinks
+            .iter()
+            .filter(|sink| sink.overflow == Overflow::Wait)
+        {
+            if sink
+                .sender
+                .send(ProtocolMessage::FinalizeBlock(block_no, block.clone()))
+                .await
+                .is_err()
+            {
+                closed.push(sink.name.clone());
+            }
+        }
+        for name in closed {
+            error!(target: "fan_out", "Sink {} stopped, removing it", name);
+            self.sinks.retain(|sink| sink.name != name);
+        }
+        for sink in self.sinks.iter() {
+            metrics::FAN_OUT_QUEUED
+                .with_label_values(&[&sink.name])
+                .set((sink.sender.max_capacity() - sink.sender.capacity()) as i64);
+        }
+    }
+}
+
+/// This function creates the bounded queue a block or account stream is sent its messages
+/// through
+///
+/// # Returns
+///
+/// * `(Sender<ProtocolMessage>, Receiver<ProtocolMessage>)` - The ends of the queue
+pub fn subscriber_channel() -> (Sender<ProtocolMessage>, Receiver<ProtocolMessage>) {
+    mpsc::channel(SUBSCRIBER_CAPACITY)
+}
+
+/// This function sends a live message to a stream without waiting. A stream whose queue is
+/// full gets the Drop policy: the message is dropped and the stream is ended once its queued
+/// messages are read, so the client reconnects from its last position instead of missing it.
+///
+/// # Arguments
+///
+/// * `stream` - A string slice that holds the name the dropped messages are counted under
+/// * `subscriber` - A reference to the Sender<ProtocolMessage> of the stream
+/// * `message` - A ProtocolMessage that holds the message
+///
+/// # Returns
+///
+/// * `bool` - Whether the stream is kept
+pub fn deliver(
+    stream: &str,
+    subscriber: &Sender<ProtocolMessage>,
+    message: ProtocolMessage,
+) -> bool {
+    match subscriber.try_send(message) {
+        Ok(()) => true,
+        Err(TrySendError::Full(_)) => {
+            metrics::FAN_OUT_DROPPED.with_label_values(&[stream]).inc();
+            false
+        }
+        Err(TrySendError::Closed(_)) => false,
+    }
+}
+
+impl Worker for FanOut {
+    fn set_shutdown(&mut self, shutdown: Shutdown) {
+        self.shutdown = Some(shutdown);
+    }
+
+    fn work(&mut self) -> BoxFuture<'_, ()> {
+        Box::pin(self.run())
+    }
+}
@@ -0,0 +1,383 @@
+use crate::config::FetchCacheConfig;
+use crate::error::AggError;
+use crate::metrics::{FETCH_CACHE_LOOKUPS, FETCH_CACHE_SIZE};
+use log::{info, warn};
+use serde_json::{from_slice, to_vec};
+use solana_client::rpc_config::RpcBlockConfig;
+use solana_transaction_status::{TransactionDetails, UiConfirmedBlock, UiTransactionEncoding};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// Extension of the files holding a cached block, named after its slot and how it was requested
+const EXTENSION: &str = "block";
+/// Extension of a block being written, renamed once complete so a crash never leaves a torn block
+const PARTIAL_EXTENSION: &str = "partial";
+
+/// A cached block, the same slot requested with another encoding, transaction details, rewards
+/// or transaction version is another block
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Key {
+    slot: u64,
+    variant: String,
+}
+
+#[derive(Debug)]
+struct Entry {
+    size: u64,
+    last_used: u64,
+}
+
+#[derive(Debug, Default)]
+struct Index {
+    entries: HashMap<Key, Entry>,
+    size: u64,
+    /// Ticks on every use of an entry, orders the entries from least to most recently used
+    clock: u64,
+}
+
+/// This function names how a block is requested from the node, e.g. `base64-full-rewards-v0`.
+/// The commitment is left out, only finalized blocks are cached.
+///
+/// # Arguments
+///
+/// * `config` - A RpcBlockConfig that holds how the block is requested
+///
+/// # Returns
+///
+/// * `String` - The variant of the cached block, part of its file name
+pub fn variant(config: &RpcBlockConfig) -> String {
+    let encoding = config.encoding.unwrap_or(UiTransactionEncoding::Json);
+    let details = config
+        .transaction_details
+        .unwrap_or(TransactionDetails::Full);
+    let rewards = if config.rewards.unwrap_or(true) {
+        "rewards"
+    } else {
+        "norewards"
+    };
+    let version = config
+        .max_supported_transaction_version
+        .map_or_else(|| "legacy".to_string(), |version| format!("v{}", version));
+    format!("{:?}-{:?}-{}-{}", encoding, details, rewards, version).to_lowercase()
+}
+
+/// Finalized blocks as fetched from the node, one file per slot and variant, evicting the least
+/// recently used blocks once their size exceeds the bound
+#[derive(Debug)]
+pub struct FetchCache {
+    dir: PathBuf,
+    max_size: u64,
+    index: Mutex<Index>,
+}
+
+impl FetchCache {
+    /// This function opens the cache directory, picking up the blocks cached by a previous run
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - A Path that holds the cache directory, created when missing
+    /// * `max_size` - A u64 that holds the bytes the cached blocks may take
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, AggError>` - A Result that holds the cache or an error if the directory
+    ///   could not be read
+    pub fn open(dir: &Path, max_size: u64) -> Result<Self, AggError> {
+        std::fs::create_dir_all(dir)?;
+        let mut found = Vec::new();
+        for file in std::fs::read_dir(dir)? {
+            let path = file?.path();
+            match path.extension().and_then(|extension| extension.to_str()) {
+                Some(EXTENSION) => {}
+                // Left behind by a write that did not complete
+                Some(PARTIAL_EXTENSION) => {
+                    let _ = std::fs::remove_file(&path);
+                    continue;
+                }
+                _ => continue,
+            }
+            let Some(key) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.split_once('.'))
+                .and_then(|(slot, variant)| {
+                    Some(Key {
+                        slot: slot.parse().ok()?,
+                        variant: variant.to_string(),
+                    })
+                })
+            else {
+                // Cached before the blocks were keyed by variant, the variant is unknown
+                let _ = std::fs::remove_file(&path);
+                continue;
+            };
+            let metadata = std::fs::metadata(&path)?;
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            found.push((modified, key, metadata.len()));
+        }
+        // Blocks written last by the previous run are the most recently used
+        found.sort_unstable_by_key(|(modified, ..)| *modified);
+        let mut index = Index::default();
+        for (_, key, size) in found {
+            index.clock += 1;
+            index.size += size;
+            index.entries.insert(
+                key,
+                Entry {
+                    size,
+                    last_used: index.clock,
+                },
+            );
+        }
+        let cache = FetchCache {
+            dir: dir.to_path_buf(),
+            max_size,
+            index: Mutex::new(index),
+        };
+        {
+            let mut index = cache.index.lock().expect("fetch cache index lock");
+            cache.evict(&mut index);
+            FETCH_CACHE_SIZE.set(index.size as i64);
+            info!(
+                target: "fetch_cache",
+                "Opened the fetch cache at {} holding {} blocks",
+                dir.display(),
+                index.entries.len()
+            );
+        }
+        Ok(cache)
+    }
+
+    /// This function reads a block back from the cache
+    ///
+    /// # Arguments
+    ///
+    /// * `slot` - A u64 that holds the slot of the block
+    /// * `config` - A RpcBlockConfig that holds how the block is requested
+    ///
+    /// # Returns
+    ///
+    /// * `Option<UiConfirmedBlock>` - The block, None when it is not cached or could not be read
+    pub fn get(&self, slot: u64, config: &RpcBlockConfig) -> Option<UiConfirmedBlock> {
+        let key = Key {
+            slot,
+            variant: variant(config),
+        };
+        let cached = {
+            let mut index = self.index.lock().expect("fetch cache index lock");
+            index.clock += 1;
+            let clock = index.clock;
+            match index.entries.get_mut(&key) {
+                Some(entry) => {
+                    entry.last_used = clock;
+                    true
+                }
+                None => false,
+            }
+        };
+        let block = if cached { self.read(&key) } else { None };
+        let result = if block.is_some() { "hit" } else { "miss" };
+        FETCH_CACHE_LOOKUPS.with_label_values(&[result]).inc();
+        block
+    }
+
+    /// This function reads a block back from the cache like `get`, on the blocking thread pool
+    /// so the runtime threads are not held while the block is read and decoded
+    ///
+    /// # Arguments
+    ///
+    /// * `slot` - A u64 that holds the slot of the block
+    /// * `config` - A RpcBlockConfig that holds how the block is requested
+    ///
+    /// # Returns
+    ///
+    /// * `Option<UiConfirmedBlock>` - The block, None when it is not cached or could not be read
+    pub async fn get_blocking(
+        self: &Arc<Self>,
+        slot: u64,
+        config: RpcBlockConfig,
+    ) -> Option<UiConfirmedBlock> {
+        let cache = self.clone();
+        match tokio::task::spawn_blocking(move || cache.get(slot, &config)).await {
+            Ok(block) => block,
+            Err(err) => {
+                warn!(target: "fetch_cache", "Failed to read the cached block of slot {} {}", slot, err);
+                None
+            }
+        }
+    }
+
+    /// This function caches a block, evicting the least recently used blocks past the bound
+    ///
+    /// # Arguments
+    ///
+    /// * `slot` - A u64 that holds the slot of the block
+    /// * `config` - A RpcBlockConfig that holds how the block was requested
+    /// * `block` - A UiConfirmedBlock that holds the block as fetched from the node
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error if the block could
+    ///   not be written
+    pub fn insert(
+        &self,
+        slot: u64,
+        config: &RpcBlockConfig,
+        block: &UiConfirmedBlock,
+    ) -> Result<(), AggError> {
+        let key = Key {
+            slot,
+            variant: variant(config),
+        };
+        let bytes = to_vec(block)?;
+        let size = bytes.len() as u64;
+        if size > self.max_size {
+            return Ok(());
+        }
+        let clock = {
+            let mut index = self.index.lock().expect("fetch cache index lock");
+            index.clock += 1;
+            index.clock
+        };
+        // Named after the tick, so concurrent fetches of the slot do not write the same file
+        let partial = self.dir.join(format!(
+            "{}.{}.{}.{}",
+            slot, key.variant, clock, PARTIAL_EXTENSION
+        ));
+        std::fs::write(&partial, &bytes)?;
+        if let Err(err) = std::fs::rename(&partial, self.path(&key)) {
+            let _ = std::fs::remove_file(&partial);
+            return Err(err.into());
+        }
+        let mut index = self.index.lock().expect("fetch cache index lock");
+        let previous = index.entries.insert(
+            key,
+            Entry {
+                size,
+                last_used: clock,
+            },
+        );
+        index.size = index.size - previous.map_or(0, |entry| entry.size) + size;
+        self.evict(&mut index);
+        FETCH_CACHE_SIZE.set(index.size as i64);
+        Ok(())
+    }
+
+    /// This function caches a block like `insert`, on the blocking thread pool so the runtime
+    /// threads are not held while the block is encoded and written
+    ///
+    /// # Arguments
+    ///
+    /// * `slot` - A u64 that holds the slot of the block
+    /// * `config` - A RpcBlockConfig that holds how the block was requested
+    /// * `block` - A UiConfirmedBlock that holds the block as fetched from the node
+    ///
+    /// # Returns
+    ///
+    /// * `Result<UiConfirmedBlock, AggError>` - A Result that holds the block handed back, cached
+    ///   or not, or an error if the task writing it failed
+    pub async fn insert_blocking(
+        self: &Arc<Self>,
+        slot: u64,
+        config: RpcBlockConfig,
+        block: UiConfirmedBlock,
+    ) -> Result<UiConfirmedBlock, AggError> {
+        let cache = self.clone();
+        let (block, inserted) = tokio::task::spawn_blocking(move || {
+            let inserted = cache.insert(slot, &config, &block);
+            (block, inserted)
+        })
+        .await
+        .map_err(|err| AggError::TaskFailed(format!("Fetch cache task failed {}", err)))?;
+        if let Err(error) = inserted {
+            warn!(target: "fetch_cache", "Failed to cache the block of slot {} {}", slot, error);
+        }
+        Ok(block)
+    }
+
+    /// This function tells whether a block is cached
+    pub fn contains(&self, slot: u64, config: &RpcBlockConfig) -> bool {
+        let key = Key {
+            slot,
+            variant: variant(config),
+        };
+        self.index
+            .lock()
+            .expect("fetch cache index lock")
+            .entries
+            .contains_key(&key)
+    }
+
+    /// This function returns the bytes the cached blocks take
+    pub fn size(&self) -> u64 {
+        self.index.lock().expect("fetch cache index lock").size
+    }
+
+    fn path(&self, key: &Key) -> PathBuf {
+        self.dir
+            .join(format!("{}.{}.{}", key.slot, key.variant, EXTENSION))
+    }
+
+    /// This function reads a cached block, dropping it from the cache when it is unreadable
+    fn read(&self, key: &Key) -> Option<UiConfirmedBlock> {
+        let path = self.path(key);
+        let read = std::fs::read(&path)
+            .map_err(AggError::from)
+            .and_then(|bytes| Ok(from_slice::<UiConfirmedBlock>(&bytes)?));
+        match read {
+            Ok(block) => Some(block),
+            Err(err) => {
+                warn!(target: "fetch_cache", "Dropping the cached block of slot {} {}", key.slot, err);
+                let _ = std::fs::remove_file(&path);
+                let mut index = self.index.lock().expect("fetch cache index lock");
+                if let Some(entry) = index.entries.remove(key) {
+                    index.size -= entry.size;
+                    FETCH_CACHE_SIZE.set(index.size as i64);
+                }
+                None
+            }
+        }
+    }
+
+    /// This function removes the least recently used blocks until the cache fits its bound
+    fn evict(&self, index: &mut Index) {
+        while index.size > self.max_size {
+            let Some(key) = index
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            if let Some(entry) = index.entries.remove(&key) {
+                index.size -= entry.size;
+            }
+            if let Err(err) = std::fs::remove_file(self.path(&key)) {
+                warn!(target: "fetch_cache", "Failed to evict the block of slot {} {}", key.slot, err);
+            }
+        }
+    }
+}
+
+/// This function opens the fetch cache the subscriber and the backfills share, when enabled
+///
+/// # Arguments
+///
+/// * `config` - A FetchCacheConfig that holds the cache directory and bound
+///
+/// # Returns
+///
+/// * `Result<Option<Arc<FetchCache>>, AggError>` - A Result that holds the cache, None when it is
+///   disabled, or an error if the cache directory could not be opened
+pub fn from_config(config: &FetchCacheConfig) -> Result<Option<Arc<FetchCache>>, AggError> {
+    if !config.enabled {
+        return Ok(None);
+    }
+    Ok(Some(Arc::new(FetchCache::open(
+        &config.dir,
+        config.max_size_bytes(),
+    )?)))
+}
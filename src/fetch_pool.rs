@@ -0,0 +1,160 @@
+use crate::error::AggError;
+use crate::gaps::FetchOutcome;
+use crate::metrics::{FETCH_IN_FLIGHT, FETCH_OLDEST_PENDING_SLOT, FETCH_QUEUE_DEPTH};
+use crate::retry::Failure;
+use crate::shutdown::Shutdown;
+use futures_util::future::BoxFuture;
+use log::error;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+/// Fetch of the block of a slot, run by the first idle worker
+type Job = (u64, BoxFuture<'static, FetchOutcome>);
+
+/// Slots submitted whose outcome was not sent yet, a slot fetched again while its previous fetch
+/// is still pending is counted twice
+#[derive(Default)]
+struct Pending {
+    slots: BTreeMap<u64, usize>,
+    count: usize,
+}
+
+impl Pending {
+    fn add(&mut self, slot: u64) {
+        *self.slots.entry(slot).or_default() += 1;
+        self.count += 1;
+        self.publish();
+    }
+
+    fn remove(&mut self, slot: u64) {
+        if let Some(fetches) = self.slots.get_mut(&slot) {
+            *fetches -= 1;
+            if *fetches == 0 {
+                self.slots.remove(&slot);
+            }
+            self.count -= 1;
+        }
+        self.publish();
+    }
+
+    fn publish(&self) {
+        FETCH_OLDEST_PENDING_SLOT.set(self.oldest().unwrap_or_default() as i64);
+    }
+
+    fn oldest(&self) -> Option<u64> {
+        self.slots.keys().next().copied()
+    }
+}
+
+/// Fixed set of workers fetching blocks from a queue, in the order the slots were submitted, so
+/// the requests sent to the node at once never exceed the number of workers however far the
+/// subscriber lags behind
+pub struct FetchPool {
+    sender: UnboundedSender<Job>,
+    pending: Arc<Mutex<Pending>>,
+}
+
+impl FetchPool {
+    /// This function spawns the workers of the pool
+    ///
+    /// # Arguments
+    ///
+    /// * `workers` - A usize that holds the number of blocks fetched at once, at least one
+    /// * `outcome_sender` - An UnboundedSender<(u64, FetchOutcome)> the outcome of every fetch is
+    ///   sent to along with its slot
+    /// * `shutdown` - An Option<Shutdown> that holds the signal the workers stop taking fetches
+    ///   from the queue on, fetches already started still complete
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The pool
+    pub fn start(
+        workers: usize,
+        outcome_sender: UnboundedSender<(u64, FetchOutcome)>,
+        shutdown: Option<Shutdown>,
+    ) -> Self {
+        let (sender, receiver) = unbounded_channel();
+        let receiver = Arc::new(tokio::sync::Mutex::new(receiver));
+        let pending = Arc::new(Mutex::new(Pending::default()));
+        for _ in 0..workers.max(1) {
+            tokio::spawn(Self::work(
+                receiver.clone(),
+                outcome_sender.clone(),
+                pending.clone(),
+                shutdown.clone(),
+            ));
+        }
+        Self { sender, pending }
+    }
+
+    /// This function queues the fetch of the block of a slot, its outcome is sent once a worker
+    /// ran it
+    ///
+    /// # Arguments
+    ///
+    /// * `slot` - A u64 that holds the slot
+    /// * `fetch` - A BoxFuture<'static, FetchOutcome> that fetches the block
+    pub fn submit(&self, slot: u64, fetch: BoxFuture<'static, FetchOutcome>) {
+        self.pending.lock().unwrap().add(slot);
+        if self.sender.send((slot, fetch)).is_ok() {
+            FETCH_QUEUE_DEPTH.inc();
+        } else {
+            // Every worker stopped, the fetch will never run
+            self.pending.lock().unwrap().remove(slot);
+        }
+    }
+
+    /// This function returns the number of fetches queued or running
+    pub fn pending(&self) -> usize {
+        self.pending.lock().unwrap().count
+    }
+
+    /// This function returns the oldest slot whose fetch is queued or running, the blocks of the
+    /// slots before it were all handed to the parser, skipped or failed
+    pub fn oldest_pending(&self) -> Option<u64> {
+        self.pending.lock().unwrap().oldest()
+    }
+
+    /// This function runs the fetches of the queue one at a time until the pool is dropped or
+    /// asked to stop
+    ///
+    /// # Arguments
+    ///
+    /// * `receiver` - The queue shared by the workers
+    /// * `outcome_sender` - An UnboundedSender<(u64, FetchOutcome)> the outcomes are sent to
+    /// * `pending` - The slots whose outcome was not sent yet
+    /// * `shutdown` - An Option<Shutdown> that holds the signal the worker stops on
+    async fn work(
+        receiver: Arc<tokio::sync::Mutex<UnboundedReceiver<Job>>>,
+        outcome_sender: UnboundedSender<(u64, FetchOutcome)>,
+        pending: Arc<Mutex<Pending>>,
+        mut shutdown: Option<Shutdown>,
+    ) {
+        loop {
+            let job = tokio::select! {
+                job = async { receiver.lock().await.recv().await } => job,
+                _ = Shutdown::wait(&mut shutdown) => None,
+            };
+            let Some((slot, fetch)) = job else {
+                return;
+            };
+            FETCH_QUEUE_DEPTH.dec();
+            FETCH_IN_FLIGHT.inc();
+            // Spawned so a fetch that panics does not take the worker down with it
+            let outcome = tokio::spawn(fetch).await;
+            FETCH_IN_FLIGHT.dec();
+            pending.lock().unwrap().remove(slot);
+            let outcome = outcome.unwrap_or_else(|err| {
+                error!(target: "subscriber", "Fetch of slot {} panicked {:?}", slot, err);
+                // Reported failed, so the slot is fetched again like any other gap
+                FetchOutcome::Failed(Failure {
+                    error: AggError::TaskFailed(format!("fetch of slot {} panicked", slot)),
+                    attempts: 1,
+                })
+            });
+            // The receiver only goes away with the subscriber
+            let _ = outcome_sender.send((slot, outcome));
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use crate::config::RefetchConfig;
+use crate::metrics;
+use crate::retry::Failure;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// What became of the fetch of a slot
+#[derive(Debug)]
+pub enum FetchOutcome {
+    /// The block was fetched and handed to the parser
+    Fetched,
+    /// The leader of the slot produced no block, there is nothing to fetch
+    Skipped,
+    /// The node could not be reached or failed to return the block after the retries of
+    /// FETCH_RETRY
+    Failed(Failure),
+}
+
+/// What the tracker made of a failed fetch
+#[derive(Debug, PartialEq, Eq)]
+pub enum GapUpdate {
+    /// The slot is fetched again once its delay passed
+    Scheduled(Duration),
+    /// The slot failed every re-fetch it was allowed and is given up on
+    GivenUp,
+}
+
+struct Gap {
+    /// Failed fetches of the slot, the first fetch included
+    failures: u32,
+    retry_at: Instant,
+    /// Whether a re-fetch is running, so it is not started twice
+    in_flight: bool,
+}
+
+/// Slot missing from the db after its fetch failed, as listed by the tracker
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct MissingSlot {
+    pub slot: u64,
+    pub failures: u32,
+}
+
+/// Slots whose block could not be fetched, re-fetched with a delay doubling after every failure
+/// until they are fetched, turn out to be skipped, or run out of re-fetches. Skipped slots are
+/// never gaps, the node reporting a slot as skipped resolves it.
+pub struct GapTracker {
+    config: RefetchConfig,
+    gaps: BTreeMap<u64, Gap>,
+}
+
+impl GapTracker {
+    pub fn new(config: RefetchConfig) -> Self {
+        GapTracker {
+            config,
+            gaps: BTreeMap::new(),
+        }
+    }
+
+    /// This function records the outcome of the fetch of a slot
+    ///
+    /// # Arguments
+    ///
+    /// * `slot` - A u64 that holds the slot
+    /// * `outcome` - A FetchOutcome that holds what became of the fetch
+    /// * `now` - An Instant that holds the current time
+    ///
+    /// # Returns
+    ///
+    /// * `Option<GapUpdate>` - What became of the gap when the fetch failed, None otherwise
+    pub fn record(&mut self, slot: u64, outcome: &FetchOutcome, now: Instant) -> Option<GapUpdate> {
+        let update = match outcome {
+            FetchOutcome::Fetched | FetchOutcome::Skipped => {
+                if self.gaps.remove(&slot).is_some() {
+                    let outcome = match outcome {
+                        FetchOutcome::Fetched => "fetched",
+                        _ => "skipped",
+                    };
+                    metrics::RESOLVED_GAPS.with_label_values(&[outcome]).inc();
+                }
+                None
+            }
+            FetchOutcome::Failed(_) => {
+                let gap = self.gaps.entry(slot).or_insert(Gap {
+                    failures: 0,
+                    retry_at: now,
+                    in_flight: false,
+                });
+                gap.failures += 1;
+                gap.in_flight = false;
+                if gap.failures > self.config.max_refetches {
+                    self.gaps.remove(&slot);
+                    metrics::RESOLVED_GAPS
+                        .with_label_values(&["given_up"])
+                        .inc();
+                    Some(GapUpdate::GivenUp)
+                } else {
+                    let delay = delay(&self.config, gap.failures);
+                    gap.retry_at = now + delay;
+                    Some(GapUpdate::Scheduled(delay))
+                }
+            }
+        };
+        metrics::MISSING_SLOTS.set(self.gaps.len() as i64);
+        update
+    }
+
+    /// This function takes the slots whose re-fetch is due
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - An Instant that holds the current time
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<u64>` - The slots to fetch again, not returned again until their outcome is recorded
+    pub fn due(&mut self, now: Instant) -> Vec<u64> {
+        self.gaps
+            .iter_mut()
+            .filter(|(_, gap)| !gap.in_flight && gap.retry_at <= now)
+            .map(|(slot, gap)| {
+                gap.in_flight = true;
+                *slot
+            })
+            .collect()
+    }
+
+    /// This function lists the slots still missing
+    pub fn missing(&self) -> Vec<MissingSlot> {
+        self.gaps
+            .iter()
+            .map(|(slot, gap)| MissingSlot {
+                slot: *slot,
+                failures: gap.failures,
+            })
+            .collect()
+    }
+}
+
+/// This function returns the wait before the re-fetch following a slot's nth failure
+fn delay(config: &RefetchConfig, failures: u32) -> Duration {
+    Duration::from_secs(config.initial_delay_secs)
+        .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+        .min(Duration::from_secs(config.max_delay_secs))
+}
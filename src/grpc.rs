@@ -0,0 +1,522 @@
+use crate::config::QueryConfig;
+use crate::error::AggError;
+use crate::fanout;
+use crate::server::send_query;
+use crate::shutdown::Shutdown;
+use crate::util::{
+    self, AccountId, Block, BlockFilter, Instruction, ProtocolMessage, Reply, TxId, TxRecord,
+    MAX_FILTER_ACCOUNTS,
+};
+use actix_web::http::StatusCode;
+use log::error;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+pub mod proto {
+    tonic::include_proto!("solana_agg");
+}
+
+use proto::aggregator_server::{Aggregator, AggregatorServer};
+
+/// Blocks read from the db per batch of a stream
+const STREAM_BATCH_SIZE: u64 = 100;
+/// Blocks buffered per stream, reading the next batch waits until the client consumed them
+const STREAM_BUFFER: usize = 2 * STREAM_BATCH_SIZE as usize;
+
+pub struct GrpcServer {
+    handler_sender: UnboundedSender<ProtocolMessage>,
+    query_config: QueryConfig,
+}
+
+/// This function translates an error answered by the db into the gRPC status of the same class
+/// as the HTTP status the JSON API answers with
+///
+/// # Arguments
+///
+/// * `err` - An AggError that holds the error
+///
+/// # Returns
+///
+/// * `Status` - The status to fail the call with
+pub fn status(err: &AggError) -> Status {
+    let message = err.to_string();
+    match err.status() {
+        StatusCode::NOT_FOUND => Status::not_found(message),
+        StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY => {
+            Status::invalid_argument(message)
+        }
+        StatusCode::CONFLICT => Status::failed_precondition(message),
+        StatusCode::UNAUTHORIZED => Status::unauthenticated(message),
+        StatusCode::PAYLOAD_TOO_LARGE | StatusCode::TOO_MANY_REQUESTS => {
+            Status::resource_exhausted(message)
+        }
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE => Status::unavailable(message),
+        _ => Status::internal(message),
+    }
+}
+
+/// This function builds the filter of a block subscription
+///
+/// # Arguments
+///
+/// * `request` - A SubscribeBlocksRequest that holds the filter criteria
+///
+/// # Returns
+///
+/// * `Result<BlockFilter, AggError>` - The filter or a bad request error when a list holds a
+///   malformed public key or too many entries
+pub fn block_filter(request: &proto::SubscribeBlocksRequest) -> Result<BlockFilter, AggError> {
+    let accounts = |accounts: &[String]| {
+        if accounts.len() > MAX_FILTER_ACCOUNTS {
+            return Err(AggError::BadRequest(format!(
+                "at most {} accounts are allowed",
+                MAX_FILTER_ACCOUNTS
+            )));
+        }
+        accounts
+            .iter()
+            .map(|account| {
+                AccountId::try_from(account.clone())
+                    .map(AccountId::into_string)
+                    .map_err(AggError::BadRequest)
+            })
+            .collect::<Result<Vec<_>, _>>()
+    };
+    Ok(BlockFilter {
+        programs: accounts(&request.programs)?,
+        accounts: accounts(&request.accounts)?,
+        min_amount: request.min_amount,
+        failed_only: request.failed_only,
+    })
+}
+
+impl GrpcServer {
+    /// This function runs the gRPC server
+    ///
+    /// # Arguments
+    ///
+    /// * `handler_sender` - A UnboundedSender<ProtocolMessage> that holds the handler sender
+    /// * `query_config` - A QueryConfig that holds the request timeout and maximum block range
+    ///   span
+    /// * `port_no` - A u16 that holds the port to listen on
+    /// * `shutdown` - A Shutdown that holds the signal the server stops on
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    pub async fn run(
+        handler_sender: UnboundedSender<ProtocolMessage>,
+        query_config: QueryConfig,
+        port_no: u16,
+        mut shutdown: Shutdown,
+    ) -> Result<(), AggError> {
+        let address = ([127, 0, 0, 1], port_no).into();
+        tonic::transport::Server::builder()
+            .add_service(AggregatorServer::new(GrpcServer {
+                handler_sender,
+                query_config,
+            }))
+            .serve_with_shutdown(address, async move { shutdown.requested().await })
+            .await?;
+        Ok(())
+    }
+
+    /// This function answers a query through the db under the request timeout of the JSON API
+    ///
+    /// # Arguments
+    ///
+    /// * `handler_sender` - A UnboundedSender<ProtocolMessage> that holds the handler sender
+    /// * `query_config` - A QueryConfig that holds the request timeout
+    /// * `query` - A FnOnce(Reply) -> ProtocolMessage that builds the query around its reply sender
+    ///
+    /// # Returns
+    ///
+    /// * `Result<util::Response, Status>` - The response or the status to fail the call with
+    async fn query(
+        handler_sender: &UnboundedSender<ProtocolMessage>,
+        query_config: &QueryConfig,
+        query: impl FnOnce(Reply) -> ProtocolMessage,
+    ) -> Result<util::Response, Status> {
+        match send_query(handler_sender, query_config, query).await {
+            Some(Ok(response)) => Ok(response),
+            Some(Err(err)) => Err(status(&err)),
+            None => Err(Status::deadline_exceeded("Query timed out")),
+        }
+    }
+
+    /// This function streams the stored blocks of a block number range, reading the next span
+    /// from the db only once the previous one was taken by the client
+    ///
+    /// # Arguments
+    ///
+    /// * `handler_sender` - A UnboundedSender<ProtocolMessage> that holds the handler sender
+    /// * `query_config` - A QueryConfig that holds the request timeout and the span read at once
+    /// * `start_block_no` - A u64 that holds the first block number
+    /// * `end_block_no` - A u64 that holds the last block number
+    /// * `stream` - A mpsc::Sender that holds the response stream
+    async fn stream_block_range(
+        handler_sender: UnboundedSender<ProtocolMessage>,
+        query_config: QueryConfig,
+        start_block_no: u64,
+        end_block_no: u64,
+        stream: mpsc::Sender<Result<proto::Block, Status>>,
+    ) {
+        let span = query_config.max_block_range_span.max(1);
+        let mut next_block_no = Some(start_block_no);
+        while let Some(from) = next_block_no {
+            let to = from.saturating_add(span - 1).min(end_block_no);
+            let response = Self::query(&handler_sender, &query_config, |reply| {
+                ProtocolMessage::FetchBlockRange(from, to, reply)
+            })
+            .await;
+            let blocks = match response {
+                Ok(util::Response::BlockRangeDetails(blocks)) => blocks,
+                Ok(_) => {
+                    let _ = stream
+                        .send(Err(Status::internal("Unexpected response")))
+                        .await;
+                    return;
+                }
+                Err(status) => {
+                    let _ = stream.send(Err(status)).await;
+                    return;
+                }
+            };
+            for (block_no, block) in blocks {
+                if stream
+                    .send(Ok(Self::to_proto(block_no, &block)))
+                    .await
+                    .is_err()
+                {
+                    // The client went away
+                    return;
+                }
+            }
+            next_block_no = to.checked_add(1).filter(|next| *next <= end_block_no);
+        }
+    }
+
+    /// This function forwards the blocks of a subscription to the client until it goes away
+    ///
+    /// # Arguments
+    ///
+    /// * `events` - A mpsc::Receiver that holds the subscription the db delivers to
+    /// * `stream` - A mpsc::Sender that holds the response stream
+    async fn forward_blocks(
+        mut events: mpsc::Receiver<ProtocolMessage>,
+        stream: mpsc::Sender<Result<proto::Block, Status>>,
+    ) {
+        loop {
+            let (block_no, block) = tokio::select! {
+                message = events.recv() => match message {
+                    Some(ProtocolMessage::NewBlock(block_no, block)) => (block_no, block),
+                    _ => return,
+                },
+                // Dropping the receiver ends the subscription in the db
+                _ = stream.closed() => return,
+            };
+            if stream
+                .send(Ok(Self::to_proto(block_no, &block)))
+                .await
+                .is_err()
+            {
+                return;
+            }
+        }
+    }
+
+    /// This function streams the stored blocks of a slot range, reading the next batch from
+    /// the db only once the previous one was taken by the client
+    ///
+    /// # Arguments
+    ///
+    /// * `handler_sender` - A UnboundedSender<ProtocolMessage> that holds the handler sender
+    /// * `start_slot` - A u64 that holds the first slot
+    /// * `end_slot` - A u64 that holds the last slot
+    /// * `stream` - A mpsc::Sender that holds the response stream
+    async fn stream_blocks(
+        handler_sender: UnboundedSender<ProtocolMessage>,
+        start_slot: u64,
+        end_slot: u64,
+        stream: mpsc::Sender<Result<proto::Block, Status>>,
+    ) {
+        let mut next_slot = Some(start_slot);
+        while let Some(from_slot) = next_slot {
+            // A client leaving while the batches hold no block is not noticed by sending
+            if stream.is_closed() {
+                return;
+            }
+            let response = ProtocolMessage::ask(&handler_sender, |reply| {
+                ProtocolMessage::FetchBlocksBySlot(from_slot, end_slot, STREAM_BATCH_SIZE, reply)
+            })
+            .await;
+            match response {
+                Ok(util::Response::BlocksBySlot(blocks, next)) => {
+                    for (block_no, block) in blocks {
+                        if stream
+                            .send(Ok(Self::to_proto(block_no, &block)))
+                            .await
+                            .is_err()
+                        {
+                            // The client went away
+                            return;
+                        }
+                    }
+                    next_slot = next;
+                }
+                Err(err @ AggError::MpscChannelError(_)) => {
+                    error!(target: "grpc", "Error from handler_sender {}", err);
+                    let _ = stream.send(Err(Status::unavailable(err.to_string()))).await;
+                    return;
+                }
+                Err(err) => {
+                    let _ = stream.send(Err(Status::internal(err.to_string()))).await;
+                    return;
+                }
+                Ok(_) => {
+                    let _ = stream
+                        .send(Err(Status::internal("Unexpected response")))
+                        .await;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// This function converts a stored block into its gRPC message
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number
+    /// * `block` - A Block that holds the block
+    ///
+    /// # Returns
+    ///
+    /// * `proto::Block` - The gRPC block
+    fn to_proto(block_no: u64, block: &Block) -> proto::Block {
+        proto::Block {
+            block_no,
+            slot: block.slot().unwrap_or_default(),
+            blockhash: block.blockhash().unwrap_or_default().to_string(),
+            block_time: block.block_time(),
+            transactions: block
+                .transactions()
+                .map(|(tx_id, tx)| Self::tx_to_proto(tx_id, tx))
+                .collect(),
+            previous_blockhash: block.previous_blockhash().map(str::to_string),
+            parent_slot: block.parent_slot(),
+            transaction_count: block.transaction_count(),
+        }
+    }
+
+    /// This function converts a stored transaction into its gRPC message
+    ///
+    /// # Arguments
+    ///
+    /// * `tx_id` - A &str that holds the transaction id
+    /// * `tx` - A TxRecord that holds the transaction
+    ///
+    /// # Returns
+    ///
+    /// * `proto::Transaction` - The gRPC transaction
+    fn tx_to_proto(tx_id: &str, tx: &TxRecord) -> proto::Transaction {
+        proto::Transaction {
+            tx_id: tx_id.to_string(),
+            fee: tx.fee(),
+            succeeded: tx.succeeded(),
+            accounts: tx.accounts().to_vec(),
+            transfers: tx
+                .instructions()
+                .iter()
+                .filter_map(|instruction| match instruction {
+                    Instruction::Transfer(from, to, amount) => Some(proto::Transfer {
+                        from: from.clone(),
+                        to: to.clone(),
+                        amount: *amount,
+                    }),
+                    Instruction::TokenTransfer { .. } | Instruction::Authority { .. } => None,
+                })
+                .collect(),
+            metadata: tx.metadata().map(str::to_string),
+            logs: tx.logs().to_vec(),
+            compute_units: tx.compute_units(),
+            error: tx.error().map(str::to_string),
+            token_transfers: tx
+                .instructions()
+                .iter()
+                .filter_map(|instruction| match instruction {
+                    Instruction::Transfer(..) | Instruction::Authority { .. } => None,
+                    Instruction::TokenTransfer {
+                        source,
+                        destination,
+                        source_owner,
+                        destination_owner,
+                        mint,
+                        amount,
+                        decimals,
+                    } => Some(proto::TokenTransfer {
+                        source: source.clone(),
+                        destination: destination.clone(),
+                        source_owner: source_owner.clone(),
+                        destination_owner: destination_owner.clone(),
+                        mint: mint.clone(),
+                        amount: *amount,
+                        decimals: decimals.map(u32::from),
+                    }),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Aggregator for GrpcServer {
+    type StreamBlocksStream = ReceiverStream<Result<proto::Block, Status>>;
+    type GetBlockRangeStream = ReceiverStream<Result<proto::Block, Status>>;
+    type SubscribeBlocksStream = ReceiverStream<Result<proto::Block, Status>>;
+
+    async fn stream_blocks(
+        &self,
+        request: Request<proto::StreamBlocksRequest>,
+    ) -> Result<Response<Self::StreamBlocksStream>, Status> {
+        let proto::StreamBlocksRequest {
+            start_slot,
+            end_slot,
+        } = request.into_inner();
+        if start_slot > end_slot {
+            return Err(Status::invalid_argument("start_slot must be <= end_slot"));
+        }
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+        tokio::spawn(Self::stream_blocks(
+            self.handler_sender.clone(),
+            start_slot,
+            end_slot,
+            sender,
+        ));
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    async fn get_transaction(
+        &self,
+        request: Request<proto::GetTransactionRequest>,
+    ) -> Result<Response<proto::Transaction>, Status> {
+        let tx_id = TxId::try_from(request.into_inner().tx_id)
+            .map_err(Status::invalid_argument)?
+            .into_string();
+        let response = Self::query(&self.handler_sender, &self.query_config, |reply| {
+            ProtocolMessage::FetchTransactionDetails(tx_id.clone(), reply)
+        })
+        .await?;
+        match response {
+            util::Response::TxDetails(tx) => Ok(Response::new(Self::tx_to_proto(&tx_id, &tx))),
+            _ => Err(Status::internal("Unexpected response")),
+        }
+    }
+
+    async fn get_block(
+        &self,
+        request: Request<proto::GetBlockRequest>,
+    ) -> Result<Response<proto::Block>, Status> {
+        let block_no = request.into_inner().block_no;
+        let response = Self::query(
+            &self.handler_sender,
+            &self.query_config,
+            |reply| match block_no {
+                Some(block_no) => ProtocolMessage::FetchBlockDetails(block_no, reply),
+                None => ProtocolMessage::FetchLatestBlock(reply),
+            },
+        )
+        .await?;
+        match (response, block_no) {
+            (util::Response::BlockDetails(block), Some(block_no))
+            | (util::Response::LatestBlockDetails(block_no, block), None) => {
+                Ok(Response::new(Self::to_proto(block_no, &block)))
+            }
+            _ => Err(Status::internal("Unexpected response")),
+        }
+    }
+
+    async fn get_block_range(
+        &self,
+        request: Request<proto::GetBlockRangeRequest>,
+    ) -> Result<Response<Self::GetBlockRangeStream>, Status> {
+        let proto::GetBlockRangeRequest {
+            start_block_no,
+            end_block_no,
+        } = request.into_inner();
+        if start_block_no > end_block_no {
+            return Err(Status::invalid_argument(
+                "start_block_no must be <= end_block_no",
+            ));
+        }
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+        tokio::spawn(Self::stream_block_range(
+            self.handler_sender.clone(),
+            self.query_config.clone(),
+            start_block_no,
+            end_block_no,
+            sender,
+        ));
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    async fn get_account_balance(
+        &self,
+        request: Request<proto::GetAccountBalanceRequest>,
+    ) -> Result<Response<proto::AccountBalance>, Status> {
+        let proto::GetAccountBalanceRequest {
+            public_key,
+            block_no,
+            slot,
+        } = request.into_inner();
+        let account = AccountId::try_from(public_key)
+            .map_err(Status::invalid_argument)?
+            .into_string();
+        if block_no.is_some() && slot.is_some() {
+            return Err(Status::invalid_argument(
+                "Request the balance at either a block or a slot, not both",
+            ));
+        }
+        let response = Self::query(
+            &self.handler_sender,
+            &self.query_config,
+            |reply| match slot {
+                Some(slot) => ProtocolMessage::FetchAccountBalanceAtSlot(account, slot, reply),
+                None => ProtocolMessage::FetchAccountBalance(account, block_no, reply),
+            },
+        )
+        .await?;
+        match response {
+            util::Response::AccountBalance(balance) if !balance.known => {
+                Err(Status::not_found("Account balance not known"))
+            }
+            util::Response::AccountBalance(balance) => Ok(Response::new(proto::AccountBalance {
+                balance: balance.balance,
+                as_of_slot: balance.as_of_slot,
+                observed_slot: balance.observed_slot,
+                observed_block_no: balance.observed_block_no,
+            })),
+            _ => Err(Status::internal("Unexpected response")),
+        }
+    }
+
+    async fn subscribe_blocks(
+        &self,
+        request: Request<proto::SubscribeBlocksRequest>,
+    ) -> Result<Response<Self::SubscribeBlocksStream>, Status> {
+        let request = request.into_inner();
+        let filter = block_filter(&request).map_err(|err| status(&err))?;
+        let (subscriber, events) = fanout::subscriber_channel();
+        self.handler_sender
+            .send(ProtocolMessage::SubscribeBlocks(
+                request.block_no,
+                filter,
+                subscriber,
+            ))
+            .map_err(|err| Status::unavailable(err.to_string()))?;
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+        tokio::spawn(Self::forward_blocks(events, sender));
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+}
@@ -1,14 +1,40 @@
+use crate::config::{Commitment, PluginConfig, ShutdownConfig};
 use crate::error::AggError;
-use crate::util::{Block, ProtocolMessage, UnprocessedBlock};
-use log::error;
+use crate::invariants::{self, FinalizedSlots};
+use crate::plugin;
+use crate::retry::Failure;
+use crate::shutdown::{Shutdown, Worker};
+use crate::util::{
+    Block, BlockFilter, DeadLetter, FailureStage, ProtocolMessage, Reply, Response,
+    UnprocessedBlock,
+};
+use futures_util::future::BoxFuture;
+use log::{error, info, warn};
 use solana_program::clock::Slot;
 use std::collections::HashMap;
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use std::time::Duration;
+use tokio::sync::mpsc::{Sender, UnboundedReceiver, UnboundedSender};
+use tokio::time::Instant;
 
 pub struct Handler {
     message_receiver: UnboundedReceiver<ProtocolMessage>,
     db_sender: UnboundedSender<ProtocolMessage>,
+    /// Stage the finalized blocks go to instead of the db when set
+    fan_out_sender: Option<UnboundedSender<ProtocolMessage>>,
+    stats_sender: Option<UnboundedSender<ProtocolMessage>>,
     unprocessed_block_collector: HashMap<Slot, UnprocessedBlock>,
+    /// Slots of the blocks finalized so far, the slot of every new block is checked against
+    finalized_slots: FinalizedSlots,
+    /// Commitment of the ingested blocks, slots are only checked for finalized blocks
+    commitment: Commitment,
+    /// Which of the registered plugins run on the finalized blocks
+    plugin_config: PluginConfig,
+    shutdown: Option<Shutdown>,
+    /// Time the chunks of the blocks still being parsed have to arrive once the handler is asked
+    /// to stop, the blocks still incomplete after it are dropped
+    drain_timeout: Duration,
+    /// Time the handler stops waiting for chunks, set once it is asked to stop
+    drain_deadline: Option<Instant>,
 }
 
 impl Handler {
@@ -30,43 +56,230 @@ impl Handler {
         Self {
             message_receiver,
             db_sender,
+            fan_out_sender: None,
+            stats_sender: None,
             unprocessed_block_collector: HashMap::new(),
+            finalized_slots: FinalizedSlots::default(),
+            commitment: Commitment::default(),
+            plugin_config: PluginConfig::default(),
+            shutdown: None,
+            drain_timeout: ShutdownConfig::default().chunk_drain_timeout(),
+            drain_deadline: None,
         }
     }
 
-    /// This function runs the handler
+    /// This function makes the handler send the finalized blocks to the fan-out, which delivers
+    /// them to the db and the other sinks
+    ///
+    /// # Arguments
+    ///
+    /// * `fan_out_sender` - A UnboundedSender<ProtocolMessage> that holds the fan-out sender
+    pub fn set_fan_out(&mut self, fan_out_sender: UnboundedSender<ProtocolMessage>) {
+        self.fan_out_sender = Some(fan_out_sender);
+    }
+
+    /// This function sets which of the registered plugins run on the finalized blocks before
+    /// they are stored
+    ///
+    /// # Arguments
+    ///
+    /// * `plugin_config` - A PluginConfig that holds the disabled plugins
+    pub fn set_plugin_config(&mut self, plugin_config: PluginConfig) {
+        self.plugin_config = plugin_config;
+    }
+
+    /// This function sets the commitment of the ingested blocks. The slots of confirmed blocks
+    /// are not checked against the blocks before them, the db follows the reorgs replacing them.
+    ///
+    /// # Arguments
+    ///
+    /// * `commitment` - A Commitment that holds the commitment of the ingested blocks
+    pub fn set_commitment(&mut self, commitment: Commitment) {
+        self.commitment = commitment;
+    }
+
+    /// This function sets the stats aggregator the throughput stats are queried from
+    ///
+    /// # Arguments
+    ///
+    /// * `stats_sender` - A UnboundedSender<ProtocolMessage> that holds the stats sender
+    pub fn set_stats_sender(&mut self, stats_sender: UnboundedSender<ProtocolMessage>) {
+        self.stats_sender = Some(stats_sender);
+    }
+
+    /// This function sets the time the chunks of the incomplete blocks have to arrive once the
+    /// handler is asked to stop
+    ///
+    /// # Arguments
+    ///
+    /// * `drain_timeout` - A Duration that holds the time the chunks have to arrive
+    pub fn set_drain_timeout(&mut self, drain_timeout: Duration) {
+        self.drain_timeout = drain_timeout;
+    }
+
+    /// This function waits for the next message. Once the handler is asked to stop it only takes
+    /// the messages already queued and the chunks of the incomplete blocks, up to
+    /// the drain timeout.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<ProtocolMessage>` - The next message or None once the handler is drained
+    async fn next_message(&mut self) -> Option<ProtocolMessage> {
+        if !Shutdown::is_set(&self.shutdown) {
+            tokio::select! {
+                message = self.message_receiver.recv() => return message,
+                _ = Shutdown::wait(&mut self.shutdown) => {}
+            }
+        }
+        if let Ok(message) = self.message_receiver.try_recv() {
+            return Some(message);
+        }
+        if self.unprocessed_block_collector.is_empty() {
+            return None;
+        }
+        let deadline = *self
+            .drain_deadline
+            .get_or_insert_with(|| Instant::now() + self.drain_timeout);
+        match tokio::time::timeout_at(deadline, self.message_receiver.recv()).await {
+            Ok(message) => message,
+            Err(_) => {
+                let mut block_nos: Vec<_> = self.unprocessed_block_collector.keys().collect();
+                block_nos.sort_unstable();
+                warn!(
+                    target: "handler",
+                    "Dropping {} incomplete blocks {:?}", block_nos.len(), block_nos
+                );
+                None
+            }
+        }
+    }
+
+    /// This function runs the handler until it is asked to stop and drained
     pub async fn run(&mut self) {
-        loop {
-            if let Some(message) = self.message_receiver.recv().await {
-                match message {
-                    ProtocolMessage::ParsedBlock(block_no, total_chunks, chunk_no, block) => {
-                        if let Err(err) =
-                            self.handle_unprocessed_block(block_no, total_chunks, chunk_no, block)
-                        {
-                            error!(target: "handler", "Error from handle_unprocessed_block {}", err);
-                            return;
-                        }
-                    }
-                    ProtocolMessage::FetchTransactionDetails(tx_id, server_sender) => {
-                        self.handle_tx_details(tx_id, server_sender);
-                    }
-                    ProtocolMessage::FetchBlockDetails(block_no, server_sender) => {
-                        self.handle_block_details(block_no, server_sender);
+        while let Some(message) = self.next_message().await {
+            match message {
+                ProtocolMessage::ParsedBlock(block_no, total_chunks, chunk_no, block) => {
+                    if let Err(err) =
+                        self.handle_unprocessed_block(block_no, total_chunks, chunk_no, block)
+                    {
+                        error!(target: "handler", "Error from handle_unprocessed_block {}", err);
+                        return;
                     }
-                    ProtocolMessage::FetchLatestBlock(server_sender) => {
-                        self.handle_latest_block_request(server_sender);
-                    }
-                    ProtocolMessage::FetchBlockRange(start, end, server_sender) => {
-                        self.handle_block_range_request(start, end, server_sender);
-                    }
-                    ProtocolMessage::FetchAccountBalance(pubkey, block_no, server_sender) => {
-                        self.handle_account_balance(pubkey, block_no, server_sender);
-                    }
-
-                    _ => {}
                 }
+                ProtocolMessage::FetchTransactionDetails(tx_id, reply) => {
+                    self.handle_tx_details(tx_id, reply);
+                }
+                ProtocolMessage::FetchBlockDetails(block_no, reply) => {
+                    self.handle_block_details(block_no, reply);
+                }
+                ProtocolMessage::FetchLatestBlock(reply) => {
+                    self.handle_latest_block_request(reply);
+                }
+                ProtocolMessage::FetchBlockRange(start, end, reply) => {
+                    self.handle_block_range_request(start, end, reply);
+                }
+                ProtocolMessage::FetchAccountBalance(pubkey, block_no, reply) => {
+                    self.handle_account_balance(pubkey, block_no, reply);
+                }
+                ProtocolMessage::SkippedSlot(slot) => {
+                    self.forward_to_db(ProtocolMessage::SkippedSlot(slot));
+                }
+                ProtocolMessage::FetchIndexedSlots(start, end, limit, reply) => {
+                    self.forward_to_db(ProtocolMessage::FetchIndexedSlots(
+                        start, end, limit, reply,
+                    ));
+                }
+                ProtocolMessage::FetchTokenHolders(mint, block_no, reply) => {
+                    self.forward_to_db(ProtocolMessage::FetchTokenHolders(mint, block_no, reply));
+                }
+                ProtocolMessage::FetchAccountSummary(pubkey, reply) => {
+                    self.forward_to_db(ProtocolMessage::FetchAccountSummary(pubkey, reply));
+                }
+                ProtocolMessage::FetchAccountTransactions(pubkey, cursor, limit, reply) => {
+                    self.forward_to_db(ProtocolMessage::FetchAccountTransactions(
+                        pubkey, cursor, limit, reply,
+                    ));
+                }
+                ProtocolMessage::FetchCustomStats(rule, reply) => {
+                    self.forward_to_db(ProtocolMessage::FetchCustomStats(rule, reply));
+                }
+                ProtocolMessage::FetchActiveAccounts(days, reply) => {
+                    self.forward_to_db(ProtocolMessage::FetchActiveAccounts(days, reply));
+                }
+                ProtocolMessage::Compact(range, reply) => {
+                    self.forward_to_db(ProtocolMessage::Compact(range, reply));
+                }
+                ProtocolMessage::DeleteSlotRange(start, end, reply) => {
+                    self.forward_to_db(ProtocolMessage::DeleteSlotRange(start, end, reply));
+                }
+                ProtocolMessage::RefetchBlock(block_no, raw_block, reply) => {
+                    self.forward_to_db(ProtocolMessage::RefetchBlock(block_no, raw_block, reply));
+                }
+                ProtocolMessage::FetchLatestBlockSummaries(limit, reply) => {
+                    self.forward_to_db(ProtocolMessage::FetchLatestBlockSummaries(limit, reply));
+                }
+                ProtocolMessage::FetchAccountBalanceAtSlot(pubkey, slot, reply) => {
+                    self.forward_to_db(ProtocolMessage::FetchAccountBalanceAtSlot(
+                        pubkey, slot, reply,
+                    ));
+                }
+                ProtocolMessage::FetchBalanceHistory(
+                    pubkey,
+                    start_slot,
+                    end_slot,
+                    limit,
+                    reply,
+                ) => {
+                    self.forward_to_db(ProtocolMessage::FetchBalanceHistory(
+                        pubkey, start_slot, end_slot, limit, reply,
+                    ));
+                }
+                ProtocolMessage::FetchStatus(reply) => {
+                    self.forward_to_db(ProtocolMessage::FetchStatus(reply));
+                }
+                ProtocolMessage::FetchTpsStats(reply) => {
+                    self.handle_tps_stats_request(reply);
+                }
+                ProtocolMessage::FetchBlockDigest(block_no, reply) => {
+                    self.handle_block_digest(block_no, reply);
+                }
+                ProtocolMessage::SubscribeBlocks(from_block_no, filter, subscriber) => {
+                    self.handle_block_subscription(from_block_no, filter, subscriber);
+                }
+                message @ (ProtocolMessage::FetchTxInclusion(..)
+                | ProtocolMessage::FetchBlockAtSlot(..)
+                | ProtocolMessage::FetchTokenBalance(..)
+                | ProtocolMessage::FetchTokenBalances(..)
+                | ProtocolMessage::SubscribeAccount(..)
+                | ProtocolMessage::AckResumeCursor(..)
+                | ProtocolMessage::CreateWebhook(..)
+                | ProtocolMessage::DeleteWebhook(..)
+                | ProtocolMessage::FetchSubscriptions(..)
+                | ProtocolMessage::FetchWebhookDeliveries(..)
+                | ProtocolMessage::FetchBlocksBySlot(..)
+                | ProtocolMessage::FetchBalancesAt(..)
+                | ProtocolMessage::ArchiveRawBlock(..)
+                | ProtocolMessage::StartJob(..)
+                | ProtocolMessage::FetchJobs(..)
+                | ProtocolMessage::FetchJob(..)
+                | ProtocolMessage::CancelJob(..)
+                | ProtocolMessage::UpdateJob(..)
+                | ProtocolMessage::VerifyGenesisHash(..)
+                | ProtocolMessage::Annotate(..)
+                | ProtocolMessage::DeadLetter(..)
+                | ProtocolMessage::FetchDeadLetters(..)) => {
+                    self.forward_to_db(message);
+                }
+                message @ ProtocolMessage::Deadline(..) => {
+                    self.forward_to_db(message);
+                }
+
+                _ => {}
             }
         }
+        if Shutdown::is_set(&self.shutdown) {
+            info!(target: "handler", "Handler drained");
+        }
     }
 
     /// This function handles the unprocessed block
@@ -88,25 +301,67 @@ impl Handler {
         chunk_no: u64,
         block: Block,
     ) -> Result<(), AggError> {
-        if let Some(unprocessed_block) = self.unprocessed_block_collector.get_mut(&block_no) {
-            unprocessed_block.insert_chunk(chunk_no, block);
-            if unprocessed_block.is_complete() {
-                let complete_block = unprocessed_block.complete_the_block();
-                self.unprocessed_block_collector.remove(&block_no);
-                self.db_sender
-                    .send(ProtocolMessage::FinalizeBlock(block_no, complete_block))?;
+        let unprocessed_block = self
+            .unprocessed_block_collector
+            .entry(block_no)
+            .or_insert_with(|| UnprocessedBlock::new(total_chunks));
+        if let Err(err) = unprocessed_block.insert_chunk(chunk_no, block) {
+            warn!(target: "handler", "Dropping chunk of block {}: {}", block_no, err);
+            return Ok(());
+        }
+        if unprocessed_block.is_complete() {
+            let mut complete_block = unprocessed_block.complete_the_block()?;
+            let mut violations = invariants::check_assembly(unprocessed_block, &complete_block);
+            self.unprocessed_block_collector.remove(&block_no);
+            let slot = complete_block.slot();
+            let finalized_slot = slot.filter(|_| self.commitment == Commitment::Finalized);
+            if let Some(slot) = finalized_slot {
+                violations.extend(self.finalized_slots.check(block_no, slot));
             }
-        } else {
-            let mut unprocessed_block = UnprocessedBlock::new(total_chunks);
-            unprocessed_block.insert_chunk(chunk_no, block);
-            if unprocessed_block.is_complete() {
-                let complete_block = unprocessed_block.complete_the_block();
-                self.unprocessed_block_collector.remove(&block_no);
+            if !violations.is_empty() {
+                warn!(
+                    target: "handler",
+                    "Block {} violates {} invariant(s): {}",
+                    block_no,
+                    violations.len(),
+                    violations.join("; ")
+                );
+                let failure = Failure {
+                    error: AggError::InvariantViolation(violations.join("; ")),
+                    attempts: 1,
+                };
+                self.db_sender.send(ProtocolMessage::DeadLetter(Box::new(
+                    DeadLetter::new(FailureStage::Validate, slot, Some(block_no), &failure)
+                        .with_details(violations),
+                )))?;
+                return Ok(());
+            }
+            if let Err(rejection) = plugin::run(&self.plugin_config, block_no, &mut complete_block)
+            {
+                warn!(
+                    target: "handler",
+                    "Plugin {} rejected block {}: {}", rejection.plugin, block_no, rejection.reason
+                );
+                let failure = Failure {
+                    error: AggError::PluginRejected(rejection.plugin, rejection.reason),
+                    attempts: 1,
+                };
                 self.db_sender
-                    .send(ProtocolMessage::FinalizeBlock(block_no, complete_block))?;
+                    .send(ProtocolMessage::DeadLetter(Box::new(DeadLetter::new(
+                        FailureStage::Plugin,
+                        slot,
+                        Some(block_no),
+                        &failure,
+                    ))))?;
+                return Ok(());
+            }
+            if let Some(slot) = finalized_slot {
+                self.finalized_slots.record(block_no, slot);
             }
-            self.unprocessed_block_collector
-                .insert(block_no, unprocessed_block);
+            self.fan_out_sender
+                .as_ref()
+                .unwrap_or(&self.db_sender)
+                .send(ProtocolMessage::FinalizeBlock(block_no, complete_block))?;
         }
         Ok(())
     }
@@ -116,22 +371,15 @@ impl Handler {
     /// # Arguments
     ///
     /// * `tx_id` - A String that holds the transaction id
-    /// * `server_sender` - A UnboundedSender<ProtocolMessage> that holds the server sender
+    /// * `reply` - A Reply that holds the sender of the response
     ///
     /// # Returns
     ///
     /// * `Result<(), AggError>` - A Result that holds the result or an error
-    pub fn handle_tx_details(
-        &mut self,
-        tx_id: String,
-        server_sender: UnboundedSender<ProtocolMessage>,
-    ) {
+    pub fn handle_tx_details(&mut self, tx_id: String, reply: Reply) {
         if let Err(error) = self
             .db_sender
-            .send(ProtocolMessage::FetchTransactionDetails(
-                tx_id,
-                server_sender,
-            ))
+            .send(ProtocolMessage::FetchTransactionDetails(tx_id, reply))
         {
             error!(target: "handler", "Error from db_sender {}", error);
         }
@@ -141,20 +389,16 @@ impl Handler {
     ///
     /// # Arguments
     ///
-    /// * `block_no` - A String that holds the block number
-    /// * `server_sender` - A UnboundedSender<ProtocolMessage> that holds the server sender
+    /// * `block_no` - A u64 that holds the block number
+    /// * `reply` - A Reply that holds the sender of the response
     ///
     /// # Returns
     ///
     /// * `Result<(), AggError>` - A Result that holds the result or an error
-    pub fn handle_block_details(
-        &mut self,
-        block_no: String,
-        server_sender: UnboundedSender<ProtocolMessage>,
-    ) {
+    pub fn handle_block_details(&mut self, block_no: u64, reply: Reply) {
         if let Err(err) = self
             .db_sender
-            .send(ProtocolMessage::FetchBlockDetails(block_no, server_sender))
+            .send(ProtocolMessage::FetchBlockDetails(block_no, reply))
         {
             error!(target: "handler", "Error from db_sender {}", err);
         }
@@ -166,21 +410,14 @@ impl Handler {
     ///
     /// * `pubkey` - A String that holds the public key
     /// * `block_no` - An Option<u64> that holds the block number
-    /// * `server_sender` - A UnboundedSender<ProtocolMessage> that holds the server sender
+    /// * `reply` - A Reply that holds the sender of the response
     ///
     /// # Returns
     ///
     /// * `Result<(), AggError>` - A Result that holds the result or an error
-    pub fn handle_account_balance(
-        &mut self,
-        pubkey: String,
-        block_no: Option<u64>,
-        server_sender: UnboundedSender<ProtocolMessage>,
-    ) {
+    pub fn handle_account_balance(&mut self, pubkey: String, block_no: Option<u64>, reply: Reply) {
         if let Err(err) = self.db_sender.send(ProtocolMessage::FetchAccountBalance(
-            pubkey,
-            block_no,
-            server_sender,
+            pubkey, block_no, reply,
         )) {
             error!(target: "handler", "Error from db_sender {}", err);
         }
@@ -190,11 +427,11 @@ impl Handler {
     ///
     /// # Arguments
     ///
-    /// * `server_sender` - A UnboundedSender<ProtocolMessage> that holds the server sender
-    pub fn handle_latest_block_request(&mut self, server_sender: UnboundedSender<ProtocolMessage>) {
+    /// * `reply` - A Reply that holds the sender of the response
+    pub fn handle_latest_block_request(&mut self, reply: Reply) {
         if let Err(err) = self
             .db_sender
-            .send(ProtocolMessage::FetchLatestBlock(server_sender))
+            .send(ProtocolMessage::FetchLatestBlock(reply))
         {
             error!(target: "handler", "Error from db_sender {}", err);
         }
@@ -206,22 +443,93 @@ impl Handler {
     ///
     /// * `start` - A u64 that holds the start
     /// * `end` - A u64 that holds the end
-    /// * `server_sender` - A UnboundedSender<ProtocolMessage> that holds the server sender
+    /// * `reply` - A Reply that holds the sender of the response
     ///
     /// # Returns
     ///
     /// * `Result<(), AggError>` - A Result that holds the result or an error
-    pub fn handle_block_range_request(
+    pub fn handle_block_range_request(&mut self, start: u64, end: u64, reply: Reply) {
+        if let Err(err) = self
+            .db_sender
+            .send(ProtocolMessage::FetchBlockRange(start, end, reply))
+        {
+            error!(target: "handler", "Error from db_sender {}", err);
+        }
+    }
+
+    /// This function forwards the tps stats request to the stats aggregator
+    ///
+    /// # Arguments
+    ///
+    /// * `reply` - A Reply that holds the sender of the response
+    fn handle_tps_stats_request(&mut self, reply: Reply) {
+        let Some(stats_sender) = self.stats_sender.as_ref() else {
+            let error = AggError::ConfigError("Throughput stats are not aggregated".to_string());
+            if reply.send(Response::Error(error)).is_err() {
+                error!(target: "handler", "Server stopped waiting for the tps stats");
+            }
+            return;
+        };
+        if let Err(err) = stats_sender.send(ProtocolMessage::FetchTpsStats(reply)) {
+            error!(target: "handler", "Error from stats_sender {}", err);
+        }
+    }
+
+    /// This function forwards a message to the db as is
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - A ProtocolMessage that holds the message
+    fn forward_to_db(&mut self, message: ProtocolMessage) {
+        if let Err(err) = self.db_sender.send(message) {
+            error!(target: "handler", "Error from db_sender {}", err);
+        }
+    }
+
+    /// This function handles the block digest request
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number
+    /// * `reply` - A Reply that holds the sender of the response
+    pub fn handle_block_digest(&mut self, block_no: u64, reply: Reply) {
+        if let Err(err) = self
+            .db_sender
+            .send(ProtocolMessage::FetchBlockDigest(block_no, reply))
+        {
+            error!(target: "handler", "Error from db_sender {}", err);
+        }
+    }
+
+    /// This function handles the block subscription request
+    ///
+    /// # Arguments
+    ///
+    /// * `from_block_no` - An Option<u64> that holds the first block number to replay
+    /// * `filter` - A BlockFilter that holds the transactions the subscriber wants
+    /// * `subscriber` - A Sender<ProtocolMessage> that receives the blocks
+    pub fn handle_block_subscription(
         &mut self,
-        start: u64,
-        end: u64,
-        server_sender: UnboundedSender<ProtocolMessage>,
+        from_block_no: Option<u64>,
+        filter: BlockFilter,
+        subscriber: Sender<ProtocolMessage>,
     ) {
-        if let Err(err) =
-            self.db_sender
-                .send(ProtocolMessage::FetchBlockRange(start, end, server_sender))
-        {
+        if let Err(err) = self.db_sender.send(ProtocolMessage::SubscribeBlocks(
+            from_block_no,
+            filter,
+            subscriber,
+        )) {
             error!(target: "handler", "Error from db_sender {}", err);
         }
     }
 }
+
+impl Worker for Handler {
+    fn set_shutdown(&mut self, shutdown: Shutdown) {
+        self.shutdown = Some(shutdown);
+    }
+
+    fn work(&mut self) -> BoxFuture<'_, ()> {
+        Box::pin(self.run())
+    }
+}
@@ -0,0 +1,177 @@
+use crate::chain;
+use crate::error::{AggError, ErrorContextExt};
+use log::{info, warn};
+use reqwest::StatusCode;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::native_token::LAMPORTS_PER_SOL;
+use solana_sdk::signature::{Keypair, Signature, Signer};
+use solana_sdk::system_transaction;
+use std::time::Duration;
+
+/// Lamports requested from the faucet for the payer of the transfers
+const AIRDROP_LAMPORTS: u64 = LAMPORTS_PER_SOL;
+/// Lamports sent by every transfer, above the rent-exempt minimum of the new recipient
+const TRANSFER_LAMPORTS: u64 = 1_000_000;
+/// Interval between lookups of the transfers not indexed yet
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A synthetic transfer submitted by the harness and how long the aggregator took to index it
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransferOutcome {
+    pub signature: String,
+    /// Message hash the aggregator indexes the transfer under
+    pub tx_id: String,
+    /// Slot the transfer was confirmed in
+    pub slot: u64,
+    /// Slots the chain advanced past `slot` before the transfer was found in the index, None when
+    /// it was not found in time
+    pub indexed_after_slots: Option<u64>,
+}
+
+/// This function tells whether every transfer was found in the index in time
+///
+/// # Arguments
+///
+/// * `outcomes` - A slice of TransferOutcome that holds the outcome of every transfer
+pub fn passed(outcomes: &[TransferOutcome]) -> bool {
+    !outcomes.is_empty()
+        && outcomes
+            .iter()
+            .all(|outcome| outcome.indexed_after_slots.is_some())
+}
+
+/// This function runs the end-to-end smoke test against a test validator or devnet: it funds a
+/// new payer from the faucet, submits synthetic transfers and waits for the aggregator to index
+/// each of them. A transfer not indexed once the chain is `within_slots` slots past it fails the
+/// test.
+///
+/// # Arguments
+///
+/// * `chain_url` - A string slice that holds the RPC url of the node the aggregator ingests from
+/// * `api_url` - A string slice that holds the base url of the aggregator, e.g.
+///   http://127.0.0.1:9944
+/// * `transfers` - A u64 that holds the number of transfers submitted
+/// * `within_slots` - A u64 that holds the slots each transfer has to be indexed within
+///
+/// # Returns
+///
+/// * `Result<Vec<TransferOutcome>, AggError>` - The outcome of every transfer, or an error if the
+///   node is a mainnet node or the transfers could not be submitted
+pub async fn run(
+    chain_url: &str,
+    api_url: &str,
+    transfers: u64,
+    within_slots: u64,
+) -> Result<Vec<TransferOutcome>, AggError> {
+    let rpc = RpcClient::new_with_commitment(chain_url.to_string(), CommitmentConfig::confirmed());
+    let genesis_hash = rpc
+        .get_genesis_hash()
+        .await
+        .with_endpoint(chain_url)?
+        .to_string();
+    if chain::chain_id_of(&genesis_hash) == "mainnet-beta" {
+        return Err(AggError::ConfigError(
+            "the test harness only submits transactions to a test validator, devnet or testnet"
+                .to_string(),
+        ));
+    }
+    let payer = Keypair::new();
+    let airdrop = rpc
+        .request_airdrop(&payer.pubkey(), AIRDROP_LAMPORTS)
+        .await
+        .with_endpoint(chain_url)?;
+    rpc.poll_for_signature(&airdrop)
+        .await
+        .with_endpoint(chain_url)?;
+    info!(target: "harness", "Funded payer {} from the faucet", payer.pubkey());
+
+    let mut outcomes = Vec::new();
+    for _ in 0..transfers {
+        let (signature, tx_id, slot) = transfer(&rpc, &payer).await.with_endpoint(chain_url)?;
+        info!(target: "harness", "Submitted transfer {} in slot {}", signature, slot);
+        outcomes.push(TransferOutcome {
+            signature: signature.to_string(),
+            tx_id,
+            slot,
+            indexed_after_slots: None,
+        });
+    }
+
+    let http = reqwest::Client::new();
+    let mut pending: Vec<usize> = (0..outcomes.len()).collect();
+    while !pending.is_empty() {
+        let current_slot = rpc.get_slot().await.with_endpoint(chain_url)?;
+        let mut still_pending = Vec::new();
+        for index in pending {
+            let outcome = &mut outcomes[index];
+            if is_indexed(&http, api_url, &outcome.tx_id).await? {
+                outcome.indexed_after_slots = Some(current_slot.saturating_sub(outcome.slot));
+            } else if current_slot <= outcome.slot.saturating_add(within_slots) {
+                still_pending.push(index);
+            }
+        }
+        pending = still_pending;
+        if !pending.is_empty() {
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+    Ok(outcomes)
+}
+
+/// This function submits a transfer from the payer to a new account and waits for it to be
+/// confirmed
+///
+/// # Arguments
+///
+/// * `rpc` - An RpcClient that holds the client of the node
+/// * `payer` - A Keypair that holds the funded payer
+///
+/// # Returns
+///
+/// * `Result<(Signature, String, u64), AggError>` - The signature, message hash and slot of the
+///   transfer or an error
+async fn transfer(rpc: &RpcClient, payer: &Keypair) -> Result<(Signature, String, u64), AggError> {
+    let recipient = Keypair::new().pubkey();
+    let blockhash = rpc.get_latest_blockhash().await?;
+    let tx = system_transaction::transfer(payer, &recipient, TRANSFER_LAMPORTS, blockhash);
+    let tx_id = tx.message.hash().to_string();
+    let signature = rpc.send_and_confirm_transaction(&tx).await?;
+    let slot = rpc
+        .get_signature_statuses(&[signature])
+        .await?
+        .value
+        .into_iter()
+        .next()
+        .flatten()
+        .map(|status| status.slot)
+        .ok_or_else(|| {
+            AggError::UnexpectedReply(format!("no status for confirmed transfer {}", signature))
+        })?;
+    Ok((signature, tx_id, slot))
+}
+
+/// This function tells whether the aggregator indexed a transaction
+///
+/// # Arguments
+///
+/// * `http` - A reqwest Client that holds the HTTP client
+/// * `api_url` - A string slice that holds the base url of the aggregator
+/// * `tx_id` - A string slice that holds the message hash of the transaction
+///
+/// # Returns
+///
+/// * `Result<bool, AggError>` - Whether the transaction is indexed, or an error if the aggregator
+///   could not be reached
+async fn is_indexed(http: &reqwest::Client, api_url: &str, tx_id: &str) -> Result<bool, AggError> {
+    let url = format!("{}/tx_details/{}", api_url.trim_end_matches('/'), tx_id);
+    let response = http.get(&url).send().await.with_endpoint(&url)?;
+    match response.status() {
+        status if status.is_success() => Ok(true),
+        StatusCode::NOT_FOUND => Ok(false),
+        status => {
+            warn!(target: "harness", "Looking up transfer {} answered {}", tx_id, status);
+            Ok(false)
+        }
+    }
+}
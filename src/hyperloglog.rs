@@ -0,0 +1,103 @@
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+
+/// Bits of the hash selecting the register, 2^12 registers estimate with a standard error of
+/// about 1.6%
+const PRECISION: u32 = 12;
+const REGISTERS: usize = 1 << PRECISION;
+
+/// Estimator of the number of distinct items of a set too large to keep, such as the accounts
+/// active over a day. Sketches of two sets merge into the sketch of their union, so a block can
+/// be added to the sketch of its day any number of times.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        HyperLogLog {
+            registers: vec![0; REGISTERS],
+        }
+    }
+}
+
+impl HyperLogLog {
+    /// This function adds an item to the set
+    ///
+    /// # Arguments
+    ///
+    /// * `item` - A byte slice that holds the item
+    pub fn insert(&mut self, item: &[u8]) {
+        // A cryptographic hash keeps stored sketches valid across builds, unlike the std hasher
+        let digest = Sha256::digest(item);
+        let mut hash = [0; 8];
+        hash.copy_from_slice(&digest[..8]);
+        let hash = u64::from_be_bytes(hash);
+        let index = (hash >> (64 - PRECISION)) as usize;
+        // The guard bit bounds the rank when the remaining bits are all zero
+        let rest = (hash << PRECISION) | (1 << (PRECISION - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        self.registers[index] = self.registers[index].max(rank);
+    }
+
+    /// This function merges the sketch of another set into this one
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - A HyperLogLog that holds the sketch of the other set
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        for (register, other) in self.registers.iter_mut().zip(other.registers.iter()) {
+            *register = (*register).max(*other);
+        }
+    }
+
+    /// This function estimates the number of distinct items added
+    ///
+    /// # Returns
+    ///
+    /// * `u64` - The estimated count
+    pub fn count(&self) -> u64 {
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|register| 2f64.powi(-(*register as i32)))
+            .sum();
+        let estimate = alpha * m * m / sum;
+        let zeros = self
+            .registers
+            .iter()
+            .filter(|register| **register == 0)
+            .count();
+        // Small sets are counted more precisely by the share of registers still empty
+        if estimate <= 2.5 * m && zeros > 0 {
+            return (m * (m / zeros as f64).ln()).round() as u64;
+        }
+        estimate.round() as u64
+    }
+}
+
+/// Sketches are stored as the hex encoded registers
+impl Serialize for HyperLogLog {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(&self.registers))
+    }
+}
+
+impl<'de> Deserialize<'de> for HyperLogLog {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let registers =
+            hex::decode(String::deserialize(deserializer)?).map_err(D::Error::custom)?;
+        if registers.len() != REGISTERS {
+            return Err(D::Error::custom(format!(
+                "expected {} registers, found {}",
+                REGISTERS,
+                registers.len()
+            )));
+        }
+        Ok(HyperLogLog { registers })
+    }
+}
@@ -0,0 +1,185 @@
+use crate::block_importer;
+use crate::config::Commitment;
+use crate::endpoints::RpcEndpoints;
+use crate::error::AggError;
+use crate::util::{TxInclusion, TxRecord};
+use serde::{Deserialize, Serialize};
+use solana_transaction_status::UiConfirmedBlock;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// Outcome of checking a stored transaction against the block the node serves for its slot
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct TxVerification {
+    pub tx_id: String,
+    pub block_no: u64,
+    pub slot: u64,
+    /// Position of the transaction among the transactions of the block as served by the node,
+    /// None when the node's block does not hold it
+    pub index: Option<usize>,
+    pub verified: bool,
+    /// What the stored block or record holds that the node's block does not, empty once verified
+    pub mismatches: Vec<String>,
+}
+
+/// This function fetches the block of a stored transaction from the node again and checks the
+/// stored block and record against it
+///
+/// # Arguments
+///
+/// * `endpoints` - An Arc<RpcEndpoints> that holds the endpoints the block is fetched from,
+///   shared with the subscriber so endpoints cooling down are skipped
+/// * `commitment` - A Commitment that holds the commitment of the block fetched
+/// * `inclusion` - A TxInclusion that holds where the transaction was stored
+/// * `record` - A TxRecord that holds the stored record of the transaction
+///
+/// # Returns
+///
+/// * `Result<TxVerification, AggError>` - The outcome of the check or an error if the block could
+///   not be fetched
+pub async fn fetch_and_verify(
+    endpoints: &Arc<RpcEndpoints>,
+    commitment: Commitment,
+    inclusion: &TxInclusion,
+    record: &TxRecord,
+) -> Result<TxVerification, AggError> {
+    let slot = inclusion.slot.ok_or_else(|| {
+        AggError::MalformedTransaction(format!(
+            "block {} of transaction {} has no slot",
+            inclusion.block_no, inclusion.tx_id
+        ))
+    })?;
+    let block = endpoints
+        .call_blocking(move |client| {
+            Ok(client.get_block_with_config(slot, block_importer::block_config(commitment))?)
+        })
+        .await?;
+    Ok(verify(inclusion, record, &block))
+}
+
+/// This function checks a stored transaction and the context of its block against the block the
+/// node serves for its slot
+///
+/// # Arguments
+///
+/// * `inclusion` - A TxInclusion that holds where the transaction was stored
+/// * `record` - A TxRecord that holds the stored record of the transaction
+/// * `block` - A UiConfirmedBlock that holds the block fetched from the node
+///
+/// # Returns
+///
+/// * `TxVerification` - The outcome of the check, listing every mismatch
+pub fn verify(
+    inclusion: &TxInclusion,
+    record: &TxRecord,
+    block: &UiConfirmedBlock,
+) -> TxVerification {
+    let mut mismatches = vec![];
+    if let Some(blockhash) = &inclusion.blockhash {
+        compare(&mut mismatches, "blockhash", blockhash, &block.blockhash);
+    }
+    if let Some(previous_blockhash) = &inclusion.previous_blockhash {
+        compare(
+            &mut mismatches,
+            "previous_blockhash",
+            previous_blockhash,
+            &block.previous_blockhash,
+        );
+    }
+    if let Some(parent_slot) = &inclusion.parent_slot {
+        compare(
+            &mut mismatches,
+            "parent_slot",
+            parent_slot,
+            &block.parent_slot,
+        );
+    }
+    let transactions = block.transactions.as_deref().unwrap_or_default();
+    if let Some(transaction_count) = inclusion.transaction_count {
+        compare(
+            &mut mismatches,
+            "transaction_count",
+            &transaction_count,
+            &(transactions.len() as u64),
+        );
+    }
+    let found = transactions.iter().enumerate().find_map(|(index, tx)| {
+        let transaction = tx.transaction.decode()?;
+        (transaction.message.hash().to_string() == inclusion.tx_id).then_some((
+            index,
+            transaction,
+            tx.meta.clone(),
+        ))
+    });
+    let index = found.as_ref().map(|(index, ..)| *index);
+    match found {
+        Some((_, transaction, meta)) => {
+            let signatures: Vec<String> = transaction
+                .signatures
+                .iter()
+                .map(ToString::to_string)
+                .collect();
+            // Records stored before the signatures were kept have none to compare
+            if !record.signatures().is_empty() {
+                compare(
+                    &mut mismatches,
+                    "signatures",
+                    &record.signatures().to_vec(),
+                    &signatures,
+                );
+            }
+            let stored = record.tx_logs(&inclusion.tx_id);
+            let fetched = TxRecord::new(vec![], meta)
+                .unwrap_or_default()
+                .tx_logs(&inclusion.tx_id);
+            compare(
+                &mut mismatches,
+                "succeeded",
+                &stored.succeeded,
+                &fetched.succeeded,
+            );
+            compare(&mut mismatches, "fee", &stored.fee, &fetched.fee);
+            compare(&mut mismatches, "error", &stored.error, &fetched.error);
+            compare(
+                &mut mismatches,
+                "compute_units",
+                &stored.compute_units,
+                &fetched.compute_units,
+            );
+            if stored.logs != fetched.logs {
+                mismatches.push(format!(
+                    "logs: stored {} lines, node {} lines",
+                    stored.logs.len(),
+                    fetched.logs.len()
+                ));
+            }
+        }
+        None => mismatches.push(format!(
+            "transaction: {} is not in the block of the node",
+            inclusion.tx_id
+        )),
+    }
+    TxVerification {
+        tx_id: inclusion.tx_id.clone(),
+        block_no: inclusion.block_no,
+        slot: inclusion.slot.unwrap_or_default(),
+        index,
+        verified: mismatches.is_empty(),
+        mismatches,
+    }
+}
+
+/// This function records a mismatch when a stored value differs from the node's
+fn compare<T: PartialEq + Debug>(
+    mismatches: &mut Vec<String>,
+    field: &str,
+    stored: &T,
+    fetched: &T,
+) {
+    if stored != fetched {
+        mismatches.push(format!(
+            "{}: stored {:?}, node {:?}",
+            field, stored, fetched
+        ));
+    }
+}
@@ -0,0 +1,126 @@
+use crate::util::{Block, UnprocessedBlock};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Finalized blocks whose slot the handler keeps to check the slots of the next blocks against
+const TRACKED_BLOCKS: usize = 4_096;
+
+/// This function returns the transactions a parsed chunk or block accounts for, the parsed and
+/// partial records and the quarantined transactions alike
+fn accounted_transactions(block: &Block) -> u64 {
+    (block.transactions().count() + block.quarantined_transactions().len()) as u64
+}
+
+/// This function checks an assembled block against the chunks it was assembled from, so a block
+/// missing a chunk or transactions is never taken for complete
+///
+/// # Arguments
+///
+/// * `unprocessed` - An UnprocessedBlock that holds the collected chunks
+/// * `block` - A Block that holds the block assembled from the chunks
+///
+/// # Returns
+///
+/// * `Vec<String>` - What the block violates, empty for a sound block
+pub fn check_assembly(unprocessed: &UnprocessedBlock, block: &Block) -> Vec<String> {
+    let mut violations = vec![];
+    let collected: BTreeSet<u64> = unprocessed.chunks().map(|(chunk_no, _)| chunk_no).collect();
+    let missing: Vec<u64> = (0..unprocessed.total_chunks())
+        .filter(|chunk_no| !collected.contains(chunk_no))
+        .collect();
+    if !missing.is_empty() {
+        violations.push(format!(
+            "chunks {:?} of {} are missing",
+            missing,
+            unprocessed.total_chunks()
+        ));
+    }
+    let slots: BTreeSet<Option<u64>> = unprocessed
+        .chunks()
+        .map(|(_, chunk)| chunk.slot())
+        .collect();
+    if slots.len() > 1 {
+        violations.push(format!("the chunks are of the slots {:?}", slots));
+    }
+    let chunk_transactions: u64 = unprocessed
+        .chunks()
+        .map(|(_, chunk)| accounted_transactions(chunk))
+        .sum();
+    if let Some(transaction_count) = block.transaction_count() {
+        if chunk_transactions != transaction_count {
+            violations.push(format!(
+                "the chunks hold {} transactions, the node served {}",
+                chunk_transactions, transaction_count
+            ));
+        }
+    }
+    let assembled = accounted_transactions(block);
+    if assembled != chunk_transactions {
+        violations.push(format!(
+            "{} transactions were assembled out of the {} of the chunks",
+            assembled, chunk_transactions
+        ));
+    }
+    violations
+}
+
+/// Slots of the blocks finalized since the handler started, as the slots of finalized blocks must
+/// increase with their block numbers. Confirmed blocks are not checked, a block of another fork
+/// may replace them.
+#[derive(Default)]
+pub struct FinalizedSlots {
+    slots: BTreeMap<u64, u64>,
+}
+
+impl FinalizedSlots {
+    /// This function checks the slot of a block against the finalized blocks around it
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number
+    /// * `slot` - A u64 that holds the slot of the block
+    ///
+    /// # Returns
+    ///
+    /// * `Option<String>` - The violation, None when the slot is in order
+    pub fn check(&self, block_no: u64, slot: u64) -> Option<String> {
+        if let Some(finalized) = self.slots.get(&block_no) {
+            if *finalized != slot {
+                return Some(format!(
+                    "block {} was finalized at slot {}, not {}",
+                    block_no, finalized, slot
+                ));
+            }
+        }
+        if let Some((previous_no, previous_slot)) = self.slots.range(..block_no).next_back() {
+            if *previous_slot >= slot {
+                return Some(format!(
+                    "slot {} of block {} is not after slot {} of the finalized block {}",
+                    slot, block_no, previous_slot, previous_no
+                ));
+            }
+        }
+        if let Some((next_no, next_slot)) = self.slots.range(block_no + 1..).next() {
+            if *next_slot <= slot {
+                return Some(format!(
+                    "slot {} of block {} is not before slot {} of the finalized block {}",
+                    slot, block_no, next_slot, next_no
+                ));
+            }
+        }
+        None
+    }
+
+    /// This function records the slot of a finalized block, forgetting the oldest blocks past
+    /// TRACKED_BLOCKS
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number
+    /// * `slot` - A u64 that holds the slot of the block
+    pub fn record(&mut self, block_no: u64, slot: u64) {
+        self.slots.insert(block_no, slot);
+        while self.slots.len() > TRACKED_BLOCKS {
+            self.slots.pop_first();
+        }
+    }
+}
@@ -0,0 +1,68 @@
+//! Solana block aggregator: ingests blocks from a node into RocksDb and serves them over HTTP,
+//! WebSocket and gRPC. The `solana-agg` binary is a thin wrapper of this crate, so other services
+//! can run the whole pipeline through [`run`], assemble their own through [`Builder`], reuse the
+//! indexed types, or query a running instance through `AggClient` with the `client` feature.
+
+pub mod aggregation;
+pub mod alerting;
+pub mod autotune;
+pub mod backfill;
+pub mod block_importer;
+pub mod breaker;
+pub mod builder;
+pub mod chain;
+pub mod cli;
+#[cfg(feature = "client")]
+pub mod client;
+pub mod codec;
+pub mod compare;
+pub mod config;
+pub mod db_handler;
+pub mod endpoints;
+pub mod envelope;
+pub mod error;
+pub mod export;
+pub mod fanout;
+pub mod fetch_cache;
+pub mod fetch_pool;
+pub mod gaps;
+pub mod grpc;
+pub mod handler;
+pub mod harness;
+pub mod hyperloglog;
+pub mod inclusion;
+pub mod invariants;
+pub mod logging;
+pub mod metrics;
+pub mod parser;
+pub mod pipeline;
+pub mod plugin;
+pub mod preflight;
+#[cfg(feature = "profiling")]
+pub mod profiling;
+pub mod program_metrics;
+pub mod rate_limit;
+pub mod recovery;
+pub mod reorg;
+pub mod replication;
+pub mod retry;
+pub mod server;
+pub mod shutdown;
+pub mod snapshot;
+pub mod spill;
+pub mod state_applier;
+pub mod stats;
+pub mod tenant;
+pub mod timestamp;
+#[cfg(feature = "ui")]
+pub mod ui;
+pub mod util;
+pub mod warmup;
+pub mod webhook;
+
+pub use builder::Builder;
+#[cfg(feature = "client")]
+pub use client::AggClient;
+pub use error::AggError;
+pub use pipeline::run;
+pub use util::{Block, Instruction, TxRecord};
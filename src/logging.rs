@@ -0,0 +1,205 @@
+use crate::chain;
+use crate::error::AggError;
+use chrono::DateTime;
+use log::{LevelFilter, Log, Metadata, Record};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Environment variable holding the levels set on startup, as `level` and `target=level` pairs
+/// separated by commas
+const LOG_ENV: &str = "RUST_LOG";
+
+/// Levels of the logger, listed by `/admin/log_levels`
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct LogLevels {
+    /// Level of the targets without a level of their own
+    pub default: String,
+    pub targets: BTreeMap<String, String>,
+}
+
+struct Levels {
+    default: LevelFilter,
+    targets: BTreeMap<String, LevelFilter>,
+}
+
+/// Logger writing to stderr whose levels can be changed per target while running, so a
+/// production sync issue can be debugged without a restart losing the state of the pipeline
+struct TargetLogger {
+    levels: RwLock<Levels>,
+}
+
+impl TargetLogger {
+    /// This function returns the level of a target, the level of its longest configured prefix
+    /// for module path targets such as `actix_web::middleware::logger`
+    fn level(&self, target: &str) -> LevelFilter {
+        let levels = match self.levels.read() {
+            Ok(levels) => levels,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        levels
+            .targets
+            .iter()
+            .filter(|(prefix, _)| {
+                target == prefix.as_str()
+                    || target
+                        .strip_prefix(prefix.as_str())
+                        .is_some_and(|rest| rest.starts_with("::"))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(levels.default, |(_, level)| *level)
+    }
+
+    fn update(&self, update: impl FnOnce(&mut Levels)) {
+        let mut levels = match self.levels.write() {
+            Ok(levels) => levels,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        update(&mut levels);
+    }
+}
+
+impl Log for TargetLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_secs() as i64);
+        let time = DateTime::from_timestamp(now, 0)
+            .map(|time| time.to_rfc3339())
+            .unwrap_or_default();
+        match chain::installed() {
+            Some(chain_id) => eprintln!(
+                "[{} {:<5} {} {}] {}",
+                time,
+                record.level(),
+                chain_id,
+                record.target(),
+                record.args()
+            ),
+            None => eprintln!(
+                "[{} {:<5} {}] {}",
+                time,
+                record.level(),
+                record.target(),
+                record.args()
+            ),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: Lazy<TargetLogger> = Lazy::new(|| TargetLogger {
+    levels: RwLock::new(Levels {
+        default: LevelFilter::Info,
+        targets: BTreeMap::new(),
+    }),
+});
+
+/// This function installs the logger with the levels of `RUST_LOG`, `info` for every target if
+/// unset
+///
+/// # Returns
+///
+/// * `Result<(), AggError>` - A Result that holds the result or an error if `RUST_LOG` is
+///   invalid or a logger is already installed
+pub fn init() -> Result<(), AggError> {
+    if let Ok(spec) = std::env::var(LOG_ENV) {
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((target, level)) => set_level(Some(target), parse_level(level)?),
+                None => set_level(None, parse_level(directive)?),
+            }
+        }
+    }
+    log::set_logger(&*LOGGER)
+        .map_err(|err| AggError::ConfigError(format!("Unable to install the logger: {}", err)))?;
+    // Records are filtered per target by the logger
+    log::set_max_level(LevelFilter::Trace);
+    Ok(())
+}
+
+/// This function parses a level name such as `debug` or `off`
+///
+/// # Arguments
+///
+/// * `level` - A string slice that holds the level name
+///
+/// # Returns
+///
+/// * `Result<LevelFilter, AggError>` - A Result that holds the level or an error
+pub fn parse_level(level: &str) -> Result<LevelFilter, AggError> {
+    LevelFilter::from_str(level.trim())
+        .map_err(|_| AggError::ConfigError(format!("Unknown log level {}", level)))
+}
+
+/// This function returns the current levels
+pub fn levels() -> LogLevels {
+    let levels = match LOGGER.levels.read() {
+        Ok(levels) => levels,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    let name = |level: &LevelFilter| level.as_str().to_lowercase();
+    LogLevels {
+        default: name(&levels.default),
+        targets: levels
+            .targets
+            .iter()
+            .map(|(target, level)| (target.clone(), name(level)))
+            .collect(),
+    }
+}
+
+/// This function sets the level of a target, or the default level
+///
+/// # Arguments
+///
+/// * `target` - An Option<&str> that holds the target, such as `db` or `subscriber`, None for
+///   the default level
+/// * `level` - A LevelFilter that holds the level
+pub fn set_level(target: Option<&str>, level: LevelFilter) {
+    LOGGER.update(|levels| match target {
+        Some(target) => {
+            levels.targets.insert(target.to_string(), level);
+        }
+        None => levels.default = level,
+    });
+}
+
+/// This function drops the level of a target, which falls back to the default level
+///
+/// # Arguments
+///
+/// * `target` - A string slice that holds the target
+///
+/// # Returns
+///
+/// * `bool` - Whether the target had a level of its own
+pub fn reset_level(target: &str) -> bool {
+    let mut removed = false;
+    LOGGER.update(|levels| removed = levels.targets.remove(target).is_some());
+    removed
+}
+
+/// This function returns the level records of a target are logged at
+///
+/// # Arguments
+///
+/// * `target` - A string slice that holds the target
+///
+/// # Returns
+///
+/// * `LevelFilter` - The most verbose level logged for the target
+pub fn level(target: &str) -> LevelFilter {
+    LOGGER.level(target)
+}
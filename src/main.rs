@@ -1,61 +1,118 @@
-use crate::builder::Builder;
-use crate::cli::Cli;
-use crate::util::{Channel, ProtocolMessage};
 use log::error;
+use solana_agg::cli::{Cli, Command, SnapshotCommand};
+use solana_agg::config::Config;
+use solana_agg::{compare, endpoints, harness, logging, snapshot};
 use structopt::StructOpt;
 
-mod block_importer;
-mod builder;
-mod cli;
-mod db_handler;
-mod error;
-mod handler;
-mod parser;
-mod server;
-mod util;
+// The allocator is only set by the binary, so embedders of the library keep their own
+#[cfg(feature = "profiling")]
+#[global_allocator]
+static ALLOCATOR: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
 
-#[tokio::main]
-async fn main() {
+/// Samples an allocation every 512 KiB on average from startup, so a heap profile can be dumped
+/// at any time without restarting
+#[cfg(feature = "profiling")]
+#[allow(non_upper_case_globals)]
+#[export_name = "_rjem_malloc_conf"]
+pub static malloc_conf: &[u8] = b"prof:true,prof_active:true,lg_prof_sample:19\0";
+
+fn main() {
     let opt: Cli = Cli::from_args();
-    let handler_channel = Channel::<ProtocolMessage>::new();
-    let db_channel = Channel::<ProtocolMessage>::new();
-    let handler_channel_receiver_server = handler_channel.sender();
-    let mut subscriber_client = match Builder::default()
-        .chain_url(opt.chain_url)
-        .router_sender(handler_channel.sender())
-        .build()
+    if let Err(e) = logging::init() {
+        eprintln!("Error initializing the logger {}", e);
+        return;
+    }
+    let config = match opt.command {
+        Some(Command::Compare { .. }) => Config::default(),
+        _ => match Config::resolve(&opt) {
+            Ok(config) => config,
+            Err(e) => {
+                error!(target:"config", "Error loading config {}",e);
+                return;
+            }
+        },
+    };
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.enable_all();
+    if let Some(worker_threads) = config.node.worker_threads {
+        runtime.worker_threads(worker_threads);
+    }
+    match runtime.build() {
+        Ok(runtime) => runtime.block_on(run(opt, config)),
+        Err(e) => error!(target:"config", "Error starting the runtime {}",e),
+    }
+}
+
+async fn run(opt: Cli, config: Config) {
+    if let Some(Command::Compare {
+        left,
+        right,
+        start,
+        end,
+    }) = &opt.command
     {
-        Ok(subscriber) => subscriber,
-        Err(e) => {
-            error!(target:"subscriber", "Error from subscriber client {}",e);
-            return;
+        match compare::run(left, right, *start, *end).await {
+            Ok(true) => println!("Blocks {}..={} are identical", start, end),
+            Ok(false) => std::process::exit(1),
+            Err(e) => {
+                error!(target:"compare", "Error comparing instances {}",e);
+                std::process::exit(1);
+            }
         }
-    };
-    let mut handler = Builder::default()
-        .db_sender(db_channel.sender())
-        .router_receiver(handler_channel.receiver)
-        .build();
-    let mut db_client = match Builder::default()
-        .db_path(opt.db_path)
-        .db_receiver(db_channel.receiver)
-        .build()
+        return;
+    }
+    if let Some(Command::TestHarness {
+        api_url,
+        transfers,
+        within_slots,
+    }) = &opt.command
     {
-        Ok(db) => db,
-        Err(e) => {
-            error!(target:"db", "Error from db client {}",e);
-            return;
+        let chain_urls = endpoints::chain_urls(&config.node.chain_url);
+        let chain_url = chain_urls.first().map(String::as_str).unwrap_or_default();
+        match harness::run(chain_url, api_url, *transfers, *within_slots).await {
+            Ok(outcomes) => {
+                for outcome in &outcomes {
+                    match outcome.indexed_after_slots {
+                        Some(slots) => println!(
+                            "Transfer {} of slot {} indexed within {} slots",
+                            outcome.signature, outcome.slot, slots
+                        ),
+                        None => println!(
+                            "Transfer {} of slot {} not indexed within {} slots",
+                            outcome.signature, outcome.slot, within_slots
+                        ),
+                    }
+                }
+                if !harness::passed(&outcomes) {
+                    std::process::exit(1);
+                }
+            }
+            Err(e) => {
+                error!(target:"harness", "Error from test harness {}",e);
+                std::process::exit(1);
+            }
         }
-    };
-    tokio::spawn(async move {
-        db_client.run().await;
-    });
-    tokio::spawn(async move {
-        handler.run().await;
-    });
-    tokio::spawn(async move {
-        subscriber_client.run().await;
-    });
-    if let Err(error) = server::AggServer::run(handler_channel_receiver_server, opt.port_no).await {
-        error!(target:"server", "Error from server client {}",error);
+        return;
+    }
+    if let Some(Command::Snapshot(command)) = &opt.command {
+        let db_path = &config.node.db_path;
+        let result = match command {
+            SnapshotCommand::Create { path } => snapshot::create(db_path, path),
+            SnapshotCommand::Restore { path } => snapshot::restore(path, db_path),
+        };
+        match result {
+            Ok(manifest) => println!(
+                "Snapshot through block {:?} of genesis {:?}",
+                manifest.latest_block_no, manifest.genesis_hash
+            ),
+            Err(e) => {
+                error!(target:"snapshot", "Error from snapshot {}",e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if solana_agg::run(config, opt).await.is_err() {
+        std::process::exit(1);
     }
 }
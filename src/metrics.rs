@@ -0,0 +1,381 @@
+use crate::chain;
+use once_cell::sync::Lazy;
+use prometheus::core::Collector;
+use prometheus::proto::LabelPair;
+use prometheus::{
+    CounterVec, Encoder, Gauge, GaugeVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use std::collections::BTreeMap;
+
+pub static REGISTRY: Lazy<Registry> = Lazy::new(Registry::new);
+
+pub static TPS: Lazy<GaugeVec> = Lazy::new(|| {
+    register(GaugeVec::new(
+        Opts::new("agg_tps", "Transactions per second of finalized blocks"),
+        &["window"],
+    ))
+});
+
+pub static SUCCESS_RATIO: Lazy<GaugeVec> = Lazy::new(|| {
+    register(GaugeVec::new(
+        Opts::new("agg_tx_success_ratio", "Ratio of successful transactions"),
+        &["window"],
+    ))
+});
+
+pub static AVERAGE_FEE: Lazy<GaugeVec> = Lazy::new(|| {
+    register(GaugeVec::new(
+        Opts::new(
+            "agg_average_fee_lamports",
+            "Average transaction fee in lamports",
+        ),
+        &["window"],
+    ))
+});
+
+pub static EXPIRED_QUERIES: Lazy<IntCounter> = Lazy::new(|| {
+    register(IntCounter::new(
+        "agg_expired_queries_total",
+        "Queries dropped by the db because their deadline passed while queued",
+    ))
+});
+
+pub static SANITIZED_BLOCK_TIMES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "agg_sanitized_block_times_total",
+            "Block times interpolated or clamped because the node reported none or out of order",
+        ),
+        &["flag"],
+    ))
+});
+
+pub static FAN_OUT_QUEUED: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register(IntGaugeVec::new(
+        Opts::new(
+            "agg_fan_out_queued_blocks",
+            "Finalized blocks queued for a sink of the fan-out",
+        ),
+        &["sink"],
+    ))
+});
+
+pub static FAN_OUT_DROPPED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "agg_fan_out_dropped_blocks_total",
+            "Finalized blocks dropped for a sink of the fan-out, or live messages for a stream, because its queue was full",
+        ),
+        &["sink"],
+    ))
+});
+
+pub static PLUGIN_BLOCKS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "agg_plugin_blocks_total",
+            "Finalized blocks seen by each plugin, by outcome (kept, rejected or panicked)",
+        ),
+        &["plugin", "outcome"],
+    ))
+});
+
+pub static PARSE_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "agg_parse_failures_total",
+            "Transactions that failed to parse, stored as partial records or quarantined",
+        ),
+        &["outcome"],
+    ))
+});
+
+pub static PROGRAM_METRICS: Lazy<CounterVec> = Lazy::new(|| {
+    register(CounterVec::new(
+        Opts::new(
+            "agg_program_metric_total",
+            "Values of the program metric hooks summed over the parsed instructions",
+        ),
+        &["metric", "program"],
+    ))
+});
+
+pub static RETRIES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "agg_retries_total",
+            "Attempts of the pipeline that failed with a transient error and were retried",
+        ),
+        &["stage"],
+    ))
+});
+
+pub static DEAD_LETTERS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "agg_dead_letters_total",
+            "Blocks the pipeline gave up on and stored in the dead-letter queue",
+        ),
+        &["stage", "code"],
+    ))
+});
+
+pub static TX_CONFLICTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "agg_tx_conflicts_total",
+            "Transactions found in blocks of two different slots, by the block the index kept",
+        ),
+        &["resolution"],
+    ))
+});
+
+pub static REORGS: Lazy<IntCounter> = Lazy::new(|| {
+    register(IntCounter::new(
+        "agg_reorgs_total",
+        "Reorgs of the confirmed chain followed by rolling back the blocks of the abandoned fork",
+    ))
+});
+
+pub static ROLLED_BACK_BLOCKS: Lazy<IntCounter> = Lazy::new(|| {
+    register(IntCounter::new(
+        "agg_rolled_back_blocks_total",
+        "Stored blocks rolled back because the chain abandoned their fork",
+    ))
+});
+
+pub static WORKER_RESTARTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "agg_worker_restarts_total",
+            "Restarts of a supervised worker after it panicked or stopped on its own",
+        ),
+        &["worker"],
+    ))
+});
+
+pub static MISSING_SLOTS: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new(
+        "agg_missing_slots",
+        "Slots whose block failed to be fetched and is waiting to be fetched again",
+    ))
+});
+
+pub static RESOLVED_GAPS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "agg_resolved_gaps_total",
+            "Missing slots that were fetched, turned out to be skipped or were given up on",
+        ),
+        &["outcome"],
+    ))
+});
+
+pub static BLOCK_WRITE_LATENCY: Lazy<Gauge> = Lazy::new(|| {
+    register(Gauge::new(
+        "agg_block_write_latency_seconds",
+        "Moving average of the time the db takes to store a block",
+    ))
+});
+
+pub static AUTOTUNE_SETTINGS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register(IntGaugeVec::new(
+        Opts::new(
+            "agg_autotune_setting",
+            "Fetch settings chosen by the auto-tuner, slots_per_round and chunk_size",
+        ),
+        &["setting"],
+    ))
+});
+
+pub static FETCH_CACHE_LOOKUPS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "agg_fetch_cache_lookups_total",
+            "Blocks looked up in the fetch cache before fetching them from the node, by hit or miss",
+        ),
+        &["result"],
+    ))
+});
+
+pub static FETCH_CACHE_SIZE: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new(
+        "agg_fetch_cache_bytes",
+        "Bytes the blocks in the fetch cache take on disk",
+    ))
+});
+
+pub static FETCH_QUEUE_DEPTH: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new(
+        "agg_fetch_queue_depth",
+        "Block fetches queued for a worker of the fetch pool",
+    ))
+});
+
+pub static FETCH_IN_FLIGHT: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new(
+        "agg_fetch_in_flight",
+        "Block fetches a worker of the fetch pool is running",
+    ))
+});
+
+pub static FETCH_OLDEST_PENDING_SLOT: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new(
+        "agg_fetch_oldest_pending_slot",
+        "Oldest slot whose block fetch is queued or running, 0 when none is",
+    ))
+});
+
+pub static ALERTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "agg_alerts_total",
+            "Alerts raised and resolved by the alert monitor, by kind and state",
+        ),
+        &["kind", "state"],
+    ))
+});
+
+pub static ALERT_NOTIFICATION_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "agg_alert_notification_failures_total",
+            "Alerts an alerting sink failed to be sent",
+        ),
+        &["sink"],
+    ))
+});
+
+pub static READY: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new(
+        "agg_ready",
+        "1 once the db is opened and its caches warmed up, the value /ready answers with",
+    ))
+});
+
+pub static WARMUP_SECONDS: Lazy<Gauge> = Lazy::new(|| {
+    register(Gauge::new(
+        "agg_warmup_seconds",
+        "Time the startup warm-up of the caches took",
+    ))
+});
+
+pub static RPC_ENDPOINT_LATENCY: Lazy<GaugeVec> = Lazy::new(|| {
+    register(GaugeVec::new(
+        Opts::new(
+            "agg_rpc_endpoint_latency_seconds",
+            "Moving average of the time an RPC endpoint takes to respond",
+        ),
+        &["endpoint"],
+    ))
+});
+
+pub static RPC_ENDPOINT_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "agg_rpc_endpoint_failures_total",
+            "Requests to an RPC endpoint that timed out, were throttled or found the node unavailable",
+        ),
+        &["endpoint"],
+    ))
+});
+
+pub static RPC_ENDPOINT_AVAILABLE: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register(IntGaugeVec::new(
+        Opts::new(
+            "agg_rpc_endpoint_available",
+            "Whether an RPC endpoint is used, 0 while it cools down after failing",
+        ),
+        &["endpoint"],
+    ))
+});
+
+pub static UPSTREAM_BREAKER_OPEN: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new(
+        "agg_upstream_breaker_open",
+        "Whether the calls made to the node while answering requests fail at once, 1 after it kept failing",
+    ))
+});
+
+pub static CHUNK_IN_MEMORY_BYTES: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new(
+        "agg_chunk_in_memory_bytes",
+        "Estimated bytes of the transaction chunks held in memory until they are parsed",
+    ))
+});
+
+pub static SPILLED_CHUNKS: Lazy<IntCounter> = Lazy::new(|| {
+    register(IntCounter::new(
+        "agg_spilled_chunks_total",
+        "Transaction chunks written to the spill directory because the chunks in memory took their budget",
+    ))
+});
+
+pub static SLOTS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register(IntGaugeVec::new(
+        Opts::new(
+            "agg_slot",
+            "Latest finalized slot of the chain and slot of the latest indexed block",
+        ),
+        &["slot"],
+    ))
+});
+
+fn register<C: Collector + Clone + 'static>(collector: prometheus::Result<C>) -> C {
+    let collector = collector.expect("metric options are valid");
+    if let Err(err) = REGISTRY.register(Box::new(collector.clone())) {
+        log::error!(target: "metrics", "Failed to register metric {}", err);
+    }
+    collector
+}
+
+/// This function sums the values of a counter by one of its labels
+///
+/// # Arguments
+///
+/// * `counter` - An IntCounterVec that holds the counter
+/// * `label` - A string slice that holds the name of the label
+///
+/// # Returns
+///
+/// * `BTreeMap<String, u64>` - The total of every value of the label
+pub fn counter_totals(counter: &IntCounterVec, label: &str) -> BTreeMap<String, u64> {
+    let mut totals = BTreeMap::new();
+    for family in counter.collect() {
+        for metric in family.get_metric() {
+            let value = metric
+                .get_label()
+                .iter()
+                .find(|pair| pair.get_name() == label)
+                .map(|pair| pair.get_value().to_string())
+                .unwrap_or_default();
+            *totals.entry(value).or_default() += metric.get_counter().get_value() as u64;
+        }
+    }
+    totals
+}
+
+/// This function renders every registered metric in the Prometheus text format, labeled with the
+/// chain id of the instance once it is set, so the metrics of instances indexing different
+/// clusters can be told apart
+pub fn render() -> String {
+    let mut families = REGISTRY.gather();
+    if let Some(chain_id) = chain::installed() {
+        for family in families.iter_mut() {
+            for metric in family.mut_metric().iter_mut() {
+                let mut label = LabelPair::default();
+                label.set_name("chain_id".to_string());
+                label.set_value(chain_id.to_string());
+                let mut labels = metric.take_label();
+                labels.push(label);
+                metric.set_label(labels);
+            }
+        }
+    }
+    let mut buffer = vec![];
+    if let Err(err) = TextEncoder::new().encode(&families, &mut buffer) {
+        log::error!(target: "metrics", "Failed to encode metrics {}", err);
+    }
+    String::from_utf8(buffer).unwrap_or_default()
+}
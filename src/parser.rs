@@ -1,11 +1,51 @@
+use crate::config::ParseMode;
 use crate::error::AggError;
-use crate::util::{Block, Instruction, ProtocolMessage, TxRecord};
+use crate::metrics;
+use crate::program_metrics::{self, InstructionContext};
+use crate::util::{
+    AuthorityChange, Block, BlockHeader, Instruction, ParseErrorKind, ProtocolMessage,
+    QuarantinedTx, TokenAuthorityType, TokenBalance, TxRecord,
+};
 use log::debug;
+use solana_program::hash::{hash, Hash};
 use solana_program::instruction::CompiledInstruction;
 use solana_program::message::VersionedMessage;
 use solana_program::pubkey::Pubkey;
+use solana_transaction_status::option_serializer::OptionSerializer;
+use solana_transaction_status::{
+    EncodedTransactionWithStatusMeta, UiTransactionStatusMeta, UiTransactionTokenBalance,
+};
 use std::str::FromStr;
 
+/// The SPL Token program and Token-2022, which share the layout of the transfer instructions
+const TOKEN_PROGRAM_IDS: [&str; 2] = [
+    "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA",
+    "TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb",
+];
+/// Tag of `Transfer { amount }`, accounts: source, destination, authority
+const TOKEN_TRANSFER: u8 = 3;
+/// Tag of `TransferChecked { amount, decimals }`, accounts: source, mint, destination, authority
+const TOKEN_TRANSFER_CHECKED: u8 = 12;
+/// Tag of `Approve { amount }`, accounts: source, delegate, owner
+const TOKEN_APPROVE: u8 = 4;
+/// Tag of `Revoke`, accounts: source, owner
+const TOKEN_REVOKE: u8 = 5;
+/// Tag of `SetAuthority { authority_type, new_authority }`, accounts: account or mint, current
+/// authority
+const TOKEN_SET_AUTHORITY: u8 = 6;
+/// Tag of `ApproveChecked { amount, decimals }`, accounts: source, mint, delegate, owner
+const TOKEN_APPROVE_CHECKED: u8 = 13;
+const SYSTEM_PROGRAM_ID: &str = "11111111111111111111111111111111";
+/// Tag of `CreateAccount { lamports, space, owner }`, accounts: funder, new account
+const SYSTEM_CREATE_ACCOUNT: u32 = 0;
+/// Tag of `Assign { owner }`, accounts: assigned account
+const SYSTEM_ASSIGN: u32 = 1;
+/// Tag of `CreateAccountWithSeed { base, seed, lamports, space, owner }`, accounts: funder, new
+/// account, base
+const SYSTEM_CREATE_ACCOUNT_WITH_SEED: u32 = 3;
+/// Tag of `AssignWithSeed { base, seed, owner }`, accounts: assigned account, base
+const SYSTEM_ASSIGN_WITH_SEED: u32 = 10;
+
 pub struct Parser;
 
 impl Parser {
@@ -15,38 +55,17 @@ impl Parser {
     /// # Arguments
     ///
     /// * `message` - A ProtocolMessage that holds the message
+    /// * `mode` - A ParseMode that holds what to do with transactions failing to parse
     ///
     /// # Returns
     ///
     /// * `Result<(), AggError>` - A Result that holds the result or an error
-    pub async fn invoke(message: ProtocolMessage) -> Result<(), AggError> {
-        if let ProtocolMessage::NewChuck(block_no, chunk_no, total_chunks, txs, sender) = message {
-            let mut partial_block = Block::default();
-            for (_, tx) in txs.iter().enumerate() {
-                let mut instructions = vec![];
-                if let Some(transaction) = tx.transaction.decode() {
-                    let message = &transaction.message;
-                    for (_, instruction) in message.instructions().iter().enumerate() {
-                        if Self::is_transfer_instruction(&message, instruction)? {
-                            instructions
-                                .push(Self::decode_transfer_instruction(&message, instruction)?);
-                        }
-                    }
-                    if let Some(meta) = tx.meta.clone() {
-                        let sender_account = message.static_account_keys()[0];
-                        let sender_balance = meta.post_balances[0];
-                        let receiver_account = message.static_account_keys()[1];
-                        let receiver_balance = meta.post_balances[1];
-                        partial_block.insert_account(sender_account.to_string(), sender_balance);
-                        partial_block
-                            .insert_account(receiver_account.to_string(), receiver_balance);
-                    }
-                    partial_block.push_transaction(
-                        transaction.message.hash(),
-                        TxRecord::new(instructions, tx.meta.clone()),
-                    );
-                }
-            }
+    pub async fn invoke(message: ProtocolMessage, mode: ParseMode) -> Result<(), AggError> {
+        if let ProtocolMessage::NewChuck(block_no, header, chunk_no, total_chunks, txs, sender) =
+            message
+        {
+            let txs = txs.transactions()?;
+            let partial_block = Self::parse(header, &txs, mode, !program_metrics::is_empty())?;
             sender.send(ProtocolMessage::parsed_block(
                 block_no,
                 total_chunks,
@@ -57,32 +76,338 @@ impl Parser {
         Ok(())
     }
 
+    /// This function parses a chunk of encoded transactions into a partial block in permissive
+    /// mode, without evaluating the program metric hooks
+    ///
+    /// # Arguments
+    ///
+    /// * `header` - A BlockHeader that holds the slot, blockhash and time of the block
+    /// * `txs` - A slice of EncodedTransactionWithStatusMeta that holds the transactions
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Block, AggError>` - A Result that holds the partial block or an error
+    pub fn parse_chunk(
+        header: BlockHeader,
+        txs: &[EncodedTransactionWithStatusMeta],
+    ) -> Result<Block, AggError> {
+        Self::parse(header, txs, ParseMode::Permissive, false)
+    }
+
+    /// This function parses the transactions of a block fetched again into a block, without
+    /// evaluating the program metric hooks, the block was counted when first ingested
+    ///
+    /// # Arguments
+    ///
+    /// * `header` - A BlockHeader that holds the slot, blockhash and time of the block
+    /// * `txs` - A slice of EncodedTransactionWithStatusMeta that holds the transactions
+    /// * `mode` - A ParseMode that holds what to do with transactions failing to parse
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Block, AggError>` - A Result that holds the block or an error
+    pub fn parse_block(
+        header: BlockHeader,
+        txs: &[EncodedTransactionWithStatusMeta],
+        mode: ParseMode,
+    ) -> Result<Block, AggError> {
+        Self::parse(header, txs, mode, false)
+    }
+
+    /// This function parses a chunk of encoded transactions into a partial block, evaluating the
+    /// program metric hooks on its instructions when `observe` is set. Re-parsed chunks are not
+    /// observed, they were counted when first ingested.
+    ///
+    /// # Arguments
+    ///
+    /// * `header` - A BlockHeader that holds the slot, blockhash and time of the block
+    /// * `txs` - A slice of EncodedTransactionWithStatusMeta that holds the transactions
+    /// * `mode` - A ParseMode that holds what to do with transactions failing to parse
+    /// * `observe` - A bool that holds whether to evaluate the program metric hooks
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Block, AggError>` - A Result that holds the partial block or an error
+    fn parse(
+        header: BlockHeader,
+        txs: &[EncodedTransactionWithStatusMeta],
+        mode: ParseMode,
+        observe: bool,
+    ) -> Result<Block, AggError> {
+        let mut partial_block = Block::default();
+        partial_block.set_header(header);
+        for tx in txs.iter() {
+            let Err(error) = Self::parse_transaction(tx, observe, &mut partial_block) else {
+                continue;
+            };
+            debug!(target: "parser", "Failed to parse transaction: {}", error);
+            partial_block.record_parse_error(ParseErrorKind::from(&error));
+            match mode {
+                ParseMode::Permissive => {
+                    metrics::PARSE_FAILURES
+                        .with_label_values(&["partial"])
+                        .inc();
+                    partial_block.push_transaction(
+                        Self::fallback_tx_hash(tx),
+                        TxRecord::unparsed(tx.meta.clone(), error.to_string()),
+                    );
+                }
+                ParseMode::Strict => {
+                    metrics::PARSE_FAILURES
+                        .with_label_values(&["quarantined"])
+                        .inc();
+                    partial_block.quarantine_transaction(QuarantinedTx {
+                        error: error.to_string(),
+                        raw: tx.clone(),
+                    });
+                }
+            }
+        }
+        Ok(partial_block)
+    }
+
+    /// This function parses a transaction into the partial block. Its instructions are decoded
+    /// before anything is recorded, so a transaction failing to parse leaves the block untouched
+    ///
+    /// # Arguments
+    ///
+    /// * `tx` - An EncodedTransactionWithStatusMeta that holds the transaction
+    /// * `observe` - A bool that holds whether to evaluate the program metric hooks
+    /// * `partial_block` - A Block that receives the transaction
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    fn parse_transaction(
+        tx: &EncodedTransactionWithStatusMeta,
+        observe: bool,
+        partial_block: &mut Block,
+    ) -> Result<(), AggError> {
+        let transaction = tx
+            .transaction
+            .decode()
+            .ok_or(AggError::UndecodableTransaction)?;
+        let message = &transaction.message;
+        let account_keys = Self::account_keys(message, tx.meta.as_ref());
+        let mut instructions = vec![];
+        for instruction in message.instructions() {
+            if Self::is_transfer_instruction(message, instruction)? {
+                instructions.push(Self::decode_transfer_instruction(message, instruction)?);
+            } else if let Some(transfer) =
+                Self::decode_token_transfer(&account_keys, tx.meta.as_ref(), instruction)?
+            {
+                instructions.push(transfer);
+            } else if let Some(change) = Self::decode_authority_change(&account_keys, instruction)?
+            {
+                instructions.push(change);
+            }
+        }
+        let unresolved_lookups = message
+            .address_table_lookups()
+            .is_some_and(|lookups| !lookups.is_empty())
+            && !matches!(
+                tx.meta.as_ref().map(|meta| &meta.loaded_addresses),
+                Some(OptionSerializer::Some(_))
+            );
+        if unresolved_lookups {
+            partial_block.record_parse_error(ParseErrorKind::UnresolvedLookupAddresses);
+        }
+        let succeeded = tx.meta.as_ref().is_some_and(|meta| meta.err.is_none());
+        for instruction in message.instructions() {
+            if let Some(program_id) = message
+                .static_account_keys()
+                .get(instruction.program_id_index as usize)
+            {
+                partial_block.record_program_call(program_id.to_string());
+            }
+            if observe {
+                if let Some(program_id) = account_keys.get(instruction.program_id_index as usize) {
+                    program_metrics::observe(&InstructionContext {
+                        program_id,
+                        accounts: instruction
+                            .accounts
+                            .iter()
+                            .filter_map(|index| account_keys.get(*index as usize))
+                            .map(String::as_str)
+                            .collect(),
+                        data: &instruction.data,
+                        succeeded,
+                    });
+                }
+            }
+        }
+        let tx_hash = transaction.message.hash();
+        for account in account_keys.iter() {
+            partial_block.observe_account(account.clone(), &tx_hash.to_string());
+        }
+        let record = TxRecord::new(instructions, tx.meta.clone())?
+            .with_accounts(account_keys)
+            .with_signatures(
+                transaction
+                    .signatures
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect(),
+            );
+        if let Some(meta) = tx.meta.as_ref() {
+            // The balances of the sender and the receiver, a transaction paying its fee without
+            // any instruction only has a sender
+            let mut balances = vec![];
+            for (index, account) in message.static_account_keys().iter().take(2).enumerate() {
+                let balance = meta.post_balances.get(index).ok_or_else(|| {
+                    AggError::MalformedTransaction(format!("no post balance for account {index}"))
+                })?;
+                balances.push((account.to_string(), *balance));
+            }
+            for (account, balance) in balances {
+                partial_block.insert_account(account, balance);
+            }
+            Self::collect_token_balances(record.accounts(), meta, partial_block);
+        }
+        partial_block.push_transaction(tx_hash, record);
+        Ok(())
+    }
+
+    /// This function returns the key of the partial record of a transaction failing to parse,
+    /// its message hash when it decodes and otherwise the hash of its encoding
+    fn fallback_tx_hash(tx: &EncodedTransactionWithStatusMeta) -> Hash {
+        match tx.transaction.decode() {
+            Some(transaction) => transaction.message.hash(),
+            None => hash(&serde_json::to_vec(&tx.transaction).unwrap_or_default()),
+        }
+    }
+
+    /// This function lists the account keys of a transaction, including the addresses
+    /// loaded from lookup tables when the metadata is available
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - A VersionedMessage that holds the transaction message
+    /// * `meta` - An Option<&UiTransactionStatusMeta> that holds the transaction metadata
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<String>` - The account keys in transaction order
+    fn account_keys(
+        message: &VersionedMessage,
+        meta: Option<&UiTransactionStatusMeta>,
+    ) -> Vec<String> {
+        let mut account_keys: Vec<String> = message
+            .static_account_keys()
+            .iter()
+            .map(|key| key.to_string())
+            .collect();
+        if let Some(OptionSerializer::Some(loaded_addresses)) =
+            meta.map(|meta| &meta.loaded_addresses)
+        {
+            account_keys.extend(loaded_addresses.writable.iter().cloned());
+            account_keys.extend(loaded_addresses.readonly.iter().cloned());
+        }
+        account_keys
+    }
+
+    /// This function records the post token balances of a transaction in the partial block. A
+    /// token account with a pre balance and no post balance was closed by the transaction and is
+    /// recorded with an amount of zero.
+    ///
+    /// # Arguments
+    ///
+    /// * `account_keys` - A slice of String that holds the account keys of the transaction
+    /// * `meta` - A UiTransactionStatusMeta that holds the transaction metadata
+    /// * `partial_block` - A Block that receives the token balances
+    fn collect_token_balances(
+        account_keys: &[String],
+        meta: &UiTransactionStatusMeta,
+        partial_block: &mut Block,
+    ) {
+        let OptionSerializer::Some(token_balances) = &meta.post_token_balances else {
+            return;
+        };
+        let closed_accounts: Vec<&UiTransactionTokenBalance> = match &meta.pre_token_balances {
+            OptionSerializer::Some(pre_token_balances) => pre_token_balances
+                .iter()
+                .filter(|pre| {
+                    !token_balances
+                        .iter()
+                        .any(|post| post.account_index == pre.account_index)
+                })
+                .collect(),
+            _ => vec![],
+        };
+        let balances = token_balances
+            .iter()
+            .map(|token_balance| (token_balance, false))
+            .chain(closed_accounts.into_iter().map(|closed| (closed, true)));
+        for (token_balance, closed) in balances {
+            let Some(token_account) = account_keys.get(token_balance.account_index as usize) else {
+                continue;
+            };
+            let amount = if closed {
+                0
+            } else {
+                let Ok(amount) = token_balance.ui_token_amount.amount.parse::<u64>() else {
+                    continue;
+                };
+                amount
+            };
+            let owner = match &token_balance.owner {
+                OptionSerializer::Some(owner) => Some(owner.clone()),
+                _ => None,
+            };
+            partial_block.insert_token_balance(
+                token_account.clone(),
+                TokenBalance {
+                    mint: token_balance.mint.clone(),
+                    owner,
+                    amount,
+                    decimals: token_balance.ui_token_amount.decimals,
+                },
+            );
+        }
+    }
+
     fn is_transfer_instruction(
         message: &VersionedMessage,
         instruction: &CompiledInstruction,
     ) -> Result<bool, AggError> {
         // Check if the program ID is the System Program
-        let program_id = message.static_account_keys()[instruction.program_id_index as usize];
+        let program_id = message
+            .static_account_keys()
+            .get(instruction.program_id_index as usize)
+            .ok_or_else(|| {
+                AggError::MalformedInstruction(format!(
+                    "program id index {} out of range",
+                    instruction.program_id_index
+                ))
+            })?;
         let system_program_id = Pubkey::from_str("11111111111111111111111111111111")?;
-        Ok(program_id == system_program_id && instruction.data[0] == 2) // 2 is the index for transfer instruction
+        Ok(*program_id == system_program_id && instruction.data.first() == Some(&2))
+        // 2 is the index for transfer instruction
     }
 
     fn decode_transfer_instruction(
         message: &VersionedMessage,
         instruction: &CompiledInstruction,
     ) -> Result<Instruction, AggError> {
-        let accounts = &instruction.accounts;
-        let default_key = Pubkey::from([1; 32]);
-        let from = message
-            .static_account_keys()
-            .get(accounts[0] as usize)
-            .unwrap_or(&default_key);
-        let to = message
-            .static_account_keys()
-            .get(accounts[1] as usize)
-            .unwrap_or(&default_key);
+        let account = |position: usize| -> Result<Pubkey, AggError> {
+            let index = *instruction.accounts.get(position).ok_or_else(|| {
+                AggError::MalformedInstruction(format!("transfer lacks account {position}"))
+            })?;
+            message
+                .static_account_keys()
+                .get(index as usize)
+                .copied()
+                .ok_or_else(|| {
+                    AggError::MalformedInstruction(format!("account index {index} out of range"))
+                })
+        };
+        let from = account(0)?;
+        let to = account(1)?;
 
-        let amount = u64::from_le_bytes(instruction.data[4..12].try_into()?);
+        let amount = instruction.data.get(4..12).ok_or_else(|| {
+            AggError::MalformedInstruction("transfer lacks an amount".to_string())
+        })?;
+        let amount = u64::from_le_bytes(amount.try_into()?);
         let amount = amount as f64 / 1_000_000_000.0;
 
         debug!(
@@ -91,6 +416,248 @@ impl Parser {
             from.to_string(),
             to.to_string()
         );
-        Ok(Instruction::transfer(*from, *to, amount))
+        Ok(Instruction::transfer(from, to, amount))
+    }
+
+    /// This function decodes an SPL Token `Transfer` or `TransferChecked` instruction, resolving
+    /// the mint, decimals and token account owners from the token balances of the metadata
+    ///
+    /// # Arguments
+    ///
+    /// * `account_keys` - A slice of String that holds the account keys of the transaction
+    /// * `meta` - An Option<&UiTransactionStatusMeta> that holds the transaction metadata
+    /// * `instruction` - A CompiledInstruction that holds the instruction to decode
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<Instruction>, AggError>` - The token transfer, None for any other
+    ///   instruction, or an error when the instruction is malformed
+    fn decode_token_transfer(
+        account_keys: &[String],
+        meta: Option<&UiTransactionStatusMeta>,
+        instruction: &CompiledInstruction,
+    ) -> Result<Option<Instruction>, AggError> {
+        let is_token_program = account_keys
+            .get(instruction.program_id_index as usize)
+            .is_some_and(|program_id| TOKEN_PROGRAM_IDS.contains(&program_id.as_str()));
+        if !is_token_program {
+            return Ok(None);
+        }
+        let account = |position: usize| -> Result<(u8, String), AggError> {
+            let index = *instruction.accounts.get(position).ok_or_else(|| {
+                AggError::MalformedInstruction(format!("token transfer lacks account {position}"))
+            })?;
+            let key = account_keys.get(index as usize).ok_or_else(|| {
+                AggError::MalformedInstruction(format!("account index {index} out of range"))
+            })?;
+            Ok((index, key.clone()))
+        };
+        let (source, mint, destination, decimals) = match instruction.data.first() {
+            Some(&TOKEN_TRANSFER) => (account(0)?, None, account(1)?, None),
+            Some(&TOKEN_TRANSFER_CHECKED) => {
+                let decimals = *instruction.data.get(9).ok_or_else(|| {
+                    AggError::MalformedInstruction("transfer_checked lacks decimals".to_string())
+                })?;
+                (
+                    account(0)?,
+                    Some(account(1)?.1),
+                    account(2)?,
+                    Some(decimals),
+                )
+            }
+            _ => return Ok(None),
+        };
+        let amount = instruction.data.get(1..9).ok_or_else(|| {
+            AggError::MalformedInstruction("token transfer lacks an amount".to_string())
+        })?;
+        let amount = u64::from_le_bytes(amount.try_into()?);
+
+        let token_balances: Vec<&UiTransactionTokenBalance> = meta
+            .into_iter()
+            .flat_map(|meta| [&meta.pre_token_balances, &meta.post_token_balances])
+            .filter_map(|balances| match balances {
+                OptionSerializer::Some(balances) => Some(balances),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        let balance_of = |index: u8| {
+            token_balances
+                .iter()
+                .find(|balance| balance.account_index == index)
+        };
+        let owner_of = |index: u8| match balance_of(index).map(|balance| &balance.owner) {
+            Some(OptionSerializer::Some(owner)) => Some(owner.clone()),
+            _ => None,
+        };
+        let known_balance = balance_of(source.0).or_else(|| balance_of(destination.0));
+        debug!(
+            "Token transfer: {} from {} to {}",
+            amount, source.1, destination.1
+        );
+        Ok(Some(Instruction::TokenTransfer {
+            source_owner: owner_of(source.0),
+            destination_owner: owner_of(destination.0),
+            mint: mint.or_else(|| known_balance.map(|balance| balance.mint.clone())),
+            decimals: decimals
+                .or_else(|| known_balance.map(|balance| balance.ui_token_amount.decimals)),
+            source: source.1,
+            destination: destination.1,
+            amount,
+        }))
+    }
+
+    /// This function decodes a System Program instruction assigning the owner of an account, or
+    /// an SPL Token instruction approving or revoking a delegate or setting an authority
+    ///
+    /// # Arguments
+    ///
+    /// * `account_keys` - A slice of String that holds the account keys of the transaction
+    /// * `instruction` - A CompiledInstruction that holds the instruction to decode
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<Instruction>, AggError>` - The authority change, None for any other
+    ///   instruction, or an error when the instruction is malformed
+    fn decode_authority_change(
+        account_keys: &[String],
+        instruction: &CompiledInstruction,
+    ) -> Result<Option<Instruction>, AggError> {
+        let program_id = account_keys
+            .get(instruction.program_id_index as usize)
+            .ok_or_else(|| {
+                AggError::MalformedInstruction(format!(
+                    "program id index {} out of range",
+                    instruction.program_id_index
+                ))
+            })?;
+        let data = &instruction.data;
+        let account = |position: usize| -> Result<String, AggError> {
+            let index = *instruction.accounts.get(position).ok_or_else(|| {
+                AggError::MalformedInstruction(format!("authority change lacks account {position}"))
+            })?;
+            let key = account_keys.get(index as usize).ok_or_else(|| {
+                AggError::MalformedInstruction(format!("account index {index} out of range"))
+            })?;
+            Ok(key.clone())
+        };
+        let bytes_at = |offset: usize, len: usize| {
+            offset
+                .checked_add(len)
+                .and_then(|end| data.get(offset..end))
+                .ok_or_else(|| {
+                    AggError::MalformedInstruction(format!(
+                        "authority change lacks {len} bytes at {offset}"
+                    ))
+                })
+        };
+        let pubkey_at = |offset: usize| -> Result<String, AggError> {
+            Ok(Pubkey::try_from(bytes_at(offset, 32)?)?.to_string())
+        };
+        let u64_at = |offset: usize| -> Result<u64, AggError> {
+            Ok(u64::from_le_bytes(bytes_at(offset, 8)?.try_into()?))
+        };
+
+        let (account, change) = if program_id == SYSTEM_PROGRAM_ID {
+            let Some(tag) = data.get(0..4) else {
+                return Ok(None);
+            };
+            // The seeds are strings prefixed by their u64 length, after the tag and the base
+            let seed_end = || -> Result<usize, AggError> {
+                let len = usize::try_from(u64_at(36)?).map_err(|_| {
+                    AggError::MalformedInstruction("seed length out of range".to_string())
+                })?;
+                44usize.checked_add(len).ok_or_else(|| {
+                    AggError::MalformedInstruction("seed length out of range".to_string())
+                })
+            };
+            let (account, owner_offset) = match u32::from_le_bytes(tag.try_into()?) {
+                SYSTEM_CREATE_ACCOUNT => (account(1)?, 20),
+                SYSTEM_ASSIGN => (account(0)?, 4),
+                SYSTEM_CREATE_ACCOUNT_WITH_SEED => (account(1)?, seed_end()?.saturating_add(16)),
+                SYSTEM_ASSIGN_WITH_SEED => (account(0)?, seed_end()?),
+                _ => return Ok(None),
+            };
+            let owner = pubkey_at(owner_offset)?;
+            (account, AuthorityChange::Owner { owner })
+        } else if TOKEN_PROGRAM_IDS.contains(&program_id.as_str()) {
+            match data.first() {
+                Some(&TOKEN_APPROVE) => (
+                    account(0)?,
+                    AuthorityChange::Delegate {
+                        delegate: Some(account(1)?),
+                        amount: Some(u64_at(1)?),
+                    },
+                ),
+                Some(&TOKEN_APPROVE_CHECKED) => (
+                    account(0)?,
+                    AuthorityChange::Delegate {
+                        delegate: Some(account(2)?),
+                        amount: Some(u64_at(1)?),
+                    },
+                ),
+                Some(&TOKEN_REVOKE) => (
+                    account(0)?,
+                    AuthorityChange::Delegate {
+                        delegate: None,
+                        amount: None,
+                    },
+                ),
+                Some(&TOKEN_SET_AUTHORITY) => {
+                    let authority_type = TokenAuthorityType::from(bytes_at(1, 1)?[0]);
+                    let new_authority = match bytes_at(2, 1)?[0] {
+                        0 => None,
+                        1 => Some(pubkey_at(3)?),
+                        tag => {
+                            return Err(AggError::MalformedInstruction(format!(
+                                "set_authority has an invalid option tag {tag}"
+                            )))
+                        }
+                    };
+                    (
+                        account(0)?,
+                        AuthorityChange::TokenAuthority {
+                            authority_type,
+                            new_authority,
+                        },
+                    )
+                }
+                _ => return Ok(None),
+            }
+        } else {
+            return Ok(None);
+        };
+        debug!("Authority change of {}: {:?}", account, change);
+        Ok(Some(Instruction::Authority { account, change }))
+    }
+}
+
+#[cfg(feature = "fuzzing")]
+impl Parser {
+
+    /// This function decodes a single compiled instruction the same way the parser does, so fuzz
+    /// targets can reach the instruction decoding without building a whole block
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - A VersionedMessage that holds the transaction message
+    /// * `instruction` - A CompiledInstruction that holds the instruction to decode
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<Instruction>, AggError>` - The decoded SOL or token transfer or authority
+    ///   change, None for any other instruction, or an error
+    pub fn decode_instruction(
+        message: &VersionedMessage,
+        instruction: &CompiledInstruction,
+    ) -> Result<Option<Instruction>, AggError> {
+        if !Self::is_transfer_instruction(message, instruction)? {
+            let account_keys = Self::account_keys(message, None);
+            if let Some(transfer) = Self::decode_token_transfer(&account_keys, None, instruction)? {
+                return Ok(Some(transfer));
+            }
+            return Self::decode_authority_change(&account_keys, instruction);
+        }
+        Self::decode_transfer_instruction(message, instruction).map(Some)
     }
 }
@@ -0,0 +1,385 @@
+//! Assembly of the aggregator pipeline: the subscriber, handler, fan out, db and servers wired
+//! together and stopped stage by stage on SIGINT or SIGTERM. The `solana-agg` binary runs it after
+//! parsing its flags and loading its config, embedders call [`run`] the same way.
+
+use crate::aggregation::RuleEngine;
+use crate::alerting::AlertMonitor;
+use crate::autotune::WriteLatency;
+use crate::backfill::Backfiller;
+use crate::builder::Builder;
+use crate::cli::Cli;
+use crate::config::Config;
+use crate::endpoints::RpcEndpoints;
+use crate::envelope::SlotTracker;
+use crate::error::AggError;
+use crate::export::Exporter;
+use crate::fanout::{FanOut, Overflow};
+use crate::grpc::GrpcServer;
+use crate::preflight::{self, Check};
+use crate::replication::Follower;
+use crate::shutdown::{self, ShutdownCoordinator, ShutdownStage};
+use crate::stats::StatsAggregator;
+use crate::util::{Channel, ProtocolMessage};
+use crate::warmup::Readiness;
+use crate::webhook::WebhookDispatcher;
+use crate::{chain, fetch_cache, program_metrics, recovery, server, spill};
+use log::{error, info};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// This function runs the pipeline until the process receives SIGINT or SIGTERM or the server
+/// fails, then stops it stage by stage
+///
+/// # Arguments
+///
+/// * `config` - A Config that holds the resolved config, see Config::resolve
+/// * `opt` - A Cli that holds the flags, whether the instance is a standby, read only or
+///   backfills among others
+///
+/// # Returns
+///
+/// * `Result<(), AggError>` - An error when the pipeline failed to start or the server failed,
+///   logged with the target of the failing stage
+pub async fn run(config: Config, opt: Cli) -> Result<(), AggError> {
+    let node = config.node.clone();
+    if let Err(e) = program_metrics::register_config(&config.program_metrics) {
+        error!(target:"config", "Error registering program metrics {}",e);
+        return Err(e);
+    }
+    let fetch_cache = match fetch_cache::from_config(&config.fetch_cache) {
+        Ok(fetch_cache) => fetch_cache,
+        Err(e) => {
+            error!(target:"fetch_cache", "Error opening the fetch cache {}",e);
+            return Err(e);
+        }
+    };
+    if let Err(e) = spill::install(&config.spill) {
+        error!(target:"spill", "Error opening the spill directory {}",e);
+        return Err(e);
+    }
+    if !opt.read_only {
+        match recovery::ensure_openable(&node.db_path, &config.recovery) {
+            Ok(Some(report)) => report.log(),
+            Ok(None) => {}
+            Err(e) => {
+                error!(target:"recovery", "Unable to recover db at {}: {}", node.db_path, e);
+                return Err(e);
+            }
+        }
+    }
+    let mut checks = vec![Check::SchemaVersion];
+    if !opt.read_only {
+        checks.push(Check::DiskSpace);
+    }
+    if opt.standby_of.is_none() && !opt.read_only {
+        checks.extend([Check::Rpc, Check::Commitment, Check::GenesisHash]);
+    }
+    if let Err(e) = preflight::run(&config, &checks).enforce(opt.force) {
+        error!(target:"startup", "Refusing to start {}",e);
+        return Err(e);
+    }
+    let mut coordinator = ShutdownCoordinator::new(config.shutdown.stage_timeout());
+    let slot_tracker = Arc::new(SlotTracker::default());
+    let handler_channel = Channel::<ProtocolMessage>::new();
+    let db_channel = Channel::<ProtocolMessage>::new();
+    let handler_channel_receiver_server = handler_channel.sender();
+    let write_latency = Arc::new(WriteLatency::default());
+    let refetch_channel = Channel::<u64>::new();
+    let refetch_sender = refetch_channel.sender();
+    // Read by the subscriber ingesting blocks, which is the one a standby starts on takeover
+    let mut refetch_receiver = Some(refetch_channel.receiver);
+    let subscriber_client = match opt.standby_of {
+        Some(_) => None,
+        None if opt.read_only => None,
+        None => match Builder::default()
+            .chain_url(node.chain_url.clone())
+            .router_sender(handler_channel.sender())
+            .build()
+        {
+            Ok(mut subscriber) => {
+                subscriber.set_fetch_settings(&node);
+                subscriber.archive_raw_blocks(config.archive.raw_blocks);
+                subscriber.set_parse_mode(config.parser.mode);
+                subscriber.set_ws_url(config.subscriber.ws_url(&node.chain_url));
+                subscriber.set_ws_timeout(config.subscriber.ws_timeout());
+                subscriber.set_refetch_config(config.subscriber.refetch.clone());
+                subscriber.set_slot_tracker(slot_tracker.clone());
+                if let Some(refetch_receiver) = refetch_receiver.take() {
+                    subscriber.set_refetch_receiver(refetch_receiver);
+                }
+                subscriber.set_autotune(config.autotune.clone(), write_latency.clone());
+                if let Some(fetch_cache) = &fetch_cache {
+                    subscriber.set_fetch_cache(fetch_cache.clone());
+                }
+                Some(subscriber)
+            }
+            Err(e) => {
+                error!(target:"subscriber", "Error from subscriber client {}",e);
+                return Err(e);
+            }
+        },
+    };
+    // Transactions are verified through the endpoints of the subscriber, sharing their cooldowns
+    let rpc_endpoints = match &subscriber_client {
+        Some(subscriber) => Some(subscriber.endpoints()),
+        None => RpcEndpoints::new(node.chain_urls()).ok().map(Arc::new),
+    };
+    let chain_id = match (&node.chain_id, &subscriber_client) {
+        (Some(chain_id), _) => Some(chain_id.clone()),
+        (None, Some(subscriber)) => Some(chain::chain_id_of(subscriber.genesis_hash())),
+        (None, None) => None,
+    };
+    if let Some(Err(e)) = chain_id.map(chain::install) {
+        error!(target:"startup", "Error setting the chain id {}",e);
+        return Err(e);
+    }
+    let backfill = subscriber_client.as_ref().map(|subscriber| {
+        let mut backfiller = Backfiller::initialize(
+            subscriber.endpoints(),
+            &config.backfill,
+            handler_channel.sender(),
+        );
+        backfiller.set_chunk_size(node.chunk_size);
+        backfiller.archive_raw_blocks(config.archive.raw_blocks);
+        backfiller.set_parse_mode(config.parser.mode);
+        backfiller.set_shutdown(coordinator.signal(ShutdownStage::Intake));
+        if let Some(fetch_cache) = &fetch_cache {
+            backfiller.set_fetch_cache(fetch_cache.clone());
+        }
+        let requested = opt
+            .backfill_from
+            .map(|from_slot| (from_slot, subscriber.first_slot().saturating_sub(1)));
+        (Arc::new(backfiller), requested)
+    });
+    let archive_raw_blocks = config.archive.raw_blocks;
+    let ws_url = config.subscriber.ws_url(&node.chain_url);
+    let ws_timeout = config.subscriber.ws_timeout();
+    let parse_mode = config.parser.mode;
+    let refetch = config.subscriber.refetch.clone();
+    let standby_node = node.clone();
+    let standby_slot_tracker = slot_tracker.clone();
+    let standby_fetch_cache = fetch_cache.clone();
+    let standby_refetch_receiver = refetch_receiver.take();
+    let standby_autotune = config.autotune.clone();
+    let standby_write_latency = write_latency.clone();
+    let standby_endpoints = rpc_endpoints.clone();
+    let standby = opt.standby_of.map(|primary_url| {
+        let follower = Follower::initialize(
+            primary_url,
+            db_channel.sender(),
+            Duration::from_secs(opt.failover_timeout_secs),
+        );
+        (follower, handler_channel.sender())
+    });
+    let mut handler = Builder::default()
+        .db_sender(db_channel.sender())
+        .router_receiver(handler_channel.receiver)
+        .build();
+    let fan_out_channel = Channel::<ProtocolMessage>::new();
+    let stats_channel = Channel::<ProtocolMessage>::new();
+    handler.set_fan_out(fan_out_channel.sender());
+    handler.set_stats_sender(stats_channel.sender());
+    handler.set_plugin_config(config.plugins.clone());
+    handler.set_commitment(node.commitment);
+    handler.set_drain_timeout(config.shutdown.chunk_drain_timeout());
+    let mut fan_out = FanOut::initialize(fan_out_channel.receiver);
+    let queue_capacity = config.fan_out.queue_capacity;
+    let db_block_receiver = fan_out.add_sink("db", queue_capacity, Overflow::Wait);
+    let stats_aggregator = StatsAggregator::initialize(
+        fan_out.add_sink("stats", queue_capacity, Overflow::Drop),
+        stats_channel.receiver,
+    );
+    let receipt_sender = db_channel.sender();
+    let db_builder = Builder::default()
+        .db_path(node.db_path.clone())
+        .db_receiver(db_channel.receiver);
+    let db_client = if opt.read_only {
+        let secondary_path = opt
+            .secondary_path
+            .clone()
+            .unwrap_or_else(|| format!("{}-secondary", node.db_path));
+        db_builder.build_read_only(secondary_path)
+    } else {
+        db_builder.build()
+    };
+    let mut db_client = match db_client {
+        Ok(db) => db,
+        Err(e) => {
+            error!(target:"db", "Error from db client {}",e);
+            return Err(e);
+        }
+    };
+    db_client.set_rule_engine(RuleEngine::new(config.aggregation_rules.clone()));
+    db_client.set_durability(config.durability.clone());
+    db_client.set_codec(config.storage.codec);
+    db_client.set_compaction(config.compaction.clone());
+    db_client.set_query_limits(config.query.clone());
+    db_client.set_parsing(config.parser.mode, config.plugins.clone());
+    db_client.set_slot_tracker(slot_tracker.clone());
+    db_client.set_write_latency(write_latency);
+    let readiness = Arc::new(Readiness::default());
+    db_client.set_warmup(config.warmup.clone(), readiness.clone());
+    db_client.set_block_receiver(db_block_receiver);
+    db_client.set_reorg_handling(
+        node.commitment,
+        config.reorg.clone(),
+        (!opt.read_only).then_some(refetch_sender),
+    );
+    if let Some(subscriber_client) = subscriber_client.as_ref() {
+        if let Err(e) = db_client.verify_genesis_hash(subscriber_client.genesis_hash()) {
+            error!(target:"db", "Refusing to ingest {}",e);
+            return Err(e);
+        }
+    }
+    let webhook_channel = Channel::<ProtocolMessage>::new();
+    db_client.set_subscriptions(config.subscriptions.clone(), webhook_channel.sender());
+    let webhook_dispatcher =
+        WebhookDispatcher::initialize(webhook_channel.receiver, receipt_sender);
+    coordinator.supervise(ShutdownStage::Intake, "webhook", webhook_dispatcher);
+    coordinator.supervise(ShutdownStage::Sinks, "db", db_client);
+    coordinator.supervise(ShutdownStage::FanOut, "fan_out", fan_out);
+    coordinator.supervise(ShutdownStage::Sinks, "stats", stats_aggregator);
+    coordinator.supervise(ShutdownStage::Handler, "handler", handler);
+    if let Some(subscriber_client) = subscriber_client {
+        coordinator.supervise(ShutdownStage::Intake, "subscriber", subscriber_client);
+    }
+    let alert_monitor = AlertMonitor::new(&config.alerting, slot_tracker.clone());
+    // A read only instance indexes nothing, it would always be reported stalled
+    if alert_monitor.has_notifiers() && !opt.read_only {
+        coordinator.supervise(ShutdownStage::Intake, "alerting", alert_monitor);
+    }
+    if let Some((backfiller, requested)) = backfill {
+        let backfill = tokio::spawn(async move {
+            match backfiller.start(requested).await {
+                // The jobs stop after their current batch once the intake stage is asked to stop
+                Ok(_) => backfiller.wait().await,
+                Err(e) => error!(target:"backfill", "Error starting backfill {}",e),
+            }
+        });
+        coordinator.track(ShutdownStage::Intake, "backfill", backfill);
+    }
+    if let Some((mut follower, router_sender)) = standby {
+        coordinator.spawn(ShutdownStage::Intake, "follower", async move {
+            let last_slot = follower.run().await;
+            match Builder::default()
+                .chain_url(standby_node.chain_url.clone())
+                .router_sender(router_sender.clone())
+                .build()
+            {
+                Ok(mut subscriber_client) => {
+                    let genesis_hash = subscriber_client.genesis_hash().to_string();
+                    // The chain id set on startup wins over the one of the genesis hash, which
+                    // the db checks against the cluster it holds data of right after
+                    if chain::installed().is_none() {
+                        if let Err(e) = chain::install(chain::chain_id_of(&genesis_hash)) {
+                            error!(target:"startup", "Error setting the chain id {}",e);
+                            return;
+                        }
+                    }
+                    match ProtocolMessage::ask(&router_sender, |reply| {
+                        ProtocolMessage::VerifyGenesisHash(genesis_hash, reply)
+                    })
+                    .await
+                    {
+                        Ok(_) => {}
+                        Err(e @ AggError::MpscChannelError(_)) => {
+                            error!(target:"subscriber", "Error from router sender {}",e);
+                            return;
+                        }
+                        Err(e) => {
+                            error!(target:"db", "Refusing to ingest {}",e);
+                            return;
+                        }
+                    }
+                    subscriber_client.set_fetch_settings(&standby_node);
+                    subscriber_client.archive_raw_blocks(archive_raw_blocks);
+                    subscriber_client.set_parse_mode(parse_mode);
+                    subscriber_client.set_ws_url(ws_url);
+                    subscriber_client.set_ws_timeout(ws_timeout);
+                    subscriber_client.set_refetch_config(refetch);
+                    subscriber_client.set_slot_tracker(standby_slot_tracker);
+                    if let Some(refetch_receiver) = standby_refetch_receiver {
+                        subscriber_client.set_refetch_receiver(refetch_receiver);
+                    }
+                    subscriber_client.set_autotune(standby_autotune, standby_write_latency);
+                    // Transactions are verified through the endpoints the subscriber fetches from
+                    if let Some(endpoints) = standby_endpoints {
+                        subscriber_client.set_endpoints(endpoints);
+                    }
+                    if let Some(fetch_cache) = standby_fetch_cache {
+                        subscriber_client.set_fetch_cache(fetch_cache);
+                    }
+                    if let Some(slot) = last_slot {
+                        subscriber_client.resume_from_slot(slot);
+                    }
+                    subscriber_client.run().await;
+                }
+                Err(e) => {
+                    error!(target:"subscriber", "Error from subscriber client {}",e);
+                }
+            }
+        });
+    }
+    if let Some(grpc_port) = opt.grpc_port {
+        let handler_sender = handler_channel_receiver_server.clone();
+        let query_config = config.query.clone();
+        let grpc_shutdown = coordinator.signal(ShutdownStage::Intake);
+        let grpc = tokio::spawn(async move {
+            if let Err(error) =
+                GrpcServer::run(handler_sender, query_config, grpc_port, grpc_shutdown).await
+            {
+                error!(target:"grpc", "Error from grpc server {}",error);
+            }
+        });
+        coordinator.track(ShutdownStage::Intake, "grpc", grpc);
+    }
+    let exporter =
+        match Exporter::initialize(&config.export, handler_channel_receiver_server.clone()) {
+            Ok(exporter) => exporter,
+            Err(e) => {
+                error!(target:"export", "Error loading exports {}",e);
+                coordinator.shutdown().await;
+                return Err(e);
+            }
+        };
+    exporter.resume_running();
+    let mut server = tokio::spawn(server::AggServer::run(
+        handler_channel_receiver_server,
+        config,
+        slot_tracker,
+        exporter,
+        readiness,
+        rpc_endpoints,
+        coordinator.signal(ShutdownStage::Intake),
+    ));
+    let outcome = tokio::select! {
+        signal = shutdown::signal() => {
+            info!(target:"shutdown", "Received {}, shutting down", signal);
+            Ok(())
+        }
+        result = &mut server => match result {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(error)) => {
+                error!(target:"server", "Error from server client {}",error);
+                Err(error)
+            }
+            Err(error) => {
+                error!(target:"server", "Server task failed {}",error);
+                Err(AggError::TaskFailed(error.to_string()))
+            }
+        },
+    };
+    if !server.is_finished() {
+        coordinator.track(
+            ShutdownStage::Intake,
+            "server",
+            tokio::spawn(async move {
+                if let Ok(Err(error)) = server.await {
+                    error!(target:"server", "Error from server client {}",error);
+                }
+            }),
+        );
+    }
+    coordinator.shutdown().await;
+    outcome
+}
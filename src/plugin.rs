@@ -0,0 +1,163 @@
+use crate::config::PluginConfig;
+use crate::error::AggError;
+use crate::metrics;
+use crate::util::Block;
+use log::{error, warn};
+use once_cell::sync::Lazy;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// What a plugin decides about a finalized block
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    /// Pass the block, possibly enriched, on to the next plugin and then to storage
+    Keep,
+    /// Keep the block from being stored, with the reason recorded in the dead-letter queue
+    Reject(String),
+}
+
+/// In-process extension called with every finalized block before it is stored. A plugin may
+/// validate the block, enrich it in place, or route it elsewhere, e.g. to a channel of its own.
+/// Plugins run on the handler task in the order they are registered, so they should not block.
+/// Each plugin is locked on its own, so one plugin only ever sees one block at a time.
+pub trait BlockPlugin: Send + 'static {
+    /// This function returns the name the plugin is registered, configured and reported under
+    fn name(&self) -> &str;
+
+    /// This function is called with every finalized block
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number
+    /// * `block` - A mutable reference to the Block, changes are kept in the stored block
+    ///
+    /// # Returns
+    ///
+    /// * `Verdict` - Whether the block is kept
+    fn on_block(&mut self, block_no: u64, block: &mut Block) -> Verdict;
+}
+
+/// A block a plugin rejected
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rejection {
+    pub plugin: String,
+    pub reason: String,
+}
+
+/// A registered plugin, locked on its own so the registry is not locked while plugins run
+struct Registered {
+    name: String,
+    plugin: Mutex<Box<dyn BlockPlugin>>,
+}
+
+static PLUGINS: Lazy<Mutex<Vec<Arc<Registered>>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+fn registry() -> MutexGuard<'static, Vec<Arc<Registered>>> {
+    match PLUGINS.lock() {
+        Ok(plugins) => plugins,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+/// This function registers a plugin run on every finalized block, embedders call it before the
+/// pipeline starts
+///
+/// # Arguments
+///
+/// * `plugin` - A BlockPlugin that holds the plugin
+///
+/// # Returns
+///
+/// * `Result<(), AggError>` - A Result that holds the result or an error if the name is taken
+pub fn register(plugin: impl BlockPlugin) -> Result<(), AggError> {
+    let mut plugins = registry();
+    if plugin.name().is_empty() {
+        return Err(AggError::ConfigError("Plugins need a name".to_string()));
+    }
+    if plugins
+        .iter()
+        .any(|registered| registered.name == plugin.name())
+    {
+        return Err(AggError::ConfigError(format!(
+            "Plugin {} is already registered",
+            plugin.name()
+        )));
+    }
+    plugins.push(Arc::new(Registered {
+        name: plugin.name().to_string(),
+        plugin: Mutex::new(Box::new(plugin)),
+    }));
+    Ok(())
+}
+
+/// This function returns the names of the registered plugins, in the order they run
+pub fn names() -> Vec<String> {
+    registry()
+        .iter()
+        .map(|registered| registered.name.clone())
+        .collect()
+}
+
+/// This function runs the registered plugins not disabled in the config on a block, stopping at
+/// the first one rejecting it. Each plugin works on a copy of the block that is only kept once
+/// the plugin returns, so a plugin panicking halfway through is logged and skipped without
+/// leaving the block half changed, and a broken plugin does not stop ingestion.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the PluginConfig that holds the disabled plugins
+/// * `block_no` - A u64 that holds the block number
+/// * `block` - A mutable reference to the Block
+///
+/// # Returns
+///
+/// * `Result<(), Rejection>` - Ok when the block is to be stored, or the rejection
+pub fn run(config: &PluginConfig, block_no: u64, block: &mut Block) -> Result<(), Rejection> {
+    // The registry is only locked to copy the list, a slow plugin does not hold back the
+    // registration or the plugins of other tasks
+    let plugins: Vec<Arc<Registered>> = registry().clone();
+    for registered in plugins
+        .iter()
+        .filter(|registered| !config.disabled.contains(&registered.name))
+    {
+        let name = registered.name.as_str();
+        let mut plugin = match registered.plugin.lock() {
+            Ok(plugin) => plugin,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let mut changed = block.clone();
+        let verdict =
+            panic::catch_unwind(AssertUnwindSafe(|| plugin.on_block(block_no, &mut changed)));
+        let outcome = match verdict {
+            Ok(Verdict::Keep) => {
+                *block = changed;
+                "kept"
+            }
+            Ok(Verdict::Reject(reason)) if config.enforce => {
+                metrics::PLUGIN_BLOCKS
+                    .with_label_values(&[name, "rejected"])
+                    .inc();
+                return Err(Rejection {
+                    plugin: name.to_string(),
+                    reason,
+                });
+            }
+            Ok(Verdict::Reject(reason)) => {
+                warn!(
+                    target: "plugins",
+                    "Plugin {} would reject block {}: {}", name, block_no, reason
+                );
+                *block = changed;
+                "rejected"
+            }
+            Err(_) => {
+                error!(target: "plugins", "Plugin {} panicked on block {}", name, block_no);
+                "panicked"
+            }
+        };
+        metrics::PLUGIN_BLOCKS
+            .with_label_values(&[name, outcome])
+            .inc();
+    }
+    Ok(())
+}
@@ -0,0 +1,343 @@
+use crate::config::Config;
+use crate::db_handler::{get_meta, open_db, GENESIS_HASH_KEY, SCHEMA_VERSION, SCHEMA_VERSION_KEY};
+use crate::error::AggError;
+use log::{error, info, warn};
+#[cfg(unix)]
+use nix::sys::statvfs::statvfs;
+use serde_json::from_slice;
+use solana_client::rpc_client::RpcClient;
+use std::path::Path;
+
+/// A sanity check run at startup, named in `startup.disabled` by its name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Check {
+    /// The db was not written by a newer build with a layout this build cannot read
+    SchemaVersion,
+    /// The db holds data of the cluster of the RPC node
+    GenesisHash,
+    /// The RPC node answers
+    Rpc,
+    /// The RPC node serves slots of the configured commitment
+    Commitment,
+    /// The volume of the db has `startup.min_free_disk_mb` free
+    DiskSpace,
+}
+
+impl Check {
+    pub const ALL: [Check; 5] = [
+        Check::SchemaVersion,
+        Check::DiskSpace,
+        Check::Rpc,
+        Check::Commitment,
+        Check::GenesisHash,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Check::SchemaVersion => "schema_version",
+            Check::GenesisHash => "genesis_hash",
+            Check::Rpc => "rpc",
+            Check::Commitment => "commitment",
+            Check::DiskSpace => "disk_space",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    /// Why the check was not run
+    Skipped(String),
+    /// What is wrong and how to fix it
+    Failed(String),
+}
+
+/// Outcomes of the startup checks, in the order they ran
+#[derive(Debug, Default)]
+pub struct Report {
+    pub results: Vec<(Check, Outcome)>,
+}
+
+impl Report {
+    /// This function returns the outcome of a check, None if it was not requested
+    pub fn outcome(&self, check: Check) -> Option<&Outcome> {
+        self.results
+            .iter()
+            .find(|(ran, _)| *ran == check)
+            .map(|(_, outcome)| outcome)
+    }
+
+    /// This function returns the number of failed checks
+    pub fn failures(&self) -> usize {
+        self.results
+            .iter()
+            .filter(|(_, outcome)| matches!(outcome, Outcome::Failed(_)))
+            .count()
+    }
+
+    /// This function logs the outcome of every check and fails if one of them failed, unless
+    /// forced, in which case the failures are logged as warnings
+    ///
+    /// # Arguments
+    ///
+    /// * `force` - A bool that tells whether to start despite failed checks
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error if a check failed
+    pub fn enforce(&self, force: bool) -> Result<(), AggError> {
+        for (check, outcome) in &self.results {
+            match outcome {
+                Outcome::Passed => info!(target: "startup", "Check {} passed", check.name()),
+                Outcome::Skipped(reason) => {
+                    info!(target: "startup", "Check {} skipped, {}", check.name(), reason)
+                }
+                Outcome::Failed(message) if force => warn!(
+                    target: "startup",
+                    "Check {} failed, starting anyway because of --force: {}", check.name(), message
+                ),
+                Outcome::Failed(message) => {
+                    error!(target: "startup", "Check {} failed: {}", check.name(), message)
+                }
+            }
+        }
+        match self.failures() {
+            0 => Ok(()),
+            _ if force => Ok(()),
+            failures => Err(AggError::StartupCheckFailed(failures)),
+        }
+    }
+}
+
+/// Meta keys of an existing db the checks compare against
+struct StoredMeta {
+    schema_version: Option<u64>,
+    genesis_hash: Option<String>,
+}
+
+/// This function runs the requested startup checks not disabled in `startup.disabled`
+///
+/// # Arguments
+///
+/// * `config` - A Config that holds the node and startup settings
+/// * `checks` - A slice of the checks that apply to how the aggregator is started
+///
+/// # Returns
+///
+/// * `Report` - The outcome of every requested check
+pub fn run(config: &Config, checks: &[Check]) -> Report {
+    let node = &config.node;
+    let startup = &config.startup;
+    for name in &startup.disabled {
+        if !Check::ALL.iter().any(|check| check.name() == name) {
+            warn!(target: "startup", "Unknown check {} in startup.disabled", name);
+        }
+    }
+    let meta = read_meta(&node.db_path);
+    let chain_urls = node.chain_urls();
+    // The checks against the RPC node run against the first of the nodes that answers
+    let mut chain_url = chain_urls.first().cloned().unwrap_or_default();
+    let mut client = RpcClient::new_with_timeout(chain_url.clone(), startup.rpc_timeout());
+    // Checks against the RPC node are skipped once the rpc check found it unreachable
+    let mut rpc_reachable = true;
+    let mut report = Report::default();
+    for check in Check::ALL {
+        if !checks.contains(&check) {
+            continue;
+        }
+        if startup.disabled.iter().any(|name| name == check.name()) {
+            report.results.push((
+                check,
+                Outcome::Skipped("disabled in startup.disabled".to_string()),
+            ));
+            continue;
+        }
+        let outcome = match check {
+            Check::SchemaVersion => match &meta {
+                Ok(Some(StoredMeta {
+                    schema_version: Some(version),
+                    ..
+                })) if *version > SCHEMA_VERSION => Outcome::Failed(format!(
+                    "the db at {} has schema version {} but this build reads up to {}, upgrade the aggregator or point node.db_path, --db-url or AGG_DB_PATH at another db",
+                    node.db_path, version, SCHEMA_VERSION
+                )),
+                Ok(Some(_)) => Outcome::Passed,
+                Ok(None) => Outcome::Skipped(format!("no db at {} yet", node.db_path)),
+                Err(err) => Outcome::Failed(format!(
+                    "unable to read the db at {}: {}, check node.db_path and that no other process holds the db",
+                    node.db_path, err
+                )),
+            },
+            Check::DiskSpace => disk_space(&node.db_path, startup.min_free_disk_mb),
+            Check::Rpc => {
+                let mut failures = vec![];
+                let mut reachable = None;
+                for url in &chain_urls {
+                    let candidate = RpcClient::new_with_timeout(url.clone(), startup.rpc_timeout());
+                    match candidate.get_version() {
+                        Ok(_) => {
+                            reachable = Some((url.clone(), candidate));
+                            break;
+                        }
+                        Err(err) => {
+                            failures.push(format!("the RPC node {} is unreachable: {}", url, err))
+                        }
+                    }
+                }
+                match reachable {
+                    Some((url, candidate)) => {
+                        for failure in &failures {
+                            warn!(target: "startup", "Failing over, {}", failure);
+                        }
+                        chain_url = url;
+                        client = candidate;
+                        Outcome::Passed
+                    }
+                    None => {
+                        rpc_reachable = false;
+                        Outcome::Failed(format!(
+                            "{}, check node.chain_url, --chain-url or AGG_CHAIN_URL",
+                            failures.join("; ")
+                        ))
+                    }
+                }
+            }
+            Check::Commitment if !rpc_reachable => {
+                Outcome::Skipped("the RPC node is unreachable".to_string())
+            }
+            Check::Commitment => match client.get_slot_with_commitment(node.commitment.config()) {
+                Ok(_) => Outcome::Passed,
+                Err(err) => Outcome::Failed(format!(
+                    "the RPC node {} does not serve {} slots: {}, set node.commitment or AGG_COMMITMENT to a commitment it serves",
+                    chain_url, node.commitment, err
+                )),
+            },
+            Check::GenesisHash => match &meta {
+                _ if !rpc_reachable => Outcome::Skipped("the RPC node is unreachable".to_string()),
+                Ok(Some(StoredMeta {
+                    genesis_hash: Some(stored),
+                    ..
+                })) => match client.get_genesis_hash() {
+                    Ok(genesis_hash) if genesis_hash.to_string() == *stored => Outcome::Passed,
+                    Ok(genesis_hash) => Outcome::Failed(format!(
+                        "the db at {} holds data of the cluster with genesis hash {} but the RPC node {} has {}, point node.db_path at a db of this cluster or node.chain_url at a node of the other",
+                        node.db_path, stored, chain_url, genesis_hash
+                    )),
+                    Err(err) => Outcome::Failed(format!(
+                        "unable to fetch the genesis hash of the RPC node {}: {}",
+                        chain_url, err
+                    )),
+                },
+                Ok(_) => Outcome::Skipped("the db has no genesis hash recorded yet".to_string()),
+                Err(_) => Outcome::Skipped("the db is unreadable".to_string()),
+            },
+        };
+        report.results.push((check, outcome));
+    }
+    report
+}
+
+/// This function reads the meta keys of the db the checks compare against
+///
+/// # Arguments
+///
+/// * `path` - A string slice that holds the path to the database
+///
+/// # Returns
+///
+/// * `Result<Option<StoredMeta>, AggError>` - None if there is no db at the path yet, the meta
+///   keys otherwise, or an error if the db could not be read
+fn read_meta(path: &str) -> Result<Option<StoredMeta>, AggError> {
+    // Rocksdb writes CURRENT when it creates a db
+    if !Path::new(path).join("CURRENT").exists() {
+        return Ok(None);
+    }
+    let db = open_db(path, true)?;
+    let schema_version = match get_meta(&db, SCHEMA_VERSION_KEY)? {
+        Some(version) => Some(from_slice::<u64>(&version)?),
+        None => None,
+    };
+    let genesis_hash = match get_meta(&db, GENESIS_HASH_KEY)? {
+        Some(genesis_hash) => Some(from_slice::<String>(&genesis_hash)?),
+        None => None,
+    };
+    Ok(Some(StoredMeta {
+        schema_version,
+        genesis_hash,
+    }))
+}
+
+/// This function checks the free space of the volume the db is or will be created on
+///
+/// # Arguments
+///
+/// * `path` - A string slice that holds the path to the database
+/// * `min_free_mb` - A u64 that holds the free space required in megabytes
+///
+/// # Returns
+///
+/// * `Outcome` - Whether the volume has enough free space
+fn disk_space(path: &str, min_free_mb: u64) -> Outcome {
+    // Relative paths end in an empty ancestor, the working directory
+    let existing = Path::new(path)
+        .ancestors()
+        .map(|ancestor| {
+            if ancestor.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                ancestor
+            }
+        })
+        .find(|ancestor| ancestor.exists());
+    let Some(existing) = existing else {
+        return Outcome::Skipped(format!("no existing directory holds {}", path));
+    };
+    let free_mb = match free_mb(existing) {
+        Some(Ok(free_mb)) => free_mb,
+        Some(Err(err)) => {
+            return Outcome::Failed(format!(
+                "unable to read the free space of {}: {}",
+                existing.display(),
+                err
+            ))
+        }
+        None => return Outcome::Skipped("free space is only read on unix".to_string()),
+    };
+    if free_mb >= min_free_mb {
+        Outcome::Passed
+    } else {
+        Outcome::Failed(format!(
+            "{} MB free on the volume of {}, less than the {} MB of startup.min_free_disk_mb, free space or point node.db_path at a larger volume",
+            free_mb, path, min_free_mb
+        ))
+    }
+}
+
+/// This function reads the free space of the volume holding a directory
+///
+/// # Arguments
+///
+/// * `directory` - A Path that holds the directory
+///
+/// # Returns
+///
+/// * `Option<Result<u64, String>>` - The free space in megabytes or an error, None where it is
+///   not read
+#[cfg(unix)]
+fn free_mb(directory: &Path) -> Option<Result<u64, String>> {
+    Some(
+        statvfs(directory)
+            .map(|stats| {
+                stats
+                    .blocks_available()
+                    .saturating_mul(stats.fragment_size())
+                    / (1024 * 1024)
+            })
+            .map_err(|err| err.to_string()),
+    )
+}
+
+#[cfg(not(unix))]
+fn free_mb(_directory: &Path) -> Option<Result<u64, String>> {
+    None
+}
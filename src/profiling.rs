@@ -0,0 +1,182 @@
+use crate::error::AggError;
+use crate::server::{error_response, is_admin, not_admin, AdminKey};
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+use log::info;
+use pprof::protos::Message;
+use pprof::ProfilerGuardBuilder;
+use serde::Deserialize;
+use std::ffi::CString;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+use tikv_jemalloc_ctl::raw;
+
+const DEFAULT_PROFILE_SECS: u64 = 30;
+const MAX_PROFILE_SECS: u64 = 300;
+const DEFAULT_FREQUENCY: i32 = 99;
+const MAX_FREQUENCY: i32 = 1_000;
+
+/// Set while a CPU profile is captured, the profiler samples the whole process
+static CAPTURING: AtomicBool = AtomicBool::new(false);
+/// Numbers the heap dumps written to the temporary directory
+static HEAP_DUMPS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProfileFormat {
+    /// Protobuf profile read by `pprof`
+    #[default]
+    Pprof,
+    /// SVG flamegraph
+    Flamegraph,
+}
+
+#[derive(Deserialize)]
+pub struct ProfileParams {
+    pub(crate) seconds: Option<u64>,
+    pub(crate) frequency: Option<i32>,
+    #[serde(default)]
+    pub(crate) format: ProfileFormat,
+}
+
+/// Clears CAPTURING once the capture holding it ends, whichever way it ends
+struct Capture;
+
+impl Capture {
+    fn start() -> Result<Self, AggError> {
+        CAPTURING
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .map(|_| Capture)
+            .map_err(|_| AggError::ProfileInProgress)
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        CAPTURING.store(false, Ordering::Release);
+    }
+}
+
+/// This function registers the profiling endpoints
+///
+/// # Arguments
+///
+/// * `config` - A ServiceConfig the endpoints are added to
+pub fn configure(config: &mut web::ServiceConfig) {
+    config.service(cpu_profile).service(heap_profile);
+}
+
+#[get("/admin/pprof/profile")]
+async fn cpu_profile(
+    request: HttpRequest,
+    query: web::Query<ProfileParams>,
+    admin_key: web::Data<AdminKey>,
+) -> impl Responder {
+    if !is_admin(&request, &admin_key) {
+        return not_admin();
+    }
+    let seconds = query.seconds.unwrap_or(DEFAULT_PROFILE_SECS);
+    let frequency = query.frequency.unwrap_or(DEFAULT_FREQUENCY);
+    if seconds == 0 || seconds > MAX_PROFILE_SECS || frequency <= 0 || frequency > MAX_FREQUENCY {
+        return error_response(&AggError::BadRequest(format!(
+            "seconds must be between 1 and {} and frequency between 1 and {}",
+            MAX_PROFILE_SECS, MAX_FREQUENCY
+        )));
+    }
+    match capture_cpu_profile(Duration::from_secs(seconds), frequency, query.format).await {
+        Ok(body) if query.format == ProfileFormat::Flamegraph => {
+            HttpResponse::Ok().content_type("image/svg+xml").body(body)
+        }
+        Ok(body) => HttpResponse::Ok()
+            .content_type("application/octet-stream")
+            .body(body),
+        Err(err) => error_response(&err),
+    }
+}
+
+#[get("/admin/pprof/heap")]
+async fn heap_profile(request: HttpRequest, admin_key: web::Data<AdminKey>) -> impl Responder {
+    if !is_admin(&request, &admin_key) {
+        return not_admin();
+    }
+    let dump = web::block(dump_heap_profile)
+        .await
+        .map_err(|err| AggError::ProfilingError(err.to_string()))
+        .and_then(|dump| dump);
+    match dump {
+        Ok(body) => HttpResponse::Ok()
+            .content_type("application/octet-stream")
+            .body(body),
+        Err(err) => error_response(&err),
+    }
+}
+
+/// This function samples the stacks of every thread of the process for a while
+///
+/// # Arguments
+///
+/// * `duration` - A Duration that holds how long to sample for
+/// * `frequency` - An i32 that holds the samples taken per second
+/// * `format` - A ProfileFormat that holds the format of the profile
+///
+/// # Returns
+///
+/// * `Result<Vec<u8>, AggError>` - A Result that holds the encoded profile or an error if
+///   another profile is being captured, no sample was taken or the profiler failed
+pub async fn capture_cpu_profile(
+    duration: Duration,
+    frequency: i32,
+    format: ProfileFormat,
+) -> Result<Vec<u8>, AggError> {
+    let _capture = Capture::start()?;
+    info!(target: "server", "Capturing a CPU profile for {:?} at {} Hz", duration, frequency);
+    let guard = ProfilerGuardBuilder::default()
+        .frequency(frequency)
+        .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+        .build()
+        .map_err(|err| AggError::ProfilingError(err.to_string()))?;
+    tokio::time::sleep(duration).await;
+    let report = guard
+        .report()
+        .build()
+        .map_err(|err| AggError::ProfilingError(err.to_string()))?;
+    if report.data.is_empty() {
+        return Err(AggError::ProfilingError(
+            "no samples were taken, the process was idle".to_string(),
+        ));
+    }
+    let mut body = Vec::new();
+    match format {
+        ProfileFormat::Flamegraph => report
+            .flamegraph(&mut body)
+            .map_err(|err| AggError::ProfilingError(err.to_string()))?,
+        ProfileFormat::Pprof => report
+            .pprof()
+            .map_err(|err| AggError::ProfilingError(err.to_string()))?
+            .write_to_vec(&mut body)
+            .map_err(|err| AggError::ProfilingError(err.to_string()))?,
+    }
+    Ok(body)
+}
+
+/// This function has jemalloc dump the sampled live allocations to a temporary file and reads
+/// it back. Only the binary allocates with jemalloc, elsewhere the dump fails.
+///
+/// # Returns
+///
+/// * `Result<Vec<u8>, AggError>` - A Result that holds the heap profile, read by `jeprof`, or an
+///   error if it could not be dumped
+pub fn dump_heap_profile() -> Result<Vec<u8>, AggError> {
+    let path = std::env::temp_dir().join(format!(
+        "solana-agg-{}-{}.heap",
+        std::process::id(),
+        HEAP_DUMPS.fetch_add(1, Ordering::Relaxed)
+    ));
+    let c_path = CString::new(path.to_string_lossy().into_owned())
+        .map_err(|err| AggError::ProfilingError(err.to_string()))?;
+    // SAFETY: prof.dump takes a pointer to a null terminated path, which outlives the call
+    unsafe { raw::write(b"prof.dump\0", c_path.as_ptr()) }
+        .map_err(|err| AggError::ProfilingError(format!("prof.dump failed: {}", err)))?;
+    let dump = std::fs::read(&path);
+    let _ = std::fs::remove_file(&path);
+    Ok(dump?)
+}
@@ -0,0 +1,151 @@
+use crate::config::{ProgramMetricConfig, ProgramMetricKind};
+use crate::error::AggError;
+use crate::metrics;
+use once_cell::sync::Lazy;
+use solana_program::pubkey::Pubkey;
+use solana_program::system_program;
+use std::str::FromStr;
+use std::sync::RwLock;
+
+/// Index of the transfer instruction of the system program
+const SYSTEM_TRANSFER: u32 = 2;
+
+/// Instruction handed to the hooks registered for its program
+pub struct InstructionContext<'a> {
+    pub program_id: &'a str,
+    /// Accounts of the instruction in instruction order
+    pub accounts: Vec<&'a str>,
+    pub data: &'a [u8],
+    /// Whether the transaction of the instruction succeeded
+    pub succeeded: bool,
+}
+
+/// Callback evaluated on every instruction of its program, returning the amount to add to its
+/// metric
+pub type ProgramHook = Box<dyn Fn(&InstructionContext) -> f64 + Send + Sync>;
+
+struct RegisteredHook {
+    name: String,
+    program_id: String,
+    hook: ProgramHook,
+}
+
+static HOOKS: Lazy<RwLock<Vec<RegisteredHook>>> = Lazy::new(|| RwLock::new(Vec::new()));
+
+/// This function registers a hook evaluated on every instruction invoking a program while blocks
+/// are parsed, adding its result to `agg_program_metric_total{metric="<name>"}`
+///
+/// # Arguments
+///
+/// * `name` - A string slice that holds the name of the metric
+/// * `program_id` - A string slice that holds the program the hook is evaluated on
+/// * `hook` - A callback that returns the amount to add for an instruction
+///
+/// # Returns
+///
+/// * `Result<(), AggError>` - A Result that holds the result or an error if the name is taken or
+///   the program id is invalid
+pub fn register(
+    name: &str,
+    program_id: &str,
+    hook: impl Fn(&InstructionContext) -> f64 + Send + Sync + 'static,
+) -> Result<(), AggError> {
+    Pubkey::from_str(program_id)?;
+    let mut hooks = match HOOKS.write() {
+        Ok(hooks) => hooks,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    if name.is_empty() {
+        return Err(AggError::ConfigError(
+            "Program metrics need a name".to_string(),
+        ));
+    }
+    if hooks.iter().any(|registered| registered.name == name) {
+        return Err(AggError::ConfigError(format!(
+            "Program metric {} is already registered",
+            name
+        )));
+    }
+    hooks.push(RegisteredHook {
+        name: name.to_string(),
+        program_id: program_id.to_string(),
+        hook: Box::new(hook),
+    });
+    Ok(())
+}
+
+/// This function registers the hooks of the configured program metrics, which only count
+/// instructions of successful transactions
+///
+/// # Arguments
+///
+/// * `configs` - A slice of ProgramMetricConfig that holds the configured metrics
+///
+/// # Returns
+///
+/// * `Result<(), AggError>` - A Result that holds the result or an error
+pub fn register_config(configs: &[ProgramMetricConfig]) -> Result<(), AggError> {
+    for config in configs {
+        match &config.kind {
+            ProgramMetricKind::CountInstructions {
+                program,
+                data_prefix,
+            } => {
+                let data_prefix = data_prefix.clone();
+                register(&config.name, program, move |instruction| {
+                    f64::from(instruction.succeeded && instruction.data.starts_with(&data_prefix))
+                })?
+            }
+            ProgramMetricKind::SumTransfersTo { address } => {
+                Pubkey::from_str(address)?;
+                let address = address.clone();
+                register(
+                    &config.name,
+                    &system_program::id().to_string(),
+                    move |instruction| {
+                        let data = instruction.data;
+                        let is_transfer = data.len() >= 12
+                            && data[..4] == SYSTEM_TRANSFER.to_le_bytes()
+                            && instruction.accounts.get(1) == Some(&address.as_str());
+                        if !instruction.succeeded || !is_transfer {
+                            return 0.0;
+                        }
+                        let mut lamports = [0; 8];
+                        lamports.copy_from_slice(&data[4..12]);
+                        u64::from_le_bytes(lamports) as f64
+                    },
+                )?
+            }
+        }
+    }
+    Ok(())
+}
+
+/// This function tells whether any hook is registered, so the parser skips building the
+/// instruction contexts otherwise
+pub fn is_empty() -> bool {
+    HOOKS.read().map_or(true, |hooks| hooks.is_empty())
+}
+
+/// This function evaluates the hooks registered for the program of an instruction
+///
+/// # Arguments
+///
+/// * `instruction` - An InstructionContext that holds the instruction
+pub fn observe(instruction: &InstructionContext) {
+    let Ok(hooks) = HOOKS.read() else {
+        return;
+    };
+    for registered in hooks
+        .iter()
+        .filter(|registered| registered.program_id == instruction.program_id)
+    {
+        let value = (registered.hook)(instruction);
+        // Counters only go up
+        if value > 0.0 {
+            metrics::PROGRAM_METRICS
+                .with_label_values(&[&registered.name, &registered.program_id])
+                .inc_by(value);
+        }
+    }
+}
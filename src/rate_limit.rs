@@ -0,0 +1,394 @@
+use crate::config::{PublicConfig, RateLimitConfig};
+use crate::error::AggError;
+use crate::server::{error_response, AdminKey};
+use crate::tenant::{is_admin_request, TenantRegistry, API_KEY_HEADER};
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue, RETRY_AFTER};
+use actix_web::{web, Error};
+use futures_util::future::{ready, LocalBoxFuture, Ready};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const BUDGET_WINDOW: Duration = Duration::from_secs(60);
+/// Number of clients tracked before the ones whose window has passed are forgotten
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+/// Requests to the admin endpoints without the admin api key allowed per address and minute,
+/// whether or not the addresses are limited otherwise
+const FAILED_ADMIN_ATTEMPTS_PER_MINUTE: u64 = 10;
+/// Built in weights of the endpoints scanning many blocks, slots or index entries
+const ENDPOINT_COSTS: [(&str, u64); 7] = [
+    ("block_range", 10),
+    ("token_holders", 10),
+    ("balance_history", 5),
+    ("indexed_slots", 5),
+    ("account_txs", 5),
+    ("active_accounts", 5),
+    ("latest_blocks", 2),
+];
+
+pub const LIMIT_HEADER: &str = "x-ratelimit-limit";
+pub const REMAINING_HEADER: &str = "x-ratelimit-remaining";
+pub const RESET_HEADER: &str = "x-ratelimit-reset";
+pub const COST_HEADER: &str = "x-ratelimit-cost";
+
+struct ClientBudget {
+    window_start: Instant,
+    spent: u64,
+}
+
+/// Outcome of charging a request to the budget of its client
+pub struct Charge {
+    admitted: bool,
+    cost: u64,
+    limit: u64,
+    remaining: u64,
+    reset: Duration,
+}
+
+impl Charge {
+    pub fn is_admitted(&self) -> bool {
+        self.admitted
+    }
+
+    /// This function answers a request over budget with a 429 telling when to retry
+    fn reject(&self, req: ServiceRequest) -> ServiceResponse {
+        let mut response = error_response(&AggError::RateLimited(format!(
+            "the request costs {} and {} of {} is left, retry in {}s",
+            self.cost,
+            self.remaining,
+            self.limit,
+            self.reset.as_secs()
+        )));
+        self.write_headers(response.headers_mut());
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(self.reset.as_secs()));
+        req.into_response(response)
+    }
+
+    fn write_headers(&self, headers: &mut HeaderMap) {
+        for (name, value) in [
+            (LIMIT_HEADER, self.limit),
+            (REMAINING_HEADER, self.remaining),
+            (RESET_HEADER, self.reset.as_secs()),
+            (COST_HEADER, self.cost),
+        ] {
+            headers.insert(HeaderName::from_static(name), HeaderValue::from(value));
+        }
+    }
+}
+
+/// Per minute budgets of the clients of a limiter
+struct Budgets {
+    budget_per_minute: u64,
+    budgets: Mutex<HashMap<String, ClientBudget>>,
+}
+
+impl Budgets {
+    fn new(budget_per_minute: u64) -> Self {
+        Budgets {
+            budget_per_minute,
+            budgets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// This function charges a cost to the budget of a client, starting a new window once the
+    /// previous one has passed
+    fn charge(&self, client: &str, cost: u64) -> Charge {
+        let mut budgets = match self.budgets.lock() {
+            Ok(budgets) => budgets,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if budgets.len() >= MAX_TRACKED_CLIENTS && !budgets.contains_key(client) {
+            budgets.retain(|_, budget| budget.window_start.elapsed() < BUDGET_WINDOW);
+        }
+        let budget = budgets
+            .entry(client.to_string())
+            .or_insert_with(|| ClientBudget {
+                window_start: Instant::now(),
+                spent: 0,
+            });
+        if budget.window_start.elapsed() >= BUDGET_WINDOW {
+            budget.window_start = Instant::now();
+            budget.spent = 0;
+        }
+        let admitted = budget.spent + cost <= self.budget_per_minute;
+        if admitted {
+            budget.spent += cost;
+        }
+        Charge {
+            admitted,
+            cost,
+            limit: self.budget_per_minute,
+            remaining: self.budget_per_minute.saturating_sub(budget.spent),
+            reset: BUDGET_WINDOW.saturating_sub(budget.window_start.elapsed()),
+        }
+    }
+}
+
+pub struct PublicLimiter {
+    enabled: bool,
+    default_cost: u64,
+    costs: BTreeMap<String, u64>,
+    budgets: Budgets,
+}
+
+impl PublicLimiter {
+    /// This function creates the limiter of the public profile
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - A PublicConfig that holds the budget and the endpoint costs
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The limiter
+    pub fn new(config: &PublicConfig) -> Self {
+        let mut costs: BTreeMap<String, u64> = ENDPOINT_COSTS
+            .iter()
+            .map(|(endpoint, cost)| (endpoint.to_string(), *cost))
+            .collect();
+        costs.extend(config.costs.clone());
+        PublicLimiter {
+            enabled: config.enabled,
+            default_cost: config.default_cost,
+            costs,
+            budgets: Budgets::new(config.budget_per_minute),
+        }
+    }
+
+    /// This function returns the cost of a request
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - A string slice that holds the path of the request
+    ///
+    /// # Returns
+    ///
+    /// * `u64` - The cost of the endpoint, keyed by the first segment of the path
+    pub fn cost(&self, path: &str) -> u64 {
+        let endpoint = path.trim_start_matches('/').split('/').next().unwrap_or("");
+        self.costs
+            .get(endpoint)
+            .copied()
+            .unwrap_or(self.default_cost)
+    }
+
+    /// This function charges the cost of a request to the budget of its client
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - A string slice that holds the api key or address of the client
+    /// * `cost` - A u64 that holds the cost of the request
+    ///
+    /// # Returns
+    ///
+    /// * `Charge` - Whether the request is admitted and what is left of the budget
+    pub fn charge(&self, client: &str, cost: u64) -> Charge {
+        self.budgets.charge(client, cost)
+    }
+}
+
+/// Middleware charging every request of the public profile to the per minute budget of its
+/// client, identified by its api key when the key belongs to a tenant and otherwise by its
+/// address, so made up keys do not get a budget each. The budget is reported in the
+/// `X-RateLimit-*` headers. Admin routes are not charged.
+pub struct PublicRateLimit(pub Arc<PublicLimiter>);
+
+impl<S, B> Transform<S, ServiceRequest> for PublicRateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = PublicRateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(PublicRateLimitMiddleware {
+            service,
+            limiter: self.0.clone(),
+        }))
+    }
+}
+
+pub struct PublicRateLimitMiddleware<S> {
+    service: S,
+    limiter: Arc<PublicLimiter>,
+}
+
+impl<S, B> Service<ServiceRequest> for PublicRateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        if !self.limiter.enabled || is_admin_request(&req) {
+            let fut = self.service.call(req);
+            return Box::pin(async move { fut.await.map(ServiceResponse::map_into_left_body) });
+        }
+        let client = match req
+            .headers()
+            .get(API_KEY_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|api_key| {
+                req.app_data::<web::Data<TenantRegistry>>()
+                    .is_some_and(|tenants| tenants.knows(api_key))
+            }) {
+            Some(api_key) => format!("key:{}", api_key),
+            None => format!(
+                "addr:{}",
+                req.peer_addr()
+                    .map(|addr| addr.ip().to_string())
+                    .unwrap_or_default()
+            ),
+        };
+        let charge = self.limiter.charge(&client, self.limiter.cost(req.path()));
+        if !charge.admitted {
+            return Box::pin(ready(Ok(charge.reject(req).map_into_right_body())));
+        }
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let mut response = fut.await?;
+            charge.write_headers(response.headers_mut());
+            Ok(response.map_into_left_body())
+        })
+    }
+}
+
+/// Limit on the requests of every client address, whatever api key it presents
+pub struct IpLimiter {
+    enabled: bool,
+    exempt: HashSet<IpAddr>,
+    budgets: Budgets,
+    failed_admin_attempts: Budgets,
+}
+
+impl IpLimiter {
+    /// This function creates the limiter of the client addresses
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - A RateLimitConfig that holds the requests allowed per minute and the exempt
+    ///   addresses
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The limiter
+    pub fn new(config: &RateLimitConfig) -> Self {
+        IpLimiter {
+            enabled: config.enabled,
+            exempt: config.exempt_ips.iter().copied().collect(),
+            budgets: Budgets::new(config.requests_per_minute),
+            failed_admin_attempts: Budgets::new(FAILED_ADMIN_ATTEMPTS_PER_MINUTE),
+        }
+    }
+
+    /// This function counts a request to an admin endpoint without the admin api key against
+    /// the attempts allowed per minute, so the key can not be guessed
+    ///
+    /// # Arguments
+    ///
+    /// * `ip` - An Option<IpAddr> that holds the address of the client, None when not known
+    ///
+    /// # Returns
+    ///
+    /// * `Charge` - Whether the request is admitted
+    pub fn charge_failed_admin(&self, ip: Option<IpAddr>) -> Charge {
+        let client = ip.map(|ip| ip.to_string()).unwrap_or_default();
+        self.failed_admin_attempts.charge(&client, 1)
+    }
+
+    /// This function counts a request of an address against its per minute limit
+    ///
+    /// # Arguments
+    ///
+    /// * `ip` - An Option<IpAddr> that holds the address of the client, None when not known
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Charge>` - Whether the request is admitted, None when the address is not limited
+    pub fn charge(&self, ip: Option<IpAddr>) -> Option<Charge> {
+        if !self.enabled || ip.is_some_and(|ip| self.exempt.contains(&ip)) {
+            return None;
+        }
+        let client = ip.map(|ip| ip.to_string()).unwrap_or_default();
+        Some(self.budgets.charge(&client, 1))
+    }
+}
+
+/// Middleware limiting the requests of every client address, checked before the api key of the
+/// request so keys can not be guessed at full speed either. The `X-RateLimit-*` headers are only
+/// set on rejected requests, the ones of the public profile are left to it. Admin routes are not
+/// limited for requests carrying the admin api key, the ones without it are limited like any
+/// other and their attempts capped even when the limiter is disabled.
+pub struct IpRateLimit(pub Arc<IpLimiter>);
+
+impl<S, B> Transform<S, ServiceRequest> for IpRateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = IpRateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(IpRateLimitMiddleware {
+            service,
+            limiter: self.0.clone(),
+        }))
+    }
+}
+
+pub struct IpRateLimitMiddleware<S> {
+    service: S,
+    limiter: Arc<IpLimiter>,
+}
+
+impl<S, B> Service<ServiceRequest> for IpRateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let ip = req.peer_addr().map(|addr| addr.ip());
+        let admin = is_admin_request(&req)
+            && req
+                .app_data::<web::Data<AdminKey>>()
+                .is_some_and(|admin_key| admin_key.accepts(req.headers()));
+        if !admin {
+            if is_admin_request(&req) {
+                let charge = self.limiter.charge_failed_admin(ip);
+                if !charge.admitted {
+                    return Box::pin(ready(Ok(charge.reject(req).map_into_right_body())));
+                }
+            }
+            let charge = self.limiter.charge(ip);
+            if let Some(charge) = charge.filter(|charge| !charge.admitted) {
+                return Box::pin(ready(Ok(charge.reject(req).map_into_right_body())));
+            }
+        }
+        let fut = self.service.call(req);
+        Box::pin(async move { fut.await.map(ServiceResponse::map_into_left_body) })
+    }
+}
@@ -0,0 +1,160 @@
+use crate::config::RecoveryConfig;
+use crate::db_handler::{get_meta, open_db, LATEST_BLOCK_NO_KEY};
+use crate::error::AggError;
+use log::{error, warn};
+use rocksdb::{ErrorKind, Options, DB};
+use serde_json::from_slice;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug)]
+pub enum RecoveryMethod {
+    Repaired,
+    RestoredFromCheckpoint(PathBuf),
+}
+
+/// Outcome of recovering a corrupted db
+#[derive(Debug)]
+pub struct RecoveryReport {
+    pub method: RecoveryMethod,
+    /// Latest block still present after the recovery
+    pub latest_block_no: Option<u64>,
+}
+
+impl RecoveryReport {
+    /// This function logs what was recovered and which blocks may have been lost
+    pub fn log(&self) {
+        match &self.method {
+            RecoveryMethod::Repaired => warn!(
+                target: "recovery",
+                "Repaired corrupted db, data in dropped sst files is lost, see the rocksdb LOG for the dropped files"
+            ),
+            RecoveryMethod::RestoredFromCheckpoint(checkpoint) => warn!(
+                target: "recovery",
+                "Restored corrupted db from checkpoint {:?}", checkpoint
+            ),
+        }
+        match self.latest_block_no {
+            Some(block_no) => warn!(
+                target: "recovery",
+                "Recovered through block {}, blocks after {} up to the chain tip may have been lost", block_no, block_no
+            ),
+            None => warn!(
+                target: "recovery",
+                "No finalised block left after recovery, all indexed blocks may have been lost"
+            ),
+        }
+    }
+}
+
+/// This function makes sure the db can be opened, repairing it or restoring the latest
+/// checkpoint if it is corrupted
+///
+/// # Arguments
+///
+/// * `path` - A string slice that holds the path to the database
+/// * `config` - A RecoveryConfig that holds the checkpoint directory
+///
+/// # Returns
+///
+/// * `Result<Option<RecoveryReport>, AggError>` - None if the db opened cleanly, the report of
+///   the recovery otherwise, or an error if the db could not be recovered
+pub fn ensure_openable(
+    path: &str,
+    config: &RecoveryConfig,
+) -> Result<Option<RecoveryReport>, AggError> {
+    match open_db(path, false) {
+        Ok(_) => return Ok(None),
+        Err(err) if err.kind() == ErrorKind::Corruption => {
+            error!(target: "recovery", "Db at {} is corrupted {}", path, err);
+        }
+        Err(err) => return Err(err.into()),
+    }
+    let repaired = DB::repair(&Options::default(), path).and_then(|_| open_db(path, false));
+    match repaired {
+        Ok(db) => {
+            return Ok(Some(RecoveryReport {
+                method: RecoveryMethod::Repaired,
+                latest_block_no: latest_block_no(&db),
+            }))
+        }
+        Err(err) => error!(target: "recovery", "Repair failed {}", err),
+    }
+    restore_latest_checkpoint(path, config).map(Some)
+}
+
+/// This function moves a corrupted db aside and restores the latest checkpoint in its place
+///
+/// # Arguments
+///
+/// * `path` - A string slice that holds the path to the database
+/// * `config` - A RecoveryConfig that holds the checkpoint directory
+///
+/// # Returns
+///
+/// * `Result<RecoveryReport, AggError>` - The report of the restore, or an error if there is no
+///   checkpoint to restore
+pub fn restore_latest_checkpoint(
+    path: &str,
+    config: &RecoveryConfig,
+) -> Result<RecoveryReport, AggError> {
+    let checkpoint = config
+        .checkpoint_dir
+        .as_deref()
+        .map(latest_checkpoint)
+        .transpose()?
+        .flatten()
+        .ok_or_else(|| {
+            AggError::RecoveryError("Repair failed and no checkpoint is available".to_string())
+        })?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let corrupted = format!("{}.corrupted-{}", path, now);
+    fs::rename(path, &corrupted)?;
+    warn!(target: "recovery", "Moved corrupted db to {}", corrupted);
+    fs::create_dir_all(path)?;
+    for entry in fs::read_dir(&checkpoint)? {
+        let entry = entry?;
+        fs::copy(entry.path(), Path::new(path).join(entry.file_name()))?;
+    }
+    let db = open_db(path, false)?;
+    Ok(RecoveryReport {
+        method: RecoveryMethod::RestoredFromCheckpoint(checkpoint),
+        latest_block_no: latest_block_no(&db),
+    })
+}
+
+/// This function finds the most recently modified checkpoint in the checkpoint directory
+///
+/// # Arguments
+///
+/// * `checkpoint_dir` - A Path that holds one checkpoint per sub directory
+///
+/// # Returns
+///
+/// * `Result<Option<PathBuf>, AggError>` - The latest checkpoint if any
+fn latest_checkpoint(checkpoint_dir: &Path) -> Result<Option<PathBuf>, AggError> {
+    let mut latest: Option<(SystemTime, PathBuf)> = None;
+    for entry in fs::read_dir(checkpoint_dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if !metadata.is_dir() {
+            continue;
+        }
+        let modified = metadata.modified()?;
+        if latest.as_ref().is_none_or(|(time, _)| modified > *time) {
+            latest = Some((modified, entry.path()));
+        }
+    }
+    Ok(latest.map(|(_, path)| path))
+}
+
+fn latest_block_no(db: &DB) -> Option<u64> {
+    get_meta(db, LATEST_BLOCK_NO_KEY)
+        .ok()
+        .flatten()
+        .and_then(|block_no| from_slice::<u64>(&block_no).ok())
+}
@@ -0,0 +1,103 @@
+use crate::util::Block;
+
+/// A stored block of a fork the chain abandoned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OrphanedBlock {
+    pub block_no: u64,
+    pub slot: u64,
+}
+
+/// Stored blocks a new block shows were abandoned by the chain
+#[derive(Debug, PartialEq, Eq)]
+pub struct Reorg {
+    /// The abandoned blocks, lowest first
+    pub orphaned: Vec<OrphanedBlock>,
+    /// Slot of the canonical parent of the new block, to fetch when the stored parent was
+    /// abandoned
+    pub canonical_parent_slot: Option<u64>,
+}
+
+/// What a new block tells about the stored chain
+#[derive(Debug, PartialEq, Eq)]
+pub enum ForkCheck {
+    /// The new block extends the stored chain, or nothing stored contradicts it
+    Extends,
+    Reorg(Reorg),
+    /// The abandoned fork holds more stored blocks than may be rolled back, at least this many
+    TooDeep(u64),
+}
+
+/// This function compares a new block against the stored blocks at its height and below it.
+/// A stored block at its height with another blockhash, or a stored parent whose blockhash is
+/// not the previous blockhash of the new block, belongs to an abandoned fork along with the
+/// stored blocks chaining onto it.
+///
+/// # Arguments
+///
+/// * `block_no` - A u64 that holds the block number of the new block
+/// * `block` - A Block that holds the new block
+/// * `max_depth` - A u64 that holds the stored blocks a reorg may roll back
+/// * `stored` - A closure that returns the stored block of a block number
+///
+/// # Returns
+///
+/// * `ForkCheck` - Whether the new block extends the stored chain or which blocks it orphans
+pub fn detect(
+    block_no: u64,
+    block: &Block,
+    max_depth: u64,
+    stored: impl Fn(u64) -> Option<Block>,
+) -> ForkCheck {
+    let replaced = stored(block_no).filter(|stored| stored.blockhash() != block.blockhash());
+    let parent = block_no.checked_sub(1).and_then(|parent_no| {
+        stored(parent_no)
+            .filter(
+                |parent| match (parent.blockhash(), block.previous_blockhash()) {
+                    (Some(parent_hash), Some(previous)) => parent_hash != previous,
+                    _ => false,
+                },
+            )
+            .map(|parent| (parent_no, parent))
+    });
+    let (first_no, first, canonical_parent_slot) = match (parent, replaced) {
+        (Some((parent_no, parent)), _) => (parent_no, parent, block.parent_slot()),
+        (None, Some(replaced)) => (block_no, replaced, None),
+        (None, None) => return ForkCheck::Extends,
+    };
+    // Blocks stored before slots were kept cannot be rolled back by slot
+    let Some(slot) = first.slot() else {
+        return ForkCheck::Extends;
+    };
+    let mut orphaned = vec![OrphanedBlock {
+        block_no: first_no,
+        slot,
+    }];
+    let mut blockhash = first.blockhash().map(str::to_string);
+    let mut next_no = first_no + 1;
+    while let Some(next) = stored(next_no) {
+        let Some(slot) = next.slot() else {
+            break;
+        };
+        // The blocks of the new fork stored ahead of the new block do not chain onto the
+        // abandoned blocks
+        if blockhash.is_none() || next.previous_blockhash() != blockhash.as_deref() {
+            break;
+        }
+        orphaned.push(OrphanedBlock {
+            block_no: next_no,
+            slot,
+        });
+        if orphaned.len() as u64 > max_depth {
+            break;
+        }
+        blockhash = next.blockhash().map(str::to_string);
+        next_no += 1;
+    }
+    if orphaned.len() as u64 > max_depth {
+        return ForkCheck::TooDeep(orphaned.len() as u64);
+    }
+    ForkCheck::Reorg(Reorg {
+        orphaned,
+        canonical_parent_slot,
+    })
+}
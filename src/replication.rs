@@ -0,0 +1,130 @@
+use crate::error::AggError;
+use crate::util::{Block, ProtocolMessage, Response};
+use futures_util::StreamExt;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Interval at which the primary sends heartbeats on an idle block stream
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Messages sent by a primary on its block stream
+#[derive(Serialize, Deserialize, Debug)]
+pub enum ReplicationMessage {
+    Block(u64, Box<Block>),
+    Heartbeat,
+}
+
+pub struct Follower {
+    primary_url: String,
+    db_sender: UnboundedSender<ProtocolMessage>,
+    failover_timeout: Duration,
+    last_slot: Option<u64>,
+}
+
+impl Follower {
+    /// This function initializes the standby follower
+    ///
+    /// # Arguments
+    ///
+    /// * `primary_url` - A String that holds the block stream url of the primary
+    /// * `db_sender` - A UnboundedSender<ProtocolMessage> that holds the db sender
+    /// * `failover_timeout` - A Duration after which a silent primary is considered failed
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The follower
+    pub fn initialize(
+        primary_url: String,
+        db_sender: UnboundedSender<ProtocolMessage>,
+        failover_timeout: Duration,
+    ) -> Self {
+        Self {
+            primary_url,
+            db_sender,
+            failover_timeout,
+            last_slot: None,
+        }
+    }
+
+    /// This function follows the primary until it stops sending blocks and heartbeats
+    ///
+    /// # Returns
+    ///
+    /// * `Option<u64>` - The slot of the last stored block, from where ingestion should resume
+    pub async fn run(&mut self) -> Option<u64> {
+        let mut last_seen = Instant::now();
+        while last_seen.elapsed() < self.failover_timeout {
+            match self.follow(&mut last_seen).await {
+                Ok(()) => warn!(target: "replication", "Primary closed the block stream"),
+                Err(err) => warn!(target: "replication", "Block stream failed {}", err),
+            }
+            tokio::time::sleep(HEARTBEAT_INTERVAL).await;
+        }
+        warn!(target: "replication", "Primary silent for {:?}, taking over ingestion", self.failover_timeout);
+        self.last_slot
+    }
+
+    /// This function streams blocks from the primary into the local db
+    ///
+    /// # Arguments
+    ///
+    /// * `last_seen` - An Instant updated whenever the primary shows signs of life
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - A Result that holds the result or an error
+    async fn follow(&mut self, last_seen: &mut Instant) -> Result<(), AggError> {
+        let from_block_no = self.latest_stored_block().await?;
+        let url = match from_block_no {
+            Some(block_no) => format!("{}?block_no={}", self.primary_url, block_no + 1),
+            None => self.primary_url.clone(),
+        };
+        let (mut stream, _) = tokio_tungstenite::connect_async(url)
+            .await
+            .map_err(|err| AggError::ReplicationError(err.to_string()))?;
+        info!(target: "replication", "Following primary from block {:?}", from_block_no);
+        *last_seen = Instant::now();
+        loop {
+            let message = match tokio::time::timeout(self.failover_timeout, stream.next()).await {
+                Ok(Some(message)) => {
+                    message.map_err(|err| AggError::ReplicationError(err.to_string()))?
+                }
+                Ok(None) => return Ok(()),
+                Err(_) => {
+                    return Err(AggError::ReplicationError(
+                        "Heartbeat timed out".to_string(),
+                    ))
+                }
+            };
+            *last_seen = Instant::now();
+            if let Message::Text(text) = message {
+                if let ReplicationMessage::Block(block_no, block) = serde_json::from_str(&text)? {
+                    self.last_slot = block.slot().or(self.last_slot);
+                    self.db_sender
+                        .send(ProtocolMessage::FinalizeBlock(block_no, *block))?;
+                }
+            }
+        }
+    }
+
+    /// This function fetches the latest block stored locally
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<u64>, AggError>` - A Result that holds the block number or an error
+    async fn latest_stored_block(&mut self) -> Result<Option<u64>, AggError> {
+        let response =
+            ProtocolMessage::ask(&self.db_sender, ProtocolMessage::FetchLatestBlock).await;
+        match response {
+            Ok(Response::LatestBlockDetails(block_no, block)) => {
+                self.last_slot = block.slot().or(self.last_slot);
+                Ok(Some(block_no))
+            }
+            Err(err @ AggError::MpscChannelError(_)) => Err(err),
+            _ => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use crate::error::AggError;
+use crate::metrics;
+use crate::util::FailureStage;
+use log::warn;
+use std::future::Future;
+use std::time::Duration;
+
+/// Retries of a block fetch, the node may be catching up or briefly unavailable
+pub const FETCH_RETRY: RetryPolicy = RetryPolicy {
+    max_attempts: 4,
+    initial_backoff: Duration::from_millis(500),
+    max_backoff: Duration::from_secs(8),
+};
+/// Retries of a chunk parse, parsing only fails transiently when a channel is congested
+pub const PARSE_RETRY: RetryPolicy = RetryPolicy {
+    max_attempts: 2,
+    initial_backoff: Duration::from_millis(100),
+    max_backoff: Duration::from_millis(100),
+};
+/// Retries of storing a block, waited out on the db actor so the waits are short
+pub const WRITE_RETRY: RetryPolicy = RetryPolicy {
+    max_attempts: 3,
+    initial_backoff: Duration::from_millis(10),
+    max_backoff: Duration::from_millis(100),
+};
+
+/// The last error of an operation that was given up on, along with the attempts made
+#[derive(Debug)]
+pub struct Failure {
+    pub error: AggError,
+    pub attempts: u32,
+}
+
+/// How often and how patiently an operation is retried. Only transient errors are retried, an
+/// operation failing with a permanent error is given up on after its first attempt.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    /// Wait before the second attempt, doubled before every further one
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_backoff)
+    }
+
+    /// This function tells whether an attempt that failed with an error is retried
+    fn retries(&self, stage: FailureStage, attempt: u32, error: &AggError) -> bool {
+        if !error.is_transient() || attempt >= self.max_attempts {
+            return false;
+        }
+        warn!(
+            target: "retry",
+            "Attempt {} of {} of the {} failed, retrying: {}",
+            attempt, self.max_attempts, stage.as_str(), error
+        );
+        metrics::RETRIES.with_label_values(&[stage.as_str()]).inc();
+        true
+    }
+
+    /// This function runs an operation until it succeeds, fails permanently or runs out of
+    /// attempts
+    ///
+    /// # Arguments
+    ///
+    /// * `stage` - A FailureStage that holds the step the operation belongs to
+    /// * `operation` - A closure that starts an attempt
+    ///
+    /// # Returns
+    ///
+    /// * `Result<T, Failure>` - The result of the first successful attempt or the last error
+    pub async fn run<T, F, Fut>(&self, stage: FailureStage, mut operation: F) -> Result<T, Failure>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, AggError>>,
+    {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            match operation().await {
+                Ok(value) => return Ok(value),
+                Err(error) if self.retries(stage, attempt, &error) => {
+                    tokio::time::sleep(self.backoff(attempt)).await;
+                }
+                Err(error) => {
+                    return Err(Failure {
+                        error,
+                        attempts: attempt,
+                    })
+                }
+            }
+        }
+    }
+
+    /// This function runs a blocking operation until it succeeds, fails permanently or runs out
+    /// of attempts, blocking the thread while it waits, for the blocking calls made on startup
+    ///
+    /// # Arguments
+    ///
+    /// * `stage` - A FailureStage that holds the step the operation belongs to
+    /// * `operation` - A closure that runs an attempt
+    ///
+    /// # Returns
+    ///
+    /// * `Result<T, Failure>` - The result of the first successful attempt or the last error
+    pub fn run_blocking<T>(
+        &self,
+        stage: FailureStage,
+        mut operation: impl FnMut() -> Result<T, AggError>,
+    ) -> Result<T, Failure> {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            match operation() {
+                Ok(value) => return Ok(value),
+                Err(error) if self.retries(stage, attempt, &error) => {
+                    std::thread::sleep(self.backoff(attempt));
+                }
+                Err(error) => {
+                    return Err(Failure {
+                        error,
+                        attempts: attempt,
+                    })
+                }
+            }
+        }
+    }
+}
@@ -1,9 +1,95 @@
+use crate::block_importer;
+use crate::breaker::CircuitBreaker;
+use crate::config::{Commitment, Config, NodeConfig, QueryConfig};
+use crate::endpoints::RpcEndpoints;
+use crate::envelope::{Envelope, Finality, ResponseFormat, SlotTracker};
 use crate::error::AggError;
-use crate::util::{Channel, ProtocolMessage, QueryParams};
-use actix_web::{get, middleware, web, App, HttpResponse, HttpServer, Responder};
+use crate::export::Exporter;
+use crate::fanout;
+use crate::inclusion;
+use crate::logging;
+use crate::metrics;
+use crate::rate_limit::{IpLimiter, IpRateLimit, PublicLimiter, PublicRateLimit};
+use crate::replication::{ReplicationMessage, HEARTBEAT_INTERVAL};
+use crate::shutdown::Shutdown;
+use crate::tenant::{TenantAuth, TenantRegistry, API_KEY_HEADER};
+use crate::util::{
+    AccountBalanceParams, AccountId, AccountStreamParams, AnnotationParams, BalanceHistoryParams,
+    BalancesAtParams, Block, BlockDigest, BlockLookup, BlockLookupParams, BlockStreamParams,
+    CommitmentParams, CompactParams, CursorParams, DaysParams, DeleteSlotsParams, ExportParams,
+    JobKind, JobParams, JobState, JobTask, LimitParams, LogLevelParams, ProtocolMessage,
+    PruneProgress, QueryParams, ReparseParams, ReparseProgress, Reply, Response, SlotRangeParams,
+    TimeRange, TimeRangeParams, TxId, TxRecord, WebhookParams,
+};
+use crate::warmup::Readiness;
+use actix_web::http::header::HeaderMap;
+use actix_web::{
+    delete, get, middleware, post, web, App, HttpRequest, HttpResponse, HttpServer, Responder,
+    ResponseError,
+};
+use actix_ws::Message;
+use log::info;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Instant;
+use subtle::ConstantTimeEq;
 use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::{oneshot, Semaphore};
 
-pub(crate) struct AggServer;
+const DEFAULT_SLOT_LIMIT: u64 = 1_000;
+const MAX_SLOT_LIMIT: u64 = 10_000;
+const DEFAULT_SUMMARY_LIMIT: u64 = 20;
+const MAX_SUMMARY_LIMIT: u64 = 1_000;
+const DEFAULT_ACTIVE_ACCOUNT_DAYS: u64 = 7;
+const MAX_ACTIVE_ACCOUNT_DAYS: u64 = 366;
+const DEFAULT_TX_LIMIT: u64 = 100;
+const MAX_TX_LIMIT: u64 = 1_000;
+const DEFAULT_DELIVERY_LIMIT: u64 = 100;
+const MAX_DELIVERY_LIMIT: u64 = 1_000;
+const MAX_ANNOTATION_LEN: usize = 1_024;
+const MAX_BALANCE_ACCOUNTS: usize = 1_000;
+/// Transactions verified against the node at once, each costs a block fetch
+const MAX_CONCURRENT_VERIFICATIONS: usize = 4;
+
+/// Api key of the admin endpoints, none when they are disabled
+pub struct AdminKey(Option<String>);
+
+impl AdminKey {
+    pub fn new(key: Option<String>) -> Self {
+        AdminKey(key)
+    }
+
+    /// This function tells whether the headers of a request carry the admin api key
+    ///
+    /// # Arguments
+    ///
+    /// * `headers` - A HeaderMap that holds the headers of the request
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Whether an admin api key is configured and presented
+    pub fn accepts(&self, headers: &HeaderMap) -> bool {
+        match (&self.0, headers.get(API_KEY_HEADER)) {
+            // Constant time, so the time taken does not tell how much of a guess is right
+            (Some(expected), Some(provided)) => {
+                provided.as_bytes().ct_eq(expected.as_bytes()).into()
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Endpoints of the node called while answering requests, which transactions are verified
+/// against and blocks are fetched again from, with permits bounding the verifications running so
+/// the public `/verify_tx` can not flood the node, and the breaker failing the calls at once
+/// while the node keeps failing
+struct Upstream {
+    endpoints: Option<Arc<RpcEndpoints>>,
+    permits: Semaphore,
+    breaker: CircuitBreaker,
+}
+
+pub struct AggServer;
 
 impl AggServer {
 
@@ -12,121 +98,1719 @@ impl AggServer {
     /// # Arguments
     ///
     /// * `handler_sender` - A UnboundedSender<ProtocolMessage> that holds the handler sender
-    /// * `port_no` - A string slice that holds the port number
+    /// * `config` - A Config that holds the port, the tenants, the admin api key and the rate
+    ///   limits
+    /// * `slot_tracker` - An Arc<SlotTracker> that holds the slots responses are stamped with
+    /// * `exporter` - An Arc<Exporter> that runs the range exports
+    /// * `readiness` - An Arc<Readiness> that holds whether the db warmed its caches up
+    /// * `rpc_endpoints` - An Option<Arc<RpcEndpoints>> that holds the endpoints transactions are
+    ///   verified against, None when no node is configured
+    /// * `shutdown` - A Shutdown that holds the signal the server stops on, once the requests in
+    ///   flight are answered
     ///
     /// # Returns
     ///
     /// * `Result<(), AggError>` - A Result that holds the result or an error
     pub async fn run(
         handler_sender: UnboundedSender<ProtocolMessage>,
-        port_no: String,
+        config: Config,
+        slot_tracker: Arc<SlotTracker>,
+        exporter: Arc<Exporter>,
+        readiness: Arc<Readiness>,
+        rpc_endpoints: Option<Arc<RpcEndpoints>>,
+        mut shutdown: Shutdown,
     ) -> Result<(), AggError> {
-        HttpServer::new(move || {
-            App::new()
+        let tenants = Arc::new(TenantRegistry::new(&config.tenants));
+        let public_limiter = Arc::new(PublicLimiter::new(&config.public));
+        let ip_limiter = Arc::new(IpLimiter::new(&config.rate_limit));
+        let admin_key = web::Data::new(AdminKey(config.admin_api_key));
+        let breaker = CircuitBreaker::new(config.breaker);
+        let query_config = web::Data::new(config.query);
+        let finality = web::Data::new(Finality::new(config.node.commitment, slot_tracker.clone()));
+        let port_no = config.node.port;
+        let node_config = web::Data::new(config.node);
+        let upstream = web::Data::new(Upstream {
+            endpoints: rpc_endpoints,
+            permits: Semaphore::new(MAX_CONCURRENT_VERIFICATIONS),
+            breaker,
+        });
+        let server = HttpServer::new(move || {
+            let app = App::new()
                 .app_data(web::Data::new(handler_sender.clone()))
+                .app_data(web::Data::from(tenants.clone()))
+                .app_data(admin_key.clone())
+                .app_data(query_config.clone())
+                .app_data(node_config.clone())
+                .app_data(finality.clone())
+                .app_data(web::Data::from(exporter.clone()))
+                .app_data(web::Data::from(readiness.clone()))
+                .app_data(upstream.clone())
+                .app_data(web::PathConfig::default().error_handler(bad_request))
+                .app_data(web::QueryConfig::default().error_handler(bad_request))
+                .app_data(web::JsonConfig::default().error_handler(bad_request))
+                .wrap(TenantAuth(tenants.clone()))
+                .wrap(PublicRateLimit(public_limiter.clone()))
+                .wrap(IpRateLimit(ip_limiter.clone()))
+                .wrap(Envelope(slot_tracker.clone()))
                 .wrap(middleware::Logger::default())
-                .service(get_tx_details)
-                .service(get_block_details)
-                .service(get_latest_block)
-                .service(get_block_range)
-                .service(get_account_balance)
+                .configure(configure);
+            #[cfg(feature = "ui")]
+            let app = app.configure(crate::ui::configure);
+            #[cfg(feature = "profiling")]
+            let app = app.configure(crate::profiling::configure);
+            app
         })
         .bind(format!("127.0.0.1:{port_no}"))?
-        .run()
-        .await?;
+        // Signals are handled by the shutdown coordinator, which stops the server first
+        .disable_signals()
+        .run();
+        let handle = server.handle();
+        tokio::spawn(async move {
+            shutdown.requested().await;
+            info!(target: "server", "Stopping the server");
+            handle.stop(true).await;
+        });
+        server.await?;
         Ok(())
     }
 }
 
+/// This function registers the endpoints of the api, the app provides the data they read
+///
+/// # Arguments
+///
+/// * `config` - A ServiceConfig the endpoints are added to
+pub fn configure(config: &mut web::ServiceConfig) {
+    config
+        .service(get_tx_details)
+        .service(get_tx_logs)
+        .service(get_tx_inclusion)
+        .service(verify_tx)
+        .service(get_block_details)
+        .service(get_latest_block)
+        .service(get_block_range)
+        .service(get_block_range_by_time)
+        .service(get_latest_block_summaries)
+        .service(get_account_balance)
+        .service(get_balances_at)
+        .service(get_balance_history)
+        .service(get_indexed_slots)
+        .service(get_token_holders)
+        .service(get_token_balance)
+        .service(get_token_balances)
+        .service(get_account_summary)
+        .service(get_account_transactions)
+        .service(get_status)
+        .service(get_ready)
+        .service(get_tps_stats)
+        .service(get_custom_stats)
+        .service(get_active_accounts)
+        .service(get_metrics)
+        .service(get_block_digest)
+        .service(get_tenant_usage)
+        .service(compact)
+        .service(delete_slots)
+        .service(refetch_block)
+        .service(annotate_slot)
+        .service(reparse)
+        .service(get_reparse_progress)
+        .service(start_job)
+        .service(get_jobs)
+        .service(get_job)
+        .service(cancel_job)
+        .service(start_export)
+        .service(get_exports)
+        .service(get_export)
+        .service(resume_export)
+        .service(create_webhook)
+        .service(delete_webhook)
+        .service(get_webhook_deliveries)
+        .service(get_dead_letters)
+        .service(get_subscriptions)
+        .service(get_log_levels)
+        .service(set_log_level)
+        .service(reset_log_level)
+        .service(block_stream)
+        .service(account_stream);
+}
+
 #[get("/tx_details/{tx_id}")]
 async fn get_tx_details(
-    tx_id: web::Path<String>,
+    tx_id: web::Path<TxId>,
+    commitment: web::Query<CommitmentParams>,
+    finality: web::Data<Finality>,
+    query_config: web::Data<QueryConfig>,
     sender: web::Data<UnboundedSender<ProtocolMessage>>,
 ) -> impl Responder {
-    let mut channel = Channel::<ProtocolMessage>::new();
-    if let Err(error) = sender.send(ProtocolMessage::FetchTransactionDetails(
-        tx_id.into_inner(),
-        channel.sender(),
-    )) {
-        return HttpResponse::InternalServerError().json(error.to_string());
+    let tx_id = tx_id.into_inner().into_string();
+    match fetch_tx(
+        tx_id,
+        commitment.commitment,
+        &finality,
+        &query_config,
+        &sender,
+    )
+    .await
+    {
+        Ok(tx) => HttpResponse::Ok().json(tx),
+        Err(response) => response,
+    }
+}
+
+#[get("/tx_logs/{tx_id}")]
+async fn get_tx_logs(
+    tx_id: web::Path<TxId>,
+    commitment: web::Query<CommitmentParams>,
+    finality: web::Data<Finality>,
+    query_config: web::Data<QueryConfig>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+) -> impl Responder {
+    let tx_id = tx_id.into_inner().into_string();
+    let response = fetch_tx(
+        tx_id.clone(),
+        commitment.commitment,
+        &finality,
+        &query_config,
+        &sender,
+    )
+    .await;
+    match response {
+        Ok(tx) => HttpResponse::Ok().json(tx.tx_logs(&tx_id)),
+        Err(response) => response,
+    }
+}
+
+/// This function fetches the record of a transaction once its block reached the commitment the
+/// query asked for
+///
+/// # Arguments
+///
+/// * `tx_id` - A String that holds the transaction id
+/// * `commitment` - An Option<Commitment> that holds the commitment the query asked for
+/// * `finality` - A Finality that tells which blocks are finalized
+/// * `query_config` - A QueryConfig that holds the request timeout
+/// * `sender` - A UnboundedSender<ProtocolMessage> that holds the handler sender
+///
+/// # Returns
+///
+/// * `Result<TxRecord, HttpResponse>` - The record or the response to fail the request with
+async fn fetch_tx(
+    tx_id: String,
+    commitment: Option<Commitment>,
+    finality: &Finality,
+    query_config: &QueryConfig,
+    sender: &UnboundedSender<ProtocolMessage>,
+) -> Result<TxRecord, HttpResponse> {
+    if !finality.filters(commitment) {
+        return match query_db(sender, query_config, |reply| {
+            ProtocolMessage::FetchTransactionDetails(tx_id, reply)
+        })
+        .await?
+        {
+            Response::TxDetails(tx) => Ok(tx),
+            _ => Err(unexpected_reply()),
+        };
     }
-    match channel.receiver.recv().await {
-        Some(ProtocolMessage::TxDetails(tx)) => HttpResponse::Ok().json(tx),
-        Some(ProtocolMessage::Error(err)) => HttpResponse::InternalServerError().json(err),
-        _ => HttpResponse::InternalServerError().finish(),
+    // The slot of the transaction is read from its block
+    match query_db(sender, query_config, |reply| {
+        ProtocolMessage::FetchTxInclusion(tx_id, reply)
+    })
+    .await?
+    {
+        Response::TxInclusion(inclusion, tx) => {
+            finality
+                .check(commitment, inclusion.slot)
+                .map_err(|err| error_response(&err))?;
+            Ok(tx)
+        }
+        _ => Err(unexpected_reply()),
     }
 }
 
-#[get("/block_details/{block_no}")]
-async fn get_block_details(
-    block_no: web::Path<String>,
+#[get("/tx_inclusion/{tx_id}")]
+async fn get_tx_inclusion(
+    tx_id: web::Path<TxId>,
+    commitment: web::Query<CommitmentParams>,
+    finality: web::Data<Finality>,
+    query_config: web::Data<QueryConfig>,
     sender: web::Data<UnboundedSender<ProtocolMessage>>,
 ) -> impl Responder {
-    let mut channel = Channel::<ProtocolMessage>::new();
-    if let Err(error) = sender.send(ProtocolMessage::FetchBlockDetails(
-        block_no.into_inner(),
-        channel.sender(),
-    )) {
-        return HttpResponse::InternalServerError().json(error.to_string());
+    let response = query_db(&sender, &query_config, |reply| {
+        ProtocolMessage::FetchTxInclusion(tx_id.into_inner().into_string(), reply)
+    })
+    .await;
+    match response {
+        Ok(Response::TxInclusion(inclusion, _)) => {
+            match finality.check(commitment.commitment, inclusion.slot) {
+                Ok(()) => HttpResponse::Ok().json(inclusion),
+                Err(err) => error_response(&err),
+            }
+        }
+        Ok(_) => unexpected_reply(),
+        Err(response) => response,
+    }
+}
+
+#[get("/verify_tx/{tx_id}")]
+async fn verify_tx(
+    tx_id: web::Path<TxId>,
+    query_config: web::Data<QueryConfig>,
+    node_config: web::Data<NodeConfig>,
+    upstream: web::Data<Upstream>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+) -> impl Responder {
+    let Some(endpoints) = &upstream.endpoints else {
+        return error_response(&AggError::ConfigError(
+            "node.chain_url holds no url to verify transactions against".to_string(),
+        ));
+    };
+    let Ok(_permit) = upstream.permits.try_acquire() else {
+        return error_response(&AggError::RateLimited(
+            "too many transactions are being verified, retry later".to_string(),
+        ));
+    };
+    let response = query_db(&sender, &query_config, |reply| {
+        ProtocolMessage::FetchTxInclusion(tx_id.into_inner().into_string(), reply)
+    })
+    .await;
+    match response {
+        Ok(Response::TxInclusion(inclusion, record)) => {
+            let verification = upstream
+                .breaker
+                .call(
+                    query_config.request_timeout(),
+                    inclusion::fetch_and_verify(
+                        endpoints,
+                        node_config.commitment,
+                        &inclusion,
+                        &record,
+                    ),
+                )
+                .await;
+            match verification {
+                Ok(verification) => HttpResponse::Ok().json(verification),
+                Err(err) => error_response(&err),
+            }
+        }
+        Ok(_) => unexpected_reply(),
+        Err(response) => response,
     }
-    match channel.receiver.recv().await {
-        Some(ProtocolMessage::BlockDetails(block)) => HttpResponse::Ok().json(block),
-        _ => HttpResponse::InternalServerError().finish(),
+}
+
+#[get("/block_details/{id}")]
+async fn get_block_details(
+    id: web::Path<u64>,
+    lookup: web::Query<BlockLookupParams>,
+    format: web::Query<ResponseFormat>,
+    commitment: web::Query<CommitmentParams>,
+    finality: web::Data<Finality>,
+    query_config: web::Data<QueryConfig>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+) -> impl Responder {
+    let id = id.into_inner();
+    let response = query_db(&sender, &query_config, |reply| match lookup.by {
+        BlockLookup::Height => ProtocolMessage::FetchBlockDetails(id, reply),
+        BlockLookup::Slot => ProtocolMessage::FetchBlockAtSlot(id, reply),
+    })
+    .await;
+    match response {
+        Ok(Response::BlockDetails(block)) => {
+            if let Err(err) = finality.check(commitment.commitment, block.slot()) {
+                return error_response(&err);
+            }
+            match format.project_block(block) {
+                Ok(block) => HttpResponse::Ok().json(block),
+                Err(err) => error_response(&AggError::from(err)),
+            }
+        }
+        Ok(_) => unexpected_reply(),
+        Err(response) => response,
     }
 }
 
 #[get("/latest_block")]
-async fn get_latest_block(sender: web::Data<UnboundedSender<ProtocolMessage>>) -> impl Responder {
-    let mut channel = Channel::<ProtocolMessage>::new();
-    if let Err(error) = sender.send(ProtocolMessage::FetchLatestBlock(channel.sender())) {
-        return HttpResponse::InternalServerError().json(error.to_string());
+async fn get_latest_block(
+    format: web::Query<ResponseFormat>,
+    commitment: web::Query<CommitmentParams>,
+    finality: web::Data<Finality>,
+    query_config: web::Data<QueryConfig>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+) -> impl Responder {
+    let response = query_db(&sender, &query_config, ProtocolMessage::FetchLatestBlock).await;
+    let (block_no, block) = match response {
+        Ok(Response::LatestBlockDetails(block_no, block)) => (block_no, block),
+        Ok(_) => return unexpected_reply(),
+        Err(response) => return response,
+    };
+    let latest = if finality.admits(commitment.commitment, block.slot()) {
+        Ok((block_no, block))
+    } else {
+        latest_finalized_block(block_no, &block, &finality, &query_config, &sender).await
+    };
+    match latest {
+        Ok((block_no, block)) => match format.project_block(block) {
+            Ok(block) => HttpResponse::Ok().json((block_no, block)),
+            Err(err) => error_response(&AggError::from(err)),
+        },
+        Err(response) => response,
     }
-    match channel.receiver.recv().await {
-        Some(ProtocolMessage::LatestBlockDetails(block_no, block)) => {
-            HttpResponse::Ok().json((block_no, block))
-        }
-        _ => HttpResponse::InternalServerError().finish(),
+}
+
+/// This function finds the latest finalized block among the blocks stored before the latest
+/// block, looking back at most `max_block_range_span` blocks
+///
+/// # Arguments
+///
+/// * `latest_block_no` - A u64 that holds the number of the latest block, not finalized yet
+/// * `latest` - A Block that holds the latest block
+/// * `finality` - A Finality that tells which blocks are finalized
+/// * `query_config` - A QueryConfig that holds the maximum span and request timeout
+/// * `sender` - A UnboundedSender<ProtocolMessage> that holds the handler sender
+///
+/// # Returns
+///
+/// * `Result<(u64, Block), HttpResponse>` - The block and its number or the response to fail the
+///   request with
+async fn latest_finalized_block(
+    latest_block_no: u64,
+    latest: &Block,
+    finality: &Finality,
+    query_config: &QueryConfig,
+    sender: &UnboundedSender<ProtocolMessage>,
+) -> Result<(u64, Block), HttpResponse> {
+    let not_finalized = || {
+        let err = finality
+            .check(Some(Commitment::Finalized), latest.slot())
+            .err()
+            .unwrap_or(AggError::NoBlockFinalised);
+        error_response(&err)
+    };
+    if latest_block_no == 0 {
+        return Err(not_finalized());
+    }
+    let end = latest_block_no - 1;
+    let start = end.saturating_sub(query_config.max_block_range_span.max(1) - 1);
+    match query_db(sender, query_config, |reply| {
+        ProtocolMessage::FetchBlockRange(start, end, reply)
+    })
+    .await?
+    {
+        Response::BlockRangeDetails(blocks) => blocks
+            .into_iter()
+            .rev()
+            .find(|(_, block)| finality.admits(Some(Commitment::Finalized), block.slot()))
+            .ok_or_else(not_finalized),
+        _ => Err(unexpected_reply()),
     }
 }
 
 #[get("/block_range/{start}/{end}")]
 async fn get_block_range(
     range: web::Path<(u64, u64)>,
+    format: web::Query<ResponseFormat>,
+    commitment: web::Query<CommitmentParams>,
+    finality: web::Data<Finality>,
+    query_config: web::Data<QueryConfig>,
     sender: web::Data<UnboundedSender<ProtocolMessage>>,
 ) -> impl Responder {
-    let mut channel = Channel::<ProtocolMessage>::new();
     let (start, end) = range.into_inner();
-    if let Err(err) = sender.send(ProtocolMessage::FetchBlockRange(
-        start,
-        end,
-        channel.sender(),
-    )) {
-        return HttpResponse::InternalServerError().json(err.to_string());
+    let admitted = |block: &Block| finality.admits(commitment.commitment, block.slot());
+    block_range(start, end, &format, admitted, &query_config, &sender).await
+}
+
+#[get("/block_range")]
+async fn get_block_range_by_time(
+    query: web::Query<TimeRangeParams>,
+    format: web::Query<ResponseFormat>,
+    commitment: web::Query<CommitmentParams>,
+    finality: web::Data<Finality>,
+    query_config: web::Data<QueryConfig>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+) -> impl Responder {
+    let (start_time, end_time) = (query.start_time.unix_secs(), query.end_time.unix_secs());
+    if start_time > end_time {
+        return error_response(&AggError::BadRequest(
+            "start_time must be <= end_time".to_string(),
+        ));
+    }
+    let admitted = |block: &Block| finality.admits(commitment.commitment, block.slot());
+    match resolve_time_range(start_time, end_time, &query_config, &sender).await {
+        Ok(Some(range)) => {
+            block_range(
+                range.start_block_no,
+                range.end_block_no,
+                &format,
+                admitted,
+                &query_config,
+                &sender,
+            )
+            .await
+        }
+        Ok(None) => HttpResponse::Ok().json(BTreeMap::<u64, ()>::new()),
+        Err(response) => response,
+    }
+}
+
+/// This function answers a block range query once the range is known
+///
+/// # Arguments
+///
+/// * `start` - A u64 that holds the first block number
+/// * `end` - A u64 that holds the last block number
+/// * `format` - A ResponseFormat that holds how much of the transactions the blocks hold
+/// * `admitted` - A closure telling whether a block reached the commitment the query asked for
+/// * `query_config` - A QueryConfig that holds the maximum span and request timeout
+/// * `sender` - A UnboundedSender<ProtocolMessage> that holds the handler sender
+///
+/// # Returns
+///
+/// * `HttpResponse` - The blocks of the range or the reason they were not returned
+async fn block_range(
+    start: u64,
+    end: u64,
+    format: &ResponseFormat,
+    admitted: impl Fn(&Block) -> bool,
+    query_config: &QueryConfig,
+    sender: &UnboundedSender<ProtocolMessage>,
+) -> HttpResponse {
+    let max_span = query_config.max_block_range_span.max(1);
+    if start > end {
+        return error_response(&AggError::BadRequest("start must be <= end".to_string()));
     }
-    match channel.receiver.recv().await {
-        Some(ProtocolMessage::BlockRangeDetails(blocks)) => HttpResponse::Ok().json(blocks),
-        _ => HttpResponse::InternalServerError().finish(),
+    if end - start >= max_span {
+        return error_response(&AggError::BadRequest(format!(
+            "At most {} blocks can be requested at once, page through the range with /block_range/{}/{}",
+            max_span,
+            start,
+            start.saturating_add(max_span - 1)
+        )));
+    }
+    let response = query_db(sender, query_config, |reply| {
+        ProtocolMessage::FetchBlockRange(start, end, reply)
+    })
+    .await;
+    match response {
+        Ok(Response::BlockRangeDetails(blocks)) => {
+            let blocks: Result<BTreeMap<u64, serde_json::Value>, serde_json::Error> = blocks
+                .into_iter()
+                .filter(|(_, block)| admitted(block))
+                .map(|(block_no, block)| Ok((block_no, format.project_block(block)?)))
+                .collect();
+            match blocks {
+                Ok(blocks) => HttpResponse::Ok().json(blocks),
+                Err(err) => error_response(&AggError::from(err)),
+            }
+        }
+        Ok(_) => unexpected_reply(),
+        Err(response) => response,
     }
 }
 
 #[get("/account_balance/{account_id}")]
 async fn get_account_balance(
-    account_id: web::Path<String>,
+    account_id: web::Path<AccountId>,
+    query: web::Query<AccountBalanceParams>,
+    query_config: web::Data<QueryConfig>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+) -> impl Responder {
+    let account = account_id.into_inner().into_string();
+    let response = query_db(&sender, &query_config, |reply| match query.slot {
+        Some(slot) => ProtocolMessage::FetchAccountBalanceAtSlot(account, slot, reply),
+        None => ProtocolMessage::FetchAccountBalance(account, query.block_no, reply),
+    })
+    .await;
+    match response {
+        Ok(Response::AccountBalance(balance)) if !balance.known => {
+            HttpResponse::NotFound().json(balance)
+        }
+        Ok(Response::AccountBalance(balance)) => HttpResponse::Ok().json(balance),
+        Ok(_) => unexpected_reply(),
+        Err(response) => response,
+    }
+}
+
+/// Balances of several accounts as of one block in one response, for the jobs reconstructing
+/// portfolios without a query per account
+#[post("/balances_at")]
+async fn get_balances_at(
+    body: web::Json<BalancesAtParams>,
+    query_config: web::Data<QueryConfig>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+) -> impl Responder {
+    let BalancesAtParams { accounts, block_no } = body.into_inner();
+    if accounts.is_empty() || accounts.len() > MAX_BALANCE_ACCOUNTS {
+        return error_response(&AggError::BadRequest(format!(
+            "accounts must hold between 1 and {} public keys",
+            MAX_BALANCE_ACCOUNTS
+        )));
+    }
+    // Checked before the db is asked, so a bad key is named instead of failing the whole scan
+    for account in &accounts {
+        if let Err(err) = AccountId::try_from(account.clone()) {
+            return error_response(&AggError::BadRequest(err));
+        }
+    }
+    let response = query_db(&sender, &query_config, |reply| {
+        ProtocolMessage::FetchBalancesAt(accounts, block_no, reply)
+    })
+    .await;
+    match response {
+        Ok(Response::BalancesAt(balances)) => HttpResponse::Ok().json(balances),
+        Ok(_) => unexpected_reply(),
+        Err(response) => response,
+    }
+}
+
+#[get("/balance_history/{account_id}")]
+async fn get_balance_history(
+    account_id: web::Path<AccountId>,
+    query: web::Query<BalanceHistoryParams>,
+    query_config: web::Data<QueryConfig>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+) -> impl Responder {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_SLOT_LIMIT)
+        .clamp(1, MAX_SLOT_LIMIT);
+    let (start_slot, end_slot) = match (query.from, query.to) {
+        (None, None) => (
+            query.start_slot.unwrap_or_default(),
+            query.end_slot.unwrap_or(u64::MAX),
+        ),
+        _ if query.start_slot.is_some() || query.end_slot.is_some() => {
+            return error_response(&AggError::BadRequest(
+                "Bound the history with either slots or timestamps, not both".to_string(),
+            ));
+        }
+        (from, to) => {
+            let from = from.map_or(0, |from| from.unix_secs());
+            let to = to.map_or(u64::MAX, |to| to.unix_secs());
+            if from > to {
+                return error_response(&AggError::BadRequest("from must be <= to".to_string()));
+            }
+            match resolve_time_range(from, to, &query_config, &sender).await {
+                Ok(Some(range)) => (range.start_slot, range.end_slot),
+                Ok(None) => return HttpResponse::Ok().json(Vec::<()>::new()),
+                Err(response) => return response,
+            }
+        }
+    };
+    let response = query_db(&sender, &query_config, |reply| {
+        ProtocolMessage::FetchBalanceHistory(
+            account_id.into_inner().into_string(),
+            start_slot,
+            end_slot,
+            limit,
+            reply,
+        )
+    })
+    .await;
+    match response {
+        Ok(Response::BalanceHistory(history)) => HttpResponse::Ok().json(history),
+        Ok(_) => unexpected_reply(),
+        Err(response) => response,
+    }
+}
+
+#[get("/indexed_slots")]
+async fn get_indexed_slots(
+    query: web::Query<SlotRangeParams>,
+    query_config: web::Data<QueryConfig>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+) -> impl Responder {
+    let query = query.into_inner();
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_SLOT_LIMIT)
+        .clamp(1, MAX_SLOT_LIMIT);
+    let end = query
+        .end
+        .unwrap_or_else(|| query.start.saturating_add(limit - 1));
+    let response = query_db(&sender, &query_config, |reply| {
+        ProtocolMessage::FetchIndexedSlots(query.start, end, limit, reply)
+    })
+    .await;
+    match response {
+        Ok(Response::IndexedSlots(slots)) => HttpResponse::Ok().json(slots),
+        Ok(_) => unexpected_reply(),
+        Err(response) => response,
+    }
+}
+
+#[get("/token_holders/{mint}")]
+async fn get_token_holders(
+    mint: web::Path<AccountId>,
     query: web::Query<QueryParams>,
+    query_config: web::Data<QueryConfig>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+) -> impl Responder {
+    let response = query_db(&sender, &query_config, |reply| {
+        ProtocolMessage::FetchTokenHolders(
+            mint.into_inner().into_string(),
+            query.into_inner().block_no,
+            reply,
+        )
+    })
+    .await;
+    match response {
+        Ok(Response::TokenHolders(holders)) => HttpResponse::Ok().json(holders),
+        Ok(_) => unexpected_reply(),
+        Err(response) => response,
+    }
+}
+
+#[get("/token_balance/{owner}/{mint}")]
+async fn get_token_balance(
+    path: web::Path<(AccountId, AccountId)>,
+    query_config: web::Data<QueryConfig>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+) -> impl Responder {
+    let (owner, mint) = path.into_inner();
+    let response = query_db(&sender, &query_config, |reply| {
+        ProtocolMessage::FetchTokenBalance(owner.into_string(), mint.into_string(), reply)
+    })
+    .await;
+    match response {
+        Ok(Response::TokenBalance(balance)) => HttpResponse::Ok().json(balance),
+        Ok(_) => unexpected_reply(),
+        Err(response) => response,
+    }
+}
+
+#[get("/token_balances/{owner}")]
+async fn get_token_balances(
+    owner: web::Path<AccountId>,
+    query_config: web::Data<QueryConfig>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+) -> impl Responder {
+    let response = query_db(&sender, &query_config, |reply| {
+        ProtocolMessage::FetchTokenBalances(owner.into_inner().into_string(), reply)
+    })
+    .await;
+    match response {
+        Ok(Response::TokenBalances(balances)) => HttpResponse::Ok().json(balances),
+        Ok(_) => unexpected_reply(),
+        Err(response) => response,
+    }
+}
+
+#[get("/account_txs/{account_id}")]
+async fn get_account_transactions(
+    account_id: web::Path<AccountId>,
+    query: web::Query<CursorParams>,
+    commitment: web::Query<CommitmentParams>,
+    finality: web::Data<Finality>,
+    query_config: web::Data<QueryConfig>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+) -> impl Responder {
+    let query = query.into_inner();
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_TX_LIMIT)
+        .clamp(1, MAX_TX_LIMIT);
+    let response = query_db(&sender, &query_config, |reply| {
+        ProtocolMessage::FetchAccountTransactions(
+            account_id.into_inner().into_string(),
+            query.cursor,
+            limit,
+            reply,
+        )
+    })
+    .await;
+    match response {
+        Ok(Response::AccountTransactions(mut page)) => {
+            // The cursor still points past the transactions left out
+            page.transactions
+                .retain(|tx| finality.admits(commitment.commitment, Some(tx.slot)));
+            HttpResponse::Ok().json(page)
+        }
+        Ok(_) => unexpected_reply(),
+        Err(response) => response,
+    }
+}
+
+#[get("/account_summary/{account_id}")]
+async fn get_account_summary(
+    account_id: web::Path<AccountId>,
+    query_config: web::Data<QueryConfig>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+) -> impl Responder {
+    let response = query_db(&sender, &query_config, |reply| {
+        ProtocolMessage::FetchAccountSummary(account_id.into_inner().into_string(), reply)
+    })
+    .await;
+    match response {
+        Ok(Response::AccountSummary(summary)) => HttpResponse::Ok().json(summary),
+        Ok(_) => unexpected_reply(),
+        Err(response) => response,
+    }
+}
+
+#[get("/latest_blocks")]
+async fn get_latest_block_summaries(
+    query: web::Query<LimitParams>,
+    commitment: web::Query<CommitmentParams>,
+    finality: web::Data<Finality>,
+    query_config: web::Data<QueryConfig>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+) -> impl Responder {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_SUMMARY_LIMIT)
+        .clamp(1, MAX_SUMMARY_LIMIT);
+    let response = query_db(&sender, &query_config, |reply| {
+        ProtocolMessage::FetchLatestBlockSummaries(limit, reply)
+    })
+    .await;
+    match response {
+        Ok(Response::BlockSummaries(mut summaries)) => {
+            summaries.retain(|summary| finality.admits(commitment.commitment, summary.slot));
+            HttpResponse::Ok().json(summaries)
+        }
+        Ok(_) => unexpected_reply(),
+        Err(response) => response,
+    }
+}
+
+#[get("/status")]
+async fn get_status(
+    query_config: web::Data<QueryConfig>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+) -> impl Responder {
+    let response = query_db(&sender, &query_config, ProtocolMessage::FetchStatus).await;
+    match response {
+        Ok(Response::Status(status)) => HttpResponse::Ok().json(status),
+        Ok(_) => unexpected_reply(),
+        Err(response) => response,
+    }
+}
+
+#[get("/ready")]
+async fn get_ready(readiness: web::Data<Readiness>) -> impl Responder {
+    let ready = readiness.is_ready();
+    let body = serde_json::json!({ "ready": ready });
+    if ready {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
+
+#[get("/stats/tps")]
+async fn get_tps_stats(
+    query_config: web::Data<QueryConfig>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+) -> impl Responder {
+    let response = query_db(&sender, &query_config, ProtocolMessage::FetchTpsStats).await;
+    match response {
+        Ok(Response::TpsStats(stats)) => HttpResponse::Ok().json(stats),
+        Ok(_) => unexpected_reply(),
+        Err(response) => response,
+    }
+}
+
+#[get("/custom_stats/{rule}")]
+async fn get_custom_stats(
+    rule: web::Path<String>,
+    query_config: web::Data<QueryConfig>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+) -> impl Responder {
+    let response = query_db(&sender, &query_config, |reply| {
+        ProtocolMessage::FetchCustomStats(rule.into_inner(), reply)
+    })
+    .await;
+    match response {
+        Ok(Response::CustomStats(stats)) => HttpResponse::Ok().json(stats),
+        Ok(_) => unexpected_reply(),
+        Err(response) => response,
+    }
+}
+
+#[get("/active_accounts")]
+async fn get_active_accounts(
+    query: web::Query<DaysParams>,
+    query_config: web::Data<QueryConfig>,
     sender: web::Data<UnboundedSender<ProtocolMessage>>,
 ) -> impl Responder {
-    let mut channel = Channel::<ProtocolMessage>::new();
-    if let Err(error) = sender.send(ProtocolMessage::FetchAccountBalance(
-        account_id.into_inner(),
-        query.into_inner().block_no,
-        channel.sender(),
+    let days = query
+        .days
+        .unwrap_or(DEFAULT_ACTIVE_ACCOUNT_DAYS)
+        .clamp(1, MAX_ACTIVE_ACCOUNT_DAYS);
+    let response = query_db(&sender, &query_config, |reply| {
+        ProtocolMessage::FetchActiveAccounts(days, reply)
+    })
+    .await;
+    match response {
+        Ok(Response::ActiveAccounts(stats)) => HttpResponse::Ok().json(stats),
+        Ok(_) => unexpected_reply(),
+        Err(response) => response,
+    }
+}
+
+#[get("/metrics")]
+async fn get_metrics() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics::render())
+}
+
+#[get("/block_digest/{block_no}")]
+async fn get_block_digest(
+    block_no: web::Path<u64>,
+    query_config: web::Data<QueryConfig>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+) -> impl Responder {
+    let response = query_db(&sender, &query_config, |reply| {
+        ProtocolMessage::FetchBlockDigest(block_no.into_inner(), reply)
+    })
+    .await;
+    match response {
+        Ok(Response::BlockDigest(block_no, digest)) => {
+            HttpResponse::Ok().json(BlockDigest { block_no, digest })
+        }
+        Ok(_) => unexpected_reply(),
+        Err(response) => response,
+    }
+}
+
+#[get("/block_stream")]
+async fn block_stream(
+    request: HttpRequest,
+    body: web::Payload,
+    query: web::Query<BlockStreamParams>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+) -> actix_web::Result<HttpResponse> {
+    let (response, mut session, mut incoming) = actix_ws::handle(&request, body)?;
+    let query = query.into_inner();
+    let (subscriber, mut events) = fanout::subscriber_channel();
+    if let Err(error) = sender.send(ProtocolMessage::SubscribeBlocks(
+        query.block_no,
+        query.filter(),
+        subscriber,
     )) {
-        return HttpResponse::InternalServerError().json(error.to_string());
+        return Ok(error_response(&AggError::from(error)));
     }
-    match channel.receiver.recv().await {
-        Some(ProtocolMessage::AccountBalance(balance)) => HttpResponse::Ok().json(balance),
-        _ => HttpResponse::InternalServerError().finish(),
+    actix_web::rt::spawn(async move {
+        let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+        loop {
+            let outgoing = tokio::select! {
+                message = events.recv() => match message {
+                    Some(ProtocolMessage::NewBlock(block_no, block)) => {
+                        ReplicationMessage::Block(block_no, Box::new(block))
+                    }
+                    _ => break,
+                },
+                _ = heartbeat.tick() => ReplicationMessage::Heartbeat,
+                message = incoming.recv() => match message {
+                    Some(Ok(Message::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                },
+            };
+            match serde_json::to_string(&outgoing) {
+                Ok(text) => {
+                    if session.text(text).await.is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+        let _ = session.close(None).await;
+    });
+    Ok(response)
+}
+
+#[get("/account_stream/{account_id}")]
+async fn account_stream(
+    request: HttpRequest,
+    body: web::Payload,
+    account_id: web::Path<AccountId>,
+    query: web::Query<AccountStreamParams>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+) -> actix_web::Result<HttpResponse> {
+    let (response, mut session, mut incoming) = actix_ws::handle(&request, body)?;
+    let query = query.into_inner();
+    let resume_token = query.resume_token;
+    let (subscriber, mut events) = fanout::subscriber_channel();
+    if let Err(error) = sender.send(ProtocolMessage::SubscribeAccount(
+        account_id.into_inner().into_string(),
+        query.from_slot,
+        resume_token.clone(),
+        subscriber,
+    )) {
+        return Ok(error_response(&AggError::from(error)));
     }
+    actix_web::rt::spawn(async move {
+        let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+        loop {
+            tokio::select! {
+                message = events.recv() => match message {
+                    Some(ProtocolMessage::AccountEvent(event)) => {
+                        let Ok(text) = serde_json::to_string(&event) else {
+                            break;
+                        };
+                        if session.text(text).await.is_err() {
+                            break;
+                        }
+                        if let Some(token) = &resume_token {
+                            let _ = sender.send(ProtocolMessage::AckResumeCursor(
+                                token.clone(),
+                                event.slot,
+                            ));
+                        }
+                    }
+                    _ => break,
+                },
+                _ = heartbeat.tick() => {
+                    if session.ping(b"").await.is_err() {
+                        break;
+                    }
+                }
+                message = incoming.recv() => match message {
+                    Some(Ok(Message::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            break;
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+        let _ = session.close(None).await;
+    });
+    Ok(response)
+}
+
+#[get("/admin/usage")]
+async fn get_tenant_usage(
+    request: HttpRequest,
+    admin_key: web::Data<AdminKey>,
+    tenants: web::Data<TenantRegistry>,
+) -> impl Responder {
+    if !is_admin(&request, &admin_key) {
+        return not_admin();
+    }
+    HttpResponse::Ok().json(tenants.usage())
+}
+
+#[post("/admin/compact")]
+async fn compact(
+    request: HttpRequest,
+    query: web::Query<CompactParams>,
+    admin_key: web::Data<AdminKey>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+    query_config: web::Data<QueryConfig>,
+) -> impl Responder {
+    if !is_admin(&request, &admin_key) {
+        return not_admin();
+    }
+    let range = match (query.start, query.end) {
+        (Some(start), Some(end)) if start <= end => Some((start, end)),
+        (None, None) => None,
+        _ => {
+            return error_response(&AggError::BadRequest(
+                "Both start and end are required, start <= end".to_string(),
+            ))
+        }
+    };
+    let response = ask_db(&sender, &query_config, |reply| {
+        ProtocolMessage::Compact(range, reply)
+    })
+    .await;
+    match response {
+        Ok(Response::CompactionStats(stats)) => HttpResponse::Ok().json(stats),
+        Ok(_) => unexpected_reply(),
+        Err(err) => error_response(&err),
+    }
+}
+
+#[post("/admin/delete_slots")]
+async fn delete_slots(
+    request: HttpRequest,
+    query: web::Query<DeleteSlotsParams>,
+    admin_key: web::Data<AdminKey>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+    query_config: web::Data<QueryConfig>,
+) -> impl Responder {
+    if !is_admin(&request, &admin_key) {
+        return not_admin();
+    }
+    if query.start > query.end || query.end - query.start >= MAX_SLOT_LIMIT {
+        return error_response(&AggError::BadRequest(format!(
+            "start must be <= end and at most {} slots can be deleted at once",
+            MAX_SLOT_LIMIT
+        )));
+    }
+    let response = ask_db(&sender, &query_config, |reply| {
+        ProtocolMessage::DeleteSlotRange(query.start, query.end, reply)
+    })
+    .await;
+    match response {
+        Ok(Response::DeletedSlots(deleted)) => HttpResponse::Ok().json(deleted),
+        Ok(_) => unexpected_reply(),
+        Err(err) => error_response(&err),
+    }
+}
+
+#[post("/admin/refetch/{slot}")]
+async fn refetch_block(
+    request: HttpRequest,
+    slot: web::Path<u64>,
+    admin_key: web::Data<AdminKey>,
+    node_config: web::Data<NodeConfig>,
+    upstream: web::Data<Upstream>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+    query_config: web::Data<QueryConfig>,
+) -> impl Responder {
+    if !is_admin(&request, &admin_key) {
+        return not_admin();
+    }
+    let Some(endpoints) = &upstream.endpoints else {
+        return error_response(&AggError::ConfigError(
+            "node.chain_url holds no url to fetch blocks from".to_string(),
+        ));
+    };
+    // The endpoints fail over and cool down like for the subscriber, the request is bounded
+    // like a query of the db
+    let fetched = upstream
+        .breaker
+        .call(
+            query_config.request_timeout(),
+            block_importer::fetch_raw_block(endpoints, node_config.commitment, slot.into_inner()),
+        )
+        .await;
+    let (block_no, raw_block) = match fetched {
+        Ok(fetched) => fetched,
+        Err(err) => return error_response(&err),
+    };
+    let response = ask_db(&sender, &query_config, |reply| {
+        ProtocolMessage::RefetchBlock(block_no, Box::new(raw_block), reply)
+    })
+    .await;
+    match response {
+        Ok(Response::RefetchedBlock(refetched)) => HttpResponse::Ok().json(refetched),
+        Ok(_) => unexpected_reply(),
+        Err(err) => error_response(&err),
+    }
+}
+
+#[post("/annotations/{slot}")]
+async fn annotate_slot(
+    request: HttpRequest,
+    slot: web::Path<u64>,
+    body: web::Json<AnnotationParams>,
+    admin_key: web::Data<AdminKey>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+    query_config: web::Data<QueryConfig>,
+) -> impl Responder {
+    if !is_admin(&request, &admin_key) {
+        return not_admin();
+    }
+    let note = body.into_inner().note.trim().to_string();
+    if note.is_empty() || note.len() > MAX_ANNOTATION_LEN {
+        return error_response(&AggError::BadRequest(format!(
+            "note must be non-empty and at most {} bytes",
+            MAX_ANNOTATION_LEN
+        )));
+    }
+    let slot = slot.into_inner();
+    let response = ask_db(&sender, &query_config, |reply| {
+        ProtocolMessage::Annotate(slot, note, reply)
+    })
+    .await;
+    match response {
+        Ok(Response::Annotations(annotations)) => HttpResponse::Created().json(annotations),
+        Ok(_) => unexpected_reply(),
+        Err(err) => error_response(&err),
+    }
+}
+
+#[post("/admin/reparse")]
+async fn reparse(
+    request: HttpRequest,
+    query: web::Query<ReparseParams>,
+    admin_key: web::Data<AdminKey>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+    query_config: web::Data<QueryConfig>,
+) -> impl Responder {
+    if !is_admin(&request, &admin_key) {
+        return not_admin();
+    }
+    if query.start > query.end {
+        return error_response(&AggError::BadRequest("start must be <= end".to_string()));
+    }
+    start_db_job(
+        &sender,
+        &query_config,
+        JobTask::Reindex(ReparseProgress::new(query.start, query.end)),
+    )
+    .await
+}
+
+#[get("/admin/reparse")]
+async fn get_reparse_progress(
+    request: HttpRequest,
+    admin_key: web::Data<AdminKey>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+    query_config: web::Data<QueryConfig>,
+) -> impl Responder {
+    if !is_admin(&request, &admin_key) {
+        return not_admin();
+    }
+    let response = ask_db(&sender, &query_config, ProtocolMessage::FetchJobs).await;
+    match response {
+        Ok(Response::Jobs(jobs)) => match jobs
+            .into_iter()
+            .rev()
+            .find(|job| matches!(job.task, JobTask::Reindex(_)))
+        {
+            Some(job) => HttpResponse::Ok().json(job),
+            None => error_response(&AggError::NotFound("re-parse job".to_string())),
+        },
+        Ok(_) => unexpected_reply(),
+        Err(err) => error_response(&err),
+    }
+}
+
+#[post("/admin/jobs")]
+async fn start_job(
+    request: HttpRequest,
+    query: web::Query<JobParams>,
+    admin_key: web::Data<AdminKey>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+    query_config: web::Data<QueryConfig>,
+    exporter: web::Data<Exporter>,
+) -> impl Responder {
+    if !is_admin(&request, &admin_key) {
+        return not_admin();
+    }
+    let JobParams {
+        kind,
+        start,
+        end,
+        format,
+        shards,
+    } = query.into_inner();
+    if start > end {
+        return error_response(&AggError::BadRequest("start must be <= end".to_string()));
+    }
+    match kind {
+        JobKind::Reindex => {
+            start_db_job(
+                &sender,
+                &query_config,
+                JobTask::Reindex(ReparseProgress::new(start, end)),
+            )
+            .await
+        }
+        JobKind::Prune => {
+            start_db_job(
+                &sender,
+                &query_config,
+                JobTask::Prune(PruneProgress::new(start, end)),
+            )
+            .await
+        }
+        JobKind::Export => {
+            let Some(format) = format else {
+                return error_response(&AggError::BadRequest(
+                    "format is required for export jobs".to_string(),
+                ));
+            };
+            let params = ExportParams {
+                start_slot: start,
+                end_slot: end,
+                format,
+                shards,
+            };
+            match exporter.start(params).await {
+                Ok(export) => HttpResponse::Accepted().json(export),
+                Err(err) => error_response(&err),
+            }
+        }
+    }
+}
+
+#[get("/admin/jobs")]
+async fn get_jobs(
+    request: HttpRequest,
+    admin_key: web::Data<AdminKey>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+    query_config: web::Data<QueryConfig>,
+) -> impl Responder {
+    if !is_admin(&request, &admin_key) {
+        return not_admin();
+    }
+    let response = ask_db(&sender, &query_config, ProtocolMessage::FetchJobs).await;
+    match response {
+        Ok(Response::Jobs(jobs)) => HttpResponse::Ok().json(jobs),
+        Ok(_) => unexpected_reply(),
+        Err(err) => error_response(&err),
+    }
+}
+
+#[get("/admin/jobs/{id}")]
+async fn get_job(
+    request: HttpRequest,
+    admin_key: web::Data<AdminKey>,
+    id: web::Path<u64>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+    query_config: web::Data<QueryConfig>,
+) -> impl Responder {
+    if !is_admin(&request, &admin_key) {
+        return not_admin();
+    }
+    let response = ask_db(&sender, &query_config, |reply| {
+        ProtocolMessage::FetchJob(id.into_inner(), reply)
+    })
+    .await;
+    match response {
+        Ok(Response::Job(Some(job))) => HttpResponse::Ok().json(job),
+        Ok(Response::Job(None)) => error_response(&AggError::NotFound("job".to_string())),
+        Ok(_) => unexpected_reply(),
+        Err(err) => error_response(&err),
+    }
+}
+
+#[post("/admin/jobs/{id}/cancel")]
+async fn cancel_job(
+    request: HttpRequest,
+    admin_key: web::Data<AdminKey>,
+    id: web::Path<u64>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+    query_config: web::Data<QueryConfig>,
+    exporter: web::Data<Exporter>,
+) -> impl Responder {
+    if !is_admin(&request, &admin_key) {
+        return not_admin();
+    }
+    let id = id.into_inner();
+    let response = ask_db(&sender, &query_config, |reply| {
+        ProtocolMessage::CancelJob(id, reply)
+    })
+    .await;
+    match response {
+        Ok(Response::Job(Some(job))) if job.state == JobState::Cancelled => {
+            if let JobTask::Export(_) = job.task {
+                exporter.cancel(id);
+            }
+            HttpResponse::Ok().json(job)
+        }
+        Ok(Response::Job(Some(job))) => error_response(&AggError::JobConflict(format!(
+            "Job {} is not running",
+            job.id
+        ))),
+        Ok(Response::Job(None)) => error_response(&AggError::NotFound("job".to_string())),
+        Ok(_) => unexpected_reply(),
+        Err(err) => error_response(&err),
+    }
+}
+
+/// This function starts a job run by the db
+///
+/// # Arguments
+///
+/// * `sender` - A UnboundedSender<ProtocolMessage> that holds the handler sender
+/// * `query_config` - A QueryConfig that holds the request timeout
+/// * `task` - A JobTask that holds the work of the job
+///
+/// # Returns
+///
+/// * `HttpResponse` - The started job
+async fn start_db_job(
+    sender: &UnboundedSender<ProtocolMessage>,
+    query_config: &QueryConfig,
+    task: JobTask,
+) -> HttpResponse {
+    let response = ask_db(sender, query_config, |reply| {
+        ProtocolMessage::StartJob(task, reply)
+    })
+    .await;
+    match response {
+        Ok(Response::Job(Some(job))) => HttpResponse::Accepted().json(job),
+        Ok(_) => unexpected_reply(),
+        Err(err) => error_response(&err),
+    }
+}
+
+#[post("/admin/exports")]
+async fn start_export(
+    request: HttpRequest,
+    admin_key: web::Data<AdminKey>,
+    params: web::Query<ExportParams>,
+    exporter: web::Data<Exporter>,
+) -> impl Responder {
+    if !is_admin(&request, &admin_key) {
+        return not_admin();
+    }
+    match exporter.start(params.into_inner()).await {
+        Ok(job) => HttpResponse::Accepted().json(job),
+        Err(err) => error_response(&err),
+    }
+}
+
+#[get("/admin/exports")]
+async fn get_exports(
+    request: HttpRequest,
+    admin_key: web::Data<AdminKey>,
+    exporter: web::Data<Exporter>,
+) -> impl Responder {
+    if !is_admin(&request, &admin_key) {
+        return not_admin();
+    }
+    HttpResponse::Ok().json(exporter.jobs())
+}
+
+#[get("/admin/exports/{id}")]
+async fn get_export(
+    request: HttpRequest,
+    admin_key: web::Data<AdminKey>,
+    id: web::Path<u64>,
+    exporter: web::Data<Exporter>,
+) -> impl Responder {
+    if !is_admin(&request, &admin_key) {
+        return not_admin();
+    }
+    match exporter.job(id.into_inner()) {
+        Some(job) => HttpResponse::Ok().json(job),
+        None => error_response(&AggError::NotFound("export".to_string())),
+    }
+}
+
+#[post("/admin/exports/{id}/resume")]
+async fn resume_export(
+    request: HttpRequest,
+    admin_key: web::Data<AdminKey>,
+    id: web::Path<u64>,
+    exporter: web::Data<Exporter>,
+) -> impl Responder {
+    if !is_admin(&request, &admin_key) {
+        return not_admin();
+    }
+    match exporter.resume(id.into_inner()) {
+        Ok(Some(job)) => HttpResponse::Accepted().json(job),
+        Ok(None) => error_response(&AggError::NotFound("export".to_string())),
+        Err(err) => error_response(&err),
+    }
+}
+
+#[get("/admin/log_levels")]
+async fn get_log_levels(request: HttpRequest, admin_key: web::Data<AdminKey>) -> impl Responder {
+    if !is_admin(&request, &admin_key) {
+        return not_admin();
+    }
+    HttpResponse::Ok().json(logging::levels())
+}
+
+#[post("/admin/log_levels")]
+async fn set_log_level(
+    request: HttpRequest,
+    admin_key: web::Data<AdminKey>,
+    params: web::Query<LogLevelParams>,
+) -> impl Responder {
+    if !is_admin(&request, &admin_key) {
+        return not_admin();
+    }
+    let level = match logging::parse_level(&params.level) {
+        Ok(level) => level,
+        Err(err) => return error_response(&AggError::BadRequest(err.to_string())),
+    };
+    let target = params.target.as_deref().filter(|target| !target.is_empty());
+    info!(target: "server", "Setting the log level of {} to {}", target.unwrap_or("every target"), level);
+    logging::set_level(target, level);
+    HttpResponse::Ok().json(logging::levels())
+}
+
+#[delete("/admin/log_levels/{target}")]
+async fn reset_log_level(
+    request: HttpRequest,
+    admin_key: web::Data<AdminKey>,
+    target: web::Path<String>,
+) -> impl Responder {
+    if !is_admin(&request, &admin_key) {
+        return not_admin();
+    }
+    if !logging::reset_level(&target) {
+        return error_response(&AggError::NotFound("log level of the target".to_string()));
+    }
+    HttpResponse::Ok().json(logging::levels())
+}
+
+#[post("/admin/webhooks")]
+async fn create_webhook(
+    request: HttpRequest,
+    query: web::Query<WebhookParams>,
+    admin_key: web::Data<AdminKey>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+    query_config: web::Data<QueryConfig>,
+) -> impl Responder {
+    if !is_admin(&request, &admin_key) {
+        return not_admin();
+    }
+    let query = query.into_inner();
+    if !query.url.starts_with("http://") && !query.url.starts_with("https://") {
+        return error_response(&AggError::BadRequest(
+            "url must be an http or https url".to_string(),
+        ));
+    }
+    let response = ask_db(&sender, &query_config, |reply| {
+        ProtocolMessage::CreateWebhook(
+            query.account.into_string(),
+            query.url,
+            query.ttl_secs,
+            reply,
+        )
+    })
+    .await;
+    match response {
+        Ok(Response::Webhook(Some(webhook))) => HttpResponse::Created().json(webhook),
+        Ok(_) => unexpected_reply(),
+        Err(err) => error_response(&err),
+    }
+}
+
+#[delete("/admin/webhooks/{id}")]
+async fn delete_webhook(
+    request: HttpRequest,
+    id: web::Path<u64>,
+    admin_key: web::Data<AdminKey>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+    query_config: web::Data<QueryConfig>,
+) -> impl Responder {
+    if !is_admin(&request, &admin_key) {
+        return not_admin();
+    }
+    let response = ask_db(&sender, &query_config, |reply| {
+        ProtocolMessage::DeleteWebhook(id.into_inner(), reply)
+    })
+    .await;
+    match response {
+        Ok(Response::Webhook(Some(webhook))) => HttpResponse::Ok().json(webhook),
+        Ok(Response::Webhook(None)) => error_response(&AggError::NotFound("webhook".to_string())),
+        Ok(_) => unexpected_reply(),
+        Err(err) => error_response(&err),
+    }
+}
+
+#[get("/admin/webhooks/{id}/deliveries")]
+async fn get_webhook_deliveries(
+    request: HttpRequest,
+    id: web::Path<u64>,
+    query: web::Query<LimitParams>,
+    admin_key: web::Data<AdminKey>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+    query_config: web::Data<QueryConfig>,
+) -> impl Responder {
+    if !is_admin(&request, &admin_key) {
+        return not_admin();
+    }
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_DELIVERY_LIMIT)
+        .min(MAX_DELIVERY_LIMIT);
+    let response = ask_db(&sender, &query_config, |reply| {
+        ProtocolMessage::FetchWebhookDeliveries(id.into_inner(), limit, reply)
+    })
+    .await;
+    match response {
+        Ok(Response::WebhookDeliveries(deliveries)) => HttpResponse::Ok().json(deliveries),
+        Ok(_) => unexpected_reply(),
+        Err(err) => error_response(&err),
+    }
+}
+
+#[get("/admin/dead_letters")]
+async fn get_dead_letters(
+    request: HttpRequest,
+    query: web::Query<LimitParams>,
+    admin_key: web::Data<AdminKey>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+    query_config: web::Data<QueryConfig>,
+) -> impl Responder {
+    if !is_admin(&request, &admin_key) {
+        return not_admin();
+    }
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_DELIVERY_LIMIT)
+        .min(MAX_DELIVERY_LIMIT);
+    let response = ask_db(&sender, &query_config, |reply| {
+        ProtocolMessage::FetchDeadLetters(limit, reply)
+    })
+    .await;
+    match response {
+        Ok(Response::DeadLetters(letters)) => HttpResponse::Ok().json(letters),
+        Ok(_) => unexpected_reply(),
+        Err(err) => error_response(&err),
+    }
+}
+
+#[get("/admin/subscriptions")]
+async fn get_subscriptions(
+    request: HttpRequest,
+    admin_key: web::Data<AdminKey>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+    query_config: web::Data<QueryConfig>,
+) -> impl Responder {
+    if !is_admin(&request, &admin_key) {
+        return not_admin();
+    }
+    let response = ask_db(&sender, &query_config, ProtocolMessage::FetchSubscriptions).await;
+    match response {
+        Ok(Response::Subscriptions(subscriptions)) => HttpResponse::Ok().json(subscriptions),
+        Ok(_) => unexpected_reply(),
+        Err(err) => error_response(&err),
+    }
+}
+
+/// This function sends a query to the handler with the request deadline attached, so the db
+/// drops it instead of answering once nobody is waiting for the response, and waits for the
+/// response until the deadline
+///
+/// # Arguments
+///
+/// * `sender` - A UnboundedSender<ProtocolMessage> that holds the handler sender
+/// * `query_config` - A QueryConfig that holds the request timeout
+/// * `query` - A FnOnce(Reply) -> ProtocolMessage that builds the query around its reply sender
+///
+/// # Returns
+///
+/// * `Option<Result<Response, AggError>>` - The response or the error the query failed with,
+///   None when no response came before the deadline
+pub(crate) async fn send_query(
+    sender: &UnboundedSender<ProtocolMessage>,
+    query_config: &QueryConfig,
+    query: impl FnOnce(Reply) -> ProtocolMessage,
+) -> Option<Result<Response, AggError>> {
+    let (reply, response) = oneshot::channel();
+    let deadline = Instant::now() + query_config.request_timeout();
+    if let Err(err) = sender.send(ProtocolMessage::Deadline(deadline, Box::new(query(reply)))) {
+        return Some(Err(err.into()));
+    }
+    // The reply sender is dropped with a query past its deadline or a stopped db, ending the wait
+    match tokio::time::timeout(query_config.request_timeout(), response).await {
+        Ok(Ok(Response::Error(err))) => Some(Err(err)),
+        Ok(Ok(response)) => Some(Ok(response)),
+        Ok(Err(_)) | Err(_) => None,
+    }
+}
+
+/// This function sends an admin command to the db and waits for its response up to the request
+/// timeout. Unlike a query, the command is not dropped once the request gave up on it, the db
+/// still carries out a command it received.
+///
+/// # Arguments
+///
+/// * `sender` - A UnboundedSender<ProtocolMessage> that holds the handler sender
+/// * `query_config` - A QueryConfig that holds the request timeout
+/// * `command` - A FnOnce(Reply) -> ProtocolMessage that builds the command around its reply
+///   sender
+///
+/// # Returns
+///
+/// * `Result<Response, AggError>` - The response, the error the db answered with or
+///   QueryTimedOut when no response came within the request timeout
+async fn ask_db(
+    sender: &UnboundedSender<ProtocolMessage>,
+    query_config: &QueryConfig,
+    command: impl FnOnce(Reply) -> ProtocolMessage,
+) -> Result<Response, AggError> {
+    tokio::time::timeout(
+        query_config.request_timeout(),
+        ProtocolMessage::ask(sender, command),
+    )
+    .await
+    .unwrap_or(Err(AggError::QueryTimedOut))
+}
+
+/// This function answers a query of the api through the db
+///
+/// # Arguments
+///
+/// * `sender` - A UnboundedSender<ProtocolMessage> that holds the handler sender
+/// * `query_config` - A QueryConfig that holds the request timeout
+/// * `query` - A FnOnce(Reply) -> ProtocolMessage that builds the query around its reply sender
+///
+/// # Returns
+///
+/// * `Result<Response, HttpResponse>` - The response or the response to fail the request with
+async fn query_db(
+    sender: &UnboundedSender<ProtocolMessage>,
+    query_config: &QueryConfig,
+    query: impl FnOnce(Reply) -> ProtocolMessage,
+) -> Result<Response, HttpResponse> {
+    match send_query(sender, query_config, query).await {
+        Some(Ok(response)) => Ok(response),
+        Some(Err(err)) => Err(error_response(&err)),
+        None => Err(error_response(&AggError::QueryTimedOut)),
+    }
+}
+
+/// This function translates a time window to the stored slots and blocks inside it
+///
+/// # Arguments
+///
+/// * `start_time` - A u64 that holds the start of the window in unix seconds
+/// * `end_time` - A u64 that holds the end of the window in unix seconds
+/// * `query_config` - A QueryConfig that holds the request timeout
+/// * `sender` - A UnboundedSender<ProtocolMessage> that holds the handler sender
+///
+/// # Returns
+///
+/// * `Result<Option<TimeRange>, HttpResponse>` - The range, None when no stored block falls
+///   inside the window, or the response to fail the request with
+async fn resolve_time_range(
+    start_time: u64,
+    end_time: u64,
+    query_config: &QueryConfig,
+    sender: &UnboundedSender<ProtocolMessage>,
+) -> Result<Option<TimeRange>, HttpResponse> {
+    let response = query_db(sender, query_config, |reply| {
+        ProtocolMessage::FetchTimeRange(start_time, end_time, reply)
+    })
+    .await;
+    match response? {
+        Response::TimeRange(range) => Ok(range),
+        _ => Err(unexpected_reply()),
+    }
+}
+
+/// This function answers a request failing with an error with the status of the error, e.g. 422
+/// for a query reading too many blocks and 413 for a response that would be too large, and its
+/// code and message in an ErrorBody
+pub(crate) fn error_response(err: &AggError) -> HttpResponse {
+    err.error_response()
+}
+
+/// This function answers an admin route requested without the admin api key
+pub(crate) fn not_admin() -> HttpResponse {
+    error_response(&AggError::Unauthorized(
+        "missing or invalid admin api key".to_string(),
+    ))
+}
+
+/// This function answers a request the db answered with a response of another query
+fn unexpected_reply() -> HttpResponse {
+    error_response(&AggError::UnexpectedReply(
+        "the db answered with the response of another query".to_string(),
+    ))
+}
+
+/// This function turns malformed path and query parameters and request bodies into a 400
+/// response with the reason
+fn bad_request<E: std::fmt::Display>(err: E, _: &HttpRequest) -> actix_web::Error {
+    AggError::BadRequest(err.to_string()).into()
+}
+
+pub(crate) fn is_admin(request: &HttpRequest, admin_key: &AdminKey) -> bool {
+    admin_key.accepts(request.headers())
 }
 
 // Curl Requests
@@ -0,0 +1,279 @@
+use crate::metrics;
+use futures_util::future::BoxFuture;
+use futures_util::FutureExt;
+use log::{error, info, warn};
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// Wait before a crashed worker is restarted, doubled for every crash in a row
+const INITIAL_RESTART_DELAY: Duration = Duration::from_secs(1);
+/// Longest wait before a restart, a worker running this long without crashing starts over at
+/// INITIAL_RESTART_DELAY
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+
+/// Stages of the pipeline, stopped one after the other so every stage can hand what it holds
+/// to the next one before that one stops
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ShutdownStage {
+    /// Subscriber, backfill, standby follower, webhook dispatcher and the servers, which stop
+    /// taking in new work
+    Intake,
+    /// Handler, completing the blocks whose chunks are still being parsed
+    Handler,
+    /// Fan-out, delivering the finalized blocks it holds to the sinks
+    FanOut,
+    /// Db and stats aggregator, the db stores the blocks it was handed and flushes its writes
+    Sinks,
+}
+
+impl ShutdownStage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ShutdownStage::Intake => "intake",
+            ShutdownStage::Handler => "handler",
+            ShutdownStage::FanOut => "fan_out",
+            ShutdownStage::Sinks => "sinks",
+        }
+    }
+}
+
+/// Signal telling the tasks of a stage to stop, cloned into every one of them
+#[derive(Clone, Debug)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    /// This function tells whether the stage was asked to stop
+    pub fn is_requested(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// This function waits until the stage is asked to stop
+    pub async fn requested(&mut self) {
+        while !*self.0.borrow_and_update() {
+            if self.0.changed().await.is_err() {
+                // The coordinator is gone without asking, the process is exiting anyway
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+
+    /// This function waits until the stage is asked to stop, or never completes for a task run
+    /// without a coordinator
+    pub(crate) async fn wait(shutdown: &mut Option<Shutdown>) {
+        match shutdown {
+            Some(shutdown) => shutdown.requested().await,
+            None => std::future::pending().await,
+        }
+    }
+
+    /// This function tells whether a task run with a coordinator was asked to stop
+    pub(crate) fn is_set(shutdown: &Option<Shutdown>) -> bool {
+        shutdown.as_ref().is_some_and(Shutdown::is_requested)
+    }
+}
+
+/// A long running task the coordinator restarts when it crashes. The worker stops on its own
+/// once its shutdown signal is set, a worker returning before that is restarted too.
+pub trait Worker: Send + 'static {
+    /// This function hands the worker the signal it stops on
+    fn set_shutdown(&mut self, shutdown: Shutdown);
+
+    /// This function runs the worker until it is asked to stop
+    fn work(&mut self) -> BoxFuture<'_, ()>;
+}
+
+struct Stage {
+    sender: watch::Sender<bool>,
+    tasks: Vec<(String, JoinHandle<()>)>,
+}
+
+/// Spawns and supervises the tasks of the process, and stops them stage by stage once the
+/// process is asked to stop
+pub struct ShutdownCoordinator {
+    stages: BTreeMap<ShutdownStage, Stage>,
+    /// Time the tasks of a stage have to stop before they are aborted
+    stage_timeout: Duration,
+}
+
+impl ShutdownCoordinator {
+    /// This function initializes the coordinator
+    ///
+    /// # Arguments
+    ///
+    /// * `stage_timeout` - A Duration that holds the time the tasks of a stage have to stop
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The coordinator
+    pub fn new(stage_timeout: Duration) -> Self {
+        Self {
+            stages: BTreeMap::new(),
+            stage_timeout,
+        }
+    }
+
+    fn stage(&mut self, stage: ShutdownStage) -> &mut Stage {
+        self.stages.entry(stage).or_insert_with(|| Stage {
+            sender: watch::channel(false).0,
+            tasks: Vec::new(),
+        })
+    }
+
+    /// This function returns the signal the tasks of a stage stop on
+    ///
+    /// # Arguments
+    ///
+    /// * `stage` - A ShutdownStage that holds the stage
+    ///
+    /// # Returns
+    ///
+    /// * `Shutdown` - The signal of the stage
+    pub fn signal(&mut self, stage: ShutdownStage) -> Shutdown {
+        Shutdown(self.stage(stage).sender.subscribe())
+    }
+
+    /// This function spawns a worker, restarting it with a growing delay whenever it panics or
+    /// returns before its stage is asked to stop
+    ///
+    /// # Arguments
+    ///
+    /// * `stage` - A ShutdownStage that holds the stage the worker stops in
+    /// * `name` - A string slice that holds the name the worker is logged and counted under
+    /// * `worker` - A Worker that holds the worker
+    pub fn supervise<W: Worker>(&mut self, stage: ShutdownStage, name: &str, mut worker: W) {
+        let mut shutdown = self.signal(stage);
+        worker.set_shutdown(shutdown.clone());
+        let task_name = name.to_string();
+        let handle = tokio::spawn(async move {
+            let mut delay = INITIAL_RESTART_DELAY;
+            loop {
+                let started = Instant::now();
+                let outcome = AssertUnwindSafe(worker.work()).catch_unwind().await;
+                if shutdown.is_requested() {
+                    return;
+                }
+                match outcome {
+                    Ok(()) => warn!(
+                        target: "shutdown",
+                        "Worker {} stopped, restarting it in {:?}", task_name, delay
+                    ),
+                    Err(panic) => error!(
+                        target: "shutdown",
+                        "Worker {} panicked, restarting it in {:?}: {}",
+                        task_name, delay, panic_message(&panic)
+                    ),
+                }
+                metrics::WORKER_RESTARTS
+                    .with_label_values(&[&task_name])
+                    .inc();
+                if started.elapsed() >= MAX_RESTART_DELAY {
+                    delay = INITIAL_RESTART_DELAY;
+                }
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = shutdown.requested() => return,
+                }
+                delay = delay.saturating_mul(2).min(MAX_RESTART_DELAY);
+            }
+        });
+        self.stage(stage).tasks.push((name.to_string(), handle));
+    }
+
+    /// This function spawns a task that is dropped where it is once its stage is asked to stop
+    ///
+    /// # Arguments
+    ///
+    /// * `stage` - A ShutdownStage that holds the stage the task stops in
+    /// * `name` - A string slice that holds the name the task is logged under
+    /// * `task` - A Future that holds the task
+    pub fn spawn<F>(&mut self, stage: ShutdownStage, name: &str, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let mut shutdown = self.signal(stage);
+        let handle = tokio::spawn(async move {
+            tokio::select! {
+                _ = task => {}
+                _ = shutdown.requested() => {}
+            }
+        });
+        self.track(stage, name, handle);
+    }
+
+    /// This function makes the coordinator wait for a task that stops on the signal of its
+    /// stage by itself
+    ///
+    /// # Arguments
+    ///
+    /// * `stage` - A ShutdownStage that holds the stage the task stops in
+    /// * `name` - A string slice that holds the name the task is logged under
+    /// * `handle` - A JoinHandle<()> that holds the task
+    pub fn track(&mut self, stage: ShutdownStage, name: &str, handle: JoinHandle<()>) {
+        self.stage(stage).tasks.push((name.to_string(), handle));
+    }
+
+    /// This function stops the stages in order, waiting for the tasks of a stage to stop before
+    /// the next stage is asked to. Tasks still running after the stage timeout are aborted.
+    pub async fn shutdown(self) {
+        for (stage, Stage { sender, tasks }) in self.stages {
+            info!(target: "shutdown", "Stopping the {} stage", stage.as_str());
+            let _ = sender.send(true);
+            let deadline = tokio::time::Instant::now() + self.stage_timeout;
+            for (name, mut handle) in tasks {
+                match tokio::time::timeout_at(deadline, &mut handle).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(err)) => {
+                        error!(target: "shutdown", "Task {} failed while stopping {}", name, err)
+                    }
+                    Err(_) => {
+                        warn!(
+                            target: "shutdown",
+                            "Task {} did not stop within {:?}, aborting it", name, self.stage_timeout
+                        );
+                        handle.abort();
+                    }
+                }
+            }
+        }
+        info!(target: "shutdown", "Shutdown complete");
+    }
+}
+
+/// This function waits until the process receives SIGINT or SIGTERM
+///
+/// # Returns
+///
+/// * `&'static str` - The name of the signal received
+pub async fn signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => return "SIGINT",
+                    _ = terminate.recv() => return "SIGTERM",
+                }
+            }
+            Err(err) => error!(target: "shutdown", "Unable to listen for SIGTERM {}", err),
+        }
+    }
+    if let Err(err) = tokio::signal::ctrl_c().await {
+        error!(target: "shutdown", "Unable to listen for SIGINT {}", err);
+        std::future::pending::<()>().await;
+    }
+    "SIGINT"
+}
+
+fn panic_message(panic: &Box<dyn Any + Send>) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
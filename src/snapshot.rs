@@ -0,0 +1,209 @@
+use crate::db_handler::{
+    get_meta, open_db, GENESIS_HASH_KEY, LATEST_BLOCK_NO_KEY, SCHEMA_VERSION_KEY,
+};
+use crate::error::AggError;
+use log::{info, warn};
+use rocksdb::checkpoint::Checkpoint;
+use rocksdb::DB;
+use serde::{Deserialize, Serialize};
+use serde_json::{from_slice, to_vec_pretty};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// File written next to the checkpoint, describing what the snapshot holds
+pub const MANIFEST_FILE: &str = "snapshot.json";
+
+/// What a snapshot holds, checked against the restored db
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SnapshotManifest {
+    /// Unix seconds the snapshot was taken at
+    pub created_at: u64,
+    /// Latest block stored when the snapshot was taken, None for a db without blocks
+    pub latest_block_no: Option<u64>,
+    pub genesis_hash: Option<String>,
+    pub schema_version: u64,
+}
+
+impl SnapshotManifest {
+    /// This function reads the manifest of a snapshot
+    ///
+    /// # Arguments
+    ///
+    /// * `snapshot` - A Path that holds the snapshot directory
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, AggError>` - The manifest or an error if the directory is not a snapshot
+    pub fn read(snapshot: &Path) -> Result<Self, AggError> {
+        let manifest = fs::read(snapshot.join(MANIFEST_FILE)).map_err(|err| {
+            AggError::SnapshotError(format!(
+                "{} is not a snapshot, {} is unreadable: {}",
+                snapshot.display(),
+                MANIFEST_FILE,
+                err
+            ))
+        })?;
+        Ok(from_slice(&manifest)?)
+    }
+
+    fn of(db: &DB) -> Result<Self, AggError> {
+        let latest_block_no = get_meta(db, LATEST_BLOCK_NO_KEY)?
+            .map(|block_no| from_slice::<u64>(&block_no))
+            .transpose()?;
+        let genesis_hash = get_meta(db, GENESIS_HASH_KEY)?
+            .map(|genesis_hash| from_slice::<String>(&genesis_hash))
+            .transpose()?;
+        let schema_version = get_meta(db, SCHEMA_VERSION_KEY)?
+            .map(|version| from_slice::<u64>(&version))
+            .transpose()?
+            .unwrap_or_default();
+        Ok(SnapshotManifest {
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            latest_block_no,
+            genesis_hash,
+            schema_version,
+        })
+    }
+}
+
+/// This function writes a consistent snapshot of the db, blocks, indexes and meta keys alike, as
+/// a RocksDB checkpoint. The node must be stopped, as the db is opened for writing.
+///
+/// # Arguments
+///
+/// * `db_path` - A string slice that holds the path to the database
+/// * `snapshot` - A Path that holds the directory to write, which must not exist
+///
+/// # Returns
+///
+/// * `Result<SnapshotManifest, AggError>` - The manifest of the snapshot or an error if the db
+///   could not be opened or the checkpoint written
+pub fn create(db_path: &str, snapshot: &Path) -> Result<SnapshotManifest, AggError> {
+    if !Path::new(db_path).join("CURRENT").exists() {
+        return Err(AggError::SnapshotError(format!(
+            "No db found at {}",
+            db_path
+        )));
+    }
+    if snapshot.exists() {
+        return Err(AggError::SnapshotError(format!(
+            "{} already exists",
+            snapshot.display()
+        )));
+    }
+    let db = open_db(db_path, false).map_err(|err| {
+        AggError::SnapshotError(format!(
+            "Unable to open the db at {}, stop the node first: {}",
+            db_path, err
+        ))
+    })?;
+    let manifest = SnapshotManifest::of(&db)?;
+    // Sst files are hard linked when the snapshot is on the same filesystem as the db
+    Checkpoint::new(&db)?.create_checkpoint(snapshot)?;
+    fs::write(snapshot.join(MANIFEST_FILE), to_vec_pretty(&manifest)?)?;
+    info!(
+        target: "snapshot",
+        "Wrote snapshot of {} through block {:?} to {}",
+        db_path, manifest.latest_block_no, snapshot.display()
+    );
+    Ok(manifest)
+}
+
+/// This function bootstraps a fresh node from a snapshot, copying it to a staging directory that
+/// is renamed to the db path once the db it holds matches the manifest. A staged db that does not
+/// match is deleted.
+///
+/// # Arguments
+///
+/// * `snapshot` - A Path that holds the snapshot directory
+/// * `db_path` - A string slice that holds the path to the database, which must be missing or
+///   empty
+///
+/// # Returns
+///
+/// * `Result<SnapshotManifest, AggError>` - The manifest of the restored snapshot or an error if
+///   the db path is in use or the restored db does not match the snapshot
+pub fn restore(snapshot: &Path, db_path: &str) -> Result<SnapshotManifest, AggError> {
+    let manifest = SnapshotManifest::read(snapshot)?;
+    let target = Path::new(db_path);
+    if target.exists() && fs::read_dir(target)?.next().is_some() {
+        return Err(AggError::SnapshotError(format!(
+            "{} is not empty, restore into a fresh db path",
+            db_path
+        )));
+    }
+    let staging = PathBuf::from(format!("{}.restoring", db_path));
+    if staging.exists() {
+        // Left behind by a restore that did not complete
+        fs::remove_dir_all(&staging)?;
+    }
+    // The db path only ever holds a restored db that matches the snapshot
+    if let Err(err) = stage(snapshot, &staging, &manifest) {
+        if let Err(cleanup) = fs::remove_dir_all(&staging) {
+            warn!(
+                target: "snapshot",
+                "Unable to remove {}: {}", staging.display(), cleanup
+            );
+        }
+        return Err(err);
+    }
+    if target.exists() {
+        fs::remove_dir(target)?;
+    }
+    fs::rename(&staging, target)?;
+    info!(
+        target: "snapshot",
+        "Restored {} through block {:?} to {}",
+        snapshot.display(), manifest.latest_block_no, db_path
+    );
+    Ok(manifest)
+}
+
+/// This function copies a snapshot to the staging directory and checks the db it holds against
+/// the manifest
+///
+/// # Arguments
+///
+/// * `snapshot` - A Path that holds the snapshot directory
+/// * `staging` - A Path that holds the staging directory, which must not exist
+/// * `manifest` - A reference to the SnapshotManifest of the snapshot
+///
+/// # Returns
+///
+/// * `Result<(), AggError>` - A Result that holds the result or an error if the snapshot could
+///   not be copied or the staged db does not match it
+fn stage(snapshot: &Path, staging: &Path, manifest: &SnapshotManifest) -> Result<(), AggError> {
+    fs::create_dir_all(staging)?;
+    for entry in fs::read_dir(snapshot)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name == MANIFEST_FILE || !entry.metadata()?.is_file() {
+            continue;
+        }
+        let to = staging.join(&name);
+        // Sst files are never modified once written, the others are appended to by the db
+        let linked = Path::new(&name).extension().is_some_and(|ext| ext == "sst")
+            && fs::hard_link(entry.path(), &to).is_ok();
+        if !linked {
+            fs::copy(entry.path(), &to)?;
+        }
+    }
+    let db = open_db(&staging.to_string_lossy(), false)?;
+    let restored = SnapshotManifest::of(&db)?;
+    if restored.latest_block_no != manifest.latest_block_no
+        || restored.genesis_hash != manifest.genesis_hash
+    {
+        return Err(AggError::SnapshotError(format!(
+            "Restored db holds block {:?} of genesis {:?}, the snapshot block {:?} of genesis {:?}",
+            restored.latest_block_no,
+            restored.genesis_hash,
+            manifest.latest_block_no,
+            manifest.genesis_hash
+        )));
+    }
+    Ok(())
+}
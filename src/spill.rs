@@ -0,0 +1,283 @@
+use crate::config::SpillConfig;
+use crate::error::AggError;
+use crate::metrics::{CHUNK_IN_MEMORY_BYTES, SPILLED_CHUNKS};
+use log::{info, warn};
+use once_cell::sync::OnceCell;
+use solana_transaction_status::option_serializer::OptionSerializer;
+use solana_transaction_status::{EncodedTransaction, EncodedTransactionWithStatusMeta};
+use std::borrow::Cow;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Extension of the files holding a spilled chunk
+const EXTENSION: &str = "chunk";
+/// Bytes a transaction is counted for besides its payload and logs, a rough bound of its metadata
+const TX_OVERHEAD: u64 = 512;
+/// Bytes a transaction fetched in a JSON encoding is counted for, the packet size limit
+const JSON_TX_SIZE: u64 = 1232;
+
+/// Store shared by the block fetches, set once at startup when enabled
+static STORE: OnceCell<SpillStore> = OnceCell::new();
+
+/// Budget of the chunks of transactions held in memory between the block fetcher and the parser.
+/// Chunks past the budget are written to the spill directory and read back by the parser, so the
+/// memory of a catch-up through large blocks stays bounded.
+pub struct SpillStore {
+    dir: PathBuf,
+    budget: u64,
+    in_memory: Arc<AtomicU64>,
+    next_id: AtomicU64,
+}
+
+impl SpillStore {
+    /// This function opens the spill directory, removing the chunks spilled by a previous run,
+    /// whose blocks are fetched again
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - A Path that holds the spill directory, created when missing
+    /// * `budget` - A u64 that holds the bytes the chunks in memory may take
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, AggError>` - A Result that holds the store or an error if the directory
+    ///   could not be read
+    pub fn open(dir: &Path, budget: u64) -> Result<Self, AggError> {
+        std::fs::create_dir_all(dir)?;
+        for file in std::fs::read_dir(dir)? {
+            let path = file?.path();
+            if path.extension().and_then(|extension| extension.to_str()) == Some(EXTENSION) {
+                let _ = std::fs::remove_file(&path);
+            }
+        }
+        info!(target: "spill", "Opened the spill directory at {}", dir.display());
+        Ok(SpillStore {
+            dir: dir.to_path_buf(),
+            budget,
+            in_memory: Arc::new(AtomicU64::new(0)),
+            next_id: AtomicU64::new(0),
+        })
+    }
+
+    /// This function keeps a chunk in memory while the budget allows, and writes it to the spill
+    /// directory otherwise. A chunk failing to be written is kept in memory.
+    ///
+    /// # Arguments
+    ///
+    /// * `slot` - A u64 that holds the slot of the block
+    /// * `chunk_no` - A u64 that holds the number of the chunk in the block
+    /// * `txs` - A Vec<EncodedTransactionWithStatusMeta> that holds the transactions of the chunk
+    ///
+    /// # Returns
+    ///
+    /// * `ChunkPayload` - The payload the chunk message carries
+    pub fn payload(
+        &self,
+        slot: u64,
+        chunk_no: u64,
+        txs: Vec<EncodedTransactionWithStatusMeta>,
+    ) -> ChunkPayload {
+        let size = estimated_size(&txs);
+        let fits = self
+            .in_memory
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                (used + size <= self.budget).then_some(used + size)
+            })
+            .is_ok();
+        if !fits {
+            match self.spill(slot, chunk_no, &txs) {
+                Ok(path) => {
+                    SPILLED_CHUNKS.inc();
+                    return ChunkPayload(Arc::new(Held::Spilled(SpilledFile {
+                        path,
+                        len: txs.len(),
+                    })));
+                }
+                Err(err) => {
+                    warn!(target: "spill", "Failed to spill chunk {} of slot {} {}", chunk_no, slot, err);
+                    self.in_memory.fetch_add(size, Ordering::SeqCst);
+                }
+            }
+        }
+        CHUNK_IN_MEMORY_BYTES.set(self.in_memory() as i64);
+        ChunkPayload(Arc::new(Held::InMemory {
+            txs,
+            _reservation: Some(Reservation {
+                in_memory: self.in_memory.clone(),
+                size,
+            }),
+        }))
+    }
+
+    /// This function returns the estimated bytes of the chunks held in memory
+    pub fn in_memory(&self) -> u64 {
+        self.in_memory.load(Ordering::SeqCst)
+    }
+
+    fn spill(
+        &self,
+        slot: u64,
+        chunk_no: u64,
+        txs: &[EncodedTransactionWithStatusMeta],
+    ) -> Result<PathBuf, AggError> {
+        // Named after a counter, so the chunks of a block fetched twice do not share a file
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let path = self
+            .dir
+            .join(format!("{}-{}-{}.{}", slot, chunk_no, id, EXTENSION));
+        std::fs::write(&path, serde_json::to_vec(txs)?)?;
+        Ok(path)
+    }
+}
+
+/// Transactions of a chunk, held in memory or spilled to disk. Clones share the transactions,
+/// which are released once the last clone is dropped.
+#[derive(Clone)]
+pub struct ChunkPayload(Arc<Held>);
+
+enum Held {
+    InMemory {
+        txs: Vec<EncodedTransactionWithStatusMeta>,
+        /// Given back with the chunk, None for chunks not counted against the budget
+        _reservation: Option<Reservation>,
+    },
+    Spilled(SpilledFile),
+}
+
+/// Share of the budget taken by a chunk in memory, given back when the chunk is dropped
+struct Reservation {
+    in_memory: Arc<AtomicU64>,
+    size: u64,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        let used = self.in_memory.fetch_sub(self.size, Ordering::SeqCst) - self.size;
+        CHUNK_IN_MEMORY_BYTES.set(used as i64);
+    }
+}
+
+/// A spilled chunk, removed from the spill directory when dropped
+struct SpilledFile {
+    path: PathBuf,
+    len: usize,
+}
+
+impl Drop for SpilledFile {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            warn!(target: "spill", "Failed to remove the spilled chunk {} {}", self.path.display(), err);
+        }
+    }
+}
+
+impl ChunkPayload {
+    /// This function returns the transactions of the chunk, read back from the spill directory
+    /// when the chunk was spilled
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Cow<[EncodedTransactionWithStatusMeta]>, AggError>` - The transactions or an
+    ///   error if the spilled chunk could not be read
+    pub fn transactions(&self) -> Result<Cow<'_, [EncodedTransactionWithStatusMeta]>, AggError> {
+        match self.0.as_ref() {
+            Held::InMemory { txs, .. } => Ok(Cow::Borrowed(txs)),
+            Held::Spilled(file) => Ok(Cow::Owned(serde_json::from_slice(&std::fs::read(
+                &file.path,
+            )?)?)),
+        }
+    }
+
+    /// This function tells whether the chunk was spilled to disk
+    pub fn is_spilled(&self) -> bool {
+        matches!(self.0.as_ref(), Held::Spilled(_))
+    }
+}
+
+/// Chunks built outside the block fetcher, by the tests and benches, are held in memory without
+/// counting against the budget
+impl From<Vec<EncodedTransactionWithStatusMeta>> for ChunkPayload {
+    fn from(txs: Vec<EncodedTransactionWithStatusMeta>) -> Self {
+        ChunkPayload(Arc::new(Held::InMemory {
+            txs,
+            _reservation: None,
+        }))
+    }
+}
+
+impl fmt::Debug for ChunkPayload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.as_ref() {
+            Held::InMemory { txs, .. } => write!(f, "{} transactions in memory", txs.len()),
+            Held::Spilled(file) => write!(
+                f,
+                "{} transactions spilled to {}",
+                file.len,
+                file.path.display()
+            ),
+        }
+    }
+}
+
+/// This function estimates the bytes a chunk takes in memory from the payloads and logs of its
+/// transactions, without serializing them
+///
+/// # Arguments
+///
+/// * `txs` - A slice of EncodedTransactionWithStatusMeta that holds the transactions
+pub fn estimated_size(txs: &[EncodedTransactionWithStatusMeta]) -> u64 {
+    txs.iter()
+        .map(|tx| {
+            let payload = match &tx.transaction {
+                EncodedTransaction::LegacyBinary(data) | EncodedTransaction::Binary(data, _) => {
+                    data.len() as u64
+                }
+                EncodedTransaction::Json(_) | EncodedTransaction::Accounts(_) => JSON_TX_SIZE,
+            };
+            let logs = match tx.meta.as_ref().map(|meta| meta.log_messages.as_ref()) {
+                Some(OptionSerializer::Some(logs)) => logs.iter().map(|log| log.len() as u64).sum(),
+                _ => 0,
+            };
+            TX_OVERHEAD + payload + logs
+        })
+        .sum()
+}
+
+/// This function opens the spill directory shared by every block fetch of the process
+///
+/// # Arguments
+///
+/// * `config` - A SpillConfig that holds the spill directory and budget
+///
+/// # Returns
+///
+/// * `Result<(), AggError>` - A Result that holds the result or an error if the spill directory
+///   could not be opened
+pub fn install(config: &SpillConfig) -> Result<(), AggError> {
+    if !config.enabled || STORE.get().is_some() {
+        return Ok(());
+    }
+    let store = SpillStore::open(&config.dir, config.max_in_memory_bytes())?;
+    let _ = STORE.set(store);
+    Ok(())
+}
+
+/// This function builds the payload of a chunk with the installed store, in memory when none is
+///
+/// # Arguments
+///
+/// * `slot` - A u64 that holds the slot of the block
+/// * `chunk_no` - A u64 that holds the number of the chunk in the block
+/// * `txs` - A Vec<EncodedTransactionWithStatusMeta> that holds the transactions of the chunk
+pub(crate) fn payload(
+    slot: u64,
+    chunk_no: u64,
+    txs: Vec<EncodedTransactionWithStatusMeta>,
+) -> ChunkPayload {
+    match STORE.get() {
+        Some(store) => store.payload(slot, chunk_no, txs),
+        None => ChunkPayload::from(txs),
+    }
+}
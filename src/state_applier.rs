@@ -0,0 +1,92 @@
+use log::warn;
+use std::collections::BTreeSet;
+
+/// Number of blocks that may wait behind a missing block before the missing block is given up on
+const MAX_PENDING_BLOCKS: usize = 64;
+
+pub enum ReadyBlock {
+    /// The next block in order, its account updates can be applied on top of the latest block
+    Apply(u64),
+    /// A block at or below the latest applied block, its account updates must not be applied
+    Stale(u64),
+}
+
+/// Orders the application of account state by block number, independently of the order in
+/// which block bodies are finalized and stored
+#[derive(Default)]
+pub struct StateApplier {
+    pending: BTreeSet<u64>,
+    /// Blocks given up on before they were stored, passed over instead of waited for
+    skipped: BTreeSet<u64>,
+}
+
+impl StateApplier {
+    /// This function creates the applier with the blocks left pending by a previous run
+    ///
+    /// # Arguments
+    ///
+    /// * `pending` - A BTreeSet<u64> that holds the stored blocks whose state was not applied
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The state applier
+    pub fn new(pending: BTreeSet<u64>) -> Self {
+        StateApplier {
+            pending,
+            skipped: BTreeSet::new(),
+        }
+    }
+
+    /// This function queues a stored block for state application
+    pub fn park(&mut self, block_no: u64) {
+        self.pending.insert(block_no);
+    }
+
+    /// This function drops a pending block, e.g. when it is deleted
+    pub fn remove(&mut self, block_no: u64) {
+        self.pending.remove(&block_no);
+    }
+
+    /// This function records a block that will never be stored, e.g. as a plugin rejected it, so
+    /// the blocks after it are not held back waiting for it
+    pub fn skip(&mut self, block_no: u64) {
+        self.skipped.insert(block_no);
+    }
+
+    /// This function pops the next block whose state can be handled
+    ///
+    /// # Arguments
+    ///
+    /// * `latest_block_no` - An Option<u64> that holds the latest applied block
+    ///
+    /// # Returns
+    ///
+    /// * `Option<ReadyBlock>` - The next block to apply or discard, None if the next block in
+    ///   order has not been stored yet
+    pub fn next_ready(&mut self, latest_block_no: Option<u64>) -> Option<ReadyBlock> {
+        let latest_block_no = latest_block_no.map(|latest| {
+            self.skipped = self.skipped.split_off(&latest.saturating_add(1));
+            let mut latest = latest;
+            while self.skipped.contains(&(latest + 1)) {
+                latest += 1;
+            }
+            latest
+        });
+        let first = *self.pending.first()?;
+        let ready = match latest_block_no {
+            None => ReadyBlock::Apply(first),
+            Some(latest) if first <= latest => ReadyBlock::Stale(first),
+            Some(latest) if first == latest + 1 => ReadyBlock::Apply(first),
+            Some(latest) if self.pending.len() > MAX_PENDING_BLOCKS => {
+                warn!(
+                    target: "db",
+                    "Blocks {}..{} never arrived, applying state from block {}", latest + 1, first, first
+                );
+                ReadyBlock::Apply(first)
+            }
+            Some(_) => return None,
+        };
+        self.pending.remove(&first);
+        Some(ready)
+    }
+}
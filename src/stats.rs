@@ -0,0 +1,203 @@
+use crate::metrics;
+use crate::shutdown::{Shutdown, Worker};
+use crate::util::{Block, ProtocolMessage, Response};
+use futures_util::future::BoxFuture;
+use log::error;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{Receiver, UnboundedReceiver};
+
+const WINDOWS: [(&str, Duration); 3] = [
+    ("1m", Duration::from_secs(60)),
+    ("5m", Duration::from_secs(300)),
+    ("1h", Duration::from_secs(3600)),
+];
+
+struct BlockSample {
+    at: Instant,
+    transactions: u64,
+    successful: u64,
+    fees: u64,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct WindowStats {
+    pub window: &'static str,
+    pub blocks: u64,
+    pub transactions: u64,
+    pub tps: f64,
+    pub success_ratio: f64,
+    pub average_fee: f64,
+}
+
+/// Rolling throughput statistics over the blocks finalized in the last hour
+pub struct TpsStats {
+    started: Instant,
+    samples: VecDeque<BlockSample>,
+}
+
+impl Default for TpsStats {
+    fn default() -> Self {
+        TpsStats::new(Instant::now())
+    }
+}
+
+impl TpsStats {
+    /// This function initializes the statistics
+    ///
+    /// # Arguments
+    ///
+    /// * `started` - An Instant that holds when the blocks started being recorded
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The statistics, without any block
+    pub fn new(started: Instant) -> Self {
+        TpsStats {
+            started,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// This function records a finalized block and refreshes the exported gauges
+    ///
+    /// # Arguments
+    ///
+    /// * `block` - A Block that holds the finalized block
+    /// * `now` - An Instant that holds the current time
+    pub fn record(&mut self, block: &Block, now: Instant) {
+        let (transactions, successful, fees) = block.tx_stats();
+        self.samples.push_back(BlockSample {
+            at: now,
+            transactions,
+            successful,
+            fees,
+        });
+        let max_window = WINDOWS[WINDOWS.len() - 1].1;
+        while let Some(sample) = self.samples.front() {
+            if now.duration_since(sample.at) <= max_window {
+                break;
+            }
+            self.samples.pop_front();
+        }
+        for window in self.snapshot(now) {
+            metrics::TPS
+                .with_label_values(&[window.window])
+                .set(window.tps);
+            metrics::SUCCESS_RATIO
+                .with_label_values(&[window.window])
+                .set(window.success_ratio);
+            metrics::AVERAGE_FEE
+                .with_label_values(&[window.window])
+                .set(window.average_fee);
+        }
+    }
+
+    /// This function computes the statistics of every window
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - An Instant that holds the current time
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<WindowStats>` - The statistics of the 1m, 5m and 1h windows
+    pub fn snapshot(&self, now: Instant) -> Vec<WindowStats> {
+        WINDOWS
+            .iter()
+            .map(|(name, window)| {
+                let mut stats = WindowStats {
+                    window: name,
+                    ..Default::default()
+                };
+                let mut successful = 0;
+                let mut fees = 0;
+                for sample in self
+                    .samples
+                    .iter()
+                    .filter(|sample| now.duration_since(sample.at) <= *window)
+                {
+                    stats.blocks += 1;
+                    stats.transactions += sample.transactions;
+                    successful += sample.successful;
+                    fees += sample.fees;
+                }
+                // Right after startup the window is only partially filled
+                let elapsed = now.duration_since(self.started).min(*window);
+                if !elapsed.is_zero() {
+                    stats.tps = stats.transactions as f64 / elapsed.as_secs_f64();
+                }
+                if stats.transactions > 0 {
+                    stats.success_ratio = successful as f64 / stats.transactions as f64;
+                    stats.average_fee = fees as f64 / stats.transactions as f64;
+                }
+                stats
+            })
+            .collect()
+    }
+}
+
+/// Sink of the fan-out keeping the throughput statistics of the finalized blocks
+pub struct StatsAggregator {
+    block_receiver: Receiver<ProtocolMessage>,
+    query_receiver: UnboundedReceiver<ProtocolMessage>,
+    tps_stats: TpsStats,
+    shutdown: Option<Shutdown>,
+}
+
+impl StatsAggregator {
+    /// This function initializes the stats aggregator
+    ///
+    /// # Arguments
+    ///
+    /// * `block_receiver` - A Receiver<ProtocolMessage> that receives the finalized blocks
+    /// * `query_receiver` - A UnboundedReceiver<ProtocolMessage> that receives the stats queries
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The stats aggregator
+    pub fn initialize(
+        block_receiver: Receiver<ProtocolMessage>,
+        query_receiver: UnboundedReceiver<ProtocolMessage>,
+    ) -> Self {
+        Self {
+            block_receiver,
+            query_receiver,
+            tps_stats: TpsStats::default(),
+            shutdown: None,
+        }
+    }
+
+    /// This function runs the stats aggregator
+    pub async fn run(&mut self) {
+        loop {
+            tokio::select! {
+                Some(message) = self.block_receiver.recv() => {
+                    if let ProtocolMessage::FinalizeBlock(_, block) = message {
+                        self.tps_stats.record(&block, Instant::now());
+                    }
+                }
+                Some(message) = self.query_receiver.recv() => {
+                    if let ProtocolMessage::FetchTpsStats(reply) = message {
+                        if reply.send(Response::TpsStats(self.tps_stats.snapshot(Instant::now()))).is_err() {
+                            error!(target: "stats", "Server stopped waiting for the tps stats");
+                        }
+                    }
+                }
+                _ = Shutdown::wait(&mut self.shutdown) => return,
+                else => return,
+            }
+        }
+    }
+}
+
+impl Worker for StatsAggregator {
+    fn set_shutdown(&mut self, shutdown: Shutdown) {
+        self.shutdown = Some(shutdown);
+    }
+
+    fn work(&mut self) -> BoxFuture<'_, ()> {
+        Box::pin(self.run())
+    }
+}
@@ -0,0 +1,204 @@
+use crate::config::TenantConfig;
+use crate::error::AggError;
+use crate::server::error_response;
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::Method;
+use actix_web::Error;
+use futures_util::future::{ready, LocalBoxFuture, Ready};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+const QUOTA_WINDOW: Duration = Duration::from_secs(60);
+
+/// This function tells whether a request goes to an endpoint authenticated with the admin api
+/// key, the `/admin` endpoints and annotating a slot, which tenant keys and public rate limits
+/// do not apply to
+pub fn is_admin_request(req: &ServiceRequest) -> bool {
+    req.path().starts_with("/admin")
+        || (req.method() == Method::POST && req.path().starts_with("/annotations/"))
+}
+
+#[derive(Serialize, Clone)]
+pub struct TenantUsage {
+    total_requests: u64,
+    rejected_requests: u64,
+    requests_per_minute: Option<u64>,
+    #[serde(skip)]
+    window_start: Instant,
+    #[serde(skip)]
+    window_requests: u64,
+}
+
+impl TenantUsage {
+    fn new(requests_per_minute: Option<u64>) -> Self {
+        TenantUsage {
+            total_requests: 0,
+            rejected_requests: 0,
+            requests_per_minute,
+            window_start: Instant::now(),
+            window_requests: 0,
+        }
+    }
+
+    fn admit(&mut self) -> bool {
+        if self.window_start.elapsed() >= QUOTA_WINDOW {
+            self.window_start = Instant::now();
+            self.window_requests = 0;
+        }
+        if let Some(limit) = self.requests_per_minute {
+            if self.window_requests >= limit {
+                self.rejected_requests += 1;
+                return false;
+            }
+        }
+        self.window_requests += 1;
+        self.total_requests += 1;
+        true
+    }
+}
+
+pub struct TenantRegistry {
+    tenant_by_key: HashMap<String, String>,
+    usage: Mutex<BTreeMap<String, TenantUsage>>,
+}
+
+impl TenantRegistry {
+    /// This function creates the tenant registry
+    ///
+    /// # Arguments
+    ///
+    /// * `tenants` - A slice of TenantConfig that holds the configured tenants
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The tenant registry
+    pub fn new(tenants: &[TenantConfig]) -> Self {
+        let mut tenant_by_key = HashMap::new();
+        let mut usage = BTreeMap::new();
+        for tenant in tenants {
+            for key in tenant.api_keys.iter() {
+                tenant_by_key.insert(key.clone(), tenant.name.clone());
+            }
+            usage.insert(
+                tenant.name.clone(),
+                TenantUsage::new(tenant.requests_per_minute),
+            );
+        }
+        TenantRegistry {
+            tenant_by_key,
+            usage: Mutex::new(usage),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.tenant_by_key.is_empty()
+    }
+
+    /// This function tells whether an api key belongs to a tenant
+    ///
+    /// # Arguments
+    ///
+    /// * `api_key` - A string slice that holds the api key
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Whether the key is configured for a tenant
+    pub fn knows(&self, api_key: &str) -> bool {
+        self.tenant_by_key.contains_key(api_key)
+    }
+
+    /// This function returns the usage of every tenant
+    pub fn usage(&self) -> BTreeMap<String, TenantUsage> {
+        self.usage
+            .lock()
+            .map(|usage| usage.clone())
+            .unwrap_or_default()
+    }
+
+    /// This function resolves the tenant of a request and accounts it against its quota
+    ///
+    /// # Arguments
+    ///
+    /// * `api_key` - An Option<&str> that holds the api key of the request
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - Ok if the request is admitted, otherwise why it is rejected
+    fn admit(&self, api_key: Option<&str>) -> Result<(), AggError> {
+        let unauthorized = || AggError::Unauthorized("missing or unknown api key".to_string());
+        let tenant = api_key
+            .and_then(|key| self.tenant_by_key.get(key))
+            .ok_or_else(unauthorized)?;
+        let mut usage = self
+            .usage
+            .lock()
+            .map_err(|_| AggError::TaskFailed("tenant usage lock poisoned".to_string()))?;
+        match usage.get_mut(tenant).map(TenantUsage::admit) {
+            Some(true) => Ok(()),
+            Some(false) => Err(AggError::RateLimited(format!(
+                "quota of tenant {} exceeded",
+                tenant
+            ))),
+            None => Err(unauthorized()),
+        }
+    }
+}
+
+/// Middleware resolving the tenant of each request from its api key and enforcing its quota.
+/// Admin routes are authenticated separately and are not accounted to tenants.
+pub struct TenantAuth(pub Arc<TenantRegistry>);
+
+impl<S, B> Transform<S, ServiceRequest> for TenantAuth
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = TenantAuthMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(TenantAuthMiddleware {
+            service,
+            registry: self.0.clone(),
+        }))
+    }
+}
+
+pub struct TenantAuthMiddleware<S> {
+    service: S,
+    registry: Arc<TenantRegistry>,
+}
+
+impl<S, B> Service<ServiceRequest> for TenantAuthMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        if self.registry.is_enabled() && !is_admin_request(&req) {
+            let api_key = req
+                .headers()
+                .get(API_KEY_HEADER)
+                .and_then(|value| value.to_str().ok());
+            if let Err(err) = self.registry.admit(api_key) {
+                let response = error_response(&err);
+                return Box::pin(ready(Ok(req.into_response(response).map_into_right_body())));
+            }
+        }
+        let fut = self.service.call(req);
+        Box::pin(async move { fut.await.map(ServiceResponse::map_into_left_body) })
+    }
+}
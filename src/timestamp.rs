@@ -0,0 +1,76 @@
+use crate::util::BlockTimeFlag;
+use solana_program::clock::DEFAULT_MS_PER_SLOT;
+
+/// Block time of a neighbouring stored block, used to sanitize the time of a new block
+#[derive(Debug, Clone, Copy)]
+pub struct TimeAnchor {
+    pub slot: u64,
+    pub block_time: i64,
+}
+
+/// Sanitized block time and how it was derived from the time reported by the node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SanitizedTime {
+    pub block_time: Option<i64>,
+    pub flag: Option<BlockTimeFlag>,
+}
+
+/// This function sanitizes the block time reported by the node against the nearest stored
+/// blocks before and after it, so time based queries and daily aggregates see monotonic times.
+/// A missing time is interpolated by slot between the neighbours, or extrapolated at the
+/// nominal slot duration when only one is known. A time regressing behind the previous block,
+/// or running ahead of the next one, is clamped between them.
+///
+/// # Arguments
+///
+/// * `slot` - A u64 that holds the slot of the block
+/// * `raw` - An Option<i64> that holds the block time reported by the node
+/// * `previous` - An Option<TimeAnchor> that holds the nearest earlier block with a time
+/// * `next` - An Option<TimeAnchor> that holds the nearest later block with a time
+///
+/// # Returns
+///
+/// * `SanitizedTime` - The sanitized block time, flagged when it differs from the raw time
+pub fn sanitize(
+    slot: u64,
+    raw: Option<i64>,
+    previous: Option<TimeAnchor>,
+    next: Option<TimeAnchor>,
+) -> SanitizedTime {
+    // Neighbours out of order among themselves cannot bound the block
+    let next =
+        next.filter(|next| previous.is_none_or(|previous| next.block_time >= previous.block_time));
+    let Some(raw) = raw else {
+        let block_time = interpolate(slot, previous, next);
+        return SanitizedTime {
+            block_time,
+            flag: block_time.map(|_| BlockTimeFlag::Interpolated),
+        };
+    };
+    let lower = previous.map_or(i64::MIN, |previous| previous.block_time);
+    let upper = next.map_or(i64::MAX, |next| next.block_time);
+    let block_time = raw.clamp(lower, upper.max(lower));
+    SanitizedTime {
+        block_time: Some(block_time),
+        flag: (block_time != raw).then_some(BlockTimeFlag::OutOfOrder),
+    }
+}
+
+fn interpolate(slot: u64, previous: Option<TimeAnchor>, next: Option<TimeAnchor>) -> Option<i64> {
+    let slot_secs = |slots: u64| (slots.saturating_mul(DEFAULT_MS_PER_SLOT) / 1000) as i64;
+    match (previous, next) {
+        (Some(previous), Some(next)) if next.slot > previous.slot => {
+            let elapsed = slot
+                .saturating_sub(previous.slot)
+                .min(next.slot - previous.slot);
+            let span = (next.block_time - previous.block_time) as i128;
+            let offset = span * elapsed as i128 / (next.slot - previous.slot) as i128;
+            Some(previous.block_time + offset as i64)
+        }
+        (Some(previous), Some(_)) | (Some(previous), None) => {
+            Some(previous.block_time + slot_secs(slot.saturating_sub(previous.slot)))
+        }
+        (None, Some(next)) => Some(next.block_time - slot_secs(next.slot.saturating_sub(slot))),
+        (None, None) => None,
+    }
+}
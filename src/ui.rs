@@ -0,0 +1,292 @@
+use crate::config::QueryConfig;
+use crate::server::send_query;
+use crate::util::{
+    AccountId, AccountSummary, AccountTransaction, AccountTransactions, Instruction,
+    ProtocolMessage, Reply, Response, TxId,
+};
+use actix_web::http::StatusCode;
+use actix_web::{get, web, HttpResponse, Responder};
+use askama::Template;
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Number of the latest transactions listed on an account page
+const ACCOUNT_TX_LIMIT: u64 = 25;
+
+struct TxRow {
+    tx_id: String,
+    fee: String,
+    status: &'static str,
+}
+
+struct TransferRow {
+    from: String,
+    to: String,
+    amount: f64,
+}
+
+struct TokenTransferRow {
+    source: String,
+    destination: String,
+    mint: String,
+    /// Base units of the mint
+    amount: u64,
+}
+
+#[derive(Template)]
+#[template(path = "ui/block.html")]
+struct BlockPage {
+    block_no: u64,
+    previous: Option<u64>,
+    slot: String,
+    blockhash: String,
+    block_time: String,
+    transactions: Vec<TxRow>,
+}
+
+#[derive(Template)]
+#[template(path = "ui/tx.html")]
+struct TxPage {
+    tx_id: String,
+    status: &'static str,
+    fee: String,
+    accounts: Vec<String>,
+    transfers: Vec<TransferRow>,
+    token_transfers: Vec<TokenTransferRow>,
+    metadata: String,
+}
+
+#[derive(Template)]
+#[template(path = "ui/account.html")]
+struct AccountPage {
+    account: String,
+    balance: String,
+    first_seen_block_no: Option<u64>,
+    transactions: Vec<AccountTransaction>,
+}
+
+#[derive(Template)]
+#[template(path = "ui/error.html")]
+struct ErrorPage {
+    status: u16,
+    reason: String,
+}
+
+/// This function registers the explorer pages
+///
+/// # Arguments
+///
+/// * `config` - A ServiceConfig the pages are added to
+pub fn configure(config: &mut web::ServiceConfig) {
+    config
+        .service(block_page)
+        .service(tx_page)
+        .service(account_page);
+}
+
+#[get("/ui/block/{block_no}")]
+async fn block_page(
+    block_no: web::Path<u64>,
+    query_config: web::Data<QueryConfig>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+) -> impl Responder {
+    let block_no = block_no.into_inner();
+    let block = match query(&sender, &query_config, |reply| {
+        ProtocolMessage::FetchBlockDetails(block_no, reply)
+    })
+    .await
+    {
+        Ok(Response::BlockDetails(block)) => block,
+        Ok(_) => return unexpected_reply(),
+        Err(response) => return response,
+    };
+    let transactions: Vec<_> = block
+        .transactions()
+        .map(|(tx_id, tx)| TxRow {
+            tx_id: tx_id.to_string(),
+            fee: display(tx.fee()),
+            status: status(tx.succeeded()),
+        })
+        .collect();
+    render(
+        StatusCode::OK,
+        &BlockPage {
+            block_no,
+            previous: block_no.checked_sub(1),
+            slot: display(block.slot()),
+            blockhash: display(block.blockhash()),
+            block_time: block
+                .block_time()
+                .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+                .map_or_else(|| "-".to_string(), |time| time.to_rfc3339()),
+            transactions,
+        },
+    )
+}
+
+#[get("/ui/tx/{tx_id}")]
+async fn tx_page(
+    tx_id: web::Path<TxId>,
+    query_config: web::Data<QueryConfig>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+) -> impl Responder {
+    let tx_id = tx_id.into_inner().into_string();
+    let tx = match query(&sender, &query_config, |reply| {
+        ProtocolMessage::FetchTransactionDetails(tx_id.clone(), reply)
+    })
+    .await
+    {
+        Ok(Response::TxDetails(tx)) => tx,
+        Ok(_) => return unexpected_reply(),
+        Err(response) => return response,
+    };
+    render(
+        StatusCode::OK,
+        &TxPage {
+            tx_id,
+            status: status(tx.succeeded()),
+            fee: display(tx.fee()),
+            accounts: tx.accounts().to_vec(),
+            transfers: tx
+                .instructions()
+                .iter()
+                .filter_map(|instruction| match instruction {
+                    Instruction::Transfer(from, to, amount) => Some(TransferRow {
+                        from: from.clone(),
+                        to: to.clone(),
+                        amount: *amount,
+                    }),
+                    Instruction::TokenTransfer { .. } | Instruction::Authority { .. } => None,
+                })
+                .collect(),
+            token_transfers: tx
+                .instructions()
+                .iter()
+                .filter_map(|instruction| match instruction {
+                    Instruction::Transfer(..) | Instruction::Authority { .. } => None,
+                    Instruction::TokenTransfer {
+                        source,
+                        destination,
+                        mint,
+                        amount,
+                        ..
+                    } => Some(TokenTransferRow {
+                        source: source.clone(),
+                        destination: destination.clone(),
+                        mint: display(mint.as_ref()),
+                        amount: *amount,
+                    }),
+                })
+                .collect(),
+            metadata: tx.metadata().unwrap_or_default().to_string(),
+        },
+    )
+}
+
+#[get("/ui/account/{account_id}")]
+async fn account_page(
+    account_id: web::Path<AccountId>,
+    query_config: web::Data<QueryConfig>,
+    sender: web::Data<UnboundedSender<ProtocolMessage>>,
+) -> impl Responder {
+    let account = account_id.into_inner().into_string();
+    let summary = match query(&sender, &query_config, |reply| {
+        ProtocolMessage::FetchAccountSummary(account.clone(), reply)
+    })
+    .await
+    {
+        Ok(Response::AccountSummary(summary)) => summary,
+        Ok(_) => return unexpected_reply(),
+        Err(response) => return response,
+    };
+    let transactions = match query(&sender, &query_config, |reply| {
+        ProtocolMessage::FetchAccountTransactions(account.clone(), None, ACCOUNT_TX_LIMIT, reply)
+    })
+    .await
+    {
+        Ok(Response::AccountTransactions(AccountTransactions { transactions, .. })) => transactions,
+        Ok(_) => return unexpected_reply(),
+        Err(response) => return response,
+    };
+    let AccountSummary {
+        account,
+        balance,
+        first_seen,
+        ..
+    } = summary;
+    render(
+        StatusCode::OK,
+        &AccountPage {
+            account,
+            balance: display(balance),
+            first_seen_block_no: first_seen.map(|first_seen| first_seen.block_no),
+            transactions,
+        },
+    )
+}
+
+/// This function sends a query to the db and waits for its reply
+///
+/// # Arguments
+///
+/// * `sender` - A UnboundedSender<ProtocolMessage> that holds the handler sender
+/// * `query_config` - A QueryConfig that holds the request timeout
+/// * `message` - A function building the query around its reply sender
+///
+/// # Returns
+///
+/// * `Result<Response, HttpResponse>` - The response or the error page to respond with
+async fn query<F>(
+    sender: &UnboundedSender<ProtocolMessage>,
+    query_config: &QueryConfig,
+    message: F,
+) -> Result<Response, HttpResponse>
+where
+    F: FnOnce(Reply) -> ProtocolMessage,
+{
+    match send_query(sender, query_config, message).await {
+        Some(Ok(response)) => Ok(response),
+        Some(Err(err)) => Err(error_page(err.status(), err.to_string())),
+        None => Err(error_page(
+            StatusCode::GATEWAY_TIMEOUT,
+            "Query timed out".to_string(),
+        )),
+    }
+}
+
+fn render<T: Template>(status: StatusCode, page: &T) -> HttpResponse {
+    match page.render() {
+        Ok(html) => HttpResponse::build(status)
+            .content_type("text/html; charset=utf-8")
+            .body(html),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+fn error_page(status: StatusCode, reason: String) -> HttpResponse {
+    render(
+        status,
+        &ErrorPage {
+            status: status.as_u16(),
+            reason,
+        },
+    )
+}
+
+fn unexpected_reply() -> HttpResponse {
+    error_page(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Unexpected reply from the db".to_string(),
+    )
+}
+
+fn display<T: ToString>(value: Option<T>) -> String {
+    value.map_or_else(|| "-".to_string(), |value| value.to_string())
+}
+
+fn status(succeeded: Option<bool>) -> &'static str {
+    match succeeded {
+        Some(true) => "success",
+        Some(false) => "failed",
+        None => "unknown",
+    }
+}
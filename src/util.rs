@@ -1,68 +1,530 @@
+use crate::chain::SyncState;
+use crate::codec::Record;
+use crate::config::{Commitment, DurabilityConfig, ParseMode};
+use crate::endpoints::RpcEndpoints;
+use crate::error::AggError;
+use crate::export::ExportFormat;
+use crate::fetch_cache::FetchCache;
+use crate::hyperloglog::HyperLogLog;
+use crate::retry::Failure;
+use crate::spill::ChunkPayload;
+use crate::stats::WindowStats;
+use crate::timestamp::SanitizedTime;
+use chrono::DateTime;
 use serde::{Deserialize, Serialize};
 use solana_client::rpc_config::RpcBlockConfig;
-use solana_program::hash::Hash;
+use solana_program::hash::{Hash, Hasher};
 use solana_program::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
 use solana_transaction_status::{EncodedTransactionWithStatusMeta, UiTransactionStatusMeta};
-use std::collections::{BTreeMap, HashMap};
-use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::{unbounded_channel, Sender, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
 
 type SlotNo = u64;
 type ChunkNo = u64;
 type TotalChunk = u64;
 
+/// The answer of the db to a query, sent back over the oneshot channel of the query
+#[derive(Debug)]
+pub enum Response {
+    TxDetails(TxRecord),
+    TxInclusion(TxInclusion, TxRecord),
+    LatestBlockDetails(u64, Block),
+    BlockDetails(Block),
+    BlockRangeDetails(BTreeMap<u64, Block>),
+    BlocksBySlot(Vec<(u64, Block)>, Option<SlotNo>),
+    TimeRange(Option<TimeRange>),
+    BalanceHistory(Vec<BalanceChange>),
+    AccountBalance(AccountBalance),
+    BalancesAt(BalancesAt),
+    IndexedSlots(IndexedSlots),
+    TokenHolders(Vec<TokenHolder>),
+    TokenBalance(MintBalance),
+    TokenBalances(Vec<MintBalance>),
+    AccountTransactions(AccountTransactions),
+    AccountSummary(AccountSummary),
+    CustomStats(BTreeMap<String, f64>),
+    ActiveAccounts(Vec<ActiveAccountsStats>),
+    DeletedSlots(DeletedSlots),
+    RefetchedBlock(RefetchedBlock),
+    CompactionStats(CompactionStats),
+    BlockSummaries(Vec<BlockSummary>),
+    Status(Status),
+    Annotations(Vec<Annotation>),
+    TpsStats(Vec<WindowStats>),
+    BlockDigest(u64, String),
+    Webhook(Option<WebhookSubscription>),
+    WebhookDeliveries(Vec<DeliveryReceipt>),
+    DeadLetters(Vec<DeadLetter>),
+    Subscriptions(Subscriptions),
+    Job(Option<Job>),
+    Jobs(Vec<Job>),
+    GenesisHash(String),
+    Error(AggError),
+}
+
+/// The sender a query carries for its one response
+pub type Reply = oneshot::Sender<Response>;
+
 #[derive(Debug)]
 pub enum ProtocolMessage {
-    FetchBlock(String, RpcBlockConfig, SlotNo, UnboundedSender<Self>),
+    FetchBlock(
+        Arc<RpcEndpoints>,
+        RpcBlockConfig,
+        SlotNo,
+        bool,
+        ParseMode,
+        usize,
+        Option<Arc<FetchCache>>,
+        UnboundedSender<Self>,
+    ),
     NewChuck(
         SlotNo,
+        BlockHeader,
         ChunkNo,
         TotalChunk,
-        Vec<EncodedTransactionWithStatusMeta>,
+        ChunkPayload,
         UnboundedSender<Self>,
     ),
     ParsedBlock(SlotNo, TotalChunk, ChunkNo, Block),
     FinalizeBlock(SlotNo, Block),
-    FetchTransactionDetails(String, UnboundedSender<Self>),
-    TxDetails(TxRecord),
-    FetchBlockDetails(String, UnboundedSender<Self>),
-    FetchLatestBlock(UnboundedSender<Self>),
-    LatestBlockDetails(u64, Block),
-    BlockDetails(Block),
-    FetchBlockRange(u64, u64, UnboundedSender<Self>),
-    BlockRangeDetails(BTreeMap<u64, Block>),
-    FetchAccountBalance(String, Option<u64>, UnboundedSender<Self>),
-    AccountBalance(u64),
-    Error(String),
+    FetchTransactionDetails(String, Reply),
+    FetchTxInclusion(String, Reply),
+    FetchBlockDetails(u64, Reply),
+    /// Fetches the block of a slot through the slot to block height index
+    FetchBlockAtSlot(SlotNo, Reply),
+    FetchLatestBlock(Reply),
+    FetchBlockRange(u64, u64, Reply),
+    FetchBlocksBySlot(SlotNo, SlotNo, u64, Reply),
+    FetchAccountBalance(String, Option<u64>, Reply),
+    FetchAccountBalanceAtSlot(String, SlotNo, Reply),
+    /// Balances of several accounts as of one block
+    FetchBalancesAt(Vec<String>, u64, Reply),
+    FetchBalanceHistory(String, SlotNo, SlotNo, u64, Reply),
+    /// Resolves unix timestamps to the slots and blocks stored between them
+    FetchTimeRange(u64, u64, Reply),
+    SkippedSlot(SlotNo),
+    FetchIndexedSlots(SlotNo, SlotNo, u64, Reply),
+    FetchTokenHolders(String, Option<u64>, Reply),
+    /// Latest balance of an owner in a mint, summed over its token accounts
+    FetchTokenBalance(String, String, Reply),
+    /// Latest balances of an owner in every mint it holds
+    FetchTokenBalances(String, Reply),
+    FetchAccountSummary(String, Reply),
+    FetchAccountTransactions(String, Option<TxCursor>, u64, Reply),
+    FetchCustomStats(String, Reply),
+    FetchActiveAccounts(u64, Reply),
+    Compact(Option<(u64, u64)>, Reply),
+    DeleteSlotRange(SlotNo, SlotNo, Reply),
+    /// Replaces the stored block of a slot with the block fetched from the node again
+    RefetchBlock(u64, Box<RawBlock>, Reply),
+    FetchLatestBlockSummaries(u64, Reply),
+    FetchStatus(Reply),
+    Annotate(SlotNo, String, Reply),
+    FetchTpsStats(Reply),
+    FetchBlockDigest(u64, Reply),
+    SubscribeBlocks(Option<u64>, BlockFilter, Sender<Self>),
+    SubscribeAccount(String, Option<SlotNo>, Option<String>, Sender<Self>),
+    AccountEvent(Box<AccountEvent>),
+    AckResumeCursor(String, SlotNo),
+    CreateWebhook(String, String, Option<u64>, Reply),
+    DeleteWebhook(u64, Reply),
+    WebhookEvent(WebhookSubscription, Box<WebhookDelivery>),
+    DeliveryReport(DeliveryReceipt),
+    FetchWebhookDeliveries(u64, u64, Reply),
+    /// Stores a block the pipeline gave up on in the dead-letter queue
+    DeadLetter(Box<DeadLetter>),
+    FetchDeadLetters(u64, Reply),
+    FetchSubscriptions(Reply),
+    NewBlock(u64, Block),
+    ArchiveRawBlock(u64, Box<RawBlock>),
+    StartJob(JobTask, Reply),
+    FetchJobs(Reply),
+    FetchJob(u64, Reply),
+    CancelJob(u64, Reply),
+    /// Progress of a job run outside the db, ignored once the job is finished or cancelled
+    UpdateJob(u64, JobTask, JobState, Option<String>),
+    /// Checks the db holds data of the cluster with the genesis hash, recording it on first use
+    VerifyGenesisHash(String, Reply),
+    /// A query the db drops without answering once the deadline has passed
+    Deadline(Instant, Box<Self>),
+    Error(AggError),
 }
 
 impl ProtocolMessage {
     pub fn new_chuck(
-        slot: SlotNo,
+        block_no: SlotNo,
+        header: BlockHeader,
         chunk_no: ChunkNo,
         total_chunks: u64,
-        txs: Vec<EncodedTransactionWithStatusMeta>,
+        txs: impl Into<ChunkPayload>,
         sender: UnboundedSender<Self>,
     ) -> Self {
-        ProtocolMessage::NewChuck(slot, chunk_no, total_chunks, txs, sender)
-    }
-
-    pub fn fetch_block(
-        client_url: String,
-        rpc_block_config: RpcBlockConfig,
-        slot: SlotNo,
-        sender: UnboundedSender<ProtocolMessage>,
-    ) -> Self {
-        ProtocolMessage::FetchBlock(client_url, rpc_block_config, slot, sender)
+        ProtocolMessage::NewChuck(block_no, header, chunk_no, total_chunks, txs.into(), sender)
     }
 
     pub fn parsed_block(slot: SlotNo, total_chunks: u64, chunk_no: u64, block: Block) -> Self {
         ProtocolMessage::ParsedBlock(slot, total_chunks, chunk_no, block)
     }
+
+    /// This function sends a query and waits for its response, however long the db takes
+    ///
+    /// # Arguments
+    ///
+    /// * `sender` - A UnboundedSender<ProtocolMessage> that holds the handler or db sender
+    /// * `query` - A FnOnce(Reply) -> ProtocolMessage that builds the query around its reply sender
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, AggError>` - A Result that holds the response or the error the db
+    ///   answered with, OneshotChannelError when the query was dropped unanswered
+    pub async fn ask(
+        sender: &UnboundedSender<Self>,
+        query: impl FnOnce(Reply) -> Self,
+    ) -> Result<Response, AggError> {
+        let (reply, response) = oneshot::channel();
+        sender.send(query(reply))?;
+        match response.await.map_err(|_| AggError::OneshotChannelError)? {
+            Response::Error(err) => Err(err),
+            response => Ok(response),
+        }
+    }
+}
+
+/// Block level data known to the fetcher, attached to every chunk of the block
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct BlockHeader {
+    pub slot: SlotNo,
+    pub blockhash: String,
+    pub block_time: Option<i64>,
+    /// Blockhash of the parent block, checked against the stored parent when state is applied
+    #[serde(default)]
+    pub previous_blockhash: Option<String>,
+    /// Slot of the parent block, fetched again when a reorg orphans the stored parent
+    #[serde(default)]
+    pub parent_slot: Option<SlotNo>,
+    /// Transactions in the block as served by the node, including the ones not indexed
+    #[serde(default)]
+    pub transaction_count: Option<u64>,
+}
+
+/// Block as fetched from the node, archived so it can be re-parsed after the parser changes
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct RawBlock {
+    pub header: BlockHeader,
+    pub transactions: Vec<EncodedTransactionWithStatusMeta>,
+}
+
+/// Wire form of a raw block, the transactions hold their JSON encoding, which bincode cannot
+/// encode
+#[derive(Serialize, Deserialize)]
+pub struct RawBlockWire {
+    header: BlockHeader,
+    transactions: Vec<String>,
+}
+
+impl Record for RawBlock {
+    type Wire = RawBlockWire;
+
+    fn to_wire(&self) -> Result<RawBlockWire, AggError> {
+        Ok(RawBlockWire {
+            header: self.header.clone(),
+            transactions: self
+                .transactions
+                .iter()
+                .map(serde_json::to_string)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    fn from_wire(wire: RawBlockWire) -> Result<Self, AggError> {
+        Ok(RawBlock {
+            header: wire.header,
+            transactions: wire
+                .transactions
+                .iter()
+                .map(|tx| serde_json::from_str(tx))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// Progress of the background job moving the transaction index from JSON string keys in the
+/// default column family to raw transaction id keys
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TxIndexMigration {
+    /// Estimate of the keys in the default column family when the migration started, the
+    /// legacy entries are not counted upfront
+    pub total: u64,
+    pub migrated: u64,
+    /// Legacy entries whose block is not stored anymore, deleted without being migrated
+    pub orphaned: u64,
+    pub finished: bool,
+}
+
+/// Progress of the background job moving blocks and first sightings from string keys in the
+/// default column family to the big endian and pubkey keys of their own column families
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct LayoutMigration {
+    /// Estimate of the keys in the default column family when the migration started, the
+    /// legacy entries are not counted upfront
+    pub total: u64,
+    pub migrated: u64,
+    /// Legacy entries whose key does not parse, deleted without being migrated
+    pub skipped: u64,
+    pub finished: bool,
+}
+
+/// Where a transaction is stored, the value of the transaction index keyed by the raw bytes of
+/// the transaction id
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxLocation {
+    pub slot: u64,
+    pub block_no: u64,
+    /// Position of the transaction in the block
+    pub offset: u32,
+}
+
+impl TxLocation {
+    pub const ENCODED_LEN: usize = 20;
+
+    /// This function encodes the location as the big endian slot, block number and offset
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_LEN] {
+        let mut bytes = [0; Self::ENCODED_LEN];
+        bytes[..8].copy_from_slice(&self.slot.to_be_bytes());
+        bytes[8..16].copy_from_slice(&self.block_no.to_be_bytes());
+        bytes[16..].copy_from_slice(&self.offset.to_be_bytes());
+        bytes
+    }
+
+    /// This function decodes a location encoded by `to_bytes`
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - A byte slice that holds the encoded location
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, AggError>` - The location or an error when the length does not match
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, AggError> {
+        let bytes: &[u8; Self::ENCODED_LEN] = bytes.try_into()?;
+        let (slot, rest) = bytes.split_at(8);
+        let (block_no, offset) = rest.split_at(8);
+        Ok(TxLocation {
+            slot: u64::from_be_bytes(slot.try_into()?),
+            block_no: u64::from_be_bytes(block_no.try_into()?),
+            offset: u32::from_be_bytes(offset.try_into()?),
+        })
+    }
+}
+
+/// A transaction the node returned in blocks of two different slots, e.g. on both sides of a
+/// reorg. The index points at the kept block, the other one is where it falls back to when the
+/// kept block is quarantined or deleted.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TxConflict {
+    pub tx_id: String,
+    pub kept: TxLocation,
+    pub other: TxLocation,
+    /// Unix timestamp at which the conflict was detected
+    pub at: u64,
+}
+
+/// Progress of the background job re-parsing archived raw blocks
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReparseProgress {
+    pub start: u64,
+    pub end: u64,
+    /// Next block to re-parse
+    pub next_block_no: u64,
+    pub upgraded_blocks: u64,
+    pub upgraded_transactions: u64,
+    /// Blocks in the range without a stored block or archived raw block
+    pub missing_blocks: u64,
+    pub failed_blocks: u64,
+    pub finished: bool,
+}
+
+impl ReparseProgress {
+    pub fn new(start: u64, end: u64) -> Self {
+        Self {
+            start,
+            end,
+            next_block_no: start,
+            upgraded_blocks: 0,
+            upgraded_transactions: 0,
+            missing_blocks: 0,
+            failed_blocks: 0,
+            finished: false,
+        }
+    }
+}
+
+/// Progress of a prune job deleting everything indexed for a slot range
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PruneProgress {
+    pub start_slot: u64,
+    pub end_slot: u64,
+    /// Next slot to delete
+    pub next_slot: u64,
+    pub deleted_blocks: u64,
+    pub deleted_transactions: u64,
+}
+
+impl PruneProgress {
+    pub fn new(start_slot: u64, end_slot: u64) -> Self {
+        Self {
+            start_slot,
+            end_slot,
+            next_slot: start_slot,
+            deleted_blocks: 0,
+            deleted_transactions: 0,
+        }
+    }
+}
+
+/// Progress of an export job, the shards themselves are checkpointed next to the exported files
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExportProgress {
+    pub format: ExportFormat,
+    pub start_slot: u64,
+    pub end_slot: u64,
+    pub shards: u64,
+    pub finished_shards: u64,
+    pub exported_slots: u64,
+    pub exported_blocks: u64,
+}
+
+/// Progress of a backfill job fetching the blocks of past slots, checkpointed after every batch
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BackfillProgress {
+    pub start_slot: u64,
+    pub end_slot: u64,
+    /// First slot of the next batch
+    pub next_slot: u64,
+    /// Blocks stored for the backfilled slots, including the blocks stored before the backfill
+    pub indexed_blocks: u64,
+    pub skipped_slots: u64,
+    /// Slots neither stored nor known to be skipped once their batch gave up on them
+    pub missing_slots: u64,
+}
+
+impl BackfillProgress {
+    pub fn new(start_slot: u64, end_slot: u64) -> Self {
+        Self {
+            start_slot,
+            end_slot,
+            next_slot: start_slot,
+            indexed_blocks: 0,
+            skipped_slots: 0,
+            missing_slots: 0,
+        }
+    }
+}
+
+/// Work of a background job along with its progress
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JobTask {
+    /// Re-parses archived raw blocks, run by the db
+    Reindex(ReparseProgress),
+    /// Deletes a slot range, run by the db
+    Prune(PruneProgress),
+    /// Exports a slot range to files, run by the exporter
+    Export(ExportProgress),
+    /// Fetches the blocks of past slots from the node, run by the backfiller
+    Backfill(BackfillProgress),
+    /// Moves the transaction index to raw transaction id keys, run by the db
+    MigrateTxIndex(TxIndexMigration),
+    /// Moves blocks and first sightings to their column families, run by the db
+    MigrateLayout(LayoutMigration),
+}
+
+impl JobTask {
+    /// This function returns the share of the job that is done
+    ///
+    /// # Returns
+    ///
+    /// * `f64` - A f64 between 0 and 1
+    pub fn progress(&self) -> f64 {
+        let (done, total) = match self {
+            JobTask::Reindex(progress) => (
+                progress.next_block_no.saturating_sub(progress.start),
+                progress.end.saturating_sub(progress.start) + 1,
+            ),
+            JobTask::Prune(progress) => (
+                progress.next_slot.saturating_sub(progress.start_slot),
+                progress.end_slot.saturating_sub(progress.start_slot) + 1,
+            ),
+            JobTask::Export(progress) => (
+                progress.exported_slots,
+                progress.end_slot.saturating_sub(progress.start_slot) + 1,
+            ),
+            JobTask::Backfill(progress) => (
+                progress.next_slot.saturating_sub(progress.start_slot),
+                progress.end_slot.saturating_sub(progress.start_slot) + 1,
+            ),
+            JobTask::MigrateTxIndex(progress) if progress.finished => (1, 1),
+            JobTask::MigrateTxIndex(progress) => {
+                (progress.migrated + progress.orphaned, progress.total.max(1))
+            }
+            JobTask::MigrateLayout(progress) if progress.finished => (1, 1),
+            JobTask::MigrateLayout(progress) => {
+                (progress.migrated + progress.skipped, progress.total.max(1))
+            }
+        };
+        (done as f64 / total as f64).min(1.0)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Running,
+    Finished,
+    Failed,
+    Cancelled,
+}
+
+/// A long running admin operation, persisted in the db so its progress survives restarts
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Job {
+    pub id: u64,
+    pub state: JobState,
+    /// Share of the job that is done, between 0 and 1
+    pub progress: f64,
+    pub task: JobTask,
+    pub error: Option<String>,
+    /// Unix timestamp at which the job was started
+    pub created_at: u64,
+    /// Unix timestamp of the last progress or state change
+    pub updated_at: u64,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub enum Instruction {
     Transfer(String, String, f64),
+    /// SPL Token `Transfer` or `TransferChecked` between two token accounts. The mint, decimals
+    /// and owners are taken from the token balances of the metadata when the instruction does not
+    /// carry them, `amount` is in base units of the mint
+    TokenTransfer {
+        source: String,
+        destination: String,
+        source_owner: Option<String>,
+        destination_owner: Option<String>,
+        mint: Option<String>,
+        amount: u64,
+        decimals: Option<u8>,
+    },
+    /// System Program owner assignment or SPL Token delegation or authority change of an account
+    Authority {
+        account: String,
+        change: AuthorityChange,
+    },
 }
 
 impl Instruction {
@@ -71,124 +533,2331 @@ impl Instruction {
     }
 }
 
+/// How an instruction changed who controls an account
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthorityChange {
+    /// The program owning the account, assigned by `CreateAccount`, `CreateAccountWithSeed`,
+    /// `Assign` or `AssignWithSeed`
+    Owner { owner: String },
+    /// The delegate of a token account, approved for `amount` base units by `Approve` or
+    /// `ApproveChecked`, None for both once revoked by `Revoke`
+    Delegate {
+        delegate: Option<String>,
+        amount: Option<u64>,
+    },
+    /// An authority of a token account or mint set by `SetAuthority`, None once removed
+    TokenAuthority {
+        authority_type: TokenAuthorityType,
+        new_authority: Option<String>,
+    },
+}
+
+impl AuthorityChange {
+    /// This function returns the account the change hands control to, if any
+    pub fn new_authority(&self) -> Option<&String> {
+        match self {
+            AuthorityChange::Owner { owner } => Some(owner),
+            AuthorityChange::Delegate { delegate, .. } => delegate.as_ref(),
+            AuthorityChange::TokenAuthority { new_authority, .. } => new_authority.as_ref(),
+        }
+    }
+}
+
+/// Authority of a token account or mint, as numbered by the `SetAuthority` instruction
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenAuthorityType {
+    MintTokens,
+    FreezeAccount,
+    AccountOwner,
+    CloseAccount,
+    /// Authorities of the Token-2022 extensions
+    Other(u8),
+}
+
+impl From<u8> for TokenAuthorityType {
+    fn from(authority_type: u8) -> Self {
+        match authority_type {
+            0 => TokenAuthorityType::MintTokens,
+            1 => TokenAuthorityType::FreezeAccount,
+            2 => TokenAuthorityType::AccountOwner,
+            3 => TokenAuthorityType::CloseAccount,
+            other => TokenAuthorityType::Other(other),
+        }
+    }
+}
+
+/// Value of an `authority_history` entry, the changes a transaction made to an account
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuthorityEntry {
+    pub block_no: u64,
+    pub changes: Vec<AuthorityChange>,
+}
+
+/// A change of the owner, delegate or authority of an account, as listed by its summary
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AuthorityEvent {
+    pub slot: u64,
+    pub block_no: u64,
+    pub tx_id: String,
+    pub change: AuthorityChange,
+}
+
+/// Where a transaction was included, with the block context needed to check it against the
+/// chain
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct TxInclusion {
+    pub tx_id: String,
+    pub signatures: Vec<String>,
+    pub block_no: u64,
+    pub slot: Option<SlotNo>,
+    pub blockhash: Option<String>,
+    pub previous_blockhash: Option<String>,
+    pub parent_slot: Option<SlotNo>,
+    pub block_time: Option<i64>,
+    /// Transactions of the block as served by the node
+    pub transaction_count: Option<u64>,
+    /// Position of the transaction among the transactions stored for the block, None for blocks
+    /// stored before their order was kept
+    pub position: Option<u32>,
+}
+
+/// Outcome and program logs of a transaction
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct TxLogs {
+    pub tx_id: String,
+    pub succeeded: Option<bool>,
+    pub error: Option<String>,
+    pub fee: Option<u64>,
+    pub compute_units: Option<u64>,
+    pub logs: Vec<String>,
+}
+
 #[derive(Default, Clone, Serialize, Deserialize, Debug)]
 pub struct TxRecord {
     instruction: Vec<Instruction>,
     metadata: Option<String>,
+    #[serde(default)]
+    fee: Option<u64>,
+    #[serde(default)]
+    succeeded: Option<bool>,
+    /// Account keys of the transaction, including addresses loaded from lookup tables
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    accounts: Vec<String>,
+    /// Why the transaction could only be partially parsed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    parse_error: Option<String>,
+    /// Log messages of the programs the transaction invoked
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    logs: Vec<String>,
+    /// Compute units the transaction consumed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    compute_units: Option<u64>,
+    /// Why the transaction failed, as the node reports it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// Signatures of the transaction, the first one identifies it on chain
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    signatures: Vec<String>,
 }
 
 impl TxRecord {
-    pub fn new(instruction: Vec<Instruction>, metadata: Option<UiTransactionStatusMeta>) -> Self {
-        let metadata = metadata.map(|meta| serde_json::to_string(&meta).unwrap());
-        TxRecord {
+    /// This function builds the record of a parsed transaction
+    ///
+    /// # Arguments
+    ///
+    /// * `instruction` - A Vec<Instruction> that holds the decoded instructions
+    /// * `metadata` - An Option<UiTransactionStatusMeta> that holds the transaction metadata
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, AggError>` - The record, or an error when the metadata does not serialize
+    pub fn new(
+        instruction: Vec<Instruction>,
+        metadata: Option<UiTransactionStatusMeta>,
+    ) -> Result<Self, AggError> {
+        let record = TxRecord {
             instruction,
-            metadata,
-        }
+            metadata: metadata.as_ref().map(serde_json::to_string).transpose()?,
+            ..TxRecord::default()
+        };
+        Ok(record.with_status(metadata.as_ref()))
     }
-}
 
-#[derive(Default, Clone, Serialize, Deserialize, Debug)]
-pub struct Block {
-    tx_map: HashMap<String, TxRecord>,
-    account_map: Option<BTreeMap<String, u64>>,
-}
+    /// This function builds the partial record of a transaction that failed to parse, keeping
+    /// what its metadata tells. Metadata failing to serialize is left out of the record.
+    ///
+    /// # Arguments
+    ///
+    /// * `metadata` - An Option<UiTransactionStatusMeta> that holds the transaction metadata
+    /// * `parse_error` - A String that holds why the transaction failed to parse
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The partial record
+    pub fn unparsed(metadata: Option<UiTransactionStatusMeta>, parse_error: String) -> Self {
+        let record = TxRecord {
+            metadata: metadata
+                .as_ref()
+                .and_then(|meta| serde_json::to_string(meta).ok()),
+            parse_error: Some(parse_error),
+            ..TxRecord::default()
+        };
+        record.with_status(metadata.as_ref())
+    }
 
-impl Block {
-    pub fn insert_account(&mut self, account: String, balance: u64) {
-        if let Some(account_map) = &mut self.account_map {
-            account_map.insert(account, balance);
-        } else {
-            let mut account_map = BTreeMap::new();
-            account_map.insert(account, balance);
-            self.account_map = Some(account_map);
+    /// This function keeps the fee, outcome, compute units and program logs the metadata of the
+    /// transaction reports
+    ///
+    /// # Arguments
+    ///
+    /// * `metadata` - An Option<&UiTransactionStatusMeta> that holds the transaction metadata
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The record with the fields of the metadata
+    fn with_status(mut self, metadata: Option<&UiTransactionStatusMeta>) -> Self {
+        if let Some(meta) = metadata {
+            self.fee = Some(meta.fee);
+            self.succeeded = Some(meta.err.is_none());
+            self.error = meta.err.as_ref().map(ToString::to_string);
+            self.logs = Option::<Vec<String>>::from(meta.log_messages.clone()).unwrap_or_default();
+            self.compute_units = meta.compute_units_consumed.clone().into();
         }
+        self
     }
 
-    pub fn get_tx_details(&self, tx_hash: &str) -> Option<&TxRecord> {
-        self.tx_map.get(tx_hash)
+    pub fn with_accounts(mut self, accounts: Vec<String>) -> Self {
+        self.accounts = accounts;
+        self
     }
 
-    pub fn push_transaction(&mut self, tx_hash: Hash, tx: TxRecord) {
-        self.tx_map.insert(tx_hash.to_string(), tx);
+    pub fn with_signatures(mut self, signatures: Vec<String>) -> Self {
+        self.signatures = signatures;
+        self
     }
 
-    pub fn get_tx_hash(&self) -> Vec<String> {
-        self.tx_map.keys().cloned().collect()
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instruction
     }
 
-    pub fn get_account_balance(&self, account: &str) -> Option<u64> {
-        if let Some(account_map) = &self.account_map {
-            account_map.get(account).cloned()
-        } else {
-            None
+    pub fn metadata(&self) -> Option<&str> {
+        self.metadata.as_deref()
+    }
+
+    pub fn fee(&self) -> Option<u64> {
+        self.fee
+    }
+
+    pub fn succeeded(&self) -> Option<bool> {
+        self.succeeded
+    }
+
+    pub fn accounts(&self) -> &[String] {
+        &self.accounts
+    }
+
+    pub fn parse_error(&self) -> Option<&str> {
+        self.parse_error.as_deref()
+    }
+
+    pub fn signatures(&self) -> &[String] {
+        &self.signatures
+    }
+
+    pub fn logs(&self) -> &[String] {
+        &self.logs
+    }
+
+    pub fn compute_units(&self) -> Option<u64> {
+        self.compute_units
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// This function gathers the outcome and program logs of the transaction, read from its
+    /// metadata for records stored before they were kept
+    ///
+    /// # Arguments
+    ///
+    /// * `tx_id` - A string slice that holds the transaction id
+    ///
+    /// # Returns
+    ///
+    /// * `TxLogs` - The outcome and logs of the transaction
+    pub fn tx_logs(&self, tx_id: &str) -> TxLogs {
+        let stored_before = self.logs.is_empty() && self.compute_units.is_none();
+        let parsed = self
+            .metadata
+            .as_deref()
+            .filter(|_| stored_before)
+            .and_then(|metadata| serde_json::from_str::<UiTransactionStatusMeta>(metadata).ok())
+            .map(|meta| TxRecord::default().with_status(Some(&meta)));
+        let (error, compute_units, logs) = match parsed {
+            Some(parsed) => (parsed.error, parsed.compute_units, parsed.logs),
+            None => (self.error.clone(), self.compute_units, self.logs.clone()),
+        };
+        TxLogs {
+            tx_id: tx_id.to_string(),
+            succeeded: self.succeeded,
+            error,
+            fee: self.fee,
+            compute_units,
+            logs,
         }
     }
 
-    pub fn get_account_map(&self) -> Option<BTreeMap<String, u64>> {
-        self.account_map.clone()
+    /// This function lists the accounts the transaction involves, its account keys along with
+    /// the parties of its transfers, which records stored before the keys were kept only have
+    pub fn parties(&self) -> impl Iterator<Item = &str> {
+        let transfer_parties = self.instruction.iter().flat_map(|instruction| {
+            let parties = match instruction {
+                Instruction::Transfer(from, to, _) => [Some(from), Some(to), None, None],
+                Instruction::TokenTransfer {
+                    source,
+                    destination,
+                    source_owner,
+                    destination_owner,
+                    ..
+                } => [
+                    Some(source),
+                    Some(destination),
+                    source_owner.as_ref(),
+                    destination_owner.as_ref(),
+                ],
+                Instruction::Authority { account, change } => {
+                    [Some(account), change.new_authority(), None, None]
+                }
+            };
+            parties.into_iter().flatten().map(String::as_str)
+        });
+        self.accounts
+            .iter()
+            .map(String::as_str)
+            .chain(transfer_parties)
     }
 
-    pub fn set_account_map(&mut self, account_map: BTreeMap<String, u64>) {
-        self.account_map = Some(account_map);
+    /// This function tells how the transaction moved the SOL or tokens of an account
+    ///
+    /// # Arguments
+    ///
+    /// * `account` - A string slice that holds the account
+    ///
+    /// # Returns
+    ///
+    /// * `TxDirection` - Whether the account sent, received, both or neither
+    pub fn direction(&self, account: &str) -> TxDirection {
+        let (mut sent, mut received) = (false, false);
+        for instruction in self.instruction.iter() {
+            let (senders, receivers) = match instruction {
+                Instruction::Transfer(from, to, _) => ([Some(from), None], [Some(to), None]),
+                Instruction::TokenTransfer {
+                    source,
+                    destination,
+                    source_owner,
+                    destination_owner,
+                    ..
+                } => (
+                    [Some(source), source_owner.as_ref()],
+                    [Some(destination), destination_owner.as_ref()],
+                ),
+                Instruction::Authority { .. } => ([None, None], [None, None]),
+            };
+            sent |= senders.into_iter().flatten().any(|key| key == account);
+            received |= receivers.into_iter().flatten().any(|key| key == account);
+        }
+        match (sent, received) {
+            (true, true) => TxDirection::Both,
+            (true, false) => TxDirection::Out,
+            (false, true) => TxDirection::In,
+            (false, false) => TxDirection::Other,
+        }
+    }
+
+    /// This function returns the SOL moved by the System Program transfers of the transaction
+    pub fn sol_transferred(&self) -> f64 {
+        self.instruction
+            .iter()
+            .map(|instruction| match instruction {
+                Instruction::Transfer(_, _, amount) => *amount,
+                Instruction::TokenTransfer { .. } | Instruction::Authority { .. } => 0.0,
+            })
+            .sum()
+    }
+
+    /// This function summarizes the record for block responses
+    ///
+    /// # Arguments
+    ///
+    /// * `tx_id` - A string slice that holds the transaction id
+    ///
+    /// # Returns
+    ///
+    /// * `TxSummary` - The summary of the transaction
+    pub fn summary(&self, tx_id: &str) -> TxSummary {
+        let mut summary = TxSummary {
+            tx_id: tx_id.to_string(),
+            succeeded: self.succeeded,
+            fee: self.fee,
+            sol_transferred: 0.0,
+            token_transfers: 0,
+            token_transferred: BTreeMap::new(),
+            parse_error: self.parse_error.clone(),
+        };
+        for instruction in &self.instruction {
+            match instruction {
+                Instruction::Transfer(_, _, amount) => summary.sol_transferred += amount,
+                Instruction::TokenTransfer { mint, amount, .. } => {
+                    summary.token_transfers += 1;
+                    if let Some(mint) = mint {
+                        let total = summary.token_transferred.entry(mint.clone()).or_default();
+                        *total = total.saturating_add(*amount);
+                    }
+                }
+                Instruction::Authority { .. } => {}
+            }
+        }
+        summary
     }
 }
 
-#[derive(Default)]
-pub struct UnprocessedBlock {
-    total_chunks: u64,
-    total_collected_chunks: u64,
-    collected_partial_blocks: BTreeMap<ChunkNo, Block>,
+/// Transaction as listed by block responses unless the full records are asked for
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TxSummary {
+    pub tx_id: String,
+    pub succeeded: Option<bool>,
+    pub fee: Option<u64>,
+    /// SOL moved by the System Program transfers of the transaction
+    pub sol_transferred: f64,
+    pub token_transfers: u64,
+    /// Base units moved by the token transfers of the transaction by mint, transfers whose mint
+    /// is unknown are only counted in `token_transfers`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub token_transferred: BTreeMap<String, u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parse_error: Option<String>,
 }
 
-impl UnprocessedBlock {
-    pub fn new(total_chunks: u64) -> Self {
-        UnprocessedBlock {
-            total_chunks,
-            total_collected_chunks: 0,
-            collected_partial_blocks: BTreeMap::new(),
+/// Category of the problems the parser hit in a block, counted per block so consumers know how
+/// complete its parsed view is
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum ParseErrorKind {
+    /// The transaction could not be decoded from its encoding
+    UndecodableTransaction,
+    /// The transaction uses address lookup tables but the metadata lacks the loaded addresses,
+    /// so its account keys are incomplete
+    UnresolvedLookupAddresses,
+    /// An instruction or account index fell outside the data it refers to
+    SliceError,
+    Other,
+}
+
+impl From<&AggError> for ParseErrorKind {
+    fn from(error: &AggError) -> Self {
+        match error {
+            AggError::UndecodableTransaction => ParseErrorKind::UndecodableTransaction,
+            AggError::ConversionError(_)
+            | AggError::MalformedInstruction(_)
+            | AggError::MalformedTransaction(_) => ParseErrorKind::SliceError,
+            _ => ParseErrorKind::Other,
         }
     }
+}
 
-    pub fn is_complete(&self) -> bool {
-        self.total_chunks == self.total_collected_chunks
+/// A transaction that failed to parse in strict mode, kept in its block as fetched so it can be
+/// inspected and re-parsed later
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct QuarantinedTx {
+    pub error: String,
+    pub raw: EncodedTransactionWithStatusMeta,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct TokenBalance {
+    pub mint: String,
+    pub owner: Option<String>,
+    pub amount: u64,
+    pub decimals: u8,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TokenHolder {
+    pub account: String,
+    pub owner: Option<String>,
+    pub amount: u64,
+    pub decimals: u8,
+}
+
+/// Latest balance of a token account, as of the last block that changed it
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct TokenAccountBalance {
+    pub account: String,
+    pub amount: u64,
+    pub decimals: u8,
+    pub block_no: u64,
+}
+
+/// Balance of an owner in a mint, summed over the token accounts it owns in that mint
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct MintBalance {
+    pub mint: String,
+    pub amount: u64,
+    pub decimals: u8,
+    /// Token accounts holding the balance, largest first
+    pub accounts: Vec<TokenAccountBalance>,
+}
+
+impl MintBalance {
+    /// This function sums the balances of the token accounts of an owner in a mint
+    ///
+    /// # Arguments
+    ///
+    /// * `mint` - A String that holds the mint address
+    /// * `accounts` - A Vec<TokenAccountBalance> that holds the token accounts
+    ///
+    /// # Returns
+    ///
+    /// * `MintBalance` - The balance, zero without any token account
+    pub fn new(mint: String, mut accounts: Vec<TokenAccountBalance>) -> Self {
+        accounts.sort_by_key(|account| std::cmp::Reverse(account.amount));
+        MintBalance {
+            mint,
+            amount: accounts
+                .iter()
+                .fold(0u64, |total, account| total.saturating_add(account.amount)),
+            decimals: accounts.first().map_or(0, |account| account.decimals),
+            accounts,
+        }
     }
+}
+
+/// Where an account was first observed by the aggregator
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct FirstSeen {
+    pub block_no: u64,
+    pub slot: Option<u64>,
+    pub tx_hash: String,
+}
+
+/// Opaque position in a transaction listing. Listings are ordered by slot and transaction id, so
+/// the page after a cursor stays the same while new blocks are appended
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "String")]
+pub struct TxCursor {
+    pub slot: u64,
+    pub tx_id: Vec<u8>,
+}
 
-    pub fn insert_chunk(&mut self, chunk_no: ChunkNo, block: Block) {
-        self.collected_partial_blocks.insert(chunk_no, block);
-        self.total_collected_chunks += 1;
+impl TxCursor {
+    /// This function encodes the cursor as a base58 string of the big endian slot followed by the
+    /// raw transaction id
+    pub fn encode(&self) -> String {
+        let mut bytes = self.slot.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&self.tx_id);
+        bs58::encode(bytes).into_string()
     }
+}
 
-    pub fn complete_the_block(&self) -> Block {
-        let mut block = Block::default();
-        for (_, partial_block) in self.collected_partial_blocks.iter() {
-            block.tx_map.extend(partial_block.tx_map.clone());
-            if let Some(account_map) = &partial_block.account_map {
-                for (account, balance) in account_map.iter() {
-                    block.insert_account(account.clone(), *balance);
-                }
+impl TryFrom<String> for TxCursor {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("{} is not a valid cursor", value);
+        let bytes = bs58::decode(&value).into_vec().map_err(|_| invalid())?;
+        if bytes.len() <= 8 {
+            return Err(invalid());
+        }
+        let (slot, tx_id) = bytes.split_at(8);
+        Ok(TxCursor {
+            slot: u64::from_be_bytes(slot.try_into().map_err(|_| invalid())?),
+            tx_id: tx_id.to_vec(),
+        })
+    }
+}
+
+/// How a transaction moved the SOL or tokens of an account, from its System Program and SPL
+/// Token transfers. Token transfers count for the token accounts and their owners.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TxDirection {
+    /// The account only received
+    In,
+    /// The account only sent
+    Out,
+    /// The account sent and received, e.g. in a swap or a transfer to itself
+    Both,
+    /// The account took part without sending or receiving, e.g. as a signer or program
+    #[default]
+    Other,
+}
+
+/// Value of an `account_txs` entry. Entries written before directions were indexed hold the
+/// block number alone.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(untagged)]
+pub enum AccountTxEntry {
+    Indexed {
+        block_no: u64,
+        direction: TxDirection,
+    },
+    Legacy(u64),
+}
+
+impl AccountTxEntry {
+    pub fn block_no(&self) -> u64 {
+        match self {
+            AccountTxEntry::Indexed { block_no, .. } | AccountTxEntry::Legacy(block_no) => {
+                *block_no
             }
         }
-        block
     }
 }
 
-pub struct Channel<T> {
-    sender: UnboundedSender<T>,
-    pub receiver: UnboundedReceiver<T>,
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AccountTransaction {
+    pub slot: u64,
+    pub block_no: u64,
+    pub tx_id: String,
+    #[serde(default)]
+    pub direction: TxDirection,
 }
 
-impl<T> Channel<T> {
-    pub fn new() -> Self {
-        let (sender, receiver) = unbounded_channel::<T>();
-        Channel { sender, receiver }
+/// A transaction involving an account, streamed to the subscribers of the account
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AccountEvent {
+    pub slot: u64,
+    pub block_no: u64,
+    pub tx_id: String,
+    pub transaction: TxRecord,
+    /// Balance of the account after the block
+    pub balance: Option<u64>,
+    /// True when the event is replayed from the index, false when it is streamed live
+    pub replayed: bool,
+}
+
+/// A webhook receiving the transactions involving an account
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebhookSubscription {
+    pub id: u64,
+    pub account: String,
+    pub url: String,
+    /// Secret the delivered bodies are signed with, only returned when the webhook is created
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub secret: String,
+    /// Unix timestamp at which the subscription was created
+    pub created_at: u64,
+    /// Unix timestamp after which the subscription is removed, never when None
+    pub expires_at: Option<u64>,
+    /// Slot of the last transaction handed to the dispatcher
+    pub last_slot: Option<u64>,
+}
+
+/// Body posted to a webhook
+#[derive(Serialize, Debug)]
+pub struct WebhookDelivery {
+    /// Increases with every delivery, so consumers can detect replays and gaps
+    pub delivery_id: u64,
+    pub webhook_id: u64,
+    pub event: AccountEvent,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeliveryAttempt {
+    /// Unix timestamp of the attempt
+    pub at: u64,
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+}
+
+/// Outcome of a webhook delivery with the history of its attempts
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeliveryReceipt {
+    pub delivery_id: u64,
+    pub webhook_id: u64,
+    pub slot: u64,
+    pub tx_id: String,
+    pub status: DeliveryStatus,
+    pub attempts: Vec<DeliveryAttempt>,
+}
+
+impl DeliveryReceipt {
+    pub fn pending(delivery: &WebhookDelivery) -> Self {
+        Self {
+            delivery_id: delivery.delivery_id,
+            webhook_id: delivery.webhook_id,
+            slot: delivery.event.slot,
+            tx_id: delivery.event.tx_id.clone(),
+            status: DeliveryStatus::Pending,
+            attempts: Vec::new(),
+        }
     }
+}
 
-    pub fn sender(&self) -> UnboundedSender<T> {
-        self.sender.clone()
+/// Step of the ingestion pipeline a block failed in
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureStage {
+    Fetch,
+    Parse,
+    /// A plugin rejected the finalized block
+    Plugin,
+    /// The assembled block violates an invariant of the block or of the finalized blocks
+    Validate,
+    Write,
+}
+
+impl FailureStage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailureStage::Fetch => "fetch",
+            FailureStage::Parse => "parse",
+            FailureStage::Plugin => "plugin",
+            FailureStage::Validate => "validate",
+            FailureStage::Write => "write",
+        }
+    }
+
+    /// This function returns whether a block failing at the stage is dropped before it reaches
+    /// the db, so its block number is never stored
+    pub fn drops_block(&self) -> bool {
+        matches!(
+            self,
+            FailureStage::Parse | FailureStage::Plugin | FailureStage::Validate
+        )
     }
 }
 
-#[derive(Deserialize)]
-pub struct QueryParams {
-    pub(crate) block_no: Option<u64>,
+/// A block the pipeline gave up on, because its error is permanent or it kept failing once
+/// retried, kept in the dead-letter queue of the db for inspection
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeadLetter {
+    /// Assigned by the db when the letter is stored
+    pub id: u64,
+    pub stage: FailureStage,
+    pub slot: Option<u64>,
+    pub block_no: Option<u64>,
+    /// Stable code of the error, see AggError::code
+    pub code: String,
+    pub error: String,
+    /// Whether the error was transient, in which case the retries were exhausted
+    pub transient: bool,
+    pub attempts: u32,
+    /// Unix timestamp at which the block was given up on
+    pub at: u64,
+    /// Every check the block failed, when there are several
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub details: Vec<String>,
+}
+
+impl DeadLetter {
+    /// This function builds the dead letter of a failure
+    ///
+    /// # Arguments
+    ///
+    /// * `stage` - A FailureStage that holds the step that failed
+    /// * `slot` - An Option<u64> that holds the slot of the block, if known
+    /// * `block_no` - An Option<u64> that holds the block number, if known
+    /// * `failure` - A Failure that holds the last error and the attempts made
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The dead letter, without an id
+    pub fn new(
+        stage: FailureStage,
+        slot: Option<u64>,
+        block_no: Option<u64>,
+        failure: &Failure,
+    ) -> Self {
+        Self {
+            id: 0,
+            stage,
+            slot,
+            block_no,
+            code: failure.error.code().to_string(),
+            error: failure.error.to_string(),
+            transient: failure.error.is_transient(),
+            attempts: failure.attempts,
+            at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            details: vec![],
+        }
+    }
+
+    /// This function attaches every check the block failed to the dead letter
+    pub fn with_details(mut self, details: Vec<String>) -> Self {
+        self.details = details;
+        self
+    }
+}
+
+/// Position of an account stream, so a client reconnecting with the same token resumes from it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ResumeCursor {
+    pub token: String,
+    pub account: String,
+    /// Slot of the last event sent to the client
+    pub last_slot: Option<u64>,
+    /// Unix timestamp after which the cursor is removed, pushed back by every event
+    pub expires_at: u64,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct Subscriptions {
+    pub webhooks: Vec<WebhookSubscription>,
+    pub resume_cursors: Vec<ResumeCursor>,
+}
+
+/// A page of the transactions involving an account, newest first
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct AccountTransactions {
+    pub transactions: Vec<AccountTransaction>,
+    /// Cursor of the next page, None on the last page
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AccountSummary {
+    pub account: String,
+    pub balance: Option<u64>,
+    pub first_seen: Option<FirstSeen>,
+    /// Latest changes of the owner, delegate or authorities of the account, newest first
+    #[serde(default)]
+    pub authority_history: Vec<AuthorityEvent>,
+}
+
+#[derive(Default, Clone, Serialize, Deserialize, Debug)]
+pub struct Block {
+    tx_map: HashMap<String, TxRecord>,
+    /// Transaction ids in the order of the block, empty for blocks stored before the order was
+    /// kept
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tx_order: Vec<String>,
+    /// Post balances of the accounts touched by the block. Blocks applied before the balance
+    /// index existed hold the balances of every account seen up to them.
+    account_map: Option<BTreeMap<String, u64>>,
+    #[serde(default)]
+    slot: Option<u64>,
+    /// Height of the block, the number it is stored at. None for blocks stored before the height
+    /// was kept until they are read back.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    block_height: Option<u64>,
+    /// Block time as reported by the node
+    #[serde(default)]
+    block_time: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sanitized_block_time: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    block_time_flag: Option<BlockTimeFlag>,
+    #[serde(default)]
+    blockhash: Option<String>,
+    #[serde(default)]
+    previous_blockhash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    parent_slot: Option<u64>,
+    /// Transactions in the block as served by the node, None for blocks stored before it was
+    /// kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    transaction_count: Option<u64>,
+    #[serde(default)]
+    token_balances: BTreeMap<String, TokenBalance>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    first_seen: BTreeMap<String, String>,
+    #[serde(default)]
+    program_calls: BTreeMap<String, u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    quarantined_txs: Vec<QuarantinedTx>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    parse_errors: BTreeMap<ParseErrorKind, u64>,
+    /// Operator notes on the slot of the block, attached when the block is served and never
+    /// stored with it
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    annotations: Vec<Annotation>,
+}
+
+impl Block {
+    pub fn slot(&self) -> Option<u64> {
+        self.slot
+    }
+
+    pub fn block_height(&self) -> Option<u64> {
+        self.block_height
+    }
+
+    /// This function records the height of the block, which blocks are stored at
+    ///
+    /// # Arguments
+    ///
+    /// * `block_height` - A u64 that holds the block height
+    pub fn set_block_height(&mut self, block_height: u64) {
+        self.block_height = Some(block_height);
+    }
+
+    /// This function returns the sanitized block time, or the reported one for blocks stored
+    /// before times were sanitized
+    pub fn block_time(&self) -> Option<i64> {
+        self.sanitized_block_time.or(self.block_time)
+    }
+
+    /// This function returns the UTC day of the block time, as `YYYY-MM-DD`
+    pub fn day(&self) -> Option<String> {
+        self.block_time()
+            .and_then(|block_time| DateTime::from_timestamp(block_time, 0))
+            .map(|time| time.date_naive().to_string())
+    }
+
+    pub fn raw_block_time(&self) -> Option<i64> {
+        self.block_time
+    }
+
+    pub fn block_time_flag(&self) -> Option<BlockTimeFlag> {
+        self.block_time_flag
+    }
+
+    /// This function records the sanitized block time next to the reported one
+    ///
+    /// # Arguments
+    ///
+    /// * `sanitized` - A SanitizedTime that holds the sanitized time and its flag
+    pub fn set_sanitized_block_time(&mut self, sanitized: SanitizedTime) {
+        self.sanitized_block_time = sanitized.block_time;
+        self.block_time_flag = sanitized.flag;
+    }
+
+    pub fn blockhash(&self) -> Option<&str> {
+        self.blockhash.as_deref()
+    }
+
+    pub fn previous_blockhash(&self) -> Option<&str> {
+        self.previous_blockhash.as_deref()
+    }
+
+    pub fn parent_slot(&self) -> Option<u64> {
+        self.parent_slot
+    }
+
+    pub fn transaction_count(&self) -> Option<u64> {
+        self.transaction_count
+    }
+
+    pub fn set_annotations(&mut self, annotations: Vec<Annotation>) {
+        self.annotations = annotations;
+    }
+
+    /// This function lists the transactions of the block in the order of the block
+    ///
+    /// # Returns
+    ///
+    /// * `impl Iterator<Item = (&str, &TxRecord)>` - The transaction id and its record
+    pub fn transactions(&self) -> impl Iterator<Item = (&str, &TxRecord)> {
+        self.ordered_tx_ids().into_iter().filter_map(|tx_id| {
+            self.tx_map
+                .get_key_value(tx_id)
+                .map(|(tx_id, tx)| (tx_id.as_str(), tx))
+        })
+    }
+
+    /// This function strips the transactions from the block, summarizing them
+    ///
+    /// # Returns
+    ///
+    /// * `(Block, Vec<TxSummary>)` - The block without its transactions and their summaries in
+    ///   the order of the block
+    pub fn into_tx_summaries(mut self) -> (Block, Vec<TxSummary>) {
+        let summaries = self
+            .transactions()
+            .map(|(tx_id, tx)| tx.summary(tx_id))
+            .collect();
+        self.tx_map = HashMap::new();
+        self.tx_order = Vec::new();
+        (self, summaries)
+    }
+
+    /// This function copies the block with only the transactions a filter matches and the
+    /// balances of their accounts. The other aggregates of the block describe every transaction,
+    /// they are left out.
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - A BlockFilter that holds the transactions to keep
+    ///
+    /// # Returns
+    ///
+    /// * `Block` - The filtered block, in the order of the block
+    pub fn filtered(&self, filter: &BlockFilter) -> Block {
+        let mut block = Block {
+            slot: self.slot,
+            block_time: self.block_time,
+            sanitized_block_time: self.sanitized_block_time,
+            block_time_flag: self.block_time_flag,
+            blockhash: self.blockhash.clone(),
+            previous_blockhash: self.previous_blockhash.clone(),
+            parent_slot: self.parent_slot,
+            transaction_count: self.transaction_count,
+            ..Block::default()
+        };
+        for (tx_id, tx) in self.transactions() {
+            if filter.matches(tx) {
+                block.insert_transaction(tx_id.to_string(), tx.clone());
+            }
+        }
+        if let Some(account_map) = &self.account_map {
+            let accounts: HashSet<&str> =
+                block.tx_map.values().flat_map(|tx| tx.parties()).collect();
+            block.account_map = Some(
+                account_map
+                    .iter()
+                    .filter(|(account, _)| accounts.contains(account.as_str()))
+                    .map(|(account, balance)| (account.clone(), *balance))
+                    .collect(),
+            );
+        }
+        block
+    }
+
+    /// This function lists the transaction ids in the order of the block. Blocks stored before
+    /// the order was kept list them in transaction id order.
+    fn ordered_tx_ids(&self) -> Vec<&str> {
+        if self.tx_order.len() == self.tx_map.len() {
+            return self.tx_order.iter().map(String::as_str).collect();
+        }
+        let mut tx_ids: Vec<&str> = self.tx_map.keys().map(String::as_str).collect();
+        tx_ids.sort_unstable();
+        tx_ids
+    }
+
+    /// This function tells whether the block knows the order of its transactions
+    fn has_tx_order(&self) -> bool {
+        self.tx_order.len() == self.tx_map.len()
+    }
+
+    /// This function returns the position of a transaction in the order of the block, None when
+    /// the transaction is not in the block or the block was stored before the order was kept
+    pub fn tx_position(&self, tx_id: &str) -> Option<u32> {
+        if !self.has_tx_order() {
+            return None;
+        }
+        self.tx_order
+            .iter()
+            .position(|ordered| ordered == tx_id)
+            .map(|position| position as u32)
+    }
+
+    /// This function describes where a transaction of the block was included
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number
+    /// * `tx_id` - A string slice that holds the transaction id
+    ///
+    /// # Returns
+    ///
+    /// * `Option<TxInclusion>` - The inclusion, None when the transaction is not in the block
+    pub fn tx_inclusion(&self, block_no: u64, tx_id: &str) -> Option<TxInclusion> {
+        let tx = self.tx_map.get(tx_id)?;
+        Some(TxInclusion {
+            tx_id: tx_id.to_string(),
+            signatures: tx.signatures().to_vec(),
+            block_no,
+            slot: self.slot,
+            blockhash: self.blockhash.clone(),
+            previous_blockhash: self.previous_blockhash.clone(),
+            parent_slot: self.parent_slot,
+            block_time: self.block_time(),
+            transaction_count: self.transaction_count,
+            position: self.tx_position(tx_id),
+        })
+    }
+
+    pub fn set_header(&mut self, header: BlockHeader) {
+        self.slot = Some(header.slot);
+        self.blockhash = Some(header.blockhash);
+        self.block_time = header.block_time;
+        self.previous_blockhash = header.previous_blockhash;
+        self.parent_slot = header.parent_slot;
+        self.transaction_count = header.transaction_count;
+    }
+
+    pub fn record_program_call(&mut self, program_id: String) {
+        *self.program_calls.entry(program_id).or_default() += 1;
+    }
+
+    pub fn get_program_calls(&self) -> &BTreeMap<String, u64> {
+        &self.program_calls
+    }
+
+    pub fn record_parse_error(&mut self, kind: ParseErrorKind) {
+        *self.parse_errors.entry(kind).or_default() += 1;
+    }
+
+    /// This function returns the number of parse errors of the block by category, empty when the
+    /// whole block was parsed
+    pub fn parse_errors(&self) -> &BTreeMap<ParseErrorKind, u64> {
+        &self.parse_errors
+    }
+
+    /// This function lists the decoded SOL transfers of the block
+    ///
+    /// # Returns
+    ///
+    /// * `impl Iterator<Item = (&str, &str, f64)>` - The sender, receiver and SOL amount
+    pub fn transfers(&self) -> impl Iterator<Item = (&str, &str, f64)> {
+        self.transactions()
+            .flat_map(|(_, tx)| tx.instruction.iter())
+            .filter_map(|instruction| match instruction {
+                Instruction::Transfer(from, to, amount) => {
+                    Some((from.as_str(), to.as_str(), *amount))
+                }
+                Instruction::TokenTransfer { .. } | Instruction::Authority { .. } => None,
+            })
+    }
+
+    /// This function lists the owner, delegate and authority changes made by the transactions of
+    /// the block that succeeded
+    ///
+    /// # Returns
+    ///
+    /// * `impl Iterator<Item = (&str, &str, &AuthorityChange)>` - The transaction id, the
+    ///   account and the change
+    pub fn authority_changes(&self) -> impl Iterator<Item = (&str, &str, &AuthorityChange)> {
+        self.transactions()
+            .filter(|(_, tx)| tx.succeeded != Some(false))
+            .flat_map(|(tx_id, tx)| {
+                tx.instruction
+                    .iter()
+                    .filter_map(move |instruction| match instruction {
+                        Instruction::Authority { account, change } => {
+                            Some((tx_id, account.as_str(), change))
+                        }
+                        _ => None,
+                    })
+            })
+    }
+
+    pub fn insert_account(&mut self, account: String, balance: u64) {
+        if let Some(account_map) = &mut self.account_map {
+            account_map.insert(account, balance);
+        } else {
+            let mut account_map = BTreeMap::new();
+            account_map.insert(account, balance);
+            self.account_map = Some(account_map);
+        }
+    }
+
+    /// This function records the post balance of a token account observed in the block
+    ///
+    /// # Arguments
+    ///
+    /// * `token_account` - A String that holds the token account address
+    /// * `balance` - A TokenBalance that holds the mint, owner and amount
+    pub fn insert_token_balance(&mut self, token_account: String, balance: TokenBalance) {
+        self.token_balances.insert(token_account, balance);
+    }
+
+    /// This function records the transaction an account is observed in, keeping the first one
+    ///
+    /// # Arguments
+    ///
+    /// * `account` - A String that holds the account
+    /// * `tx_hash` - A string slice that holds the transaction hash
+    pub fn observe_account(&mut self, account: String, tx_hash: &str) {
+        self.first_seen
+            .entry(account)
+            .or_insert_with(|| tx_hash.to_string());
+    }
+
+    pub fn get_first_seen(&self) -> &BTreeMap<String, String> {
+        &self.first_seen
+    }
+
+    pub fn get_token_balances(&self) -> &BTreeMap<String, TokenBalance> {
+        &self.token_balances
+    }
+
+    /// This function lists the transactions of the block involving an account
+    ///
+    /// # Arguments
+    ///
+    /// * `account` - A string slice that holds the account
+    ///
+    /// # Returns
+    ///
+    /// * `impl Iterator<Item = (&str, &TxRecord)>` - The transaction id and its record
+    pub fn account_transactions<'a>(
+        &'a self,
+        account: &'a str,
+    ) -> impl Iterator<Item = (&'a str, &'a TxRecord)> {
+        self.tx_map
+            .iter()
+            .filter(move |(_, tx)| tx.accounts.iter().any(|key| key == account))
+            .map(|(tx_id, tx)| (tx_id.as_str(), tx))
+    }
+
+    pub fn get_tx_details(&self, tx_hash: &str) -> Option<&TxRecord> {
+        self.tx_map.get(tx_hash)
+    }
+
+    /// This function appends a transaction to the block, a transaction already in the block
+    /// keeps its position
+    pub fn push_transaction(&mut self, tx_hash: Hash, tx: TxRecord) {
+        self.insert_transaction(tx_hash.to_string(), tx);
+    }
+
+    fn insert_transaction(&mut self, tx_id: String, tx: TxRecord) {
+        if self.tx_map.insert(tx_id.clone(), tx).is_none() {
+            self.tx_order.push(tx_id);
+        }
+    }
+
+    /// This function keeps a transaction that failed to parse in strict mode
+    pub fn quarantine_transaction(&mut self, tx: QuarantinedTx) {
+        self.quarantined_txs.push(tx);
+    }
+
+    pub fn quarantined_transactions(&self) -> &[QuarantinedTx] {
+        &self.quarantined_txs
+    }
+
+    /// This function replaces the stored transaction records with the ones of a re-parsed block
+    ///
+    /// Only the transactions already in the block are replaced, balances and the rest of the
+    /// block are left as they were applied. Blocks stored before the transaction order was kept
+    /// take the order of the re-parsed block.
+    ///
+    /// # Arguments
+    ///
+    /// * `reparsed` - A Block that holds the block re-parsed from its raw transactions
+    ///
+    /// # Returns
+    ///
+    /// * `u64` - The number of upgraded transaction records
+    pub fn upgrade_transactions(&mut self, reparsed: Block) -> u64 {
+        let mut upgraded = 0;
+        if !self.has_tx_order() && reparsed.has_tx_order() {
+            let mut tx_order: Vec<String> = reparsed
+                .tx_order
+                .iter()
+                .filter(|tx_id| self.tx_map.contains_key(*tx_id))
+                .cloned()
+                .collect();
+            // Transactions the re-parsed block lacks follow the others in transaction id order
+            let ordered: HashSet<&String> = tx_order.iter().collect();
+            let mut rest: Vec<String> = self
+                .tx_map
+                .keys()
+                .filter(|tx_id| !ordered.contains(tx_id))
+                .cloned()
+                .collect();
+            rest.sort_unstable();
+            tx_order.extend(rest);
+            self.tx_order = tx_order;
+        }
+        for (tx_id, tx) in reparsed.tx_map {
+            if let Some(record) = self.tx_map.get_mut(&tx_id) {
+                *record = tx;
+                upgraded += 1;
+            }
+        }
+        upgraded
+    }
+
+    /// This function counts the transactions of the block
+    ///
+    /// # Returns
+    ///
+    /// * `(u64, u64, u64)` - The number of transactions, successful transactions and total fees
+    pub fn tx_stats(&self) -> (u64, u64, u64) {
+        self.tx_map
+            .values()
+            .fold((0, 0, 0), |(total, successful, fees), tx| {
+                (
+                    total + 1,
+                    successful + u64::from(tx.succeeded.unwrap_or_default()),
+                    fees + tx.fee.unwrap_or_default(),
+                )
+            })
+    }
+
+    /// This function builds the summary stored alongside the block
+    ///
+    /// # Arguments
+    ///
+    /// * `block_no` - A u64 that holds the block number
+    ///
+    /// # Returns
+    ///
+    /// * `BlockSummary` - The summary of the block
+    pub fn summary(&self, block_no: u64) -> BlockSummary {
+        let (transactions, successful_transactions, total_fees) = self.tx_stats();
+        BlockSummary {
+            block_no,
+            slot: self.slot,
+            blockhash: self.blockhash.clone(),
+            block_time: self.block_time(),
+            raw_block_time: self.block_time,
+            block_time_flag: self.block_time_flag,
+            transactions,
+            successful_transactions,
+            total_fees,
+            accounts: self.account_map.as_ref().map_or(0, |map| map.len() as u64),
+            fee_payers: self.fee_payers().len() as u64,
+            active_accounts: self.active_accounts().len() as u64,
+            parse_errors: self.parse_errors.clone(),
+        }
+    }
+
+    /// This function lists the distinct fee payers of the block, the first account key of every
+    /// transaction
+    pub fn fee_payers(&self) -> HashSet<&str> {
+        self.tx_map
+            .values()
+            .filter_map(|tx| tx.accounts.first().map(String::as_str))
+            .collect()
+    }
+
+    /// This function lists the distinct accounts the transactions of the block touched, programs
+    /// and accounts only read included
+    pub fn active_accounts(&self) -> HashSet<&str> {
+        self.tx_map
+            .values()
+            .flat_map(|tx| tx.accounts.iter().map(String::as_str))
+            .collect()
+    }
+
+    pub fn get_tx_hash(&self) -> Vec<String> {
+        self.ordered_tx_ids()
+            .into_iter()
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// This function lists the transaction ids of the block with their position in the block
+    pub fn tx_offsets(&self) -> BTreeMap<String, u32> {
+        self.ordered_tx_ids()
+            .into_iter()
+            .enumerate()
+            .map(|(offset, tx_id)| (tx_id.to_string(), offset as u32))
+            .collect()
+    }
+
+    /// This function lists the account keys of every transaction of the block
+    ///
+    /// # Returns
+    ///
+    /// * `impl Iterator<Item = (&str, &[String])>` - The transaction id and its account keys
+    pub fn tx_accounts(&self) -> impl Iterator<Item = (&str, &[String])> {
+        self.tx_map
+            .iter()
+            .map(|(tx_id, tx)| (tx_id.as_str(), tx.accounts.as_slice()))
+    }
+
+    pub fn get_account_balance(&self, account: &str) -> Option<u64> {
+        if let Some(account_map) = &self.account_map {
+            account_map.get(account).cloned()
+        } else {
+            None
+        }
+    }
+
+    pub fn get_account_map(&self) -> Option<BTreeMap<String, u64>> {
+        self.account_map.clone()
+    }
+
+    pub fn set_account_map(&mut self, account_map: BTreeMap<String, u64>) {
+        self.account_map = Some(account_map);
+    }
+
+    /// This function computes a canonical hash of the parsed block contents
+    ///
+    /// Transactions are hashed in signature order together with their decoded instructions,
+    /// followed by the balances observed in the block, so two instances that indexed the same
+    /// block produce the same digest regardless of chunk arrival order.
+    ///
+    /// # Returns
+    ///
+    /// * `Hash` - The digest of the block
+    pub fn digest(&self) -> Hash {
+        let mut hasher = Hasher::default();
+        let mut txs: Vec<(&String, &TxRecord)> = self.tx_map.iter().collect();
+        txs.sort_by_key(|(tx_hash, _)| *tx_hash);
+        for (tx_hash, tx) in txs {
+            hasher.hash(tx_hash.as_bytes());
+            if let Ok(instructions) = serde_json::to_vec(&tx.instruction) {
+                hasher.hash(&instructions);
+            }
+        }
+        if let Some(account_map) = &self.account_map {
+            for (account, balance) in account_map.iter() {
+                hasher.hashv(&[account.as_bytes(), &balance.to_le_bytes()]);
+            }
+        }
+        hasher.result()
+    }
+}
+
+/// Wire form of a transaction record, every field serialized
+#[derive(Serialize, Deserialize)]
+pub struct TxRecordWire {
+    instruction: Vec<Instruction>,
+    metadata: Option<String>,
+    fee: Option<u64>,
+    succeeded: Option<bool>,
+    accounts: Vec<String>,
+    parse_error: Option<String>,
+    logs: Vec<String>,
+    compute_units: Option<u64>,
+    error: Option<String>,
+    signatures: Vec<String>,
+}
+
+impl From<&TxRecord> for TxRecordWire {
+    fn from(record: &TxRecord) -> Self {
+        TxRecordWire {
+            instruction: record.instruction.clone(),
+            metadata: record.metadata.clone(),
+            fee: record.fee,
+            succeeded: record.succeeded,
+            accounts: record.accounts.clone(),
+            parse_error: record.parse_error.clone(),
+            logs: record.logs.clone(),
+            compute_units: record.compute_units,
+            error: record.error.clone(),
+            signatures: record.signatures.clone(),
+        }
+    }
+}
+
+impl From<TxRecordWire> for TxRecord {
+    fn from(wire: TxRecordWire) -> Self {
+        TxRecord {
+            instruction: wire.instruction,
+            metadata: wire.metadata,
+            fee: wire.fee,
+            succeeded: wire.succeeded,
+            accounts: wire.accounts,
+            parse_error: wire.parse_error,
+            logs: wire.logs,
+            compute_units: wire.compute_units,
+            error: wire.error,
+            signatures: wire.signatures,
+        }
+    }
+}
+
+/// Wire form of a block, every field serialized. Quarantined transactions hold their raw
+/// encoding as JSON, which bincode cannot encode, and annotations are never stored.
+#[derive(Serialize, Deserialize)]
+pub struct BlockWire {
+    tx_map: HashMap<String, TxRecordWire>,
+    tx_order: Vec<String>,
+    account_map: Option<BTreeMap<String, u64>>,
+    slot: Option<u64>,
+    block_height: Option<u64>,
+    block_time: Option<i64>,
+    sanitized_block_time: Option<i64>,
+    block_time_flag: Option<BlockTimeFlag>,
+    blockhash: Option<String>,
+    previous_blockhash: Option<String>,
+    parent_slot: Option<u64>,
+    transaction_count: Option<u64>,
+    token_balances: BTreeMap<String, TokenBalance>,
+    first_seen: BTreeMap<String, String>,
+    program_calls: BTreeMap<String, u64>,
+    /// Error and JSON encoding of the quarantined transactions
+    quarantined_txs: Vec<(String, String)>,
+    parse_errors: BTreeMap<ParseErrorKind, u64>,
+}
+
+impl Record for Block {
+    type Wire = BlockWire;
+
+    fn to_wire(&self) -> Result<BlockWire, AggError> {
+        Ok(BlockWire {
+            tx_map: self
+                .tx_map
+                .iter()
+                .map(|(tx_id, record)| (tx_id.clone(), record.into()))
+                .collect(),
+            tx_order: self.tx_order.clone(),
+            account_map: self.account_map.clone(),
+            slot: self.slot,
+            block_height: self.block_height,
+            block_time: self.block_time,
+            sanitized_block_time: self.sanitized_block_time,
+            block_time_flag: self.block_time_flag,
+            blockhash: self.blockhash.clone(),
+            previous_blockhash: self.previous_blockhash.clone(),
+            parent_slot: self.parent_slot,
+            transaction_count: self.transaction_count,
+            token_balances: self.token_balances.clone(),
+            first_seen: self.first_seen.clone(),
+            program_calls: self.program_calls.clone(),
+            quarantined_txs: self
+                .quarantined_txs
+                .iter()
+                .map(|tx| Ok((tx.error.clone(), serde_json::to_string(&tx.raw)?)))
+                .collect::<Result<_, AggError>>()?,
+            parse_errors: self.parse_errors.clone(),
+        })
+    }
+
+    fn from_wire(wire: BlockWire) -> Result<Self, AggError> {
+        Ok(Block {
+            tx_map: wire
+                .tx_map
+                .into_iter()
+                .map(|(tx_id, record)| (tx_id, record.into()))
+                .collect(),
+            tx_order: wire.tx_order,
+            account_map: wire.account_map,
+            slot: wire.slot,
+            block_height: wire.block_height,
+            block_time: wire.block_time,
+            sanitized_block_time: wire.sanitized_block_time,
+            block_time_flag: wire.block_time_flag,
+            blockhash: wire.blockhash,
+            previous_blockhash: wire.previous_blockhash,
+            parent_slot: wire.parent_slot,
+            transaction_count: wire.transaction_count,
+            token_balances: wire.token_balances,
+            first_seen: wire.first_seen,
+            program_calls: wire.program_calls,
+            quarantined_txs: wire
+                .quarantined_txs
+                .into_iter()
+                .map(|(error, raw)| {
+                    Ok(QuarantinedTx {
+                        error,
+                        raw: serde_json::from_str(&raw)?,
+                    })
+                })
+                .collect::<Result<_, AggError>>()?,
+            parse_errors: wire.parse_errors,
+            annotations: Vec::new(),
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct UnprocessedBlock {
+    total_chunks: u64,
+    collected_partial_blocks: BTreeMap<ChunkNo, Block>,
+}
+
+impl UnprocessedBlock {
+    pub fn new(total_chunks: u64) -> Self {
+        UnprocessedBlock {
+            total_chunks,
+            collected_partial_blocks: BTreeMap::new(),
+        }
+    }
+
+    pub fn total_chunks(&self) -> u64 {
+        self.total_chunks
+    }
+
+    /// This function returns the collected chunks by chunk number
+    pub fn chunks(&self) -> impl Iterator<Item = (ChunkNo, &Block)> {
+        self.collected_partial_blocks
+            .iter()
+            .map(|(chunk_no, block)| (*chunk_no, block))
+    }
+
+    /// This function checks whether every chunk of the block has been collected
+    pub fn is_complete(&self) -> bool {
+        self.collected_partial_blocks.len() as u64 == self.total_chunks
+    }
+
+    /// This function collects a parsed chunk. A chunk delivered twice is collected once, so
+    /// duplicates can not make a block look complete while another chunk is still missing
+    ///
+    /// # Arguments
+    ///
+    /// * `chunk_no` - A ChunkNo that holds the chunk number
+    /// * `block` - A Block that holds the parsed chunk
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), AggError>` - An error if the chunk number is out of range
+    pub fn insert_chunk(&mut self, chunk_no: ChunkNo, block: Block) -> Result<(), AggError> {
+        if chunk_no >= self.total_chunks {
+            return Err(AggError::InvalidChunk(chunk_no, self.total_chunks));
+        }
+        self.collected_partial_blocks
+            .entry(chunk_no)
+            .or_insert(block);
+        Ok(())
+    }
+
+    /// This function merges the collected chunks into the complete block
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Block, AggError>` - The block, or an error if a chunk is still missing
+    pub fn complete_the_block(&self) -> Result<Block, AggError> {
+        if !self.is_complete() {
+            return Err(AggError::IncompleteBlock(
+                self.collected_partial_blocks.len() as u64,
+                self.total_chunks,
+            ));
+        }
+        let mut block = Block::default();
+        for (_, partial_block) in self.collected_partial_blocks.iter() {
+            block.slot = block.slot.or(partial_block.slot);
+            block.block_time = block.block_time.or(partial_block.block_time);
+            block.blockhash = block.blockhash.clone().or(partial_block.blockhash.clone());
+            block.previous_blockhash = block
+                .previous_blockhash
+                .clone()
+                .or(partial_block.previous_blockhash.clone());
+            block.parent_slot = block.parent_slot.or(partial_block.parent_slot);
+            block.transaction_count = block.transaction_count.or(partial_block.transaction_count);
+            for (tx_id, tx) in partial_block.transactions() {
+                block.insert_transaction(tx_id.to_string(), tx.clone());
+            }
+            block
+                .token_balances
+                .extend(partial_block.token_balances.clone());
+            for (account, tx_hash) in partial_block.first_seen.iter() {
+                block.observe_account(account.clone(), tx_hash);
+            }
+            for (program_id, calls) in partial_block.program_calls.iter() {
+                *block.program_calls.entry(program_id.clone()).or_default() += calls;
+            }
+            block
+                .quarantined_txs
+                .extend(partial_block.quarantined_txs.iter().cloned());
+            for (kind, count) in partial_block.parse_errors.iter() {
+                *block.parse_errors.entry(*kind).or_default() += count;
+            }
+            if let Some(account_map) = &partial_block.account_map {
+                for (account, balance) in account_map.iter() {
+                    block.insert_account(account.clone(), *balance);
+                }
+            }
+        }
+        Ok(block)
+    }
+}
+
+pub struct Channel<T> {
+    sender: UnboundedSender<T>,
+    pub receiver: UnboundedReceiver<T>,
+}
+
+impl<T> Channel<T> {
+    pub fn new() -> Self {
+        let (sender, receiver) = unbounded_channel::<T>();
+        Channel { sender, receiver }
+    }
+
+    pub fn sender(&self) -> UnboundedSender<T> {
+        self.sender.clone()
+    }
+}
+
+impl<T> Default for Channel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct BlockDigest {
+    pub block_no: u64,
+    pub digest: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Status {
+    pub latest_block_no: Option<u64>,
+    pub read_only: bool,
+    pub durability: DurabilityConfig,
+    pub compaction: CompactionStats,
+    pub chain: ChainStatus,
+    /// Genesis hash of the cluster the db holds data of, None until a subscriber first ingested
+    pub genesis_hash: Option<String>,
+    /// Sync state of the chain the instance indexes, a process indexing a single chain, empty
+    /// until its chain id is set
+    #[serde(default)]
+    pub chains: Vec<SyncState>,
+    /// Notes of the latest annotated slots, oldest first
+    #[serde(default)]
+    pub annotations: Vec<Annotation>,
+}
+
+/// Note an operator attached to a slot, e.g. the start of an incident window or of a parser
+/// version, to help interpret the data around it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Annotation {
+    pub slot: SlotNo,
+    pub note: String,
+    /// Unix time the note was attached at
+    pub created_at: u64,
+}
+
+/// How the sanitized time of a block differs from the time reported by the node
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockTimeFlag {
+    /// The node reported no time, it was interpolated from the neighbouring blocks
+    Interpolated,
+    /// The time regressed behind an earlier block or ran ahead of a later one and was clamped
+    OutOfOrder,
+}
+
+/// Outcome of checking the previous blockhash of a block against the blockhash of its parent
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChainLink {
+    Verified,
+    /// The parent is not stored, is quarantined or either hash is unknown
+    Unverified,
+    /// The hashes differ, the node served inconsistent blocks or the chain reorganized
+    Broken,
+}
+
+/// A block quarantined because it does not chain onto its parent
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChainBreak {
+    pub block_no: u64,
+    pub slot: Option<u64>,
+    pub previous_blockhash: Option<String>,
+    pub parent_blockhash: Option<String>,
+}
+
+/// Continuity of the stored chain, counted over every block whose state was handled
+#[derive(Default, Serialize, Deserialize, Debug, Clone)]
+pub struct ChainStatus {
+    pub verified_blocks: u64,
+    pub unverified_blocks: u64,
+    pub broken_links: u64,
+    /// Transactions found in blocks of two different slots, see TxConflict
+    #[serde(default)]
+    pub tx_conflicts: u64,
+    pub last_verified_block_no: Option<u64>,
+    /// Reorgs followed by rolling back the blocks of the abandoned fork
+    #[serde(default)]
+    pub reorgs: u64,
+    #[serde(default)]
+    pub rolled_back_blocks: u64,
+    /// The latest quarantined blocks, oldest first
+    pub quarantined_blocks: Vec<u64>,
+}
+
+/// Lamport balance of an account after a block that touched it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AccountDelta {
+    pub block_no: u64,
+    pub balance: u64,
+}
+
+/// Balance of an account, distinguishing an account holding zero lamports from one never observed
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AccountBalance {
+    pub known: bool,
+    pub balance: u64,
+    /// Slot the balance was requested at, the slot of the requested block
+    pub as_of_slot: Option<u64>,
+    /// Slot of the last block at or before `as_of_slot` that touched the account
+    pub observed_slot: Option<u64>,
+    pub observed_block_no: Option<u64>,
+    pub source: Option<BalanceSource>,
+}
+
+/// Balances of several accounts as of the same block
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BalancesAt {
+    pub block_no: u64,
+    pub slot: Option<u64>,
+    /// Balance of every account asked for, by public key
+    pub balances: BTreeMap<String, AccountBalance>,
+}
+
+/// Whether a balance was observed in the requested block or carried over from an earlier block
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BalanceSource {
+    RequestedBlock,
+    EarlierBlock,
+}
+
+impl AccountBalance {
+    pub fn new(balance: Option<u64>, as_of_slot: Option<u64>) -> Self {
+        AccountBalance {
+            known: balance.is_some(),
+            balance: balance.unwrap_or_default(),
+            as_of_slot,
+            observed_slot: None,
+            observed_block_no: None,
+            source: None,
+        }
+    }
+
+    /// This function records the block the balance was last observed in
+    ///
+    /// # Arguments
+    ///
+    /// * `observed_slot` - A u64 that holds the slot of the block that touched the account
+    /// * `observed_block_no` - A u64 that holds the number of that block
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The balance with its provenance
+    pub fn observed(mut self, observed_slot: u64, observed_block_no: u64) -> Self {
+        self.source = Some(if Some(observed_slot) == self.as_of_slot {
+            BalanceSource::RequestedBlock
+        } else {
+            BalanceSource::EarlierBlock
+        });
+        self.observed_slot = Some(observed_slot);
+        self.observed_block_no = Some(observed_block_no);
+        self
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct BalanceChange {
+    pub slot: u64,
+    pub block_no: u64,
+    pub balance: u64,
+    /// Difference with the previous recorded balance, None for the first entry of the history
+    pub change: Option<i128>,
+}
+
+/// Slots and blocks of the first and last stored block whose block time falls inside a time window
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct TimeRange {
+    pub start_slot: u64,
+    pub end_slot: u64,
+    pub start_block_no: u64,
+    pub end_block_no: u64,
+}
+
+/// Lightweight per block record served by list endpoints without loading the full block
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlockSummary {
+    pub block_no: u64,
+    pub slot: Option<u64>,
+    pub blockhash: Option<String>,
+    /// Sanitized block time
+    pub block_time: Option<i64>,
+    /// Block time as reported by the node
+    #[serde(default)]
+    pub raw_block_time: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_time_flag: Option<BlockTimeFlag>,
+    pub transactions: u64,
+    pub successful_transactions: u64,
+    pub total_fees: u64,
+    pub accounts: u64,
+    /// Distinct fee payers, zero for blocks stored before they were counted
+    #[serde(default)]
+    pub fee_payers: u64,
+    /// Distinct accounts touched by the transactions, zero for blocks stored before they were
+    /// counted
+    #[serde(default)]
+    pub active_accounts: u64,
+    /// Parse errors by category, empty when the whole block was parsed
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub parse_errors: BTreeMap<ParseErrorKind, u64>,
+}
+
+/// Sketches of the fee payers and accounts active over a UTC day, stored per day and merged with
+/// every block of the day once the block can no longer be rolled back by a reorg
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ActiveAccountsDay {
+    pub blocks: u64,
+    pub fee_payers: HyperLogLog,
+    pub accounts: HyperLogLog,
+}
+
+impl ActiveAccountsDay {
+    /// This function adds the fee payers and accounts of a block to the day
+    ///
+    /// # Arguments
+    ///
+    /// * `block` - A Block that holds the block
+    pub fn add_block(&mut self, block: &Block) {
+        self.blocks += 1;
+        self.add_accounts(block);
+    }
+
+    /// This function adds the fee payers and accounts of a block to the day without counting
+    /// the block, for a block delivered again
+    ///
+    /// # Arguments
+    ///
+    /// * `block` - A Block that holds the block
+    pub fn add_accounts(&mut self, block: &Block) {
+        for fee_payer in block.fee_payers() {
+            self.fee_payers.insert(fee_payer.as_bytes());
+        }
+        for account in block.active_accounts() {
+            self.accounts.insert(account.as_bytes());
+        }
+    }
+
+    /// This function merges the blocks and sketches of another part of the day into this one
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - A reference to the ActiveAccountsDay that holds the other part
+    pub fn merge(&mut self, other: &ActiveAccountsDay) {
+        self.blocks += other.blocks;
+        self.fee_payers.merge(&other.fee_payers);
+        self.accounts.merge(&other.accounts);
+    }
+
+    /// This function estimates the counts of the day
+    ///
+    /// # Arguments
+    ///
+    /// * `day` - A String that holds the day as `YYYY-MM-DD`
+    ///
+    /// # Returns
+    ///
+    /// * `ActiveAccountsStats` - The estimated distinct fee payers and accounts of the day
+    pub fn stats(&self, day: String) -> ActiveAccountsStats {
+        ActiveAccountsStats {
+            day,
+            blocks: self.blocks,
+            fee_payers: self.fee_payers.count(),
+            active_accounts: self.accounts.count(),
+        }
+    }
+}
+
+/// Distinct fee payers and accounts of a UTC day, estimated with a standard error of about 1.6%
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ActiveAccountsStats {
+    pub day: String,
+    pub blocks: u64,
+    pub fee_payers: u64,
+    pub active_accounts: u64,
+}
+
+#[derive(Default, Serialize, Debug)]
+pub struct DeletedSlots {
+    pub block_nos: Vec<u64>,
+    pub transactions: u64,
+}
+
+/// Outcome of replacing the stored block of a slot with the block fetched from the node again
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct RefetchedBlock {
+    pub slot: u64,
+    pub block_no: u64,
+    pub transactions: u64,
+    /// Block number the replaced block was stored at, None when the slot held no block
+    pub replaced: Option<u64>,
+}
+
+/// Block fetched again whose replaced block is deleted but which is not stored yet, recorded with
+/// the deletion so a db stopped in between stores it when started again
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PendingRefetch {
+    pub block: Block,
+    /// Raw block archived with the block, when the replaced block was archived
+    pub raw_block: Option<RawBlock>,
+}
+
+/// Wire form of a pending refetch
+#[derive(Serialize, Deserialize)]
+pub struct PendingRefetchWire {
+    block: BlockWire,
+    raw_block: Option<RawBlockWire>,
+}
+
+impl Record for PendingRefetch {
+    type Wire = PendingRefetchWire;
+
+    fn to_wire(&self) -> Result<PendingRefetchWire, AggError> {
+        Ok(PendingRefetchWire {
+            block: self.block.to_wire()?,
+            raw_block: self.raw_block.as_ref().map(RawBlock::to_wire).transpose()?,
+        })
+    }
+
+    fn from_wire(wire: PendingRefetchWire) -> Result<Self, AggError> {
+        Ok(PendingRefetch {
+            block: Block::from_wire(wire.block)?,
+            raw_block: wire.raw_block.map(RawBlock::from_wire).transpose()?,
+        })
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Debug, Clone)]
+pub struct CompactionStats {
+    pub runs: u64,
+    /// Unix timestamp at which the last run started
+    pub last_run_at: Option<u64>,
+    pub last_duration_ms: Option<u64>,
+    /// Block range compacted by the last run, None if the whole db was compacted
+    pub last_range: Option<(u64, u64)>,
+    /// Blocks up to this one have been compacted by the schedule
+    pub compacted_through: Option<u64>,
+    pub pending_compaction_bytes: Option<u64>,
+}
+
+#[derive(Default, Serialize, Debug)]
+pub struct IndexedSlots {
+    pub indexed: Vec<u64>,
+    pub skipped: Vec<u64>,
+    /// First slot not scanned because the limit was reached
+    pub next_slot: Option<u64>,
+}
+
+/// What the id of `/block_details/{id}` is, `?by=height|slot`, the block height by default
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockLookup {
+    #[default]
+    Height,
+    Slot,
+}
+
+#[derive(Deserialize, Default)]
+pub struct BlockLookupParams {
+    #[serde(default)]
+    pub by: BlockLookup,
+}
+
+/// Commitment the blocks and transactions of a response must have reached,
+/// `?commitment=confirmed|finalized`
+#[derive(Deserialize, Default)]
+pub struct CommitmentParams {
+    pub commitment: Option<Commitment>,
+}
+
+#[derive(Deserialize)]
+pub struct CursorParams {
+    pub(crate) cursor: Option<TxCursor>,
+    pub(crate) limit: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct SlotRangeParams {
+    pub(crate) start: u64,
+    pub(crate) end: Option<u64>,
+    pub(crate) limit: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct CompactParams {
+    pub(crate) start: Option<u64>,
+    pub(crate) end: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct DeleteSlotsParams {
+    pub(crate) start: u64,
+    pub(crate) end: u64,
+}
+
+#[derive(Deserialize)]
+pub struct AnnotationParams {
+    pub(crate) note: String,
+}
+
+#[derive(Deserialize)]
+pub struct ReparseParams {
+    pub(crate) start: u64,
+    pub(crate) end: u64,
+}
+
+#[derive(Deserialize)]
+pub struct ExportParams {
+    pub(crate) start_slot: u64,
+    pub(crate) end_slot: u64,
+    pub(crate) format: ExportFormat,
+    pub(crate) shards: Option<u64>,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    Reindex,
+    Prune,
+    Export,
+}
+
+#[derive(Deserialize)]
+pub struct JobParams {
+    pub(crate) kind: JobKind,
+    /// First block of a re-index job, first slot of the other jobs
+    pub(crate) start: u64,
+    pub(crate) end: u64,
+    pub(crate) format: Option<ExportFormat>,
+    pub(crate) shards: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct LogLevelParams {
+    /// Target such as `db` or `subscriber`, the default level when omitted
+    pub(crate) target: Option<String>,
+    pub(crate) level: String,
+}
+
+#[derive(Deserialize)]
+pub struct LimitParams {
+    pub(crate) limit: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct DaysParams {
+    pub(crate) days: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct AccountBalanceParams {
+    pub(crate) block_no: Option<u64>,
+    pub(crate) slot: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct BalancesAtParams {
+    pub(crate) accounts: Vec<String>,
+    pub(crate) block_no: u64,
+}
+
+#[derive(Deserialize)]
+pub struct BalanceHistoryParams {
+    pub(crate) start_slot: Option<u64>,
+    pub(crate) end_slot: Option<u64>,
+    pub(crate) from: Option<Timestamp>,
+    pub(crate) to: Option<Timestamp>,
+    pub(crate) limit: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct TimeRangeParams {
+    pub(crate) start_time: Timestamp,
+    pub(crate) end_time: Timestamp,
+}
+
+/// An RFC3339 timestamp taken from the query string, held as unix seconds
+#[derive(Deserialize, Clone, Copy)]
+#[serde(try_from = "String")]
+pub struct Timestamp(u64);
+
+impl Timestamp {
+    pub fn unix_secs(&self) -> u64 {
+        self.0
+    }
+}
+
+impl TryFrom<String> for Timestamp {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let time = chrono::DateTime::parse_from_rfc3339(&value)
+            .map_err(|_| format!("{} is not an RFC3339 timestamp", value))?;
+        let secs = u64::try_from(time.timestamp())
+            .map_err(|_| format!("{} is before the unix epoch", value))?;
+        Ok(Timestamp(secs))
+    }
+}
+
+/// A base58 encoded public key taken from the request path
+#[derive(Deserialize)]
+#[serde(try_from = "String")]
+pub struct AccountId(String);
+
+impl AccountId {
+    pub fn into_string(self) -> String {
+        self.0
+    }
+}
+
+impl TryFrom<String> for AccountId {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Pubkey::from_str(&value)
+            .map_err(|_| format!("{} is not a base58 encoded public key", value))?;
+        Ok(AccountId(value))
+    }
+}
+
+/// A base58 encoded transaction id taken from the request path, either the message hash the
+/// transactions are indexed by or a signature
+#[derive(Deserialize)]
+#[serde(try_from = "String")]
+pub struct TxId(String);
+
+impl TxId {
+    pub fn into_string(self) -> String {
+        self.0
+    }
+}
+
+impl TryFrom<String> for TxId {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if Hash::from_str(&value).is_err() && Signature::from_str(&value).is_err() {
+            return Err(format!("{} is not a base58 encoded transaction id", value));
+        }
+        Ok(TxId(value))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct QueryParams {
+    pub(crate) block_no: Option<u64>,
+}
+
+/// Accounts a block stream is filtered on
+pub(crate) const MAX_FILTER_ACCOUNTS: usize = 100;
+
+/// A comma separated list of base58 encoded public keys taken from the query string
+#[derive(Deserialize, Default)]
+#[serde(try_from = "String")]
+pub struct AccountIds(Vec<String>);
+
+impl TryFrom<String> for AccountIds {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let accounts = value
+            .split(',')
+            .map(|account| AccountId::try_from(account.trim().to_string()))
+            .map(|account| account.map(AccountId::into_string))
+            .collect::<Result<Vec<_>, _>>()?;
+        if accounts.len() > MAX_FILTER_ACCOUNTS {
+            return Err(format!(
+                "at most {} accounts are allowed",
+                MAX_FILTER_ACCOUNTS
+            ));
+        }
+        Ok(AccountIds(accounts))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct BlockStreamParams {
+    pub(crate) block_no: Option<u64>,
+    #[serde(default)]
+    pub(crate) programs: AccountIds,
+    #[serde(default)]
+    pub(crate) accounts: AccountIds,
+    pub(crate) min_amount: Option<f64>,
+    #[serde(default)]
+    pub(crate) failed_only: bool,
+}
+
+impl BlockStreamParams {
+    pub fn filter(self) -> BlockFilter {
+        BlockFilter {
+            programs: self.programs.0,
+            accounts: self.accounts.0,
+            min_amount: self.min_amount,
+            failed_only: self.failed_only,
+        }
+    }
+}
+
+/// Transactions a block stream client wants to receive. Every criterion that is set must hold,
+/// a list is matched by a transaction involving any of its entries. The empty filter streams
+/// blocks whole.
+#[derive(Debug, Clone, Default)]
+pub struct BlockFilter {
+    /// Programs among the account keys of the transaction
+    pub programs: Vec<String>,
+    /// Accounts among the account keys or the transfer parties of the transaction
+    pub accounts: Vec<String>,
+    /// Least SOL moved by the System Program transfers of the transaction
+    pub min_amount: Option<f64>,
+    /// Only transactions that failed
+    pub failed_only: bool,
+}
+
+impl BlockFilter {
+    pub fn is_empty(&self) -> bool {
+        self.programs.is_empty()
+            && self.accounts.is_empty()
+            && self.min_amount.is_none()
+            && !self.failed_only
+    }
+
+    /// This function tells whether the filter keeps a transaction
+    ///
+    /// # Arguments
+    ///
+    /// * `tx` - A TxRecord that holds the transaction
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Whether every criterion set holds for the transaction
+    pub fn matches(&self, tx: &TxRecord) -> bool {
+        if self.failed_only && tx.succeeded() != Some(false) {
+            return false;
+        }
+        if self
+            .min_amount
+            .is_some_and(|min_amount| tx.sol_transferred() < min_amount)
+        {
+            return false;
+        }
+        if !self.programs.is_empty()
+            && !tx
+                .accounts()
+                .iter()
+                .any(|account| self.programs.contains(account))
+        {
+            return false;
+        }
+        self.accounts.is_empty()
+            || tx
+                .parties()
+                .any(|account| self.accounts.iter().any(|wanted| wanted == account))
+    }
+
+    /// This function filters a block for a stream
+    ///
+    /// # Arguments
+    ///
+    /// * `block` - A Block that holds the block
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Block>` - The block, reduced to the matching transactions when the filter is
+    ///   set, None when none of its transactions match
+    pub fn apply(&self, block: &Block) -> Option<Block> {
+        if self.is_empty() {
+            return Some(block.clone());
+        }
+        let filtered = block.filtered(self);
+        if filtered.tx_map.is_empty() {
+            return None;
+        }
+        Some(filtered)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AccountStreamParams {
+    pub(crate) from_slot: Option<u64>,
+    pub(crate) resume_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct WebhookParams {
+    pub(crate) account: AccountId,
+    pub(crate) url: String,
+    pub(crate) ttl_secs: Option<u64>,
 }
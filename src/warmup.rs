@@ -0,0 +1,56 @@
+use crate::metrics::READY;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether the instance serves queries at full speed, false until the db finished warming its
+/// caches up. Load balancers poll it through `/ready` and keep a restarted instance out of
+/// rotation until then.
+#[derive(Debug, Default)]
+pub struct Readiness {
+    ready: AtomicBool,
+}
+
+impl Readiness {
+    /// This function tells whether the instance is ready
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Acquire)
+    }
+
+    /// This function marks the instance ready, once and for all
+    pub fn mark_ready(&self) {
+        self.ready.store(true, Ordering::Release);
+        READY.set(1);
+    }
+}
+
+/// What the startup warm-up read
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WarmupReport {
+    /// Latest blocks read
+    pub blocks: u64,
+    /// Accounts whose summary was read
+    pub accounts: usize,
+}
+
+/// This function picks the accounts involved in the most transactions, the ones queried the most
+/// right after a restart
+///
+/// # Arguments
+///
+/// * `counts` - A HashMap<String, u64> that holds the transactions each account is involved in
+/// * `limit` - A usize that holds the number of accounts picked
+///
+/// # Returns
+///
+/// * `Vec<String>` - The accounts, most involved first, ties broken by account
+pub fn hottest_accounts(counts: HashMap<String, u64>, limit: usize) -> Vec<String> {
+    let mut counts: Vec<(String, u64)> = counts.into_iter().collect();
+    counts.sort_by(|(left, left_count), (right, right_count)| {
+        right_count.cmp(left_count).then_with(|| left.cmp(right))
+    });
+    counts
+        .into_iter()
+        .take(limit)
+        .map(|(account, _)| account)
+        .collect()
+}
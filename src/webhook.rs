@@ -0,0 +1,239 @@
+use crate::shutdown::{Shutdown, Worker};
+use crate::util::{
+    DeliveryAttempt, DeliveryReceipt, DeliveryStatus, ProtocolMessage, WebhookDelivery,
+    WebhookSubscription,
+};
+use futures_util::future::BoxFuture;
+use futures_util::stream::FuturesUnordered;
+use futures_util::StreamExt;
+use hmac::{Hmac, Mac};
+use log::{error, warn};
+use sha2::Sha256;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+/// Header holding the delivery id of a webhook body
+pub const DELIVERY_ID_HEADER: &str = "x-agg-delivery-id";
+/// Header holding `sha256=<hex hmac of the body>` keyed with the secret of the webhook
+pub const SIGNATURE_HEADER: &str = "x-agg-signature";
+/// Time a webhook endpoint has to answer a delivery
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_DELIVERY_ATTEMPTS: u32 = 3;
+/// Wait before the second attempt, doubled before every further one
+const INITIAL_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// This function signs a webhook body
+///
+/// # Arguments
+///
+/// * `secret` - A string slice that holds the secret of the webhook
+/// * `body` - A byte slice that holds the body
+///
+/// # Returns
+///
+/// * `String` - The hex encoded HMAC-SHA256 of the body
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// This function generates the secret of a new webhook
+///
+/// # Returns
+///
+/// * `String` - 32 random bytes, hex encoded
+pub fn generate_secret() -> String {
+    hex::encode(rand::random::<[u8; 32]>())
+}
+
+pub struct WebhookDispatcher {
+    receiver: UnboundedReceiver<ProtocolMessage>,
+    db_sender: UnboundedSender<ProtocolMessage>,
+    client: reqwest::Client,
+    shutdown: Option<Shutdown>,
+}
+
+impl WebhookDispatcher {
+    /// This function initializes the webhook dispatcher
+    ///
+    /// # Arguments
+    ///
+    /// * `receiver` - A UnboundedReceiver<ProtocolMessage> that receives the deliveries
+    /// * `db_sender` - A UnboundedSender<ProtocolMessage> that receives the delivery receipts
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The webhook dispatcher
+    pub fn initialize(
+        receiver: UnboundedReceiver<ProtocolMessage>,
+        db_sender: UnboundedSender<ProtocolMessage>,
+    ) -> Self {
+        Self {
+            receiver,
+            db_sender,
+            client: reqwest::Client::new(),
+            shutdown: None,
+        }
+    }
+
+    /// This function delivers the events of the webhooks and reports the outcome of every
+    /// delivery to the db. Webhooks are delivered to concurrently, so a slow endpoint only holds
+    /// back its own deliveries, each webhook gets its events in the order they were published.
+    /// Once asked to stop it finishes the deliveries in progress, the receipts of the queued ones
+    /// stay pending in the db.
+    pub async fn run(&mut self) {
+        let mut in_flight = FuturesUnordered::new();
+        // Deliveries waiting for the one in progress to their webhook, by webhook id
+        let mut queued: HashMap<u64, VecDeque<(WebhookSubscription, Box<WebhookDelivery>)>> =
+            HashMap::new();
+        loop {
+            tokio::select! {
+                message = self.receiver.recv() => match message {
+                    Some(ProtocolMessage::WebhookEvent(subscription, delivery)) => {
+                        match queued.get_mut(&subscription.id) {
+                            Some(queue) => queue.push_back((subscription, delivery)),
+                            None => {
+                                queued.insert(subscription.id, VecDeque::new());
+                                in_flight.push(Self::deliver(
+                                    self.client.clone(),
+                                    subscription,
+                                    delivery,
+                                ));
+                            }
+                        }
+                    }
+                    Some(_) => {}
+                    None => break,
+                },
+                Some(receipt) = in_flight.next(), if !in_flight.is_empty() => {
+                    let next = queued
+                        .get_mut(&receipt.webhook_id)
+                        .and_then(VecDeque::pop_front);
+                    match next {
+                        Some((subscription, delivery)) => in_flight.push(Self::deliver(
+                            self.client.clone(),
+                            subscription,
+                            delivery,
+                        )),
+                        None => {
+                            queued.remove(&receipt.webhook_id);
+                        }
+                    }
+                    self.report(receipt);
+                }
+                _ = Shutdown::wait(&mut self.shutdown) => break,
+            }
+        }
+        while let Some(receipt) = in_flight.next().await {
+            self.report(receipt);
+        }
+    }
+
+    /// This function reports the outcome of a delivery to the db
+    ///
+    /// # Arguments
+    ///
+    /// * `receipt` - A DeliveryReceipt that holds the outcome
+    fn report(&self, receipt: DeliveryReceipt) {
+        if receipt.status == DeliveryStatus::Failed {
+            warn!(
+                target: "webhook",
+                "Failed to deliver {} to webhook {} after {} attempts",
+                receipt.delivery_id, receipt.webhook_id, receipt.attempts.len()
+            );
+        }
+        if let Err(err) = self
+            .db_sender
+            .send(ProtocolMessage::DeliveryReport(receipt))
+        {
+            error!(target: "webhook", "Error from db_sender {}", err);
+        }
+    }
+
+    /// This function posts a signed delivery to the url of a webhook, retrying with backoff
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - A reqwest::Client that holds the client the delivery is posted with
+    /// * `subscription` - A WebhookSubscription that holds the url and secret
+    /// * `delivery` - A Box<WebhookDelivery> that holds the body
+    ///
+    /// # Returns
+    ///
+    /// * `DeliveryReceipt` - The outcome with every attempt made
+    async fn deliver(
+        client: reqwest::Client,
+        subscription: WebhookSubscription,
+        delivery: Box<WebhookDelivery>,
+    ) -> DeliveryReceipt {
+        let mut receipt = DeliveryReceipt::pending(&delivery);
+        receipt.status = DeliveryStatus::Failed;
+        let body = match serde_json::to_vec(&delivery) {
+            Ok(body) => body,
+            Err(err) => {
+                receipt
+                    .attempts
+                    .push(Self::attempt(None, Some(err.to_string())));
+                return receipt;
+            }
+        };
+        let signature = format!("sha256={}", sign(&subscription.secret, &body));
+        let mut backoff = INITIAL_RETRY_BACKOFF;
+        for attempt in 1..=MAX_DELIVERY_ATTEMPTS {
+            let result = client
+                .post(&subscription.url)
+                .timeout(DELIVERY_TIMEOUT)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header(DELIVERY_ID_HEADER, delivery.delivery_id)
+                .header(SIGNATURE_HEADER, &signature)
+                .body(body.clone())
+                .send()
+                .await;
+            let (status_code, error) = match result {
+                Ok(response) if response.status().is_success() => {
+                    (Some(response.status().as_u16()), None)
+                }
+                Ok(response) => (
+                    Some(response.status().as_u16()),
+                    Some(format!("Endpoint responded with {}", response.status())),
+                ),
+                Err(err) => (None, Some(err.to_string())),
+            };
+            let delivered = error.is_none();
+            receipt.attempts.push(Self::attempt(status_code, error));
+            if delivered {
+                receipt.status = DeliveryStatus::Delivered;
+                break;
+            }
+            if attempt < MAX_DELIVERY_ATTEMPTS {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+        }
+        receipt
+    }
+
+    fn attempt(status_code: Option<u16>, error: Option<String>) -> DeliveryAttempt {
+        DeliveryAttempt {
+            at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            status_code,
+            error,
+        }
+    }
+}
+
+impl Worker for WebhookDispatcher {
+    fn set_shutdown(&mut self, shutdown: Shutdown) {
+        self.shutdown = Some(shutdown);
+    }
+
+    fn work(&mut self) -> BoxFuture<'_, ()> {
+        Box::pin(self.run())
+    }
+}
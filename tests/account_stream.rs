@@ -0,0 +1,95 @@
+mod common;
+
+use common::key;
+use solana_agg::fanout;
+use solana_agg::util::{Block, BlockHeader, Instruction, ProtocolMessage, TxRecord};
+use solana_agg::Builder;
+use solana_program::hash::hash;
+use solana_program::pubkey::Pubkey;
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+/// A block of the slot with `transfers` transfers from `key(1)` to `to`
+fn transfer_block(slot: u64, transfers: u64, to: Pubkey) -> Block {
+    let mut block = Block::default();
+    block.set_header(BlockHeader {
+        slot,
+        blockhash: format!("hash-{}", slot),
+        transaction_count: Some(transfers),
+        ..Default::default()
+    });
+    for transfer in 0..transfers {
+        block.push_transaction(
+            hash(format!("{}/{}", slot, transfer).as_bytes()),
+            TxRecord::new(
+                vec![Instruction::Transfer(
+                    key(1).to_string(),
+                    to.to_string(),
+                    1.0,
+                )],
+                None,
+            )
+            .expect("record")
+            .with_accounts(vec![key(1).to_string(), to.to_string()]),
+        );
+    }
+    block
+}
+
+#[tokio::test]
+async fn a_long_replay_is_followed_by_the_blocks_applied_meanwhile_without_gaps() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(dir.path().join("db").to_string_lossy().into_owned())
+        .db_receiver(receiver)
+        .build()
+        .expect("db opens");
+    tokio::spawn(async move { db.run().await });
+    sender
+        .send(ProtocolMessage::FinalizeBlock(
+            1,
+            transfer_block(10, 600, key(2)),
+        ))
+        .expect("db running");
+
+    let (subscriber, mut events) = fanout::subscriber_channel();
+    sender
+        .send(ProtocolMessage::SubscribeAccount(
+            key(1).to_string(),
+            Some(0),
+            None,
+            subscriber,
+        ))
+        .expect("db running");
+    // Applied while the replay of block 1 is still being sent
+    sender
+        .send(ProtocolMessage::FinalizeBlock(
+            2,
+            transfer_block(20, 1, key(3)),
+        ))
+        .expect("db running");
+
+    let mut tx_ids = BTreeSet::new();
+    let mut slots = vec![];
+    while tx_ids.len() < 601 {
+        let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+            .await
+            .expect("every transaction is sent");
+        match event {
+            Some(ProtocolMessage::AccountEvent(event)) => {
+                assert!(tx_ids.insert(event.tx_id), "no transaction is sent twice");
+                slots.push(event.slot);
+            }
+            other => panic!("unexpected message {other:?}"),
+        }
+    }
+    assert!(slots.windows(2).all(|pair| pair[0] <= pair[1]));
+    assert_eq!(slots.last(), Some(&20));
+    assert!(
+        tokio::time::timeout(Duration::from_millis(200), events.recv())
+            .await
+            .is_err(),
+        "no transaction is sent twice"
+    );
+}
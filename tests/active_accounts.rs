@@ -0,0 +1,170 @@
+mod common;
+
+use common::key;
+use solana_agg::hyperloglog::HyperLogLog;
+use solana_agg::util::{
+    ActiveAccountsDay, ActiveAccountsStats, Block, BlockHeader, ProtocolMessage, Response, TxRecord,
+};
+use solana_agg::Builder;
+use solana_program::hash::hash;
+use solana_program::pubkey::Pubkey;
+use tokio::sync::mpsc::UnboundedSender;
+
+fn sketch(items: std::ops::Range<u64>) -> HyperLogLog {
+    let mut sketch = HyperLogLog::default();
+    for item in items {
+        sketch.insert(&item.to_le_bytes());
+    }
+    sketch
+}
+
+fn assert_close(estimate: u64, actual: u64) {
+    let error = (estimate as f64 - actual as f64).abs() / actual as f64;
+    assert!(error < 0.05, "estimated {} for {}", estimate, actual);
+}
+
+#[test]
+fn sketches_estimate_small_and_large_sets() {
+    assert_eq!(HyperLogLog::default().count(), 0);
+    assert_close(sketch(0..100).count(), 100);
+    assert_close(sketch(0..200_000).count(), 200_000);
+    // Items added again are not counted twice
+    let mut repeated = sketch(0..1_000);
+    repeated.merge(&sketch(0..1_000));
+    assert_close(repeated.count(), 1_000);
+}
+
+#[test]
+fn merged_sketches_count_the_union() {
+    let mut union = sketch(0..30_000);
+    union.merge(&sketch(20_000..50_000));
+    assert_close(union.count(), 50_000);
+
+    let stored = serde_json::to_string(&union).expect("serializes");
+    let restored: HyperLogLog = serde_json::from_str(&stored).expect("deserializes");
+    assert_eq!(restored, union);
+    assert!(serde_json::from_str::<HyperLogLog>("\"00ff\"").is_err());
+}
+
+#[test]
+fn blocks_count_distinct_fee_payers_and_accounts() {
+    let mut block = Block::default();
+    block.set_header(BlockHeader {
+        slot: 1,
+        blockhash: "hash-1".to_string(),
+        block_time: Some(1_717_200_000),
+        ..Default::default()
+    });
+    for (seed, accounts) in [
+        (0u8, vec!["Payer1", "Acc1", "Program"]),
+        (1, vec!["Payer1", "Acc2", "Program"]),
+        (2, vec!["Payer2", "Acc1"]),
+    ] {
+        let record = TxRecord::new(vec![], None)
+            .expect("record")
+            .with_accounts(accounts.into_iter().map(str::to_string).collect());
+        block.push_transaction(hash(&[seed]), record);
+    }
+    let summary = block.summary(1);
+    assert_eq!(summary.fee_payers, 2);
+    assert_eq!(summary.active_accounts, 5);
+    assert_eq!(block.day().as_deref(), Some("2024-06-01"));
+
+    let mut day = ActiveAccountsDay::default();
+    day.add_block(&block);
+    day.add_block(&block);
+    let stats = day.stats("2024-06-01".to_string());
+    assert_eq!(stats.blocks, 2);
+    assert_eq!(stats.fee_payers, 2);
+    assert_eq!(stats.active_accounts, 5);
+}
+
+/// A block of the slot on 2024-06-01 with a transaction of `payer` touching `account`
+fn block(slot: u64, payer: Pubkey, account: Pubkey) -> Block {
+    let mut block = Block::default();
+    block.set_header(BlockHeader {
+        slot,
+        blockhash: format!("hash-{}", slot),
+        block_time: Some(1_717_200_000),
+        transaction_count: Some(1),
+        ..Default::default()
+    });
+    let record = TxRecord::new(vec![], None)
+        .expect("record")
+        .with_accounts(vec![payer.to_string(), account.to_string()]);
+    block.push_transaction(hash(&slot.to_be_bytes()), record);
+    block
+}
+
+async fn active_accounts(sender: &UnboundedSender<ProtocolMessage>) -> Vec<ActiveAccountsStats> {
+    match ProtocolMessage::ask(sender, |reply| {
+        ProtocolMessage::FetchActiveAccounts(7, reply)
+    })
+    .await
+    {
+        Ok(Response::ActiveAccounts(stats)) => stats,
+        other => panic!("unexpected response {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn a_block_delivered_again_is_counted_once() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let path = dir.path().join("db").to_string_lossy().into_owned();
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(path.clone())
+        .db_receiver(receiver)
+        .build()
+        .expect("db opens");
+    let db = tokio::spawn(async move { db.run().await });
+    for (block_no, block) in [
+        (1, block(10, key(1), key(11))),
+        (2, block(20, key(2), key(12))),
+        // Block 1 again, with an account it was missing
+        (1, block(10, key(1), key(13))),
+    ] {
+        sender
+            .send(ProtocolMessage::FinalizeBlock(block_no, block))
+            .expect("db running");
+    }
+    let stats = active_accounts(&sender).await;
+    assert_eq!(stats.len(), 1);
+    assert_eq!(stats[0].day, "2024-06-01");
+    assert_eq!(stats[0].blocks, 2);
+    assert_eq!(stats[0].fee_payers, 2);
+    assert_eq!(stats[0].active_accounts, 5);
+    drop(sender);
+    db.await.expect("db stops");
+
+    // An older db kept the days in the default column family
+    let options = rocksdb::Options::default();
+    let column_families = rocksdb::DB::list_cf(&options, &path).expect("column families");
+    let raw = rocksdb::DB::open_cf(&options, &path, column_families).expect("db opens");
+    let mut day = ActiveAccountsDay::default();
+    day.add_block(&block(5, key(3), key(14)));
+    raw.put(
+        "ActiveAccounts/2024-05-31",
+        serde_json::to_vec(&day).expect("serializes"),
+    )
+    .expect("legacy day written");
+    drop(raw);
+
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(path)
+        .db_receiver(receiver)
+        .build()
+        .expect("db opens");
+    let db = tokio::spawn(async move { db.run().await });
+    let stats = active_accounts(&sender).await;
+    assert_eq!(
+        stats
+            .iter()
+            .map(|stats| (stats.day.as_str(), stats.blocks))
+            .collect::<Vec<_>>(),
+        vec![("2024-05-31", 1), ("2024-06-01", 2)]
+    );
+    drop(sender);
+    db.await.expect("db stops");
+}
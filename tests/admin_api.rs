@@ -0,0 +1,66 @@
+use actix_web::{test, web, App};
+use solana_agg::config::QueryConfig;
+use solana_agg::error::ErrorBody;
+use solana_agg::server::{self, AdminKey};
+use solana_agg::tenant::API_KEY_HEADER;
+use solana_agg::util::{Channel, Job, ProtocolMessage};
+use solana_agg::Builder;
+use std::time::{Duration, Instant};
+
+fn query_config(request_timeout_ms: u64) -> QueryConfig {
+    QueryConfig {
+        request_timeout_ms,
+        ..QueryConfig::default()
+    }
+}
+
+fn jobs_request() -> actix_http::Request {
+    test::TestRequest::get()
+        .uri("/admin/jobs")
+        .insert_header((API_KEY_HEADER, "admin-key"))
+        .to_request()
+}
+
+#[actix_web::test]
+async fn admin_requests_are_answered_by_the_db() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(dir.path().join("db").to_string_lossy().into_owned())
+        .db_receiver(receiver)
+        .build()
+        .expect("db opens");
+    tokio::spawn(async move { db.run().await });
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(sender))
+            .app_data(web::Data::new(AdminKey::new(Some("admin-key".to_string()))))
+            .app_data(web::Data::new(query_config(5_000)))
+            .configure(server::configure),
+    )
+    .await;
+    let response = test::call_service(&app, jobs_request()).await;
+    assert_eq!(response.status(), 200);
+    let jobs: Vec<Job> = test::read_body_json(response).await;
+    assert!(jobs.is_empty());
+}
+
+#[actix_web::test]
+async fn admin_requests_time_out_when_the_db_does_not_answer() {
+    // A db that never takes its messages
+    let channel = Channel::<ProtocolMessage>::new();
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(channel.sender()))
+            .app_data(web::Data::new(AdminKey::new(Some("admin-key".to_string()))))
+            .app_data(web::Data::new(query_config(50)))
+            .configure(server::configure),
+    )
+    .await;
+    let started = Instant::now();
+    let response = test::call_service(&app, jobs_request()).await;
+    assert_eq!(response.status(), 504);
+    assert!(started.elapsed() < Duration::from_secs(5));
+    let body: ErrorBody = test::read_body_json(response).await;
+    assert_eq!(body.error.code, "query_timed_out");
+}
@@ -0,0 +1,199 @@
+use solana_agg::alerting::{
+    Alert, AlertKind, AlertRules, AlertState, Notifier, Observation, PagerDutyNotifier,
+    SlackNotifier,
+};
+use solana_agg::config::{AlertSinkConfig, AlertingConfig, Config};
+use solana_agg::envelope::SlotContext;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+fn rules() -> AlertRules {
+    AlertRules::new(&AlertingConfig {
+        stall_secs: 60,
+        max_lag_slots: 100,
+        dead_letter_growth: 5,
+        ..AlertingConfig::default()
+    })
+}
+
+fn observe(latest_indexed: u64, finalized: u64) -> Observation {
+    Observation {
+        slots: Some(SlotContext {
+            latest_indexed: Some(latest_indexed),
+            finalized: Some(finalized),
+        }),
+        ..Observation::default()
+    }
+}
+
+fn raised(alerts: &[Alert]) -> Vec<(AlertKind, AlertState)> {
+    alerts
+        .iter()
+        .map(|alert| (alert.kind, alert.state))
+        .collect()
+}
+
+#[test]
+fn a_stall_fires_once_and_resolves_when_indexing_resumes() {
+    let mut rules = rules();
+    let start = Instant::now();
+    assert!(rules.evaluate(&observe(10, 10), start).is_empty());
+    assert!(rules
+        .evaluate(&observe(10, 10), start + Duration::from_secs(59))
+        .is_empty());
+    assert_eq!(
+        raised(&rules.evaluate(&observe(10, 10), start + Duration::from_secs(60))),
+        vec![(AlertKind::Stall, AlertState::Firing)]
+    );
+    assert!(rules
+        .evaluate(&observe(10, 10), start + Duration::from_secs(120))
+        .is_empty());
+    assert_eq!(
+        raised(&rules.evaluate(&observe(11, 11), start + Duration::from_secs(121))),
+        vec![(AlertKind::Stall, AlertState::Resolved)]
+    );
+}
+
+#[test]
+fn an_instance_never_indexing_is_reported_stalled() {
+    let mut rules = rules();
+    let start = Instant::now();
+    assert!(rules.evaluate(&Observation::default(), start).is_empty());
+    let alerts = rules.evaluate(&Observation::default(), start + Duration::from_secs(60));
+    assert_eq!(
+        raised(&alerts),
+        vec![(AlertKind::Stall, AlertState::Firing)]
+    );
+    assert!(alerts[0].summary.contains("after startup"));
+}
+
+#[test]
+fn lag_fires_above_the_threshold_and_resolves_at_or_below_it() {
+    let mut rules = rules();
+    let start = Instant::now();
+    assert!(rules.evaluate(&observe(1_000, 1_100), start).is_empty());
+    assert_eq!(
+        raised(&rules.evaluate(&observe(1_001, 1_102), start)),
+        vec![(AlertKind::Lag, AlertState::Firing)]
+    );
+    assert!(rules.evaluate(&observe(1_002, 1_200), start).is_empty());
+    assert_eq!(
+        raised(&rules.evaluate(&observe(1_100, 1_200), start)),
+        vec![(AlertKind::Lag, AlertState::Resolved)]
+    );
+}
+
+#[test]
+fn every_worker_restart_is_reported() {
+    let mut rules = rules();
+    let start = Instant::now();
+    let restarts = |db: u64, handler: u64| Observation {
+        worker_restarts: BTreeMap::from([("db".to_string(), db), ("handler".to_string(), handler)]),
+        ..observe(10, 10)
+    };
+    assert!(rules.evaluate(&restarts(0, 0), start).is_empty());
+    let alerts = rules.evaluate(&restarts(2, 0), start);
+    assert_eq!(
+        raised(&alerts),
+        vec![(AlertKind::Crash, AlertState::Firing)]
+    );
+    assert!(alerts[0]
+        .summary
+        .contains("worker db was restarted 2 time(s)"));
+    assert!(rules.evaluate(&restarts(2, 0), start).is_empty());
+    assert_eq!(
+        raised(&rules.evaluate(&restarts(3, 1), start)),
+        vec![
+            (AlertKind::Crash, AlertState::Firing),
+            (AlertKind::Crash, AlertState::Firing)
+        ]
+    );
+}
+
+#[test]
+fn dead_letter_growth_fires_per_check_interval() {
+    let mut rules = rules();
+    let start = Instant::now();
+    let dead_letters = |dead_letters: u64| Observation {
+        dead_letters,
+        ..observe(10, 10)
+    };
+    assert!(rules.evaluate(&dead_letters(4), start).is_empty());
+    assert_eq!(
+        raised(&rules.evaluate(&dead_letters(9), start)),
+        vec![(AlertKind::DeadLetters, AlertState::Firing)]
+    );
+    assert!(rules.evaluate(&dead_letters(20), start).is_empty());
+    assert_eq!(
+        raised(&rules.evaluate(&dead_letters(21), start)),
+        vec![(AlertKind::DeadLetters, AlertState::Resolved)]
+    );
+}
+
+#[test]
+fn pagerduty_events_trigger_and_resolve_the_same_incident() {
+    let notifier = PagerDutyNotifier::new("routing-key".to_string());
+    let mut alert = Alert {
+        kind: AlertKind::Lag,
+        state: AlertState::Firing,
+        summary: "behind".to_string(),
+        chain_id: Some("devnet".to_string()),
+    };
+    let trigger = notifier.event(&alert);
+    assert_eq!(trigger["routing_key"], "routing-key");
+    assert_eq!(trigger["event_action"], "trigger");
+    assert_eq!(trigger["dedup_key"], "devnet/lag");
+    assert_eq!(
+        trigger["payload"]["summary"],
+        "[FIRING] lag on devnet: behind"
+    );
+    assert_eq!(trigger["payload"]["severity"], "warning");
+    alert.state = AlertState::Resolved;
+    let resolve = notifier.event(&alert);
+    assert_eq!(resolve["event_action"], "resolve");
+    assert_eq!(resolve["dedup_key"], trigger["dedup_key"]);
+}
+
+#[test]
+fn sinks_are_read_from_the_config() {
+    let config: Config = toml::from_str(
+        "[alerting]\nstall_secs = 120\n\n\
+         [[alerting.sinks]]\nkind = \"slack\"\nurl = \"https://hooks.slack.com/services/T/B/X\"\n\n\
+         [[alerting.sinks]]\nkind = \"webhook\"\nurl = \"https://ops.example/alerts\"\n\n\
+         [[alerting.sinks]]\nkind = \"pagerduty\"\nrouting_key = \"key\"",
+    )
+    .expect("valid config");
+    assert_eq!(config.alerting.stall_secs, 120);
+    assert_eq!(config.alerting.check_interval_secs, 30);
+    assert_eq!(
+        config.alerting.sinks,
+        vec![
+            AlertSinkConfig::Slack {
+                url: "https://hooks.slack.com/services/T/B/X".to_string()
+            },
+            AlertSinkConfig::Webhook {
+                url: "https://ops.example/alerts".to_string()
+            },
+            AlertSinkConfig::PagerDuty {
+                routing_key: "key".to_string()
+            },
+        ]
+    );
+    assert!(Config::default().alerting.sinks.is_empty());
+}
+
+#[tokio::test]
+async fn failed_notifications_do_not_reveal_the_sink_url() {
+    // Nothing listens on port 1, the url itself is the secret of a Slack incoming webhook
+    let notifier = SlackNotifier::new("http://127.0.0.1:1/services/T000/B000/secret".to_string());
+    let alert = Alert {
+        kind: AlertKind::Stall,
+        state: AlertState::Firing,
+        summary: "stalled".to_string(),
+        chain_id: None,
+    };
+    let err = notifier.notify(&alert).await.unwrap_err().to_string();
+    assert!(!err.contains("secret"), "{err}");
+    assert!(!err.contains("/services/"), "{err}");
+    assert!(err.contains("http://127.0.0.1:1"), "{err}");
+}
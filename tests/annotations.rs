@@ -0,0 +1,170 @@
+use actix_web::{web, App};
+use serde_json::{json, to_value};
+use solana_agg::config::QueryConfig;
+use solana_agg::server::{self, AdminKey};
+use solana_agg::tenant::API_KEY_HEADER;
+use solana_agg::util::{Annotation, Block, BlockHeader, Channel, ProtocolMessage, Response};
+use solana_agg::Builder;
+use tokio::sync::mpsc::UnboundedSender;
+
+fn block() -> Block {
+    let mut block = Block::default();
+    block.set_header(BlockHeader {
+        slot: 100,
+        blockhash: "h100".to_string(),
+        previous_blockhash: Some("h99".to_string()),
+        parent_slot: Some(99),
+        ..Default::default()
+    });
+    block
+}
+
+#[test]
+fn blocks_without_annotations_serialize_as_before() {
+    let value = to_value(block()).expect("serialize");
+    assert!(value.get("annotations").is_none());
+}
+
+#[test]
+fn annotations_are_served_with_the_block_and_read_back() {
+    let annotations = vec![
+        Annotation {
+            slot: 100,
+            note: "incident window".to_string(),
+            created_at: 1_700_000_000,
+        },
+        Annotation {
+            slot: 100,
+            note: "parser v2 from here".to_string(),
+            created_at: 1_700_000_060,
+        },
+    ];
+    let mut block = block();
+    block.set_annotations(annotations);
+    let value = to_value(&block).expect("serialize");
+    assert_eq!(
+        value["annotations"],
+        json!([
+            {"slot": 100, "note": "incident window", "created_at": 1_700_000_000u64},
+            {"slot": 100, "note": "parser v2 from here", "created_at": 1_700_000_060u64},
+        ])
+    );
+    let read: Block = serde_json::from_value(value.clone()).expect("deserialize");
+    assert_eq!(to_value(&read).expect("serialize"), value);
+}
+
+fn annotate(slot: u64, note: &str, admin_key: Option<&str>) -> actix_http::Request {
+    let request = actix_web::test::TestRequest::post()
+        .uri(&format!("/annotations/{}", slot))
+        .set_json(json!({ "note": note }));
+    match admin_key {
+        Some(admin_key) => request.insert_header((API_KEY_HEADER, admin_key)),
+        None => request,
+    }
+    .to_request()
+}
+
+async fn status_annotations(sender: &UnboundedSender<ProtocolMessage>) -> Vec<(u64, String)> {
+    match ProtocolMessage::ask(sender, ProtocolMessage::FetchStatus).await {
+        Ok(Response::Status(status)) => status
+            .annotations
+            .into_iter()
+            .map(|annotation| (annotation.slot, annotation.note))
+            .collect(),
+        other => panic!("unexpected response {other:?}"),
+    }
+}
+
+async fn block_annotations(sender: &UnboundedSender<ProtocolMessage>, slot: u64) -> Vec<String> {
+    match ProtocolMessage::ask(sender, |reply| {
+        ProtocolMessage::FetchBlockAtSlot(slot, reply)
+    })
+    .await
+    {
+        Ok(Response::BlockDetails(block)) => {
+            let annotations = to_value(&block).expect("serialize")["annotations"].clone();
+            serde_json::from_value::<Vec<Annotation>>(annotations)
+                .expect("annotations")
+                .into_iter()
+                .map(|annotation| annotation.note)
+                .collect()
+        }
+        other => panic!("unexpected response {other:?}"),
+    }
+}
+
+#[actix_web::test]
+async fn annotating_a_slot_takes_the_admin_key_and_a_note() {
+    // A db that never takes its messages, the requests are rejected before reaching it
+    let channel = Channel::<ProtocolMessage>::new();
+    let app = actix_web::test::init_service(
+        App::new()
+            .app_data(web::Data::new(channel.sender()))
+            .app_data(web::Data::new(AdminKey::new(Some("admin-key".to_string()))))
+            .app_data(web::Data::new(QueryConfig::default()))
+            .configure(server::configure),
+    )
+    .await;
+    let response =
+        actix_web::test::call_service(&app, annotate(100, "incident window", None)).await;
+    assert_eq!(response.status(), 401);
+    let response =
+        actix_web::test::call_service(&app, annotate(100, "  ", Some("admin-key"))).await;
+    assert_eq!(response.status(), 400);
+}
+
+#[actix_web::test]
+async fn annotations_are_listed_by_creation_and_kept_across_restarts() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let path = dir.path().join("db").to_string_lossy().into_owned();
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(path.clone())
+        .db_receiver(receiver)
+        .build()
+        .expect("db opens");
+    let db = tokio::spawn(async move { db.run().await });
+    sender
+        .send(ProtocolMessage::FinalizeBlock(1, block()))
+        .expect("db running");
+    let app = actix_web::test::init_service(
+        App::new()
+            .app_data(web::Data::new(sender.clone()))
+            .app_data(web::Data::new(AdminKey::new(Some("admin-key".to_string()))))
+            .app_data(web::Data::new(QueryConfig::default()))
+            .configure(server::configure),
+    )
+    .await;
+    for (slot, note) in [
+        (100, "incident window"),
+        (100, "parser v2 from here"),
+        (50, "backfilled late"),
+    ] {
+        let response =
+            actix_web::test::call_service(&app, annotate(slot, note, Some("admin-key"))).await;
+        assert_eq!(response.status(), 201);
+    }
+    // The note on the earlier slot was attached last
+    let listed = vec![
+        (100, "incident window".to_string()),
+        (100, "parser v2 from here".to_string()),
+        (50, "backfilled late".to_string()),
+    ];
+    assert_eq!(status_annotations(&sender).await, listed);
+
+    drop(app);
+    drop(sender);
+    db.await.expect("db stops");
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(path)
+        .db_receiver(receiver)
+        .build()
+        .expect("db opens again");
+    tokio::spawn(async move { db.run().await });
+    assert_eq!(status_annotations(&sender).await, listed);
+    assert_eq!(
+        block_annotations(&sender, 100).await,
+        vec!["incident window", "parser v2 from here"]
+    );
+}
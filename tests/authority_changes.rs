@@ -0,0 +1,169 @@
+mod common;
+
+use common::{key, parse, TOKEN_PROGRAM};
+use serde_json::{json, Value};
+use solana_agg::util::{AuthorityChange, Instruction, TokenAuthorityType};
+use solana_program::instruction::{AccountMeta, Instruction as ProgramInstruction};
+use solana_program::message::Message;
+use solana_program::pubkey::Pubkey;
+use solana_program::system_instruction;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::Transaction;
+use solana_transaction_status::{Encodable, UiTransactionEncoding};
+
+/// A transaction with a single instruction paid by `key(1)`, failed when `err` is set
+fn transaction(instruction: ProgramInstruction, err: Option<Value>) -> Value {
+    let message = Message::new(&[instruction], Some(&key(1)));
+    let accounts = message.account_keys.len();
+    let transaction = Transaction {
+        signatures: vec![Signature::default(); message.header.num_required_signatures as usize],
+        message,
+    };
+    json!({
+        "transaction": transaction.encode(UiTransactionEncoding::Base64),
+        "meta": {
+            "err": err,
+            "status": {"Ok": null},
+            "fee": 5000,
+            "preBalances": vec![1_000_000u64; accounts],
+            "postBalances": vec![995_000u64; accounts],
+        },
+    })
+}
+
+fn token_instruction(data: Vec<u8>, accounts: &[Pubkey]) -> ProgramInstruction {
+    ProgramInstruction {
+        program_id: TOKEN_PROGRAM.parse().unwrap(),
+        accounts: accounts
+            .iter()
+            .map(|account| AccountMeta::new(*account, *account == key(1)))
+            .collect(),
+        data,
+    }
+}
+
+async fn changes(instruction: ProgramInstruction) -> Vec<(String, AuthorityChange)> {
+    parse(transaction(instruction, None))
+        .await
+        .authority_changes()
+        .map(|(_, account, change)| (account.to_string(), change.clone()))
+        .collect()
+}
+
+fn owner(owner: Pubkey) -> AuthorityChange {
+    AuthorityChange::Owner {
+        owner: owner.to_string(),
+    }
+}
+
+#[tokio::test]
+async fn system_owner_assignments_are_decoded() {
+    let program = key(9);
+    assert_eq!(
+        changes(system_instruction::assign(&key(1), &program)).await,
+        vec![(key(1).to_string(), owner(program))]
+    );
+    assert_eq!(
+        changes(system_instruction::create_account(
+            &key(1),
+            &key(2),
+            1_000,
+            165,
+            &program
+        ))
+        .await,
+        vec![(key(2).to_string(), owner(program))]
+    );
+}
+
+#[tokio::test]
+async fn seeded_owner_assignments_skip_the_seed() {
+    let program = key(9);
+    let seeded = Pubkey::create_with_seed(&key(1), "vault-seed", &program).unwrap();
+    assert_eq!(
+        changes(system_instruction::assign_with_seed(
+            &seeded,
+            &key(1),
+            "vault-seed",
+            &program
+        ))
+        .await,
+        vec![(seeded.to_string(), owner(program))]
+    );
+    assert_eq!(
+        changes(system_instruction::create_account_with_seed(
+            &key(1),
+            &seeded,
+            &key(1),
+            "vault-seed",
+            1_000,
+            165,
+            &program
+        ))
+        .await,
+        vec![(seeded.to_string(), owner(program))]
+    );
+}
+
+#[tokio::test]
+async fn token_delegates_are_approved_and_revoked() {
+    let mut approve = vec![4];
+    approve.extend(500u64.to_le_bytes());
+    assert_eq!(
+        changes(token_instruction(approve, &[key(2), key(3), key(1)])).await,
+        vec![(
+            key(2).to_string(),
+            AuthorityChange::Delegate {
+                delegate: Some(key(3).to_string()),
+                amount: Some(500),
+            }
+        )]
+    );
+    assert_eq!(
+        changes(token_instruction(vec![5], &[key(2), key(1)])).await,
+        vec![(
+            key(2).to_string(),
+            AuthorityChange::Delegate {
+                delegate: None,
+                amount: None,
+            }
+        )]
+    );
+}
+
+#[tokio::test]
+async fn token_account_owner_changes_are_decoded() {
+    let mut set_owner = vec![6, 2, 1];
+    set_owner.extend(key(7).to_bytes());
+    let block = parse(transaction(
+        token_instruction(set_owner, &[key(2), key(1)]),
+        None,
+    ))
+    .await;
+    let (_, tx) = block.transactions().next().expect("one transaction");
+    assert!(tx.parties().any(|party| party == key(7).to_string()));
+    match tx.instructions() {
+        [Instruction::Authority { account, change }] => {
+            assert_eq!(account, &key(2).to_string());
+            assert_eq!(
+                change,
+                &AuthorityChange::TokenAuthority {
+                    authority_type: TokenAuthorityType::AccountOwner,
+                    new_authority: Some(key(7).to_string()),
+                }
+            );
+        }
+        other => panic!("unexpected instructions {other:?}"),
+    }
+    assert_eq!(tx.sol_transferred(), 0.0);
+}
+
+#[tokio::test]
+async fn failed_transactions_change_no_authority() {
+    let block = parse(transaction(
+        system_instruction::assign(&key(1), &key(9)),
+        Some(json!({"InstructionError": [0, "InvalidAccountData"]})),
+    ))
+    .await;
+    assert_eq!(block.authority_changes().count(), 0);
+}
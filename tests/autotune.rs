@@ -0,0 +1,108 @@
+use solana_agg::autotune::{Adjustment, Autotuner, FetchSettings, WriteLatency};
+use solana_agg::config::AutotuneConfig;
+use std::time::{Duration, Instant};
+
+fn autotuner(slots_per_round: u64, chunk_size: usize) -> Autotuner {
+    let config = AutotuneConfig {
+        enabled: true,
+        target_write_latency_ms: 100,
+        lag_slots: 32,
+        min_slots_per_round: 2,
+        max_slots_per_round: 64,
+        min_chunk_size: 5,
+        max_chunk_size: 40,
+        interval_secs: 5,
+    };
+    Autotuner::new(
+        config,
+        FetchSettings {
+            slots_per_round,
+            chunk_size,
+        },
+    )
+}
+
+fn ms(millis: u64) -> Option<Duration> {
+    Some(Duration::from_millis(millis))
+}
+
+#[test]
+fn the_configured_settings_are_clamped_to_the_bounds() {
+    let settings = autotuner(1_000, 1).settings();
+    assert_eq!(settings.slots_per_round, 64);
+    assert_eq!(settings.chunk_size, 5);
+}
+
+#[test]
+fn slow_writes_back_off_multiplicatively_down_to_the_minimum() {
+    let mut autotuner = autotuner(16, 10);
+    let now = Instant::now();
+    assert_eq!(
+        autotuner.adjust(ms(150), 1_000, now),
+        Some(Adjustment::BackOff)
+    );
+    assert_eq!(
+        autotuner.settings(),
+        FetchSettings {
+            slots_per_round: 8,
+            chunk_size: 20
+        }
+    );
+    // Adjustments are spaced by the interval
+    assert_eq!(autotuner.adjust(ms(150), 1_000, now), None);
+    let mut at = now;
+    for _ in 0..4 {
+        at += Duration::from_secs(5);
+        autotuner.adjust(ms(150), 1_000, at);
+    }
+    assert_eq!(
+        autotuner.settings(),
+        FetchSettings {
+            slots_per_round: 2,
+            chunk_size: 40
+        }
+    );
+    // Nothing changes once at the bounds
+    assert_eq!(
+        autotuner.adjust(ms(150), 1_000, at + Duration::from_secs(5)),
+        None
+    );
+}
+
+#[test]
+fn lagging_with_headroom_speeds_up_and_holds_in_between() {
+    let mut autotuner = autotuner(16, 16);
+    let now = Instant::now();
+    assert_eq!(
+        autotuner.adjust(ms(50), 100, now),
+        Some(Adjustment::SpeedUp)
+    );
+    assert_eq!(
+        autotuner.settings(),
+        FetchSettings {
+            slots_per_round: 18,
+            chunk_size: 14
+        }
+    );
+    let later = now + Duration::from_secs(5);
+    // Caught up
+    assert_eq!(autotuner.adjust(ms(50), 10, later), None);
+    // Lagging but writes close to the target
+    assert_eq!(autotuner.adjust(ms(90), 100, later), None);
+    // No block stored yet
+    assert_eq!(autotuner.adjust(None, 0, later), None);
+    assert_eq!(
+        autotuner.adjust(None, 100, later),
+        Some(Adjustment::SpeedUp)
+    );
+}
+
+#[test]
+fn write_latency_is_a_moving_average() {
+    let latency = WriteLatency::default();
+    assert_eq!(latency.get(), None);
+    latency.record(Duration::from_millis(80));
+    assert_eq!(latency.get(), ms(80));
+    latency.record(Duration::from_millis(160));
+    assert_eq!(latency.get(), ms(90));
+}
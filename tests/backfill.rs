@@ -0,0 +1,204 @@
+use solana_agg::backfill::Backfiller;
+use solana_agg::config::BackfillConfig;
+use solana_agg::endpoints::RpcEndpoints;
+use solana_agg::shutdown::{ShutdownCoordinator, ShutdownStage};
+use solana_agg::util::{
+    BackfillProgress, Channel, IndexedSlots, Job, JobState, JobTask, ProtocolMessage, Response,
+};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const SKIPPED_SLOT: u64 = 145;
+
+/// An RPC node nothing listens on, the db answers every slot as stored
+fn endpoints() -> Arc<RpcEndpoints> {
+    Arc::new(RpcEndpoints::new(vec!["http://127.0.0.1:1".to_string()]).expect("one url"))
+}
+
+/// Answers the backfiller like a db storing every slot but a skipped one, returning the
+/// checkpoints it received
+async fn serve(
+    mut channel: Channel<ProtocolMessage>,
+    checkpointed: BackfillProgress,
+) -> Vec<(BackfillProgress, JobState)> {
+    let job = Job {
+        id: 7,
+        state: JobState::Running,
+        progress: 0.0,
+        task: JobTask::Backfill(checkpointed),
+        error: None,
+        created_at: 0,
+        updated_at: 0,
+    };
+    let mut updates = Vec::new();
+    while let Some(message) = channel.receiver.recv().await {
+        match message {
+            ProtocolMessage::FetchJobs(sender) => {
+                sender.send(Response::Jobs(vec![job.clone()])).ok();
+            }
+            ProtocolMessage::FetchJob(_, sender) => {
+                sender.send(Response::Job(Some(job.clone()))).ok();
+            }
+            ProtocolMessage::FetchIndexedSlots(start, end, _, sender) => {
+                let slots = IndexedSlots {
+                    indexed: (start..=end).filter(|slot| *slot != SKIPPED_SLOT).collect(),
+                    skipped: (start..=end).filter(|slot| *slot == SKIPPED_SLOT).collect(),
+                    next_slot: None,
+                };
+                sender.send(Response::IndexedSlots(slots)).ok();
+            }
+            ProtocolMessage::UpdateJob(7, JobTask::Backfill(progress), state, _) => {
+                updates.push((progress, state));
+                if state != JobState::Running {
+                    break;
+                }
+            }
+            other => panic!("unexpected message {other:?}"),
+        }
+    }
+    updates
+}
+
+#[tokio::test]
+async fn running_backfill_resumes_from_its_checkpoint_without_fetching_stored_slots() {
+    let channel = Channel::<ProtocolMessage>::new();
+    let backfiller = Arc::new(Backfiller::initialize(
+        endpoints(),
+        &BackfillConfig {
+            batch_size: 8,
+            ..BackfillConfig::default()
+        },
+        channel.sender(),
+    ));
+    let mut checkpointed = BackfillProgress::new(100, 150);
+    checkpointed.next_slot = 140;
+    let db = tokio::spawn(serve(channel, checkpointed));
+
+    // Restarted with the same start slot, the subscriber now starting after slot 160
+    let ids = backfiller.start(Some((100, 160))).await.expect("starts");
+    assert_eq!(ids, vec![7]);
+
+    let updates = db.await.expect("db serves");
+    // The extended range, then one checkpoint per batch of 8 slots from slot 140
+    let next_slots: Vec<u64> = updates
+        .iter()
+        .map(|(progress, _)| progress.next_slot)
+        .collect();
+    assert_eq!(next_slots, vec![140, 148, 156, 161, 161]);
+    let (finished, state) = updates.last().expect("finished");
+    assert_eq!(*state, JobState::Finished);
+    assert_eq!(finished.end_slot, 160);
+    assert_eq!(finished.indexed_blocks, 20);
+    assert_eq!(finished.skipped_slots, 1);
+    assert_eq!(finished.missing_slots, 0);
+    assert_eq!(
+        JobTask::Backfill(finished.clone()).progress(),
+        1.0,
+        "the whole range is done"
+    );
+}
+
+#[tokio::test]
+async fn backfill_must_start_before_the_subscriber() {
+    let mut channel = Channel::<ProtocolMessage>::new();
+    let backfiller = Arc::new(Backfiller::initialize(
+        endpoints(),
+        &BackfillConfig::default(),
+        channel.sender(),
+    ));
+    tokio::spawn(async move {
+        if let Some(ProtocolMessage::FetchJobs(sender)) = channel.receiver.recv().await {
+            sender.send(Response::Jobs(vec![])).ok();
+        }
+    });
+    assert!(backfiller.start(Some((200, 100))).await.is_err());
+}
+
+#[tokio::test]
+async fn a_supervised_backfill_stops_after_its_current_batch_on_shutdown() {
+    let mut channel = Channel::<ProtocolMessage>::new();
+    let mut coordinator = ShutdownCoordinator::new(Duration::from_secs(5));
+    let mut backfiller = Backfiller::initialize(
+        endpoints(),
+        &BackfillConfig {
+            batch_size: 8,
+            ..BackfillConfig::default()
+        },
+        channel.sender(),
+    );
+    backfiller.set_shutdown(coordinator.signal(ShutdownStage::Intake));
+    let backfiller = Arc::new(backfiller);
+    let checkpoints = Arc::new(Mutex::new(Vec::new()));
+    let db_checkpoints = checkpoints.clone();
+    // A db storing every slot of a range too long to be done before the shutdown
+    tokio::spawn(async move {
+        let mut job = Job {
+            id: 7,
+            state: JobState::Running,
+            progress: 0.0,
+            task: JobTask::Backfill(BackfillProgress::new(0, u64::MAX - 1)),
+            error: None,
+            created_at: 0,
+            updated_at: 0,
+        };
+        while let Some(message) = channel.receiver.recv().await {
+            match message {
+                ProtocolMessage::FetchJobs(sender) => {
+                    sender.send(Response::Jobs(vec![job.clone()])).ok();
+                }
+                ProtocolMessage::FetchJob(_, sender) => {
+                    sender.send(Response::Job(Some(job.clone()))).ok();
+                }
+                ProtocolMessage::FetchIndexedSlots(start, end, _, sender) => {
+                    let slots = IndexedSlots {
+                        indexed: (start..=end).collect(),
+                        skipped: vec![],
+                        next_slot: None,
+                    };
+                    sender.send(Response::IndexedSlots(slots)).ok();
+                }
+                ProtocolMessage::UpdateJob(7, task, state, _) => {
+                    job.task = task.clone();
+                    job.state = state;
+                    db_checkpoints.lock().unwrap().push((task, state));
+                }
+                other => panic!("unexpected message {other:?}"),
+            }
+        }
+    });
+
+    let running = backfiller.clone();
+    coordinator.track(
+        ShutdownStage::Intake,
+        "backfill",
+        tokio::spawn(async move {
+            running.start(None).await.expect("starts");
+            running.wait().await;
+        }),
+    );
+    while checkpoints.lock().unwrap().len() < 3 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let stopping = Instant::now();
+    coordinator.shutdown().await;
+    assert!(
+        stopping.elapsed() < Duration::from_secs(5),
+        "the backfill stopped by itself"
+    );
+
+    let stopped = checkpoints.lock().unwrap().len();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let checkpoints = checkpoints.lock().unwrap();
+    assert_eq!(
+        checkpoints.len(),
+        stopped,
+        "no batch runs after the shutdown"
+    );
+    let (task, state) = checkpoints.last().expect("checkpointed");
+    // Left running, the job resumes from its last checkpoint after the restart
+    assert_eq!(*state, JobState::Running);
+    match task {
+        JobTask::Backfill(progress) => assert_eq!(progress.next_slot % 8, 0),
+        other => panic!("unexpected task {other:?}"),
+    }
+}
@@ -0,0 +1,102 @@
+mod common;
+
+use actix_web::{test, web, App};
+use common::key;
+use serde_json::json;
+use solana_agg::config::QueryConfig;
+use solana_agg::error::ErrorBody;
+use solana_agg::server;
+use solana_agg::util::{BalancesAt, Block, BlockHeader, Channel, ProtocolMessage};
+use solana_agg::Builder;
+use solana_program::pubkey::Pubkey;
+use std::collections::BTreeMap;
+
+fn block(slot: u64, balances: &[(Pubkey, u64)]) -> Block {
+    let mut block = Block::default();
+    block.set_header(BlockHeader {
+        slot,
+        blockhash: format!("hash-{}", slot),
+        ..Default::default()
+    });
+    block.set_account_map(
+        balances
+            .iter()
+            .map(|(account, balance)| (account.to_string(), *balance))
+            .collect::<BTreeMap<_, _>>(),
+    );
+    block
+}
+
+fn balances_at(accounts: &[String], block_no: u64) -> actix_http::Request {
+    test::TestRequest::post()
+        .uri("/balances_at")
+        .set_json(json!({"accounts": accounts, "block_no": block_no}))
+        .to_request()
+}
+
+#[actix_web::test]
+async fn the_balances_of_several_accounts_are_answered_as_of_the_block() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(dir.path().join("db").to_string_lossy().into_owned())
+        .db_receiver(receiver)
+        .build()
+        .expect("db opens");
+    tokio::spawn(async move { db.run().await });
+    for (block_no, block) in [
+        (1, block(10, &[(key(1), 100), (key(2), 200)])),
+        (2, block(20, &[(key(1), 150)])),
+    ] {
+        sender
+            .send(ProtocolMessage::FinalizeBlock(block_no, block))
+            .expect("db running");
+    }
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(sender))
+            .app_data(web::Data::new(QueryConfig::default()))
+            .configure(server::configure),
+    )
+    .await;
+
+    let accounts = [key(1), key(2), key(3)].map(|key| key.to_string());
+    let response = test::call_service(&app, balances_at(&accounts, 1)).await;
+    assert_eq!(response.status(), 200);
+    let at: BalancesAt = test::read_body_json(response).await;
+    assert_eq!((at.block_no, at.slot), (1, Some(10)));
+    assert_eq!(at.balances[&accounts[0]].balance, 100);
+    assert_eq!(at.balances[&accounts[1]].balance, 200);
+    assert!(!at.balances[&accounts[2]].known);
+
+    let response = test::call_service(&app, balances_at(&accounts, 2)).await;
+    let at: BalancesAt = test::read_body_json(response).await;
+    assert_eq!(at.balances[&accounts[0]].balance, 150);
+    assert_eq!(at.balances[&accounts[1]].balance, 200);
+}
+
+#[actix_web::test]
+async fn a_malformed_key_is_rejected_by_name_before_the_db_is_asked() {
+    // A db that never takes its messages, so a request reaching it would time out
+    let channel = Channel::<ProtocolMessage>::new();
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(channel.sender()))
+            .app_data(web::Data::new(QueryConfig {
+                request_timeout_ms: 50,
+                ..QueryConfig::default()
+            }))
+            .configure(server::configure),
+    )
+    .await;
+
+    let accounts = [key(1).to_string(), "not-a-key".to_string()];
+    let response = test::call_service(&app, balances_at(&accounts, 1)).await;
+    assert_eq!(response.status(), 400);
+    let body: ErrorBody = test::read_body_json(response).await;
+    assert_eq!(body.error.code, "bad_request");
+    assert!(body.error.message.contains("not-a-key"));
+
+    let response = test::call_service(&app, balances_at(&[], 1)).await;
+    assert_eq!(response.status(), 400);
+}
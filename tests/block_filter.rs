@@ -0,0 +1,80 @@
+use serde_json::json;
+use solana_agg::util::{Block, BlockFilter, Instruction, TxRecord};
+use solana_program::hash::hash;
+
+fn record(accounts: &[&str], transferred: f64, succeeded: bool) -> TxRecord {
+    let record = TxRecord::new(
+        vec![Instruction::Transfer(
+            accounts[0].to_string(),
+            "Destination".to_string(),
+            transferred,
+        )],
+        None,
+    )
+    .expect("record")
+    .with_accounts(accounts.iter().map(|account| account.to_string()).collect());
+    let mut json = serde_json::to_value(record).expect("serializes");
+    json["succeeded"] = json!(succeeded);
+    serde_json::from_value(json).expect("deserializes")
+}
+
+fn block() -> Block {
+    let mut block = Block::default();
+    block.push_transaction(hash(&[0]), record(&["Payer1", "Program1"], 0.5, true));
+    block.push_transaction(hash(&[1]), record(&["Payer2", "Program2"], 12.0, false));
+    block.push_transaction(hash(&[2]), record(&["Payer3", "Program1"], 20.0, true));
+    for (account, balance) in [("Payer1", 1), ("Payer2", 2), ("Payer3", 3)] {
+        block.insert_account(account.to_string(), balance);
+    }
+    block
+}
+
+fn tx_ids(block: &Block) -> Vec<String> {
+    block.get_tx_hash()
+}
+
+#[test]
+fn the_empty_filter_keeps_blocks_whole() {
+    let filter = BlockFilter::default();
+    assert!(filter.is_empty());
+    assert_eq!(tx_ids(&filter.apply(&block()).expect("kept")).len(), 3);
+}
+
+#[test]
+fn filters_keep_the_matching_transactions_and_their_balances() {
+    let filter = BlockFilter {
+        programs: vec!["Program1".to_string()],
+        min_amount: Some(10.0),
+        ..BlockFilter::default()
+    };
+    let filtered = filter.apply(&block()).expect("kept");
+    assert_eq!(tx_ids(&filtered), vec![hash(&[2]).to_string()]);
+    let json = serde_json::to_value(&filtered).expect("serializes");
+    assert_eq!(json["account_map"], json!({"Payer3": 3}));
+
+    let failed = BlockFilter {
+        failed_only: true,
+        ..BlockFilter::default()
+    };
+    assert_eq!(
+        tx_ids(&failed.apply(&block()).expect("kept")),
+        vec![hash(&[1]).to_string()]
+    );
+
+    // Transfer parties match the accounts filter
+    let accounts = BlockFilter {
+        accounts: vec!["Destination".to_string(), "Payer1".to_string()],
+        ..BlockFilter::default()
+    };
+    assert_eq!(tx_ids(&accounts.apply(&block()).expect("kept")).len(), 3);
+}
+
+#[test]
+fn blocks_without_a_matching_transaction_are_not_sent() {
+    let filter = BlockFilter {
+        programs: vec!["Program2".to_string()],
+        min_amount: Some(100.0),
+        ..BlockFilter::default()
+    };
+    assert!(filter.apply(&block()).is_none());
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use actix_web::{test, web, App};
+use common::block;
+use serde_json::Value;
+use solana_agg::config::{Commitment, QueryConfig};
+use solana_agg::envelope::{Finality, SlotTracker};
+use solana_agg::server;
+use solana_agg::util::ProtocolMessage;
+use solana_agg::Builder;
+use std::sync::Arc;
+
+#[actix_web::test]
+async fn block_details_are_looked_up_by_height_or_through_the_slot_index() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(dir.path().join("db").to_string_lossy().into_owned())
+        .db_receiver(receiver)
+        .build()
+        .expect("db opens");
+    tokio::spawn(async move { db.run().await });
+    for (block_no, slot) in [(1, 10), (2, 25)] {
+        sender
+            .send(ProtocolMessage::FinalizeBlock(block_no, block(slot)))
+            .expect("db running");
+    }
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(sender))
+            .app_data(web::Data::new(QueryConfig::default()))
+            .app_data(web::Data::new(Finality::new(
+                Commitment::Finalized,
+                Arc::new(SlotTracker::default()),
+            )))
+            .configure(server::configure),
+    )
+    .await;
+    let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+
+    let response = test::call_service(&app, get("/block_details/25?by=slot")).await;
+    assert_eq!(response.status(), 200);
+    let by_slot: Value = test::read_body_json(response).await;
+    assert_eq!(by_slot["block_height"], 2);
+    let response = test::call_service(&app, get("/block_details/2")).await;
+    assert_eq!(response.status(), 200);
+    let by_height: Value = test::read_body_json(response).await;
+    assert_eq!(by_height, by_slot);
+
+    // Slot 2 has no block, only height 2 does
+    let response = test::call_service(&app, get("/block_details/2?by=slot")).await;
+    assert_eq!(response.status(), 404);
+}
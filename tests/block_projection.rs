@@ -0,0 +1,86 @@
+use serde_json::json;
+use solana_agg::envelope::{Expand, ResponseFormat};
+use solana_agg::util::{Block, Instruction, TxRecord};
+use solana_program::hash::hash;
+
+fn token_transfer(mint: Option<&str>, amount: u64) -> Instruction {
+    Instruction::TokenTransfer {
+        source: "Source".to_string(),
+        destination: "Destination".to_string(),
+        source_owner: None,
+        destination_owner: None,
+        mint: mint.map(str::to_string),
+        amount,
+        decimals: Some(6),
+    }
+}
+
+fn block() -> Block {
+    let mut block = Block::default();
+    let transfers = TxRecord::new(
+        vec![
+            Instruction::Transfer("A".to_string(), "B".to_string(), 1.5),
+            Instruction::Transfer("B".to_string(), "C".to_string(), 0.25),
+            token_transfer(Some("Mint"), 400),
+            token_transfer(Some("Mint"), 100),
+            token_transfer(None, 7),
+        ],
+        None,
+    )
+    .expect("record");
+    block.push_transaction(hash(&[1]), transfers);
+    block.push_transaction(hash(&[0]), TxRecord::new(vec![], None).expect("record"));
+    block.insert_account("A".to_string(), 5_000);
+    block
+}
+
+#[test]
+fn blocks_list_transaction_summaries_by_default() {
+    let projected = ResponseFormat::default()
+        .project_block(block())
+        .expect("projects");
+    assert!(projected.get("tx_map").is_none());
+    assert!(projected.get("tx_order").is_none());
+    assert_eq!(projected["account_map"], json!({"A": 5_000}));
+
+    let transactions = projected["transactions"].as_array().expect("summaries");
+    assert_eq!(transactions.len(), 2);
+    // In the order of the block
+    assert_eq!(transactions[0]["tx_id"], hash(&[1]).to_string());
+    assert_eq!(transactions[1]["tx_id"], hash(&[0]).to_string());
+    assert_eq!(transactions[0]["sol_transferred"], 1.75);
+    assert_eq!(transactions[0]["token_transfers"], 3);
+    assert_eq!(transactions[0]["token_transferred"], json!({"Mint": 500}));
+    assert_eq!(transactions[1]["sol_transferred"], 0.0);
+    assert!(transactions[1].get("token_transferred").is_none());
+}
+
+#[test]
+fn expand_full_keeps_the_full_records() {
+    let format = ResponseFormat {
+        expand: Expand::Full,
+        ..ResponseFormat::default()
+    };
+    let projected = format.project_block(block()).expect("projects");
+    assert!(projected.get("transactions").is_none());
+    assert_eq!(projected["tx_map"].as_object().expect("records").len(), 2);
+    assert_eq!(
+        projected["tx_order"],
+        json!([hash(&[1]).to_string(), hash(&[0]).to_string()])
+    );
+}
+
+#[test]
+fn block_height_is_served_once_recorded() {
+    let projected = ResponseFormat::default()
+        .project_block(block())
+        .expect("projects");
+    assert!(projected.get("block_height").is_none());
+
+    let mut block = block();
+    block.set_block_height(42);
+    let projected = ResponseFormat::default()
+        .project_block(block)
+        .expect("projects");
+    assert_eq!(projected["block_height"], json!(42));
+}
@@ -0,0 +1,103 @@
+mod common;
+
+use common::block;
+use solana_agg::fanout::{self, SUBSCRIBER_CAPACITY};
+use solana_agg::metrics::FAN_OUT_DROPPED;
+use solana_agg::util::{BlockFilter, ProtocolMessage, Response};
+use solana_agg::Builder;
+use std::time::Duration;
+use tokio::sync::mpsc::{Receiver, UnboundedSender};
+
+fn open_db(dir: &tempfile::TempDir) -> UnboundedSender<ProtocolMessage> {
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(dir.path().join("db").to_string_lossy().into_owned())
+        .db_receiver(receiver)
+        .build()
+        .expect("db opens");
+    tokio::spawn(async move { db.run().await });
+    sender
+}
+
+fn store(sender: &UnboundedSender<ProtocolMessage>, block_nos: std::ops::RangeInclusive<u64>) {
+    for block_no in block_nos {
+        sender
+            .send(ProtocolMessage::FinalizeBlock(
+                block_no,
+                block(block_no * 10),
+            ))
+            .expect("db running");
+    }
+}
+
+fn subscribe(
+    sender: &UnboundedSender<ProtocolMessage>,
+    from_block_no: Option<u64>,
+) -> Receiver<ProtocolMessage> {
+    let (subscriber, events) = fanout::subscriber_channel();
+    sender
+        .send(ProtocolMessage::SubscribeBlocks(
+            from_block_no,
+            BlockFilter::default(),
+            subscriber,
+        ))
+        .expect("db running");
+    events
+}
+
+/// Block numbers of the stream until it is ended
+async fn block_nos(events: &mut Receiver<ProtocolMessage>, count: usize) -> Vec<u64> {
+    let mut block_nos = Vec::new();
+    while block_nos.len() < count {
+        match tokio::time::timeout(Duration::from_secs(10), events.recv()).await {
+            Ok(Some(ProtocolMessage::NewBlock(block_no, _))) => block_nos.push(block_no),
+            Ok(None) => break,
+            other => panic!("unexpected message {other:?}"),
+        }
+    }
+    block_nos
+}
+
+#[tokio::test]
+async fn a_stream_that_is_not_read_is_ended_once_its_queue_is_full() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let sender = open_db(&dir);
+    let mut slow = subscribe(&sender, None);
+    let mut fast = subscribe(&sender, None);
+    let total = SUBSCRIBER_CAPACITY as u64 + 5;
+    let reader = tokio::spawn(async move { block_nos(&mut fast, total as usize).await });
+    store(&sender, 1..=total);
+
+    // The stream read along gets every block
+    assert_eq!(
+        reader.await.expect("reads"),
+        (1..=total).collect::<Vec<_>>()
+    );
+    // The other one keeps what fit in its queue and is then ended instead of growing
+    let kept = block_nos(&mut slow, usize::MAX).await;
+    assert_eq!(kept, (1..=SUBSCRIBER_CAPACITY as u64).collect::<Vec<_>>());
+    assert!(FAN_OUT_DROPPED.with_label_values(&["block_stream"]).get() >= 1);
+}
+
+#[tokio::test]
+async fn a_replay_longer_than_the_queue_waits_for_the_client() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let sender = open_db(&dir);
+    let stored = SUBSCRIBER_CAPACITY as u64 + 10;
+    store(&sender, 1..=stored);
+    match ProtocolMessage::ask(&sender, ProtocolMessage::FetchStatus).await {
+        Ok(Response::Status(status)) => assert_eq!(status.latest_block_no, Some(stored)),
+        other => panic!("unexpected response {other:?}"),
+    }
+
+    let mut events = subscribe(&sender, Some(1));
+    // The client only starts reading once the replay filled its queue
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(
+        block_nos(&mut events, stored as usize).await,
+        (1..=stored).collect::<Vec<_>>()
+    );
+    // Blocks stored after the replay follow it
+    store(&sender, stored + 1..=stored + 1);
+    assert_eq!(block_nos(&mut events, 1).await, vec![stored + 1]);
+}
@@ -0,0 +1,87 @@
+use solana_agg::timestamp::{sanitize, SanitizedTime, TimeAnchor};
+use solana_agg::util::{Block, BlockHeader, BlockTimeFlag};
+
+fn anchor(slot: u64, block_time: i64) -> Option<TimeAnchor> {
+    Some(TimeAnchor { slot, block_time })
+}
+
+#[test]
+fn monotonic_times_are_kept() {
+    let sanitized = sanitize(
+        11,
+        Some(1_700_000_004),
+        anchor(10, 1_700_000_000),
+        anchor(12, 1_700_000_008),
+    );
+    assert_eq!(sanitized.block_time, Some(1_700_000_004));
+    assert_eq!(sanitized.flag, None);
+}
+
+#[test]
+fn missing_times_are_interpolated_between_neighbours() {
+    let sanitized = sanitize(
+        15,
+        None,
+        anchor(10, 1_700_000_000),
+        anchor(20, 1_700_000_010),
+    );
+    assert_eq!(sanitized.block_time, Some(1_700_000_005));
+    assert_eq!(sanitized.flag, Some(BlockTimeFlag::Interpolated));
+}
+
+#[test]
+fn missing_times_are_extrapolated_from_a_single_neighbour() {
+    // 400ms slots
+    let after = sanitize(20, None, anchor(10, 1_700_000_000), None);
+    assert_eq!(after.block_time, Some(1_700_000_004));
+    let before = sanitize(10, None, None, anchor(20, 1_700_000_004));
+    assert_eq!(before.block_time, Some(1_700_000_000));
+    let alone = sanitize(10, None, None, None);
+    assert_eq!(
+        alone,
+        SanitizedTime {
+            block_time: None,
+            flag: None
+        }
+    );
+}
+
+#[test]
+fn regressing_times_are_clamped_and_flagged() {
+    let behind = sanitize(11, Some(1_699_999_990), anchor(10, 1_700_000_000), None);
+    assert_eq!(behind.block_time, Some(1_700_000_000));
+    assert_eq!(behind.flag, Some(BlockTimeFlag::OutOfOrder));
+    let ahead = sanitize(
+        11,
+        Some(1_700_000_100),
+        anchor(10, 1_700_000_000),
+        anchor(12, 1_700_000_001),
+    );
+    assert_eq!(ahead.block_time, Some(1_700_000_001));
+    assert_eq!(ahead.flag, Some(BlockTimeFlag::OutOfOrder));
+}
+
+#[test]
+fn blocks_keep_the_raw_time_next_to_the_sanitized_one() {
+    let mut block = Block::default();
+    block.set_header(BlockHeader {
+        slot: 11,
+        blockhash: "hash".to_string(),
+        block_time: Some(1_699_999_990),
+        ..Default::default()
+    });
+    block.set_sanitized_block_time(sanitize(
+        11,
+        block.raw_block_time(),
+        anchor(10, 1_700_000_000),
+        None,
+    ));
+    let block: Block =
+        serde_json::from_slice(&serde_json::to_vec(&block).expect("serializes")).expect("parses");
+    assert_eq!(block.raw_block_time(), Some(1_699_999_990));
+    assert_eq!(block.block_time(), Some(1_700_000_000));
+    let summary = block.summary(11);
+    assert_eq!(summary.block_time, Some(1_700_000_000));
+    assert_eq!(summary.raw_block_time, Some(1_699_999_990));
+    assert_eq!(summary.block_time_flag, Some(BlockTimeFlag::OutOfOrder));
+}
@@ -0,0 +1,107 @@
+use solana_agg::breaker::CircuitBreaker;
+use solana_agg::config::BreakerConfig;
+use solana_agg::error::AggError;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+fn breaker() -> CircuitBreaker {
+    CircuitBreaker::new(BreakerConfig {
+        failure_threshold: 3,
+        open_ms: 1_000,
+    })
+}
+
+#[test]
+fn opens_after_the_threshold_of_failures_in_a_row_and_lets_a_probe_through_after_open_ms() {
+    let breaker = breaker();
+    let now = Instant::now();
+    breaker.record(true, now);
+    breaker.record(true, now);
+    assert!(breaker.check(now).is_ok());
+    breaker.record(true, now);
+    let err = breaker.check(now).expect_err("open after 3 failures");
+    assert_eq!(err.code(), "upstream_unavailable");
+    assert!(breaker.check(now + Duration::from_millis(999)).is_err());
+    let later = now + Duration::from_millis(1_000);
+    assert!(breaker.check(later).is_ok());
+    // The other calls fail until the outcome of the probe is recorded
+    assert!(breaker.check(later).is_err());
+    breaker.record(false, later);
+    assert!(breaker.check(later).is_ok());
+    assert!(breaker.check(later).is_ok());
+}
+
+#[test]
+fn a_success_resets_the_failures_in_a_row() {
+    let breaker = breaker();
+    let now = Instant::now();
+    breaker.record(true, now);
+    breaker.record(true, now);
+    breaker.record(false, now);
+    breaker.record(true, now);
+    breaker.record(true, now);
+    assert!(breaker.check(now).is_ok());
+}
+
+#[tokio::test]
+async fn timed_out_calls_open_the_breaker_and_later_calls_fail_without_running() {
+    let breaker = breaker();
+    for _ in 0..3 {
+        let result: Result<(), AggError> = breaker
+            .call(Duration::from_millis(10), async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                Ok(())
+            })
+            .await;
+        assert!(matches!(result, Err(AggError::QueryTimedOut)));
+    }
+    let mut ran = false;
+    let result = breaker
+        .call(Duration::from_secs(1), async {
+            ran = true;
+            Ok(())
+        })
+        .await;
+    assert!(matches!(result, Err(AggError::UpstreamUnavailable(_))));
+    assert!(!ran, "the call reached the node while the breaker was open");
+}
+
+#[tokio::test]
+async fn permanent_errors_do_not_count_as_failures() {
+    let breaker = breaker();
+    for _ in 0..5 {
+        let result: Result<(), AggError> = breaker
+            .call(Duration::from_secs(1), async {
+                Err(AggError::NotFound("slot skipped".to_string()))
+            })
+            .await;
+        assert!(matches!(result, Err(AggError::NotFound(_))));
+    }
+    assert!(breaker.check(Instant::now()).is_ok());
+}
+
+#[tokio::test]
+async fn a_single_call_probes_the_node_once_open_ms_passed() {
+    let breaker = CircuitBreaker::new(BreakerConfig {
+        failure_threshold: 1,
+        open_ms: 50,
+    });
+    breaker.record(true, Instant::now());
+    tokio::time::sleep(Duration::from_millis(60)).await;
+
+    let reached = AtomicUsize::new(0);
+    // The node is still down, the call times out
+    let call = || {
+        breaker.call(Duration::from_millis(20), async {
+            reached.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok::<(), AggError>(())
+        })
+    };
+    let (probe, other) = tokio::join!(call(), call());
+    assert_eq!(reached.load(Ordering::SeqCst), 1);
+    assert!(matches!(probe, Err(AggError::QueryTimedOut)));
+    assert!(matches!(other, Err(AggError::UpstreamUnavailable(_))));
+    // The failed probe opened the breaker again
+    assert!(breaker.check(Instant::now()).is_err());
+}
@@ -0,0 +1,56 @@
+use solana_agg::chain::{self, chain_id_of, SyncState};
+use solana_agg::envelope::{SlotContext, SlotTracker};
+use solana_agg::metrics;
+
+#[test]
+fn public_clusters_are_named_and_others_shortened() {
+    assert_eq!(
+        chain_id_of("5eykt4UsFv8P8NJdTREpY1vzqKqZKvdpKuc147dw2N9d"),
+        "mainnet-beta"
+    );
+    assert_eq!(
+        chain_id_of("EtWTRABZaYq6iMfeYKouRu166VU2xqa1wcaWoxPkrZBG"),
+        "devnet"
+    );
+    assert_eq!(
+        chain_id_of("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin"),
+        "9xQeWvG8"
+    );
+}
+
+#[test]
+fn sync_state_reports_how_far_indexing_trails_the_chain() {
+    let state = SyncState::new(
+        "devnet",
+        SlotContext {
+            latest_indexed: Some(1_000),
+            finalized: Some(1_250),
+        },
+    );
+    assert_eq!(state.slot_lag, Some(250));
+    let not_ingesting = SyncState::new(
+        "devnet",
+        SlotContext {
+            latest_indexed: Some(1_000),
+            finalized: None,
+        },
+    );
+    assert_eq!(not_ingesting.slot_lag, None);
+}
+
+#[test]
+fn metrics_are_labeled_with_the_chain_id_once_it_is_set() {
+    let tracker = SlotTracker::default();
+    tracker.set_finalized(1_250);
+    assert!(!metrics::render().contains("chain_id="));
+
+    chain::install("devnet".to_string()).expect("first chain id");
+    assert_eq!(chain::installed(), Some("devnet"));
+    // A process indexes a single chain
+    assert!(chain::install("devnet".to_string()).is_ok());
+    let err = chain::install("mainnet-beta".to_string()).unwrap_err();
+    assert_eq!(err.code(), "config");
+    assert_eq!(chain::installed(), Some("devnet"));
+    // The chain id is appended to the labels of every metric
+    assert!(metrics::render().contains("agg_slot{slot=\"finalized\",chain_id=\"devnet\"} 1250"));
+}
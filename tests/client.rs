@@ -0,0 +1,45 @@
+#![cfg(feature = "client")]
+
+use solana_agg::client::{self, filter_query, route};
+use solana_agg::util::BlockFilter;
+
+#[test]
+fn every_client_route_is_served() {
+    let server = include_str!("../src/server.rs");
+    for template in client::ROUTES {
+        assert!(
+            server.contains(&format!("#[get(\"{}\")]", template)),
+            "{} is not a route of the server",
+            template
+        );
+    }
+}
+
+#[test]
+fn routes_are_filled_in_order() {
+    assert_eq!(route(client::TX_DETAILS, &["abc"]), "/tx_details/abc");
+    assert_eq!(
+        route(client::BLOCK_RANGE, &["10", "20"]),
+        "/block_range/10/20"
+    );
+    assert_eq!(route(client::STATUS, &[]), "/status");
+}
+
+#[test]
+fn block_filters_become_stream_parameters() {
+    assert!(filter_query(&BlockFilter::default()).is_empty());
+    let filter = BlockFilter {
+        programs: vec!["P1".to_string(), "P2".to_string()],
+        accounts: vec![],
+        min_amount: Some(1.5),
+        failed_only: true,
+    };
+    assert_eq!(
+        filter_query(&filter),
+        vec![
+            ("programs", "P1,P2".to_string()),
+            ("min_amount", "1.5".to_string()),
+            ("failed_only", "true".to_string()),
+        ]
+    );
+}
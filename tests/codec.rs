@@ -0,0 +1,77 @@
+use serde_json::Value;
+use solana_agg::codec::{decode, Codec};
+use solana_agg::util::{Block, BlockHeader, PendingRefetch, RawBlock};
+use solana_transaction_status::UiConfirmedBlock;
+use std::path::Path;
+
+fn fixture(name: &str) -> Vec<u8> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name);
+    std::fs::read(path).unwrap()
+}
+
+fn raw_block() -> RawBlock {
+    let fetched: UiConfirmedBlock =
+        serde_json::from_slice(&fixture("system_transfers.json")).unwrap();
+    RawBlock {
+        header: BlockHeader {
+            slot: fetched.parent_slot + 1,
+            blockhash: fetched.blockhash.clone(),
+            block_time: fetched.block_time,
+            previous_blockhash: Some(fetched.previous_blockhash.clone()),
+            parent_slot: Some(fetched.parent_slot),
+            transaction_count: fetched.transactions.as_ref().map(|txs| txs.len() as u64),
+        },
+        transactions: fetched.transactions.unwrap_or_default(),
+    }
+}
+
+#[test]
+fn legacy_json_records_are_read_and_rewritten_in_bincode() {
+    // Parsed fixtures are blocks as stored before the codec existed
+    let legacy = fixture("system_transfers.parsed.json");
+    let block = decode::<Block>(&legacy).unwrap();
+    let expected: Value = serde_json::from_slice(&legacy).unwrap();
+    assert_eq!(serde_json::to_value(&block).unwrap(), expected);
+
+    let json = Codec::Json.encode(&block).unwrap();
+    assert_eq!(json[0], 0x01);
+    let bincode = Codec::Bincode.encode(&block).unwrap();
+    assert_eq!(bincode[0], 0x02);
+    assert!(bincode.len() < json.len());
+    for encoded in [json, bincode] {
+        let decoded = decode::<Block>(&encoded).unwrap();
+        assert_eq!(serde_json::to_value(&decoded).unwrap(), expected);
+    }
+}
+
+#[test]
+fn raw_blocks_round_trip_with_either_codec() {
+    let raw_block = raw_block();
+    let expected = serde_json::to_value(&raw_block).unwrap();
+    for codec in [Codec::Json, Codec::Bincode] {
+        let decoded = decode::<RawBlock>(&codec.encode(&raw_block).unwrap()).unwrap();
+        assert_eq!(serde_json::to_value(&decoded).unwrap(), expected, "{codec}");
+    }
+}
+
+#[test]
+fn pending_refetches_round_trip_with_either_codec() {
+    let block = decode::<Block>(&fixture("system_transfers.parsed.json")).unwrap();
+    let refetch = PendingRefetch {
+        block,
+        raw_block: Some(raw_block()),
+    };
+    let expected = serde_json::to_value(&refetch).unwrap();
+    for codec in [Codec::Json, Codec::Bincode] {
+        let decoded = decode::<PendingRefetch>(&codec.encode(&refetch).unwrap()).unwrap();
+        assert_eq!(serde_json::to_value(&decoded).unwrap(), expected, "{codec}");
+    }
+}
+
+#[test]
+fn corrupted_bincode_records_fail_with_a_codec_error() {
+    let err = decode::<Block>(&[0x02, 0xff, 0x00]).unwrap_err();
+    assert_eq!(err.code(), "codec");
+}
@@ -0,0 +1,48 @@
+use actix_web::http::StatusCode;
+use actix_web::web::Query;
+use solana_agg::config::Commitment;
+use solana_agg::envelope::{Finality, SlotTracker};
+use solana_agg::util::CommitmentParams;
+use std::sync::Arc;
+
+#[test]
+fn confirmed_blocks_are_finalized_once_the_chain_finalized_their_slot() {
+    let slot_tracker = Arc::new(SlotTracker::default());
+    let finality = Finality::new(Commitment::Confirmed, slot_tracker.clone());
+    let finalized = Some(Commitment::Finalized);
+
+    // Nothing is finalized while the finalized slot is not known
+    assert!(!finality.admits(finalized, Some(10)));
+    slot_tracker.set_finalized(100);
+    assert!(finality.admits(finalized, Some(100)));
+    assert!(!finality.admits(finalized, Some(101)));
+    // Blocks stored without their slot are never taken for finalized
+    assert!(!finality.admits(finalized, None));
+
+    // Queries not asking for finalized blocks see every block
+    assert!(!finality.filters(None));
+    assert!(finality.admits(None, Some(101)));
+    assert!(finality.admits(Some(Commitment::Confirmed), Some(101)));
+
+    let err = finality.check(finalized, Some(101)).unwrap_err();
+    assert_eq!(err.code(), "not_finalized");
+    assert_eq!(err.status(), StatusCode::NOT_FOUND);
+    assert!(finality.check(finalized, Some(99)).is_ok());
+}
+
+#[test]
+fn blocks_ingested_at_finalized_are_always_finalized() {
+    let finality = Finality::new(Commitment::Finalized, Arc::new(SlotTracker::default()));
+    assert!(!finality.filters(Some(Commitment::Finalized)));
+    assert!(finality.admits(Some(Commitment::Finalized), Some(10)));
+    assert!(finality.admits(Some(Commitment::Finalized), None));
+}
+
+#[test]
+fn the_commitment_is_read_from_the_query() {
+    let params = Query::<CommitmentParams>::from_query("commitment=finalized").unwrap();
+    assert_eq!(params.commitment, Some(Commitment::Finalized));
+    let params = Query::<CommitmentParams>::from_query("expand=full").unwrap();
+    assert_eq!(params.commitment, None);
+    assert!(Query::<CommitmentParams>::from_query("commitment=processed").is_err());
+}
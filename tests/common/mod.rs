@@ -0,0 +1,53 @@
+//! Fixtures shared by the tests
+#![allow(dead_code)]
+
+use serde_json::Value;
+use solana_agg::config::ParseMode;
+use solana_agg::parser::Parser;
+use solana_agg::util::{Block, BlockHeader, Channel, ProtocolMessage};
+use solana_program::pubkey::Pubkey;
+
+pub const TOKEN_PROGRAM: &str = "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA";
+pub const MINT: &str = "Gq2ZG2URrWhVFeWRua7PnRuipDytEXyh26VgCo7cBm46";
+
+pub fn key(byte: u8) -> Pubkey {
+    Pubkey::new_from_array([byte; 32])
+}
+
+/// An empty block of the slot, hashed `hash-<slot>`
+pub fn block(slot: u64) -> Block {
+    let mut block = Block::default();
+    block.set_header(BlockHeader {
+        slot,
+        blockhash: format!("hash-{}", slot),
+        ..Default::default()
+    });
+    block
+}
+
+/// Parses a block of slot 1 holding the transaction
+pub async fn parse(tx: Value) -> Block {
+    let mut channel = Channel::<ProtocolMessage>::new();
+    let header = BlockHeader {
+        slot: 1,
+        blockhash: "hash".to_string(),
+        ..Default::default()
+    };
+    Parser::invoke(
+        ProtocolMessage::new_chuck(
+            1,
+            header,
+            0,
+            1,
+            vec![serde_json::from_value(tx).expect("valid transaction")],
+            channel.sender(),
+        ),
+        ParseMode::Permissive,
+    )
+    .await
+    .expect("parses");
+    match channel.receiver.recv().await {
+        Some(ProtocolMessage::ParsedBlock(_, _, _, block)) => block,
+        other => panic!("unexpected message {other:?}"),
+    }
+}
@@ -0,0 +1,18 @@
+use solana_agg::cli::Cli;
+use solana_agg::config::Config;
+use structopt::StructOpt;
+
+#[test]
+fn a_zero_compaction_interval_is_rejected() {
+    let config: Config =
+        toml::from_str("[compaction]\ninterval_minutes = 60").expect("valid config");
+    assert_eq!(config.compaction.interval_minutes, Some(60));
+    assert_eq!(Config::default().compaction.interval_minutes, None);
+
+    let dir = tempfile::tempdir().expect("temp dir");
+    let path = dir.path().join("config.toml");
+    std::fs::write(&path, "[compaction]\ninterval_minutes = 0").expect("config written");
+    let cli = Cli::from_iter(["solana-agg", "--config", path.to_str().expect("utf-8 path")]);
+    let error = Config::resolve(&cli).expect_err("a zero interval is rejected");
+    assert!(error.to_string().contains("compaction.interval_minutes"));
+}
@@ -0,0 +1,175 @@
+mod common;
+
+use actix_web::{web, App};
+use common::key;
+use serde_json::{json, Value};
+use solana_agg::aggregation::RuleEngine;
+use solana_agg::config::{AggregationRule, QueryConfig, RuleBucket, RuleKind};
+use solana_agg::server;
+use solana_agg::util::{Block, BlockHeader, Instruction, ProtocolMessage, Response, TxRecord};
+use solana_agg::Builder;
+use solana_program::hash::hash;
+use solana_program::pubkey::Pubkey;
+
+/// 2023-11-14T22:13:20Z
+const BLOCK_TIME: i64 = 1_700_000_000;
+const DAY: i64 = 24 * 60 * 60;
+
+fn rule(name: &str, kind: RuleKind, bucket: RuleBucket) -> AggregationRule {
+    AggregationRule {
+        name: name.to_string(),
+        kind,
+        bucket,
+    }
+}
+
+fn treasury_rules() -> Vec<AggregationRule> {
+    let to_treasury = || RuleKind::SumTransfersTo {
+        addresses: vec![key(2).to_string()],
+    };
+    vec![
+        rule("treasury_daily", to_treasury(), RuleBucket::Day),
+        rule("treasury_epoch", to_treasury(), RuleBucket::Epoch),
+        rule("treasury_total", to_treasury(), RuleBucket::Total),
+        rule(
+            "program_calls",
+            RuleKind::CountProgramCalls {
+                program: key(9).to_string(),
+            },
+            RuleBucket::Total,
+        ),
+    ]
+}
+
+/// A block of the slot with a transfer from `key(1)` for each of `transfers`
+fn transfer_block(slot: u64, block_time: Option<i64>, transfers: &[(Pubkey, f64)]) -> Block {
+    let mut block = Block::default();
+    block.set_header(BlockHeader {
+        slot,
+        blockhash: format!("hash-{}", slot),
+        block_time,
+        ..Default::default()
+    });
+    for (transfer, (to, amount)) in transfers.iter().enumerate() {
+        block.push_transaction(
+            hash(format!("{}/{}", slot, transfer).as_bytes()),
+            TxRecord::new(
+                vec![Instruction::Transfer(
+                    key(1).to_string(),
+                    to.to_string(),
+                    *amount,
+                )],
+                None,
+            )
+            .expect("record"),
+        );
+    }
+    block
+}
+
+#[test]
+fn rules_sum_the_transfers_to_their_addresses_into_their_bucket() {
+    let engine = RuleEngine::new(treasury_rules());
+    // Slot 432_001 is in epoch 1
+    let block = transfer_block(
+        432_001,
+        Some(BLOCK_TIME),
+        &[(key(2), 1.5), (key(3), 5.0), (key(2), 2.0)],
+    );
+    assert_eq!(
+        engine.evaluate(&block),
+        vec![
+            ("treasury_daily", "2023-11-14".to_string(), 3.5),
+            ("treasury_epoch", "1".to_string(), 3.5),
+            ("treasury_total", "total".to_string(), 3.5),
+        ]
+    );
+}
+
+#[test]
+fn blocks_without_a_time_are_left_out_of_the_day_buckets() {
+    let engine = RuleEngine::new(treasury_rules());
+    let block = transfer_block(10, None, &[(key(2), 1.0)]);
+    assert_eq!(
+        engine.evaluate(&block),
+        vec![
+            ("treasury_epoch", "0".to_string(), 1.0),
+            ("treasury_total", "total".to_string(), 1.0),
+        ]
+    );
+}
+
+#[test]
+fn program_calls_are_counted_and_rules_a_block_does_not_touch_are_skipped() {
+    let engine = RuleEngine::new(treasury_rules());
+    let mut block = transfer_block(10, Some(BLOCK_TIME), &[(key(3), 1.0)]);
+    block.record_program_call(key(9).to_string());
+    block.record_program_call(key(9).to_string());
+    block.record_program_call(key(8).to_string());
+    assert_eq!(
+        engine.evaluate(&block),
+        vec![("program_calls", "total".to_string(), 2.0)]
+    );
+    assert!(engine.has_rule("treasury_daily"));
+    assert!(!engine.has_rule("unknown"));
+}
+
+#[actix_web::test]
+async fn custom_stats_are_served_per_bucket_and_count_a_block_stored_twice_once() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(dir.path().join("db").to_string_lossy().into_owned())
+        .db_receiver(receiver)
+        .build()
+        .expect("db opens");
+    db.set_rule_engine(RuleEngine::new(treasury_rules()));
+    tokio::spawn(async move { db.run().await });
+    let blocks = [
+        (1, transfer_block(10, Some(BLOCK_TIME), &[(key(2), 1.5)])),
+        (2, transfer_block(11, Some(BLOCK_TIME), &[(key(2), 2.0)])),
+        (
+            3,
+            transfer_block(12, Some(BLOCK_TIME + DAY), &[(key(2), 1.0)]),
+        ),
+    ];
+    for (block_no, block) in blocks.iter().cloned() {
+        sender
+            .send(ProtocolMessage::FinalizeBlock(block_no, block))
+            .expect("db running");
+    }
+    // Delivered again, e.g. by a retried write
+    sender
+        .send(ProtocolMessage::FinalizeBlock(3, blocks[2].1.clone()))
+        .expect("db running");
+    match ProtocolMessage::ask(&sender, ProtocolMessage::FetchStatus).await {
+        Ok(Response::Status(status)) => assert_eq!(status.latest_block_no, Some(3)),
+        other => panic!("unexpected response {other:?}"),
+    }
+    let app = actix_web::test::init_service(
+        App::new()
+            .app_data(web::Data::new(sender))
+            .app_data(web::Data::new(QueryConfig::default()))
+            .configure(server::configure),
+    )
+    .await;
+    let get = |uri: &str| actix_web::test::TestRequest::get().uri(uri).to_request();
+
+    let response = actix_web::test::call_service(&app, get("/custom_stats/treasury_daily")).await;
+    assert_eq!(response.status(), 200);
+    let daily: Value = actix_web::test::read_body_json(response).await;
+    assert_eq!(daily, json!({"2023-11-14": 3.5, "2023-11-15": 1.0}));
+    let response = actix_web::test::call_service(&app, get("/custom_stats/treasury_total")).await;
+    let total: Value = actix_web::test::read_body_json(response).await;
+    assert_eq!(total, json!({"total": 4.5}));
+    // A configured rule no block contributed to has no bucket yet
+    let response = actix_web::test::call_service(&app, get("/custom_stats/program_calls")).await;
+    assert_eq!(response.status(), 200);
+    let calls: Value = actix_web::test::read_body_json(response).await;
+    assert_eq!(calls, json!({}));
+
+    let response = actix_web::test::call_service(&app, get("/custom_stats/unknown")).await;
+    assert_eq!(response.status(), 404);
+    let body: Value = actix_web::test::read_body_json(response).await;
+    assert_eq!(body["error"]["code"], "rule_not_found");
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use common::block;
+use solana_agg::util::{ProtocolMessage, Response};
+use solana_agg::Builder;
+use tokio::sync::mpsc::UnboundedSender;
+
+async fn delete(sender: &UnboundedSender<ProtocolMessage>, start: u64, end: u64) -> Vec<u64> {
+    let response = ProtocolMessage::ask(sender, |reply| {
+        ProtocolMessage::DeleteSlotRange(start, end, reply)
+    })
+    .await;
+    match response {
+        Ok(Response::DeletedSlots(deleted)) => deleted.block_nos,
+        other => panic!("unexpected response {other:?}"),
+    }
+}
+
+async fn summary_block_nos(sender: &UnboundedSender<ProtocolMessage>) -> Vec<u64> {
+    let response = ProtocolMessage::ask(sender, |reply| {
+        ProtocolMessage::FetchLatestBlockSummaries(10, reply)
+    })
+    .await;
+    match response {
+        Ok(Response::BlockSummaries(summaries)) => {
+            summaries.iter().map(|summary| summary.block_no).collect()
+        }
+        other => panic!("unexpected response {other:?}"),
+    }
+}
+
+async fn latest_block_no(sender: &UnboundedSender<ProtocolMessage>) -> Option<u64> {
+    match ProtocolMessage::ask(sender, ProtocolMessage::FetchStatus).await {
+        Ok(Response::Status(status)) => status.latest_block_no,
+        other => panic!("unexpected response {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn deleting_the_latest_block_moves_the_latest_block_to_the_one_stored_before() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(dir.path().join("db").to_string_lossy().into_owned())
+        .db_receiver(receiver)
+        .build()
+        .expect("db opens");
+    tokio::spawn(async move { db.run().await });
+    for (block_no, slot) in [(1, 10), (2, 20), (3, 30)] {
+        sender
+            .send(ProtocolMessage::FinalizeBlock(block_no, block(slot)))
+            .expect("db running");
+    }
+    assert_eq!(latest_block_no(&sender).await, Some(3));
+
+    // Deleting a block before the latest one leaves the latest block in place
+    assert_eq!(delete(&sender, 15, 25).await, vec![2]);
+    assert_eq!(latest_block_no(&sender).await, Some(3));
+    assert_eq!(summary_block_nos(&sender).await, vec![3, 1]);
+    let deleted = ProtocolMessage::ask(&sender, |reply| {
+        ProtocolMessage::FetchBlockDetails(2, reply)
+    })
+    .await;
+    assert!(deleted.is_err(), "unexpected response {deleted:?}");
+    // Block 2 is no longer stored, so the latest block moves back past it
+    assert_eq!(delete(&sender, 30, 30).await, vec![3]);
+    assert_eq!(latest_block_no(&sender).await, Some(1));
+    assert_eq!(delete(&sender, 0, 100).await, vec![1]);
+    assert_eq!(latest_block_no(&sender).await, None);
+}
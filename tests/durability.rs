@@ -0,0 +1,19 @@
+use solana_agg::cli::Cli;
+use solana_agg::config::Config;
+use structopt::StructOpt;
+
+#[test]
+fn a_zero_wal_flush_interval_is_rejected() {
+    let config: Config =
+        toml::from_str("[durability]\nwal_flush_interval_ms = 250").expect("valid config");
+    assert_eq!(config.durability.wal_flush_interval_ms, Some(250));
+
+    let dir = tempfile::tempdir().expect("temp dir");
+    let path = dir.path().join("config.toml");
+    std::fs::write(&path, "[durability]\nwal_flush_interval_ms = 0").expect("config written");
+    let cli = Cli::from_iter(["solana-agg", "--config", path.to_str().expect("utf-8 path")]);
+    let error = Config::resolve(&cli).expect_err("a zero interval is rejected");
+    assert!(error
+        .to_string()
+        .contains("durability.wal_flush_interval_ms"));
+}
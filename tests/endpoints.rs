@@ -0,0 +1,103 @@
+use solana_agg::endpoints::{chain_urls, RpcEndpoints};
+use solana_agg::error::AggError;
+use std::time::{Duration, Instant};
+
+fn endpoints(urls: &[&str]) -> RpcEndpoints {
+    RpcEndpoints::new(urls.iter().map(|url| url.to_string()).collect()).expect("urls")
+}
+
+#[test]
+fn chain_url_holds_several_urls_separated_by_commas() {
+    assert_eq!(
+        chain_urls("http://a:8899, http://b:8899,,"),
+        vec!["http://a:8899", "http://b:8899"]
+    );
+    assert_eq!(chain_urls("http://a:8899"), vec!["http://a:8899"]);
+    assert!(RpcEndpoints::new(chain_urls(" , ")).is_err());
+}
+
+#[test]
+fn requests_rotate_between_the_endpoints() {
+    let endpoints = endpoints(&["http://a", "http://b", "http://c"]);
+    let now = Instant::now();
+    let picked: Vec<usize> = (0..4).map(|_| endpoints.pick(now)).collect();
+    assert_eq!(picked, vec![0, 1, 2, 0]);
+}
+
+#[test]
+fn failing_endpoints_are_skipped_until_their_cooldown_ends() {
+    let endpoints = endpoints(&["http://a", "http://b"]);
+    let now = Instant::now();
+    let timeout = AggError::PubsubError("timed out".to_string());
+    endpoints.record(0, Duration::from_millis(10), Some(&timeout), now);
+    assert_eq!(endpoints.pick(now), 1);
+    assert_eq!(endpoints.pick(now), 1);
+
+    // Failing again in a row doubles the cooldown
+    endpoints.record(0, Duration::from_millis(10), Some(&timeout), now);
+    let health = endpoints.health("http://a").expect("endpoint");
+    assert_eq!(health.consecutive_failures, 2);
+    assert_eq!(
+        health.cooling_down_until,
+        Some(now + Duration::from_secs(2))
+    );
+    assert_eq!(endpoints.pick(now + Duration::from_secs(1)), 1);
+
+    // Once every endpoint cools down, the one recovering first is used
+    endpoints.record(1, Duration::from_millis(10), Some(&timeout), now);
+    assert_eq!(endpoints.pick(now), 1);
+
+    // A response ends the cooldown
+    endpoints.record(0, Duration::from_millis(10), None, now);
+    assert_eq!(endpoints.pick(now), 0);
+}
+
+#[test]
+fn permanent_errors_are_answers_and_latency_is_averaged() {
+    let endpoints = endpoints(&["http://a", "http://b"]);
+    let now = Instant::now();
+    endpoints.record(0, Duration::from_millis(80), None, now);
+    endpoints.record(
+        0,
+        Duration::from_millis(160),
+        Some(&AggError::TxNotFound),
+        now,
+    );
+    let health = endpoints.health("http://a").expect("endpoint");
+    assert_eq!(health.consecutive_failures, 0);
+    assert_eq!(health.cooling_down_until, None);
+    assert_eq!(health.latency, Some(Duration::from_millis(90)));
+}
+
+#[test]
+fn endpoints_are_labeled_with_their_host_only() {
+    let endpoints = endpoints(&[
+        "https://example.solana-mainnet.quiknode.pro/secret-token/",
+        "https://example.solana-mainnet.quiknode.pro/other-token/",
+        "http://a:8899/?api-key=secret",
+    ]);
+    assert_eq!(
+        endpoints.labels(),
+        vec![
+            "https://example.solana-mainnet.quiknode.pro",
+            "https://example.solana-mainnet.quiknode.pro#1",
+            "http://a:8899",
+        ]
+    );
+    assert!(!format!("{:?}", endpoints).contains("token"));
+}
+
+#[test]
+fn slow_endpoints_are_left_out_of_the_rotation_but_still_probed() {
+    let endpoints = endpoints(&["http://a", "http://b", "http://c"]);
+    let now = Instant::now();
+    endpoints.record(0, Duration::from_millis(10), None, now);
+    endpoints.record(1, Duration::from_millis(500), None, now);
+    endpoints.record(2, Duration::from_millis(30), None, now);
+    let picked: Vec<usize> = (0..16).map(|_| endpoints.pick(now)).collect();
+    // The first request is a probe, the slow endpoint is skipped by the others
+    assert_eq!(picked[0], 0);
+    assert_eq!(picked.iter().filter(|index| **index == 1).count(), 0);
+    assert!(picked.contains(&0) && picked.contains(&2));
+    assert_eq!(endpoints.pick(now), 1, "the 16th request probes it");
+}
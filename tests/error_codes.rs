@@ -0,0 +1,83 @@
+use actix_web::http::StatusCode;
+use solana_agg::error::{AggError, ErrorContext, ErrorContextExt};
+use std::error::Error;
+
+#[test]
+fn errors_map_to_stable_codes_and_statuses() {
+    let cases = [
+        (AggError::TxNotFound, "tx_not_found", StatusCode::NOT_FOUND),
+        (
+            AggError::BlockNotFound,
+            "block_not_found",
+            StatusCode::NOT_FOUND,
+        ),
+        (
+            AggError::ScanLimitExceeded(10),
+            "scan_limit_exceeded",
+            StatusCode::UNPROCESSABLE_ENTITY,
+        ),
+        (
+            AggError::ResponseTooLarge(10),
+            "response_too_large",
+            StatusCode::PAYLOAD_TOO_LARGE,
+        ),
+        (AggError::ReadOnly, "read_only", StatusCode::CONFLICT),
+        (
+            AggError::BadRequest("start must be <= end".to_string()),
+            "bad_request",
+            StatusCode::BAD_REQUEST,
+        ),
+        (
+            AggError::NotFound("job".to_string()),
+            "not_found",
+            StatusCode::NOT_FOUND,
+        ),
+        (
+            AggError::QueryTimedOut,
+            "query_timed_out",
+            StatusCode::GATEWAY_TIMEOUT,
+        ),
+        (
+            AggError::UpstreamUnavailable("breaker open".to_string()),
+            "upstream_unavailable",
+            StatusCode::SERVICE_UNAVAILABLE,
+        ),
+        (
+            AggError::from(serde_json::from_str::<u64>("{").unwrap_err()),
+            "json",
+            StatusCode::INTERNAL_SERVER_ERROR,
+        ),
+    ];
+    for (error, code, status) in cases {
+        assert_eq!(error.code(), code, "{error}");
+        assert_eq!(error.status(), status, "{error}");
+    }
+}
+
+#[test]
+fn context_is_displayed_and_keeps_the_code_of_its_source() {
+    let result: Result<(), AggError> = Err(AggError::BlockNotFound);
+    let error = result
+        .with_block(42)
+        .with_endpoint("http://a:9944")
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Block Not Found (block 42) (endpoint http://a:9944)"
+    );
+    assert_eq!(error.code(), "block_not_found");
+    assert_eq!(error.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        error.contexts(),
+        vec![
+            &ErrorContext::Block(42),
+            &ErrorContext::Endpoint("http://a:9944".to_string())
+        ]
+    );
+    assert!(matches!(error.root(), AggError::BlockNotFound));
+    // The error without its outermost context is the source
+    assert_eq!(
+        error.source().map(ToString::to_string).as_deref(),
+        Some("Block Not Found (block 42)")
+    );
+}
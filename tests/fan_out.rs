@@ -0,0 +1,55 @@
+use solana_agg::fanout::{FanOut, Overflow};
+use solana_agg::util::{Block, Channel, ProtocolMessage};
+use tokio::sync::mpsc::Receiver;
+
+async fn block_nos(receiver: &mut Receiver<ProtocolMessage>, count: usize) -> Vec<u64> {
+    let mut block_nos = Vec::new();
+    for _ in 0..count {
+        match receiver.recv().await {
+            Some(ProtocolMessage::FinalizeBlock(block_no, _)) => block_nos.push(block_no),
+            other => panic!("unexpected message {:?}", other),
+        }
+    }
+    block_nos
+}
+
+#[tokio::test]
+async fn full_dropping_sink_does_not_hold_back_waiting_sink() {
+    let channel = Channel::<ProtocolMessage>::new();
+    let sender = channel.sender();
+    let mut fan_out = FanOut::initialize(channel.receiver);
+    let mut db = fan_out.add_sink("db", 2, Overflow::Wait);
+    let mut slow = fan_out.add_sink("slow", 1, Overflow::Drop);
+    tokio::spawn(async move { fan_out.run().await });
+    for block_no in 0..10 {
+        sender
+            .send(ProtocolMessage::FinalizeBlock(block_no, Block::default()))
+            .unwrap();
+    }
+    // The waiting sink gets every block in order even though the other sink is never read
+    assert_eq!(block_nos(&mut db, 10).await, (0..10).collect::<Vec<_>>());
+    // The dropping sink only kept what fit in its queue
+    assert_eq!(block_nos(&mut slow, 1).await, vec![0]);
+    assert!(slow.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn full_waiting_sink_holds_back_the_fan_out() {
+    let channel = Channel::<ProtocolMessage>::new();
+    let sender = channel.sender();
+    let mut fan_out = FanOut::initialize(channel.receiver);
+    let mut db = fan_out.add_sink("db", 1, Overflow::Wait);
+    let mut stats = fan_out.add_sink("stats", 16, Overflow::Drop);
+    tokio::spawn(async move { fan_out.run().await });
+    for block_no in 0..4 {
+        sender
+            .send(ProtocolMessage::FinalizeBlock(block_no, Block::default()))
+            .unwrap();
+    }
+    // Only the blocks up to the one waiting for room in the db queue reach the other sinks
+    assert_eq!(block_nos(&mut stats, 2).await, vec![0, 1]);
+    tokio::task::yield_now().await;
+    assert!(stats.try_recv().is_err());
+    assert_eq!(block_nos(&mut db, 4).await, vec![0, 1, 2, 3]);
+    assert_eq!(block_nos(&mut stats, 2).await, vec![2, 3]);
+}
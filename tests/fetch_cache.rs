@@ -0,0 +1,153 @@
+use solana_agg::fetch_cache::{self, FetchCache};
+use solana_client::rpc_config::RpcBlockConfig;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_transaction_status::{TransactionDetails, UiConfirmedBlock, UiTransactionEncoding};
+use std::sync::Arc;
+
+/// How the subscriber and the backfills request finalized blocks
+fn config() -> RpcBlockConfig {
+    RpcBlockConfig {
+        encoding: Some(UiTransactionEncoding::Base64),
+        transaction_details: None,
+        rewards: None,
+        commitment: Some(CommitmentConfig::finalized()),
+        max_supported_transaction_version: Some(0),
+    }
+}
+
+fn block(slot: u64, padding: usize) -> UiConfirmedBlock {
+    UiConfirmedBlock {
+        previous_blockhash: format!("h{}", slot - 1),
+        blockhash: "x".repeat(padding),
+        parent_slot: slot - 1,
+        transactions: Some(vec![]),
+        signatures: None,
+        rewards: None,
+        num_reward_partitions: None,
+        block_time: Some(1_700_000_000),
+        block_height: Some(slot / 10),
+    }
+}
+
+#[test]
+fn cached_blocks_are_read_back_and_survive_a_restart() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let cache = FetchCache::open(dir.path(), 1 << 20).expect("open");
+    assert!(cache.get(100, &config()).is_none());
+    cache
+        .insert(100, &config(), &block(100, 10))
+        .expect("insert");
+    let cached = cache.get(100, &config()).expect("cached block");
+    assert_eq!(cached.blockhash, "x".repeat(10));
+    assert_eq!(cached.parent_slot, 99);
+    assert_eq!(cached.block_height, Some(10));
+    drop(cache);
+
+    // A write that did not complete is cleaned up
+    std::fs::write(dir.path().join("101.7.partial"), b"{").expect("write");
+    let cache = FetchCache::open(dir.path(), 1 << 20).expect("reopen");
+    assert!(cache.contains(100, &config()));
+    assert!(!cache.contains(101, &config()));
+    assert!(!dir.path().join("101.7.partial").exists());
+    assert_eq!(
+        cache.get(100, &config()).map(|block| block.parent_slot),
+        Some(99)
+    );
+}
+
+#[test]
+fn the_least_recently_used_blocks_are_evicted_past_the_bound() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let size = serde_json::to_vec(&block(101, 1_000))
+        .expect("serialize")
+        .len() as u64;
+    let cache = FetchCache::open(dir.path(), 3 * size).expect("open");
+    for slot in [100, 101, 102] {
+        cache
+            .insert(slot, &config(), &block(slot, 1_000))
+            .expect("insert");
+    }
+    // Reading 100 makes 101 the least recently used
+    assert!(cache.get(100, &config()).is_some());
+    cache
+        .insert(103, &config(), &block(103, 1_000))
+        .expect("insert");
+    assert!(cache.contains(100, &config()));
+    assert!(!cache.contains(101, &config()));
+    assert!(!dir.path().join("101.base64-full-rewards-v0.block").exists());
+    assert!(cache.contains(102, &config()) && cache.contains(103, &config()));
+    assert!(cache.size() <= 3 * size);
+    // A block larger than the whole cache is not kept
+    cache
+        .insert(104, &config(), &block(104, 10_000))
+        .expect("insert");
+    assert!(!cache.contains(104, &config()));
+    assert!(cache.contains(103, &config()));
+}
+
+#[test]
+fn unreadable_blocks_are_dropped() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let cache = FetchCache::open(dir.path(), 1 << 20).expect("open");
+    cache
+        .insert(100, &config(), &block(100, 10))
+        .expect("insert");
+    std::fs::write(
+        dir.path().join("100.base64-full-rewards-v0.block"),
+        b"not json",
+    )
+    .expect("write");
+    assert!(cache.get(100, &config()).is_none());
+    assert!(!cache.contains(100, &config()));
+    assert_eq!(cache.size(), 0);
+}
+
+#[test]
+fn blocks_requested_differently_are_cached_apart() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let cache = FetchCache::open(dir.path(), 1 << 20).expect("open");
+    let signatures = RpcBlockConfig {
+        transaction_details: Some(TransactionDetails::Signatures),
+        ..config()
+    };
+    assert_eq!(fetch_cache::variant(&config()), "base64-full-rewards-v0");
+    assert_eq!(
+        fetch_cache::variant(&signatures),
+        "base64-signatures-rewards-v0"
+    );
+    cache
+        .insert(100, &config(), &block(100, 10))
+        .expect("insert");
+    assert!(cache.get(100, &signatures).is_none());
+    assert!(!cache.contains(100, &signatures));
+    cache
+        .insert(100, &signatures, &block(100, 20))
+        .expect("insert");
+    assert_eq!(
+        cache.get(100, &config()).map(|block| block.blockhash),
+        Some("x".repeat(10))
+    );
+    assert_eq!(
+        cache.get(100, &signatures).map(|block| block.blockhash),
+        Some("x".repeat(20))
+    );
+}
+
+#[tokio::test]
+async fn blocks_are_read_and_written_on_the_blocking_pool() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let cache = Arc::new(FetchCache::open(dir.path(), 1 << 20).expect("open"));
+    assert!(cache.get_blocking(100, config()).await.is_none());
+    let block = cache
+        .insert_blocking(100, config(), block(100, 10))
+        .await
+        .expect("handed back");
+    assert_eq!(block.parent_slot, 99);
+    assert_eq!(
+        cache
+            .get_blocking(100, config())
+            .await
+            .map(|block| block.blockhash),
+        Some("x".repeat(10))
+    );
+}
@@ -0,0 +1,90 @@
+use futures_util::future::BoxFuture;
+use futures_util::FutureExt;
+use solana_agg::fetch_pool::FetchPool;
+use solana_agg::gaps::FetchOutcome;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::unbounded_channel;
+
+#[derive(Default)]
+struct Probe {
+    running: AtomicUsize,
+    most_running: AtomicUsize,
+    started: Mutex<Vec<u64>>,
+}
+
+impl Probe {
+    fn fetch(self: &Arc<Self>, slot: u64) -> BoxFuture<'static, FetchOutcome> {
+        let probe = self.clone();
+        async move {
+            probe.started.lock().unwrap().push(slot);
+            let running = probe.running.fetch_add(1, Ordering::SeqCst) + 1;
+            probe.most_running.fetch_max(running, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            probe.running.fetch_sub(1, Ordering::SeqCst);
+            if slot.is_multiple_of(2) {
+                FetchOutcome::Fetched
+            } else {
+                FetchOutcome::Skipped
+            }
+        }
+        .boxed()
+    }
+}
+
+#[tokio::test]
+async fn fetches_are_bounded_by_the_workers_and_start_in_order() {
+    let (outcome_sender, mut outcome_receiver) = unbounded_channel();
+    let pool = FetchPool::start(3, outcome_sender, None);
+    let probe = Arc::new(Probe::default());
+    for slot in 100..112 {
+        pool.submit(slot, probe.fetch(slot));
+    }
+    assert_eq!(pool.pending(), 12);
+    assert_eq!(pool.oldest_pending(), Some(100));
+
+    let mut outcomes = Vec::new();
+    while outcomes.len() < 12 {
+        let (slot, outcome) = tokio::time::timeout(Duration::from_secs(5), outcome_receiver.recv())
+            .await
+            .expect("fetches complete")
+            .expect("pool alive");
+        assert_eq!(matches!(outcome, FetchOutcome::Fetched), slot % 2 == 0);
+        outcomes.push(slot);
+    }
+    outcomes.sort_unstable();
+    assert_eq!(outcomes, (100..112).collect::<Vec<_>>());
+    assert_eq!(probe.most_running.load(Ordering::SeqCst), 3);
+    assert_eq!(
+        *probe.started.lock().unwrap(),
+        (100..112).collect::<Vec<_>>()
+    );
+    assert_eq!(pool.pending(), 0);
+    assert_eq!(pool.oldest_pending(), None);
+}
+
+#[tokio::test]
+async fn a_panicking_fetch_fails_its_slot_without_stopping_its_worker() {
+    let (outcome_sender, mut outcome_receiver) = unbounded_channel();
+    let pool = FetchPool::start(1, outcome_sender, None);
+    pool.submit(1, async { panic!("fetch failed") }.boxed());
+    pool.submit(2, async { FetchOutcome::Fetched }.boxed());
+    let mut outcomes = Vec::new();
+    for _ in 0..2 {
+        outcomes.push(
+            tokio::time::timeout(Duration::from_secs(5), outcome_receiver.recv())
+                .await
+                .expect("both fetches complete")
+                .expect("pool alive"),
+        );
+    }
+    assert_eq!(outcomes[0].0, 1);
+    match &outcomes[0].1 {
+        FetchOutcome::Failed(failure) => assert_eq!(failure.error.code(), "task_failed"),
+        other => panic!("unexpected outcome {other:?}"),
+    }
+    assert_eq!(outcomes[1].0, 2);
+    assert!(matches!(outcomes[1].1, FetchOutcome::Fetched));
+    assert_eq!(pool.pending(), 0);
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use common::{block, key};
+use solana_agg::util::{Block, FirstSeen, ProtocolMessage, Response};
+use solana_agg::Builder;
+use solana_program::pubkey::Pubkey;
+use tokio::sync::mpsc::UnboundedSender;
+
+/// A block of the slot observing the accounts in the transaction
+fn observing_block(slot: u64, tx_hash: &str, accounts: &[Pubkey]) -> Block {
+    let mut block = block(slot);
+    for account in accounts {
+        block.observe_account(account.to_string(), tx_hash);
+    }
+    block
+}
+
+async fn first_seen(
+    sender: &UnboundedSender<ProtocolMessage>,
+    account: Pubkey,
+) -> Option<FirstSeen> {
+    match ProtocolMessage::ask(sender, |reply| {
+        ProtocolMessage::FetchAccountSummary(account.to_string(), reply)
+    })
+    .await
+    {
+        Ok(Response::AccountSummary(summary)) => summary.first_seen,
+        other => panic!("unexpected response {other:?}"),
+    }
+}
+
+#[test]
+fn a_block_keeps_the_first_transaction_an_account_is_observed_in() {
+    let mut block = block(10);
+    block.observe_account(key(1).to_string(), "first");
+    block.observe_account(key(1).to_string(), "second");
+    block.observe_account(key(2).to_string(), "second");
+    let first_seen = block.get_first_seen();
+    assert_eq!(first_seen.len(), 2);
+    assert_eq!(first_seen[&key(1).to_string()], "first");
+    assert_eq!(first_seen[&key(2).to_string()], "second");
+}
+
+#[tokio::test]
+async fn the_earliest_block_an_account_is_seen_in_is_kept_even_when_stored_late() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(dir.path().join("db").to_string_lossy().into_owned())
+        .db_receiver(receiver)
+        .build()
+        .expect("db opens");
+    tokio::spawn(async move { db.run().await });
+    // Block 1 is finalised after the blocks following it
+    let blocks = [
+        (2, observing_block(20, "tx-b", &[key(1)])),
+        (3, observing_block(30, "tx-c", &[key(1), key(2)])),
+        (1, observing_block(10, "tx-a", &[key(1)])),
+    ];
+    for (block_no, block) in blocks {
+        sender
+            .send(ProtocolMessage::FinalizeBlock(block_no, block))
+            .expect("db running");
+    }
+
+    let seen = first_seen(&sender, key(1)).await.expect("seen");
+    assert_eq!(
+        (seen.block_no, seen.slot, seen.tx_hash.as_str()),
+        (1, Some(10), "tx-a")
+    );
+    let seen = first_seen(&sender, key(2)).await.expect("seen");
+    assert_eq!(
+        (seen.block_no, seen.slot, seen.tx_hash.as_str()),
+        (3, Some(30), "tx-c")
+    );
+    assert!(first_seen(&sender, key(3)).await.is_none());
+}
@@ -0,0 +1,85 @@
+use solana_agg::config::RefetchConfig;
+use solana_agg::error::AggError;
+use solana_agg::gaps::{FetchOutcome, GapTracker, GapUpdate, MissingSlot};
+use solana_agg::retry::Failure;
+use std::time::{Duration, Instant};
+
+fn failed() -> FetchOutcome {
+    FetchOutcome::Failed(Failure {
+        error: AggError::PubsubError("node unavailable".to_string()),
+        attempts: 4,
+    })
+}
+
+fn tracker() -> GapTracker {
+    GapTracker::new(RefetchConfig {
+        initial_delay_secs: 5,
+        max_delay_secs: 30,
+        max_refetches: 3,
+    })
+}
+
+#[test]
+fn failed_slots_are_fetched_again_with_a_doubling_delay() {
+    let mut gaps = tracker();
+    let now = Instant::now();
+    let secs = Duration::from_secs;
+    assert_eq!(
+        gaps.record(7, &failed(), now),
+        Some(GapUpdate::Scheduled(secs(5)))
+    );
+    assert!(gaps.due(now).is_empty());
+    assert_eq!(gaps.due(now + secs(5)), vec![7]);
+    // A re-fetch already running is not started again
+    assert!(gaps.due(now + secs(6)).is_empty());
+
+    assert_eq!(
+        gaps.record(7, &failed(), now),
+        Some(GapUpdate::Scheduled(secs(10)))
+    );
+    assert_eq!(
+        gaps.record(7, &failed(), now),
+        Some(GapUpdate::Scheduled(secs(20)))
+    );
+    assert_eq!(
+        gaps.missing(),
+        vec![MissingSlot {
+            slot: 7,
+            failures: 3
+        }]
+    );
+    assert_eq!(gaps.record(7, &failed(), now), Some(GapUpdate::GivenUp));
+    assert!(gaps.missing().is_empty());
+}
+
+#[test]
+fn the_delay_is_capped() {
+    let mut gaps = GapTracker::new(RefetchConfig {
+        initial_delay_secs: 5,
+        max_delay_secs: 30,
+        max_refetches: 10,
+    });
+    let now = Instant::now();
+    let mut update = None;
+    for _ in 0..6 {
+        update = gaps.record(1, &failed(), now);
+    }
+    assert_eq!(update, Some(GapUpdate::Scheduled(Duration::from_secs(30))));
+}
+
+#[test]
+fn fetched_and_skipped_slots_close_their_gap() {
+    let mut gaps = tracker();
+    let now = Instant::now();
+    gaps.record(1, &failed(), now);
+    gaps.record(2, &failed(), now);
+    gaps.record(3, &failed(), now);
+    assert_eq!(gaps.record(1, &FetchOutcome::Fetched, now), None);
+    // The node reporting the slot as skipped means there never was a block to miss
+    assert_eq!(gaps.record(2, &FetchOutcome::Skipped, now), None);
+    let missing: Vec<u64> = gaps.missing().iter().map(|gap| gap.slot).collect();
+    assert_eq!(missing, vec![3]);
+    // Slots fetched at the first attempt never were gaps
+    assert_eq!(gaps.record(4, &FetchOutcome::Fetched, now), None);
+    assert_eq!(gaps.missing().len(), 1);
+}
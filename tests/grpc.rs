@@ -0,0 +1,220 @@
+mod common;
+
+use common::block;
+use solana_agg::config::QueryConfig;
+use solana_agg::error::AggError;
+use solana_agg::grpc::proto::aggregator_client::AggregatorClient;
+use solana_agg::grpc::proto::{GetTransactionRequest, StreamBlocksRequest, SubscribeBlocksRequest};
+use solana_agg::grpc::{block_filter, status, GrpcServer};
+use solana_agg::shutdown::{ShutdownCoordinator, ShutdownStage};
+use solana_agg::util::{Block, BlockHeader, Channel, ProtocolMessage, TxRecord};
+use solana_agg::Builder;
+use solana_program::hash::hash;
+use solana_sdk::signature::Signature;
+use std::time::Duration;
+use tonic::transport::Channel as GrpcChannel;
+use tonic::Code;
+
+const GRPC_PORT: u16 = 19955;
+const STREAM_GRPC_PORT: u16 = 19956;
+
+const SYSTEM_PROGRAM: &str = "11111111111111111111111111111111";
+
+async fn connect(port: u16) -> AggregatorClient<GrpcChannel> {
+    for _ in 0..100 {
+        match AggregatorClient::connect(format!("http://127.0.0.1:{port}")).await {
+            Ok(client) => return client,
+            Err(_) => tokio::time::sleep(Duration::from_millis(50)).await,
+        }
+    }
+    panic!("gRPC server not listening on {port}");
+}
+
+#[test]
+fn db_errors_map_to_the_status_of_their_class() {
+    assert_eq!(status(&AggError::BlockNotFound).code(), Code::NotFound);
+    assert_eq!(status(&AggError::TxNotFound).code(), Code::NotFound);
+    assert_eq!(
+        status(&AggError::NoBlockFinalised).code(),
+        Code::Unavailable
+    );
+    assert_eq!(
+        status(&AggError::ConfigError("bad".to_string())).code(),
+        Code::InvalidArgument
+    );
+    assert_eq!(status(&AggError::ReadOnly).code(), Code::FailedPrecondition);
+    assert_eq!(
+        status(&AggError::ServerError(std::io::Error::other("disk"))).code(),
+        Code::Internal
+    );
+}
+
+#[test]
+fn subscription_filters_are_validated() {
+    let request = SubscribeBlocksRequest {
+        block_no: Some(10),
+        programs: vec![SYSTEM_PROGRAM.to_string()],
+        accounts: vec![],
+        min_amount: Some(1.5),
+        failed_only: true,
+    };
+    let filter = block_filter(&request).expect("valid filter");
+    assert_eq!(filter.programs, vec![SYSTEM_PROGRAM.to_string()]);
+    assert!(filter.accounts.is_empty());
+    assert_eq!(filter.min_amount, Some(1.5));
+    assert!(filter.failed_only);
+
+    let malformed = SubscribeBlocksRequest {
+        accounts: vec!["not-a-key".to_string()],
+        ..SubscribeBlocksRequest::default()
+    };
+    assert_eq!(
+        status(&block_filter(&malformed).unwrap_err()).code(),
+        Code::InvalidArgument
+    );
+    let too_many = SubscribeBlocksRequest {
+        accounts: vec![SYSTEM_PROGRAM.to_string(); 101],
+        ..SubscribeBlocksRequest::default()
+    };
+    assert_eq!(
+        status(&block_filter(&too_many).unwrap_err()).code(),
+        Code::InvalidArgument
+    );
+}
+
+#[tokio::test]
+async fn transactions_are_served_by_message_hash_and_signature() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let channel = Channel::<ProtocolMessage>::new();
+    let db_sender = channel.sender();
+    let mut db = Builder::default()
+        .db_path(dir.path().join("db").to_string_lossy().into_owned())
+        .db_receiver(channel.receiver)
+        .build()
+        .expect("db opens");
+    tokio::spawn(async move { db.run().await });
+
+    let tx_hash = hash(b"transfer");
+    let signature = Signature::from([7; 64]).to_string();
+    let mut block = Block::default();
+    block.set_header(BlockHeader {
+        slot: 5,
+        blockhash: "hash-5".to_string(),
+        block_time: Some(1_700_000_000),
+        transaction_count: Some(1),
+        ..Default::default()
+    });
+    block.push_transaction(
+        tx_hash,
+        TxRecord::new(vec![], None)
+            .expect("record")
+            .with_signatures(vec![signature.clone()]),
+    );
+    db_sender
+        .send(ProtocolMessage::FinalizeBlock(1, block))
+        .expect("db running");
+
+    let mut coordinator = ShutdownCoordinator::new(Duration::from_secs(5));
+    let shutdown = coordinator.signal(ShutdownStage::Intake);
+    tokio::spawn(GrpcServer::run(
+        db_sender,
+        QueryConfig::default(),
+        GRPC_PORT,
+        shutdown,
+    ));
+    let mut client = connect(GRPC_PORT).await;
+
+    for tx_id in [tx_hash.to_string(), signature] {
+        let tx = client
+            .get_transaction(GetTransactionRequest {
+                tx_id: tx_id.clone(),
+            })
+            .await
+            .expect("transaction found")
+            .into_inner();
+        assert_eq!(tx.tx_id, tx_id);
+    }
+    let missing = client
+        .get_transaction(GetTransactionRequest {
+            tx_id: hash(b"unknown").to_string(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(missing.code(), Code::NotFound);
+    coordinator.shutdown().await;
+}
+
+#[tokio::test]
+async fn stream_blocks_backfills_a_slot_range_in_slot_order_across_batches() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let channel = Channel::<ProtocolMessage>::new();
+    let db_sender = channel.sender();
+    let mut db = Builder::default()
+        .db_path(dir.path().join("db").to_string_lossy().into_owned())
+        .db_receiver(channel.receiver)
+        .build()
+        .expect("db opens");
+    tokio::spawn(async move { db.run().await });
+    // Every odd slot is skipped
+    for block_no in 1..=250 {
+        db_sender
+            .send(ProtocolMessage::FinalizeBlock(
+                block_no,
+                block(block_no * 2),
+            ))
+            .expect("db running");
+    }
+
+    let mut coordinator = ShutdownCoordinator::new(Duration::from_secs(5));
+    let shutdown = coordinator.signal(ShutdownStage::Intake);
+    tokio::spawn(GrpcServer::run(
+        db_sender,
+        QueryConfig::default(),
+        STREAM_GRPC_PORT,
+        shutdown,
+    ));
+    let mut client = connect(STREAM_GRPC_PORT).await;
+    let stream_blocks = |start_slot, end_slot| {
+        let mut client = client.clone();
+        async move {
+            let mut stream = client
+                .stream_blocks(StreamBlocksRequest {
+                    start_slot,
+                    end_slot,
+                })
+                .await?
+                .into_inner();
+            let mut blocks = vec![];
+            while let Some(block) = stream.message().await? {
+                blocks.push((block.block_no, block.slot));
+            }
+            Ok::<_, tonic::Status>(blocks)
+        }
+    };
+
+    // More blocks than a batch, starting and ending on skipped slots
+    let blocks = stream_blocks(3, 401).await.expect("streamed");
+    let expected: Vec<(u64, u64)> = (2..=200).map(|block_no| (block_no, block_no * 2)).collect();
+    assert_eq!(blocks, expected);
+    let blocks = stream_blocks(500, 500).await.expect("streamed");
+    assert_eq!(blocks, vec![(250, 500)]);
+    // A range past the stored blocks, longer than a batch scans, ends without a block
+    let blocks = stream_blocks(1_000, 30_000).await.expect("streamed");
+    assert!(blocks.is_empty());
+    let reversed = stream_blocks(10, 9).await.unwrap_err();
+    assert_eq!(reversed.code(), Code::InvalidArgument);
+
+    // The client only reads what it needs, the rest of the range is not streamed
+    let mut stream = client
+        .stream_blocks(StreamBlocksRequest {
+            start_slot: 0,
+            end_slot: u64::MAX,
+        })
+        .await
+        .expect("streaming")
+        .into_inner();
+    let first = stream.message().await.expect("streamed").expect("a block");
+    assert_eq!((first.block_no, first.slot), (1, 2));
+    drop(stream);
+    coordinator.shutdown().await;
+}
@@ -0,0 +1,114 @@
+use solana_agg::config::ParseMode;
+use solana_agg::inclusion::verify;
+use solana_agg::parser::Parser;
+use solana_agg::util::{Block, BlockHeader, Channel, ProtocolMessage, UnprocessedBlock};
+use solana_transaction_status::UiConfirmedBlock;
+use std::path::Path;
+
+fn fixture() -> UiConfirmedBlock {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/system_transfers.json");
+    serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+}
+
+/// Parses the fixture in a single chunk, the way the block fetcher and handler store it
+async fn parse(block: &UiConfirmedBlock) -> Block {
+    let header = BlockHeader {
+        slot: block.parent_slot + 1,
+        blockhash: block.blockhash.clone(),
+        block_time: block.block_time,
+        previous_blockhash: Some(block.previous_blockhash.clone()),
+        parent_slot: Some(block.parent_slot),
+        transaction_count: block.transactions.as_ref().map(|txs| txs.len() as u64),
+    };
+    let mut channel = Channel::<ProtocolMessage>::new();
+    Parser::invoke(
+        ProtocolMessage::new_chuck(
+            block.block_height.unwrap(),
+            header,
+            0,
+            1,
+            block.transactions.clone().unwrap_or_default(),
+            channel.sender(),
+        ),
+        ParseMode::Permissive,
+    )
+    .await
+    .unwrap();
+    let mut unprocessed = UnprocessedBlock::new(1);
+    match channel.receiver.recv().await {
+        Some(ProtocolMessage::ParsedBlock(_, _, chunk_no, partial)) => {
+            unprocessed.insert_chunk(chunk_no, partial).unwrap();
+        }
+        other => panic!("unexpected message {other:?}"),
+    }
+    unprocessed.complete_the_block().unwrap()
+}
+
+#[tokio::test]
+async fn a_stored_transaction_verifies_against_the_block_of_the_node() {
+    let fetched = fixture();
+    let block = parse(&fetched).await;
+    let block_no = fetched.block_height.unwrap();
+    let tx_ids: Vec<String> = block.get_tx_hash();
+    assert!(tx_ids.len() > 1);
+    for (position, tx_id) in tx_ids.iter().enumerate() {
+        let inclusion = block.tx_inclusion(block_no, tx_id).unwrap();
+        assert_eq!(inclusion.position, Some(position as u32));
+        assert_eq!(
+            inclusion.blockhash.as_deref(),
+            Some(fetched.blockhash.as_str())
+        );
+        assert_eq!(inclusion.slot, Some(fetched.parent_slot + 1));
+        assert!(!inclusion.signatures.is_empty());
+
+        let (_, record) = block
+            .transactions()
+            .find(|(id, _)| *id == tx_id.as_str())
+            .unwrap();
+        let verification = verify(&inclusion, record, &fetched);
+        assert_eq!(verification.mismatches, Vec::<String>::new());
+        assert!(verification.verified);
+        assert_eq!(verification.index, Some(position));
+    }
+    assert!(block.tx_inclusion(block_no, "missing").is_none());
+}
+
+#[tokio::test]
+async fn a_block_that_differs_from_the_node_lists_every_mismatch() {
+    let fetched = fixture();
+    let block = parse(&fetched).await;
+    let block_no = fetched.block_height.unwrap();
+    let tx_id = block.get_tx_hash()[0].clone();
+    let inclusion = block.tx_inclusion(block_no, &tx_id).unwrap();
+    let (_, record) = block
+        .transactions()
+        .find(|(id, _)| *id == tx_id.as_str())
+        .unwrap();
+
+    // The node serves another block for the slot, without the transaction
+    let mut orphaned = fetched.clone();
+    orphaned.blockhash = "11111111111111111111111111111111".to_string();
+    orphaned.transactions.as_mut().unwrap().remove(0);
+    let verification = verify(&inclusion, record, &orphaned);
+    assert!(!verification.verified);
+    assert_eq!(verification.index, None);
+    let fields: Vec<&str> = verification
+        .mismatches
+        .iter()
+        .map(|mismatch| mismatch.split(':').next().unwrap())
+        .collect();
+    assert_eq!(
+        fields,
+        vec!["blockhash", "transaction_count", "transaction"]
+    );
+
+    // The node reports another fee for the transaction
+    let mut refetched = fetched.clone();
+    let tx = &mut refetched.transactions.as_mut().unwrap()[0];
+    tx.meta.as_mut().unwrap().fee += 1;
+    let verification = verify(&inclusion, record, &refetched);
+    assert!(!verification.verified);
+    assert_eq!(verification.index, Some(0));
+    assert_eq!(verification.mismatches.len(), 1);
+    assert!(verification.mismatches[0].starts_with("fee: "));
+}
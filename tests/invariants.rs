@@ -0,0 +1,126 @@
+use solana_agg::config::Commitment;
+use solana_agg::handler::Handler;
+use solana_agg::invariants::{check_assembly, FinalizedSlots};
+use solana_agg::util::{
+    Block, BlockHeader, Channel, FailureStage, ProtocolMessage, TxRecord, UnprocessedBlock,
+};
+use solana_program::hash::hash;
+
+/// Builds the partial block of a chunk of a block of `transaction_count` transactions
+fn chunk(slot: u64, transaction_count: u64, txs: std::ops::Range<u64>) -> Block {
+    let mut block = Block::default();
+    block.set_header(BlockHeader {
+        slot,
+        blockhash: format!("hash-{slot}"),
+        transaction_count: Some(transaction_count),
+        ..BlockHeader::default()
+    });
+    for tx in txs {
+        block.push_transaction(
+            hash(&tx.to_le_bytes()),
+            TxRecord::new(vec![], None).unwrap(),
+        );
+    }
+    block
+}
+
+fn assemble(chunks: Vec<Block>) -> (UnprocessedBlock, Block) {
+    let mut unprocessed = UnprocessedBlock::new(chunks.len() as u64);
+    for (chunk_no, chunk) in chunks.into_iter().enumerate() {
+        unprocessed.insert_chunk(chunk_no as u64, chunk).unwrap();
+    }
+    let block = unprocessed.complete_the_block().unwrap();
+    (unprocessed, block)
+}
+
+#[test]
+fn a_sound_block_violates_nothing() {
+    let (unprocessed, block) = assemble(vec![chunk(7, 5, 0..3), chunk(7, 5, 3..5)]);
+    assert_eq!(check_assembly(&unprocessed, &block), Vec::<String>::new());
+}
+
+#[test]
+fn truncated_mixed_and_overlapping_chunks_are_reported() {
+    // A chunk lost transactions
+    let (unprocessed, block) = assemble(vec![chunk(7, 5, 0..3), chunk(7, 5, 3..4)]);
+    assert_eq!(
+        check_assembly(&unprocessed, &block),
+        vec!["the chunks hold 4 transactions, the node served 5"]
+    );
+
+    // Chunks of the block of another slot
+    let (unprocessed, block) = assemble(vec![chunk(7, 4, 0..2), chunk(8, 4, 2..4)]);
+    assert_eq!(
+        check_assembly(&unprocessed, &block),
+        vec!["the chunks are of the slots {Some(7), Some(8)}"]
+    );
+
+    // The same transaction in two chunks is only assembled once
+    let (unprocessed, block) = assemble(vec![chunk(7, 4, 0..2), chunk(7, 4, 1..3)]);
+    assert_eq!(
+        check_assembly(&unprocessed, &block),
+        vec!["3 transactions were assembled out of the 4 of the chunks"]
+    );
+}
+
+#[test]
+fn slots_must_increase_with_the_block_numbers() {
+    let mut slots = FinalizedSlots::default();
+    slots.record(10, 100);
+    slots.record(12, 105);
+    assert_eq!(slots.check(11, 103), None);
+    assert_eq!(slots.check(10, 100), None);
+    assert_eq!(
+        slots.check(11, 99).as_deref(),
+        Some("slot 99 of block 11 is not after slot 100 of the finalized block 10")
+    );
+    assert_eq!(
+        slots.check(11, 105).as_deref(),
+        Some("slot 105 of block 11 is not before slot 105 of the finalized block 12")
+    );
+    assert_eq!(
+        slots.check(12, 106).as_deref(),
+        Some("block 12 was finalized at slot 105, not 106")
+    );
+}
+
+#[tokio::test]
+async fn blocks_violating_an_invariant_are_dead_lettered_instead_of_finalized() {
+    let handler_channel = Channel::<ProtocolMessage>::new();
+    let mut db_channel = Channel::<ProtocolMessage>::new();
+    let mut handler = Handler::initialize(handler_channel.receiver, db_channel.sender());
+    handler.set_commitment(Commitment::Finalized);
+
+    handler
+        .handle_unprocessed_block(10, 1, 0, chunk(100, 2, 0..2))
+        .unwrap();
+    match db_channel.receiver.try_recv() {
+        Ok(ProtocolMessage::FinalizeBlock(10, _)) => {}
+        other => panic!("unexpected message {other:?}"),
+    }
+
+    // Truncated, and at a slot before the slot of the block before it
+    handler
+        .handle_unprocessed_block(11, 1, 0, chunk(99, 2, 2..3))
+        .unwrap();
+    match db_channel.receiver.try_recv() {
+        Ok(ProtocolMessage::DeadLetter(letter)) => {
+            assert_eq!(letter.stage, FailureStage::Validate);
+            assert_eq!(letter.code, "invariant_violation");
+            assert_eq!(letter.slot, Some(99));
+            assert_eq!(letter.block_no, Some(11));
+            assert_eq!(letter.details.len(), 2);
+        }
+        other => panic!("unexpected message {other:?}"),
+    }
+
+    // Confirmed blocks may be replaced by another fork, their slots are not checked
+    handler.set_commitment(Commitment::Confirmed);
+    handler
+        .handle_unprocessed_block(11, 1, 0, chunk(99, 1, 2..3))
+        .unwrap();
+    match db_channel.receiver.try_recv() {
+        Ok(ProtocolMessage::FinalizeBlock(11, _)) => {}
+        other => panic!("unexpected message {other:?}"),
+    }
+}
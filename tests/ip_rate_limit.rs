@@ -0,0 +1,138 @@
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::{test, web, App, HttpResponse};
+use solana_agg::config::{RateLimitConfig, TenantConfig};
+use solana_agg::error::ErrorBody;
+use solana_agg::rate_limit::{IpLimiter, IpRateLimit, REMAINING_HEADER};
+use solana_agg::server::AdminKey;
+use solana_agg::tenant::{TenantAuth, TenantRegistry, API_KEY_HEADER};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+fn limiter(enabled: bool) -> Arc<IpLimiter> {
+    Arc::new(IpLimiter::new(&RateLimitConfig {
+        enabled,
+        requests_per_minute: 2,
+        exempt_ips: vec!["10.0.0.9".parse().unwrap()],
+    }))
+}
+
+async fn ok() -> HttpResponse {
+    HttpResponse::Ok().json(true)
+}
+
+fn from(ip: &str, uri: &str) -> test::TestRequest {
+    test::TestRequest::get()
+        .uri(uri)
+        .peer_addr(SocketAddr::new(ip.parse().unwrap(), 40_000))
+}
+
+#[actix_web::test]
+async fn addresses_are_limited_whatever_the_api_key() {
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(AdminKey::new(Some("admin-key".to_string()))))
+            .wrap(IpRateLimit(limiter(true)))
+            .default_service(web::to(ok)),
+    )
+    .await;
+    for key in ["a", "b"] {
+        let request = from("10.0.0.1", "/status").insert_header((API_KEY_HEADER, key));
+        let response = test::call_service(&app, request.to_request()).await;
+        assert_eq!(response.status(), 200);
+    }
+    let request = from("10.0.0.1", "/status").insert_header((API_KEY_HEADER, "c"));
+    let response = test::call_service(&app, request.to_request()).await;
+    assert_eq!(response.status(), 429);
+    assert!(response.headers().contains_key(RETRY_AFTER));
+    assert_eq!(
+        response
+            .headers()
+            .get(REMAINING_HEADER)
+            .map(|value| value.to_str().unwrap()),
+        Some("0")
+    );
+    let body: ErrorBody = test::read_body_json(response).await;
+    assert_eq!(body.error.code, "rate_limited");
+    assert!(body.error.message.starts_with("Rate Limited: "), "{body:?}");
+
+    // Other addresses, exempt addresses and admin routes with the admin key are not held back
+    let response = test::call_service(&app, from("10.0.0.2", "/status").to_request()).await;
+    assert_eq!(response.status(), 200);
+    for _ in 0..3 {
+        let response = test::call_service(&app, from("10.0.0.9", "/status").to_request()).await;
+        assert_eq!(response.status(), 200);
+    }
+    let admin = from("10.0.0.1", "/admin/jobs").insert_header((API_KEY_HEADER, "admin-key"));
+    let response = test::call_service(&app, admin.to_request()).await;
+    assert_eq!(response.status(), 200);
+    let guess = from("10.0.0.1", "/admin/jobs").insert_header((API_KEY_HEADER, "guess"));
+    let response = test::call_service(&app, guess.to_request()).await;
+    assert_eq!(response.status(), 429);
+}
+
+#[actix_web::test]
+async fn admin_key_guesses_are_capped_even_without_a_limit() {
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(AdminKey::new(Some("admin-key".to_string()))))
+            .wrap(IpRateLimit(limiter(false)))
+            .default_service(web::to(ok)),
+    )
+    .await;
+    for attempt in 0..11 {
+        let guess = from("10.0.0.1", "/admin/compact")
+            .insert_header((API_KEY_HEADER, format!("guess-{attempt}")));
+        let response = test::call_service(&app, guess.to_request()).await;
+        let expected = if attempt < 10 { 200 } else { 429 };
+        assert_eq!(response.status(), expected, "attempt {attempt}");
+    }
+    // The key itself still gets through, and other addresses are not held back
+    let admin = from("10.0.0.1", "/admin/compact").insert_header((API_KEY_HEADER, "admin-key"));
+    let response = test::call_service(&app, admin.to_request()).await;
+    assert_eq!(response.status(), 200);
+    let guess = from("10.0.0.2", "/admin/compact").insert_header((API_KEY_HEADER, "guess"));
+    let response = test::call_service(&app, guess.to_request()).await;
+    assert_eq!(response.status(), 200);
+}
+
+#[actix_web::test]
+async fn a_disabled_limiter_admits_everything() {
+    let ip: IpAddr = "10.0.0.1".parse().unwrap();
+    let disabled = limiter(false);
+    for _ in 0..3 {
+        assert!(disabled.charge(Some(ip)).is_none());
+    }
+    let enabled = limiter(true);
+    assert!(enabled.charge(Some(ip)).unwrap().is_admitted());
+    assert!(enabled.charge(Some(ip)).unwrap().is_admitted());
+    assert!(!enabled.charge(Some(ip)).unwrap().is_admitted());
+}
+
+#[actix_web::test]
+async fn requests_without_a_tenant_key_are_unauthorized() {
+    let registry = Arc::new(TenantRegistry::new(&[TenantConfig {
+        name: "analytics".to_string(),
+        api_keys: vec!["analytics-key".to_string()],
+        requests_per_minute: Some(1),
+    }]));
+    let app = test::init_service(
+        App::new()
+            .wrap(TenantAuth(registry))
+            .default_service(web::to(ok)),
+    )
+    .await;
+    let response = test::call_service(&app, from("10.0.0.1", "/status").to_request()).await;
+    assert_eq!(response.status(), 401);
+    let body: ErrorBody = test::read_body_json(response).await;
+    assert_eq!(body.error.code, "unauthorized");
+    assert_eq!(
+        body.error.message,
+        "Unauthorized: missing or unknown api key"
+    );
+
+    let request = || from("10.0.0.1", "/status").insert_header((API_KEY_HEADER, "analytics-key"));
+    let response = test::call_service(&app, request().to_request()).await;
+    assert_eq!(response.status(), 200);
+    let response = test::call_service(&app, request().to_request()).await;
+    assert_eq!(response.status(), 429);
+}
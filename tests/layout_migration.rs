@@ -0,0 +1,123 @@
+mod common;
+
+use common::block;
+use solana_agg::util::{Job, JobState, JobTask, LayoutMigration, ProtocolMessage, Response};
+use solana_agg::Builder;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+
+#[test]
+fn layout_migration_progress_counts_migrated_and_skipped_entries() {
+    let mut migration = LayoutMigration {
+        total: 10,
+        ..LayoutMigration::default()
+    };
+    assert_eq!(JobTask::MigrateLayout(migration.clone()).progress(), 0.0);
+    migration.migrated = 4;
+    migration.skipped = 1;
+    assert_eq!(JobTask::MigrateLayout(migration.clone()).progress(), 0.5);
+    // Blocks written while the migration runs are not counted, finishing completes the job
+    migration.finished = true;
+    assert_eq!(JobTask::MigrateLayout(migration.clone()).progress(), 1.0);
+
+    let task = serde_json::to_value(JobTask::MigrateLayout(migration)).expect("serializes");
+    assert_eq!(task["kind"], "migrate_layout");
+    assert_eq!(task["skipped"], 1);
+}
+
+async fn migration_job(sender: &UnboundedSender<ProtocolMessage>) -> Option<Job> {
+    match ProtocolMessage::ask(sender, ProtocolMessage::FetchJobs).await {
+        Ok(Response::Jobs(jobs)) => jobs
+            .into_iter()
+            .find(|job| matches!(job.task, JobTask::MigrateLayout(_))),
+        other => panic!("unexpected response {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn blocks_under_legacy_keys_are_moved_by_a_migration_job_created_on_the_start() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let path = dir.path().join("db").to_string_lossy().into_owned();
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(path.clone())
+        .db_receiver(receiver)
+        .build()
+        .expect("db opens");
+    let db = tokio::spawn(async move { db.run().await });
+    for (block_no, slot) in [(1, 10), (2, 20)] {
+        sender
+            .send(ProtocolMessage::FinalizeBlock(block_no, block(slot)))
+            .expect("db running");
+    }
+    // A migrated db does not get a migration job
+    assert!(migration_job(&sender).await.is_none());
+    drop(sender);
+    db.await.expect("db stops");
+
+    // Move the blocks back to the keys of an older db, next to a key that does not parse
+    let options = rocksdb::Options::default();
+    let column_families = rocksdb::DB::list_cf(&options, &path).expect("column families");
+    let raw = rocksdb::DB::open_cf(&options, &path, column_families).expect("db opens");
+    let blocks = raw.cf_handle("blocks").expect("blocks column family");
+    for block_no in [1u64, 2] {
+        let value = raw
+            .get_cf(blocks, block_no.to_be_bytes())
+            .expect("block read")
+            .expect("block stored");
+        raw.put(format!("BlockNo{}", block_no), value)
+            .expect("legacy block written");
+        raw.delete_cf(blocks, block_no.to_be_bytes())
+            .expect("block deleted");
+    }
+    raw.put("BlockNomalformed", b"corrupt")
+        .expect("legacy block written");
+    drop(raw);
+
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(path.clone())
+        .db_receiver(receiver)
+        .build()
+        .expect("db opens");
+    let db = tokio::spawn(async move { db.run().await });
+    let job = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let job = migration_job(&sender).await.expect("migration job created");
+            if job.state != JobState::Running {
+                return job;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("migration finishes");
+    assert_eq!(job.state, JobState::Finished);
+    assert_eq!(job.progress, 1.0);
+    match job.task {
+        JobTask::MigrateLayout(migration) => {
+            assert_eq!(migration.migrated, 2);
+            assert_eq!(migration.skipped, 1);
+            assert!(migration.finished);
+        }
+        other => panic!("unexpected task {other:?}"),
+    }
+    drop(sender);
+    db.await.expect("db stops");
+
+    let options = rocksdb::Options::default();
+    let column_families = rocksdb::DB::list_cf(&options, &path).expect("column families");
+    let raw = rocksdb::DB::open_cf(&options, &path, column_families).expect("db opens");
+    let blocks = raw.cf_handle("blocks").expect("blocks column family");
+    for block_no in [1u64, 2] {
+        assert!(raw
+            .get_cf(blocks, block_no.to_be_bytes())
+            .expect("block read")
+            .is_some());
+        assert!(raw
+            .get(format!("BlockNo{}", block_no))
+            .expect("legacy block read")
+            .is_none());
+    }
+    assert!(raw.get("BlockNomalformed").expect("read").is_none());
+}
@@ -0,0 +1,41 @@
+use log::{log_enabled, Level, LevelFilter};
+use solana_agg::logging;
+
+#[test]
+fn levels_are_set_per_target_at_runtime() {
+    std::env::set_var("RUST_LOG", "warn,db=debug");
+    logging::init().expect("logger installs");
+    assert!(log_enabled!(target: "db", Level::Debug));
+    assert!(!log_enabled!(target: "db", Level::Trace));
+    assert!(!log_enabled!(target: "subscriber", Level::Info));
+    assert!(log_enabled!(target: "subscriber", Level::Warn));
+
+    logging::set_level(Some("subscriber"), LevelFilter::Trace);
+    assert!(log_enabled!(target: "subscriber", Level::Trace));
+    // Module path targets inherit the level of their longest configured prefix
+    logging::set_level(Some("actix_web"), LevelFilter::Error);
+    assert_eq!(
+        logging::level("actix_web::middleware::logger"),
+        LevelFilter::Error
+    );
+    assert_eq!(logging::level("actix_webx"), LevelFilter::Warn);
+
+    let levels = logging::levels();
+    assert_eq!(levels.default, "warn");
+    assert_eq!(levels.targets["db"], "debug");
+    assert_eq!(levels.targets["subscriber"], "trace");
+
+    assert!(logging::reset_level("subscriber"));
+    assert!(!logging::reset_level("subscriber"));
+    assert!(!log_enabled!(target: "subscriber", Level::Info));
+    logging::set_level(None, LevelFilter::Info);
+    assert!(log_enabled!(target: "subscriber", Level::Info));
+
+    assert!(logging::parse_level("verbose").is_err());
+    assert_eq!(
+        logging::parse_level("OFF").expect("valid level"),
+        LevelFilter::Off
+    );
+    // Only one logger can be installed
+    assert!(logging::init().is_err());
+}
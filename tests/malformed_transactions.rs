@@ -0,0 +1,241 @@
+//! Malformed and edge-case transactions go through the parser without panicking. Each one is
+//! either parsed or recorded as a partial transaction with the reason it failed to parse.
+
+use proptest::prelude::*;
+use solana_agg::parser::Parser;
+use solana_agg::util::{Block, BlockHeader, ParseErrorKind};
+use solana_program::hash::Hash;
+use solana_program::instruction::CompiledInstruction;
+use solana_program::message::v0::{self, MessageAddressTableLookup};
+use solana_program::message::{Message, MessageHeader, VersionedMessage};
+use solana_program::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::{Transaction, TransactionVersion, VersionedTransaction};
+use solana_transaction_status::{
+    Encodable, EncodableWithMeta, EncodedTransactionWithStatusMeta, TransactionStatusMeta,
+    UiTransactionEncoding,
+};
+
+const SYSTEM_PROGRAM: &str = "11111111111111111111111111111111";
+const TOKEN_PROGRAM: &str = "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA";
+
+fn encode(
+    num_required_signatures: u8,
+    account_keys: Vec<Pubkey>,
+    instructions: Vec<CompiledInstruction>,
+    post_balances: Option<Vec<u64>>,
+) -> EncodedTransactionWithStatusMeta {
+    let transaction = Transaction {
+        signatures: vec![Signature::default(); num_required_signatures as usize],
+        message: Message {
+            header: MessageHeader {
+                num_required_signatures,
+                num_readonly_signed_accounts: 0,
+                num_readonly_unsigned_accounts: 0,
+            },
+            account_keys,
+            recent_blockhash: Hash::default(),
+            instructions,
+        },
+    };
+    EncodedTransactionWithStatusMeta {
+        transaction: transaction.encode(UiTransactionEncoding::Base64),
+        meta: post_balances.map(|post_balances| {
+            TransactionStatusMeta {
+                post_balances,
+                ..TransactionStatusMeta::default()
+            }
+            .into()
+        }),
+        version: None,
+    }
+}
+
+/// A transaction of the fee payer calling the system program with an instruction
+fn system_call(
+    accounts: Vec<u8>,
+    data: Vec<u8>,
+    post_balances: Option<Vec<u64>>,
+) -> EncodedTransactionWithStatusMeta {
+    encode(
+        1,
+        vec![
+            Pubkey::new_from_array([1; 32]),
+            Pubkey::new_from_array([2; 32]),
+            SYSTEM_PROGRAM.parse().unwrap(),
+        ],
+        vec![CompiledInstruction {
+            program_id_index: 2,
+            accounts,
+            data,
+        }],
+        post_balances,
+    )
+}
+
+/// A v0 transaction of the fee payer transferring to the first address of a lookup table, the
+/// metadata does not carry the loaded addresses
+fn lookup_transfer() -> EncodedTransactionWithStatusMeta {
+    let message = v0::Message {
+        header: MessageHeader {
+            num_required_signatures: 1,
+            num_readonly_signed_accounts: 0,
+            num_readonly_unsigned_accounts: 0,
+        },
+        account_keys: vec![
+            Pubkey::new_from_array([1; 32]),
+            Pubkey::new_from_array([2; 32]),
+            SYSTEM_PROGRAM.parse().unwrap(),
+        ],
+        recent_blockhash: Hash::default(),
+        instructions: vec![CompiledInstruction {
+            program_id_index: 2,
+            accounts: vec![0, 3],
+            data: [2u8; 12].to_vec(),
+        }],
+        address_table_lookups: vec![MessageAddressTableLookup {
+            account_key: Pubkey::new_from_array([4; 32]),
+            writable_indexes: vec![0],
+            readonly_indexes: vec![],
+        }],
+    };
+    let transaction = VersionedTransaction {
+        signatures: vec![Signature::default()],
+        message: VersionedMessage::V0(message),
+    };
+    let meta = TransactionStatusMeta {
+        post_balances: vec![1, 2, 3],
+        ..TransactionStatusMeta::default()
+    };
+    EncodedTransactionWithStatusMeta {
+        transaction: transaction.encode_with_meta(UiTransactionEncoding::Base64, &meta),
+        meta: Some(meta.into()),
+        version: Some(TransactionVersion::Number(0)),
+    }
+}
+
+fn parse(tx: EncodedTransactionWithStatusMeta) -> Block {
+    let header = BlockHeader {
+        slot: 1,
+        blockhash: "hash".to_string(),
+        ..Default::default()
+    };
+    Parser::parse_chunk(header, &[tx]).expect("a failing transaction does not fail the chunk")
+}
+
+fn parse_errors(block: &Block) -> Vec<String> {
+    block
+        .transactions()
+        .filter_map(|(_, tx)| tx.parse_error().map(str::to_string))
+        .collect()
+}
+
+#[test]
+fn fee_payer_only_transactions_record_the_payer_balance() {
+    let block = parse(encode(
+        1,
+        vec![Pubkey::new_from_array([1; 32])],
+        vec![],
+        Some(vec![5]),
+    ));
+    assert!(parse_errors(&block).is_empty());
+    assert_eq!(
+        block.get_account_balance(&Pubkey::new_from_array([1; 32]).to_string()),
+        Some(5)
+    );
+}
+
+#[test]
+fn transactions_without_account_keys_are_undecodable() {
+    let block = parse(encode(0, vec![], vec![], None));
+    assert_eq!(parse_errors(&block), vec!["Undecodable Transaction"]);
+}
+
+#[test]
+fn instructions_with_empty_data_are_not_transfers() {
+    let block = parse(system_call(vec![0, 1], vec![], Some(vec![1, 2, 3])));
+    assert!(parse_errors(&block).is_empty());
+    assert_eq!(block.transfers().count(), 0);
+}
+
+#[test]
+fn transfers_lacking_an_amount_or_accounts_are_malformed() {
+    for tx in [
+        system_call(vec![0, 1], vec![2, 0, 0, 0, 1], Some(vec![1, 2, 3])),
+        system_call(vec![], [2u8; 12].to_vec(), Some(vec![1, 2, 3])),
+        // The receiver is past the three account keys of the message
+        lookup_transfer(),
+    ] {
+        let block = parse(tx);
+        let errors = parse_errors(&block);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("Malformed Instruction"), "{errors:?}");
+        assert_eq!(
+            block.parse_errors().get(&ParseErrorKind::SliceError),
+            Some(&1)
+        );
+    }
+}
+
+#[test]
+fn missing_post_balances_are_malformed() {
+    let block = parse(system_call(vec![0, 1], vec![], Some(vec![])));
+    let errors = parse_errors(&block);
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].starts_with("Malformed Transaction"), "{errors:?}");
+}
+
+#[test]
+fn transactions_without_metadata_are_parsed() {
+    let mut data = vec![2, 0, 0, 0];
+    data.extend(1_000_000_000u64.to_le_bytes());
+    let block = parse(system_call(vec![0, 1], data, None));
+    assert!(parse_errors(&block).is_empty());
+    assert_eq!(
+        block.transfers().map(|(_, _, amount)| amount).sum::<f64>(),
+        1.0
+    );
+    assert!(block.transactions().all(|(_, tx)| tx.fee().is_none()));
+}
+
+fn account_key() -> impl Strategy<Value = Pubkey> {
+    prop_oneof![
+        Just(SYSTEM_PROGRAM.parse().unwrap()),
+        Just(TOKEN_PROGRAM.parse().unwrap()),
+        any::<[u8; 32]>().prop_map(Pubkey::new_from_array),
+    ]
+}
+
+fn instruction() -> impl Strategy<Value = CompiledInstruction> {
+    (
+        0u8..6,
+        prop::collection::vec(0u8..6, 0..5),
+        prop_oneof![
+            prop::collection::vec(any::<u8>(), 0..16),
+            // Transfer tags followed by anything
+            (
+                prop_oneof![Just(2u8), Just(3u8), Just(12u8)],
+                prop::collection::vec(any::<u8>(), 0..12)
+            )
+                .prop_map(|(tag, rest)| [vec![tag, 0, 0, 0], rest].concat()),
+        ],
+    )
+        .prop_map(|(program_id_index, accounts, data)| CompiledInstruction {
+            program_id_index,
+            accounts,
+            data,
+        })
+}
+
+proptest! {
+    #[test]
+    fn no_transaction_panics_the_parser(
+        num_required_signatures in 0u8..3,
+        account_keys in prop::collection::vec(account_key(), 0..6),
+        instructions in prop::collection::vec(instruction(), 0..4),
+        post_balances in prop::option::of(prop::collection::vec(any::<u64>(), 0..6)),
+    ) {
+        let block = parse(encode(num_required_signatures, account_keys, instructions, post_balances));
+        prop_assert_eq!(block.transactions().count(), 1);
+    }
+}
@@ -0,0 +1,78 @@
+use solana_agg::cli::Cli;
+use solana_agg::config::{Commitment, Config};
+use std::collections::HashMap;
+use structopt::StructOpt;
+
+fn config(toml: &str) -> Config {
+    toml::from_str(toml).expect("valid config")
+}
+
+fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+    let vars: HashMap<String, String> = vars
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    move |name| vars.get(name).cloned()
+}
+
+#[test]
+fn defaults_hold_no_personal_settings() {
+    let node = Config::default().node;
+    assert_eq!(node.chain_url, "https://api.devnet.solana.com");
+    assert_eq!(node.db_path, "db");
+    assert_eq!(node.port, 9944);
+    assert_eq!(node.commitment, Commitment::Finalized);
+    assert_eq!(node.chunk_size, 10);
+    assert_eq!(node.max_slots_per_round, 64);
+    assert_eq!(node.fetch_workers, 16);
+    assert_eq!(node.worker_threads, None);
+}
+
+#[test]
+fn the_environment_overrides_the_file_and_the_flags_override_both() {
+    let mut config = config(
+        "[node]\nchain_url = \"http://file:8899\"\ndb_path = \"/data/db\"\nport = 8000\n\
+         commitment = \"confirmed\"\nchunk_size = 25",
+    );
+    config
+        .apply_env(env(&[
+            ("AGG_CHAIN_URL", "http://env:8899"),
+            ("AGG_PORT", "8001"),
+            ("AGG_WORKER_THREADS", "4"),
+        ]))
+        .expect("valid environment");
+    let cli = Cli::from_iter(["solana-agg", "--port-no", "8002"]);
+    config.apply_cli(&cli);
+
+    let node = config.node;
+    assert_eq!(node.chain_url, "http://env:8899");
+    assert_eq!(node.db_path, "/data/db");
+    assert_eq!(node.port, 8002);
+    assert_eq!(node.commitment, Commitment::Confirmed);
+    assert_eq!(node.chunk_size, 25);
+    assert_eq!(node.worker_threads, Some(4));
+}
+
+#[test]
+fn invalid_environment_values_are_rejected() {
+    let mut config = Config::default();
+    let error = config
+        .apply_env(env(&[("AGG_PORT", "not-a-port")]))
+        .expect_err("invalid port");
+    assert!(error.to_string().contains("AGG_PORT"));
+    assert!(config
+        .apply_env(env(&[("AGG_COMMITMENT", "processed")]))
+        .is_err());
+}
+
+#[test]
+fn fetch_workers_are_set_by_the_file_the_environment_or_the_flag() {
+    let mut config = config("[node]\nfetch_workers = 4");
+    assert_eq!(config.node.fetch_workers, 4);
+    config
+        .apply_env(env(&[("AGG_FETCH_WORKERS", "8")]))
+        .expect("valid environment");
+    assert_eq!(config.node.fetch_workers, 8);
+    config.apply_cli(&Cli::from_iter(["solana-agg", "--fetch-workers", "2"]));
+    assert_eq!(config.node.fetch_workers, 2);
+}
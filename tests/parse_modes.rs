@@ -0,0 +1,86 @@
+use serde_json::{json, Value};
+use solana_agg::config::ParseMode;
+use solana_agg::parser::Parser;
+use solana_agg::util::{Block, BlockHeader, Channel, ParseErrorKind, ProtocolMessage};
+use solana_transaction_status::EncodedTransactionWithStatusMeta;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The first transaction of the system transfers fixture, and a copy whose encoding is corrupted
+fn transactions() -> Vec<EncodedTransactionWithStatusMeta> {
+    let raw = std::fs::read_to_string(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/system_transfers.json"),
+    )
+    .expect("fixture");
+    let block: Value = serde_json::from_str(&raw).expect("valid fixture");
+    let valid = block["transactions"][0].clone();
+    let mut corrupted = valid.clone();
+    corrupted["transaction"] = json!(["not base64!", "base64"]);
+    vec![
+        serde_json::from_value(valid).expect("valid transaction"),
+        serde_json::from_value(corrupted).expect("valid json"),
+    ]
+}
+
+async fn parse(mode: ParseMode) -> Block {
+    let mut channel = Channel::<ProtocolMessage>::new();
+    let header = BlockHeader {
+        slot: 1,
+        blockhash: "hash".to_string(),
+        ..Default::default()
+    };
+    Parser::invoke(
+        ProtocolMessage::new_chuck(1, header, 0, 1, transactions(), channel.sender()),
+        mode,
+    )
+    .await
+    .expect("a failing transaction does not fail the chunk");
+    match channel.receiver.recv().await {
+        Some(ProtocolMessage::ParsedBlock(_, _, _, block)) => block,
+        other => panic!("unexpected message {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn permissive_mode_records_partial_transactions() {
+    let block = parse(ParseMode::Permissive).await;
+    assert_eq!(block.transactions().count(), 2);
+    let partial: Vec<_> = block
+        .transactions()
+        .filter_map(|(_, tx)| tx.parse_error())
+        .collect();
+    assert_eq!(partial, vec!["Undecodable Transaction"]);
+    // The metadata of the partial record is kept
+    assert_eq!(block.tx_stats(), (2, 2, 10_000));
+    assert!(block.quarantined_transactions().is_empty());
+}
+
+#[tokio::test]
+async fn strict_mode_quarantines_failing_transactions_with_their_encoding() {
+    let block = parse(ParseMode::Strict).await;
+    assert_eq!(block.transactions().count(), 1);
+    assert!(block
+        .transactions()
+        .all(|(_, tx)| tx.parse_error().is_none()));
+    let quarantined = block.quarantined_transactions();
+    assert_eq!(quarantined.len(), 1);
+    assert_eq!(quarantined[0].error, "Undecodable Transaction");
+    assert_eq!(
+        serde_json::to_value(&quarantined[0].raw).expect("serializes")["transaction"],
+        json!(["not base64!", "base64"])
+    );
+}
+
+#[tokio::test]
+async fn parse_errors_are_counted_per_block_in_both_modes() {
+    for mode in [ParseMode::Permissive, ParseMode::Strict] {
+        let block = parse(mode).await;
+        let expected = BTreeMap::from([(ParseErrorKind::UndecodableTransaction, 1)]);
+        assert_eq!(block.parse_errors(), &expected);
+        let summary = serde_json::to_value(block.summary(1)).expect("serializes");
+        assert_eq!(
+            summary["parse_errors"],
+            json!({"undecodable_transaction": 1})
+        );
+    }
+}
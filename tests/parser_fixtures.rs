@@ -0,0 +1,162 @@
+//! Replays the encoded blocks in `tests/fixtures` through the parser and compares the assembled
+//! blocks with the committed `*.parsed.json` output. Run with `UPDATE_FIXTURES=1` to regenerate
+//! the expected output after an intended parser change.
+
+use serde_json::Value;
+use solana_agg::config::ParseMode;
+use solana_agg::parser::Parser;
+use solana_agg::util::{BlockHeader, Channel, ProtocolMessage, UnprocessedBlock};
+use solana_transaction_status::UiConfirmedBlock;
+use std::path::{Path, PathBuf};
+
+const CHUNK_SIZE: usize = 10;
+
+fn fixtures_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+/// Parses a fixture the way the block fetcher and handler do, chunk by chunk
+async fn parse_fixture(name: &str) -> Value {
+    let raw = std::fs::read_to_string(fixtures_dir().join(format!("{name}.json"))).unwrap();
+    let block: UiConfirmedBlock = serde_json::from_str(&raw).unwrap();
+    let block_no = block.block_height.unwrap();
+    let header = BlockHeader {
+        slot: block.parent_slot + 1,
+        blockhash: block.blockhash.clone(),
+        block_time: block.block_time,
+        previous_blockhash: Some(block.previous_blockhash.clone()),
+        parent_slot: Some(block.parent_slot),
+        transaction_count: block.transactions.as_ref().map(|txs| txs.len() as u64),
+    };
+    let txs = block.transactions.unwrap_or_default();
+    let chunks: Vec<_> = if txs.is_empty() {
+        vec![vec![]]
+    } else {
+        txs.chunks(CHUNK_SIZE).map(<[_]>::to_vec).collect()
+    };
+    let total_chunks = chunks.len() as u64;
+    let mut channel = Channel::<ProtocolMessage>::new();
+    for (chunk_no, chunk) in chunks.into_iter().enumerate() {
+        Parser::invoke(
+            ProtocolMessage::new_chuck(
+                block_no,
+                header.clone(),
+                chunk_no as u64,
+                total_chunks,
+                chunk,
+                channel.sender(),
+            ),
+            ParseMode::Permissive,
+        )
+        .await
+        .unwrap();
+    }
+    let mut unprocessed = UnprocessedBlock::new(total_chunks);
+    while !unprocessed.is_complete() {
+        match channel.receiver.recv().await {
+            Some(ProtocolMessage::ParsedBlock(parsed_block_no, _, chunk_no, partial)) => {
+                assert_eq!(parsed_block_no, block_no);
+                unprocessed.insert_chunk(chunk_no, partial).unwrap();
+            }
+            other => panic!("unexpected message {other:?}"),
+        }
+    }
+    serde_json::to_value(unprocessed.complete_the_block().unwrap()).unwrap()
+}
+
+fn transactions(block: &Value) -> Vec<&Value> {
+    block["tx_map"].as_object().unwrap().values().collect()
+}
+
+#[tokio::test]
+async fn fixtures_match_expected_output() {
+    let mut names: Vec<String> = std::fs::read_dir(fixtures_dir())
+        .unwrap()
+        .filter_map(|entry| {
+            let name = entry.unwrap().file_name().into_string().unwrap();
+            let name = name.strip_suffix(".json")?;
+            (!name.ends_with(".parsed")).then(|| name.to_string())
+        })
+        .collect();
+    names.sort();
+    assert!(!names.is_empty());
+    for name in names {
+        let parsed = parse_fixture(&name).await;
+        let expected_path = fixtures_dir().join(format!("{name}.parsed.json"));
+        if std::env::var_os("UPDATE_FIXTURES").is_some() {
+            let json = serde_json::to_string_pretty(&parsed).unwrap();
+            std::fs::write(&expected_path, json + "\n").unwrap();
+            continue;
+        }
+        let expected: Value =
+            serde_json::from_str(&std::fs::read_to_string(&expected_path).unwrap()).unwrap();
+        assert_eq!(parsed, expected, "parsed output of fixture {name} changed");
+    }
+}
+
+#[tokio::test]
+async fn decodes_system_transfers() {
+    let block = parse_fixture("system_transfers").await;
+    let txs = transactions(&block);
+    assert_eq!(txs.len(), 13);
+    let transfers: Vec<f64> = txs
+        .iter()
+        .filter(|tx| tx["succeeded"] == true)
+        .flat_map(|tx| tx["instruction"].as_array().unwrap())
+        .map(|instruction| instruction["Transfer"][2].as_f64().unwrap())
+        .collect();
+    assert_eq!(transfers.len(), 11);
+    assert!(transfers.contains(&1.5));
+    assert!(transfers.contains(&0.01));
+    assert!(txs.iter().all(|tx| tx["fee"] == 5000));
+}
+
+#[tokio::test]
+async fn records_failed_transactions() {
+    let block = parse_fixture("system_transfers").await;
+    let failed: Vec<_> = transactions(&block)
+        .into_iter()
+        .filter(|tx| tx["succeeded"] == false)
+        .collect();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0]["fee"], 5000);
+}
+
+#[tokio::test]
+async fn records_token_balances() {
+    let block = parse_fixture("token_transfer").await;
+    let balances = block["token_balances"].as_object().unwrap();
+    assert_eq!(balances.len(), 2);
+    let mut amounts: Vec<u64> = balances
+        .values()
+        .map(|balance| balance["amount"].as_u64().unwrap())
+        .collect();
+    amounts.sort();
+    assert_eq!(amounts, vec![250_000, 750_000]);
+    assert!(balances.values().all(|balance| balance["decimals"] == 6));
+}
+
+#[tokio::test]
+async fn decodes_token_transfers() {
+    let block = parse_fixture("token_transfer").await;
+    let txs = transactions(&block);
+    let transfer = &txs[0]["instruction"][0]["TokenTransfer"];
+    assert_eq!(transfer["amount"], 250_000);
+    assert_eq!(transfer["decimals"], 6);
+    assert_eq!(
+        transfer["mint"],
+        "Gq2ZG2URrWhVFeWRua7PnRuipDytEXyh26VgCo7cBm46"
+    );
+    assert_eq!(
+        transfer["source_owner"],
+        "7LSfLv2S6K7zMPrgmJDkZoJNhWvWRzpU7qt9uMR5yz8G"
+    );
+}
+
+#[tokio::test]
+async fn assembles_empty_blocks() {
+    let block = parse_fixture("empty_block").await;
+    assert!(transactions(&block).is_empty());
+    assert_eq!(block["slot"], 300_000_102);
+    assert_eq!(block["block_time"], 1_722_000_040);
+}
@@ -0,0 +1,229 @@
+mod common;
+
+use common::block;
+use serde_json::json;
+use solana_agg::config::PluginConfig;
+use solana_agg::error::AggError;
+use solana_agg::handler::Handler;
+use solana_agg::plugin::{self, BlockPlugin, Rejection, Verdict};
+use solana_agg::retry::Failure;
+use solana_agg::util::{Block, Channel, DeadLetter, FailureStage, ProtocolMessage, Response};
+use solana_agg::Builder;
+use std::ops::Range;
+use std::sync::{Arc, Barrier, Mutex};
+use std::thread;
+
+#[derive(Clone)]
+enum Action {
+    Keep,
+    Reject,
+    Enrich,
+    Panic,
+    /// Enriches the block, then panics before returning
+    EnrichThenPanic,
+    /// Signals it is running, then waits until released
+    Block(Arc<Barrier>),
+}
+
+/// Acts on the blocks of its range only, as the registry is shared by the tests of this file
+struct Scripted {
+    name: &'static str,
+    blocks: Range<u64>,
+    action: Action,
+    seen: Arc<Mutex<Vec<u64>>>,
+}
+
+impl BlockPlugin for Scripted {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn on_block(&mut self, block_no: u64, block: &mut Block) -> Verdict {
+        if !self.blocks.contains(&block_no) {
+            return Verdict::Keep;
+        }
+        self.seen.lock().unwrap().push(block_no);
+        match &self.action {
+            Action::Keep => Verdict::Keep,
+            Action::Reject if block_no % 2 == 1 => Verdict::Reject("odd block".to_string()),
+            Action::Reject => Verdict::Keep,
+            Action::Enrich => {
+                block.insert_account("Enriched".to_string(), block_no);
+                Verdict::Keep
+            }
+            Action::Panic => panic!("broken plugin"),
+            Action::EnrichThenPanic => {
+                block.insert_account("Enriched".to_string(), block_no);
+                panic!("broken plugin")
+            }
+            Action::Block(barrier) => {
+                barrier.wait();
+                barrier.wait();
+                Verdict::Keep
+            }
+        }
+    }
+}
+
+fn register(name: &'static str, blocks: Range<u64>, action: Action) -> Arc<Mutex<Vec<u64>>> {
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    plugin::register(Scripted {
+        name,
+        blocks,
+        action,
+        seen: seen.clone(),
+    })
+    .expect("registered");
+    seen
+}
+
+#[test]
+fn plugins_run_in_order_until_one_rejects() {
+    let first = register("first", 0..100, Action::Keep);
+    register("panicker", 0..100, Action::Panic);
+    register("rejecter", 0..100, Action::Reject);
+    let last = register("last", 0..100, Action::Keep);
+    let names: Vec<String> = plugin::names()
+        .into_iter()
+        .filter(|name| ["first", "panicker", "rejecter", "last"].contains(&name.as_str()))
+        .collect();
+    assert_eq!(names, vec!["first", "panicker", "rejecter", "last"]);
+    assert!(plugin::register(Scripted {
+        name: "first",
+        blocks: 0..0,
+        action: Action::Keep,
+        seen: Arc::default(),
+    })
+    .is_err());
+
+    // The panicking plugin is skipped, the rejection stops the plugins after it
+    let config = PluginConfig::default();
+    assert_eq!(
+        plugin::run(&config, 7, &mut Block::default()),
+        Err(Rejection {
+            plugin: "rejecter".to_string(),
+            reason: "odd block".to_string(),
+        })
+    );
+    assert_eq!(*first.lock().unwrap(), vec![7]);
+    assert!(last.lock().unwrap().is_empty());
+}
+
+#[test]
+fn disabled_plugins_are_skipped_and_rejections_may_only_be_logged() {
+    let disabled = register("disabled_enricher", 100..200, Action::Enrich);
+    register("logged_rejecter", 100..200, Action::Reject);
+    let tail = register("tail", 100..200, Action::Keep);
+    let config = PluginConfig {
+        disabled: vec!["disabled_enricher".to_string()],
+        enforce: false,
+    };
+    let mut block = Block::default();
+    assert_eq!(plugin::run(&config, 151, &mut block), Ok(()));
+    assert!(disabled.lock().unwrap().is_empty());
+    assert_eq!(*tail.lock().unwrap(), vec![151]);
+    let json = serde_json::to_value(&block).expect("serializes");
+    assert!(json["account_map"]["Enriched"].is_null());
+}
+
+#[test]
+fn the_handler_stores_enriched_blocks_and_dead_letters_rejected_ones() {
+    register("enricher", 200..300, Action::Enrich);
+    register("odd_rejecter", 200..300, Action::Reject);
+    let handler_channel = Channel::<ProtocolMessage>::new();
+    let mut db_channel = Channel::<ProtocolMessage>::new();
+    let mut handler = Handler::initialize(handler_channel.receiver, db_channel.sender());
+    handler.set_plugin_config(PluginConfig::default());
+
+    handler
+        .handle_unprocessed_block(210, 1, 0, Block::default())
+        .expect("handled");
+    match db_channel.receiver.try_recv() {
+        Ok(ProtocolMessage::FinalizeBlock(210, block)) => {
+            let json = serde_json::to_value(&block).expect("serializes");
+            assert_eq!(json["account_map"], json!({"Enriched": 210}));
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+
+    handler
+        .handle_unprocessed_block(211, 1, 0, Block::default())
+        .expect("handled");
+    match db_channel.receiver.try_recv() {
+        Ok(ProtocolMessage::DeadLetter(letter)) => {
+            assert_eq!(letter.stage, FailureStage::Plugin);
+            assert_eq!(letter.block_no, Some(211));
+            assert_eq!(letter.code, "plugin_rejected");
+            assert!(letter.error.contains("odd_rejecter"));
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn a_plugin_panicking_halfway_leaves_the_block_unchanged() {
+    register("half_enricher", 300..400, Action::EnrichThenPanic);
+    let mut block = Block::default();
+    assert_eq!(
+        plugin::run(&PluginConfig::default(), 300, &mut block),
+        Ok(())
+    );
+    let json = serde_json::to_value(&block).expect("serializes");
+    assert!(json["account_map"]["Enriched"].is_null());
+}
+
+#[test]
+fn the_registry_is_not_locked_while_a_plugin_runs() {
+    let barrier = Arc::new(Barrier::new(2));
+    register("blocker", 400..500, Action::Block(barrier.clone()));
+    let running =
+        thread::spawn(|| plugin::run(&PluginConfig::default(), 400, &mut Block::default()));
+    // The plugin is running until the barrier is passed a second time
+    barrier.wait();
+    assert!(plugin::names().contains(&"blocker".to_string()));
+    register("registered_meanwhile", 400..500, Action::Keep);
+    barrier.wait();
+    assert_eq!(running.join().expect("plugins ran"), Ok(()));
+}
+
+async fn latest_block_no(
+    sender: &tokio::sync::mpsc::UnboundedSender<ProtocolMessage>,
+) -> Option<u64> {
+    match ProtocolMessage::ask(sender, ProtocolMessage::FetchStatus).await {
+        Ok(Response::Status(status)) => status.latest_block_no,
+        other => panic!("unexpected response {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn a_rejected_block_does_not_hold_back_the_blocks_after_it() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(dir.path().join("db").to_string_lossy().into_owned())
+        .db_receiver(receiver)
+        .build()
+        .expect("db opens");
+    tokio::spawn(async move { db.run().await });
+    for (block_no, slot) in [(1, 10), (3, 30)] {
+        sender
+            .send(ProtocolMessage::FinalizeBlock(block_no, block(slot)))
+            .expect("db running");
+    }
+    // Block 3 waits for block 2
+    assert_eq!(latest_block_no(&sender).await, Some(1));
+
+    let failure = Failure {
+        error: AggError::PluginRejected("odd_rejecter".to_string(), "odd block".to_string()),
+        attempts: 1,
+    };
+    sender
+        .send(ProtocolMessage::DeadLetter(Box::new(DeadLetter::new(
+            FailureStage::Plugin,
+            Some(20),
+            Some(2),
+            &failure,
+        ))))
+        .expect("db running");
+    assert_eq!(latest_block_no(&sender).await, Some(3));
+}
@@ -0,0 +1,83 @@
+use solana_agg::config::Config;
+use solana_agg::preflight::{self, Check, Outcome};
+
+/// Config of a db that does not exist yet under a temporary directory and an RPC node nothing
+/// listens on
+fn config(dir: &tempfile::TempDir) -> Config {
+    let mut config = Config::default();
+    config.node.db_path = dir.path().join("db").to_string_lossy().into_owned();
+    config.node.chain_url = "http://127.0.0.1:1".to_string();
+    config.startup.rpc_timeout_secs = 1;
+    config
+}
+
+#[test]
+fn disk_space_is_checked_on_the_volume_the_db_will_be_created_on() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut config = config(&dir);
+    config.startup.min_free_disk_mb = 0;
+    let report = preflight::run(&config, &[Check::DiskSpace]);
+    assert_eq!(report.outcome(Check::DiskSpace), Some(&Outcome::Passed));
+
+    config.startup.min_free_disk_mb = u64::MAX;
+    let report = preflight::run(&config, &[Check::DiskSpace]);
+    match report.outcome(Check::DiskSpace) {
+        Some(Outcome::Failed(message)) => assert!(message.contains("startup.min_free_disk_mb")),
+        outcome => panic!("expected the disk check to fail, got {:?}", outcome),
+    }
+}
+
+#[test]
+fn unreachable_rpc_fails_startup_unless_forced() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let config = config(&dir);
+    let report = preflight::run(&config, &Check::ALL);
+    match report.outcome(Check::Rpc) {
+        Some(Outcome::Failed(message)) => {
+            assert!(message.contains("http://127.0.0.1:1"));
+            assert!(message.contains("node.chain_url"));
+        }
+        outcome => panic!("expected the rpc check to fail, got {:?}", outcome),
+    }
+    // Checks against the node are not run once it is found unreachable
+    assert!(matches!(
+        report.outcome(Check::Commitment),
+        Some(Outcome::Skipped(_))
+    ));
+    assert!(matches!(
+        report.outcome(Check::GenesisHash),
+        Some(Outcome::Skipped(_))
+    ));
+    // There is no db to compare against yet
+    assert!(matches!(
+        report.outcome(Check::SchemaVersion),
+        Some(Outcome::Skipped(_))
+    ));
+    assert_eq!(report.failures(), 1);
+
+    let error = report.enforce(false).unwrap_err();
+    assert_eq!(error.code(), "startup_check_failed");
+    assert!(report.enforce(true).is_ok());
+}
+
+#[test]
+fn disabled_and_unrequested_checks_are_not_run() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut config = config(&dir);
+    config.startup.disabled = vec!["rpc".to_string(), "disk_space".to_string()];
+    let report = preflight::run(&config, &[Check::Rpc, Check::DiskSpace]);
+    assert_eq!(
+        report.outcome(Check::Rpc),
+        Some(&Outcome::Skipped(
+            "disabled in startup.disabled".to_string()
+        ))
+    );
+    assert_eq!(
+        report.outcome(Check::DiskSpace),
+        Some(&Outcome::Skipped(
+            "disabled in startup.disabled".to_string()
+        ))
+    );
+    assert_eq!(report.outcome(Check::Commitment), None);
+    assert!(report.enforce(false).is_ok());
+}
@@ -0,0 +1,39 @@
+#![cfg(feature = "profiling")]
+
+use solana_agg::error::AggError;
+use solana_agg::profiling::{capture_cpu_profile, dump_heap_profile, ProfileFormat};
+use std::time::Duration;
+
+#[test]
+fn heap_profiles_are_dumped_from_startup() {
+    let heap = dump_heap_profile().expect("heap profile");
+    assert!(heap.starts_with(b"heap_v2/"));
+}
+
+#[tokio::test]
+async fn one_cpu_profile_is_captured_at_a_time() {
+    let first = capture_cpu_profile(Duration::from_millis(300), 99, ProfileFormat::Flamegraph);
+    let second = async {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        capture_cpu_profile(Duration::from_millis(50), 99, ProfileFormat::Pprof).await
+    };
+    // Keeps a thread on the CPU so the profile has samples
+    let busy = std::thread::spawn(|| {
+        let mut n = 0u64;
+        for _ in 0..100_000_000u64 {
+            n = std::hint::black_box(n.wrapping_mul(31).wrapping_add(7));
+        }
+        n
+    });
+    let (first, second) = tokio::join!(first, second);
+    busy.join().expect("busy thread");
+    // Sampling is coarse on some hosts, a capture without samples fails rather than being empty
+    match first {
+        Ok(svg) => assert!(String::from_utf8(svg).unwrap().contains("<svg")),
+        Err(err) => assert_eq!(err.code(), "profiling"),
+    }
+    assert_eq!(second.unwrap_err().code(), "profile_in_progress");
+    // The profiler is free again once a capture ends
+    let third = capture_cpu_profile(Duration::from_millis(50), 99, ProfileFormat::Pprof).await;
+    assert!(!matches!(third, Err(AggError::ProfileInProgress)));
+}
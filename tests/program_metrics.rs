@@ -0,0 +1,80 @@
+use solana_agg::config::{ParseMode, ProgramMetricConfig};
+use solana_agg::metrics::PROGRAM_METRICS;
+use solana_agg::parser::Parser;
+use solana_agg::program_metrics;
+use solana_agg::util::{BlockHeader, Channel, ProtocolMessage};
+use solana_transaction_status::UiConfirmedBlock;
+use std::path::Path;
+
+const SYSTEM_PROGRAM: &str = "11111111111111111111111111111111";
+
+/// Parses the system transfers fixture the way the block fetcher does
+async fn ingest_system_transfers() {
+    let raw = std::fs::read_to_string(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/system_transfers.json"),
+    )
+    .expect("fixture");
+    let block: UiConfirmedBlock = serde_json::from_str(&raw).expect("valid fixture");
+    let header = BlockHeader {
+        slot: block.parent_slot + 1,
+        blockhash: block.blockhash.clone(),
+        block_time: block.block_time,
+        parent_slot: Some(block.parent_slot),
+        transaction_count: block.transactions.as_ref().map(|txs| txs.len() as u64),
+        ..Default::default()
+    };
+    let channel = Channel::<ProtocolMessage>::new();
+    Parser::invoke(
+        ProtocolMessage::new_chuck(
+            1,
+            header,
+            0,
+            1,
+            block.transactions.unwrap_or_default(),
+            channel.sender(),
+        ),
+        ParseMode::Permissive,
+    )
+    .await
+    .expect("parses");
+}
+
+fn metric(name: &str, program: &str) -> f64 {
+    PROGRAM_METRICS.with_label_values(&[name, program]).get()
+}
+
+#[tokio::test]
+async fn configured_and_registered_hooks_are_evaluated_while_parsing() {
+    let configs: Vec<ProgramMetricConfig> = toml::from_str::<toml::Table>(
+        r#"
+        [[program_metrics]]
+        name = "system_transfers"
+        kind = "count_instructions"
+        program = "11111111111111111111111111111111"
+        data_prefix = [2, 0, 0, 0]
+
+        [[program_metrics]]
+        name = "deposits"
+        kind = "sum_transfers_to"
+        address = "9hSR6S7WPtxmTojgo6GG3k4yDPecgJY292j7xrsUGWBu"
+        "#,
+    )
+    .expect("valid toml")["program_metrics"]
+        .clone()
+        .try_into()
+        .expect("valid program metrics");
+    program_metrics::register_config(&configs).expect("registers");
+    program_metrics::register("system_instructions", SYSTEM_PROGRAM, |_| 1.0).expect("registers");
+
+    ingest_system_transfers().await;
+
+    // Configured metrics only count successful transactions, one of the 12 transfers failed
+    assert_eq!(metric("system_transfers", SYSTEM_PROGRAM), 11.0);
+    assert_eq!(metric("deposits", SYSTEM_PROGRAM), 1_500_000_000.0);
+    assert_eq!(metric("system_instructions", SYSTEM_PROGRAM), 12.0);
+    assert!(solana_agg::metrics::render().contains("agg_program_metric_total"));
+
+    // Names are unique and program ids must be valid
+    assert!(program_metrics::register("deposits", SYSTEM_PROGRAM, |_| 1.0).is_err());
+    assert!(program_metrics::register("invalid", "not a program", |_| 1.0).is_err());
+}
@@ -0,0 +1,84 @@
+use solana_agg::{Block, Instruction, TxRecord};
+
+#[test]
+fn indexed_types_are_exported_at_the_root() {
+    let instruction = Instruction::Transfer("from".to_string(), "to".to_string(), 1.5);
+    let tx = TxRecord::new(vec![instruction], None).unwrap();
+    assert_eq!(tx.sol_transferred(), 1.5);
+    assert_eq!(Block::default().transactions().count(), 0);
+}
+
+/// Serves the db of a running primary through the pipeline of the library, started read only
+/// like `solana-agg --read-only`, and queries it through the client
+#[cfg(feature = "client")]
+#[tokio::test(flavor = "multi_thread")]
+async fn a_pipeline_run_through_the_library_is_queried_through_the_client() {
+    use solana_agg::cli::Cli;
+    use solana_agg::config::Config;
+    use solana_agg::util::{BlockHeader, ProtocolMessage, Response};
+    use solana_agg::{AggClient, Builder};
+    use std::time::Duration;
+    use structopt::StructOpt;
+
+    let dir = tempfile::tempdir().expect("temp dir");
+    let db_path = dir.path().join("db").to_string_lossy().into_owned();
+    let (primary, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(db_path.clone())
+        .db_receiver(receiver)
+        .build()
+        .expect("primary opens");
+    tokio::spawn(async move { db.run().await });
+    let mut block = Block::default();
+    block.set_header(BlockHeader {
+        slot: 10,
+        blockhash: "hash-10".to_string(),
+        ..Default::default()
+    });
+    primary
+        .send(ProtocolMessage::FinalizeBlock(1, block))
+        .expect("primary running");
+    match ProtocolMessage::ask(&primary, ProtocolMessage::FetchStatus).await {
+        Ok(Response::Status(status)) => assert_eq!(status.latest_block_no, Some(1)),
+        other => panic!("unexpected response {other:?}"),
+    }
+
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("free port")
+        .port();
+    let config_path = dir.path().join("agg.toml");
+    let exports = dir.path().join("exports");
+    std::fs::write(
+        &config_path,
+        format!("[export]\ndir = {:?}\n", exports.to_string_lossy()),
+    )
+    .expect("config written");
+    let opt = Cli::from_iter([
+        "solana-agg",
+        "--read-only",
+        "--db-url",
+        &db_path,
+        "--port-no",
+        &port.to_string(),
+        "--config",
+        &config_path.to_string_lossy(),
+    ]);
+    let config = Config::resolve(&opt).expect("config resolves");
+    tokio::spawn(solana_agg::run(config, opt));
+
+    let client = AggClient::new(&format!("http://127.0.0.1:{}", port));
+    let status = tokio::time::timeout(Duration::from_secs(30), async {
+        loop {
+            match client.status().await {
+                Ok(status) => return status,
+                Err(_) => tokio::time::sleep(Duration::from_millis(100)).await,
+            }
+        }
+    })
+    .await
+    .expect("the server never answered");
+    assert_eq!(status.latest_block_no, Some(1));
+    let block = client.block_details(1).await.expect("block served");
+    assert_eq!(block.slot(), Some(10));
+}
@@ -0,0 +1,152 @@
+use actix_web::dev::ServiceResponse;
+use actix_web::{test, web, App, HttpResponse};
+use solana_agg::config::{PublicConfig, TenantConfig};
+use solana_agg::rate_limit::{PublicLimiter, PublicRateLimit, COST_HEADER, REMAINING_HEADER};
+use solana_agg::tenant::TenantRegistry;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+fn limiter(enabled: bool) -> Arc<PublicLimiter> {
+    Arc::new(PublicLimiter::new(&PublicConfig {
+        enabled,
+        budget_per_minute: 12,
+        default_cost: 1,
+        costs: BTreeMap::from([("account_txs".to_string(), 3)]),
+    }))
+}
+
+async fn ok() -> HttpResponse {
+    HttpResponse::Ok().json(true)
+}
+
+fn header<B>(response: &ServiceResponse<B>, name: &str) -> Option<String> {
+    response
+        .headers()
+        .get(name)
+        .map(|value| value.to_str().unwrap().to_string())
+}
+
+#[actix_web::test]
+async fn endpoints_cost_their_weight() {
+    let limiter = limiter(true);
+    assert_eq!(limiter.cost("/block_range/1/10"), 10);
+    assert_eq!(limiter.cost("/block_range"), 10);
+    assert_eq!(limiter.cost("/account_txs/abc"), 3);
+    assert_eq!(limiter.cost("/tx_details/abc"), 1);
+}
+
+#[actix_web::test]
+async fn requests_are_charged_until_the_budget_is_spent() {
+    let app = test::init_service(
+        App::new()
+            .wrap(PublicRateLimit(limiter(true)))
+            .default_service(web::to(ok)),
+    )
+    .await;
+    let response = test::call_service(
+        &app,
+        test::TestRequest::get()
+            .uri("/block_range/1/10")
+            .to_request(),
+    )
+    .await;
+    assert_eq!(response.status(), 200);
+    assert_eq!(header(&response, COST_HEADER).as_deref(), Some("10"));
+    assert_eq!(header(&response, REMAINING_HEADER).as_deref(), Some("2"));
+    let response = test::call_service(
+        &app,
+        test::TestRequest::get()
+            .uri("/account_txs/abc")
+            .to_request(),
+    )
+    .await;
+    assert_eq!(response.status(), 429);
+    assert!(response.headers().contains_key("retry-after"));
+    let response = test::call_service(
+        &app,
+        test::TestRequest::get().uri("/latest_block").to_request(),
+    )
+    .await;
+    assert_eq!(response.status(), 200);
+    assert_eq!(header(&response, REMAINING_HEADER).as_deref(), Some("1"));
+}
+
+#[actix_web::test]
+async fn clients_have_their_own_budget() {
+    let tenants = TenantRegistry::new(&[TenantConfig {
+        name: "community".to_string(),
+        api_keys: vec!["first".to_string(), "second".to_string()],
+        requests_per_minute: None,
+    }]);
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(tenants))
+            .wrap(PublicRateLimit(limiter(true)))
+            .default_service(web::to(ok)),
+    )
+    .await;
+    for api_key in ["first", "second"] {
+        let request = test::TestRequest::get()
+            .uri("/block_range/1/10")
+            .insert_header(("x-api-key", api_key))
+            .to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), 200);
+    }
+}
+
+#[actix_web::test]
+async fn clients_without_a_tenant_key_are_charged_by_address() {
+    let app = test::init_service(
+        App::new()
+            .wrap(PublicRateLimit(limiter(true)))
+            .default_service(web::to(ok)),
+    )
+    .await;
+    let request = |ip: &str, api_key: Option<&str>| {
+        let request = test::TestRequest::get()
+            .uri("/block_range/1/10")
+            .peer_addr(format!("{ip}:4000").parse().unwrap());
+        match api_key {
+            Some(api_key) => request.insert_header(("x-api-key", api_key)),
+            None => request,
+        }
+        .to_request()
+    };
+    let response = test::call_service(&app, request("10.0.0.1", None)).await;
+    assert_eq!(response.status(), 200);
+    // A made up key does not get a budget of its own
+    let response = test::call_service(&app, request("10.0.0.1", Some("made-up"))).await;
+    assert_eq!(response.status(), 429);
+    let response = test::call_service(&app, request("10.0.0.2", Some("made-up"))).await;
+    assert_eq!(response.status(), 200);
+}
+
+#[actix_web::test]
+async fn admin_routes_and_disabled_profile_are_not_charged() {
+    let app = test::init_service(
+        App::new()
+            .wrap(PublicRateLimit(limiter(false)))
+            .default_service(web::to(ok)),
+    )
+    .await;
+    let response = test::call_service(
+        &app,
+        test::TestRequest::get()
+            .uri("/block_range/1/10")
+            .to_request(),
+    )
+    .await;
+    assert!(header(&response, REMAINING_HEADER).is_none());
+    let app = test::init_service(
+        App::new()
+            .wrap(PublicRateLimit(limiter(true)))
+            .default_service(web::to(ok)),
+    )
+    .await;
+    let response = test::call_service(
+        &app,
+        test::TestRequest::get().uri("/admin/usage").to_request(),
+    )
+    .await;
+    assert!(header(&response, REMAINING_HEADER).is_none());
+}
@@ -0,0 +1,39 @@
+use solana_agg::metrics::EXPIRED_QUERIES;
+use solana_agg::util::{ProtocolMessage, Response};
+use solana_agg::Builder;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+#[tokio::test]
+async fn queries_past_their_deadline_are_dropped_without_a_reply() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(dir.path().join("db").to_string_lossy().into_owned())
+        .db_receiver(receiver)
+        .build()
+        .expect("db opens");
+    tokio::spawn(async move { db.run().await });
+    let expired = EXPIRED_QUERIES.get();
+
+    // The server gave up on the query before the db got to it
+    let (reply, response) = oneshot::channel();
+    sender
+        .send(ProtocolMessage::Deadline(
+            Instant::now(),
+            Box::new(ProtocolMessage::FetchStatus(reply)),
+        ))
+        .expect("db running");
+    assert!(response.await.is_err(), "an expired query was answered");
+    assert_eq!(EXPIRED_QUERIES.get(), expired + 1);
+
+    let (reply, response) = oneshot::channel();
+    sender
+        .send(ProtocolMessage::Deadline(
+            Instant::now() + Duration::from_secs(60),
+            Box::new(ProtocolMessage::FetchStatus(reply)),
+        ))
+        .expect("db running");
+    assert!(matches!(response.await, Ok(Response::Status(_))));
+    assert_eq!(EXPIRED_QUERIES.get(), expired + 1);
+}
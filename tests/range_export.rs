@@ -0,0 +1,212 @@
+use serde_json::{json, Value};
+use solana_agg::config::ExportConfig;
+use solana_agg::export::{ExportJob, Exporter};
+use solana_agg::util::{
+    Block, BlockHeader, Channel, Job, JobState, ProtocolMessage, Response, TxRecord,
+};
+use solana_program::hash::hash;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Registers jobs and serves FetchBlocksBySlot like the db, with one block numbered after its
+/// slot on every slot that is not a multiple of `skip_every`. Job ids start at `first_job_id`.
+fn fake_db(skip_every: u64, first_job_id: u64) -> UnboundedSender<ProtocolMessage> {
+    let mut channel = Channel::<ProtocolMessage>::new();
+    let sender = channel.sender();
+    tokio::spawn(async move {
+        let mut next_job_id = first_job_id;
+        while let Some(message) = channel.receiver.recv().await {
+            let (start, end, limit, reply) = match message {
+                ProtocolMessage::FetchBlocksBySlot(start, end, limit, reply) => {
+                    (start, end, limit, reply)
+                }
+                ProtocolMessage::StartJob(task, reply) => {
+                    let job = Job {
+                        id: next_job_id,
+                        state: JobState::Running,
+                        progress: task.progress(),
+                        task,
+                        error: None,
+                        created_at: 0,
+                        updated_at: 0,
+                    };
+                    next_job_id += 1;
+                    let _ = reply.send(Response::Job(Some(job)));
+                    continue;
+                }
+                _ => continue,
+            };
+            let mut blocks = Vec::new();
+            let mut next_slot = None;
+            for slot in start..=end {
+                if blocks.len() as u64 >= limit {
+                    next_slot = Some(slot);
+                    break;
+                }
+                if slot % skip_every == 0 {
+                    continue;
+                }
+                let mut block = Block::default();
+                block.set_header(BlockHeader {
+                    slot,
+                    blockhash: format!("hash-{slot}"),
+                    block_time: Some(1_700_000_000 + slot as i64),
+                    ..Default::default()
+                });
+                block.push_transaction(
+                    hash(&slot.to_le_bytes()),
+                    TxRecord::new(vec![], None).expect("record"),
+                );
+                blocks.push((slot, block));
+            }
+            let _ = reply.send(Response::BlocksBySlot(blocks, next_slot));
+        }
+    });
+    sender
+}
+
+async fn wait_until_done(exporter: &Exporter, id: u64) -> ExportJob {
+    for _ in 0..500 {
+        let job = exporter.job(id).expect("job exists");
+        if job.state != JobState::Running {
+            return job;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("export {id} did not finish");
+}
+
+async fn start(exporter: &Arc<Exporter>, params: Value) -> ExportJob {
+    exporter
+        .start(serde_json::from_value(params).expect("valid params"))
+        .await
+        .expect("export starts")
+}
+
+fn shard_files(dir: &Path, extension: &str) -> Vec<String> {
+    let mut files: Vec<_> = std::fs::read_dir(dir)
+        .expect("export dir")
+        .map(|entry| entry.expect("entry").path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == extension))
+        .map(|path| std::fs::read_to_string(path).expect("shard file"))
+        .collect();
+    files.sort();
+    files
+}
+
+#[tokio::test]
+async fn jsonl_export_covers_every_stored_block_once() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let config = ExportConfig {
+        dir: dir.path().to_path_buf(),
+        shards: 4,
+    };
+    let exporter = Exporter::initialize(&config, fake_db(7, 1)).expect("exporter");
+    let job = start(
+        &exporter,
+        json!({"start_slot": 1, "end_slot": 1000, "format": "jsonl"}),
+    )
+    .await;
+    assert_eq!(job.shards.len(), 4);
+    let job = wait_until_done(&exporter, job.id).await;
+    assert_eq!(job.state, JobState::Finished);
+    let mut slots: Vec<u64> = shard_files(&dir.path().join(format!("{:020}", job.id)), "jsonl")
+        .iter()
+        .flat_map(|file| file.lines().map(str::to_string).collect::<Vec<_>>())
+        .map(|line| {
+            serde_json::from_str::<Value>(&line).expect("json line")["block_no"]
+                .as_u64()
+                .expect("block_no")
+        })
+        .collect();
+    slots.sort();
+    let expected: Vec<u64> = (1..=1000).filter(|slot| slot % 7 != 0).collect();
+    assert_eq!(slots, expected);
+    assert_eq!(
+        job.shards.iter().map(|shard| shard.blocks).sum::<u64>(),
+        expected.len() as u64
+    );
+}
+
+#[tokio::test]
+async fn csv_export_writes_one_header_per_shard() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let config = ExportConfig {
+        dir: dir.path().to_path_buf(),
+        shards: 4,
+    };
+    let exporter = Exporter::initialize(&config, fake_db(u64::MAX, 1)).expect("exporter");
+    let job = start(
+        &exporter,
+        json!({"start_slot": 10, "end_slot": 12, "format": "csv", "shards": 8}),
+    )
+    .await;
+    // Never more shards than slots
+    assert_eq!(job.shards.len(), 3);
+    let job = wait_until_done(&exporter, job.id).await;
+    assert_eq!(job.state, JobState::Finished);
+    for file in shard_files(&dir.path().join(format!("{:020}", job.id)), "csv") {
+        let lines: Vec<&str> = file.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("slot,block_no,block_time,tx_id"));
+    }
+}
+
+#[tokio::test]
+async fn finished_exports_are_loaded_after_a_restart() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let config = ExportConfig {
+        dir: dir.path().to_path_buf(),
+        shards: 2,
+    };
+    let exporter = Exporter::initialize(&config, fake_db(3, 1)).expect("exporter");
+    let job = start(
+        &exporter,
+        json!({"start_slot": 1, "end_slot": 300, "format": "jsonl"}),
+    )
+    .await;
+    let job = wait_until_done(&exporter, job.id).await;
+    let restarted = Exporter::initialize(&config, fake_db(3, job.id + 1)).expect("exporter");
+    let reloaded = restarted.job(job.id).expect("job is reloaded");
+    assert_eq!(reloaded.state, JobState::Finished);
+    assert_eq!(reloaded.shards.len(), 2);
+}
+
+#[tokio::test]
+async fn cancelled_exports_stop() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let config = ExportConfig {
+        dir: dir.path().to_path_buf(),
+        shards: 2,
+    };
+    let exporter = Exporter::initialize(&config, fake_db(3, 1)).expect("exporter");
+    let job = start(
+        &exporter,
+        json!({"start_slot": 1, "end_slot": 1_000_000, "format": "jsonl"}),
+    )
+    .await;
+    let cancelled = exporter.cancel(job.id).expect("job exists");
+    assert_eq!(cancelled.state, JobState::Cancelled);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let job = exporter.job(job.id).expect("job exists");
+    assert_eq!(job.state, JobState::Cancelled);
+    assert!(job.shards.iter().all(|shard| shard.next_slot.is_some()));
+    // A cancelled export is not resumed
+    assert!(exporter.resume(job.id).is_err());
+}
+
+#[tokio::test]
+async fn inverted_ranges_are_rejected() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let config = ExportConfig {
+        dir: dir.path().to_path_buf(),
+        shards: 2,
+    };
+    let exporter = Exporter::initialize(&config, fake_db(3, 1)).expect("exporter");
+    let params = serde_json::from_value(json!({"start_slot": 5, "end_slot": 1, "format": "csv"}))
+        .expect("valid params");
+    assert!(exporter.start(params).await.is_err());
+    assert!(exporter.jobs().is_empty());
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use common::block;
+use solana_agg::util::{ProtocolMessage, Response};
+use solana_agg::Builder;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+
+async fn latest_block_no(sender: &UnboundedSender<ProtocolMessage>) -> Option<u64> {
+    match ProtocolMessage::ask(sender, ProtocolMessage::FetchStatus).await {
+        Ok(Response::Status(status)) => status.latest_block_no,
+        other => panic!("unexpected response {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn a_read_only_instance_keeps_up_with_the_blocks_the_primary_writes() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let db_path = dir.path().join("db").to_string_lossy().into_owned();
+    let (primary, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(db_path.clone())
+        .db_receiver(receiver)
+        .build()
+        .expect("primary opens");
+    tokio::spawn(async move { db.run().await });
+    primary
+        .send(ProtocolMessage::FinalizeBlock(1, block(10)))
+        .expect("primary running");
+    assert_eq!(latest_block_no(&primary).await, Some(1));
+
+    let (secondary, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(db_path)
+        .db_receiver(receiver)
+        .build_read_only(dir.path().join("secondary").to_string_lossy().into_owned())
+        .expect("secondary opens while the primary holds the lock");
+    tokio::spawn(async move { db.run().await });
+    assert_eq!(latest_block_no(&secondary).await, Some(1));
+
+    primary
+        .send(ProtocolMessage::FinalizeBlock(2, block(20)))
+        .expect("primary running");
+    assert_eq!(latest_block_no(&primary).await, Some(2));
+    // The block written after the secondary opened is served once it catches up
+    let caught_up = tokio::time::timeout(Duration::from_secs(10), async {
+        while latest_block_no(&secondary).await != Some(2) {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await;
+    assert!(caught_up.is_ok(), "the secondary never saw block 2");
+    match ProtocolMessage::ask(&secondary, |reply| {
+        ProtocolMessage::FetchBlockDetails(2, reply)
+    })
+    .await
+    {
+        Ok(Response::BlockDetails(block)) => assert_eq!(block.slot(), Some(20)),
+        other => panic!("unexpected response {other:?}"),
+    }
+}
@@ -0,0 +1,139 @@
+mod common;
+
+use common::block;
+use solana_agg::config::RecoveryConfig;
+use solana_agg::error::AggError;
+use solana_agg::recovery::{self, RecoveryMethod};
+use solana_agg::util::ProtocolMessage;
+use solana_agg::{snapshot, Builder};
+use std::path::Path;
+
+/// Finalizes the blocks into the db at the path and waits for the db to stop
+async fn store(path: &str, block_nos: std::ops::RangeInclusive<u64>) {
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(path.to_string())
+        .db_receiver(receiver)
+        .build()
+        .expect("db opens");
+    let db = tokio::spawn(async move {
+        db.run().await;
+        db
+    });
+    for block_no in block_nos {
+        sender
+            .send(ProtocolMessage::FinalizeBlock(
+                block_no,
+                block(block_no * 10),
+            ))
+            .expect("db running");
+    }
+    // The db stops once drained of the blocks
+    drop(sender);
+    drop(db.await.expect("db stops"));
+}
+
+/// Corrupts the db so it no longer opens, as a torn write of its CURRENT file would. The blocks
+/// are flushed to table files first, a repair only replays the log into the default column family
+fn corrupt(path: &str) {
+    let options = rocksdb::Options::default();
+    let column_families = rocksdb::DB::list_cf(&options, path).expect("column families");
+    let db = rocksdb::DB::open_cf(&options, path, &column_families).expect("db opens");
+    for name in &column_families {
+        db.flush_cf(db.cf_handle(name).expect("column family"))
+            .expect("flushed");
+    }
+    drop(db);
+    std::fs::write(Path::new(path).join("CURRENT"), b"MANIFEST-9").expect("written");
+}
+
+#[tokio::test]
+async fn a_clean_db_is_left_alone() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let path = dir.path().join("db").to_string_lossy().into_owned();
+    store(&path, 1..=1).await;
+    let report = recovery::ensure_openable(&path, &RecoveryConfig::default()).expect("opens");
+    assert!(report.is_none());
+}
+
+#[tokio::test]
+async fn a_corrupted_db_is_repaired_in_place_keeping_its_blocks() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let path = dir.path().join("db").to_string_lossy().into_owned();
+    store(&path, 1..=2).await;
+    corrupt(&path);
+
+    let report = recovery::ensure_openable(&path, &RecoveryConfig::default())
+        .expect("recovers")
+        .expect("the db was corrupted");
+    assert!(matches!(report.method, RecoveryMethod::Repaired));
+    assert_eq!(report.latest_block_no, Some(2));
+    // Repaired for good
+    let report = recovery::ensure_openable(&path, &RecoveryConfig::default()).expect("opens");
+    assert!(report.is_none());
+}
+
+#[tokio::test]
+async fn the_latest_checkpoint_is_restored_and_the_corrupted_db_kept_aside() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let path = dir.path().join("db").to_string_lossy().into_owned();
+    let checkpoints = dir.path().join("checkpoints");
+    std::fs::create_dir(&checkpoints).expect("checkpoint dir");
+    store(&path, 1..=1).await;
+    snapshot::create(&path, &checkpoints.join("older")).expect("checkpoint");
+    store(&path, 2..=2).await;
+    // Checkpoints are told apart by their modification time
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    snapshot::create(&path, &checkpoints.join("latest")).expect("checkpoint");
+    store(&path, 3..=3).await;
+    corrupt(&path);
+
+    let config = RecoveryConfig {
+        checkpoint_dir: Some(checkpoints.clone()),
+    };
+    let report = recovery::restore_latest_checkpoint(&path, &config).expect("restores");
+    match report.method {
+        RecoveryMethod::RestoredFromCheckpoint(checkpoint) => {
+            assert_eq!(checkpoint, checkpoints.join("latest"))
+        }
+        method => panic!("unexpected method {method:?}"),
+    }
+    // Blocks stored after the checkpoint are lost
+    assert_eq!(report.latest_block_no, Some(2));
+    let aside: Vec<String> = std::fs::read_dir(dir.path())
+        .expect("listed")
+        .map(|entry| {
+            entry
+                .expect("entry")
+                .file_name()
+                .to_string_lossy()
+                .into_owned()
+        })
+        .filter(|name| name.starts_with("db.corrupted-"))
+        .collect();
+    assert_eq!(aside.len(), 1);
+    let report = recovery::ensure_openable(&path, &config).expect("opens");
+    assert!(report.is_none());
+}
+
+#[tokio::test]
+async fn a_db_is_not_restored_without_a_checkpoint() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let path = dir.path().join("db").to_string_lossy().into_owned();
+    store(&path, 1..=1).await;
+    corrupt(&path);
+    let checkpoints = dir.path().join("checkpoints");
+    std::fs::create_dir(&checkpoints).expect("checkpoint dir");
+
+    for config in [
+        RecoveryConfig::default(),
+        RecoveryConfig {
+            checkpoint_dir: Some(checkpoints),
+        },
+    ] {
+        let result = recovery::restore_latest_checkpoint(&path, &config);
+        assert!(matches!(result, Err(AggError::RecoveryError(_))));
+    }
+    // The corrupted db stays where it was
+    assert!(Path::new(&path).join("CURRENT").exists());
+}
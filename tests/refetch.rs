@@ -0,0 +1,162 @@
+mod common;
+
+use common::key;
+use solana_agg::error::AggError;
+use solana_agg::plugin::{self, BlockPlugin, Verdict};
+use solana_agg::util::{
+    Block, BlockHeader, ProtocolMessage, RawBlock, RefetchedBlock, Response, TxRecord,
+};
+use solana_agg::Builder;
+use solana_program::hash::hash;
+use solana_program::message::Message;
+use solana_program::pubkey::Pubkey;
+use solana_program::system_instruction;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::Transaction;
+use solana_transaction_status::{
+    Encodable, EncodedTransactionWithStatusMeta, TransactionStatusMeta, UiTransactionEncoding,
+};
+use std::collections::BTreeMap;
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Slot whose blocks the plugin of this file rejects
+const REJECTED_SLOT: u64 = 11;
+
+struct RejectSlot;
+
+impl BlockPlugin for RejectSlot {
+    fn name(&self) -> &str {
+        "reject_slot"
+    }
+
+    fn on_block(&mut self, _block_no: u64, block: &mut Block) -> Verdict {
+        if block.slot() == Some(REJECTED_SLOT) {
+            return Verdict::Reject("rejected slot".to_string());
+        }
+        Verdict::Keep
+    }
+}
+
+fn header(slot: u64) -> BlockHeader {
+    BlockHeader {
+        slot,
+        blockhash: format!("hash-{}", slot),
+        block_time: Some(1_700_000_000),
+        transaction_count: Some(1),
+        ..Default::default()
+    }
+}
+
+/// A block of the slot storing the given post balances, as indexed while a bug was live
+fn stored_block(slot: u64, balances: &[(Pubkey, u64)]) -> Block {
+    let mut block = Block::default();
+    block.set_header(header(slot));
+    block.push_transaction(
+        hash(&slot.to_be_bytes()),
+        TxRecord::new(vec![], None).expect("record"),
+    );
+    block.set_account_map(
+        balances
+            .iter()
+            .map(|(account, balance)| (account.to_string(), *balance))
+            .collect::<BTreeMap<_, _>>(),
+    );
+    block
+}
+
+/// `key(1)` sends 100 lamports to `key(2)`
+fn transfer() -> EncodedTransactionWithStatusMeta {
+    let message = Message::new(
+        &[system_instruction::transfer(&key(1), &key(2), 100)],
+        Some(&key(1)),
+    );
+    let transaction = Transaction {
+        signatures: vec![Signature::default()],
+        message,
+    };
+    EncodedTransactionWithStatusMeta {
+        transaction: transaction.encode(UiTransactionEncoding::Base64),
+        meta: Some(
+            TransactionStatusMeta {
+                post_balances: vec![900, 100, 1],
+                ..TransactionStatusMeta::default()
+            }
+            .into(),
+        ),
+        version: None,
+    }
+}
+
+async fn balance(sender: &UnboundedSender<ProtocolMessage>, account: Pubkey) -> Option<u64> {
+    let response = ProtocolMessage::ask(sender, |reply| {
+        ProtocolMessage::FetchAccountSummary(account.to_string(), reply)
+    })
+    .await;
+    match response {
+        Ok(Response::AccountSummary(summary)) => summary.balance,
+        other => panic!("unexpected response {other:?}"),
+    }
+}
+
+async fn refetch(
+    sender: &UnboundedSender<ProtocolMessage>,
+    block_no: u64,
+    slot: u64,
+) -> Result<Response, AggError> {
+    let raw_block = RawBlock {
+        header: header(slot),
+        transactions: vec![transfer()],
+    };
+    ProtocolMessage::ask(sender, |reply| {
+        ProtocolMessage::RefetchBlock(block_no, Box::new(raw_block), reply)
+    })
+    .await
+}
+
+#[tokio::test]
+async fn a_refetched_block_behind_the_latest_block_replaces_its_state() {
+    plugin::register(RejectSlot).expect("registers");
+    let dir = tempfile::tempdir().expect("temp dir");
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(dir.path().join("db").to_string_lossy().into_owned())
+        .db_receiver(receiver)
+        .build()
+        .expect("db opens");
+    tokio::spawn(async move { db.run().await });
+
+    // Block 1 was indexed with a wrong balance for key(2), block 2 was stored after it
+    for (block_no, block) in [
+        (1, stored_block(10, &[(key(1), 900), (key(2), 999_999)])),
+        (2, stored_block(REJECTED_SLOT, &[(key(5), 5)])),
+    ] {
+        sender
+            .send(ProtocolMessage::FinalizeBlock(block_no, block))
+            .expect("db running");
+    }
+    assert_eq!(balance(&sender, key(2)).await, Some(999_999));
+
+    match refetch(&sender, 1, 10).await {
+        Ok(Response::RefetchedBlock(refetched)) => assert_eq!(
+            refetched,
+            RefetchedBlock {
+                slot: 10,
+                block_no: 1,
+                transactions: 1,
+                replaced: Some(1),
+            }
+        ),
+        other => panic!("unexpected response {other:?}"),
+    }
+    assert_eq!(balance(&sender, key(2)).await, Some(100));
+    assert_eq!(balance(&sender, key(1)).await, Some(900));
+    assert_eq!(balance(&sender, key(5)).await, Some(5));
+
+    // A block the plugins reject leaves the stored block in place
+    match refetch(&sender, 2, REJECTED_SLOT).await {
+        Err(AggError::PluginRejected(plugin, _)) => assert_eq!(plugin, "reject_slot"),
+        other => panic!("unexpected response {other:?}"),
+    }
+    assert_eq!(balance(&sender, key(5)).await, Some(5));
+    assert_eq!(balance(&sender, key(2)).await, Some(100));
+}
@@ -0,0 +1,198 @@
+mod common;
+
+use common::key;
+use solana_agg::config::{Commitment, ReorgConfig};
+use solana_agg::error::AggError;
+use solana_agg::reorg::{detect, ForkCheck, OrphanedBlock, Reorg};
+use solana_agg::util::{
+    ActiveAccountsStats, Block, BlockHeader, ProtocolMessage, Response, TxRecord,
+};
+use solana_agg::Builder;
+use solana_program::hash::hash;
+use solana_program::pubkey::Pubkey;
+use std::collections::BTreeMap;
+use tokio::sync::mpsc::UnboundedSender;
+
+fn block(slot: u64, blockhash: &str, previous_blockhash: &str, parent_slot: u64) -> Block {
+    let mut block = Block::default();
+    block.set_header(BlockHeader {
+        slot,
+        blockhash: blockhash.to_string(),
+        previous_blockhash: Some(previous_blockhash.to_string()),
+        parent_slot: Some(parent_slot),
+        ..Default::default()
+    });
+    block
+}
+
+/// Blocks 9 to 11 of slots 90, 100 and 110
+fn stored_chain() -> BTreeMap<u64, Block> {
+    BTreeMap::from([
+        (9, block(90, "h9", "h8", 80)),
+        (10, block(100, "h10", "h9", 90)),
+        (11, block(110, "h11", "h10", 100)),
+    ])
+}
+
+fn check(stored: &BTreeMap<u64, Block>, block_no: u64, new: &Block, max_depth: u64) -> ForkCheck {
+    detect(block_no, new, max_depth, |block_no| {
+        stored.get(&block_no).cloned()
+    })
+}
+
+#[test]
+fn blocks_chaining_onto_the_stored_chain_extend_it() {
+    let stored = stored_chain();
+    assert_eq!(
+        check(&stored, 12, &block(120, "h12", "h11", 110), 32),
+        ForkCheck::Extends
+    );
+    // A block delivered twice does not reorganize anything
+    assert_eq!(
+        check(&stored, 11, &block(110, "h11", "h10", 100), 32),
+        ForkCheck::Extends
+    );
+    // Nothing is stored below a block far ahead of the chain
+    assert_eq!(
+        check(&stored, 20, &block(200, "h20", "h19", 190), 32),
+        ForkCheck::Extends
+    );
+}
+
+#[test]
+fn a_block_replacing_the_stored_block_at_its_height_orphans_it_and_its_descendants() {
+    let stored = stored_chain();
+    assert_eq!(
+        check(&stored, 10, &block(101, "h10'", "h9", 90), 32),
+        ForkCheck::Reorg(Reorg {
+            orphaned: vec![
+                OrphanedBlock {
+                    block_no: 10,
+                    slot: 100
+                },
+                OrphanedBlock {
+                    block_no: 11,
+                    slot: 110
+                },
+            ],
+            canonical_parent_slot: None,
+        })
+    );
+}
+
+#[test]
+fn a_block_not_chaining_onto_its_stored_parent_orphans_the_parent() {
+    let mut stored = stored_chain();
+    // The block after the new one arrived first, it chains onto the new block
+    stored.insert(13, block(130, "h13'", "h12'", 120));
+    assert_eq!(
+        check(&stored, 12, &block(120, "h12'", "h11'", 105), 32),
+        ForkCheck::Reorg(Reorg {
+            orphaned: vec![OrphanedBlock {
+                block_no: 11,
+                slot: 110
+            }],
+            canonical_parent_slot: Some(105),
+        })
+    );
+}
+
+#[test]
+fn reorgs_deeper_than_the_limit_are_not_rolled_back() {
+    let stored = stored_chain();
+    let new = block(101, "h10'", "h9", 90);
+    assert_eq!(check(&stored, 10, &new, 1), ForkCheck::TooDeep(2));
+    assert!(matches!(check(&stored, 10, &new, 2), ForkCheck::Reorg(_)));
+}
+
+/// A block of the slot whose only transaction is paid by the fee payer
+fn paid_block(slot: u64, blockhash: &str, previous_blockhash: &str, fee_payer: Pubkey) -> Block {
+    let mut block = Block::default();
+    block.set_header(BlockHeader {
+        slot,
+        blockhash: blockhash.to_string(),
+        block_time: Some(1_717_200_000),
+        previous_blockhash: Some(previous_blockhash.to_string()),
+        parent_slot: Some(slot - 10),
+        transaction_count: Some(1),
+    });
+    let record = TxRecord::new(vec![], None)
+        .expect("record")
+        .with_accounts(vec![fee_payer.to_string()]);
+    block.push_transaction(hash(blockhash.as_bytes()), record);
+    block
+}
+
+async fn active_accounts(sender: &UnboundedSender<ProtocolMessage>) -> ActiveAccountsStats {
+    match ProtocolMessage::ask(sender, |reply| {
+        ProtocolMessage::FetchActiveAccounts(1, reply)
+    })
+    .await
+    {
+        Ok(Response::ActiveAccounts(mut days)) => days.pop().expect("one day"),
+        other => panic!("unexpected response {other:?}"),
+    }
+}
+
+async fn block_at_slot(sender: &UnboundedSender<ProtocolMessage>, slot: u64) -> Option<Block> {
+    match ProtocolMessage::ask(sender, |reply| {
+        ProtocolMessage::FetchBlockAtSlot(slot, reply)
+    })
+    .await
+    {
+        Ok(Response::BlockDetails(block)) => Some(block),
+        Err(AggError::BlockNotFound) => None,
+        other => panic!("unexpected response {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn a_reorg_rolls_back_the_orphaned_blocks_and_their_active_accounts() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(dir.path().join("db").to_string_lossy().into_owned())
+        .db_receiver(receiver)
+        .build()
+        .expect("db opens");
+    // Block 9 is settled once block 11 is stored, blocks 10 and 11 may still be rolled back
+    db.set_reorg_handling(Commitment::Confirmed, ReorgConfig { max_depth: 2 }, None);
+    tokio::spawn(async move { db.run().await });
+    for (block_no, block) in [
+        (9, paid_block(90, "h9", "h8", key(9))),
+        (10, paid_block(100, "h10", "h9", key(10))),
+        (11, paid_block(110, "h11", "h10", key(11))),
+    ] {
+        sender
+            .send(ProtocolMessage::FinalizeBlock(block_no, block))
+            .expect("db running");
+    }
+    let before = active_accounts(&sender).await;
+    assert_eq!((before.blocks, before.fee_payers), (3, 3));
+
+    // Block 10 of another fork orphans the stored blocks 10 and 11
+    sender
+        .send(ProtocolMessage::FinalizeBlock(
+            10,
+            paid_block(101, "h10'", "h9", key(12)),
+        ))
+        .expect("db running");
+    assert!(block_at_slot(&sender, 100).await.is_none());
+    assert!(block_at_slot(&sender, 110).await.is_none());
+    assert_eq!(
+        block_at_slot(&sender, 101)
+            .await
+            .and_then(|block| block.blockhash().map(str::to_string)),
+        Some("h10'".to_string())
+    );
+    let after = active_accounts(&sender).await;
+    assert_eq!((after.blocks, after.fee_payers), (2, 2));
+    match ProtocolMessage::ask(&sender, ProtocolMessage::FetchStatus).await {
+        Ok(Response::Status(status)) => {
+            assert_eq!(status.chain.reorgs, 1);
+            assert_eq!(status.chain.rolled_back_blocks, 2);
+            assert_eq!(status.latest_block_no, Some(10));
+        }
+        other => panic!("unexpected response {other:?}"),
+    }
+}
@@ -0,0 +1,117 @@
+use serde_json::Value;
+use solana_agg::parser::Parser;
+use solana_agg::util::{
+    Block, BlockHeader, JobState, JobTask, ProtocolMessage, RawBlock, ReparseProgress, Response,
+    TxRecord,
+};
+use solana_agg::Builder;
+use solana_transaction_status::UiConfirmedBlock;
+use std::path::Path;
+use std::time::Duration;
+
+fn raw_block() -> RawBlock {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/system_transfers.json");
+    let fetched: UiConfirmedBlock =
+        serde_json::from_slice(&std::fs::read(path).expect("fixture")).expect("fixture parses");
+    RawBlock {
+        header: BlockHeader {
+            slot: fetched.parent_slot + 1,
+            blockhash: fetched.blockhash.clone(),
+            block_time: fetched.block_time,
+            previous_blockhash: Some(fetched.previous_blockhash.clone()),
+            parent_slot: Some(fetched.parent_slot),
+            transaction_count: fetched.transactions.as_ref().map(|txs| txs.len() as u64),
+        },
+        transactions: fetched.transactions.unwrap_or_default(),
+    }
+}
+
+/// The block as an older parser stored it, its transactions without any instruction
+fn stale_block(parsed: &Block) -> Block {
+    let stale_record =
+        serde_json::to_value(TxRecord::new(vec![], None).expect("record")).expect("serializes");
+    let mut block = serde_json::to_value(parsed).expect("serializes");
+    for record in block["tx_map"]
+        .as_object_mut()
+        .expect("transactions")
+        .values_mut()
+    {
+        *record = stale_record.clone();
+    }
+    serde_json::from_value(block).expect("deserializes")
+}
+
+#[tokio::test]
+async fn a_reparse_job_upgrades_the_stored_block_from_its_archived_raw_block() {
+    let raw_block = raw_block();
+    let parsed = Parser::parse_chunk(raw_block.header.clone(), &raw_block.transactions)
+        .expect("fixture parses");
+    let expected: Value = serde_json::to_value(&parsed).expect("serializes")["tx_map"].clone();
+    let stale = stale_block(&parsed);
+    assert_ne!(
+        serde_json::to_value(&stale).expect("serializes")["tx_map"],
+        expected
+    );
+
+    let dir = tempfile::tempdir().expect("temp dir");
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(dir.path().join("db").to_string_lossy().into_owned())
+        .db_receiver(receiver)
+        .build()
+        .expect("db opens");
+    tokio::spawn(async move { db.run().await });
+    sender
+        .send(ProtocolMessage::FinalizeBlock(1, stale))
+        .expect("db running");
+    sender
+        .send(ProtocolMessage::ArchiveRawBlock(1, Box::new(raw_block)))
+        .expect("db running");
+
+    let id = match ProtocolMessage::ask(&sender, |reply| {
+        ProtocolMessage::StartJob(JobTask::Reindex(ReparseProgress::new(1, 1)), reply)
+    })
+    .await
+    {
+        Ok(Response::Job(Some(job))) => job.id,
+        other => panic!("unexpected response {other:?}"),
+    };
+    let job = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            match ProtocolMessage::ask(&sender, |reply| ProtocolMessage::FetchJob(id, reply)).await
+            {
+                Ok(Response::Job(Some(job))) if job.state != JobState::Running => break job,
+                Ok(Response::Job(Some(_))) => {}
+                other => panic!("unexpected response {other:?}"),
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("the job finishes");
+    assert_eq!(job.state, JobState::Finished);
+    match job.task {
+        JobTask::Reindex(progress) => {
+            assert_eq!(progress.upgraded_blocks, 1);
+            assert_eq!(
+                progress.upgraded_transactions,
+                parsed.transactions().count() as u64
+            );
+            assert_eq!(progress.missing_blocks, 0);
+            assert_eq!(progress.failed_blocks, 0);
+        }
+        task => panic!("unexpected task {task:?}"),
+    }
+
+    match ProtocolMessage::ask(&sender, |reply| {
+        ProtocolMessage::FetchBlockDetails(1, reply)
+    })
+    .await
+    {
+        Ok(Response::BlockDetails(block)) => assert_eq!(
+            serde_json::to_value(&block).expect("serializes")["tx_map"],
+            expected
+        ),
+        other => panic!("unexpected response {other:?}"),
+    }
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use common::block;
+use futures_util::SinkExt;
+use solana_agg::replication::{Follower, ReplicationMessage};
+use solana_agg::util::{ProtocolMessage, Response};
+use solana_agg::Builder;
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tokio_tungstenite::tungstenite::handshake::server::{
+    Callback, ErrorResponse, Request, Response as HandshakeResponse,
+};
+use tokio_tungstenite::tungstenite::Message;
+
+const FAILOVER_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Keeps the uri of the handshake request of the follower
+struct RequestedUri<'a>(&'a mut String);
+
+impl Callback for RequestedUri<'_> {
+    fn on_request(
+        self,
+        request: &Request,
+        response: HandshakeResponse,
+    ) -> Result<HandshakeResponse, ErrorResponse> {
+        *self.0 = request.uri().to_string();
+        Ok(response)
+    }
+}
+
+/// A primary streaming `blocks` then heartbeats for `alive`, then going silent without closing
+/// the stream. Sends the uri the follower requested and when the primary went silent.
+async fn fake_primary(
+    blocks: Vec<(u64, u64)>,
+    alive: Duration,
+) -> (String, oneshot::Receiver<(String, Instant)>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("binds");
+    let url = format!(
+        "ws://{}/block_stream",
+        listener.local_addr().expect("bound")
+    );
+    let (sender, receiver) = oneshot::channel();
+    tokio::spawn(async move {
+        let (tcp, _) = listener.accept().await.expect("follower connects");
+        let mut uri = String::new();
+        let mut stream = tokio_tungstenite::accept_hdr_async(tcp, RequestedUri(&mut uri))
+            .await
+            .expect("handshake");
+        for (block_no, slot) in blocks {
+            let message = ReplicationMessage::Block(block_no, Box::new(block(slot)));
+            let text = serde_json::to_string(&message).expect("serializes");
+            stream.send(Message::Text(text)).await.expect("sent");
+        }
+        let heartbeats = Instant::now();
+        while heartbeats.elapsed() < alive {
+            let text = serde_json::to_string(&ReplicationMessage::Heartbeat).expect("serializes");
+            stream.send(Message::Text(text)).await.expect("sent");
+            tokio::time::sleep(FAILOVER_TIMEOUT / 5).await;
+        }
+        let _ = sender.send((uri, Instant::now()));
+        // Silent but still connected, as a hung primary
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        drop(stream);
+    });
+    (url, receiver)
+}
+
+#[tokio::test]
+async fn a_standby_follows_the_primary_from_its_last_block_and_takes_over_once_it_is_silent() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(dir.path().join("db").to_string_lossy().into_owned())
+        .db_receiver(receiver)
+        .build()
+        .expect("db opens");
+    tokio::spawn(async move { db.run().await });
+    // Stored before the standby last went down
+    sender
+        .send(ProtocolMessage::FinalizeBlock(1, block(10)))
+        .expect("db running");
+
+    // Heartbeats keep the standby following for longer than the failover timeout
+    let (url, primary) = fake_primary(vec![(2, 20), (3, 30)], FAILOVER_TIMEOUT * 3).await;
+    let mut follower = Follower::initialize(url, sender.clone(), FAILOVER_TIMEOUT);
+    let resume_slot = tokio::time::timeout(Duration::from_secs(30), follower.run())
+        .await
+        .expect("the standby takes over");
+    let (uri, silent_since) = primary.await.expect("the primary streamed");
+    assert!(silent_since.elapsed() >= FAILOVER_TIMEOUT);
+
+    assert_eq!(uri, "/block_stream?block_no=2");
+    assert_eq!(resume_slot, Some(30));
+    match ProtocolMessage::ask(&sender, ProtocolMessage::FetchStatus).await {
+        Ok(Response::Status(status)) => assert_eq!(status.latest_block_no, Some(3)),
+        other => panic!("unexpected response {other:?}"),
+    }
+}
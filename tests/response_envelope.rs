@@ -0,0 +1,155 @@
+use actix_web::{test, web, App, HttpResponse};
+use serde_json::{json, Value};
+use solana_agg::envelope::{Envelope, ResponseEnvelope, SlotTracker};
+use solana_agg::error::{AggError, ErrorBody};
+use std::sync::Arc;
+
+fn app_tracker() -> Arc<SlotTracker> {
+    let tracker = Arc::new(SlotTracker::default());
+    tracker.set_latest_indexed(120);
+    tracker.set_finalized(600);
+    tracker
+}
+
+#[actix_web::test]
+async fn json_responses_are_wrapped_with_slot_context() {
+    let app = test::init_service(App::new().wrap(Envelope(app_tracker())).route(
+        "/",
+        web::get().to(|| async { HttpResponse::Ok().json(vec![1, 2, 3]) }),
+    ))
+    .await;
+    let response: ResponseEnvelope<Vec<u64>> =
+        test::call_and_read_body_json(&app, test::TestRequest::get().uri("/").to_request()).await;
+    assert_eq!(response.data, vec![1, 2, 3]);
+    assert_eq!(response.slot_context.latest_indexed, Some(120));
+    assert_eq!(response.slot_context.finalized, Some(600));
+}
+
+#[actix_web::test]
+async fn error_responses_keep_their_status() {
+    let app = test::init_service(App::new().wrap(Envelope(app_tracker())).route(
+        "/",
+        web::get().to(|| async { HttpResponse::NotFound().json("Block not found") }),
+    ))
+    .await;
+    let response = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+    assert_eq!(response.status(), 404);
+    let body: ResponseEnvelope<String> = test::read_body_json(response).await;
+    assert_eq!(body.data, "Block not found");
+}
+
+#[actix_web::test]
+async fn error_bodies_are_not_wrapped() {
+    let app = test::init_service(App::new().wrap(Envelope(app_tracker())).route(
+        "/",
+        web::get().to(|| async { Err::<HttpResponse, AggError>(AggError::TxNotFound) }),
+    ))
+    .await;
+    let response = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+    assert_eq!(response.status(), 404);
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(
+        body,
+        json!({"error": {"code": "tx_not_found", "message": "Transaction Not Found"}})
+    );
+}
+
+#[actix_web::test]
+async fn unknown_slots_are_null() {
+    let app = test::init_service(
+        App::new()
+            .wrap(Envelope(Arc::new(SlotTracker::default())))
+            .route(
+                "/",
+                web::get().to(|| async { HttpResponse::Ok().json(true) }),
+            ),
+    )
+    .await;
+    let response: ResponseEnvelope<bool> =
+        test::call_and_read_body_json(&app, test::TestRequest::get().uri("/").to_request()).await;
+    assert!(response.slot_context.latest_indexed.is_none());
+    assert!(response.slot_context.finalized.is_none());
+}
+
+#[actix_web::test]
+async fn other_responses_pass_through() {
+    let app = test::init_service(App::new().wrap(Envelope(app_tracker())).route(
+        "/",
+        web::get().to(|| async { HttpResponse::Ok().body("agg_tps 1") }),
+    ))
+    .await;
+    let body = test::call_and_read_body(&app, test::TestRequest::get().uri("/").to_request()).await;
+    assert_eq!(body, "agg_tps 1");
+}
+
+async fn balance() -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "as_of_slot": 120,
+        "balance": 1_500_000_000u64,
+        "account_map": {"Acc_1": 2_000_000_000u64},
+    }))
+}
+
+#[actix_web::test]
+async fn default_format_is_lamports_and_snake_case() {
+    let app = test::init_service(
+        App::new()
+            .wrap(Envelope(app_tracker()))
+            .route("/", web::get().to(balance)),
+    )
+    .await;
+    let response: Value =
+        test::call_and_read_body_json(&app, test::TestRequest::get().uri("/").to_request()).await;
+    assert_eq!(response["data"]["balance"], json!(1_500_000_000u64));
+    assert_eq!(response["data"]["as_of_slot"], json!(120));
+    assert_eq!(response["slot_context"]["latest_indexed"], json!(120));
+}
+
+#[actix_web::test]
+async fn sol_units_convert_lamport_fields_only() {
+    let app = test::init_service(
+        App::new()
+            .wrap(Envelope(app_tracker()))
+            .route("/", web::get().to(balance)),
+    )
+    .await;
+    let request = test::TestRequest::get().uri("/?units=sol").to_request();
+    let response: Value = test::call_and_read_body_json(&app, request).await;
+    assert_eq!(response["data"]["balance"], json!(1.5));
+    assert_eq!(response["data"]["account_map"]["Acc_1"], json!(2.0));
+    assert_eq!(response["data"]["as_of_slot"], json!(120));
+}
+
+#[actix_web::test]
+async fn camel_case_renames_fields_but_not_data_keys() {
+    let app = test::init_service(
+        App::new()
+            .wrap(Envelope(app_tracker()))
+            .route("/", web::get().to(balance)),
+    )
+    .await;
+    let request = test::TestRequest::get().uri("/?case=camel").to_request();
+    let response: Value = test::call_and_read_body_json(&app, request).await;
+    assert_eq!(response["data"]["asOfSlot"], json!(120));
+    assert_eq!(
+        response["data"]["accountMap"]["Acc_1"],
+        json!(2_000_000_000u64)
+    );
+    assert_eq!(response["slotContext"]["latestIndexed"], json!(120));
+    assert!(response.get("tookMs").is_some());
+}
+
+#[actix_web::test]
+async fn unknown_formats_are_rejected() {
+    let app = test::init_service(
+        App::new()
+            .wrap(Envelope(app_tracker()))
+            .route("/", web::get().to(balance)),
+    )
+    .await;
+    let request = test::TestRequest::get().uri("/?units=btc").to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), 400);
+    let body: ErrorBody = test::read_body_json(response).await;
+    assert_eq!(body.error.code, "bad_request");
+}
@@ -0,0 +1,83 @@
+use solana_agg::error::AggError;
+use solana_agg::retry::{Failure, RetryPolicy};
+use solana_agg::util::{DeadLetter, FailureStage};
+use std::cell::Cell;
+use std::io;
+use std::time::Duration;
+
+const POLICY: RetryPolicy = RetryPolicy {
+    max_attempts: 3,
+    initial_backoff: Duration::from_millis(1),
+    max_backoff: Duration::from_millis(2),
+};
+
+fn timed_out() -> AggError {
+    AggError::ServerError(io::Error::new(io::ErrorKind::TimedOut, "timed out"))
+}
+
+#[test]
+fn errors_are_classified_as_transient_or_permanent() {
+    assert!(timed_out().is_transient());
+    assert!(AggError::PubsubError("closed".to_string()).is_transient());
+    assert!(!AggError::ConfigError("invalid".to_string()).is_transient());
+    assert!(
+        !AggError::ServerError(io::Error::new(io::ErrorKind::NotFound, "missing")).is_transient()
+    );
+}
+
+#[test]
+fn permanent_errors_are_not_retried() {
+    let attempts = Cell::new(0);
+    let failure = POLICY
+        .run_blocking(FailureStage::Write, || -> Result<(), AggError> {
+            attempts.set(attempts.get() + 1);
+            Err(AggError::ConfigError("invalid".to_string()))
+        })
+        .unwrap_err();
+    assert_eq!(attempts.get(), 1);
+    assert_eq!(failure.attempts, 1);
+}
+
+#[tokio::test]
+async fn transient_errors_are_retried_until_the_attempts_run_out() {
+    let attempts = Cell::new(0);
+    let failure = POLICY
+        .run(FailureStage::Fetch, || {
+            attempts.set(attempts.get() + 1);
+            async { Err::<(), _>(timed_out()) }
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(failure.attempts, 3);
+    assert_eq!(attempts.get(), 3);
+
+    let attempts = Cell::new(0);
+    let value = POLICY
+        .run(FailureStage::Fetch, || {
+            attempts.set(attempts.get() + 1);
+            let result = if attempts.get() < 2 {
+                Err(timed_out())
+            } else {
+                Ok(attempts.get())
+            };
+            async move { result }
+        })
+        .await
+        .expect("succeeds on the second attempt");
+    assert_eq!(value, 2);
+}
+
+#[test]
+fn dead_letters_record_the_failure() {
+    let failure = Failure {
+        error: timed_out(),
+        attempts: 4,
+    };
+    let letter = DeadLetter::new(FailureStage::Fetch, Some(42), None, &failure);
+    assert_eq!(letter.slot, Some(42));
+    assert_eq!(letter.code, failure.error.code());
+    assert!(letter.transient);
+    assert_eq!(letter.attempts, 4);
+    let json = serde_json::to_value(&letter).expect("serializes");
+    assert_eq!(json["stage"], "fetch");
+}
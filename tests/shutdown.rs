@@ -0,0 +1,139 @@
+use futures_util::future::BoxFuture;
+use solana_agg::fanout::{FanOut, Overflow};
+use solana_agg::handler::Handler;
+use solana_agg::metrics::WORKER_RESTARTS;
+use solana_agg::shutdown::{Shutdown, ShutdownCoordinator, ShutdownStage, Worker};
+use solana_agg::util::{Block, Channel, ProtocolMessage};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Panics on its first run, records when it is restarted and when it stops
+struct Flaky {
+    name: &'static str,
+    runs: u32,
+    restarted: Arc<Mutex<Vec<&'static str>>>,
+    stopped: Arc<Mutex<Vec<&'static str>>>,
+    shutdown: Option<Shutdown>,
+}
+
+impl Worker for Flaky {
+    fn set_shutdown(&mut self, shutdown: Shutdown) {
+        self.shutdown = Some(shutdown);
+    }
+
+    fn work(&mut self) -> BoxFuture<'_, ()> {
+        Box::pin(async move {
+            self.runs += 1;
+            if self.runs == 1 {
+                panic!("{} crashed", self.name);
+            }
+            self.restarted.lock().unwrap().push(self.name);
+            self.shutdown
+                .as_mut()
+                .expect("supervised")
+                .requested()
+                .await;
+            self.stopped.lock().unwrap().push(self.name);
+        })
+    }
+}
+
+#[tokio::test]
+async fn crashed_workers_are_restarted_and_stages_stop_in_order() {
+    let restarted = Arc::new(Mutex::new(Vec::new()));
+    let stopped = Arc::new(Mutex::new(Vec::new()));
+    let mut coordinator = ShutdownCoordinator::new(Duration::from_secs(5));
+    for (stage, name) in [
+        (ShutdownStage::Sinks, "flaky_sink"),
+        (ShutdownStage::Intake, "flaky_intake"),
+        (ShutdownStage::Handler, "flaky_handler"),
+    ] {
+        let worker = Flaky {
+            name,
+            runs: 0,
+            restarted: restarted.clone(),
+            stopped: stopped.clone(),
+            shutdown: None,
+        };
+        coordinator.supervise(stage, name, worker);
+    }
+    // Restarted after the first restart delay
+    while restarted.lock().unwrap().len() < 3 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    for name in ["flaky_sink", "flaky_intake", "flaky_handler"] {
+        assert_eq!(WORKER_RESTARTS.with_label_values(&[name]).get(), 1);
+    }
+    coordinator.shutdown().await;
+    assert_eq!(
+        *stopped.lock().unwrap(),
+        vec!["flaky_intake", "flaky_handler", "flaky_sink"]
+    );
+}
+
+#[tokio::test]
+async fn tasks_ignoring_the_signal_are_aborted_after_the_stage_timeout() {
+    let mut coordinator = ShutdownCoordinator::new(Duration::from_millis(50));
+    let stuck = tokio::spawn(std::future::pending::<()>());
+    let abort = stuck.abort_handle();
+    coordinator.track(ShutdownStage::Intake, "stuck", stuck);
+    coordinator.shutdown().await;
+    tokio::task::yield_now().await;
+    assert!(abort.is_finished());
+}
+
+#[tokio::test]
+async fn handler_and_fan_out_drain_what_they_hold_before_stopping() {
+    let handler_channel = Channel::<ProtocolMessage>::new();
+    let handler_sender = handler_channel.sender();
+    let db_channel = Channel::<ProtocolMessage>::new();
+    let fan_out_channel = Channel::<ProtocolMessage>::new();
+    let mut handler = Handler::initialize(handler_channel.receiver, db_channel.sender());
+    handler.set_fan_out(fan_out_channel.sender());
+    let mut fan_out = FanOut::initialize(fan_out_channel.receiver);
+    let mut db = fan_out.add_sink("db", 16, Overflow::Wait);
+
+    let mut coordinator = ShutdownCoordinator::new(Duration::from_secs(5));
+    coordinator.supervise(ShutdownStage::Handler, "handler", handler);
+    coordinator.supervise(ShutdownStage::FanOut, "fan_out", fan_out);
+    // A complete block, and the first of the two chunks of another
+    handler_sender
+        .send(ProtocolMessage::parsed_block(1, 1, 0, Block::default()))
+        .unwrap();
+    handler_sender
+        .send(ProtocolMessage::parsed_block(2, 2, 0, Block::default()))
+        .unwrap();
+    let shutdown = tokio::spawn(coordinator.shutdown());
+    // The last chunk is still parsed while the handler stops
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    handler_sender
+        .send(ProtocolMessage::parsed_block(2, 2, 1, Block::default()))
+        .unwrap();
+    shutdown.await.unwrap();
+
+    let mut block_nos = Vec::new();
+    while let Ok(ProtocolMessage::FinalizeBlock(block_no, _)) = db.try_recv() {
+        block_nos.push(block_no);
+    }
+    assert_eq!(block_nos, vec![1, 2]);
+}
+
+#[tokio::test]
+async fn incomplete_blocks_are_dropped_after_the_drain_timeout() {
+    let handler_channel = Channel::<ProtocolMessage>::new();
+    let handler_sender = handler_channel.sender();
+    let mut db_channel = Channel::<ProtocolMessage>::new();
+    let mut handler = Handler::initialize(handler_channel.receiver, db_channel.sender());
+    handler.set_drain_timeout(Duration::from_millis(50));
+
+    let mut coordinator = ShutdownCoordinator::new(Duration::from_secs(5));
+    coordinator.supervise(ShutdownStage::Handler, "handler", handler);
+    // Only the first of the two chunks of the block ever arrives
+    handler_sender
+        .send(ProtocolMessage::parsed_block(1, 2, 0, Block::default()))
+        .unwrap();
+    tokio::time::timeout(Duration::from_secs(1), coordinator.shutdown())
+        .await
+        .expect("the handler stops once the drain timeout passed");
+    assert!(db_channel.receiver.try_recv().is_err());
+}
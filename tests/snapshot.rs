@@ -0,0 +1,132 @@
+mod common;
+
+use common::block;
+use solana_agg::error::AggError;
+use solana_agg::snapshot::{self, SnapshotManifest, MANIFEST_FILE};
+use solana_agg::util::{ProtocolMessage, Response};
+use solana_agg::Builder;
+
+fn manifest() -> SnapshotManifest {
+    SnapshotManifest {
+        created_at: 1_700_000_000,
+        latest_block_no: Some(250_000),
+        genesis_hash: Some("EtWTRABZaYq6iMfeYKouRu166VU2xqa1wcaWoxPkrZBG".to_string()),
+        schema_version: 1,
+    }
+}
+
+#[test]
+fn snapshots_are_only_created_from_an_existing_db_into_a_new_directory() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let missing_db = dir.path().join("db");
+    let result = snapshot::create(missing_db.to_str().expect("path"), &dir.path().join("snap"));
+    assert!(matches!(result, Err(AggError::SnapshotError(_))));
+    assert!(!missing_db.exists());
+
+    let db = dir.path().join("db");
+    std::fs::create_dir(&db).expect("db dir");
+    std::fs::write(db.join("CURRENT"), b"MANIFEST-000001\n").expect("write");
+    let existing = dir.path().join("existing");
+    std::fs::create_dir(&existing).expect("snapshot dir");
+    let result = snapshot::create(db.to_str().expect("path"), &existing);
+    assert!(matches!(result, Err(AggError::SnapshotError(_))));
+}
+
+#[test]
+fn snapshots_are_only_restored_from_a_manifest_into_a_fresh_db_path() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let snap = dir.path().join("snap");
+    std::fs::create_dir(&snap).expect("snapshot dir");
+    let db = dir.path().join("db");
+    let result = snapshot::restore(&snap, db.to_str().expect("path"));
+    assert!(matches!(result, Err(AggError::SnapshotError(_))));
+
+    std::fs::write(
+        snap.join(MANIFEST_FILE),
+        serde_json::to_vec(&manifest()).expect("serialize"),
+    )
+    .expect("write");
+    assert_eq!(SnapshotManifest::read(&snap).expect("manifest"), manifest());
+    std::fs::create_dir(&db).expect("db dir");
+    std::fs::write(db.join("CURRENT"), b"MANIFEST-000001\n").expect("write");
+    let result = snapshot::restore(&snap, db.to_str().expect("path"));
+    assert!(matches!(result, Err(AggError::SnapshotError(_))));
+    // The db in the way is left untouched
+    assert!(db.join("CURRENT").exists());
+    assert!(!dir.path().join("db.restoring").exists());
+}
+
+/// This function stores blocks 1 to 3 in a db at the path and stops it
+async fn stored_db(path: &str) {
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(path.to_string())
+        .db_receiver(receiver)
+        .build()
+        .expect("db opens");
+    let db = tokio::spawn(async move { db.run().await });
+    for (block_no, slot) in [(1, 10), (2, 20), (3, 30)] {
+        sender
+            .send(ProtocolMessage::FinalizeBlock(block_no, block(slot)))
+            .expect("db running");
+    }
+    // The db stops once drained of the blocks
+    drop(sender);
+    db.await.expect("db stops");
+}
+
+#[tokio::test]
+async fn a_snapshot_restores_the_blocks_and_the_latest_block() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let db_path = dir.path().join("db").to_string_lossy().into_owned();
+    stored_db(&db_path).await;
+    let snap = dir.path().join("snap");
+    let created = snapshot::create(&db_path, &snap).expect("snapshot created");
+    assert_eq!(created.latest_block_no, Some(3));
+
+    let restored_path = dir.path().join("restored").to_string_lossy().into_owned();
+    let restored = snapshot::restore(&snap, &restored_path).expect("snapshot restored");
+    assert_eq!(restored, created);
+    assert!(!dir.path().join("restored.restoring").exists());
+
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(restored_path)
+        .db_receiver(receiver)
+        .build()
+        .expect("restored db opens");
+    tokio::spawn(async move { db.run().await });
+    match ProtocolMessage::ask(&sender, ProtocolMessage::FetchStatus).await {
+        Ok(Response::Status(status)) => assert_eq!(status.latest_block_no, Some(3)),
+        other => panic!("unexpected response {other:?}"),
+    }
+    match ProtocolMessage::ask(&sender, |reply| {
+        ProtocolMessage::FetchBlockAtSlot(20, reply)
+    })
+    .await
+    {
+        Ok(Response::BlockDetails(block)) => assert_eq!(block.slot(), Some(20)),
+        other => panic!("unexpected response {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn a_restored_db_not_matching_the_manifest_is_deleted() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let db_path = dir.path().join("db").to_string_lossy().into_owned();
+    stored_db(&db_path).await;
+    let snap = dir.path().join("snap");
+    let mut manifest = snapshot::create(&db_path, &snap).expect("snapshot created");
+    manifest.latest_block_no = Some(4);
+    std::fs::write(
+        snap.join(MANIFEST_FILE),
+        serde_json::to_vec(&manifest).expect("serialize"),
+    )
+    .expect("write");
+
+    let restored_path = dir.path().join("restored");
+    let result = snapshot::restore(&snap, restored_path.to_str().expect("path"));
+    assert!(matches!(result, Err(AggError::SnapshotError(_))));
+    assert!(!restored_path.exists());
+    assert!(!dir.path().join("restored.restoring").exists());
+}
@@ -0,0 +1,56 @@
+use solana_agg::spill::{estimated_size, SpillStore};
+use solana_transaction_status::{EncodedTransactionWithStatusMeta, UiConfirmedBlock};
+use std::path::Path;
+
+fn transactions() -> Vec<EncodedTransactionWithStatusMeta> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/system_transfers.json");
+    let block: UiConfirmedBlock =
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+    block.transactions.unwrap()
+}
+
+fn spilled_files(dir: &Path) -> usize {
+    std::fs::read_dir(dir).unwrap().count()
+}
+
+#[test]
+fn chunks_past_the_budget_are_spilled_and_read_back() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let txs = transactions();
+    let size = estimated_size(&txs);
+    assert!(size > 0);
+    let store = SpillStore::open(dir.path(), size).expect("open");
+
+    let in_memory = store.payload(1, 0, txs.clone());
+    assert!(!in_memory.is_spilled());
+    assert_eq!(store.in_memory(), size);
+
+    let spilled = store.payload(1, 1, txs.clone());
+    assert!(spilled.is_spilled());
+    assert_eq!(spilled_files(dir.path()), 1);
+    let expected = serde_json::to_value(&txs).unwrap();
+    let read_back = spilled.transactions().unwrap();
+    assert_eq!(serde_json::to_value(&*read_back).unwrap(), expected);
+
+    // Clones share the chunk, which is released with the last of them
+    let clone = spilled.clone();
+    drop(read_back);
+    drop(spilled);
+    assert_eq!(spilled_files(dir.path()), 1);
+    drop(clone);
+    assert_eq!(spilled_files(dir.path()), 0);
+
+    drop(in_memory);
+    assert_eq!(store.in_memory(), 0);
+    assert!(!store.payload(1, 2, txs).is_spilled());
+}
+
+#[test]
+fn chunks_spilled_by_a_previous_run_are_removed() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    std::fs::write(dir.path().join("7-0-0.chunk"), b"\x01").expect("write");
+    std::fs::write(dir.path().join("notes.txt"), b"kept").expect("write");
+    SpillStore::open(dir.path(), 1).expect("open");
+    assert!(!dir.path().join("7-0-0.chunk").exists());
+    assert!(dir.path().join("notes.txt").exists());
+}
@@ -0,0 +1,65 @@
+use solana_agg::cli::Cli;
+use solana_agg::config::{Config, SubscriberConfig};
+use std::time::Duration;
+use structopt::StructOpt;
+
+fn config(toml: &str) -> SubscriberConfig {
+    toml::from_str(toml).expect("valid subscriber config")
+}
+
+#[test]
+fn poll_mode_has_no_websocket() {
+    assert_eq!(config("").ws_url("http://127.0.0.1:8899"), None);
+    assert_eq!(
+        config("ws_url = \"ws://127.0.0.1:8900\"").ws_url("http://127.0.0.1:8899"),
+        None
+    );
+}
+
+#[test]
+fn websocket_url_is_derived_from_the_chain_url() {
+    let websocket = config("mode = \"websocket\"");
+    assert_eq!(
+        websocket.ws_url("http://127.0.0.1:8899").as_deref(),
+        Some("ws://127.0.0.1:8900")
+    );
+    assert_eq!(
+        websocket
+            .ws_url("https://api.mainnet-beta.solana.com")
+            .as_deref(),
+        Some("wss://api.mainnet-beta.solana.com")
+    );
+    assert_eq!(
+        websocket.ws_url("http://node:8899/rpc").as_deref(),
+        Some("ws://node:8900/rpc")
+    );
+    assert_eq!(
+        websocket.ws_url("http://node:88991").as_deref(),
+        Some("ws://node:88991")
+    );
+    assert_eq!(
+        websocket.ws_url("http://a:8899, http://b:8899").as_deref(),
+        Some("ws://a:8900")
+    );
+    let explicit = config("mode = \"websocket\"\nws_url = \"wss://pubsub.example\"");
+    assert_eq!(
+        explicit.ws_url("http://127.0.0.1:8899").as_deref(),
+        Some("wss://pubsub.example")
+    );
+}
+
+#[test]
+fn the_websocket_is_given_up_on_after_a_silent_timeout() {
+    assert_eq!(config("").ws_timeout(), Duration::from_secs(30));
+    assert_eq!(
+        config("mode = \"websocket\"\nws_timeout_secs = 5").ws_timeout(),
+        Duration::from_secs(5)
+    );
+
+    let dir = tempfile::tempdir().expect("temp dir");
+    let path = dir.path().join("config.toml");
+    std::fs::write(&path, "[subscriber]\nws_timeout_secs = 0").expect("config written");
+    let cli = Cli::from_iter(["solana-agg", "--config", path.to_str().expect("utf-8 path")]);
+    let error = Config::resolve(&cli).expect_err("a zero timeout is rejected");
+    assert!(error.to_string().contains("subscriber.ws_timeout_secs"));
+}
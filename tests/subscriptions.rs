@@ -0,0 +1,138 @@
+mod common;
+
+use common::key;
+use solana_agg::db_handler::RocksDb;
+use solana_agg::util::{ProtocolMessage, Response, Subscriptions, WebhookSubscription};
+use solana_agg::Builder;
+use tokio::sync::mpsc::{Receiver, UnboundedSender};
+use tokio::task::JoinHandle;
+
+fn open_db(path: &str) -> (UnboundedSender<ProtocolMessage>, JoinHandle<RocksDb>) {
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(path.to_string())
+        .db_receiver(receiver)
+        .build()
+        .expect("db opens");
+    let db = tokio::spawn(async move {
+        db.run().await;
+        db
+    });
+    (sender, db)
+}
+
+/// Stops the db once it is drained of the messages sent so far
+async fn restart(
+    path: &str,
+    sender: UnboundedSender<ProtocolMessage>,
+    db: JoinHandle<RocksDb>,
+) -> (UnboundedSender<ProtocolMessage>, JoinHandle<RocksDb>) {
+    drop(sender);
+    drop(db.await.expect("db stops"));
+    open_db(path)
+}
+
+async fn create_webhook(
+    sender: &UnboundedSender<ProtocolMessage>,
+    url: &str,
+) -> WebhookSubscription {
+    match ProtocolMessage::ask(sender, |reply| {
+        ProtocolMessage::CreateWebhook(key(1).to_string(), url.to_string(), Some(3600), reply)
+    })
+    .await
+    {
+        Ok(Response::Webhook(Some(webhook))) => webhook,
+        other => panic!("unexpected response {other:?}"),
+    }
+}
+
+async fn subscriptions(sender: &UnboundedSender<ProtocolMessage>) -> Subscriptions {
+    match ProtocolMessage::ask(sender, ProtocolMessage::FetchSubscriptions).await {
+        Ok(Response::Subscriptions(subscriptions)) => subscriptions,
+        other => panic!("unexpected response {other:?}"),
+    }
+}
+
+fn subscribe(sender: &UnboundedSender<ProtocolMessage>, token: &str) -> Receiver<ProtocolMessage> {
+    let (subscriber, events) = tokio::sync::mpsc::channel(16);
+    sender
+        .send(ProtocolMessage::SubscribeAccount(
+            key(2).to_string(),
+            None,
+            Some(token.to_string()),
+            subscriber,
+        ))
+        .expect("db running");
+    events
+}
+
+#[tokio::test]
+async fn webhooks_survive_a_restart_until_deleted() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let path = dir.path().join("db").to_string_lossy().into_owned();
+    let (sender, db) = open_db(&path);
+    let deleted = create_webhook(&sender, "http://127.0.0.1:1/deleted").await;
+    let kept = create_webhook(&sender, "http://127.0.0.1:1/kept").await;
+    assert!(!kept.secret.is_empty());
+    match ProtocolMessage::ask(&sender, |reply| {
+        ProtocolMessage::DeleteWebhook(deleted.id, reply)
+    })
+    .await
+    {
+        Ok(Response::Webhook(Some(webhook))) => assert_eq!(webhook.id, deleted.id),
+        other => panic!("unexpected response {other:?}"),
+    }
+
+    let (sender, _db) = restart(&path, sender, db).await;
+    let webhooks = subscriptions(&sender).await.webhooks;
+    assert_eq!(webhooks.len(), 1);
+    let webhook = &webhooks[0];
+    assert_eq!(
+        (webhook.id, webhook.url.as_str(), webhook.expires_at),
+        (kept.id, "http://127.0.0.1:1/kept", kept.expires_at)
+    );
+    // Listings never give the secret away
+    assert!(webhook.secret.is_empty());
+    // Ids keep increasing after the restart
+    let created = create_webhook(&sender, "http://127.0.0.1:1/new").await;
+    assert!(created.id > kept.id);
+}
+
+#[tokio::test]
+async fn an_account_stream_resumes_from_its_cursor_after_a_restart() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let path = dir.path().join("db").to_string_lossy().into_owned();
+    let (sender, db) = open_db(&path);
+    let _events = subscribe(&sender, "client");
+    for slot in [30, 20] {
+        sender
+            .send(ProtocolMessage::AckResumeCursor("client".to_string(), slot))
+            .expect("db running");
+    }
+    let cursors = subscriptions(&sender).await.resume_cursors;
+    assert_eq!(cursors.len(), 1);
+    // A late acknowledgement does not move the cursor back
+    assert_eq!(cursors[0].last_slot, Some(30));
+
+    let (sender, _db) = restart(&path, sender, db).await;
+    let cursors = subscriptions(&sender).await.resume_cursors;
+    assert_eq!(cursors.len(), 1);
+    let cursor = &cursors[0];
+    assert_eq!(
+        (
+            cursor.token.as_str(),
+            cursor.account.clone(),
+            cursor.last_slot
+        ),
+        ("client", key(2).to_string(), Some(30))
+    );
+    // Reconnecting without a slot replays from the cursor, and a new token starts live
+    let _resumed = subscribe(&sender, "client");
+    let _fresh = subscribe(&sender, "other");
+    let cursors = subscriptions(&sender).await.resume_cursors;
+    let last_slots: Vec<_> = cursors
+        .iter()
+        .map(|cursor| (cursor.token.as_str(), cursor.last_slot))
+        .collect();
+    assert_eq!(last_slots, vec![("client", Some(30)), ("other", None)]);
+}
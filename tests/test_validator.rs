@@ -0,0 +1,118 @@
+//! End-to-end tests against a local `solana-test-validator`, run with
+//! `cargo test --features test-validator --test test_validator`. They need the validator on the
+//! PATH and its default ports free.
+#![cfg(feature = "test-validator")]
+
+use solana_agg::envelope::ResponseEnvelope;
+use solana_agg::harness;
+use solana_agg::util::Status;
+use solana_agg::TxRecord;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+use tempfile::TempDir;
+
+const CHAIN_URL: &str = "http://127.0.0.1:8899";
+const API_PORT: u16 = 19944;
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A test validator and an aggregator ingesting from it, both killed when dropped
+struct Cluster {
+    validator: Child,
+    aggregator: Child,
+    _ledger: TempDir,
+    _db: TempDir,
+}
+
+impl Drop for Cluster {
+    fn drop(&mut self) {
+        let _ = self.aggregator.kill();
+        let _ = self.validator.kill();
+        let _ = self.aggregator.wait();
+        let _ = self.validator.wait();
+    }
+}
+
+fn api_url() -> String {
+    format!("http://127.0.0.1:{}", API_PORT)
+}
+
+async fn start_cluster() -> Cluster {
+    let ledger = TempDir::new().unwrap();
+    let validator = Command::new("solana-test-validator")
+        .arg("--ledger")
+        .arg(ledger.path())
+        .args(["--reset", "--quiet"])
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("solana-test-validator is not on the PATH");
+    let rpc = RpcClient::new(CHAIN_URL.to_string());
+    let deadline = tokio::time::Instant::now() + STARTUP_TIMEOUT;
+    while rpc.get_health().await.is_err() {
+        assert!(
+            tokio::time::Instant::now() < deadline,
+            "the test validator did not start"
+        );
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+
+    let db = TempDir::new().unwrap();
+    let aggregator = Command::new(env!("CARGO_BIN_EXE_solana-agg"))
+        .args(["--chain-url", CHAIN_URL])
+        .arg("--db-url")
+        .arg(db.path())
+        .args(["--port-no", &API_PORT.to_string()])
+        .spawn()
+        .unwrap();
+    let cluster = Cluster {
+        validator,
+        aggregator,
+        _ledger: ledger,
+        _db: db,
+    };
+    let http = reqwest::Client::new();
+    let status_url = format!("{}/status", api_url());
+    while !matches!(http.get(&status_url).send().await, Ok(response) if response.status().is_success())
+    {
+        assert!(
+            tokio::time::Instant::now() < deadline + STARTUP_TIMEOUT,
+            "the aggregator did not start"
+        );
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+    cluster
+}
+
+#[tokio::test]
+async fn transfers_to_a_test_validator_are_indexed_and_served() {
+    let _cluster = start_cluster().await;
+    let outcomes = harness::run(CHAIN_URL, &api_url(), 2, 150).await.unwrap();
+    assert!(harness::passed(&outcomes), "{:?}", outcomes);
+
+    let http = reqwest::Client::new();
+    for outcome in &outcomes {
+        let tx: ResponseEnvelope<TxRecord> = http
+            .get(format!("{}/tx_details/{}", api_url(), outcome.tx_id))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(tx.data.signatures(), std::slice::from_ref(&outcome.signature));
+        assert_eq!(tx.data.succeeded(), Some(true));
+        assert_eq!(tx.data.sol_transferred(), 0.001);
+        assert!(tx.slot_context.latest_indexed >= Some(outcome.slot));
+    }
+
+    let status: ResponseEnvelope<Status> = http
+        .get(format!("{}/status", api_url()))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(!status.data.read_only);
+    assert!(status.data.genesis_hash.is_some());
+}
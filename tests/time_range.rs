@@ -0,0 +1,149 @@
+use actix_web::{test, web, App};
+use serde_json::Value;
+use solana_agg::config::{Commitment, QueryConfig};
+use solana_agg::envelope::{Finality, SlotTracker};
+use solana_agg::server;
+use solana_agg::util::{Block, BlockHeader, ProtocolMessage, Response, TimeRange};
+use solana_agg::Builder;
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+
+/// 2023-11-14T22:13:20Z
+const T: i64 = 1_700_000_000;
+
+fn block(slot: u64, block_time: Option<i64>) -> Block {
+    let mut block = Block::default();
+    block.set_header(BlockHeader {
+        slot,
+        blockhash: format!("hash-{}", slot),
+        block_time,
+        ..Default::default()
+    });
+    block
+}
+
+async fn time_range(
+    sender: &UnboundedSender<ProtocolMessage>,
+    start_time: i64,
+    end_time: i64,
+) -> Option<TimeRange> {
+    match ProtocolMessage::ask(sender, |reply| {
+        ProtocolMessage::FetchTimeRange(start_time as u64, end_time as u64, reply)
+    })
+    .await
+    {
+        Ok(Response::TimeRange(range)) => range,
+        other => panic!("unexpected response {other:?}"),
+    }
+}
+
+/// A db holding a first block without a block time, which no neighbour gives one, followed by
+/// blocks 10 seconds apart from `T`
+async fn db(dir: &tempfile::TempDir) -> UnboundedSender<ProtocolMessage> {
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(dir.path().join("db").to_string_lossy().into_owned())
+        .db_receiver(receiver)
+        .build()
+        .expect("db opens");
+    tokio::spawn(async move { db.run().await });
+    let blocks = [
+        (1, block(10, None)),
+        (2, block(20, Some(T))),
+        (3, block(30, Some(T + 10))),
+        (4, block(40, Some(T + 20))),
+    ];
+    for (block_no, block) in blocks {
+        sender
+            .send(ProtocolMessage::FinalizeBlock(block_no, block))
+            .expect("db running");
+    }
+    match ProtocolMessage::ask(&sender, ProtocolMessage::FetchStatus).await {
+        Ok(Response::Status(status)) => assert_eq!(status.latest_block_no, Some(4)),
+        other => panic!("unexpected response {other:?}"),
+    }
+    sender
+}
+
+#[tokio::test]
+async fn windows_include_the_blocks_on_their_edges() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let sender = db(&dir).await;
+    let range = time_range(&sender, T, T + 10).await.expect("blocks 2 and 3");
+    assert_eq!((range.start_block_no, range.end_block_no), (2, 3));
+    assert_eq!((range.start_slot, range.end_slot), (20, 30));
+    let range = time_range(&sender, T + 10, T + 10).await.expect("block 3");
+    assert_eq!((range.start_block_no, range.end_block_no), (3, 3));
+    let range = time_range(&sender, T + 1, T + 1_000).await.expect("blocks 3 and 4");
+    assert_eq!((range.start_block_no, range.end_block_no), (3, 4));
+}
+
+#[tokio::test]
+async fn windows_without_a_block_are_empty() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let sender = db(&dir).await;
+    assert!(time_range(&sender, T + 11, T + 19).await.is_none());
+    assert!(time_range(&sender, T + 21, T + 1_000).await.is_none());
+    // Block 1 has no time, so it falls inside no window
+    assert!(time_range(&sender, 0, T - 1).await.is_none());
+    let range = time_range(&sender, 0, T + 1_000).await.expect("blocks 2 to 4");
+    assert_eq!((range.start_block_no, range.end_block_no), (2, 4));
+}
+
+#[actix_web::test]
+async fn block_range_takes_rfc3339_times() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let sender = db(&dir).await;
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(sender))
+            .app_data(web::Data::new(QueryConfig::default()))
+            .app_data(web::Data::new(Finality::new(
+                Commitment::Finalized,
+                Arc::new(SlotTracker::default()),
+            )))
+            .configure(server::configure),
+    )
+    .await;
+    let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+
+    let response = test::call_service(
+        &app,
+        get("/block_range?start_time=2023-11-14T22:13:20Z&end_time=2023-11-14T22:13:30Z"),
+    )
+    .await;
+    assert_eq!(response.status(), 200);
+    let blocks: Value = test::read_body_json(response).await;
+    let block_nos: Vec<&String> = blocks.as_object().expect("blocks by number").keys().collect();
+    assert_eq!(block_nos, ["2", "3"]);
+    assert_eq!(blocks["2"]["slot"], 20);
+
+    // Offsets other than UTC name the same instant
+    let response = test::call_service(
+        &app,
+        get("/block_range?start_time=2023-11-15T00:13:40%2B02:00&end_time=2023-11-15T00:13:40%2B02:00"),
+    )
+    .await;
+    let blocks: Value = test::read_body_json(response).await;
+    let block_nos: Vec<&String> = blocks.as_object().expect("blocks by number").keys().collect();
+    assert_eq!(block_nos, ["4"]);
+
+    let response = test::call_service(
+        &app,
+        get("/block_range?start_time=2023-11-14T23:00:00Z&end_time=2023-11-14T23:30:00Z"),
+    )
+    .await;
+    assert_eq!(response.status(), 200);
+    let blocks: Value = test::read_body_json(response).await;
+    assert_eq!(blocks, serde_json::json!({}));
+
+    for uri in [
+        "/block_range?start_time=1700000000&end_time=2023-11-14T22:13:30Z",
+        "/block_range?start_time=2023-11-14&end_time=2023-11-14T22:13:30Z",
+        "/block_range?start_time=2023-11-14T22:13:30Z&end_time=2023-11-14T22:13:20Z",
+        "/block_range?start_time=1969-12-31T23:59:59Z&end_time=2023-11-14T22:13:30Z",
+    ] {
+        let response = test::call_service(&app, get(uri)).await;
+        assert_eq!(response.status(), 400, "{uri}");
+    }
+}
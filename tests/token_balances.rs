@@ -0,0 +1,187 @@
+mod common;
+
+use common::{key, parse, MINT, TOKEN_PROGRAM};
+use serde_json::{json, Value};
+use solana_agg::util::{
+    Block, BlockHeader, Channel, MintBalance, ProtocolMessage, Response, TokenAccountBalance,
+    TokenBalance,
+};
+use solana_agg::Builder;
+use solana_program::instruction::{AccountMeta, Instruction};
+use solana_program::message::Message;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::Transaction;
+use solana_transaction_status::{Encodable, UiTransactionEncoding};
+
+fn token_instruction(data: Vec<u8>, accounts: &[Pubkey]) -> Instruction {
+    Instruction {
+        program_id: TOKEN_PROGRAM.parse().unwrap(),
+        accounts: accounts
+            .iter()
+            .map(|account| AccountMeta::new(*account, *account == key(1)))
+            .collect(),
+        data,
+    }
+}
+
+fn token_balance(account_index: u8, owner: Pubkey, amount: u64) -> Value {
+    json!({
+        "accountIndex": account_index,
+        "mint": MINT,
+        "uiTokenAmount": {
+            "uiAmount": amount as f64 / 1e6,
+            "decimals": 6,
+            "amount": amount.to_string(),
+            "uiAmountString": (amount as f64 / 1e6).to_string(),
+        },
+        "owner": owner.to_string(),
+        "programId": TOKEN_PROGRAM,
+    })
+}
+
+/// `key(1)` moves the 500 tokens of its account `key(2)` to `key(3)`, owned by `key(4)`, and
+/// closes `key(2)`, leaving it out of the post token balances
+fn transfer_and_close() -> Value {
+    let mut transfer = vec![3];
+    transfer.extend(500u64.to_le_bytes());
+    let message = Message::new(
+        &[
+            token_instruction(transfer, &[key(2), key(3), key(1)]),
+            token_instruction(vec![9], &[key(2), key(1), key(1)]),
+        ],
+        Some(&key(1)),
+    );
+    assert_eq!(&message.account_keys[..3], &[key(1), key(2), key(3)]);
+    let accounts = message.account_keys.len();
+    let transaction = Transaction {
+        signatures: vec![Signature::default(); message.header.num_required_signatures as usize],
+        message,
+    };
+    json!({
+        "transaction": transaction.encode(UiTransactionEncoding::Base64),
+        "meta": {
+            "err": null,
+            "status": {"Ok": null},
+            "fee": 5000,
+            "preBalances": vec![1_000_000u64; accounts],
+            "postBalances": vec![995_000u64; accounts],
+            "preTokenBalances": [token_balance(1, key(1), 500), token_balance(2, key(4), 0)],
+            "postTokenBalances": [token_balance(2, key(4), 500)],
+        },
+    })
+}
+
+#[tokio::test]
+async fn closed_token_accounts_are_recorded_empty() {
+    let block = parse(transfer_and_close()).await;
+    let balances = block.get_token_balances();
+    assert_eq!(balances.len(), 2);
+    assert_eq!(
+        balances[&key(2).to_string()],
+        TokenBalance {
+            mint: MINT.to_string(),
+            owner: Some(key(1).to_string()),
+            amount: 0,
+            decimals: 6,
+        }
+    );
+    assert_eq!(
+        balances[&key(3).to_string()],
+        TokenBalance {
+            mint: MINT.to_string(),
+            owner: Some(key(4).to_string()),
+            amount: 500,
+            decimals: 6,
+        }
+    );
+}
+
+#[test]
+fn mint_balances_sum_the_token_accounts_of_the_owner() {
+    let account = |byte: u8, amount: u64, block_no: u64| TokenAccountBalance {
+        account: key(byte).to_string(),
+        amount,
+        decimals: 6,
+        block_no,
+    };
+    let balance = MintBalance::new(
+        MINT.to_string(),
+        vec![account(2, 250, 7), account(3, 1_000, 9), account(5, 40, 3)],
+    );
+    assert_eq!(balance.amount, 1_290);
+    assert_eq!(balance.decimals, 6);
+    assert_eq!(
+        balance.accounts,
+        vec![account(3, 1_000, 9), account(2, 250, 7), account(5, 40, 3)]
+    );
+
+    let empty = MintBalance::new(MINT.to_string(), vec![]);
+    assert_eq!(empty.amount, 0);
+    assert!(empty.accounts.is_empty());
+}
+
+/// A block of the slot holding the balances of token accounts of `MINT`, by account and owner
+fn token_block(slot: u64, balances: &[(Pubkey, Pubkey, u64)]) -> Block {
+    let mut block = Block::default();
+    block.set_header(BlockHeader {
+        slot,
+        blockhash: format!("hash-{}", slot),
+        ..Default::default()
+    });
+    for (account, owner, amount) in balances {
+        block.insert_token_balance(
+            account.to_string(),
+            TokenBalance {
+                mint: MINT.to_string(),
+                owner: Some(owner.to_string()),
+                amount: *amount,
+                decimals: 6,
+            },
+        );
+    }
+    block
+}
+
+#[tokio::test]
+async fn a_late_older_block_does_not_bring_a_closed_token_account_back() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let channel = Channel::<ProtocolMessage>::new();
+    let sender = channel.sender();
+    let mut db = Builder::default()
+        .db_path(dir.path().join("db").to_string_lossy().into_owned())
+        .db_receiver(channel.receiver)
+        .build()
+        .expect("db opens");
+    tokio::spawn(async move { db.run().await });
+
+    // Block 2 closes `key(2)` before block 1, which still held its 500 tokens, is stored
+    for (block_no, block) in [
+        (2, token_block(20, &[(key(2), key(1), 0)])),
+        (
+            1,
+            token_block(10, &[(key(2), key(1), 500), (key(3), key(4), 500)]),
+        ),
+    ] {
+        sender
+            .send(ProtocolMessage::FinalizeBlock(block_no, block))
+            .expect("db running");
+    }
+    let balance = |owner: Pubkey| {
+        let sender = sender.clone();
+        async move {
+            let response = ProtocolMessage::ask(&sender, |reply| {
+                ProtocolMessage::FetchTokenBalance(owner.to_string(), MINT.to_string(), reply)
+            })
+            .await;
+            match response {
+                Ok(Response::TokenBalance(balance)) => balance,
+                other => panic!("unexpected response {other:?}"),
+            }
+        }
+    };
+    let closed = balance(key(1)).await;
+    assert_eq!(closed.amount, 0);
+    assert!(closed.accounts.is_empty());
+    assert_eq!(balance(key(4)).await.amount, 500);
+}
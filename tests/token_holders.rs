@@ -0,0 +1,115 @@
+mod common;
+
+use actix_web::{test, web, App};
+use common::{block, key, MINT};
+use serde_json::{json, Value};
+use solana_agg::config::QueryConfig;
+use solana_agg::server;
+use solana_agg::util::{Block, ProtocolMessage, Response, TokenBalance};
+use solana_agg::Builder;
+use solana_program::pubkey::Pubkey;
+
+/// A block of the slot changing the balances of the token accounts, in `MINT` unless told
+fn token_block(slot: u64, balances: &[(Pubkey, Pubkey, u64, &str)]) -> Block {
+    let mut block = block(slot);
+    for (account, owner, amount, mint) in balances {
+        block.insert_token_balance(
+            account.to_string(),
+            TokenBalance {
+                mint: mint.to_string(),
+                owner: Some(owner.to_string()),
+                amount: *amount,
+                decimals: 6,
+            },
+        );
+    }
+    block
+}
+
+fn holder(account: Pubkey, owner: Pubkey, amount: u64) -> Value {
+    json!({
+        "account": account.to_string(),
+        "owner": owner.to_string(),
+        "amount": amount,
+        "decimals": 6,
+    })
+}
+
+#[actix_web::test]
+async fn token_holders_are_snapshotted_as_of_the_requested_block() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(dir.path().join("db").to_string_lossy().into_owned())
+        .db_receiver(receiver)
+        .build()
+        .expect("db opens");
+    tokio::spawn(async move { db.run().await });
+    let other_mint = key(9).to_string();
+    let blocks = [
+        token_block(
+            10,
+            &[
+                (key(1), key(11), 100, MINT),
+                (key(2), key(12), 50, MINT),
+                (key(3), key(13), 999, &other_mint),
+            ],
+        ),
+        // `key(2)` is emptied and `key(4)` receives tokens
+        token_block(
+            20,
+            &[
+                (key(1), key(11), 30, MINT),
+                (key(2), key(12), 0, MINT),
+                (key(4), key(14), 200, MINT),
+            ],
+        ),
+        // Nothing moves in the mint
+        token_block(30, &[]),
+    ];
+    for (block_no, block) in (1..).zip(blocks) {
+        sender
+            .send(ProtocolMessage::FinalizeBlock(block_no, block))
+            .expect("db running");
+    }
+    match ProtocolMessage::ask(&sender, ProtocolMessage::FetchStatus).await {
+        Ok(Response::Status(status)) => assert_eq!(status.latest_block_no, Some(3)),
+        other => panic!("unexpected response {other:?}"),
+    }
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(sender))
+            .app_data(web::Data::new(QueryConfig::default()))
+            .configure(server::configure),
+    )
+    .await;
+    let holders = |query: &str| {
+        test::TestRequest::get()
+            .uri(&format!("/token_holders/{}{}", MINT, query))
+            .to_request()
+    };
+
+    // Largest holder first, leaving out the accounts of other mints
+    let response = test::call_service(&app, holders("?block_no=1")).await;
+    assert_eq!(response.status(), 200);
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(
+        body,
+        json!([holder(key(1), key(11), 100), holder(key(2), key(12), 50)])
+    );
+    // Emptied accounts no longer hold the token
+    let at_2 = json!([holder(key(4), key(14), 200), holder(key(1), key(11), 30)]);
+    let response = test::call_service(&app, holders("?block_no=2")).await;
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body, at_2);
+    // Balances carry over the blocks that did not change them, up to the latest block
+    for query in ["?block_no=3", ""] {
+        let response = test::call_service(&app, holders(query)).await;
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body, at_2, "{query}");
+    }
+    // Before the first block the mint has no holder
+    let response = test::call_service(&app, holders("?block_no=0")).await;
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body, json!([]));
+}
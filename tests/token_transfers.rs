@@ -0,0 +1,191 @@
+mod common;
+
+use common::{parse, MINT, TOKEN_PROGRAM};
+use serde_json::{json, Value};
+use solana_agg::util::{Block, Instruction, ParseErrorKind};
+use solana_program::hash::Hash;
+use solana_program::instruction::CompiledInstruction;
+use solana_program::message::Message;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::Transaction;
+use solana_transaction_status::{Encodable, UiTransactionEncoding};
+use std::collections::BTreeMap;
+
+const TOKEN_2022_PROGRAM: &str = "TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb";
+
+struct Keys {
+    authority: Pubkey,
+    source: Pubkey,
+    destination: Pubkey,
+}
+
+fn keys() -> Keys {
+    Keys {
+        authority: Pubkey::new_from_array([1; 32]),
+        source: Pubkey::new_from_array([2; 32]),
+        destination: Pubkey::new_from_array([3; 32]),
+    }
+}
+
+/// A transaction with a single token program instruction, signed by the authority. The source
+/// and destination token accounts are at indexes 1 and 2.
+fn token_transaction(program: &str, data: Vec<u8>, accounts: Vec<u8>, with_meta: bool) -> Value {
+    let keys = keys();
+    let program: Pubkey = program.parse().unwrap();
+    let message = Message::new_with_compiled_instructions(
+        1,
+        0,
+        1,
+        vec![keys.authority, keys.source, keys.destination, program],
+        Hash::default(),
+        vec![CompiledInstruction {
+            program_id_index: 3,
+            accounts,
+            data,
+        }],
+    );
+    let transaction = Transaction {
+        signatures: vec![Signature::default()],
+        message,
+    };
+    let token_balance = |index: u8, owner: &Pubkey, amount: &str| {
+        json!({
+            "accountIndex": index,
+            "mint": MINT,
+            "uiTokenAmount": {"uiAmount": null, "decimals": 6, "amount": amount, "uiAmountString": amount},
+            "owner": owner.to_string(),
+            "programId": TOKEN_PROGRAM,
+        })
+    };
+    let meta = with_meta.then(|| {
+        json!({
+            "err": null,
+            "status": {"Ok": null},
+            "fee": 5000,
+            "preBalances": [1_000_000_000u64, 2_039_280, 2_039_280, 1],
+            "postBalances": [999_995_000u64, 2_039_280, 2_039_280, 1],
+            "preTokenBalances": [
+                token_balance(1, &keys.authority, "1000000"),
+                token_balance(2, &Pubkey::new_from_array([4; 32]), "0"),
+            ],
+            "postTokenBalances": [
+                token_balance(1, &keys.authority, "750000"),
+                token_balance(2, &Pubkey::new_from_array([4; 32]), "250000"),
+            ],
+        })
+    });
+    json!({
+        "transaction": transaction.encode(UiTransactionEncoding::Base64),
+        "meta": meta,
+    })
+}
+
+fn instructions(block: &Block) -> Vec<Instruction> {
+    block
+        .transactions()
+        .flat_map(|(_, tx)| tx.instructions().to_vec())
+        .collect()
+}
+
+fn transfer_data(tag: u8, amount: u64) -> Vec<u8> {
+    let mut data = vec![tag];
+    data.extend(amount.to_le_bytes());
+    data
+}
+
+#[tokio::test]
+async fn transfers_resolve_mint_decimals_and_owners_from_the_metadata() {
+    let keys = keys();
+    let block = parse(token_transaction(
+        TOKEN_PROGRAM,
+        transfer_data(3, 250_000),
+        vec![1, 2, 0],
+        true,
+    ))
+    .await;
+    let instructions = instructions(&block);
+    assert_eq!(instructions.len(), 1);
+    let Instruction::TokenTransfer {
+        source,
+        destination,
+        source_owner,
+        destination_owner,
+        mint,
+        amount,
+        decimals,
+    } = &instructions[0]
+    else {
+        panic!("not a token transfer {:?}", instructions[0]);
+    };
+    assert_eq!(source, &keys.source.to_string());
+    assert_eq!(destination, &keys.destination.to_string());
+    assert_eq!(source_owner, &Some(keys.authority.to_string()));
+    assert_eq!(
+        destination_owner,
+        &Some(Pubkey::new_from_array([4; 32]).to_string())
+    );
+    assert_eq!(mint.as_deref(), Some(MINT));
+    assert_eq!(*amount, 250_000);
+    assert_eq!(*decimals, Some(6));
+    // Token transfers are not counted as SOL transfers
+    assert_eq!(block.transfers().count(), 0);
+}
+
+#[tokio::test]
+async fn transfer_checked_carries_its_mint_and_decimals() {
+    let keys = keys();
+    let mut data = transfer_data(12, 42);
+    data.push(9);
+    // Token-2022 shares the layout, the mint account is the authority here
+    let block = parse(token_transaction(
+        TOKEN_2022_PROGRAM,
+        data,
+        vec![1, 0, 2, 0],
+        false,
+    ))
+    .await;
+    match &instructions(&block)[..] {
+        [Instruction::TokenTransfer {
+            mint,
+            amount: 42,
+            decimals: Some(9),
+            source_owner: None,
+            ..
+        }] => assert_eq!(mint, &Some(keys.authority.to_string())),
+        other => panic!("unexpected instructions {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn malformed_token_transfers_are_parse_errors() {
+    // The amount is cut short
+    let block = parse(token_transaction(
+        TOKEN_PROGRAM,
+        vec![3, 1, 2],
+        vec![1, 2, 0],
+        true,
+    ))
+    .await;
+    let errors: Vec<_> = block
+        .transactions()
+        .filter_map(|(_, tx)| tx.parse_error())
+        .collect();
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].starts_with("Malformed Instruction"));
+    let expected = BTreeMap::from([(ParseErrorKind::SliceError, 1)]);
+    assert_eq!(block.parse_errors(), &expected);
+}
+
+#[tokio::test]
+async fn other_token_instructions_are_not_transfers() {
+    // MintTo
+    let block = parse(token_transaction(
+        TOKEN_PROGRAM,
+        transfer_data(7, 1),
+        vec![1, 2, 0],
+        true,
+    ))
+    .await;
+    assert!(instructions(&block).is_empty());
+}
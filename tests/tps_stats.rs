@@ -0,0 +1,132 @@
+mod common;
+
+use common::block;
+use serde_json::json;
+use solana_agg::metrics;
+use solana_agg::stats::{TpsStats, WindowStats};
+use solana_agg::util::{Block, TxRecord};
+use solana_program::hash::hash;
+use solana_transaction_status::UiTransactionStatusMeta;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Held by the tests recording blocks, which all set the same gauges
+static GAUGES: Mutex<()> = Mutex::new(());
+
+fn meta(succeeded: bool) -> UiTransactionStatusMeta {
+    let err = json!({"InstructionError": [0, {"Custom": 1}]});
+    serde_json::from_value(json!({
+        "err": if succeeded { json!(null) } else { err.clone() },
+        "status": if succeeded { json!({"Ok": null}) } else { json!({"Err": err}) },
+        "fee": 5000,
+        "preBalances": [],
+        "postBalances": [],
+    }))
+    .expect("metadata")
+}
+
+/// A block of the slot with a transaction for each outcome
+fn stats_block(slot: u64, outcomes: &[bool]) -> Block {
+    let mut block = block(slot);
+    for (tx, succeeded) in outcomes.iter().enumerate() {
+        block.push_transaction(
+            hash(format!("{}/{}", slot, tx).as_bytes()),
+            TxRecord::new(vec![], Some(meta(*succeeded))).expect("record"),
+        );
+    }
+    block
+}
+
+fn window<'a>(stats: &'a [WindowStats], name: &str) -> &'a WindowStats {
+    stats
+        .iter()
+        .find(|stats| stats.window == name)
+        .expect("window")
+}
+
+#[test]
+fn windows_only_count_their_blocks_and_a_young_process_its_uptime() {
+    let _gauges = GAUGES
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let started = Instant::now();
+    let at = |secs| started + Duration::from_secs(secs);
+    let mut stats = TpsStats::new(started);
+    stats.record(&stats_block(10, &[true, false]), at(10));
+    stats.record(&stats_block(20, &[true; 4]), at(120));
+
+    let snapshot = stats.snapshot(at(130));
+    assert_eq!(snapshot.len(), 3);
+    let minute = window(&snapshot, "1m");
+    assert_eq!((minute.blocks, minute.transactions), (1, 4));
+    assert_eq!(minute.tps, 4.0 / 60.0);
+    assert_eq!(minute.success_ratio, 1.0);
+    assert_eq!(minute.average_fee, 5000.0);
+    // Up 130s, so the longer windows divide by the uptime rather than their length
+    for name in ["5m", "1h"] {
+        let stats = window(&snapshot, name);
+        assert_eq!((stats.blocks, stats.transactions), (2, 6), "{name}");
+        assert_eq!(stats.tps, 6.0 / 130.0, "{name}");
+        assert_eq!(stats.success_ratio, 5.0 / 6.0, "{name}");
+        assert_eq!(stats.average_fee, 5000.0, "{name}");
+    }
+}
+
+#[test]
+fn blocks_older_than_an_hour_are_dropped_and_the_gauges_follow_the_latest_block() {
+    let _gauges = GAUGES
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let started = Instant::now();
+    let at = |secs| started + Duration::from_secs(secs);
+    let mut stats = TpsStats::new(started);
+    stats.record(&stats_block(10, &[true, false]), at(10));
+    stats.record(&stats_block(20, &[true; 4]), at(120));
+    stats.record(&stats_block(30, &[false]), at(3720));
+
+    let snapshot = stats.snapshot(at(3720));
+    let hour = window(&snapshot, "1h");
+    assert_eq!((hour.blocks, hour.transactions), (2, 5));
+    assert_eq!(hour.tps, 5.0 / 3600.0);
+    assert_eq!(hour.success_ratio, 4.0 / 5.0);
+    for name in ["1m", "5m"] {
+        let stats = window(&snapshot, name);
+        assert_eq!((stats.blocks, stats.transactions), (1, 1), "{name}");
+        assert_eq!(stats.success_ratio, 0.0, "{name}");
+    }
+    for stats in &snapshot {
+        assert_eq!(
+            metrics::TPS.with_label_values(&[stats.window]).get(),
+            stats.tps
+        );
+        assert_eq!(
+            metrics::SUCCESS_RATIO
+                .with_label_values(&[stats.window])
+                .get(),
+            stats.success_ratio
+        );
+    }
+}
+
+#[test]
+fn windows_without_a_block_are_empty() {
+    let _gauges = GAUGES
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let started = Instant::now();
+    let mut stats = TpsStats::new(started);
+    stats.record(&stats_block(10, &[true]), started);
+
+    let snapshot = stats.snapshot(started + Duration::from_secs(7200));
+    for stats in &snapshot {
+        assert_eq!(
+            (stats.blocks, stats.transactions),
+            (0, 0),
+            "{}",
+            stats.window
+        );
+        assert_eq!(stats.tps, 0.0);
+        assert_eq!(stats.success_ratio, 0.0);
+        assert_eq!(stats.average_fee, 0.0);
+    }
+}
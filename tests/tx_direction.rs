@@ -0,0 +1,63 @@
+use serde_json::json;
+use solana_agg::util::{AccountTxEntry, Instruction, TxDirection, TxRecord};
+
+fn record(instructions: Vec<Instruction>) -> TxRecord {
+    TxRecord::new(instructions, None)
+        .expect("record")
+        .with_accounts(vec!["Signer".to_string(), "Program".to_string()])
+}
+
+fn token_transfer(source_owner: &str, destination_owner: &str) -> Instruction {
+    Instruction::TokenTransfer {
+        source: "SourceTokens".to_string(),
+        destination: "DestinationTokens".to_string(),
+        source_owner: Some(source_owner.to_string()),
+        destination_owner: Some(destination_owner.to_string()),
+        mint: None,
+        amount: 5,
+        decimals: None,
+    }
+}
+
+#[test]
+fn directions_follow_the_sol_and_token_transfers() {
+    let tx = record(vec![
+        Instruction::Transfer("Alice".to_string(), "Bob".to_string(), 1.0),
+        token_transfer("Bob", "Carol"),
+    ]);
+    assert_eq!(tx.direction("Alice"), TxDirection::Out);
+    assert_eq!(tx.direction("Carol"), TxDirection::In);
+    assert_eq!(tx.direction("DestinationTokens"), TxDirection::In);
+    assert_eq!(tx.direction("Bob"), TxDirection::Both);
+    assert_eq!(tx.direction("Signer"), TxDirection::Other);
+
+    let to_itself = record(vec![Instruction::Transfer(
+        "Alice".to_string(),
+        "Alice".to_string(),
+        1.0,
+    )]);
+    assert_eq!(to_itself.direction("Alice"), TxDirection::Both);
+}
+
+#[test]
+fn index_entries_without_a_direction_still_decode() {
+    let entry = AccountTxEntry::Indexed {
+        block_no: 42,
+        direction: TxDirection::In,
+    };
+    let json = serde_json::to_value(entry).expect("serializes");
+    assert_eq!(json, json!({"block_no": 42, "direction": "in"}));
+    let entry: AccountTxEntry = serde_json::from_value(json).expect("deserializes");
+    assert!(matches!(
+        entry,
+        AccountTxEntry::Indexed {
+            block_no: 42,
+            direction: TxDirection::In
+        }
+    ));
+
+    // Entries indexed before directions hold the block number alone
+    let legacy: AccountTxEntry = serde_json::from_slice(b"42").expect("deserializes");
+    assert!(matches!(legacy, AccountTxEntry::Legacy(42)));
+    assert_eq!(legacy.block_no(), 42);
+}
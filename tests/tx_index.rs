@@ -0,0 +1,195 @@
+use solana_agg::config::{Commitment, ReorgConfig};
+use solana_agg::util::{
+    Block, BlockHeader, ChainStatus, JobTask, ProtocolMessage, Response, TxConflict,
+    TxIndexMigration, TxLocation, TxRecord,
+};
+use solana_agg::Builder;
+use solana_program::hash::hash;
+use tokio::sync::mpsc::UnboundedSender;
+
+#[test]
+fn locations_round_trip_through_their_fixed_width_encoding() {
+    let location = TxLocation {
+        slot: 250_000_123,
+        block_no: 42,
+        offset: 7,
+    };
+    let bytes = location.to_bytes();
+    assert_eq!(bytes.len(), TxLocation::ENCODED_LEN);
+    assert_eq!(&bytes[..8], &250_000_123u64.to_be_bytes());
+    assert_eq!(TxLocation::from_bytes(&bytes).expect("decodes"), location);
+
+    assert!(TxLocation::from_bytes(&bytes[..19]).is_err());
+    assert!(TxLocation::from_bytes(&[0; 21]).is_err());
+    // Legacy values are JSON encoded block numbers
+    assert!(TxLocation::from_bytes(b"42").is_err());
+}
+
+#[test]
+fn every_transaction_of_a_block_has_a_distinct_offset() {
+    let mut block = Block::default();
+    for seed in 0u8..5 {
+        block.push_transaction(hash(&[seed]), TxRecord::new(vec![], None).expect("record"));
+    }
+    let offsets = block.tx_offsets();
+    assert_eq!(offsets.len(), 5);
+    let mut positions: Vec<u32> = offsets.values().copied().collect();
+    positions.sort_unstable();
+    assert_eq!(positions, vec![0, 1, 2, 3, 4]);
+    for tx_id in offsets.keys() {
+        assert_eq!(bs58::decode(tx_id).into_vec().expect("base58").len(), 32);
+    }
+}
+
+#[test]
+fn migration_progress_counts_migrated_and_orphaned_entries() {
+    let mut migration = TxIndexMigration {
+        total: 4,
+        ..TxIndexMigration::default()
+    };
+    assert_eq!(JobTask::MigrateTxIndex(migration.clone()).progress(), 0.0);
+    migration.migrated = 2;
+    migration.orphaned = 1;
+    assert_eq!(JobTask::MigrateTxIndex(migration.clone()).progress(), 0.75);
+    // Entries written while the migration runs are not counted, finishing completes the job
+    migration.finished = true;
+    assert_eq!(JobTask::MigrateTxIndex(migration.clone()).progress(), 1.0);
+
+    let task = serde_json::to_value(JobTask::MigrateTxIndex(migration)).expect("serializes");
+    assert_eq!(task["kind"], "migrate_tx_index");
+    assert_eq!(task["orphaned"], 1);
+}
+
+#[test]
+fn conflicts_keep_both_locations_and_older_chain_statuses_still_decode() {
+    let kept = TxLocation {
+        slot: 100,
+        block_no: 10,
+        offset: 0,
+    };
+    let other = TxLocation {
+        slot: 101,
+        block_no: 11,
+        offset: 3,
+    };
+    let conflict = TxConflict {
+        tx_id: "tx".to_string(),
+        kept,
+        other,
+        at: 1_700_000_000,
+    };
+    let json = serde_json::to_vec(&conflict).expect("serializes");
+    assert_eq!(
+        serde_json::from_slice::<TxConflict>(&json).expect("deserializes"),
+        conflict
+    );
+
+    // Chain statuses stored before conflicts were counted
+    let status: ChainStatus = serde_json::from_str(
+        r#"{"verified_blocks":1,"unverified_blocks":0,"broken_links":0,"last_verified_block_no":1,"quarantined_blocks":[]}"#,
+    )
+    .expect("deserializes");
+    assert_eq!(status.tx_conflicts, 0);
+}
+
+/// A block of the slot chaining onto the block of the parent slot, with the transactions of
+/// the seeds
+fn chained_block(slot: u64, parent_slot: u64, seeds: &[&str]) -> Block {
+    let mut block = Block::default();
+    block.set_header(BlockHeader {
+        slot,
+        blockhash: format!("hash-{}", slot),
+        previous_blockhash: Some(format!("hash-{}", parent_slot)),
+        parent_slot: Some(parent_slot),
+        transaction_count: Some(seeds.len() as u64),
+        ..Default::default()
+    });
+    for seed in seeds {
+        block.push_transaction(
+            hash(seed.as_bytes()),
+            TxRecord::new(vec![], None).expect("record"),
+        );
+    }
+    block
+}
+
+async fn indexed_slot(sender: &UnboundedSender<ProtocolMessage>, seed: &str) -> Option<u64> {
+    let tx_id = hash(seed.as_bytes()).to_string();
+    match ProtocolMessage::ask(sender, |reply| {
+        ProtocolMessage::FetchTxInclusion(tx_id, reply)
+    })
+    .await
+    {
+        Ok(Response::TxInclusion(inclusion, _)) => inclusion.slot,
+        other => panic!("unexpected response {other:?}"),
+    }
+}
+
+async fn tx_conflicts(sender: &UnboundedSender<ProtocolMessage>) -> u64 {
+    match ProtocolMessage::ask(sender, ProtocolMessage::FetchStatus).await {
+        Ok(Response::Status(status)) => status.chain.tx_conflicts,
+        other => panic!("unexpected response {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn a_transaction_in_two_finalized_blocks_is_indexed_in_the_latest() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(dir.path().join("db").to_string_lossy().into_owned())
+        .db_receiver(receiver)
+        .build()
+        .expect("db opens");
+    tokio::spawn(async move { db.run().await });
+    for (block_no, block) in [
+        (1, chained_block(10, 9, &["a"])),
+        (2, chained_block(11, 10, &["a"])),
+    ] {
+        sender
+            .send(ProtocolMessage::FinalizeBlock(block_no, block))
+            .expect("db running");
+    }
+    assert_eq!(indexed_slot(&sender, "a").await, Some(11));
+    assert_eq!(tx_conflicts(&sender).await, 1);
+
+    // Block 2 fetched again does not count its conflict twice
+    sender
+        .send(ProtocolMessage::FinalizeBlock(
+            2,
+            chained_block(11, 10, &["a"]),
+        ))
+        .expect("db running");
+    assert_eq!(indexed_slot(&sender, "a").await, Some(11));
+    assert_eq!(tx_conflicts(&sender).await, 1);
+}
+
+#[tokio::test]
+async fn a_confirmed_block_does_not_take_a_transaction_from_a_finalized_block() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(dir.path().join("db").to_string_lossy().into_owned())
+        .db_receiver(receiver)
+        .build()
+        .expect("db opens");
+    // A block is finalized once two blocks are stored after it
+    db.set_reorg_handling(Commitment::Confirmed, ReorgConfig { max_depth: 2 }, None);
+    tokio::spawn(async move { db.run().await });
+    for (block_no, block) in [
+        (1, chained_block(10, 9, &["a"])),
+        (2, chained_block(20, 10, &[])),
+        (3, chained_block(30, 20, &[])),
+        (4, chained_block(40, 30, &["a", "b"])),
+        (5, chained_block(50, 40, &["b"])),
+    ] {
+        sender
+            .send(ProtocolMessage::FinalizeBlock(block_no, block))
+            .expect("db running");
+    }
+    // Block 1 is finalized when block 4 is stored, block 4 is not
+    assert_eq!(indexed_slot(&sender, "a").await, Some(10));
+    // Neither block 4 nor block 5 is finalized, the latest one is indexed
+    assert_eq!(indexed_slot(&sender, "b").await, Some(50));
+    assert_eq!(tx_conflicts(&sender).await, 2);
+}
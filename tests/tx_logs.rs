@@ -0,0 +1,67 @@
+use serde_json::json;
+use solana_agg::util::{TxLogs, TxRecord};
+use solana_transaction_status::UiTransactionStatusMeta;
+
+fn failed_meta() -> UiTransactionStatusMeta {
+    serde_json::from_value(json!({
+        "err": {"InstructionError": [0, {"Custom": 1}]},
+        "status": {"Err": {"InstructionError": [0, {"Custom": 1}]}},
+        "fee": 5000,
+        "preBalances": [],
+        "postBalances": [],
+        "logMessages": [
+            "Program 11111111111111111111111111111111 invoke [1]",
+            "Program 11111111111111111111111111111111 failed: custom program error: 0x1",
+        ],
+        "computeUnitsConsumed": 150,
+    }))
+    .expect("metadata")
+}
+
+fn expected() -> TxLogs {
+    TxLogs {
+        tx_id: "tx".to_string(),
+        succeeded: Some(false),
+        error: Some("Error processing Instruction 0: custom program error: 0x1".to_string()),
+        fee: Some(5000),
+        compute_units: Some(150),
+        logs: vec![
+            "Program 11111111111111111111111111111111 invoke [1]".to_string(),
+            "Program 11111111111111111111111111111111 failed: custom program error: 0x1"
+                .to_string(),
+        ],
+    }
+}
+
+#[test]
+fn logs_compute_units_and_errors_are_parsed_from_the_metadata() {
+    let record = TxRecord::new(vec![], Some(failed_meta())).expect("record");
+    assert_eq!(record.compute_units(), Some(150));
+    assert_eq!(record.logs().len(), 2);
+    assert_eq!(
+        record.error(),
+        Some("Error processing Instruction 0: custom program error: 0x1")
+    );
+    assert_eq!(record.tx_logs("tx"), expected());
+
+    let unparsed = TxRecord::unparsed(Some(failed_meta()), "bad data".to_string());
+    assert_eq!(unparsed.tx_logs("tx"), expected());
+}
+
+#[test]
+fn records_stored_before_the_fields_were_kept_are_read_from_their_metadata() {
+    let stored = json!({
+        "instruction": [],
+        "metadata": serde_json::to_string(&failed_meta()).expect("serialize"),
+        "fee": 5000,
+        "succeeded": false,
+    });
+    let record: TxRecord = serde_json::from_value(stored).expect("record");
+    assert!(record.logs().is_empty());
+    assert_eq!(record.tx_logs("tx"), expected());
+
+    let value = serde_json::to_value(TxRecord::new(vec![], None).expect("record")).expect("json");
+    assert!(value.get("logs").is_none());
+    assert!(value.get("compute_units").is_none());
+    assert!(value.get("error").is_none());
+}
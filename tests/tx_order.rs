@@ -0,0 +1,69 @@
+use serde_json::json;
+use solana_agg::util::{Block, TxRecord, UnprocessedBlock};
+use solana_program::hash::{hash, Hash};
+
+fn tx_hash(position: usize) -> Hash {
+    hash(&position.to_le_bytes())
+}
+
+/// Builds the partial block of a chunk holding the transactions at the given positions
+fn chunk(positions: std::ops::Range<usize>) -> Block {
+    let mut block = Block::default();
+    for position in positions {
+        block.push_transaction(
+            tx_hash(position),
+            TxRecord::new(vec![], None).expect("record"),
+        );
+    }
+    block
+}
+
+fn expected_order(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|position| tx_hash(position).to_string())
+        .collect()
+}
+
+#[test]
+fn chunks_arriving_out_of_order_keep_the_order_of_the_block() {
+    let mut unprocessed = UnprocessedBlock::new(3);
+    unprocessed.insert_chunk(2, chunk(20..25)).unwrap();
+    unprocessed.insert_chunk(0, chunk(0..10)).unwrap();
+    unprocessed.insert_chunk(1, chunk(10..20)).unwrap();
+    let block = unprocessed.complete_the_block().unwrap();
+
+    let tx_ids: Vec<String> = block
+        .transactions()
+        .map(|(tx_id, _)| tx_id.to_string())
+        .collect();
+    assert_eq!(tx_ids, expected_order(25));
+    assert_eq!(block.get_tx_hash(), expected_order(25));
+    let offsets = block.tx_offsets();
+    for (position, tx_id) in expected_order(25).iter().enumerate() {
+        assert_eq!(offsets[tx_id], position as u32);
+    }
+    // Block responses list the order next to the transactions
+    let json = serde_json::to_value(&block).unwrap();
+    assert_eq!(json["tx_order"], json!(expected_order(25)));
+}
+
+#[test]
+fn a_transaction_pushed_twice_keeps_its_first_position() {
+    let mut block = chunk(0..3);
+    block.push_transaction(tx_hash(0), TxRecord::new(vec![], None).expect("record"));
+    assert_eq!(block.get_tx_hash(), expected_order(3));
+}
+
+#[test]
+fn blocks_stored_before_the_order_was_kept_are_listed_by_transaction_id() {
+    let mut json = serde_json::to_value(chunk(0..5)).unwrap();
+    json.as_object_mut().unwrap().remove("tx_order");
+    let mut legacy: Block = serde_json::from_value(json).unwrap();
+    let mut sorted = expected_order(5);
+    sorted.sort();
+    assert_eq!(legacy.get_tx_hash(), sorted);
+
+    // Re-parsing the block from its raw transactions restores the order
+    assert_eq!(legacy.upgrade_transactions(chunk(0..5)), 5);
+    assert_eq!(legacy.get_tx_hash(), expected_order(5));
+}
@@ -0,0 +1,90 @@
+//! Property tests for assembling parsed chunks into a block. Whatever order, duplication or gaps
+//! the chunks arrive with, the assembler either yields the complete block or reports the failure.
+
+use proptest::prelude::*;
+use solana_agg::error::AggError;
+use solana_agg::util::{Block, TxRecord, UnprocessedBlock};
+use solana_program::hash::hash;
+use std::collections::BTreeSet;
+
+/// Builds the partial block of a chunk with `tx_count` transactions that are unique to the chunk
+fn chunk(chunk_no: u64, tx_count: usize) -> Block {
+    let mut block = Block::default();
+    block.record_program_call(format!("chunk-{chunk_no}"));
+    for index in 0..tx_count {
+        block.push_transaction(
+            hash(format!("{chunk_no}/{index}").as_bytes()),
+            TxRecord::new(vec![], None).unwrap(),
+        );
+    }
+    block
+}
+
+fn assert_complete(block: &Block, chunk_sizes: &[usize]) {
+    let expected_programs: BTreeSet<String> = (0..chunk_sizes.len())
+        .map(|chunk_no| format!("chunk-{chunk_no}"))
+        .collect();
+    let programs: BTreeSet<String> = block.get_program_calls().keys().cloned().collect();
+    assert_eq!(programs, expected_programs);
+    assert!(block.get_program_calls().values().all(|calls| *calls == 1));
+    assert_eq!(block.tx_stats().0, chunk_sizes.iter().sum::<usize>() as u64);
+}
+
+proptest! {
+    #[test]
+    fn any_delivery_is_complete_or_detected(
+        chunk_sizes in prop::collection::vec(0usize..5, 1..8),
+        deliveries in prop::collection::vec(0u64..10, 0..24),
+    ) {
+        let total_chunks = chunk_sizes.len() as u64;
+        let mut unprocessed = UnprocessedBlock::new(total_chunks);
+        let mut delivered = BTreeSet::new();
+        for chunk_no in deliveries {
+            let result = match chunk_sizes.get(chunk_no as usize) {
+                Some(tx_count) => unprocessed.insert_chunk(chunk_no, chunk(chunk_no, *tx_count)),
+                None => unprocessed.insert_chunk(chunk_no, chunk(chunk_no, 1)),
+            };
+            if chunk_no < total_chunks {
+                prop_assert!(result.is_ok());
+                delivered.insert(chunk_no);
+            } else {
+                prop_assert!(matches!(result, Err(AggError::InvalidChunk(..))));
+            }
+        }
+        prop_assert_eq!(unprocessed.is_complete(), delivered.len() as u64 == total_chunks);
+        match unprocessed.complete_the_block() {
+            Ok(block) => assert_complete(&block, &chunk_sizes),
+            Err(AggError::IncompleteBlock(collected, total)) => {
+                prop_assert_eq!(collected, delivered.len() as u64);
+                prop_assert_eq!(total, total_chunks);
+                prop_assert!(collected < total);
+            }
+            Err(err) => prop_assert!(false, "unexpected error {}", err),
+        }
+    }
+
+    #[test]
+    fn shuffled_and_duplicated_chunks_assemble_the_whole_block(
+        (chunk_sizes, order) in prop::collection::vec(0usize..5, 1..8).prop_flat_map(|sizes| {
+            let total_chunks = sizes.len() as u64;
+            let duplicates = prop::collection::vec(0..total_chunks, 0..8);
+            (Just(sizes), duplicates).prop_flat_map(move |(sizes, duplicates)| {
+                let order: Vec<u64> = (0..total_chunks).chain(duplicates).collect();
+                (Just(sizes), Just(order).prop_shuffle())
+            })
+        }),
+    ) {
+        let total_chunks = chunk_sizes.len() as u64;
+        let mut unprocessed = UnprocessedBlock::new(total_chunks);
+        let mut delivered = BTreeSet::new();
+        for chunk_no in order {
+            unprocessed
+                .insert_chunk(chunk_no, chunk(chunk_no, chunk_sizes[chunk_no as usize]))
+                .unwrap();
+            delivered.insert(chunk_no);
+            prop_assert_eq!(unprocessed.is_complete(), delivered.len() as u64 == total_chunks);
+        }
+        let block = unprocessed.complete_the_block().unwrap();
+        assert_complete(&block, &chunk_sizes);
+    }
+}
@@ -0,0 +1,156 @@
+use solana_agg::config::{Config, WarmupConfig};
+use solana_agg::util::{Block, BlockHeader, Channel, Instruction, ProtocolMessage, TxRecord};
+use solana_agg::warmup::{hottest_accounts, Readiness, WarmupReport};
+use solana_agg::Builder;
+use solana_program::hash::hash;
+use solana_program::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[test]
+fn the_accounts_in_the_most_transactions_are_the_hottest() {
+    let counts = HashMap::from([
+        ("carol".to_string(), 3),
+        ("alice".to_string(), 7),
+        ("dave".to_string(), 1),
+        ("bob".to_string(), 3),
+    ]);
+    assert_eq!(
+        hottest_accounts(counts.clone(), 3),
+        vec!["alice".to_string(), "bob".to_string(), "carol".to_string()]
+    );
+    assert_eq!(hottest_accounts(counts, 10).len(), 4);
+    assert!(hottest_accounts(HashMap::new(), 10).is_empty());
+}
+
+#[test]
+fn readiness_is_only_reported_once_marked() {
+    let readiness = Readiness::default();
+    assert!(!readiness.is_ready());
+    readiness.mark_ready();
+    assert!(readiness.is_ready());
+}
+
+#[test]
+fn warmup_is_read_from_the_config() {
+    let config: Config =
+        toml::from_str("[warmup]\nenabled = true\nblocks = 200").expect("valid config");
+    assert!(config.warmup.enabled);
+    assert_eq!(config.warmup.blocks, 200);
+    assert_eq!(config.warmup.accounts, 1_000);
+    assert!(!Config::default().warmup.enabled);
+}
+
+#[tokio::test]
+async fn the_db_marks_the_instance_ready_once_warmed_up() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let channel = Channel::<ProtocolMessage>::new();
+    let mut db = Builder::default()
+        .db_path(dir.path().join("db").to_string_lossy().into_owned())
+        .db_receiver(channel.receiver)
+        .build()
+        .expect("db opens");
+    let readiness = Arc::new(Readiness::default());
+    db.set_warmup(
+        WarmupConfig {
+            enabled: true,
+            ..WarmupConfig::default()
+        },
+        readiness.clone(),
+    );
+    assert!(!readiness.is_ready());
+    let db = tokio::spawn(async move { db.run().await });
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !readiness.is_ready() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("ready after warming up an empty db");
+    db.abort();
+}
+
+fn transfer_block(slot: u64, from: &Pubkey, to: &Pubkey) -> Block {
+    let mut block = Block::default();
+    block.set_header(BlockHeader {
+        slot,
+        blockhash: format!("hash-{}", slot),
+        block_time: Some(1_700_000_000),
+        transaction_count: Some(1),
+        ..Default::default()
+    });
+    block.push_transaction(
+        hash(&slot.to_be_bytes()),
+        TxRecord::new(
+            vec![Instruction::Transfer(from.to_string(), to.to_string(), 1.0)],
+            None,
+        )
+        .expect("record"),
+    );
+    block
+}
+
+#[tokio::test]
+async fn warming_up_reads_the_stored_blocks_and_skips_corrupt_ones() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let path = dir.path().join("db").to_string_lossy().into_owned();
+    let (alice, bob, carol) = (
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+    );
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(path.clone())
+        .db_receiver(receiver)
+        .build()
+        .expect("db opens");
+    let db = tokio::spawn(async move {
+        db.run().await;
+        db
+    });
+    for (block_no, block) in [
+        (1, transfer_block(5, &alice, &bob)),
+        (2, transfer_block(6, &bob, &carol)),
+        (3, transfer_block(7, &alice, &carol)),
+    ] {
+        sender
+            .send(ProtocolMessage::FinalizeBlock(block_no, block))
+            .expect("db running");
+    }
+    // The db stops once drained of the blocks
+    drop(sender);
+    let db = db.await.expect("db stops");
+    assert_eq!(
+        db.warm_up(),
+        WarmupReport {
+            blocks: 3,
+            accounts: 3
+        }
+    );
+    drop(db);
+
+    // Corrupt the block only bob and carol transferred in
+    let options = rocksdb::Options::default();
+    let column_families = rocksdb::DB::list_cf(&options, &path).expect("column families");
+    let raw = rocksdb::DB::open_cf(&options, &path, column_families).expect("db opens");
+    let blocks = raw.cf_handle("blocks").expect("blocks column family");
+    raw.put_cf(blocks, 2u64.to_be_bytes(), b"corrupt")
+        .expect("block overwritten");
+    drop(raw);
+
+    let channel = Channel::<ProtocolMessage>::new();
+    let db = Builder::default()
+        .db_path(path)
+        .db_receiver(channel.receiver)
+        .build()
+        .expect("db opens");
+    assert_eq!(
+        db.warm_up(),
+        WarmupReport {
+            blocks: 2,
+            accounts: 3
+        }
+    );
+}
@@ -0,0 +1,93 @@
+use solana_agg::util::{
+    AccountEvent, DeliveryStatus, ProtocolMessage, TxRecord, WebhookDelivery, WebhookSubscription,
+};
+use solana_agg::webhook::WebhookDispatcher;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+fn subscription(id: u64, url: String) -> WebhookSubscription {
+    WebhookSubscription {
+        id,
+        account: "account".to_string(),
+        url,
+        secret: "secret".to_string(),
+        created_at: 0,
+        expires_at: None,
+        last_slot: None,
+    }
+}
+
+fn delivery(delivery_id: u64, webhook_id: u64) -> Box<WebhookDelivery> {
+    Box::new(WebhookDelivery {
+        delivery_id,
+        webhook_id,
+        event: AccountEvent {
+            slot: delivery_id,
+            block_no: delivery_id,
+            tx_id: format!("tx-{delivery_id}"),
+            transaction: TxRecord::new(vec![], None).expect("record"),
+            balance: None,
+            replayed: false,
+        },
+    })
+}
+
+/// An endpoint answering every delivery with 200
+async fn endpoint() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("binds");
+    let url = format!("http://{}/hook", listener.local_addr().expect("address"));
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut request = [0u8; 4096];
+                let _ = socket.read(&mut request).await;
+                let _ = socket
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+                    .await;
+            });
+        }
+    });
+    url
+}
+
+#[tokio::test]
+async fn a_slow_endpoint_does_not_hold_back_the_other_webhooks() {
+    // Takes the connection of the deliveries but never answers
+    let slow = TcpListener::bind("127.0.0.1:0").await.expect("binds");
+    let slow_url = format!("http://{}/hook", slow.local_addr().expect("address"));
+    let fast_url = endpoint().await;
+
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let (db_sender, mut reports) = tokio::sync::mpsc::unbounded_channel();
+    let mut dispatcher = WebhookDispatcher::initialize(receiver, db_sender);
+    tokio::spawn(async move { dispatcher.run().await });
+    for (delivery_id, webhook) in [
+        (1, subscription(1, slow_url.clone())),
+        (2, subscription(2, fast_url.clone())),
+        (3, subscription(2, fast_url)),
+        (4, subscription(1, slow_url)),
+    ] {
+        sender
+            .send(ProtocolMessage::WebhookEvent(
+                webhook.clone(),
+                delivery(delivery_id, webhook.id),
+            ))
+            .expect("dispatcher running");
+    }
+
+    // Both deliveries of the fast webhook arrive in order while the slow one is still waiting
+    let mut delivered = vec![];
+    while delivered.len() < 2 {
+        match tokio::time::timeout(Duration::from_secs(5), reports.recv()).await {
+            Ok(Some(ProtocolMessage::DeliveryReport(receipt))) => {
+                assert_eq!(receipt.webhook_id, 2);
+                assert_eq!(receipt.status, DeliveryStatus::Delivered);
+                delivered.push(receipt.delivery_id);
+            }
+            other => panic!("unexpected message {other:?}"),
+        }
+    }
+    assert_eq!(delivered, vec![2, 3]);
+    drop(slow);
+}
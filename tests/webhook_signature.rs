@@ -0,0 +1,58 @@
+use solana_agg::util::{ProtocolMessage, Response};
+use solana_agg::webhook::{generate_secret, sign};
+use solana_agg::Builder;
+use solana_program::pubkey::Pubkey;
+
+#[test]
+fn signature_matches_rfc_4231_test_vector() {
+    assert_eq!(
+        sign("Jefe", b"what do ya want for nothing?"),
+        "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+}
+
+#[test]
+fn signature_depends_on_secret_and_body() {
+    let secret = generate_secret();
+    assert_eq!(secret.len(), 64);
+    assert_ne!(secret, generate_secret());
+    assert_eq!(sign(&secret, b"{}"), sign(&secret, b"{}"));
+    assert_ne!(sign(&secret, b"{}"), sign(&secret, b"[]"));
+    assert_ne!(sign(&secret, b"{}"), sign(&generate_secret(), b"{}"));
+}
+
+#[tokio::test]
+async fn the_secret_is_only_returned_when_the_webhook_is_created() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut db = Builder::default()
+        .db_path(dir.path().join("db").to_string_lossy().into_owned())
+        .db_receiver(receiver)
+        .build()
+        .expect("db opens");
+    tokio::spawn(async move { db.run().await });
+    let account = Pubkey::new_from_array([1; 32]).to_string();
+
+    let created = ProtocolMessage::ask(&sender, |reply| {
+        ProtocolMessage::CreateWebhook(account, "http://127.0.0.1:1/hook".to_string(), None, reply)
+    })
+    .await;
+    let webhook = match created {
+        Ok(Response::Webhook(Some(webhook))) => webhook,
+        other => panic!("unexpected response {other:?}"),
+    };
+    assert_eq!(webhook.secret.len(), 64);
+
+    let deleted = ProtocolMessage::ask(&sender, |reply| {
+        ProtocolMessage::DeleteWebhook(webhook.id, reply)
+    })
+    .await;
+    match deleted {
+        Ok(Response::Webhook(Some(deleted))) => {
+            assert_eq!(deleted.id, webhook.id);
+            let body = serde_json::to_value(&deleted).expect("serializes");
+            assert!(body.get("secret").is_none());
+        }
+        other => panic!("unexpected response {other:?}"),
+    }
+}